@@ -1,6 +1,15 @@
 #![no_std]
 
-/// BasicFeaturesIpv4 is a struct collection all ipv4 traffic data and is 32 bytes in size.
+pub mod parse;
+
+/// BasicFeaturesIpv4 is a struct collection all ipv4 traffic data and is 42 bytes in size.
+///
+/// The struct is packed, so it has no padding and the layout is the same in the eBPF program
+/// and in userspace. `sequence_number_ack` is the TCP acknowledgment number, 0 for non-TCP packets.
+///
+/// All multi-byte fields are in network byte order (big-endian), the header fields are copied
+/// as they are on the wire and the lengths are converted by the eBPF program. Userspace has to
+/// decode every field with `from_be`, which makes the events independent of the host endianness.
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct EbpfEventIpv4 {
@@ -16,9 +25,21 @@ pub struct EbpfEventIpv4 {
     pub header_length: u8,
     pub sequence_number: u32,
     pub sequence_number_ack: u32,
+    pub ttl: u8,
+    /// The bytes of the IP header beyond its fixed 20 bytes, the IPv4 options.
+    pub ip_options_length: u16,
+    /// The flag bits of the IP header, see `parse::IPV4_DONT_FRAGMENT`.
+    pub ip_flags: u8,
+    /// The TCP option kinds of a SYN, see `parse::tcp_option_bit`, 0 for all other packets.
+    pub tcp_options_mask: u8,
+    /// When the eBPF program saw the packet, `bpf_ktime_get_ns` of the monotonic clock.
+    pub kernel_timestamp: u64,
 }
 
 impl EbpfEventIpv4 {
+    /// The size of an event in bytes.
+    pub const LEN: usize = core::mem::size_of::<Self>();
+
     pub fn new(
         ipv4_destination: u32,
         ipv4_source: u32,
@@ -32,6 +53,11 @@ impl EbpfEventIpv4 {
         header_length: u8,
         sequence_number: u32,
         sequence_number_ack: u32,
+        ttl: u8,
+        ip_options_length: u16,
+        ip_flags: u8,
+        tcp_options_mask: u8,
+        kernel_timestamp: u64,
     ) -> Self {
         EbpfEventIpv4 {
             ipv4_destination,
@@ -46,14 +72,46 @@ impl EbpfEventIpv4 {
             header_length,
             sequence_number,
             sequence_number_ack,
+            ttl,
+            ip_options_length,
+            ip_flags,
+            tcp_options_mask,
+            kernel_timestamp,
+        }
+    }
+
+    /// Reads an event from the raw bytes of a ring buffer entry.
+    ///
+    /// Returns `None` if there are fewer bytes than an event has.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::LEN {
+            return None;
         }
+        // The length is checked, the packed struct has no alignment and any bytes are a valid event
+        Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Self) })
+    }
+
+    /// Returns the raw bytes of the event, as submitted to the ring buffer.
+    pub fn as_bytes(&self) -> &[u8] {
+        // The packed struct has no padding, all of its bytes belong to the fields
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, Self::LEN) }
     }
 }
 
+// The ring buffer entries are read back with this exact size
+const _: () = assert!(core::mem::size_of::<EbpfEventIpv4>() == 42);
+
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for EbpfEventIpv4 {}
 
-/// BasicFeaturesIpv6 is a struct collection all ipv6 traffic data and is 64 bytes in size.
+/// BasicFeaturesIpv6 is a struct collection all ipv6 traffic data and is 70 bytes in size.
+///
+/// The struct is packed, so it has no padding and the layout is the same in the eBPF program
+/// and in userspace. `sequence_number_ack` is the TCP acknowledgment number, 0 for non-TCP packets.
+///
+/// All multi-byte fields are in network byte order (big-endian), the header fields are copied
+/// as they are on the wire and the lengths are converted by the eBPF program. Userspace has to
+/// decode every field with `from_be`, which makes the events independent of the host endianness.
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
 pub struct EbpfEventIpv6 {
@@ -69,9 +127,22 @@ pub struct EbpfEventIpv6 {
     pub header_length: u8,
     pub sequence_number: u32,
     pub sequence_number_ack: u32,
+    pub extension_header_count: u8,
+    pub hop_limit: u8,
+    /// The bytes of the extension headers between the IPv6 header and the transport header.
+    pub ip_options_length: u16,
+    /// The TCP option kinds of a SYN, see `parse::tcp_option_bit`, 0 for all other packets.
+    pub tcp_options_mask: u8,
+    /// The 20 bit flow label of the IPv6 header, 0 if the sender didn't set one.
+    pub flow_label: u32,
+    /// When the eBPF program saw the packet, `bpf_ktime_get_ns` of the monotonic clock.
+    pub kernel_timestamp: u64,
 }
 
 impl EbpfEventIpv6 {
+    /// The size of an event in bytes.
+    pub const LEN: usize = core::mem::size_of::<Self>();
+
     pub fn new(
         ipv6_destination: u128,
         ipv6_source: u128,
@@ -85,6 +156,12 @@ impl EbpfEventIpv6 {
         header_length: u8,
         sequence_number: u32,
         sequence_number_ack: u32,
+        extension_header_count: u8,
+        hop_limit: u8,
+        ip_options_length: u16,
+        tcp_options_mask: u8,
+        flow_label: u32,
+        kernel_timestamp: u64,
     ) -> Self {
         EbpfEventIpv6 {
             ipv6_destination,
@@ -99,9 +176,34 @@ impl EbpfEventIpv6 {
             header_length,
             sequence_number,
             sequence_number_ack,
+            extension_header_count,
+            hop_limit,
+            ip_options_length,
+            tcp_options_mask,
+            flow_label,
+            kernel_timestamp,
+        }
+    }
+
+    /// Reads an event from the raw bytes of a ring buffer entry.
+    ///
+    /// Returns `None` if there are fewer bytes than an event has.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::LEN {
+            return None;
         }
+        // The length is checked, the packed struct has no alignment and any bytes are a valid event
+        Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Self) })
+    }
+
+    /// Returns the raw bytes of the event, as submitted to the ring buffer.
+    pub fn as_bytes(&self) -> &[u8] {
+        // The packed struct has no padding, all of its bytes belong to the fields
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, Self::LEN) }
     }
 }
 
+const _: () = assert!(core::mem::size_of::<EbpfEventIpv6>() == 70);
+
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for EbpfEventIpv6 {}
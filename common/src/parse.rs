@@ -0,0 +1,388 @@
+//! Parsing of the IP and transport header fields of a packet, shared by the eBPF programs and
+//! the userspace parser of the `afpacket` backend and the pcap reader, so that both produce the
+//! same fields from the same packet.
+//!
+//! The functions take the header bytes as they are on the wire and only check them against
+//! the length of the given slice. The eBPF programs load the fixed part of a header onto the
+//! stack, with the bounds checks the verifier needs, and userspace passes the captured bytes.
+//! Whether a header with its options fits into the packet is left to the caller, as the IP
+//! length has to be worked out differently in both. The IPv6 extension headers and the TCP
+//! options are walked by each side on its own, the eBPF programs can't loop over a slice of the
+//! packet.
+
+/// The IP protocol numbers of the transport protocols that are parsed.
+pub const ICMP: u8 = 1;
+pub const TCP: u8 = 6;
+pub const UDP: u8 = 17;
+pub const DCCP: u8 = 33;
+pub const ICMPV6: u8 = 58;
+pub const SCTP: u8 = 132;
+
+/// The Don't Fragment bit of the IPv4 flags.
+pub const IPV4_DONT_FRAGMENT: u8 = 0b010;
+/// The More Fragments bit of the IPv4 flags, set on all fragments but the last.
+pub const IPV4_MORE_FRAGMENTS: u8 = 0b001;
+
+/// The SYN bit of the TCP flags.
+pub const TCP_SYN_FLAG: u8 = 0b0000_0010;
+
+/// The length of an IPv4 header without options.
+pub const IPV4_MIN_HEADER_LENGTH: usize = 20;
+/// The length of the fixed IPv6 header.
+pub const IPV6_HEADER_LENGTH: usize = 40;
+/// The bits of the flow label in the first 32 bits of the IPv6 header.
+pub const IPV6_FLOW_LABEL_MASK: u32 = 0x000f_ffff;
+/// The length of a TCP header without options.
+pub const TCP_MIN_HEADER_LENGTH: usize = 20;
+/// The most bytes of TCP options, the data offset allows a 60 byte header.
+pub const TCP_MAX_OPTIONS_LENGTH: usize = 40;
+pub const UDP_HEADER_LENGTH: usize = 8;
+/// The length of an ICMP header, the type, code and checksum and 4 bytes depending on the type.
+pub const ICMP_HEADER_LENGTH: usize = 8;
+/// The length of the SCTP common header, the chunks count as payload.
+pub const SCTP_HEADER_LENGTH: usize = 12;
+/// The length of the generic DCCP header with a short sequence number.
+pub const DCCP_MIN_HEADER_LENGTH: usize = 12;
+
+/// The TCP option kinds, the end of the option list and the padding between options take a
+/// single byte, all others have a length byte after the kind.
+pub const TCP_OPTION_END: u8 = 0;
+pub const TCP_OPTION_NOP: u8 = 1;
+pub const TCP_OPTION_MSS: u8 = 2;
+pub const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+pub const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+pub const TCP_OPTION_TIMESTAMPS: u8 = 8;
+
+/// Why a header couldn't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The header is longer than the given bytes.
+    Truncated,
+    /// A version or length field of the header is impossible, e.g. crafted or corrupt.
+    Invalid,
+    /// The transport protocol isn't one of the parsed ones.
+    Unsupported,
+}
+
+/// The fields of an IPv4 header, the addresses in network byte order and the rest decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Fields {
+    pub source: [u8; 4],
+    pub destination: [u8; 4],
+    /// The total length of the packet, 0 for packets merged by segmentation offload.
+    pub total_length: u16,
+    /// The length of the header with its options in bytes.
+    pub header_length: u8,
+    pub protocol: u8,
+    pub ttl: u8,
+    /// The three flag bits, `IPV4_DONT_FRAGMENT` and `IPV4_MORE_FRAGMENTS`.
+    pub flags: u8,
+    /// The offset of a fragment in 8 byte units, only the first fragment has a transport header.
+    pub fragment_offset: u16,
+}
+
+/// The fields of the fixed IPv6 header, the addresses in network byte order and the rest decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Fields {
+    pub source: [u8; 16],
+    pub destination: [u8; 16],
+    /// The length after the fixed header, 0 for jumbograms and segmentation offload.
+    pub payload_length: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    /// The 20 bit flow label, 0 if the sender didn't set one.
+    pub flow_label: u32,
+}
+
+/// The fields of a transport header, decoded to host byte order.
+///
+/// Protocols without ports, flags, windows or sequence numbers have them at 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportFields {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub window_size: u16,
+    /// The TCP flags at the bit positions of the TCP header.
+    pub combined_flags: u8,
+    /// The length of the header with its options in bytes.
+    pub header_length: u8,
+    pub sequence_number: u32,
+    pub sequence_number_ack: u32,
+}
+
+/// Parses an IPv4 header from at least its first `IPV4_MIN_HEADER_LENGTH` bytes.
+pub fn parse_ipv4_header(header: &[u8]) -> Result<Ipv4Fields, ParseError> {
+    if header.len() < IPV4_MIN_HEADER_LENGTH {
+        return Err(ParseError::Truncated);
+    }
+    // The IHL counts the header with options in 32-bit words
+    let header_length = (header[0] & 0x0f) * 4;
+    if header[0] >> 4 != 4 || usize::from(header_length) < IPV4_MIN_HEADER_LENGTH {
+        return Err(ParseError::Invalid);
+    }
+
+    Ok(Ipv4Fields {
+        source: [header[12], header[13], header[14], header[15]],
+        destination: [header[16], header[17], header[18], header[19]],
+        total_length: be16(header, 2),
+        header_length,
+        protocol: header[9],
+        ttl: header[8],
+        flags: header[6] >> 5,
+        fragment_offset: be16(header, 6) & 0x1fff,
+    })
+}
+
+/// Parses the fixed IPv6 header from at least its `IPV6_HEADER_LENGTH` bytes.
+pub fn parse_ipv6_header(header: &[u8]) -> Result<Ipv6Fields, ParseError> {
+    if header.len() < IPV6_HEADER_LENGTH {
+        return Err(ParseError::Truncated);
+    }
+    if header[0] >> 4 != 6 {
+        return Err(ParseError::Invalid);
+    }
+
+    let mut source = [0; 16];
+    source.copy_from_slice(&header[8..24]);
+    let mut destination = [0; 16];
+    destination.copy_from_slice(&header[24..40]);
+    Ok(Ipv6Fields {
+        source,
+        destination,
+        payload_length: be16(header, 4),
+        next_header: header[6],
+        hop_limit: header[7],
+        flow_label: be32(header, 0) & IPV6_FLOW_LABEL_MASK,
+    })
+}
+
+/// Returns the length of the fixed part of the header of a transport protocol, the bytes
+/// `parse_transport_header` reads. `None` for protocols that aren't parsed.
+pub const fn fixed_header_length(protocol: u8) -> Option<usize> {
+    match protocol {
+        TCP => Some(TCP_MIN_HEADER_LENGTH),
+        UDP => Some(UDP_HEADER_LENGTH),
+        ICMP | ICMPV6 => Some(ICMP_HEADER_LENGTH),
+        SCTP => Some(SCTP_HEADER_LENGTH),
+        DCCP => Some(DCCP_MIN_HEADER_LENGTH),
+        _ => None,
+    }
+}
+
+/// Parses a transport header from at least its first `fixed_header_length` bytes.
+///
+/// ### Arguments
+///
+/// * `protocol` - The IP protocol number of the header.
+/// * `header` - The bytes starting at the transport header.
+pub fn parse_transport_header(protocol: u8, header: &[u8]) -> Result<TransportFields, ParseError> {
+    let Some(fixed_length) = fixed_header_length(protocol) else {
+        return Err(ParseError::Unsupported);
+    };
+    if header.len() < fixed_length {
+        return Err(ParseError::Truncated);
+    }
+
+    let mut fields = TransportFields {
+        header_length: fixed_length as u8,
+        ..TransportFields::default()
+    };
+    if protocol != ICMP && protocol != ICMPV6 {
+        // All the others start with the source and destination port
+        fields.source_port = be16(header, 0);
+        fields.destination_port = be16(header, 2);
+    }
+    match protocol {
+        TCP => {
+            // The data offset counts the header with options in 32-bit words
+            fields.header_length = (header[12] >> 4) * 4;
+            if usize::from(fields.header_length) < TCP_MIN_HEADER_LENGTH {
+                return Err(ParseError::Invalid);
+            }
+            fields.sequence_number = be32(header, 4);
+            fields.sequence_number_ack = be32(header, 8);
+            fields.combined_flags = header[13];
+            fields.window_size = be16(header, 14);
+        }
+        UDP => {
+            // A length of 0 is used by IPv6 jumbograms
+            let length = usize::from(be16(header, 4));
+            if length != 0 && length < UDP_HEADER_LENGTH {
+                return Err(ParseError::Invalid);
+            }
+        }
+        DCCP => {
+            // The data offset counts the header with options in 32-bit words
+            fields.header_length = header[4].saturating_mul(4);
+            if usize::from(fields.header_length) < DCCP_MIN_HEADER_LENGTH {
+                return Err(ParseError::Invalid);
+            }
+        }
+        _ => {}
+    }
+    Ok(fields)
+}
+
+/// Returns the bit of a TCP option kind in the option masks of the flows.
+///
+/// Bit 0 is the MSS, 1 the window scale, 2 SACK-permitted, 3 the timestamps, 4 NOP, and 5 any
+/// other kind, e.g. TCP Fast Open or MD5 signatures. The end of the option list has no bit.
+pub const fn tcp_option_bit(kind: u8) -> u8 {
+    match kind {
+        TCP_OPTION_END => 0,
+        TCP_OPTION_MSS => 1 << 0,
+        TCP_OPTION_WINDOW_SCALE => 1 << 1,
+        TCP_OPTION_SACK_PERMITTED => 1 << 2,
+        TCP_OPTION_TIMESTAMPS => 1 << 3,
+        TCP_OPTION_NOP => 1 << 4,
+        _ => 1 << 5,
+    }
+}
+
+/// Combines the bits of the option kinds of a TCP header, see `tcp_option_bit`.
+///
+/// The walk stops at the end of the option list and at an impossible option length, keeping
+/// the kinds before it.
+///
+/// ### Arguments
+///
+/// * `options` - The bytes of the TCP header after its fixed `TCP_MIN_HEADER_LENGTH` bytes.
+pub fn tcp_options_mask(options: &[u8]) -> u8 {
+    let mut mask = 0;
+    let mut offset = 0;
+    while let Some(&kind) = options.get(offset) {
+        if kind == TCP_OPTION_END {
+            break;
+        }
+        mask |= tcp_option_bit(kind);
+        if kind == TCP_OPTION_NOP {
+            offset += 1;
+            continue;
+        }
+        match options.get(offset + 1) {
+            Some(&length) if length >= 2 => offset += usize::from(length),
+            _ => break,
+        }
+    }
+    mask
+}
+
+#[inline(always)]
+fn be16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline(always)]
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_ipv4_header, parse_ipv6_header, parse_transport_header, tcp_option_bit,
+        tcp_options_mask, ParseError, ICMPV6, IPV4_MORE_FRAGMENTS, IPV6_HEADER_LENGTH, TCP,
+        TCP_OPTION_MSS, TCP_OPTION_NOP, TCP_OPTION_SACK_PERMITTED, TCP_OPTION_TIMESTAMPS,
+        TCP_OPTION_WINDOW_SCALE, UDP,
+    };
+
+    #[test]
+    fn test_parse_transport_header() {
+        let tcp_header: [u8; 24] = [
+            0x01, 0xbb, 0xc7, 0x38, // ports 443 -> 51000
+            0x00, 0x00, 0x01, 0x00, // sequence number
+            0x00, 0x00, 0x00, 0x02, // acknowledgement number
+            0x60, 0x12, 0x01, 0x00, // data offset 6, SYN and ACK, window
+            0x00, 0x00, 0x00, 0x00, // checksum, urgent pointer
+            0x02, 0x04, 0x05, 0xb4, // MSS option
+        ];
+        let fields = parse_transport_header(TCP, &tcp_header).unwrap();
+        assert_eq!((fields.source_port, fields.destination_port), (443, 51000));
+        assert_eq!(fields.header_length, 24);
+        assert_eq!(fields.combined_flags, 0x12);
+        assert_eq!(fields.window_size, 256);
+        assert_eq!((fields.sequence_number, fields.sequence_number_ack), (256, 2));
+        // Only the fixed part is needed, the options are checked by the caller
+        assert!(parse_transport_header(TCP, &tcp_header[..20]).is_ok());
+        assert_eq!(parse_transport_header(TCP, &tcp_header[..19]), Err(ParseError::Truncated));
+
+        // A data offset below the fixed header
+        let mut invalid = tcp_header;
+        invalid[12] = 0x40;
+        assert_eq!(parse_transport_header(TCP, &invalid), Err(ParseError::Invalid));
+
+        let udp_header = [0x00, 0x35, 0xc7, 0x38, 0x00, 0x07, 0x00, 0x00];
+        assert_eq!(parse_transport_header(UDP, &udp_header), Err(ParseError::Invalid));
+        // ICMPv6 has no ports, the type and code aren't read as ones
+        let fields = parse_transport_header(ICMPV6, &udp_header).unwrap();
+        assert_eq!((fields.source_port, fields.header_length), (0, 8));
+        assert_eq!(parse_transport_header(47, &udp_header), Err(ParseError::Unsupported));
+    }
+
+    #[test]
+    fn test_tcp_options_mask() {
+        // The options of a Linux SYN
+        let options = [
+            0x02, 0x04, 0x05, 0xb4, // MSS 1460
+            0x04, 0x02, // SACK-permitted
+            0x08, 0x0a, 0, 0, 0, 1, 0, 0, 0, 0, // timestamps
+            0x01, // NOP
+            0x03, 0x03, 0x07, // window scale 7
+        ];
+        let expected = tcp_option_bit(TCP_OPTION_MSS)
+            | tcp_option_bit(TCP_OPTION_SACK_PERMITTED)
+            | tcp_option_bit(TCP_OPTION_TIMESTAMPS)
+            | tcp_option_bit(TCP_OPTION_NOP)
+            | tcp_option_bit(TCP_OPTION_WINDOW_SCALE);
+        assert_eq!(tcp_options_mask(&options), expected);
+        assert_eq!(tcp_options_mask(&[]), 0);
+        // The end of the list stops the walk, as does an impossible length
+        assert_eq!(tcp_options_mask(&[0x00, 0x02, 0x04]), 0);
+        assert_eq!(tcp_options_mask(&[0x22, 0x00, 0x02, 0x04]), 1 << 5);
+        // An option cut off by the end of the header keeps its kind
+        assert_eq!(tcp_options_mask(&[0x01, 0x02]), 0b1_0001);
+    }
+
+    #[test]
+    fn test_parse_ipv4_header() {
+        let header = [
+            0x46, 0x00, 0x00, 0x3c, // version 4, IHL 6, total length 60
+            0x00, 0x00, 0x20, 0x01, // more fragments, offset 1
+            0x40, 0x06, 0x00, 0x00, // TTL 64, TCP
+            192, 168, 1, 10, // source
+            10, 0, 0, 2, // destination
+        ];
+        let fields = parse_ipv4_header(&header).unwrap();
+        assert_eq!(fields.source, [192, 168, 1, 10]);
+        assert_eq!(fields.header_length, 24);
+        assert_eq!(fields.total_length, 60);
+        assert_eq!((fields.protocol, fields.ttl, fields.fragment_offset), (6, 64, 1));
+        assert_eq!(fields.flags, IPV4_MORE_FRAGMENTS);
+
+        let mut invalid = header;
+        invalid[0] = 0x44;
+        assert_eq!(parse_ipv4_header(&invalid), Err(ParseError::Invalid));
+        assert_eq!(parse_ipv4_header(&header[..19]), Err(ParseError::Truncated));
+    }
+
+    #[test]
+    fn test_parse_ipv6_header() {
+        let mut header = [0; IPV6_HEADER_LENGTH];
+        // Version 6, traffic class 0xb8 and flow label 0xabcde
+        header[..8].copy_from_slice(&[0x6b, 0x8a, 0xbc, 0xde, 0x00, 0x20, 0x11, 0x40]);
+        header[23] = 1;
+        let fields = parse_ipv6_header(&header).unwrap();
+        assert_eq!(fields.flow_label, 0xabcde);
+        assert_eq!((fields.payload_length, fields.next_header, fields.hop_limit), (32, 17, 64));
+        assert_eq!(fields.source[15], 1);
+
+        header[0] = 0x40;
+        assert_eq!(parse_ipv6_header(&header), Err(ParseError::Invalid));
+        assert_eq!(parse_ipv6_header(&header[..39]), Err(ParseError::Truncated));
+    }
+}
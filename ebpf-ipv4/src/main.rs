@@ -4,65 +4,161 @@
 
 use aya_ebpf::{
     bindings::TC_ACT_PIPE,
+    helpers::bpf_ktime_get_ns,
     macros::{classifier, map},
     maps::{RingBuf, PerCpuArray},
     programs::TcContext,
 };
 use aya_log_ebpf::error;
 
-use common::EbpfEventIpv4;
-use network_types::{
-    eth::{EthHdr, EtherType},
-    ip::{IpProto, Ipv4Hdr},
-    tcp::TcpHdr,
-    udp::UdpHdr,
-    icmp::IcmpHdr,
+use common::{
+    parse::{
+        parse_ipv4_header, parse_transport_header, tcp_option_bit, TransportFields, DCCP,
+        DCCP_MIN_HEADER_LENGTH, ICMP, ICMP_HEADER_LENGTH, IPV4_MIN_HEADER_LENGTH, SCTP,
+        SCTP_HEADER_LENGTH, TCP, TCP_MAX_OPTIONS_LENGTH, TCP_MIN_HEADER_LENGTH, TCP_OPTION_END,
+        TCP_OPTION_NOP, TCP_SYN_FLAG, UDP, UDP_HEADER_LENGTH,
+    },
+    EbpfEventIpv4,
 };
+use network_types::eth::{EthHdr, EtherType};
 
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
     unsafe { core::hint::unreachable_unchecked() }
 }
 
+/// Offset of the IP header, set by userspace when loading the program.
+///
+/// Interfaces without a link layer header (TUN, WireGuard) pass packets starting at the IP header.
+#[no_mangle]
+static IP_HEADER_OFFSET: u32 = EthHdr::LEN as u32;
+
+/// Where the EtherType sits in the Ethernet header.
+const ETHER_TYPE_OFFSET: usize = EthHdr::LEN - 2;
+
+/// The EtherType of PPPoE session frames, e.g. on DSL links.
+const ETHER_TYPE_PPPOE_SESSION: u16 = 0x8864;
+
+/// The PPPoE session header and the PPP protocol field in front of the IP header.
+const PPPOE_HEADER_LENGTH: usize = 8;
+
+/// The PPP protocol number of IPv4.
+const PPP_PROTOCOL_IPV4: u16 = 0x0021;
+
 #[map]
 static DROPPED_PACKETS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
 
+/// Packets skipped because their headers are truncated or inconsistent.
 #[map]
-static EVENTS_IPV4: RingBuf = RingBuf::with_byte_size(1024 * 1024 * 10, 0); // 10 MB
+static MALFORMED_PACKETS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// Resized by userspace with `ring_buffer_size` when loading the program
+#[map]
+static EVENTS_IPV4: RingBuf = RingBuf::with_byte_size(1024 * 1024 * 16, 0); // 16 MB
 
 #[classifier]
 pub fn tc_flow_track(ctx: TcContext) -> i32 {
     match process_packet(&ctx) {
         Ok(action) => action,
-        Err(_) => TC_ACT_PIPE,
+        Err(_) => {
+            // A header didn't fit into the packet or had an impossible length
+            if let Some(counter) = MALFORMED_PACKETS.get_ptr_mut(0) {
+                unsafe { *counter += 1; }
+            }
+            TC_ACT_PIPE
+        }
     }
 }
 
 fn process_packet(ctx: &TcContext) -> Result<i32, ()> {
-    let ether_type = ctx.load::<EthHdr>(0).map_err(|_| ())?.ether_type;
-    if ether_type != EtherType::Ipv4 {
+    let mut ip_header_offset = unsafe { core::ptr::read_volatile(&IP_HEADER_OFFSET) } as usize;
+    if ip_header_offset == EthHdr::LEN {
+        // Compared as a number, the enum has no variant for most EtherTypes
+        let ether_type = ctx.load::<u16>(ETHER_TYPE_OFFSET).map_err(|_| ())?;
+        if ether_type == ETHER_TYPE_PPPOE_SESSION.to_be() {
+            match pppoe_ip_header_offset(ctx)? {
+                Some(offset) => ip_header_offset = offset,
+                None => return Ok(TC_ACT_PIPE),
+            }
+        } else if ether_type != EtherType::Ipv4 as u16 {
+            return Ok(TC_ACT_PIPE);
+        }
+    } else if ctx.load::<u8>(ip_header_offset).map_err(|_| ())? >> 4 != 4 {
+        // Without a link layer header the IP version tells the packets apart
         return Ok(TC_ACT_PIPE);
     }
 
-    let ipv4hdr = ctx.load::<Ipv4Hdr>(EthHdr::LEN).map_err(|_| ())?;
-    let packet_info = PacketInfo::new(&ipv4hdr, ctx.data_end() - ctx.data())?;
+    // Only the fixed part is parsed, the options are skipped with the header length
+    let header = ctx.load::<[u8; IPV4_MIN_HEADER_LENGTH]>(ip_header_offset).map_err(|_| ())?;
+    let ipv4hdr = parse_ipv4_header(&header).map_err(|_| ())?;
+    // The length from the IP header, the skb data only holds the linear part of jumbo frames.
+    // Segmentation offload leaves it at 0 for packets above 64 KiB, those use the skb length.
+    let ip_length = match ipv4hdr.total_length {
+        0 => (ctx.len() as usize).saturating_sub(ip_header_offset).min(u16::MAX as usize) as u16,
+        tot_len => tot_len,
+    };
+    let ip_header_length = ipv4hdr.header_length as usize;
+    if (ip_length as usize) < ip_header_length {
+        return Err(());
+    }
+    // Only the first fragment carries the transport header
+    if ipv4hdr.fragment_offset != 0 {
+        return Ok(TC_ACT_PIPE);
+    }
+    let packet_info = PacketInfo {
+        ipv4_source: u32::from_ne_bytes(ipv4hdr.source),
+        ipv4_destination: u32::from_ne_bytes(ipv4hdr.destination),
+        length: ip_length,
+        ip_header_length: ip_header_length as u16,
+        ip_options_length: (ip_header_length - IPV4_MIN_HEADER_LENGTH) as u16,
+        protocol: ipv4hdr.protocol,
+        ttl: ipv4hdr.ttl,
+        ip_flags: ipv4hdr.flags,
+    };
+    let offset = ip_header_offset + ip_header_length;
 
-    match ipv4hdr.proto {
-        IpProto::Tcp => process_transport_packet::<TcpHdr>(ctx, packet_info),
-        IpProto::Udp => process_transport_packet::<UdpHdr>(ctx, packet_info),
-        IpProto::Icmp => process_transport_packet::<IcmpHdr>(ctx, packet_info),
+    // The fixed part of each header is loaded onto the stack, the verifier needs a constant size
+    match ipv4hdr.protocol {
+        TCP => process_transport_packet::<TCP_MIN_HEADER_LENGTH>(ctx, packet_info, offset),
+        UDP => process_transport_packet::<UDP_HEADER_LENGTH>(ctx, packet_info, offset),
+        ICMP => process_transport_packet::<ICMP_HEADER_LENGTH>(ctx, packet_info, offset),
+        SCTP => process_transport_packet::<SCTP_HEADER_LENGTH>(ctx, packet_info, offset),
+        DCCP => process_transport_packet::<DCCP_MIN_HEADER_LENGTH>(ctx, packet_info, offset),
         _ => Ok(TC_ACT_PIPE),
     }
 }
 
-fn process_transport_packet<T: NetworkHeader>(
+/// Returns where the IP header of a PPPoE session frame starts, `None` if it carries no IPv4.
+fn pppoe_ip_header_offset(ctx: &TcContext) -> Result<Option<usize>, ()> {
+    // Version 1 and type 1 in one byte, then the code, 0 for session data
+    let version_type = ctx.load::<u8>(EthHdr::LEN).map_err(|_| ())?;
+    let code = ctx.load::<u8>(EthHdr::LEN + 1).map_err(|_| ())?;
+    if version_type != 0x11 || code != 0 {
+        return Err(());
+    }
+    let ppp_protocol = u16::from_be(ctx.load::<u16>(EthHdr::LEN + 6).map_err(|_| ())?);
+    Ok((ppp_protocol == PPP_PROTOCOL_IPV4).then_some(EthHdr::LEN + PPPOE_HEADER_LENGTH))
+}
+
+fn process_transport_packet<const N: usize>(
     ctx: &TcContext,
     packet_info: PacketInfo,
+    transport_offset: usize,
 ) -> Result<i32, ()> {
-    let hdr = ctx
-        .load::<T>(EthHdr::LEN + Ipv4Hdr::LEN)
-        .map_err(|_| ())?;
-    let packet_log = packet_info.to_packet_log(&hdr);
+    let header = ctx.load::<[u8; N]>(transport_offset).map_err(|_| ())?;
+    let fields = parse_transport_header(packet_info.protocol, &header).map_err(|_| ())?;
+    // The transport header with its options must fit into the IP packet
+    if fields.header_length as u16 > packet_info.transport_length() {
+        return Err(());
+    }
+    // Only the SYNs carry the options the stacks are told apart by
+    let is_syn = packet_info.protocol == TCP && fields.combined_flags & TCP_SYN_FLAG != 0;
+    let tcp_options_mask = if is_syn {
+        syn_options_mask(ctx, transport_offset, fields.header_length as usize)
+    } else {
+        0
+    };
+    let packet_log = packet_info.to_packet_log(&fields, tcp_options_mask);
 
     // Reserve memory in the ring buffer for the event
     if let Some(mut entry) = EVENTS_IPV4.reserve::<EbpfEventIpv4>(0) {
@@ -81,127 +177,81 @@ fn process_transport_packet<T: NetworkHeader>(
     Ok(TC_ACT_PIPE)
 }
 
+/// Combines the bits of the option kinds of a TCP SYN header, see `tcp_option_bit`.
+///
+/// The options are loaded one byte at a time, each option takes at least one, so the loop is
+/// bounded by `TCP_MAX_OPTIONS_LENGTH`. The walk stops at the end of the option list and at an
+/// impossible option length, like `tcp_options_mask` of userspace.
+#[inline(always)]
+fn syn_options_mask(ctx: &TcContext, transport_offset: usize, header_length: usize) -> u8 {
+    let end = transport_offset + header_length;
+    let mut offset = transport_offset + TCP_MIN_HEADER_LENGTH;
+    let mut mask = 0;
+    for _ in 0..TCP_MAX_OPTIONS_LENGTH {
+        if offset >= end {
+            break;
+        }
+        let Ok(kind) = ctx.load::<u8>(offset) else {
+            break;
+        };
+        if kind == TCP_OPTION_END {
+            break;
+        }
+        mask |= tcp_option_bit(kind);
+        if kind == TCP_OPTION_NOP {
+            offset += 1;
+            continue;
+        }
+        match ctx.load::<u8>(offset + 1) {
+            Ok(length) if length >= 2 => offset += length as usize,
+            _ => break,
+        }
+    }
+    mask
+}
+
 struct PacketInfo {
     ipv4_source: u32,
     ipv4_destination: u32,
-    data_length: u16,
+    length: u16,
+    ip_header_length: u16,
+    ip_options_length: u16,
     protocol: u8,
+    ttl: u8,
+    ip_flags: u8,
 }
 
 impl PacketInfo {
-    fn new(ipv4hdr: &Ipv4Hdr, data_length: usize) -> Result<Self, ()> {
-        Ok(Self {
-            ipv4_source: ipv4hdr.src_addr,
-            ipv4_destination: ipv4hdr.dst_addr,
-            data_length: data_length as u16,
-            protocol: ipv4hdr.proto as u8,
-        })
+    /// The length of the IP packet after the IP header.
+    fn transport_length(&self) -> u16 {
+        self.length.saturating_sub(self.ip_header_length)
     }
-    
+
+    /// Builds the event, with the fields in network byte order as userspace expects them.
     #[inline(always)]
-    fn to_packet_log<T: NetworkHeader>(&self, header: &T) -> EbpfEventIpv4 {
+    fn to_packet_log(&self, header: &TransportFields, tcp_options_mask: u8) -> EbpfEventIpv4 {
+        let data_length = self
+            .length
+            .saturating_sub(self.ip_header_length.saturating_add(header.header_length as u16));
         EbpfEventIpv4::new(
             self.ipv4_destination,
             self.ipv4_source,
-            header.destination_port(),
-            header.source_port(),
-            self.data_length,
-            self.data_length + header.header_length() as u16,
-            header.window_size(),
-            header.combined_flags(),
+            header.destination_port.to_be(),
+            header.source_port.to_be(),
+            data_length.to_be(),
+            self.length.to_be(),
+            header.window_size.to_be(),
+            header.combined_flags,
             self.protocol,
-            header.header_length(),
-            header.sequence_number(),
-            header.sequence_number_ack(),
+            header.header_length,
+            header.sequence_number.to_be(),
+            header.sequence_number_ack.to_be(),
+            self.ttl,
+            self.ip_options_length.to_be(),
+            self.ip_flags,
+            tcp_options_mask,
+            // Userspace compares it with its monotonic clock for the capture latency
+            unsafe { bpf_ktime_get_ns() }.to_be(),
         )
     }
 }
-
-trait NetworkHeader {
-    fn source_port(&self) -> u16;
-    fn destination_port(&self) -> u16;
-    fn window_size(&self) -> u16;
-    fn combined_flags(&self) -> u8;
-    fn header_length(&self) -> u8;
-    fn sequence_number(&self) -> u32;
-    fn sequence_number_ack(&self) -> u32;
-}
-
-impl NetworkHeader for TcpHdr {
-    fn source_port(&self) -> u16 {
-        self.source
-    }
-    fn destination_port(&self) -> u16 {
-        self.dest
-    }
-    fn window_size(&self) -> u16 {
-        self.window as u16
-    }
-    fn combined_flags(&self) -> u8 {
-        ((self.fin() as u8) << 0)
-            | ((self.syn() as u8) << 1)
-            | ((self.rst() as u8) << 2)
-            | ((self.psh() as u8) << 3)
-            | ((self.ack() as u8) << 4)
-            | ((self.urg() as u8) << 5)
-            | ((self.ece() as u8) << 6)
-            | ((self.cwr() as u8) << 7)
-    }
-    fn header_length(&self) -> u8 {
-        TcpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        self.seq
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        self.ack_seq
-    }
-}
-
-impl NetworkHeader for UdpHdr {
-    fn source_port(&self) -> u16 {
-        self.source
-    }
-    fn destination_port(&self) -> u16 {
-        self.dest
-    }
-    fn window_size(&self) -> u16 {
-        0
-    }
-    fn combined_flags(&self) -> u8 {
-        0
-    }
-    fn header_length(&self) -> u8 {
-        UdpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        0
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        0
-    }
-}
-
-impl NetworkHeader for IcmpHdr {
-    fn source_port(&self) -> u16 {
-        0
-    }
-    fn destination_port(&self) -> u16 {
-        0
-    }
-    fn window_size(&self) -> u16 {
-        0
-    }
-    fn combined_flags(&self) -> u8 {
-        0
-    }
-    fn header_length(&self) -> u8 {
-        IcmpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        0
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        0
-    }
-}
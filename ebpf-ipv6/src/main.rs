@@ -4,19 +4,25 @@
 
 use aya_ebpf::{
     bindings::TC_ACT_PIPE,
+    helpers::bpf_ktime_get_ns,
     macros::{classifier, map},
     maps::{RingBuf, PerCpuArray},
     programs::TcContext,
 };
 use aya_log_ebpf::error;
 
-use common::EbpfEventIpv6;
+use common::{
+    parse::{
+        parse_ipv6_header, parse_transport_header, tcp_option_bit, TransportFields, DCCP,
+        DCCP_MIN_HEADER_LENGTH, ICMPV6, ICMP_HEADER_LENGTH, IPV6_HEADER_LENGTH, SCTP,
+        SCTP_HEADER_LENGTH, TCP, TCP_MAX_OPTIONS_LENGTH, TCP_MIN_HEADER_LENGTH, TCP_OPTION_END,
+        TCP_OPTION_NOP, TCP_SYN_FLAG, UDP, UDP_HEADER_LENGTH,
+    },
+    EbpfEventIpv6,
+};
 use network_types::{
     eth::{EthHdr, EtherType},
-    ip::{IpProto, Ipv6Hdr},
-    tcp::TcpHdr,
-    udp::UdpHdr,
-    icmp::IcmpHdr,
+    ip::IpProto,
 };
 
 #[panic_handler]
@@ -24,45 +30,186 @@ fn panic(_info: &core::panic::PanicInfo) -> ! {
     unsafe { core::hint::unreachable_unchecked() }
 }
 
+// IPv6 extension headers that are skipped to find the transport header
+const HOP_BY_HOP: u8 = IpProto::HopOpt as u8;
+const ROUTING: u8 = IpProto::Ipv6Route as u8;
+const FRAGMENT: u8 = IpProto::Ipv6Frag as u8;
+const AUTHENTICATION: u8 = IpProto::Ah as u8;
+const DESTINATION_OPTIONS: u8 = IpProto::Ipv6Opts as u8;
+
+/// Upper bound of extension headers to walk, keeps the loop bounded for the verifier
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+/// Offset of the IP header, set by userspace when loading the program.
+///
+/// Interfaces without a link layer header (TUN, WireGuard) pass packets starting at the IP header.
+#[no_mangle]
+static IP_HEADER_OFFSET: u32 = EthHdr::LEN as u32;
+
+/// Where the EtherType sits in the Ethernet header.
+const ETHER_TYPE_OFFSET: usize = EthHdr::LEN - 2;
+
+/// The EtherType of PPPoE session frames, e.g. on DSL links.
+const ETHER_TYPE_PPPOE_SESSION: u16 = 0x8864;
+
+/// The PPPoE session header and the PPP protocol field in front of the IP header.
+const PPPOE_HEADER_LENGTH: usize = 8;
+
+/// The PPP protocol number of IPv6.
+const PPP_PROTOCOL_IPV6: u16 = 0x0057;
+
 #[map]
 static DROPPED_PACKETS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
 
+/// Packets skipped because their headers are truncated or inconsistent.
+#[map]
+static MALFORMED_PACKETS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// Resized by userspace with `ring_buffer_size` when loading the program
 #[map]
-static EVENTS_IPV6: RingBuf = RingBuf::with_byte_size(1024 * 1024 * 10, 0); // 10 MB
+static EVENTS_IPV6: RingBuf = RingBuf::with_byte_size(1024 * 1024 * 16, 0); // 16 MB
 
 #[classifier]
 pub fn tc_flow_track(ctx: TcContext) -> i32 {
     match process_packet(&ctx) {
         Ok(action) => action,
-        Err(_) => TC_ACT_PIPE,
+        Err(_) => {
+            // A header didn't fit into the packet or had an impossible length
+            if let Some(counter) = MALFORMED_PACKETS.get_ptr_mut(0) {
+                unsafe { *counter += 1; }
+            }
+            TC_ACT_PIPE
+        }
     }
 }
 
 fn process_packet(ctx: &TcContext) -> Result<i32, ()> {
-    let ether_type = ctx.load::<EthHdr>(0).map_err(|_| ())?.ether_type;
-    if ether_type != EtherType::Ipv6 {
+    let mut ip_header_offset = unsafe { core::ptr::read_volatile(&IP_HEADER_OFFSET) } as usize;
+    if ip_header_offset == EthHdr::LEN {
+        // Compared as a number, the enum has no variant for most EtherTypes
+        let ether_type = ctx.load::<u16>(ETHER_TYPE_OFFSET).map_err(|_| ())?;
+        if ether_type == ETHER_TYPE_PPPOE_SESSION.to_be() {
+            match pppoe_ip_header_offset(ctx)? {
+                Some(offset) => ip_header_offset = offset,
+                None => return Ok(TC_ACT_PIPE),
+            }
+        } else if ether_type != EtherType::Ipv6 as u16 {
+            return Ok(TC_ACT_PIPE);
+        }
+    } else if ctx.load::<u8>(ip_header_offset).map_err(|_| ())? >> 4 != 6 {
+        // Without a link layer header the IP version tells the packets apart
         return Ok(TC_ACT_PIPE);
     }
     
-    let ipv6hdr = ctx.load::<Ipv6Hdr>(EthHdr::LEN).map_err(|_| ())?;
-    let packet_info = PacketInfo::new(&ipv6hdr, ctx.data_end() - ctx.data())?;
+    let header = ctx.load::<[u8; IPV6_HEADER_LENGTH]>(ip_header_offset).map_err(|_| ())?;
+    let ipv6hdr = parse_ipv6_header(&header).map_err(|_| ())?;
+    let Some((protocol, transport_offset, extension_header_count)) =
+        skip_extension_headers(ctx, ipv6hdr.next_header, ip_header_offset + IPV6_HEADER_LENGTH)?
+    else {
+        return Ok(TC_ACT_PIPE);
+    };
+    // The length from the IP header, the skb data only holds the linear part of jumbo frames.
+    // Jumbograms and segmentation offload leave it at 0, those use the skb length.
+    let ip_length = match ipv6hdr.payload_length {
+        0 => (ctx.len() as usize).saturating_sub(ip_header_offset).min(u16::MAX as usize) as u16,
+        payload_len => payload_len.saturating_add(IPV6_HEADER_LENGTH as u16),
+    };
+    // The extension headers must fit into the IP packet
+    if transport_offset - ip_header_offset > ip_length as usize {
+        return Err(());
+    }
+    let packet_info = PacketInfo {
+        // Kept in network byte order like the other header fields
+        ipv6_source: u128::from_ne_bytes(ipv6hdr.source),
+        ipv6_destination: u128::from_ne_bytes(ipv6hdr.destination),
+        length: ip_length,
+        ip_header_length: (transport_offset - ip_header_offset) as u16,
+        ip_options_length: (transport_offset - ip_header_offset - IPV6_HEADER_LENGTH) as u16,
+        protocol,
+        extension_header_count,
+        hop_limit: ipv6hdr.hop_limit,
+        flow_label: ipv6hdr.flow_label,
+    };
+    let offset = transport_offset;
 
-    match ipv6hdr.next_hdr {
-        IpProto::Tcp => process_transport_packet::<TcpHdr>(ctx, packet_info),
-        IpProto::Udp => process_transport_packet::<UdpHdr>(ctx, packet_info),
-        IpProto::Icmp => process_transport_packet::<IcmpHdr>(ctx, packet_info),
+    // The fixed part of each header is loaded onto the stack, the verifier needs a constant size
+    match protocol {
+        TCP => process_transport_packet::<TCP_MIN_HEADER_LENGTH>(ctx, packet_info, offset),
+        UDP => process_transport_packet::<UDP_HEADER_LENGTH>(ctx, packet_info, offset),
+        ICMPV6 => process_transport_packet::<ICMP_HEADER_LENGTH>(ctx, packet_info, offset),
+        SCTP => process_transport_packet::<SCTP_HEADER_LENGTH>(ctx, packet_info, offset),
+        DCCP => process_transport_packet::<DCCP_MIN_HEADER_LENGTH>(ctx, packet_info, offset),
         _ => Ok(TC_ACT_PIPE),
     }
 }
 
-fn process_transport_packet<T: NetworkHeader>(
-    ctx: &TcContext, 
-    packet_info: PacketInfo
+/// Walks the extension header chain starting at `offset`, right behind the IPv6 header.
+///
+/// Returns the transport protocol, the offset of the transport header and the number of
+/// skipped extension headers. Non-first fragments have no transport header, they return `None`.
+/// Truncated chains and chains longer than `MAX_EXTENSION_HEADERS` are an error.
+#[inline(always)]
+fn skip_extension_headers(
+    ctx: &TcContext,
+    next_hdr: u8,
+    offset: usize,
+) -> Result<Option<(u8, usize, u8)>, ()> {
+    let mut next_header = next_hdr;
+    let mut offset = offset;
+
+    for count in 0..MAX_EXTENSION_HEADERS {
+        let header_length = match next_header {
+            HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS => {
+                (ctx.load::<u8>(offset + 1).map_err(|_| ())? as usize + 1) * 8
+            }
+            FRAGMENT => {
+                let fragment_offset = u16::from_be(ctx.load::<u16>(offset + 2).map_err(|_| ())?) >> 3;
+                if fragment_offset != 0 {
+                    return Ok(None);
+                }
+                8
+            }
+            AUTHENTICATION => (ctx.load::<u8>(offset + 1).map_err(|_| ())? as usize + 2) * 4,
+            _ => return Ok(Some((next_header, offset, count as u8))),
+        };
+        next_header = ctx.load::<u8>(offset).map_err(|_| ())?;
+        offset += header_length;
+    }
+
+    Err(())
+}
+
+/// Returns where the IP header of a PPPoE session frame starts, `None` if it carries no IPv6.
+fn pppoe_ip_header_offset(ctx: &TcContext) -> Result<Option<usize>, ()> {
+    // Version 1 and type 1 in one byte, then the code, 0 for session data
+    let version_type = ctx.load::<u8>(EthHdr::LEN).map_err(|_| ())?;
+    let code = ctx.load::<u8>(EthHdr::LEN + 1).map_err(|_| ())?;
+    if version_type != 0x11 || code != 0 {
+        return Err(());
+    }
+    let ppp_protocol = u16::from_be(ctx.load::<u16>(EthHdr::LEN + 6).map_err(|_| ())?);
+    Ok((ppp_protocol == PPP_PROTOCOL_IPV6).then_some(EthHdr::LEN + PPPOE_HEADER_LENGTH))
+}
+
+fn process_transport_packet<const N: usize>(
+    ctx: &TcContext,
+    packet_info: PacketInfo,
+    transport_offset: usize,
 ) -> Result<i32, ()> {
-    let tcphdr = ctx
-        .load::<T>(EthHdr::LEN + Ipv6Hdr::LEN)
-        .map_err(|_| ())?;
-    let packet_log = packet_info.to_packet_log(&tcphdr);
+    let header = ctx.load::<[u8; N]>(transport_offset).map_err(|_| ())?;
+    let fields = parse_transport_header(packet_info.protocol, &header).map_err(|_| ())?;
+    // The transport header with its options must fit into the IP packet
+    if fields.header_length as u16 > packet_info.transport_length() {
+        return Err(());
+    }
+    // Only the SYNs carry the options the stacks are told apart by
+    let is_syn = packet_info.protocol == TCP && fields.combined_flags & TCP_SYN_FLAG != 0;
+    let tcp_options_mask = if is_syn {
+        syn_options_mask(ctx, transport_offset, fields.header_length as usize)
+    } else {
+        0
+    };
+    let packet_log = packet_info.to_packet_log(&fields, tcp_options_mask);
 
     // Reserve memory in the ring buffer for the event
     if let Some(mut entry) = EVENTS_IPV6.reserve::<EbpfEventIpv6>(0) {
@@ -81,127 +228,84 @@ fn process_transport_packet<T: NetworkHeader>(
     Ok(TC_ACT_PIPE)
 }
 
+/// Combines the bits of the option kinds of a TCP SYN header, see `tcp_option_bit`.
+///
+/// The options are loaded one byte at a time, each option takes at least one, so the loop is
+/// bounded by `TCP_MAX_OPTIONS_LENGTH`. The walk stops at the end of the option list and at an
+/// impossible option length, like `tcp_options_mask` of userspace.
+#[inline(always)]
+fn syn_options_mask(ctx: &TcContext, transport_offset: usize, header_length: usize) -> u8 {
+    let end = transport_offset + header_length;
+    let mut offset = transport_offset + TCP_MIN_HEADER_LENGTH;
+    let mut mask = 0;
+    for _ in 0..TCP_MAX_OPTIONS_LENGTH {
+        if offset >= end {
+            break;
+        }
+        let Ok(kind) = ctx.load::<u8>(offset) else {
+            break;
+        };
+        if kind == TCP_OPTION_END {
+            break;
+        }
+        mask |= tcp_option_bit(kind);
+        if kind == TCP_OPTION_NOP {
+            offset += 1;
+            continue;
+        }
+        match ctx.load::<u8>(offset + 1) {
+            Ok(length) if length >= 2 => offset += length as usize,
+            _ => break,
+        }
+    }
+    mask
+}
+
 struct PacketInfo {
     ipv6_source: u128,
     ipv6_destination: u128,
-    data_length: u16,
+    length: u16,
+    ip_header_length: u16,
+    ip_options_length: u16,
     protocol: u8,
+    extension_header_count: u8,
+    hop_limit: u8,
+    flow_label: u32,
 }
 
 impl PacketInfo {
-    fn new(ipv6hdr: &Ipv6Hdr, data_length: usize) -> Result<Self, ()> {
-        Ok(Self {
-            ipv6_source: u128::from_be_bytes(unsafe { ipv6hdr.src_addr.in6_u.u6_addr8 }),
-            ipv6_destination: u128::from_be_bytes(unsafe { ipv6hdr.dst_addr.in6_u.u6_addr8 }),
-            data_length: data_length as u16,
-            protocol: ipv6hdr.next_hdr as u8,
-        })
+    /// The length of the IP packet after the IP header.
+    fn transport_length(&self) -> u16 {
+        self.length.saturating_sub(self.ip_header_length)
     }
 
+    /// Builds the event, with the fields in network byte order as userspace expects them.
     #[inline(always)]
-    fn to_packet_log<T: NetworkHeader>(&self, header: &T) -> EbpfEventIpv6 {
+    fn to_packet_log(&self, header: &TransportFields, tcp_options_mask: u8) -> EbpfEventIpv6 {
+        // The IPv6 header length includes the extension headers
+        let data_length = self
+            .length
+            .saturating_sub(self.ip_header_length.saturating_add(header.header_length as u16));
         EbpfEventIpv6::new(
             self.ipv6_destination,
             self.ipv6_source,
-            header.destination_port(),
-            header.source_port(),
-            self.data_length,
-            self.data_length + header.header_length() as u16,
-            header.window_size(),
-            header.combined_flags(),
+            header.destination_port.to_be(),
+            header.source_port.to_be(),
+            data_length.to_be(),
+            self.length.to_be(),
+            header.window_size.to_be(),
+            header.combined_flags,
             self.protocol,
-            header.header_length(),
-            header.sequence_number(),
-            header.sequence_number_ack(),
+            header.header_length,
+            header.sequence_number.to_be(),
+            header.sequence_number_ack.to_be(),
+            self.extension_header_count,
+            self.hop_limit,
+            self.ip_options_length.to_be(),
+            tcp_options_mask,
+            self.flow_label.to_be(),
+            // Userspace compares it with its monotonic clock for the capture latency
+            unsafe { bpf_ktime_get_ns() }.to_be(),
         )
     }
 }
-
-trait NetworkHeader {
-    fn source_port(&self) -> u16;
-    fn destination_port(&self) -> u16;
-    fn window_size(&self) -> u16;
-    fn combined_flags(&self) -> u8;
-    fn header_length(&self) -> u8;
-    fn sequence_number(&self) -> u32;
-    fn sequence_number_ack(&self) -> u32;
-}
-
-impl NetworkHeader for TcpHdr {
-    fn source_port(&self) -> u16 {
-        self.source
-    }
-    fn destination_port(&self) -> u16 {
-        self.dest
-    }
-    fn window_size(&self) -> u16 {
-        self.window as u16
-    }
-    fn combined_flags(&self) -> u8 {
-        ((self.fin() as u8) << 0)
-            | ((self.syn() as u8) << 1)
-            | ((self.rst() as u8) << 2)
-            | ((self.psh() as u8) << 3)
-            | ((self.ack() as u8) << 4)
-            | ((self.urg() as u8) << 5)
-            | ((self.ece() as u8) << 6)
-            | ((self.cwr() as u8) << 7)
-    }
-    fn header_length(&self) -> u8 {
-        TcpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        self.seq
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        self.ack_seq
-    }
-}
-
-impl NetworkHeader for UdpHdr {
-    fn source_port(&self) -> u16 {
-        self.source
-    }
-    fn destination_port(&self) -> u16 {
-        self.dest
-    }
-    fn window_size(&self) -> u16 {
-        0
-    }
-    fn combined_flags(&self) -> u8 {
-        0
-    }
-    fn header_length(&self) -> u8 {
-        UdpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        0
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        0
-    }
-}
-
-impl NetworkHeader for IcmpHdr {
-    fn source_port(&self) -> u16 {
-        0
-    }
-    fn destination_port(&self) -> u16 {
-        0
-    }
-    fn window_size(&self) -> u16 {
-        0
-    }
-    fn combined_flags(&self) -> u8 {
-        0
-    }
-    fn header_length(&self) -> u8 {
-        IcmpHdr::LEN as u8
-    }
-    fn sequence_number(&self) -> u32 {
-        0
-    }
-    fn sequence_number_ack(&self) -> u32 {
-        0
-    }
-}
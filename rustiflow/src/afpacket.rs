@@ -0,0 +1,125 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    flows::{flow::Flow, settings::FlowSettings},
+    interfaces::check_interface,
+    metrics,
+    pcap::{extract_packet_features, LinkLayer},
+    pipeline::{PacketSender, Pipeline},
+};
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use pcap::{Active, Capture};
+use tokio::signal;
+
+// Upper bound for a blocking read, so the capture thread notices the shutdown
+const READ_TIMEOUT_MS: i32 = 100;
+// Size of the kernel ring buffer shared with the AF_PACKET socket
+const RING_BUFFER_SIZE: i32 = 64 * 1024 * 1024;
+
+/// Starts the realtime processing of packets on the given interface through an AF_PACKET socket,
+/// into the flow tables of a pipeline.
+///
+/// Unlike the eBPF backend, every packet is copied to userspace and parsed there. This needs
+/// only CAP_NET_RAW and no eBPF support in the kernel, at the cost of throughput.
+/// The function will return the number of packets dropped by the kernel.
+pub async fn handle_afpacket<T>(
+    pipeline: &Pipeline<T>,
+    interface: &str,
+    ingress_only: bool,
+) -> Result<u64, anyhow::Error>
+where
+    T: Flow,
+{
+    check_interface(interface)?;
+
+    // libpcap captures live traffic on Linux with a memory mapped AF_PACKET ring
+    let capture = Capture::from_device(interface)?
+        .promisc(true)
+        .immediate_mode(true)
+        .timeout(READ_TIMEOUT_MS)
+        .buffer_size(RING_BUFFER_SIZE)
+        .open()
+        .map_err(|e| {
+            anyhow!(
+                "Failed to open an AF_PACKET socket on {}: {}. Capturing needs root or the \
+                CAP_NET_RAW capability, grant it with `sudo setcap cap_net_raw+ep <path to rustiflow>`.",
+                interface,
+                e
+            )
+        })?;
+    if ingress_only {
+        capture.direction(pcap::Direction::In)?;
+    }
+
+    // Read the socket on a dedicated thread, the reads block until a packet or the timeout
+    let stop = Arc::new(AtomicBool::new(false));
+    let capture_task = tokio::task::spawn_blocking({
+        let settings = Arc::clone(pipeline.settings());
+        let sender = pipeline.sender();
+        let stop = stop.clone();
+        move || capture_packets(capture, &settings, sender, &stop)
+    });
+
+    info!("Waiting for Ctrl-C...");
+
+    signal::ctrl_c().await?;
+    stop.store(true, Ordering::Relaxed);
+
+    // The remaining flows are exported once the pipeline shuts down
+    let total_dropped = capture_task.await??;
+    debug!("Capture thread finished");
+
+    Ok(total_dropped)
+}
+
+/// Reads packets from the capture until `stop` is set and sends them to the pipeline.
+///
+/// Returns the number of packets dropped by the kernel.
+fn capture_packets(
+    mut capture: Capture<Active>,
+    settings: &FlowSettings,
+    sender: PacketSender,
+    stop: &AtomicBool,
+) -> Result<u64, pcap::Error> {
+    // TUN and WireGuard interfaces deliver packets without an Ethernet header
+    let link_layer = LinkLayer::from_linktype(capture.get_datalink());
+
+    while !stop.load(Ordering::Relaxed) {
+        let packet = match capture.next_packet() {
+            Ok(packet) => packet,
+            Err(pcap::Error::TimeoutExpired) => continue,
+            Err(e) => return Err(e),
+        };
+        metrics::add_captured_packets(1);
+
+        // Convert TimeVal from packet capture to DateTime<Utc>
+        let Some(timestamp) = DateTime::from_timestamp(
+            packet.header.ts.tv_sec,
+            (packet.header.ts.tv_usec * 1000) as u32,
+        ) else {
+            continue;
+        };
+
+        if let Some(mut packet_features) =
+            extract_packet_features(packet.data, link_layer, timestamp, settings)
+        {
+            // The kernel stamps the packets with the wall clock when they arrive
+            if let Ok(latency) = (Utc::now() - timestamp).to_std() {
+                packet_features.capture_latency = Some(latency);
+                #[cfg(feature = "otel")]
+                crate::otel::record_capture_latency(latency);
+            }
+            if let Err(e) = sender.blocking_send(packet_features) {
+                error!("Failed to send packet_features: {}", e);
+            }
+        }
+    }
+
+    let stats = capture.stats()?;
+    Ok(u64::from(stats.dropped) + u64::from(stats.if_dropped))
+}
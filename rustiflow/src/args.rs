@@ -1,5 +1,10 @@
-use clap::{ArgGroup, Args, Parser, Subcommand};
-use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use ipnetwork::IpNetwork;
+use clap::{parser::ValueSource, ArgGroup, ArgMatches, Args, Command, Parser, Subcommand};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use strum_macros::{EnumString, VariantNames};
 
 #[derive(Debug, Parser)]
@@ -10,47 +15,31 @@ pub struct Cli {
     #[clap(long, short = 'c', group = "config_group")]
     pub config_file: Option<String>,
 
-    /// The feature set to use (required if no config file is provided)
-    #[clap(long, short, group = "cli_group")]
-    pub features: Option<FlowType>,
-
-    /// The maximum time a flow is allowed to last in seconds (optional)
-    #[clap(long, default_value_t = 3600, group = "cli_group")]
-    pub active_timeout: u64,
+    /// The flow settings, the flags given override those of the config file
+    #[command(flatten)]
+    pub config: ExportConfig,
 
-    /// The maximum time with no packets for a flow in seconds (optional)
-    #[clap(long, default_value_t = 120, group = "cli_group")]
-    pub idle_timeout: u64,
+    /// The output settings, the flags given override those of the config file
+    #[command(flatten)]
+    pub output: OutputConfig,
 
-    /// The print interval for open flows in seconds (optional)
+    /// The host and native protocol port of the ClickHouse server (used if method is ClickHouse)
     #[clap(long, group = "cli_group")]
-    pub early_export: Option<u64>,
-
-    /// Interval (in seconds) for checking and expiring flows in the flowtable.
-    /// This represents how often the flowtable should be scanned to remove inactive flows.
-    #[clap(long, default_value_t = 60, group = "cli_group")]
-    pub expiration_check_interval: u64,
+    pub clickhouse_address: Option<String>,
 
-    /// The numbers of threads to use for processing packets (optional)
-    /// (default: number of logical CPUs)
+    /// The ClickHouse table the flows are inserted into (used if method is ClickHouse)
     #[clap(long, group = "cli_group")]
-    pub threads: Option<u8>,
-
-    /// Output method (required if no config file is provided)
-    #[clap(long, short, group = "cli_group")]
-    pub output: Option<ExportMethodType>,
-
-    /// File path for output (used if method is Csv)
-    #[clap(long, group = "cli_group", required_if_eq("output", "Csv"))]
-    pub export_path: Option<String>,
+    pub clickhouse_table: Option<String>,
 
-    /// Whether to export the feature header
-    #[clap(long, action = clap::ArgAction::SetTrue, group = "cli_group")]
-    pub header: bool,
+    /// Detect port and host scans in the exported flows and write the alerts to this CSV file
+    /// (optional)
+    #[clap(long, group = "cli_group")]
+    pub scan_alerts: Option<String>,
 
-    /// Whether to drop contaminant features
-    #[clap(long, action = clap::ArgAction::SetTrue, group = "cli_group")]
-    pub drop_contaminant_features: bool,
+    /// Roll the exported flows up per source IP and write the records to this CSV file
+    /// (optional)
+    #[clap(long, group = "cli_group")]
+    pub host_rollups: Option<String>,
 
     /// Subcommands (Real-time or Pcap)
     #[clap(subcommand)]
@@ -66,13 +55,62 @@ pub enum Commands {
         /// Whether to capture only ingress packets
         #[clap(long, action = clap::ArgAction::SetTrue)]
         ingress_only: bool,
+        /// How packets are captured from the interface
+        #[clap(long, value_enum, default_value_t = CaptureBackend::Ebpf)]
+        #[serde(default)]
+        backend: CaptureBackend,
     },
 
     /// Feature extraction from a pcap file
     Pcap {
-        /// The relative path to the pcap file
+        /// The relative path to the pcap file, which may be gzip or zstd compressed, or `-` to
+        /// read the capture from stdin
         path: String,
     },
+
+    /// List the network interfaces and whether realtime capture can use them
+    ListInterfaces,
+
+    /// Print the schema of the flow records of the selected feature set, capturing nothing
+    Schema {
+        /// The JSON Schema of the CSV records, or the Protocol Buffers schema of the messages
+        #[clap(long, value_enum, default_value_t = SchemaFormat::Json)]
+        #[serde(default)]
+        format: SchemaFormat,
+    },
+
+    /// Write the records of an earlier export or a dead-letter file to the output, capturing
+    /// nothing
+    Replay {
+        /// The file of the records, or `-` to read them from stdin
+        path: String,
+        /// The format of the records
+        #[clap(long, value_enum, default_value_t = ReplayFormat::Csv)]
+        #[serde(default)]
+        format: ReplayFormat,
+    },
+}
+
+/// The format of the schema printed by the `schema` command.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SchemaFormat {
+    /// A JSON Schema of the CSV records
+    #[default]
+    Json,
+
+    /// A proto3 schema of the messages of the Protobuf output
+    Proto,
+}
+
+/// The format of the records read by the `replay` command.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReplayFormat {
+    /// CSV records, e.g. of the Csv output or a dead-letter file, with or without the header
+    #[default]
+    Csv,
+
+    /// Length-delimited messages of the Protobuf output
+    Protobuf,
 }
 
 impl ToString for Commands {
@@ -81,19 +119,27 @@ impl ToString for Commands {
             Commands::Realtime {
                 interface,
                 ingress_only,
+                backend,
             } => format!(
-                "Realtime/Interface: {}/Ingress only: {}",
-                interface, ingress_only
+                "Realtime/Interface: {}/Ingress only: {}/Backend: {:?}",
+                interface, ingress_only, backend
             ),
             Commands::Pcap { path } => format!("Pcap/Path: {}", path),
+            Commands::ListInterfaces => "List interfaces".to_string(),
+            Commands::Schema { format } => format!("Schema/Format: {:?}", format),
+            Commands::Replay { path, format } => {
+                format!("Replay/Path: {}/Format: {:?}", path, format)
+            }
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Args, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ExportConfig {
-    /// The feature set to use
-    #[clap(short, long, value_enum)]
+    /// The feature set to use (required if no config file is provided)
+    #[clap(short, long, value_enum, default_value_t = FlowType::Basic)]
+    #[clap(hide_default_value = true)]
     pub features: FlowType,
 
     /// The maximum time a flow is allowed to last in seconds
@@ -108,6 +154,37 @@ pub struct ExportConfig {
     #[clap(long)]
     pub early_export: Option<u64>,
 
+    /// Export any flow that lasted this many seconds, even one kept across the active timeout
+    #[clap(long)]
+    #[serde(default)]
+    pub max_flow_duration: Option<u64>,
+
+    /// Export an interim record of every active flow every this many seconds
+    #[clap(long)]
+    #[serde(default)]
+    pub export_window: Option<u64>,
+
+    /// Export the signed lengths of the first N packets of every flow
+    #[clap(long)]
+    #[serde(default)]
+    pub packet_sequence_length: Option<usize>,
+
+    /// The memory the packet sequences of all flows may hold in MiB, further flows go without
+    #[clap(long)]
+    #[serde(default)]
+    pub feature_memory_limit: Option<u64>,
+
+    /// The number of flows each flow table (one per thread) reserves room for at the start,
+    /// so it doesn't grow and rehash while the first flows arrive
+    #[clap(long)]
+    #[serde(default)]
+    pub flow_table_capacity: Option<usize>,
+
+    /// Export the OR of the raw TCP flag bytes of each direction of every flow
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub raw_flags: bool,
+
     /// Interval (in seconds) for checking and expiring flows in the flowtable.
     /// This represents how often the flowtable should be scanned to remove inactive flows.
     #[clap(long, default_value_t = 60, group = "cli_group")]
@@ -117,18 +194,401 @@ pub struct ExportConfig {
     /// (default: number of logical CPUs)
     #[clap(short, long)]
     pub threads: Option<u8>,
+
+    /// Run capture, aggregation and export on a single thread, with a single shard, so the
+    /// output of the same input is always the same
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub single_threaded: bool,
+
+    /// Which packet length is counted in the byte features
+    #[clap(long, value_enum, default_value_t = ByteCount::Payload)]
+    #[serde(default)]
+    pub byte_count: ByteCount,
+
+    /// Which IP versions are processed, packets of the other one are ignored
+    #[clap(long, value_enum, default_value_t = IpVersion::Both)]
+    #[serde(default)]
+    pub ip_version: IpVersion,
+
+    /// Which packet fields make up the flow key, packets with the same key form a flow
+    #[clap(long, value_enum, default_value_t = FlowKeyStrategy::FiveTuple)]
+    #[serde(default)]
+    pub flow_key: FlowKeyStrategy,
+
+    /// Whether to link FTP data connections to their control connection with a session id
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub track_expectations: bool,
+
+    /// Whether a new TCP handshake (SYN) on an ongoing connection starts a new flow
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub split_on_syn: bool,
+
+    /// Start a new TCP flow when the sequence number jumps back by more than this
+    #[clap(long)]
+    #[serde(default)]
+    pub sequence_reset_threshold: Option<u32>,
+
+    /// Only track 1 in N flows, picked by a hash of their 5-tuple
+    #[clap(long)]
+    #[serde(default)]
+    pub flow_sampling: Option<u32>,
+
+    /// Flows with a lower coefficient of variation of their IATs are flagged as periodic
+    #[clap(long, default_value_t = DEFAULT_PERIODICITY_THRESHOLD)]
+    #[serde(default = "default_periodicity_threshold")]
+    pub periodicity_threshold: f64,
+
+    /// The minimum number of packets of a flow to be flagged as periodic
+    #[clap(long, default_value_t = DEFAULT_PERIODICITY_MIN_PACKETS)]
+    #[serde(default = "default_periodicity_min_packets")]
+    pub periodicity_min_packets: u32,
+
+    /// The length of the first window of a flow in milliseconds, from its first packet
+    #[clap(long, default_value_t = DEFAULT_FIRST_WINDOW)]
+    #[serde(default = "default_first_window")]
+    pub first_window: u64,
+
+    /// Whether to leave TCP keepalives and zero window probes out of the statistical features
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub exclude_keepalives: bool,
+
+    /// Feature groups that aren't computed, their features are exported as missing
+    #[clap(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    pub disabled_features: Vec<FeatureGroup>,
+
+    /// The local subnets, a flow between two addresses of one of them is east-west traffic
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub local_subnets: Vec<IpNetwork>,
+
+    /// The size of each eBPF event ring buffer in MiB, rounded up to a power of two
+    #[clap(long, default_value_t = DEFAULT_RING_BUFFER_SIZE)]
+    #[serde(default = "default_ring_buffer_size")]
+    pub ring_buffer_size: u32,
+
+    /// Log the share of the interface packets captured every N seconds (realtime only)
+    #[clap(long)]
+    #[serde(default)]
+    pub interface_check_interval: Option<u64>,
+
+    /// Log the packets per second and active flows of every shard every N seconds
+    #[clap(long)]
+    #[serde(default)]
+    pub shard_report_interval: Option<u64>,
+
+    /// Flows that are kept across the active timeout and exported periodically instead
+    #[clap(skip)]
+    #[serde(default)]
+    pub persistent_flows: Vec<PersistentFlowRule>,
+
+    /// Packets to process or to drop before they reach the flow tables
+    #[clap(skip)]
+    #[serde(default)]
+    pub packet_filters: Vec<PacketFilterRule>,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            features: FlowType::Basic,
+            active_timeout: 3600,
+            idle_timeout: 120,
+            expiration_check_interval: 60,
+            early_export: None,
+            max_flow_duration: None,
+            export_window: None,
+            packet_sequence_length: None,
+            feature_memory_limit: None,
+            flow_table_capacity: None,
+            raw_flags: false,
+            threads: None,
+            single_threaded: false,
+            byte_count: ByteCount::Payload,
+            ip_version: IpVersion::Both,
+            flow_key: FlowKeyStrategy::FiveTuple,
+            track_expectations: false,
+            split_on_syn: false,
+            sequence_reset_threshold: None,
+            flow_sampling: None,
+            periodicity_threshold: DEFAULT_PERIODICITY_THRESHOLD,
+            periodicity_min_packets: DEFAULT_PERIODICITY_MIN_PACKETS,
+            first_window: DEFAULT_FIRST_WINDOW,
+            exclude_keepalives: false,
+            disabled_features: Vec::new(),
+            local_subnets: Vec::new(),
+            ring_buffer_size: DEFAULT_RING_BUFFER_SIZE,
+            interface_check_interval: None,
+            shard_report_interval: None,
+            persistent_flows: Vec::new(),
+            packet_filters: Vec::new(),
+        }
+    }
+}
+
+impl ExportConfig {
+    /// Returns the number of threads processing packets, each with a shard of its own.
+    ///
+    /// A single thread with `single_threaded`, otherwise `threads` or the logical CPUs.
+    pub fn num_threads(&self) -> u8 {
+        if self.single_threaded {
+            1
+        } else {
+            self.threads.unwrap_or(num_cpus::get() as u8)
+        }
+    }
+}
+
+/// The default coefficient of variation of the IATs below which a flow is periodic.
+pub const DEFAULT_PERIODICITY_THRESHOLD: f64 = 0.1;
+
+/// The default minimum number of packets of a periodic flow.
+pub const DEFAULT_PERIODICITY_MIN_PACKETS: u32 = 10;
+
+fn default_periodicity_threshold() -> f64 {
+    DEFAULT_PERIODICITY_THRESHOLD
+}
+
+fn default_periodicity_min_packets() -> u32 {
+    DEFAULT_PERIODICITY_MIN_PACKETS
+}
+
+/// The default milliseconds after the first packet of a flow its first window features cover.
+pub const DEFAULT_FIRST_WINDOW: u64 = 1000;
+
+fn default_first_window() -> u64 {
+    DEFAULT_FIRST_WINDOW
+}
+
+/// The default size of the eBPF event ring buffers in MiB.
+pub const DEFAULT_RING_BUFFER_SIZE: u32 = 16;
+
+/// The largest ring buffer size in MiB, the kernel takes the size in bytes as a `u32`.
+pub const MAX_RING_BUFFER_SIZE: u32 = 2048;
+
+/// The largest flow table capacity, the flow tables reserve the memory of all slots up front.
+pub const MAX_FLOW_TABLE_CAPACITY: usize = 10_000_000;
+
+fn default_ring_buffer_size() -> u32 {
+    DEFAULT_RING_BUFFER_SIZE
+}
+
+/// Selects long-lived flows that should never be split by the active timeout.
+///
+/// A flow matches if all given fields match in either direction, omitted fields match
+/// anything. Matching flows still end on termination or after the idle timeout, and emit
+/// an early export snapshot every `snapshot_interval` seconds instead of `early_export`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PersistentFlowRule {
+    /// The IP address of one endpoint
+    pub source_ip: Option<IpAddr>,
+    /// The port of the same endpoint as `source_ip`
+    pub source_port: Option<u16>,
+    /// The IP address of the other endpoint
+    pub destination_ip: Option<IpAddr>,
+    /// The port of the same endpoint as `destination_ip`
+    pub destination_port: Option<u16>,
+    /// The IP protocol number, e.g. 6 for TCP
+    pub protocol: Option<u8>,
+    /// The interval between two snapshots of a matching flow in seconds
+    pub snapshot_interval: u64,
+}
+
+impl PersistentFlowRule {
+    /// Checks whether a flow between the given endpoints matches this rule.
+    pub fn matches(
+        &self,
+        source_ip: IpAddr,
+        source_port: u16,
+        destination_ip: IpAddr,
+        destination_port: u16,
+        protocol: u8,
+    ) -> bool {
+        if self.protocol.is_some_and(|rule| rule != protocol) {
+            return false;
+        }
+
+        self.matches_endpoints(source_ip, source_port, destination_ip, destination_port)
+            || self.matches_endpoints(destination_ip, destination_port, source_ip, source_port)
+    }
+
+    fn matches_endpoints(
+        &self,
+        source_ip: IpAddr,
+        source_port: u16,
+        destination_ip: IpAddr,
+        destination_port: u16,
+    ) -> bool {
+        self.source_ip.is_none_or(|rule| rule == source_ip)
+            && self.source_port.is_none_or(|rule| rule == source_port)
+            && self.destination_ip.is_none_or(|rule| rule == destination_ip)
+            && self.destination_port.is_none_or(|rule| rule == destination_port)
+    }
+}
+
+/// What happens to the packets matching a packet filter rule.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FilterAction {
+    /// Only matching packets are processed
+    Include,
+    /// Matching packets are dropped
+    Exclude,
+}
+
+/// A port or a range of ports, written as `53` or `"8000-8100"` in the configuration file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "PortRangeValue", into = "PortRangeValue")]
+pub struct PortRange {
+    pub start: u16,
+    /// The last port of the range, inclusive
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortRangeValue {
+    Port(u16),
+    Range(String),
+}
+
+impl TryFrom<PortRangeValue> for PortRange {
+    type Error = String;
+
+    fn try_from(value: PortRangeValue) -> Result<Self, Self::Error> {
+        let range = match value {
+            PortRangeValue::Port(port) => return Ok(PortRange { start: port, end: port }),
+            PortRangeValue::Range(range) => range,
+        };
+        let invalid = || format!("invalid port range `{}`, expected e.g. `8000-8100`", range);
+        let (start, end) = range.split_once('-').unwrap_or((&range, &range));
+        let start = start.trim().parse().map_err(|_| invalid())?;
+        let end = end.trim().parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(PortRange { start, end })
+    }
+}
+
+impl From<PortRange> for PortRangeValue {
+    fn from(range: PortRange) -> Self {
+        if range.start == range.end {
+            PortRangeValue::Port(range.start)
+        } else {
+            PortRangeValue::Range(format!("{}-{}", range.start, range.end))
+        }
+    }
+}
+
+/// Selects packets by their 5-tuple, to process only some of them or to drop some.
+///
+/// A packet matches if all given fields match in either direction, omitted fields match
+/// anything. The rules are evaluated in userspace before a packet reaches its flow, so
+/// dropped packets never create or update flows.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PacketFilterRule {
+    pub action: FilterAction,
+    /// The network of one endpoint, e.g. `10.0.0.0/8` or a single address
+    pub source_ip: Option<IpNetwork>,
+    /// The ports of the same endpoint as `source_ip`
+    pub source_port: Option<PortRange>,
+    /// The network of the other endpoint
+    pub destination_ip: Option<IpNetwork>,
+    /// The ports of the same endpoint as `destination_ip`
+    pub destination_port: Option<PortRange>,
+    /// The IP protocol number, e.g. 6 for TCP
+    pub protocol: Option<u8>,
+}
+
+impl PacketFilterRule {
+    /// Checks whether a packet between the given endpoints matches this rule.
+    pub fn matches(
+        &self,
+        source_ip: IpAddr,
+        source_port: u16,
+        destination_ip: IpAddr,
+        destination_port: u16,
+        protocol: u8,
+    ) -> bool {
+        if self.protocol.is_some_and(|rule| rule != protocol) {
+            return false;
+        }
+
+        self.matches_endpoints(source_ip, source_port, destination_ip, destination_port)
+            || self.matches_endpoints(destination_ip, destination_port, source_ip, source_port)
+    }
+
+    fn matches_endpoints(
+        &self,
+        source_ip: IpAddr,
+        source_port: u16,
+        destination_ip: IpAddr,
+        destination_port: u16,
+    ) -> bool {
+        self.source_ip.is_none_or(|rule| rule.contains(source_ip))
+            && self.source_port.is_none_or(|rule| rule.contains(source_port))
+            && self.destination_ip.is_none_or(|rule| rule.contains(destination_ip))
+            && self.destination_port.is_none_or(|rule| rule.contains(destination_port))
+    }
+
+    /// Checks whether the rules let a packet between the given endpoints through.
+    ///
+    /// A packet is dropped if it matches an `Exclude` rule, or if there are `Include` rules
+    /// and it matches none of them. Without rules, all packets are processed.
+    pub fn accepts(
+        rules: &[PacketFilterRule],
+        source_ip: IpAddr,
+        source_port: u16,
+        destination_ip: IpAddr,
+        destination_port: u16,
+        protocol: u8,
+    ) -> bool {
+        let (mut has_include_rules, mut included) = (false, false);
+        for rule in rules {
+            let matches =
+                rule.matches(source_ip, source_port, destination_ip, destination_port, protocol);
+            match rule.action {
+                FilterAction::Exclude if matches => return false,
+                FilterAction::Exclude => {}
+                FilterAction::Include => {
+                    has_include_rules = true;
+                    included |= matches;
+                }
+            }
+        }
+        included || !has_include_rules
+    }
 }
 
 #[derive(Serialize, Deserialize, Args, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct OutputConfig {
-    /// Output method
-    #[clap(short, long, value_enum)]
+    /// Output method (required if no config file is provided)
+    #[clap(short, long, value_enum, default_value_t = ExportMethodType::Print)]
+    #[clap(hide_default_value = true)]
     pub output: ExportMethodType,
 
-    /// File path for output (used if method is Csv)
-    #[clap(required_if_eq("output", "csv"))]
+    /// File path for output (used if method is Csv, Protobuf or Parquet)
+    #[clap(long)]
     pub export_path: Option<String>,
 
+    /// The TCP address the messages are streamed to instead of a file (used if method is Protobuf)
+    #[clap(long)]
+    #[serde(default)]
+    pub protobuf_address: Option<String>,
+
     /// Whether to export the feature header
     #[clap(long, action = clap::ArgAction::SetTrue)]
     pub header: bool,
@@ -136,18 +596,606 @@ pub struct OutputConfig {
     /// Whether to drop contaminant features
     #[clap(long, action = clap::ArgAction::SetTrue)]
     pub drop_contaminant_features: bool,
+
+    /// The minimum number of packets a flow needs to be exported, smaller flows are dropped
+    #[clap(long, default_value_t = 0)]
+    #[serde(default)]
+    pub min_packets: u32,
+
+    /// Export TCP connection attempts (flows with a forward SYN) below `min_packets` as well,
+    /// so failed and refused connections are kept
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub keep_connection_attempts: bool,
+
+    /// The maximum length of string features, longer values are truncated
+    #[clap(long, default_value_t = DEFAULT_MAX_STRING_LENGTH)]
+    #[serde(default = "default_max_string_length")]
+    pub max_string_length: usize,
+
+    /// How features without a value are exported
+    #[clap(long, value_enum, default_value_t = NullValue::Empty)]
+    #[serde(default)]
+    pub null_value: NullValue,
+
+    /// How the flow timestamps are exported
+    #[clap(long, value_enum, default_value_t = TimestampFormat::Default)]
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+
+    /// The UTC offset of the text timestamps, e.g. +02:00 (default: UTC)
+    #[clap(long)]
+    #[serde(default)]
+    pub timezone: Option<Timezone>,
+
+    /// Pseudonymize the IP addresses in the export with the given method
+    #[clap(long, value_enum)]
+    #[serde(default)]
+    pub anonymize: Option<Anonymization>,
+
+    /// The secret key for the IP address anonymization, the same key gives the same pseudonyms
+    #[clap(long)]
+    #[serde(default)]
+    pub anonymization_key: Option<String>,
+
+    /// Export the lower IP and port of a flow as its source, instead of the initiator
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub canonical_endpoints: bool,
+
+    /// Export the wall clock time a flow left the flow table with each flow
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub export_time: bool,
+
+    /// Export why a flow left the flow table with each flow, e.g. the timeout that expired it
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub end_reason: bool,
+
+    /// The TOML file of the signatures the first payload bytes of the flows are matched against
+    #[clap(long)]
+    #[serde(default)]
+    pub payload_signatures: Option<String>,
+
+    /// The id of the sensor exported with every flow, to tell the flows of several sensors apart
+    #[clap(long)]
+    #[serde(default)]
+    pub sensor_id: Option<String>,
+
+    /// Seconds to wait at most for the output to write the remaining flows at exit
+    #[clap(long)]
+    #[serde(default)]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Write the flows ordered by their end within a window of N seconds
+    #[clap(long)]
+    #[serde(default)]
+    pub reorder_window: Option<u64>,
+
+    /// What the flows exported together are ordered by, the start unless set
+    #[clap(long, value_enum)]
+    #[serde(default)]
+    pub export_order: Option<ExportOrder>,
+
+    /// Write the flows to a CSV file or ClickHouse table per partition
+    #[clap(long, value_enum)]
+    #[serde(default)]
+    pub partition_by: Option<PartitionKey>,
+
+    /// How often a failed write of a flow is retried, the ClickHouse inserts retry on their own
+    #[clap(long, default_value_t = 0)]
+    #[serde(default)]
+    pub write_retries: u32,
+
+    /// Milliseconds before the first retry of a failed write, doubled for every further retry
+    #[clap(long, default_value_t = DEFAULT_WRITE_RETRY_BACKOFF)]
+    #[serde(default = "default_write_retry_backoff")]
+    pub write_retry_backoff: u64,
+
+    /// The CSV file the flows that failed to export are appended to, they are lost if not given
+    #[clap(long)]
+    #[serde(default)]
+    pub dead_letter_path: Option<String>,
+
+    /// The server, table and batching of the ClickHouse export
+    #[clap(skip)]
+    #[serde(default)]
+    pub clickhouse: ClickHouseConfig,
+
+    /// The window and thresholds of the scan detection, enabled if the section is given
+    #[clap(skip)]
+    #[serde(default)]
+    pub scan_detection: Option<ScanDetectionConfig>,
+
+    /// The key and window of the host rollups, enabled if the section is given
+    #[clap(skip)]
+    #[serde(default)]
+    pub host_rollups: Option<HostRollupConfig>,
+}
+
+/// The default maximum length of string features in the export.
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 256;
+
+fn default_max_string_length() -> usize {
+    DEFAULT_MAX_STRING_LENGTH
+}
+
+/// The default milliseconds before the first retry of a failed write.
+pub const DEFAULT_WRITE_RETRY_BACKOFF: u64 = 100;
+
+fn default_write_retry_backoff() -> u64 {
+    DEFAULT_WRITE_RETRY_BACKOFF
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            output: ExportMethodType::Print,
+            export_path: None,
+            protobuf_address: None,
+            header: false,
+            drop_contaminant_features: false,
+            min_packets: 0,
+            keep_connection_attempts: false,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            null_value: NullValue::Empty,
+            timestamp_format: TimestampFormat::Default,
+            timezone: None,
+            anonymize: None,
+            anonymization_key: None,
+            canonical_endpoints: false,
+            export_time: false,
+            end_reason: false,
+            payload_signatures: None,
+            sensor_id: None,
+            shutdown_timeout: None,
+            reorder_window: None,
+            export_order: None,
+            partition_by: None,
+            write_retries: 0,
+            write_retry_backoff: DEFAULT_WRITE_RETRY_BACKOFF,
+            dead_letter_path: None,
+            clickhouse: ClickHouseConfig::default(),
+            scan_detection: None,
+            host_rollups: None,
+        }
+    }
+}
+
+/// Where and how flows are inserted into ClickHouse, the `[output.clickhouse]` section.
+///
+/// The table needs a column for every exported feature, named like the feature header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickHouseConfig {
+    /// The host and native protocol (TCP) port of the server
+    pub address: String,
+    /// The database the table is in
+    pub database: String,
+    /// The table the flows are inserted into
+    pub table: String,
+    pub user: String,
+    pub password: String,
+    /// The number of flows inserted together as one block
+    pub batch_size: usize,
+    /// The maximum time in seconds a flow waits in an incomplete batch
+    pub flush_interval: u64,
+    /// Let the server buffer the inserts (async_insert), it acknowledges them once written
+    pub async_insert: bool,
+    /// How often an insert is retried after a connection or transient server error
+    pub max_retries: u32,
+}
+
+impl Default for ClickHouseConfig {
+    fn default() -> Self {
+        ClickHouseConfig {
+            address: "localhost:9000".to_string(),
+            database: "default".to_string(),
+            table: "flows".to_string(),
+            user: "default".to_string(),
+            password: String::new(),
+            batch_size: 10_000,
+            flush_interval: 5,
+            async_insert: false,
+            max_retries: 5,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Debug)]
+/// How scans are detected in the exported flows, the `[output.scan_detection]` section.
+///
+/// A source that contacts at least `port_threshold` distinct destination ports or
+/// `host_threshold` distinct destination hosts within `window` seconds is reported.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanDetectionConfig {
+    /// The CSV file the alerts are written to, they are logged if not given
+    pub alerts_path: Option<String>,
+    /// The sliding window in seconds, by the start of the flows
+    pub window: u64,
+    /// The distinct destination ports of a source that make a port scan
+    pub port_threshold: usize,
+    /// The distinct destination hosts of a source that make a host scan (network sweep)
+    pub host_threshold: usize,
+}
+
+impl Default for ScanDetectionConfig {
+    fn default() -> Self {
+        ScanDetectionConfig {
+            alerts_path: None,
+            window: 60,
+            port_threshold: 100,
+            host_threshold: 50,
+        }
+    }
+}
+
+/// How the flows are rolled up per host, the `[output.host_rollups]` section.
+///
+/// The flows, packets and bytes of the flows of each host are summed up over tumbling windows
+/// of `window` seconds, by the end of the flows, and written as one record per host and window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostRollupConfig {
+    /// The CSV file the rollups are written to, they are logged if not given
+    pub path: Option<String>,
+    /// The address the flows are rolled up by
+    pub key: RollupKey,
+    /// The window in seconds, by the end of the flows, longer than the idle timeout
+    pub window: u64,
+    /// The prefix length of the IPv4 subnets of the subnet keys
+    pub ipv4_prefix: u8,
+    /// The prefix length of the IPv6 subnets of the subnet keys
+    pub ipv6_prefix: u8,
+    /// Write only the rollups, the flows aren't written to the output
+    pub replace_flows: bool,
+}
+
+impl Default for HostRollupConfig {
+    fn default() -> Self {
+        HostRollupConfig {
+            path: None,
+            key: RollupKey::SourceIp,
+            window: 300,
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+            replace_flows: false,
+        }
+    }
+}
+
+/// The address the host rollups sum the flows up by.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RollupKey {
+    /// The source IP of the flows, the traffic each host initiated
+    #[default]
+    SourceIp,
+
+    /// The destination IP of the flows, the traffic each server was contacted with
+    DestinationIp,
+
+    /// The subnet of the source IP, with the prefix length of its IP version
+    SourceSubnet,
+
+    /// The subnet of the destination IP, with the prefix length of its IP version
+    DestinationSubnet,
+}
+
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Debug, PartialEq)]
 pub enum ExportMethodType {
     /// The output will be printed to the console
     Print,
 
     /// The output will be written to a CSV file
     Csv,
+
+    /// The output will be printed to the console as a human-readable table
+    Table,
+
+    /// The output will be inserted into a ClickHouse table in batches
+    #[value(name = "clickhouse")]
+    ClickHouse,
+
+    /// The output will be written as length-delimited Protocol Buffers messages
+    Protobuf,
+
+    /// The output will be written to a Parquet file, a typed column per feature
+    Parquet,
+}
+
+/// The packet source for realtime capture.
+///
+/// Both backends feed the same flow tables, only the way packets reach them differs.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CaptureBackend {
+    /// Extract the packet features in the kernel with eBPF programs on the TC hook
+    #[default]
+    Ebpf,
+
+    /// Copy the packets to userspace through an AF_PACKET socket and parse them there
+    Afpacket,
+}
+
+/// The packet length used for the byte features of a flow.
+///
+/// CICFlowMeter counts the transport payload, other tools (NetFlow, nfstream) count the
+/// whole IP packet. This changes every byte based feature, so the numbers are only
+/// comparable between tools using the same choice.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ByteCount {
+    /// Count the transport payload of the packets, like CICFlowMeter
+    #[default]
+    Payload,
+
+    /// Count the total IP length of the packets, including headers
+    Total,
+}
+
+/// The IP versions whose packets are turned into flows.
+///
+/// On a single-stack network, the eBPF program of the unused version isn't loaded at all.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum IpVersion {
+    /// Process IPv4 and IPv6 packets
+    #[default]
+    Both,
+
+    /// Only process IPv4 packets
+    #[value(name = "ipv4")]
+    Ipv4,
+
+    /// Only process IPv6 packets
+    #[value(name = "ipv6")]
+    Ipv6,
+}
+
+impl IpVersion {
+    pub fn includes_ipv4(&self) -> bool {
+        matches!(self, IpVersion::Both | IpVersion::Ipv4)
+    }
+
+    pub fn includes_ipv6(&self) -> bool {
+        matches!(self, IpVersion::Both | IpVersion::Ipv6)
+    }
+}
+
+/// The packet fields a flow is keyed by, packets with the same key belong to the same flow.
+///
+/// The first packet of a flow sets its forward direction: a packet is a forward packet if its
+/// source is the source of the first packet, with the fields of the key.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FlowKeyStrategy {
+    /// Source and destination IP and port and the protocol
+    #[default]
+    FiveTuple,
+
+    /// The 5-tuple and the VLAN id, so the same connection on different VLANs is kept apart
+    FiveTupleVlan,
+
+    /// The 5-tuple and the IPv6 flow label, each direction keyed with the label of its packets
+    FiveTupleFlowLabel,
+
+    /// Source and destination IP and the protocol, all connections between two hosts
+    ThreeTuple,
+
+    /// Source and destination IP only, all traffic between two hosts
+    HostPair,
 }
 
-#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Debug, EnumString, VariantNames)]
+impl FlowKeyStrategy {
+    /// Whether the ports are part of the flow key.
+    pub fn has_ports(&self) -> bool {
+        matches!(
+            self,
+            FlowKeyStrategy::FiveTuple
+                | FlowKeyStrategy::FiveTupleVlan
+                | FlowKeyStrategy::FiveTupleFlowLabel
+        )
+    }
+}
+
+/// A group of statistical features that can be left out to save CPU time per packet.
+///
+/// The accumulators of a disabled group are skipped for every packet, its features are
+/// exported as missing values. The counters of packets, bytes and flags are always computed.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FeatureGroup {
+    /// The minimum, maximum, mean, standard deviation and variance of the packet lengths
+    PacketLength,
+
+    /// The statistics of the inter-arrival times, and the periodicity based on them
+    Iat,
+
+    /// The bulk transfer features
+    Bulk,
+
+    /// The active and idle times and the subflows
+    ActiveIdle,
+
+    /// The packet size histograms of the RustiFlow feature set
+    Histograms,
+
+    /// The payload entropy of the RustiFlow feature set, which keeps the first payload bytes
+    /// of each direction
+    Payload,
+}
+
+/// How features without a value are represented in the export.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum NullValue {
+    /// An empty field
+    #[default]
+    Empty,
+
+    /// The text `NaN`, read as a missing number by pandas and most CSV readers
+    #[value(name = "nan")]
+    NaN,
+
+    /// The number -1, like CICFlowMeter
+    MinusOne,
+
+    /// The text `null`
+    Null,
+}
+
+impl NullValue {
+    /// Returns the text a missing feature is dumped as.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NullValue::Empty => "",
+            NullValue::NaN => "NaN",
+            NullValue::MinusOne => "-1",
+            NullValue::Null => "null",
+        }
+    }
+}
+
+/// How the flow timestamps are written in the export.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimestampFormat {
+    /// The format of the feature set: epoch milliseconds for nfstream, text otherwise
+    #[default]
+    Default,
+
+    /// Text like `2023-11-14 22:13:21.500 UTC`
+    Text,
+
+    /// RFC 3339 like `2023-11-14T22:13:21.500Z`
+    Rfc3339,
+
+    /// Whole seconds since the Unix epoch
+    EpochSeconds,
+
+    /// Milliseconds since the Unix epoch
+    EpochMillis,
+
+    /// Microseconds since the Unix epoch
+    EpochMicros,
+}
+
+impl TimestampFormat {
+    /// Returns the format itself, or `default` for the format of the feature set.
+    pub fn or(self, default: TimestampFormat) -> TimestampFormat {
+        match self {
+            TimestampFormat::Default => default,
+            format => format,
+        }
+    }
+
+    /// Writes a timestamp in this format, `Default` is written as text.
+    ///
+    /// ### Arguments
+    ///
+    /// * `timestamp` - The timestamp to be written.
+    /// * `timezone` - The time zone of the text formats, UTC if `None`.
+    pub fn format(self, timestamp: DateTime<Utc>, timezone: Option<Timezone>) -> String {
+        let mut out = String::new();
+        // Writing to a `String` can't fail
+        let _ = self.write(timestamp, timezone, &mut out);
+        out
+    }
+
+    /// Appends a timestamp in this format to `out`, see `format`.
+    pub fn write(
+        self,
+        timestamp: DateTime<Utc>,
+        timezone: Option<Timezone>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        match (self, timezone) {
+            (TimestampFormat::Default | TimestampFormat::Text, None) => {
+                write!(out, "{}", timestamp)
+            }
+            (TimestampFormat::Default | TimestampFormat::Text, Some(Timezone(offset))) => {
+                write!(out, "{}", timestamp.with_timezone(&offset))
+            }
+            (TimestampFormat::Rfc3339, None) => {
+                out.write_str(&timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            (TimestampFormat::Rfc3339, Some(Timezone(offset))) => out.write_str(
+                &timestamp
+                    .with_timezone(&offset)
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ),
+            (TimestampFormat::EpochSeconds, _) => write!(out, "{}", timestamp.timestamp()),
+            (TimestampFormat::EpochMillis, _) => write!(out, "{}", timestamp.timestamp_millis()),
+            (TimestampFormat::EpochMicros, _) => write!(out, "{}", timestamp.timestamp_micros()),
+        }
+    }
+}
+
+/// A fixed UTC offset the text timestamps are written in, e.g. `+02:00`, `-0530` or `UTC`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Timezone(pub FixedOffset);
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("utc") || value == "Z" {
+            return Ok(Timezone(FixedOffset::east_opt(0).unwrap()));
+        }
+        value
+            .parse()
+            .map(Timezone)
+            .map_err(|_| format!("invalid timezone `{}`, expected e.g. `+02:00` or `UTC`", value))
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(timezone: Timezone) -> Self {
+        timezone.0.to_string()
+    }
+}
+
+/// What the flows are split into separate outputs by.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PartitionKey {
+    /// The transport protocol, e.g. tcp and udp
+    Protocol,
+
+    /// The capture interface, or the pcap file
+    Interface,
+
+    /// The /24 (IPv4) or /64 (IPv6) subnet of the flow's source
+    Subnet,
+}
+
+/// What the flows exported together are ordered by, e.g. the flows of an expiration check.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExportOrder {
+    /// The first packet, the order the connections began in, like a dataset
+    #[default]
+    Start,
+
+    /// The last packet, the order the connections ended in, like a stream
+    End,
+}
+
+/// How IP addresses are pseudonymized in the export.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Anonymization {
+    /// Crypto-PAn, addresses sharing a prefix keep sharing a prefix of the same length
+    PrefixPreserving,
+
+    /// A keyed hash of the address, unrelated addresses for any shared prefix
+    Hash,
+}
+
+#[derive(
+    Serialize, Deserialize, clap::ValueEnum, Clone, Debug, PartialEq, EnumString, VariantNames,
+)]
 #[strum(serialize_all = "kebab_case")]
 pub enum FlowType {
     /// A basic flow that stores the basic features of a flow.
@@ -165,33 +1213,665 @@ pub enum FlowType {
     /// Represents the NTL Flow, giving 120 features.
     NTL,
 
+    /// Represents the RustiFlow Flow, the CIC features extended with additional features.
+    Rustiflow,
+
     /// Represents a flow that you can implement yourself.
     Custom,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigFile {
     pub config: ExportConfig,
     pub output: OutputConfig,
 }
 
-impl Default for ConfigFile {
-    fn default() -> Self {
-        ConfigFile {
-            config: ExportConfig {
-                features: FlowType::Basic,
-                active_timeout: 3600,
-                idle_timeout: 120,
-                expiration_check_interval: 60,
-                early_export: None,
-                threads: None,
-            },
-            output: OutputConfig {
-                output: ExportMethodType::Print,
-                export_path: None,
-                header: false,
-                drop_contaminant_features: false,
-            },
+impl ConfigFile {
+    /// Loads and validates a configuration file.
+    ///
+    /// Unlike `confy::load_path`, a missing file is an error instead of being created
+    /// with defaults, and parse errors point at the offending line and field.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path` - The path of the TOML configuration file.
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file {}", path))?;
+        let config_file: ConfigFile = toml::from_str(&content)
+            .map_err(|e| anyhow!("Invalid configuration file {}: {}", path, e))?;
+        config_file
+            .validate()
+            .map_err(|e| anyhow!("Invalid configuration file {}: {}", path, e))?;
+        Ok(config_file)
+    }
+
+    /// Checks the settings that parse correctly but can't be used together.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.output.output == ExportMethodType::Csv && self.output.export_path.is_none() {
+            return Err("`output.export_path` is required when `output.output` is Csv".to_string());
+        }
+        if self.output.output == ExportMethodType::Parquet && self.output.export_path.is_none() {
+            return Err(
+                "`output.export_path` is required when `output.output` is Parquet".to_string(),
+            );
+        }
+        if self.output.output == ExportMethodType::Protobuf
+            && self.output.export_path.is_none()
+            && self.output.protobuf_address.is_none()
+        {
+            return Err(
+                "`output.export_path` or `output.protobuf_address` is required when \
+                `output.output` is Protobuf"
+                    .to_string(),
+            );
+        }
+        if self.output.output == ExportMethodType::ClickHouse {
+            let clickhouse = &self.output.clickhouse;
+            if clickhouse.address.is_empty() || clickhouse.table.is_empty() {
+                return Err(
+                    "`output.clickhouse` needs an address and a table for the ClickHouse output"
+                        .to_string(),
+                );
+            }
+            if clickhouse.batch_size == 0 || clickhouse.flush_interval == 0 {
+                return Err(
+                    "`batch_size` and `flush_interval` of `output.clickhouse` must be at least 1"
+                        .to_string(),
+                );
+            }
+        }
+        if self.output.anonymize.is_some() && self.output.anonymization_key.is_none() {
+            return Err(
+                "`output.anonymization_key` is required when `output.anonymize` is set".to_string(),
+            );
+        }
+        if self.output.shutdown_timeout == Some(0) {
+            return Err("`output.shutdown_timeout` must be at least 1".to_string());
+        }
+        if self.output.reorder_window == Some(0) {
+            return Err("`output.reorder_window` must be at least 1".to_string());
+        }
+        if self.output.reorder_window.is_some()
+            && self.output.export_order == Some(ExportOrder::Start)
+        {
+            return Err(
+                "`output.reorder_window` orders the flows by their end, it can't be combined \
+                 with `output.export_order = \"Start\"`"
+                    .to_string(),
+            );
+        }
+        if self.output.partition_by.is_some()
+            && !matches!(self.output.output, ExportMethodType::Csv | ExportMethodType::ClickHouse)
+        {
+            return Err("`output.partition_by` needs the Csv or ClickHouse output".to_string());
+        }
+        if self.output.write_retries > 0 && self.output.write_retry_backoff == 0 {
+            return Err("`output.write_retry_backoff` must be at least 1".to_string());
+        }
+        if self.output.dead_letter_path.is_some()
+            && self.output.dead_letter_path == self.output.export_path
+        {
+            return Err("`output.dead_letter_path` must differ from the export path".to_string());
+        }
+        // The id is written into the CSV records as it is
+        if self.output.sensor_id.as_ref().is_some_and(|id| {
+            id.is_empty() || id.chars().any(|c| c == ',' || c == '"' || c.is_control())
+        }) {
+            return Err(
+                "`output.sensor_id` must not be empty or contain commas, quotes or control \
+                characters"
+                    .to_string(),
+            );
+        }
+        if let Some(scan_detection) = &self.output.scan_detection {
+            if scan_detection.window == 0
+                || scan_detection.port_threshold == 0
+                || scan_detection.host_threshold == 0
+            {
+                return Err("`window`, `port_threshold` and `host_threshold` of \
+                    `output.scan_detection` must be at least 1"
+                    .to_string());
+            }
+        }
+        if let Some(host_rollups) = &self.output.host_rollups {
+            if host_rollups.window == 0
+                || host_rollups.ipv4_prefix > 32
+                || host_rollups.ipv6_prefix > 128
+            {
+                return Err("`output.host_rollups` needs a `window` of at least 1, an \
+                    `ipv4_prefix` of at most 32 and an `ipv6_prefix` of at most 128"
+                    .to_string());
+            }
+            // The idle flows are exported `idle_timeout` after their last packet, by then the
+            // capture would have passed a shorter window
+            if host_rollups.window <= self.config.idle_timeout {
+                return Err(format!(
+                    "`output.host_rollups.window` ({}) must be longer than \
+                    `config.idle_timeout` ({}), or the idle flows miss their window",
+                    host_rollups.window, self.config.idle_timeout
+                ));
+            }
+            if host_rollups.path.is_some() && host_rollups.path == self.output.export_path {
+                return Err("`output.host_rollups` can't be written to the export path".to_string());
+            }
+        }
+        if self.config.threads == Some(0) {
+            return Err("`config.threads` must be at least 1".to_string());
+        }
+        if self.config.single_threaded && self.config.threads.is_some_and(|threads| threads > 1) {
+            return Err("`config.threads` can't be more than 1 with `config.single_threaded`"
+                .to_string());
+        }
+        if self.config.max_flow_duration == Some(0) {
+            return Err("`config.max_flow_duration` must be at least 1".to_string());
+        }
+        // A gap beyond the idle timeout also takes a flow beyond the active timeout
+        if self.config.idle_timeout >= self.config.active_timeout {
+            return Err(format!(
+                "`config.idle_timeout` ({}) must be shorter than `config.active_timeout` ({}), \
+                the active timeout would expire every flow before it could idle out",
+                self.config.idle_timeout, self.config.active_timeout
+            ));
+        }
+        if let Some(early_export) = self
+            .config
+            .early_export
+            .filter(|early_export| *early_export >= self.config.active_timeout)
+        {
+            return Err(format!(
+                "`config.early_export` ({}) must be shorter than `config.active_timeout` ({}), \
+                the active timeout would export every flow before its first early export",
+                early_export, self.config.active_timeout
+            ));
+        }
+        if self.config.export_window == Some(0) {
+            return Err("`config.export_window` must be at least 1".to_string());
+        }
+        if self.config.packet_sequence_length == Some(0) {
+            return Err("`config.packet_sequence_length` must be at least 1".to_string());
+        }
+        if self.config.feature_memory_limit == Some(0) {
+            return Err("`config.feature_memory_limit` must be at least 1".to_string());
+        }
+        if self
+            .config
+            .flow_table_capacity
+            .is_some_and(|capacity| !(1..=MAX_FLOW_TABLE_CAPACITY).contains(&capacity))
+        {
+            return Err(format!(
+                "`config.flow_table_capacity` must be between 1 and {}",
+                MAX_FLOW_TABLE_CAPACITY
+            ));
+        }
+        if self.config.flow_sampling == Some(0) {
+            return Err("`config.flow_sampling` must be at least 1".to_string());
+        }
+        let threshold = self.config.periodicity_threshold;
+        if !(threshold >= 0.0 && threshold.is_finite()) {
+            return Err("`config.periodicity_threshold` must be a non-negative number".to_string());
+        }
+        if self.config.first_window == 0 {
+            return Err("`config.first_window` must be at least 1".to_string());
+        }
+        if !(1..=MAX_RING_BUFFER_SIZE).contains(&self.config.ring_buffer_size) {
+            return Err(format!(
+                "`config.ring_buffer_size` must be between 1 and {} MiB",
+                MAX_RING_BUFFER_SIZE
+            ));
+        }
+        if self.config.interface_check_interval == Some(0) {
+            return Err("`config.interface_check_interval` must be at least 1".to_string());
+        }
+        if self.config.shard_report_interval == Some(0) {
+            return Err("`config.shard_report_interval` must be at least 1".to_string());
+        }
+        if self
+            .config
+            .persistent_flows
+            .iter()
+            .any(|rule| rule.snapshot_interval == 0)
+        {
+            return Err("`snapshot_interval` of `config.persistent_flows` must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Cli {
+    /// Builds the configuration from the optional configuration file and the flags.
+    ///
+    /// Flags given on the command line override the values of the file. Without a file,
+    /// the flags and their defaults make up the whole configuration.
+    ///
+    /// ### Arguments
+    ///
+    /// * `matches` - The parsed command line, used to tell given flags from defaults.
+    pub fn to_config_file(&self, matches: &ArgMatches) -> Result<ConfigFile, anyhow::Error> {
+        let mut config_file = match &self.config_file {
+            Some(path) => {
+                let config_file = ConfigFile::load(path)?;
+                ConfigFile {
+                    config: merge_given(config_file.config, &self.config, matches)?,
+                    output: merge_given(config_file.output, &self.output, matches)?,
+                }
+            }
+            None => {
+                // The schema only depends on the features, not on where they would go
+                let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+                let needs_output = !matches!(self.command, Commands::Schema { .. });
+                if !given("features") || (needs_output && !given("output")) {
+                    return Err(anyhow!(
+                        "--features and --output are required when no config file is provided"
+                    ));
+                }
+                ConfigFile {
+                    config: self.config.clone(),
+                    output: self.output.clone(),
+                }
+            }
+        };
+
+        // These flags set a single value of a section of the output
+        let output = &mut config_file.output;
+        if let Some(address) = &self.clickhouse_address {
+            output.clickhouse.address = address.clone();
+        }
+        if let Some(table) = &self.clickhouse_table {
+            output.clickhouse.table = table.clone();
+        }
+        if let Some(path) = &self.scan_alerts {
+            let scan_detection = output.scan_detection.get_or_insert_with(Default::default);
+            scan_detection.alerts_path = Some(path.clone());
+        }
+        if let Some(path) = &self.host_rollups {
+            let host_rollups = output.host_rollups.get_or_insert_with(Default::default);
+            host_rollups.path = Some(path.clone());
+        }
+
+        config_file.validate().map_err(|e| anyhow!(e))?;
+        Ok(config_file)
+    }
+}
+
+/// Overrides the settings of `base` with those of `flags` whose flag is given on the command line.
+///
+/// The settings are matched by name, as the id of each flag is the name of its field.
+fn merge_given<T>(base: T, flags: &T, matches: &ArgMatches) -> Result<T, anyhow::Error>
+where
+    T: Args + Serialize + DeserializeOwned,
+{
+    let mut merged = serde_json::to_value(base)?;
+    let flags = serde_json::to_value(flags)?;
+    for arg in T::augment_args(Command::new("flags")).get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) == Some(ValueSource::CommandLine) {
+            merged[id] = flags[id].clone();
         }
     }
+    Ok(serde_json::from_value(merged)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use clap::{CommandFactory, FromArgMatches};
+
+    use chrono::DateTime;
+
+    use super::{
+        Cli, ConfigFile, ExportMethodType, ExportOrder, FeatureGroup, FlowType, HostRollupConfig,
+        NullValue, PacketFilterRule, PartitionKey, PersistentFlowRule, ScanDetectionConfig,
+        TimestampFormat, Timezone, DEFAULT_FIRST_WINDOW, DEFAULT_WRITE_RETRY_BACKOFF,
+    };
+
+    fn parse(config: &str) -> Result<ConfigFile, String> {
+        let config_file: ConfigFile = toml::from_str(config).map_err(|e| e.to_string())?;
+        config_file.validate()?;
+        Ok(config_file)
+    }
+
+    #[test]
+    fn test_config_file_validation() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\n",
+        )
+        .unwrap();
+        assert_eq!(config_file.config.features, FlowType::CIC);
+        assert_eq!(config_file.output.min_packets, 0);
+        assert_eq!(config_file.output.null_value, NullValue::Empty);
+        assert!(config_file.config.ip_version.includes_ipv4());
+        assert!(config_file.config.ip_version.includes_ipv6());
+
+        // A typo is reported with the offending field instead of being ignored
+        let error = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("idle_timout"));
+
+        let error = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"Csv\"\nheader = true\n\
+            drop_contaminant_features = false\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("export_path"));
+
+        let mut config_file = config_file;
+        config_file.output.output = ExportMethodType::Parquet;
+        assert!(config_file.validate().unwrap_err().contains("export_path"));
+        config_file.output.output = ExportMethodType::Print;
+        assert_eq!(config_file.config.ring_buffer_size, 16);
+        config_file.config.ring_buffer_size = 4096;
+        assert!(config_file.validate().unwrap_err().contains("ring_buffer_size"));
+        config_file.config.ring_buffer_size = 16;
+        config_file.config.flow_table_capacity = Some(0);
+        assert!(config_file.validate().unwrap_err().contains("flow_table_capacity"));
+        config_file.config.flow_table_capacity = Some(100_000);
+        assert!(config_file.validate().is_ok());
+        config_file.config.flow_table_capacity = None;
+        // The console outputs have no files or tables to partition into
+        config_file.output.partition_by = Some(PartitionKey::Protocol);
+        assert!(config_file.validate().unwrap_err().contains("partition_by"));
+        config_file.output.partition_by = None;
+        // The reorder buffer orders by the end, the flows can't be written in their start order
+        config_file.output.reorder_window = Some(30);
+        config_file.output.export_order = Some(ExportOrder::Start);
+        assert!(config_file.validate().unwrap_err().contains("export_order"));
+        config_file.output.export_order = Some(ExportOrder::End);
+        assert!(config_file.validate().is_ok());
+        config_file.output.export_order = None;
+        config_file.output.reorder_window = None;
+
+        config_file.output.write_retries = 3;
+        config_file.output.write_retry_backoff = 0;
+        assert!(config_file.validate().unwrap_err().contains("write_retry_backoff"));
+        config_file.output.write_retry_backoff = DEFAULT_WRITE_RETRY_BACKOFF;
+        config_file.output.export_path = Some("flows.csv".to_string());
+        config_file.output.dead_letter_path = Some("flows.csv".to_string());
+        assert!(config_file.validate().unwrap_err().contains("dead_letter_path"));
+        config_file.output.dead_letter_path = None;
+        config_file.output.export_path = None;
+        config_file.output.sensor_id = Some("edge,1".to_string());
+        assert!(config_file.validate().unwrap_err().contains("sensor_id"));
+        config_file.output.sensor_id = Some("edge-1".to_string());
+        assert!(config_file.validate().is_ok());
+        config_file.output.sensor_id = None;
+        config_file.output.scan_detection = Some(ScanDetectionConfig {
+            port_threshold: 0,
+            ..ScanDetectionConfig::default()
+        });
+        assert!(config_file.validate().unwrap_err().contains("scan_detection"));
+        config_file.output.scan_detection = None;
+        config_file.output.host_rollups = Some(HostRollupConfig {
+            ipv4_prefix: 33,
+            ..HostRollupConfig::default()
+        });
+        assert!(config_file.validate().unwrap_err().contains("host_rollups"));
+        config_file.output.host_rollups = Some(HostRollupConfig {
+            window: 120,
+            ..HostRollupConfig::default()
+        });
+        let error = config_file.validate().unwrap_err();
+        assert!(error.contains("`output.host_rollups.window` (120)"), "{}", error);
+        config_file.output.host_rollups = Some(HostRollupConfig::default());
+        assert!(config_file.validate().is_ok());
+        config_file.output.host_rollups = None;
+        config_file.config.idle_timeout = 3600;
+        let error = config_file.validate().unwrap_err();
+        assert!(error.contains("`config.idle_timeout` (3600)"), "{}", error);
+        config_file.config.idle_timeout = 120;
+        config_file.config.early_export = Some(4000);
+        let error = config_file.validate().unwrap_err();
+        assert!(error.contains("`config.early_export` (4000)"), "{}", error);
+        config_file.config.early_export = Some(300);
+        assert!(config_file.validate().is_ok());
+        config_file.config.early_export = None;
+        config_file.config.first_window = 0;
+        assert!(config_file.validate().unwrap_err().contains("first_window"));
+        config_file.config.first_window = DEFAULT_FIRST_WINDOW;
+        // A single thread runs a single shard
+        config_file.config.single_threaded = true;
+        assert_eq!(config_file.config.num_threads(), 1);
+        config_file.config.threads = Some(4);
+        assert!(config_file.validate().unwrap_err().contains("single_threaded"));
+
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\nip_version = \"Ipv4\"\n\n[output]\n\
+            output = \"Print\"\nheader = true\ndrop_contaminant_features = false\n",
+        )
+        .unwrap();
+        assert!(config_file.config.ip_version.includes_ipv4());
+        assert!(!config_file.config.ip_version.includes_ipv6());
+    }
+
+    #[test]
+    fn test_null_value() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\nnull_value = \"MinusOne\"\n",
+        )
+        .unwrap();
+        assert_eq!(config_file.output.null_value, NullValue::MinusOne);
+        assert_eq!(config_file.output.null_value.as_str(), "-1");
+        assert_eq!(NullValue::NaN.as_str(), "NaN");
+        assert_eq!(NullValue::Null.as_str(), "null");
+        assert_eq!(NullValue::Empty.as_str(), "");
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\ntimestamp_format = \"Rfc3339\"\n\
+            timezone = \"+02:00\"\n",
+        )
+        .unwrap();
+        assert_eq!(config_file.output.timestamp_format, TimestampFormat::Rfc3339);
+        let timezone = config_file.output.timezone;
+        assert_eq!(timezone, Some("+0200".parse().unwrap()));
+        assert!("Europe/Berlin".parse::<Timezone>().is_err());
+
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 500_000_000).unwrap();
+        let format = |format: TimestampFormat, timezone| format.format(timestamp, timezone);
+        assert_eq!(format(TimestampFormat::Default, None), "2023-11-14 22:13:20.500 UTC");
+        assert_eq!(format(TimestampFormat::Text, timezone), "2023-11-15 00:13:20.500 +02:00");
+        assert_eq!(format(TimestampFormat::Rfc3339, None), "2023-11-14T22:13:20.500Z");
+        assert_eq!(format(TimestampFormat::Rfc3339, timezone), "2023-11-15T00:13:20.500+02:00");
+        // The epoch formats don't depend on the time zone
+        assert_eq!(format(TimestampFormat::EpochSeconds, timezone), "1700000000");
+        assert_eq!(format(TimestampFormat::EpochMillis, None), "1700000000500");
+        assert_eq!(format(TimestampFormat::EpochMicros, None), "1700000000500000");
+        assert_eq!(
+            TimestampFormat::Default.or(TimestampFormat::EpochMillis),
+            TimestampFormat::EpochMillis
+        );
+        assert_eq!(TimestampFormat::Text.or(TimestampFormat::EpochMillis), TimestampFormat::Text);
+    }
+
+    #[test]
+    fn test_persistent_flow_rules() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[[config.persistent_flows]]\n\
+            destination_ip = \"10.0.0.2\"\ndestination_port = 5432\nprotocol = 6\n\
+            snapshot_interval = 300\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\n",
+        )
+        .unwrap();
+        let rule = &config_file.config.persistent_flows[0];
+        assert_eq!(rule.snapshot_interval, 300);
+
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(rule.matches(client, 40000, server, 5432, 6));
+        // The flow may have been started by either endpoint
+        assert!(rule.matches(server, 5432, client, 40000, 6));
+        assert!(!rule.matches(client, 40000, server, 5432, 17));
+        assert!(!rule.matches(client, 40000, server, 5433, 6));
+
+        let rule = PersistentFlowRule {
+            source_ip: None,
+            source_port: None,
+            destination_ip: None,
+            destination_port: None,
+            protocol: None,
+            snapshot_interval: 0,
+        };
+        let mut config_file = config_file;
+        config_file.config.persistent_flows = vec![rule];
+        assert!(config_file.validate().unwrap_err().contains("snapshot_interval"));
+    }
+
+    #[test]
+    fn test_packet_filter_rules() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[[config.packet_filters]]\n\
+            action = \"Include\"\ndestination_ip = \"10.0.0.0/24\"\n\n\
+            [[config.packet_filters]]\naction = \"Exclude\"\ndestination_ip = \"10.0.0.2\"\n\
+            destination_port = \"8000-8100\"\nprotocol = 6\n\n[output]\noutput = \"Print\"\n\
+            header = true\ndrop_contaminant_features = false\n",
+        )
+        .unwrap();
+        let rules = &config_file.config.packet_filters;
+        let client = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let server = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 1, 2));
+        let accepts = |source_ip, destination_ip, port, protocol| {
+            PacketFilterRule::accepts(rules, source_ip, 40000, destination_ip, port, protocol)
+        };
+
+        assert!(accepts(client, server, 443, 6));
+        // In both directions
+        assert!(accepts(server, client, 443, 6));
+        // Outside the included subnet
+        assert!(!accepts(client, other, 443, 6));
+        // Excluded by the port range, only for TCP
+        assert!(!accepts(client, server, 8000, 6));
+        assert!(!accepts(client, server, 8100, 6));
+        assert!(accepts(client, server, 8101, 6));
+        assert!(accepts(client, server, 8080, 17));
+        // Without rules, everything is processed
+        assert!(PacketFilterRule::accepts(&[], client, 40000, other, 8080, 6));
+
+        let invalid = "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[[config.packet_filters]]\naction = \"Exclude\"\n\
+            source_port = \"100-10\"\n\n[output]\noutput = \"Print\"\nheader = true\n\
+            drop_contaminant_features = false\n";
+        assert!(parse(invalid).unwrap_err().contains("100-10"));
+    }
+
+    #[test]
+    fn test_clickhouse_config() {
+        let config_file = parse(
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 3600\nidle_timeout = 120\n\
+            expiration_check_interval = 60\n\n[output]\noutput = \"ClickHouse\"\n\
+            header = false\ndrop_contaminant_features = false\n\n[output.clickhouse]\n\
+            address = \"clickhouse:9000\"\ntable = \"cic_flows\"\nasync_insert = true\n",
+        )
+        .unwrap();
+        let clickhouse = &config_file.output.clickhouse;
+        assert_eq!(clickhouse.address, "clickhouse:9000");
+        assert_eq!(clickhouse.table, "cic_flows");
+        assert!(clickhouse.async_insert);
+        // Omitted settings keep their defaults
+        assert_eq!(clickhouse.database, "default");
+        assert_eq!(clickhouse.batch_size, 10_000);
+
+        let mut config_file = config_file;
+        config_file.output.clickhouse.batch_size = 0;
+        assert!(config_file.validate().unwrap_err().contains("batch_size"));
+    }
+
+    #[test]
+    fn test_cli_overrides_config_file() {
+        let path = std::env::temp_dir().join("rustiflow_test_cli_overrides.toml");
+        std::fs::write(
+            &path,
+            "[config]\nfeatures = \"CIC\"\nactive_timeout = 1000\nidle_timeout = 50\n\
+            expiration_check_interval = 60\ndisabled_features = [\"Histograms\"]\n\n\
+            [[config.packet_filters]]\naction = \"Exclude\"\ndestination_port = \"8000-8100\"\n\n\
+            [output]\noutput = \"Print\"\nheader = true\ndrop_contaminant_features = false\n\
+            min_packets = 3\n\n[output.host_rollups]\nwindow = 600\n",
+        )
+        .unwrap();
+
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "rustiflow",
+                "-c",
+                path.to_str().unwrap(),
+                "--idle-timeout",
+                "30",
+                "-o",
+                "csv",
+                "--export-path",
+                "flows.csv",
+                "--canonical-endpoints",
+                "--packet-sequence-length",
+                "20",
+                "--disabled-features",
+                "iat,bulk",
+                "--host-rollups",
+                "rollups.csv",
+                "pcap",
+                "capture.pcap",
+            ])
+            .unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        let config_file = cli.to_config_file(&matches).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Values of the file are kept unless the flag is given, defaults don't override them
+        assert_eq!(config_file.config.active_timeout, 1000);
+        assert_eq!(config_file.config.idle_timeout, 30);
+        assert_eq!(config_file.output.output, ExportMethodType::Csv);
+        assert_eq!(config_file.output.export_path.as_deref(), Some("flows.csv"));
+        assert!(config_file.output.header);
+        assert_eq!(config_file.output.min_packets, 3);
+        assert!(config_file.output.canonical_endpoints);
+        assert_eq!(config_file.config.packet_sequence_length, Some(20));
+        assert_eq!(
+            config_file.config.disabled_features,
+            vec![FeatureGroup::Iat, FeatureGroup::Bulk]
+        );
+
+        // The sections without flags are kept, a flag into one only sets its own value
+        assert_eq!(config_file.config.packet_filters.len(), 1);
+        let host_rollups = config_file.output.host_rollups.unwrap();
+        assert_eq!(host_rollups.window, 600);
+        assert_eq!(host_rollups.path.as_deref(), Some("rollups.csv"));
+    }
+
+    #[test]
+    fn test_cli_without_config_file() {
+        let parse = |args: &[&str]| {
+            let matches = Cli::command().try_get_matches_from(args).unwrap();
+            let cli = Cli::from_arg_matches(&matches).unwrap();
+            cli.to_config_file(&matches)
+        };
+
+        let config_file =
+            parse(&["rustiflow", "-f", "cic", "-o", "print", "--min-packets", "2", "pcap", "a"])
+                .unwrap();
+        assert_eq!(config_file.config.features, FlowType::CIC);
+        assert_eq!(config_file.config.idle_timeout, 120);
+        assert_eq!(config_file.output.min_packets, 2);
+
+        // The feature set and the output have no default on the command line
+        assert!(parse(&["rustiflow", "-o", "print", "pcap", "a"]).is_err());
+        assert!(parse(&["rustiflow", "-f", "cic", "pcap", "a"]).is_err());
+        assert!(parse(&["rustiflow", "-f", "cic", "-o", "csv", "pcap", "a"]).is_err());
+    }
 }
@@ -0,0 +1,1089 @@
+//! Batched inserts of flows into ClickHouse over its native TCP protocol.
+//!
+//! Only the part of the protocol needed for inserts is implemented: the handshake, an
+//! `INSERT` query and uncompressed data blocks. The server answers the query with the
+//! columns of the table, the typed values of the features (see `registry::Value`) are
+//! converted to the types of the columns before sending.
+
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{info, warn};
+
+use crate::{
+    args::{ClickHouseConfig, TimestampFormat},
+    flows::{
+        registry::{self, Value},
+        settings::FlowSettings,
+    },
+};
+
+/// The protocol revision spoken by the client, the server adapts to it.
+///
+/// It is the first revision sending the query settings as strings, newer revisions only
+/// add fields to the packets that an insert doesn't need.
+const CLIENT_REVISION: u64 = 54429;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The wait before the first retry of a failed insert, doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Flows kept for later after failed inserts are capped at this many batches.
+const MAX_PENDING_BATCHES: usize = 10;
+
+// Packet types sent by the client
+const CLIENT_HELLO: u64 = 0;
+const CLIENT_QUERY: u64 = 1;
+const CLIENT_DATA: u64 = 2;
+
+// Packet types sent by the server
+const SERVER_HELLO: u64 = 0;
+const SERVER_DATA: u64 = 1;
+const SERVER_EXCEPTION: u64 = 2;
+const SERVER_PROGRESS: u64 = 3;
+const SERVER_END_OF_STREAM: u64 = 5;
+const SERVER_PROFILE_INFO: u64 = 6;
+const SERVER_TABLE_COLUMNS: u64 = 11;
+
+/// Processing stage of a query, an insert is always run to completion.
+const STAGE_COMPLETE: u64 = 2;
+
+/// Server error codes that are worth retrying, e.g. timeouts, too many parts or an
+/// overloaded server. Other errors (an unknown table, a type mismatch) won't go away.
+const TRANSIENT_ERROR_CODES: [i32; 12] = [
+    159, // TIMEOUT_EXCEEDED
+    202, // TOO_MANY_SIMULTANEOUS_QUERIES
+    203, // NO_FREE_CONNECTION
+    209, // SOCKET_TIMEOUT
+    210, // NETWORK_ERROR
+    236, // ABORTED
+    241, // MEMORY_LIMIT_EXCEEDED
+    242, // TABLE_IS_READ_ONLY
+    252, // TOO_MANY_PARTS
+    319, // UNKNOWN_STATUS_OF_INSERT
+    425, // SYSTEM_ERROR
+    999, // KEEPER_EXCEPTION
+];
+
+/// Why an insert failed, which decides whether it is retried.
+#[derive(Debug)]
+enum InsertError {
+    /// The connection failed or the server is temporarily unable to insert.
+    Transient(String),
+    /// The table doesn't fit the flows, retrying gives the same result.
+    Permanent(String),
+}
+
+impl From<io::Error> for InsertError {
+    fn from(error: io::Error) -> Self {
+        InsertError::Transient(error.to_string())
+    }
+}
+
+/// The column types the feature values can be converted to.
+#[derive(Debug, Clone, PartialEq)]
+enum ColumnType {
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Bool,
+    String,
+    IPv4,
+    IPv6,
+    DateTime,
+    /// A timestamp with the given number of decimal places of the seconds.
+    DateTime64(u32),
+    Nullable(Box<ColumnType>),
+}
+
+impl ColumnType {
+    /// Parses a ClickHouse type name like `Nullable(Float64)` or `DateTime64(6, 'UTC')`.
+    fn parse(name: &str) -> Option<ColumnType> {
+        let name = name.trim();
+        if let Some(inner) = name
+            .strip_prefix("Nullable(")
+            .and_then(|name| name.strip_suffix(')'))
+        {
+            return Some(ColumnType::Nullable(Box::new(ColumnType::parse(inner)?)));
+        }
+        if let Some(arguments) = name
+            .strip_prefix("DateTime64(")
+            .and_then(|name| name.strip_suffix(')'))
+        {
+            let precision = arguments.split(',').next()?.trim().parse().ok()?;
+            return (precision <= 9).then_some(ColumnType::DateTime64(precision));
+        }
+        // The time zone of a DateTime only changes how it is displayed
+        if name.starts_with("DateTime(") {
+            return Some(ColumnType::DateTime);
+        }
+
+        match name {
+            "UInt8" => Some(ColumnType::UInt8),
+            "UInt16" => Some(ColumnType::UInt16),
+            "UInt32" => Some(ColumnType::UInt32),
+            "UInt64" => Some(ColumnType::UInt64),
+            "Int8" => Some(ColumnType::Int8),
+            "Int16" => Some(ColumnType::Int16),
+            "Int32" => Some(ColumnType::Int32),
+            "Int64" => Some(ColumnType::Int64),
+            "Float32" => Some(ColumnType::Float32),
+            "Float64" => Some(ColumnType::Float64),
+            "Bool" => Some(ColumnType::Bool),
+            "String" => Some(ColumnType::String),
+            "IPv4" => Some(ColumnType::IPv4),
+            "IPv6" => Some(ColumnType::IPv6),
+            "DateTime" => Some(ColumnType::DateTime),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes the values of one column in the native format.
+///
+/// Missing features are inserted as NULL into nullable columns and as the default of the type
+/// (0, an empty string) otherwise.
+fn write_column(
+    buf: &mut Vec<u8>,
+    column_type: &ColumnType,
+    values: &[&Value],
+    settings: &FlowSettings,
+) -> Result<(), String> {
+    match column_type {
+        ColumnType::Nullable(inner) => {
+            // The null map comes first, the nested column has a default value for NULLs
+            buf.extend(values.iter().map(|value| u8::from(**value == Value::Null)));
+            for value in values {
+                write_value(buf, inner, value, settings)?;
+            }
+        }
+        _ => {
+            for value in values {
+                write_value(buf, column_type, value, settings)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serializes a single value, `Value::Null` is written as the default of the type.
+///
+/// The values are converted to the type of the column where that is lossless, e.g. a count into
+/// any integer column it fits. Text values, those of replayed records, are parsed.
+fn write_value(
+    buf: &mut Vec<u8>,
+    column_type: &ColumnType,
+    value: &Value,
+    settings: &FlowSettings,
+) -> Result<(), String> {
+    match column_type {
+        ColumnType::UInt8 => buf.push(integer::<u8>(value)?),
+        ColumnType::UInt16 => buf.extend(integer::<u16>(value)?.to_le_bytes()),
+        ColumnType::UInt32 => buf.extend(integer::<u32>(value)?.to_le_bytes()),
+        ColumnType::UInt64 => buf.extend(integer::<u64>(value)?.to_le_bytes()),
+        ColumnType::Int8 => buf.extend(integer::<i8>(value)?.to_le_bytes()),
+        ColumnType::Int16 => buf.extend(integer::<i16>(value)?.to_le_bytes()),
+        ColumnType::Int32 => buf.extend(integer::<i32>(value)?.to_le_bytes()),
+        ColumnType::Int64 => buf.extend(integer::<i64>(value)?.to_le_bytes()),
+        ColumnType::Float32 => {
+            let number = match value {
+                Value::Float(number) => *number,
+                Value::Text(text) => parse(text)?,
+                value => float(value)? as f32,
+            };
+            buf.extend(number.to_le_bytes());
+        }
+        ColumnType::Float64 => buf.extend(float(value)?.to_le_bytes()),
+        ColumnType::Bool => buf.push(match value {
+            Value::Null | Value::Boolean(false) | Value::Unsigned(0) => 0,
+            Value::Boolean(true) | Value::Unsigned(1) => 1,
+            Value::Text(text) if text == "0" || text == "false" => 0,
+            Value::Text(text) if text == "1" || text == "true" => 1,
+            value => return Err(format!("`{}` is not a boolean", describe(value))),
+        }),
+        ColumnType::String => match value {
+            Value::Null => write_string(buf, ""),
+            Value::Text(text) => write_string(buf, text),
+            value => {
+                let mut text = String::new();
+                value.write(settings, &mut text);
+                write_string(buf, &text);
+            }
+        },
+        // Stored as a little endian number like the UInt32 it is based on
+        ColumnType::IPv4 => {
+            let ip = match value {
+                Value::Null => Ipv4Addr::UNSPECIFIED,
+                Value::Ip(IpAddr::V4(ip)) => *ip,
+                Value::Text(text) => parse(text)?,
+                value => return Err(format!("`{}` is not an IPv4 address", describe(value))),
+            };
+            buf.extend(u32::from(ip).to_le_bytes());
+        }
+        ColumnType::IPv6 => {
+            let ip = match value {
+                Value::Null => Ipv6Addr::UNSPECIFIED,
+                Value::Ip(IpAddr::V6(ip)) => *ip,
+                // An IPv4 address fits as the IPv4-mapped address, as ClickHouse converts it
+                Value::Ip(IpAddr::V4(ip)) => ip.to_ipv6_mapped(),
+                Value::Text(text) => parse(text)?,
+                value => return Err(format!("`{}` is not an IPv6 address", describe(value))),
+            };
+            buf.extend(ip.octets());
+        }
+        ColumnType::DateTime => {
+            let seconds = timestamp(value, settings.timestamp_format)?.map_or(0, |t| t.timestamp());
+            let seconds = u32::try_from(seconds)
+                .map_err(|_| format!("`{}` is out of the DateTime range", describe(value)))?;
+            buf.extend(seconds.to_le_bytes());
+        }
+        ColumnType::DateTime64(precision) => {
+            let nanoseconds = timestamp(value, settings.timestamp_format)?
+                .map_or(Some(0), |t| t.timestamp_nanos_opt())
+                .ok_or_else(|| format!("`{}` is out of range", describe(value)))?;
+            buf.extend((nanoseconds / 10_i64.pow(9 - precision)).to_le_bytes());
+        }
+        ColumnType::Nullable(_) => return Err("nested Nullable types aren't supported".to_string()),
+    }
+    Ok(())
+}
+
+/// Converts a value into an integer column, if it is a whole number in its range.
+fn integer<T>(value: &Value) -> Result<T, String>
+where
+    T: FromStr + Default + TryFrom<u64> + TryFrom<i64>,
+{
+    let integer = match value {
+        Value::Null => return Ok(T::default()),
+        Value::Unsigned(number) => T::try_from(*number).ok(),
+        Value::Signed(number) => T::try_from(*number).ok(),
+        Value::Boolean(flag) => T::try_from(u64::from(*flag)).ok(),
+        Value::Text(text) => return parse(text),
+        _ => None,
+    };
+    integer.ok_or_else(|| {
+        format!("`{}` is not a valid {}", describe(value), std::any::type_name::<T>())
+    })
+}
+
+/// Converts a value into a floating point column.
+fn float(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Null => Ok(0.0),
+        Value::Unsigned(number) => Ok(*number as f64),
+        Value::Signed(number) => Ok(*number as f64),
+        Value::Float(number) => Ok(f64::from(*number)),
+        Value::Double(number) => Ok(*number),
+        Value::Text(text) => parse(text),
+        value => Err(format!("`{}` is not a number", describe(value))),
+    }
+}
+
+/// Takes the instant of a timestamp value, `None` for a missing one.
+///
+/// Numbers are epoch timestamps in the unit of `format`, like the ones the nfstream features
+/// are dumped as.
+fn timestamp(value: &Value, format: TimestampFormat) -> Result<Option<DateTime<Utc>>, String> {
+    let number = match value {
+        Value::Null => return Ok(None),
+        Value::Timestamp(timestamp, _) => return Ok(Some(*timestamp)),
+        Value::Text(text) => return parse_timestamp(text, format).map(Some),
+        Value::Unsigned(number) => i64::try_from(*number).ok(),
+        Value::Signed(number) => Some(*number),
+        _ => None,
+    };
+    number
+        .and_then(|number| epoch_timestamp(number, format))
+        .map(Some)
+        .ok_or_else(|| format!("`{}` is not a timestamp", describe(value)))
+}
+
+/// Shows a value in an error message.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Text(text) => text.to_string(),
+        value => format!("{:?}", value),
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid {}", value, std::any::type_name::<T>()))
+}
+
+fn epoch_timestamp(number: i64, format: TimestampFormat) -> Option<DateTime<Utc>> {
+    match format {
+        TimestampFormat::EpochSeconds => DateTime::from_timestamp(number, 0),
+        TimestampFormat::EpochMicros => DateTime::from_timestamp_micros(number),
+        _ => DateTime::from_timestamp_millis(number),
+    }
+}
+
+/// Parses a timestamp in any format the flows dump it in, see `TimestampFormat`.
+///
+/// Text timestamps are `2024-05-01 12:00:00.123456 UTC`, with a UTC offset instead of `UTC`
+/// if a time zone is set, or RFC 3339. Numbers are epoch timestamps in the configured unit,
+/// milliseconds like the nfstream features by default.
+fn parse_timestamp(value: &str, format: TimestampFormat) -> Result<DateTime<Utc>, String> {
+    let invalid = || format!("`{}` is not a timestamp", value);
+    if let Ok(number) = value.parse::<i64>() {
+        return epoch_timestamp(number, format).ok_or_else(invalid);
+    }
+    if let Some(naive) = value.strip_suffix(" UTC") {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(naive, "%Y-%m-%d %H:%M:%S%.f") {
+            return Ok(timestamp.and_utc());
+        }
+    }
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f %:z")
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| invalid())
+}
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_varint(buf, value.len() as u64);
+    buf.extend(value.as_bytes());
+}
+
+pub(crate) fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long"))
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_varint(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes a block of columns, each with its name, type name and serialized values.
+fn write_block(buf: &mut Vec<u8>, rows: usize, columns: &[(&str, &str, Vec<u8>)]) {
+    // Block info: not an overflow block (field 1) and no bucket (field 2)
+    write_varint(buf, 1);
+    buf.push(0);
+    write_varint(buf, 2);
+    buf.extend((-1_i32).to_le_bytes());
+    write_varint(buf, 0);
+
+    write_varint(buf, columns.len() as u64);
+    write_varint(buf, rows as u64);
+    for (name, type_name, data) in columns {
+        write_string(buf, name);
+        write_string(buf, type_name);
+        buf.extend(data);
+    }
+}
+
+/// Reads an exception sent by the server, returning its error code and message.
+fn read_exception(reader: &mut impl Read) -> io::Result<(i32, String)> {
+    let code = read_i32(reader)?;
+    let _name = read_string(reader)?;
+    let message = read_string(reader)?;
+    let _stack_trace = read_string(reader)?;
+    if read_u8(reader)? != 0 {
+        // The nested exception is the cause, the outer message already describes it
+        read_exception(reader)?;
+    }
+    Ok((code, message))
+}
+
+fn exception_error(code: i32, message: String) -> InsertError {
+    let message = format!("server error {}: {}", code, message);
+    if TRANSIENT_ERROR_CODES.contains(&code) {
+        InsertError::Transient(message)
+    } else {
+        InsertError::Permanent(message)
+    }
+}
+
+/// Quotes a table or column name for a query.
+fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+/// A connection to a ClickHouse server after the handshake.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    fn open(config: &ClickHouseConfig) -> Result<Connection, InsertError> {
+        let address = config
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| InsertError::Permanent(format!("can't resolve {}", config.address)))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        };
+
+        let mut hello = Vec::new();
+        write_varint(&mut hello, CLIENT_HELLO);
+        write_string(&mut hello, "RustiFlow");
+        write_varint(&mut hello, version_number(env!("CARGO_PKG_VERSION_MAJOR")));
+        write_varint(&mut hello, version_number(env!("CARGO_PKG_VERSION_MINOR")));
+        write_varint(&mut hello, CLIENT_REVISION);
+        write_string(&mut hello, &config.database);
+        write_string(&mut hello, &config.user);
+        write_string(&mut hello, &config.password);
+        connection.send(&hello)?;
+
+        let reader = &mut connection.reader;
+        match read_varint(reader)? {
+            SERVER_HELLO => {
+                let name = read_string(reader)?;
+                let major = read_varint(reader)?;
+                let minor = read_varint(reader)?;
+                let revision = read_varint(reader)?;
+                if revision < CLIENT_REVISION {
+                    return Err(InsertError::Permanent(format!(
+                        "{} {}.{} is too old, at least protocol revision {} is needed",
+                        name, major, minor, CLIENT_REVISION
+                    )));
+                }
+                let _timezone = read_string(reader)?;
+                let _display_name = read_string(reader)?;
+                let _patch = read_varint(reader)?;
+                info!("Connected to {} {}.{} at {}", name, major, minor, config.address);
+                Ok(connection)
+            }
+            SERVER_EXCEPTION => {
+                let (code, message) = read_exception(reader)?;
+                Err(exception_error(code, message))
+            }
+            packet => Err(unexpected_packet(packet)),
+        }
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.writer.write_all(packet)?;
+        self.writer.flush()
+    }
+
+    /// Inserts the flows into the columns of the table named like the features.
+    ///
+    /// ### Arguments
+    ///
+    /// * `columns` - The feature names and their positions in the values of a flow.
+    /// * `rows` - The values of the flows, see `registry::record_values`.
+    /// * `settings` - The settings the flows are dumped with, for the features written as
+    ///   text and the unit of epoch timestamps.
+    fn insert(
+        &mut self,
+        config: &ClickHouseConfig,
+        columns: &[(String, usize)],
+        rows: &[Vec<Value>],
+        settings: &FlowSettings,
+    ) -> Result<(), InsertError> {
+        let column_names: Vec<String> =
+            columns.iter().map(|(name, _)| quote_identifier(name)).collect();
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES",
+            quote_identifier(&config.table),
+            column_names.join(", ")
+        );
+
+        let mut packet = Vec::new();
+        write_varint(&mut packet, CLIENT_QUERY);
+        write_string(&mut packet, ""); // Let the server generate the query id
+        self.write_client_info(&mut packet);
+        if config.async_insert {
+            // Waiting for the buffered insert keeps a failure visible, so it can be retried
+            for (name, value) in [("async_insert", "1"), ("wait_for_async_insert", "1")] {
+                write_string(&mut packet, name);
+                write_varint(&mut packet, 0); // Flags, an unknown setting is ignored
+                write_string(&mut packet, value);
+            }
+        }
+        write_string(&mut packet, ""); // End of the settings
+        write_varint(&mut packet, STAGE_COMPLETE);
+        write_varint(&mut packet, 0); // No compression
+        write_string(&mut packet, &query);
+        // An empty block ends the (here absent) external tables
+        write_data_packet(&mut packet, 0, &[]);
+        self.send(&packet)?;
+
+        // The server answers with the columns of the table as an empty block
+        let table_columns = loop {
+            match read_varint(&mut self.reader)? {
+                SERVER_DATA => break self.read_header_block()?,
+                packet => self.skip_packet(packet)?,
+            }
+        };
+
+        let mut block_columns = Vec::with_capacity(table_columns.len());
+        for (name, type_name) in &table_columns {
+            let column_type = ColumnType::parse(type_name).ok_or_else(|| {
+                InsertError::Permanent(format!(
+                    "column {} has the unsupported type {}",
+                    name, type_name
+                ))
+            })?;
+            let &(_, index) = columns
+                .iter()
+                .find(|(feature, _)| feature == name)
+                .ok_or_else(|| InsertError::Permanent(format!("unexpected column {}", name)))?;
+            let values = rows
+                .iter()
+                .map(|row| row.get(index))
+                .collect::<Option<Vec<&Value>>>()
+                .ok_or_else(|| InsertError::Permanent(format!("flow without {}", name)))?;
+
+            let mut data = Vec::new();
+            write_column(&mut data, &column_type, &values, settings).map_err(|e| {
+                InsertError::Permanent(format!("can't insert into column {}: {}", name, e))
+            })?;
+            block_columns.push((name.as_str(), type_name.as_str(), data));
+        }
+
+        let mut packet = Vec::new();
+        write_data_packet(&mut packet, rows.len(), &block_columns);
+        // An empty block ends the data of the insert
+        write_data_packet(&mut packet, 0, &[]);
+        self.send(&packet)?;
+
+        loop {
+            match read_varint(&mut self.reader)? {
+                SERVER_END_OF_STREAM => return Ok(()),
+                packet => self.skip_packet(packet)?,
+            }
+        }
+    }
+
+    /// Writes the client info of the query, describing this client to the server.
+    fn write_client_info(&self, packet: &mut Vec<u8>) {
+        packet.push(1); // Initial query
+        write_string(packet, ""); // Initial user
+        write_string(packet, ""); // Initial query id
+        write_string(packet, "0.0.0.0:0"); // Initial address
+        packet.push(1); // TCP interface
+        write_string(packet, ""); // OS user
+        write_string(packet, ""); // Client hostname
+        write_string(packet, "RustiFlow");
+        write_varint(packet, version_number(env!("CARGO_PKG_VERSION_MAJOR")));
+        write_varint(packet, version_number(env!("CARGO_PKG_VERSION_MINOR")));
+        write_varint(packet, CLIENT_REVISION);
+        write_string(packet, ""); // Quota key
+        write_varint(packet, version_number(env!("CARGO_PKG_VERSION_PATCH")));
+    }
+
+    /// Reads a data block without rows, returning the names and types of its columns.
+    fn read_header_block(&mut self) -> Result<Vec<(String, String)>, InsertError> {
+        let reader = &mut self.reader;
+        let _table_name = read_string(reader)?;
+        loop {
+            match read_varint(reader)? {
+                0 => break,
+                1 => {
+                    read_u8(reader)?;
+                }
+                2 => {
+                    read_i32(reader)?;
+                }
+                field => {
+                    return Err(InsertError::Permanent(format!(
+                        "unknown block info field {}",
+                        field
+                    )))
+                }
+            }
+        }
+        let columns = read_varint(reader)?;
+        if read_varint(reader)? != 0 {
+            return Err(InsertError::Permanent("unexpected rows from the server".to_string()));
+        }
+        // Without rows, a column has no data after its name and type
+        (0..columns)
+            .map(|_| Ok((read_string(reader)?, read_string(reader)?)))
+            .collect()
+    }
+
+    /// Skips the informational packets, exceptions are returned as an error.
+    fn skip_packet(&mut self, packet: u64) -> Result<(), InsertError> {
+        let reader = &mut self.reader;
+        match packet {
+            SERVER_EXCEPTION => {
+                let (code, message) = read_exception(reader)?;
+                return Err(exception_error(code, message));
+            }
+            SERVER_PROGRESS => {
+                // Read and written rows and bytes and the total rows
+                for _ in 0..5 {
+                    read_varint(reader)?;
+                }
+            }
+            SERVER_PROFILE_INFO => {
+                for _ in 0..3 {
+                    read_varint(reader)?;
+                }
+                read_u8(reader)?;
+                read_varint(reader)?;
+                read_u8(reader)?;
+            }
+            SERVER_TABLE_COLUMNS => {
+                let _table_name = read_string(reader)?;
+                let _columns_description = read_string(reader)?;
+            }
+            packet => return Err(unexpected_packet(packet)),
+        }
+        Ok(())
+    }
+}
+
+fn write_data_packet(packet: &mut Vec<u8>, rows: usize, columns: &[(&str, &str, Vec<u8>)]) {
+    write_varint(packet, CLIENT_DATA);
+    write_string(packet, ""); // Not an external table
+    write_block(packet, rows, columns);
+}
+
+fn unexpected_packet(packet: u64) -> InsertError {
+    InsertError::Permanent(format!("unexpected packet type {} from the server", packet))
+}
+
+fn version_number(version: &str) -> u64 {
+    version.parse().unwrap_or_default()
+}
+
+/// Collects the exported flows into batches and inserts them into a ClickHouse table.
+///
+/// Failed inserts are retried with a backoff. When the server stays unreachable, the
+/// batch is kept and sent with the next one, up to a limit of pending flows. A batch
+/// the table rejects (e.g. a missing column) is dropped, as it would never fit.
+///
+/// A retried insert may be written twice if only the acknowledgement of the server was
+/// lost.
+pub struct ClickHouseSink {
+    config: ClickHouseConfig,
+    /// The feature names and their positions in the values of a flow, without duplicates.
+    columns: Vec<(String, usize)>,
+    /// The settings the flows are dumped with, see `ClickHouseSink::new`.
+    settings: Arc<FlowSettings>,
+    connection: Option<Connection>,
+    batch: Vec<Vec<Value>>,
+    /// The batch is inserted once it holds this many flows, grows after a failed insert.
+    flush_size: usize,
+    last_flush: Instant,
+    inserted_flows: u64,
+    lost_flows: u64,
+    /// The flows that couldn't be inserted, kept for the dead-letter file if enabled.
+    dropped_rows: Option<Vec<String>>,
+}
+
+impl ClickHouseSink {
+    /// Creates a sink for flows with the features of the given header, without connecting yet.
+    ///
+    /// ### Arguments
+    ///
+    /// * `config` - The server, table and batching settings.
+    /// * `header` - The comma separated feature names, the columns of the insert.
+    /// * `settings` - The settings the flows are dumped with, for the null value, the unit of
+    ///   epoch timestamps and the features inserted as text.
+    pub fn new(config: ClickHouseConfig, header: &str, settings: Arc<FlowSettings>) -> Self {
+        let mut columns: Vec<(String, usize)> = Vec::new();
+        for (index, name) in header.split(',').enumerate() {
+            // A repeated feature (e.g. FWD_HEADER_LENGTH of CIC) keeps its first value
+            if !columns.iter().any(|(column, _)| column == name) {
+                columns.push((name.to_string(), index));
+            }
+        }
+
+        ClickHouseSink {
+            flush_size: config.batch_size,
+            config,
+            columns,
+            settings,
+            connection: None,
+            batch: Vec::new(),
+            last_flush: Instant::now(),
+            inserted_flows: 0,
+            lost_flows: 0,
+            dropped_rows: None,
+        }
+    }
+
+    /// Keeps the flows that can't be inserted for `take_dropped_rows` instead of losing them.
+    pub fn keep_dropped_rows(&mut self) {
+        self.dropped_rows = Some(Vec::new());
+    }
+
+    /// Takes the flows dropped since the last call as records, if they are kept.
+    pub fn take_dropped_rows(&mut self) -> Vec<String> {
+        self.dropped_rows.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Gives up on the flows still waiting for an insert, e.g. after the last one failed.
+    pub fn drop_batch(&mut self) {
+        let batch = std::mem::take(&mut self.batch);
+        self.drop_rows(batch);
+    }
+
+    fn drop_rows(&mut self, rows: Vec<Vec<Value>>) {
+        let settings = &self.settings;
+        match self.dropped_rows.as_mut() {
+            Some(dropped_rows) => {
+                dropped_rows.extend(rows.iter().map(|row| registry::dump_values(row, settings)))
+            }
+            None => self.lost_flows += rows.len() as u64,
+        }
+    }
+
+    /// Connects to the server ahead of the first insert, to report a wrong address early.
+    pub async fn connect(&mut self) -> io::Result<()> {
+        let config = self.config.clone();
+        let opened = tokio::task::spawn_blocking(move || Connection::open(&config))
+            .await
+            .map_err(io::Error::other)?;
+        match opened {
+            Ok(connection) => {
+                self.connection = Some(connection);
+                Ok(())
+            }
+            Err(InsertError::Transient(message)) | Err(InsertError::Permanent(message)) => {
+                Err(io::Error::other(format!(
+                    "Can't connect to ClickHouse at {}: {}",
+                    self.config.address, message
+                )))
+            }
+        }
+    }
+
+    /// Adds the values of a flow to the batch, see `registry::record_values`, inserting the
+    /// batch when it's full or old enough.
+    pub async fn push(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.batch.push(row);
+        if self.batch.len() >= self.flush_size || self.flush_due() {
+            return self.flush().await;
+        }
+        Ok(())
+    }
+
+    /// Adds a dumped record to the batch, e.g. a replayed one, its values are parsed by the
+    /// types of the columns.
+    pub async fn push_dumped(&mut self, record: &str) -> io::Result<()> {
+        let row = registry::dumped_values(record, &self.settings);
+        self.push(row).await
+    }
+
+    /// Inserts the collected flows once `flush_interval` has passed since the last insert, e.g.
+    /// on a timer while no flows arrive to fill the batch.
+    pub async fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.flush_due() {
+            return self.flush().await;
+        }
+        Ok(())
+    }
+
+    fn flush_due(&self) -> bool {
+        !self.batch.is_empty()
+            && self.last_flush.elapsed() >= Duration::from_secs(self.config.flush_interval)
+    }
+
+    /// Inserts the collected flows.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        match self.insert_with_retries().await {
+            Ok(()) => {
+                self.inserted_flows += self.batch.len() as u64;
+                self.batch.clear();
+                self.flush_size = self.config.batch_size;
+                Ok(())
+            }
+            Err(InsertError::Permanent(message)) => {
+                let dropped = self.batch.len();
+                self.drop_batch();
+                self.flush_size = self.config.batch_size;
+                Err(io::Error::other(format!(
+                    "Dropped {} flows rejected by ClickHouse: {}",
+                    dropped, message
+                )))
+            }
+            Err(InsertError::Transient(message)) => {
+                // Keep the flows and try again once another batch has been collected
+                let limit = self.config.batch_size * MAX_PENDING_BATCHES;
+                if self.batch.len() > limit {
+                    let dropped = self.batch.len() - limit;
+                    let rows = self.batch.drain(..dropped).collect();
+                    self.drop_rows(rows);
+                    warn!("Dropped the {} oldest flows waiting for ClickHouse", dropped);
+                }
+                self.flush_size = self.batch.len() + self.config.batch_size;
+                Err(io::Error::other(format!(
+                    "Inserting into ClickHouse failed, keeping {} flows for the next attempt: {}",
+                    self.batch.len(),
+                    message
+                )))
+            }
+        }
+    }
+
+    async fn insert_with_retries(&mut self) -> Result<(), InsertError> {
+        let mut backoff = RETRY_BACKOFF;
+        for _ in 0..self.config.max_retries {
+            match self.try_insert().await {
+                Err(InsertError::Transient(message)) => {
+                    warn!(
+                        "Inserting into ClickHouse failed, retrying in {:?}: {}",
+                        backoff, message
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+        self.try_insert().await
+    }
+
+    /// Inserts the batch on a blocking thread, so the socket I/O doesn't hold up the runtime.
+    async fn try_insert(&mut self) -> Result<(), InsertError> {
+        let connection = self.connection.take();
+        let config = self.config.clone();
+        let columns = self.columns.clone();
+        let batch = std::mem::take(&mut self.batch);
+        let settings = Arc::clone(&self.settings);
+        let insert = tokio::task::spawn_blocking(move || {
+            let mut connection = match connection {
+                Some(connection) => connection,
+                None => match Connection::open(&config) {
+                    Ok(connection) => connection,
+                    Err(e) => return (batch, None, Err(e)),
+                },
+            };
+            let result = connection.insert(&config, &columns, &batch, &settings);
+            // After an error the state of the connection is unknown, the next insert reconnects
+            (batch, result.is_ok().then_some(connection), result)
+        });
+        let (batch, connection, result) = match insert.await {
+            Ok(inserted) => inserted,
+            // The insert only doesn't finish if it panicked
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        self.batch = batch;
+        self.connection = connection;
+        result
+    }
+
+    /// The number of flows inserted into the table so far.
+    pub fn inserted_flows(&self) -> u64 {
+        self.inserted_flows
+    }
+
+    /// The number of flows dropped because the table rejected them or too many were pending,
+    /// without the ones kept for `take_dropped_rows`.
+    pub fn lost_flows(&self) -> u64 {
+        self.lost_flows + self.batch.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use chrono::DateTime;
+
+    use super::{write_column, write_varint, ClickHouseSink, ColumnType};
+    use crate::{
+        args::{ClickHouseConfig, NullValue, TimestampFormat},
+        flows::{
+            registry::{self, Value},
+            settings::FlowSettings,
+        },
+    };
+
+    #[test]
+    fn test_parse_column_type() {
+        assert_eq!(ColumnType::parse("UInt16"), Some(ColumnType::UInt16));
+        assert_eq!(
+            ColumnType::parse("Nullable(Float64)"),
+            Some(ColumnType::Nullable(Box::new(ColumnType::Float64)))
+        );
+        assert_eq!(ColumnType::parse("DateTime64(6, 'UTC')"), Some(ColumnType::DateTime64(6)));
+        assert_eq!(ColumnType::parse("DateTime('Europe/Berlin')"), Some(ColumnType::DateTime));
+        assert_eq!(ColumnType::parse("LowCardinality(String)"), None);
+    }
+
+    #[test]
+    fn test_write_column() {
+        let settings = FlowSettings::default();
+        let write = |column_type: &ColumnType, values: &[Value]| {
+            let mut buf = Vec::new();
+            let values: Vec<&Value> = values.iter().collect();
+            write_column(&mut buf, column_type, &values, &settings).map(|()| buf)
+        };
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, [0xac, 0x02]);
+
+        let counts = [Value::Unsigned(1), Value::Unsigned(258)];
+        assert_eq!(write(&ColumnType::UInt16, &counts).unwrap(), [1, 0, 2, 1]);
+        let error = write(&ColumnType::UInt8, &counts).unwrap_err();
+        assert!(error.contains("258"));
+        let error = write(&ColumnType::UInt32, &[Value::Signed(-1)]).unwrap_err();
+        assert!(error.contains("-1"));
+        assert!(write(&ColumnType::UInt32, &[Value::Double(1.5)]).is_err());
+        let means = [Value::Double(1.5), Value::Float(0.25), Value::Unsigned(2)];
+        let expected: Vec<u8> = [1.5_f64, 0.25, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(write(&ColumnType::Float64, &means).unwrap(), expected);
+        assert_eq!(write(&ColumnType::Bool, &[Value::Boolean(true)]).unwrap(), [1]);
+
+        // Missing features are NULL, with a zero in the nested column
+        let nullable = ColumnType::Nullable(Box::new(ColumnType::UInt8));
+        let values = [Value::Unsigned(7), Value::Null];
+        assert_eq!(write(&nullable, &values).unwrap(), [0, 1, 7, 0]);
+
+        // Features without a text type are inserted into text columns as they are dumped
+        let values = [Value::Text("tcp".into()), Value::Null, Value::Unsigned(6)];
+        assert_eq!(write(&ColumnType::String, &values).unwrap(), [3, b't', b'c', b'p', 0, 1, b'6']);
+
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        assert_eq!(write(&ColumnType::IPv4, &[Value::Ip(ip.into())]).unwrap(), [1, 0, 0, 10]);
+        let mapped = ip.to_ipv6_mapped().octets();
+        assert_eq!(write(&ColumnType::IPv6, &[Value::Ip(ip.into())]).unwrap(), mapped);
+        let ip = Value::Ip("::1".parse().unwrap());
+        assert!(write(&ColumnType::IPv4, &[ip]).is_err());
+
+        let instant = DateTime::from_timestamp_micros(1_714_564_800_123_456).unwrap();
+        let timestamp = Value::Timestamp(instant, TimestampFormat::Text);
+        let milliseconds = 1_714_564_800_123_i64.to_le_bytes();
+        assert_eq!(write(&ColumnType::DateTime64(3), &[timestamp]).unwrap(), milliseconds);
+        // A missing nfstream timestamp is dumped as an epoch of 0
+        let epoch = write(&ColumnType::DateTime64(3), &[Value::Signed(0)]).unwrap();
+        assert_eq!(epoch, 0_i64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_dumped_column() {
+        // Replayed records are parsed by the types of the columns
+        let settings = FlowSettings {
+            null_value: NullValue::MinusOne,
+            ..FlowSettings::default()
+        };
+        let write = |column_type: &ColumnType, record: &str| {
+            let mut buf = Vec::new();
+            let values = registry::dumped_values(record, &settings);
+            let values: Vec<&Value> = values.iter().collect();
+            write_column(&mut buf, column_type, &values, &settings).map(|()| buf)
+        };
+        assert_eq!(write(&ColumnType::UInt16, "1,258").unwrap(), [1, 0, 2, 1]);
+        let nullable = ColumnType::Nullable(Box::new(ColumnType::UInt8));
+        assert_eq!(write(&nullable, "7,-1").unwrap(), [0, 1, 7, 0]);
+        assert_eq!(write(&ColumnType::String, "tcp,-1").unwrap(), [3, b't', b'c', b'p', 0]);
+        assert_eq!(write(&ColumnType::IPv4, "10.0.0.1").unwrap(), [1, 0, 0, 10]);
+
+        // The same instant in all the timestamp formats
+        for timestamp in [
+            "2024-05-01 12:00:00.123456 UTC",
+            "2024-05-01 14:00:00.123456 +02:00",
+            "2024-05-01T12:00:00.123456Z",
+            "1714564800123",
+        ] {
+            let buf = write(&ColumnType::DateTime64(3), timestamp).unwrap();
+            assert_eq!(buf, 1_714_564_800_123_i64.to_le_bytes(), "{}", timestamp);
+        }
+
+        let error = write(&ColumnType::UInt32, "1.5").unwrap_err();
+        assert!(error.contains("1.5"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_insert_keeps_batch() {
+        // Nothing listens on the port any more, so every connection attempt is refused
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let config = ClickHouseConfig {
+            address: address.to_string(),
+            batch_size: 2,
+            max_retries: 0,
+            ..ClickHouseConfig::default()
+        };
+        let settings = Arc::new(FlowSettings::default());
+        let mut sink = ClickHouseSink::new(config, "FLOW_ID,PROTOCOL,PROTOCOL", settings);
+        assert_eq!(sink.columns, [("FLOW_ID".to_string(), 0), ("PROTOCOL".to_string(), 1)]);
+
+        let row = |flow_id: &'static str, protocol: u64| {
+            vec![Value::Text(flow_id.into()), Value::Unsigned(protocol), Value::Unsigned(protocol)]
+        };
+        sink.push(row("a", 6)).await.unwrap();
+        assert!(sink.push(row("b", 17)).await.is_err());
+        assert_eq!(sink.batch.len(), 2);
+        // The kept flows are retried together with the next batch, replayed ones as well
+        sink.push(row("c", 6)).await.unwrap();
+        assert!(sink.push_dumped("d,6,6").await.is_err());
+        assert_eq!(sink.batch.len(), 4);
+        assert_eq!(sink.inserted_flows(), 0);
+
+        // The timer retries them once the flush interval has passed, without another flow
+        sink.flush_if_due().await.unwrap();
+        sink.last_flush -= Duration::from_secs(sink.config.flush_interval);
+        assert!(sink.flush_if_due().await.is_err());
+        assert_eq!(sink.batch.len(), 4);
+
+        // Flows given up on are handed over for the dead-letter file instead of being lost
+        sink.keep_dropped_rows();
+        sink.drop_batch();
+        assert_eq!(sink.take_dropped_rows(), ["a,6,6", "b,17,17", "c,6,6", "d,6,6"]);
+        assert!(sink.take_dropped_rows().is_empty());
+        assert_eq!(sink.lost_flows(), 0);
+    }
+}
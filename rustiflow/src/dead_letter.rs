@@ -0,0 +1,97 @@
+//! The dead-letter file of the export, keeping the flows the output failed to write.
+//!
+//! The flows are written as CSV records under the feature header, like a CSV export, so the
+//! file can be read back and written to a sink once it works again.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+};
+
+use log::warn;
+
+/// Appends the records of the flows that couldn't be written to a CSV file.
+pub struct DeadLetterFile {
+    path: String,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+impl DeadLetterFile {
+    /// Opens the file to append to, a new or empty file starts with the header.
+    ///
+    /// An existing file keeps its records, so the flows of several runs end up in one file;
+    /// they need the same features for it to stay readable.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `header` - The dumped feature names, the header of the records.
+    pub fn new(path: String, header: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to open dead-letter file {}: {}", path, e))
+            })?;
+        let empty = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if empty {
+            writeln!(writer, "{}", header)?;
+        }
+        Ok(DeadLetterFile {
+            path,
+            writer,
+            written: 0,
+        })
+    }
+
+    /// Appends the record of a flow and flushes it, so it survives a crash of the output.
+    pub fn write(&mut self, record: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", record)?;
+        self.writer.flush()?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// The number of flows written to the file in this run.
+    #[cfg(test)]
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Flushes the file and reports the flows written to it.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.written > 0 {
+            warn!("Wrote {} flows that failed to export to {}", self.written, self.path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeadLetterFile;
+
+    #[test]
+    fn test_dead_letter_file() {
+        let path = std::env::temp_dir().join("rustiflow_test_dead_letter.csv");
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap().to_string();
+
+        let mut dead_letter = DeadLetterFile::new(path_str.clone(), "A,B").unwrap();
+        dead_letter.write("1,2").unwrap();
+        dead_letter.close().unwrap();
+        assert_eq!(dead_letter.written(), 1);
+
+        // A later run appends below the existing records, without a second header
+        let mut dead_letter = DeadLetterFile::new(path_str, "A,B").unwrap();
+        dead_letter.write("3,4").unwrap();
+        dead_letter.close().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "A,B\n1,2\n3,4\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::packet_features::PacketFeatures;
+
+/// The FTP control channel port.
+const FTP_CONTROL_PORT: u16 = 21;
+/// Time for the data connection to show up after it was advertised, in seconds.
+const EXPECTATION_TIMEOUT: i64 = 60;
+
+static EXPECTATIONS: OnceLock<Mutex<ExpectationTable>> = OnceLock::new();
+
+/// A connection announced on a control channel, identified by the endpoints it will use.
+///
+/// The source port of the announced connection is left open, as it is usually picked by
+/// the connecting host only when it connects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExpectedFlow {
+    /// The host that opens the connection.
+    pub client_ip: IpAddr,
+    /// The host that accepts the connection.
+    pub server_ip: IpAddr,
+    /// The port the connection is accepted on.
+    pub server_port: u16,
+}
+
+/// Parses the data connection an FTP control channel packet announces, if any.
+///
+/// Understands the replies to PASV (227) and EPSV (229) sent by the server and the
+/// PORT and EPRT commands sent by the client.
+///
+/// ### Arguments
+///
+/// * `source_ip` - The source IP address of the packet.
+/// * `source_port` - The source port of the packet.
+/// * `destination_ip` - The destination IP address of the packet.
+/// * `destination_port` - The destination port of the packet.
+/// * `payload` - The TCP payload of the packet.
+///
+/// ### Returns
+///
+/// The announced data connection, or `None` if the packet doesn't announce one.
+pub fn parse_ftp_expectation(
+    source_ip: IpAddr,
+    source_port: u16,
+    destination_ip: IpAddr,
+    destination_port: u16,
+    payload: &[u8],
+) -> Option<ExpectedFlow> {
+    let line = std::str::from_utf8(payload).ok()?.lines().next()?;
+
+    if source_port == FTP_CONTROL_PORT {
+        // The server listens and the client connects
+        let (server_ip, server_port) = if let Some(reply) = line.strip_prefix("227 ") {
+            parse_host_port(between(reply, '(', ')').unwrap_or(reply.trim()))?
+        } else if let Some(reply) = line.strip_prefix("229 ") {
+            let port = parse_extended_port(between(reply, '(', ')')?)?;
+            (source_ip, port)
+        } else {
+            return None;
+        };
+        return Some(ExpectedFlow {
+            client_ip: destination_ip,
+            server_ip,
+            server_port,
+        });
+    }
+
+    if destination_port == FTP_CONTROL_PORT {
+        // The client listens and the server connects
+        let (client_ip, client_port) = if let Some(command) = line.strip_prefix("PORT ") {
+            parse_host_port(command.trim())?
+        } else if let Some(command) = line.strip_prefix("EPRT ") {
+            parse_extended_address(command.trim())?
+        } else {
+            return None;
+        };
+        return Some(ExpectedFlow {
+            client_ip: destination_ip,
+            server_ip: client_ip,
+            server_port: client_port,
+        });
+    }
+
+    None
+}
+
+/// Returns the text between the first `open` and the following `close` character.
+fn between(text: &str, open: char, close: char) -> Option<&str> {
+    let start = text.find(open)? + 1;
+    let end = start + text[start..].find(close)?;
+    Some(&text[start..end])
+}
+
+/// Parses the `h1,h2,h3,h4,p1,p2` address of PASV replies and PORT commands.
+fn parse_host_port(text: &str) -> Option<(IpAddr, u16)> {
+    let numbers = text
+        .split(',')
+        .map(|number| number.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [h1, h2, h3, h4, p1, p2] = numbers[..] else {
+        return None;
+    };
+    Some((
+        IpAddr::V4(Ipv4Addr::new(h1, h2, h3, h4)),
+        u16::from(p1) << 8 | u16::from(p2),
+    ))
+}
+
+/// Parses the `|||port|` argument of EPSV replies.
+fn parse_extended_port(text: &str) -> Option<u16> {
+    let delimiter = text.chars().next()?;
+    text.split(delimiter).nth(3)?.parse().ok()
+}
+
+/// Parses the `|protocol|address|port|` argument of EPRT commands.
+fn parse_extended_address(text: &str) -> Option<(IpAddr, u16)> {
+    let delimiter = text.chars().next()?;
+    let mut fields = text.split(delimiter).skip(2);
+    let address = fields.next()?.parse().ok()?;
+    let port = fields.next()?.parse().ok()?;
+    Some((address, port))
+}
+
+/// Returns the expectations shared by all flow tables.
+///
+/// The announced connection has other endpoints than its control connection, so it usually
+/// ends up in another shard.
+pub fn shared() -> &'static Mutex<ExpectationTable> {
+    EXPECTATIONS.get_or_init(|| Mutex::new(ExpectationTable::default()))
+}
+
+/// The connections announced on control channels that have not shown up yet.
+///
+/// Each expectation carries the session id of the control flow, which is handed to the
+/// flow of the announced connection when its first packet arrives.
+#[derive(Default)]
+pub struct ExpectationTable {
+    expectations: HashMap<ExpectedFlow, (String, DateTime<Utc>)>,
+}
+
+impl ExpectationTable {
+    /// Registers an announced connection.
+    ///
+    /// ### Arguments
+    ///
+    /// * `expected` - The announced connection.
+    /// * `session_id` - The session id of the control flow.
+    /// * `timestamp` - The time the connection was announced.
+    pub fn insert(&mut self, expected: ExpectedFlow, session_id: String, timestamp: DateTime<Utc>) {
+        self.expectations.insert(expected, (session_id, timestamp));
+    }
+
+    /// Removes the expectation the first packet of a new flow fulfills.
+    ///
+    /// ### Returns
+    ///
+    /// The session id of the control flow that announced the flow, if any.
+    pub fn take(&mut self, packet: &PacketFeatures) -> Option<String> {
+        if self.expectations.is_empty() {
+            return None;
+        }
+
+        let expected = ExpectedFlow {
+            client_ip: packet.source_ip,
+            server_ip: packet.destination_ip,
+            server_port: packet.destination_port,
+        };
+        self.expectations
+            .remove(&expected)
+            .map(|(session_id, _)| session_id)
+    }
+
+    /// Drops the expectations whose connection didn't show up in time.
+    pub fn expire(&mut self, timestamp: DateTime<Utc>) {
+        let timeout = TimeDelta::seconds(EXPECTATION_TIMEOUT);
+        self.expectations
+            .retain(|_, (_, announced)| timestamp - *announced <= timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{parse_ftp_expectation, ExpectedFlow};
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const SERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn test_parse_ftp_expectation() {
+        let passive = parse_ftp_expectation(
+            SERVER,
+            21,
+            CLIENT,
+            40000,
+            b"227 Entering Passive Mode (10,0,0,2,195,80).\r\n",
+        );
+        assert_eq!(
+            passive,
+            Some(ExpectedFlow {
+                client_ip: CLIENT,
+                server_ip: SERVER,
+                server_port: 50000,
+            })
+        );
+
+        let extended_passive = parse_ftp_expectation(
+            SERVER,
+            21,
+            CLIENT,
+            40000,
+            b"229 Entering Extended Passive Mode (|||50001|)\r\n",
+        );
+        assert_eq!(extended_passive.unwrap().server_port, 50001);
+
+        let active = parse_ftp_expectation(CLIENT, 40000, SERVER, 21, b"PORT 10,0,0,1,156,64\r\n");
+        assert_eq!(
+            active,
+            Some(ExpectedFlow {
+                client_ip: SERVER,
+                server_ip: CLIENT,
+                server_port: 40000,
+            })
+        );
+
+        let extended_active =
+            parse_ftp_expectation(CLIENT, 40000, SERVER, 21, b"EPRT |1|10.0.0.1|40001|\r\n");
+        assert_eq!(extended_active.unwrap().server_port, 40001);
+
+        assert_eq!(
+            parse_ftp_expectation(CLIENT, 40000, SERVER, 21, b"USER anonymous\r\n"),
+            None
+        );
+        assert_eq!(
+            parse_ftp_expectation(CLIENT, 40000, SERVER, 80, b"PORT 10,0,0,1,156,64\r\n"),
+            None
+        );
+    }
+}
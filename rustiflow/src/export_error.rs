@@ -0,0 +1,101 @@
+//! The errors of the export path, from the flow tables handing over their flows to the output
+//! writing them out.
+//!
+//! The flow tables can't return an error to anyone while they process packets, so they pass
+//! their errors to an `ExportErrorHandler`. The binary logs the errors and goes on, embedding
+//! code can install a handler that reacts to them, e.g. shuts down once the output stopped.
+
+use std::{fmt, io, sync::Arc};
+
+use log::error;
+
+use crate::metrics;
+
+/// Why a flow couldn't be exported.
+#[derive(Debug)]
+pub enum ExportError {
+    /// The export channel is closed, the output doesn't take any flows anymore.
+    ChannelClosed,
+    /// The output failed to write the flow out.
+    Write(io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::ChannelClosed => write!(f, "the export channel is closed"),
+            ExportError::Write(e) => write!(f, "the output failed to write it: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::ChannelClosed => None,
+            ExportError::Write(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> Self {
+        ExportError::Write(error)
+    }
+}
+
+/// Called with every error of the export path, from the task it happened in.
+pub type ExportErrorHandler = Arc<dyn Fn(&ExportError) + Send + Sync>;
+
+/// The handler of the binary, it logs the error and the export goes on.
+pub fn log_export_error(error: &ExportError) {
+    error!("Failed to export a flow: {}", error);
+}
+
+/// Returns the handler that logs the errors, the default one.
+pub fn default_handler() -> ExportErrorHandler {
+    Arc::new(log_export_error)
+}
+
+/// Counts an error in the metrics and passes it to the handler.
+pub fn report(handler: &ExportErrorHandler, error: ExportError) {
+    metrics::record_export_error();
+    handler(&error);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        error::Error,
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{report, ExportError, ExportErrorHandler};
+    use crate::metrics;
+
+    #[test]
+    fn test_report() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let handler: ExportErrorHandler = {
+            let errors = errors.clone();
+            Arc::new(move |error: &ExportError| errors.lock().unwrap().push(error.to_string()))
+        };
+        let reported = metrics::export_errors();
+
+        report(&handler, ExportError::ChannelClosed);
+        let write_error = io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe");
+        let error = ExportError::from(write_error);
+        assert!(error.source().is_some());
+        report(&handler, error);
+
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec![
+                "the export channel is closed".to_string(),
+                "the output failed to write it: broken pipe".to_string()
+            ]
+        );
+        assert!(metrics::export_errors() >= reported + 2);
+    }
+}
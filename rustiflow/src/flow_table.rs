@@ -1,18 +1,49 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
 
-use crate::{packet_features::PacketFeatures, flows::flow::Flow};
+use crate::{
+    args::ExportOrder,
+    expectations,
+    export_error::{self, ExportError, ExportErrorHandler},
+    flows::{
+        basic_flow::{BasicFlow, EndReason},
+        feature_memory,
+        flow::Flow,
+        settings::FlowSettings,
+    },
+    metrics::{self, ShardMetrics},
+    packet_features::PacketFeatures,
+    reload::ReloadableConfig,
+};
 use chrono::{DateTime, TimeDelta, Utc};
-use log::{debug, error};
-use tokio::sync::mpsc;
+use log::debug;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    watch,
+};
 
 pub struct FlowTable<T> {
+    settings: Arc<FlowSettings>, // The settings of the run, passed on to the flows
     flow_map: HashMap<String, T>, // HashMap for fast flow access by key
+    flow_ages: VecDeque<(String, DateTime<Utc>)>, // Keys and starts of the flows, oldest first
     active_timeout: u64,
     idle_timeout: u64,
+    max_flow_duration: Option<u64>, // Lifetime after which any flow is exported, persistent or not
     early_export: Option<u64>,
+    export_window: Option<u64>, // Interval of the interim records of all active flows in seconds
+    split_on_syn: bool,
+    sequence_reset_threshold: Option<u32>,
+    export_order: ExportOrder, // The time the flows exported together are sorted by
     export_channel: mpsc::Sender<T>,
     next_check_time: Option<DateTime<Utc>>, // Track the next time we check for flow expirations
     expiration_check_interval: TimeDelta,   // Check for expired flows every x seconds
+    config_updates: Option<watch::Receiver<ReloadableConfig>>, // Reloaded timeouts, if reloading is enabled
+    reported_flows: usize, // Flow count last added to the active flows metric
+    shard_metrics: Arc<ShardMetrics>, // Packets and flows of this table for the shard report
+    error_handler: ExportErrorHandler, // Called with the flows that couldn't be exported
 }
 
 impl<T> FlowTable<T>
@@ -20,6 +51,7 @@ where
     T: Flow,
 {
     pub fn new(
+        settings: Arc<FlowSettings>,
         active_timeout: u64,
         idle_timeout: u64,
         early_export: Option<u64>,
@@ -27,32 +59,119 @@ where
         expiration_check_interval: u64,
     ) -> Self {
         Self {
-            flow_map: HashMap::new(),
+            // Reserved up front, a growing map rehashes all of its flows on every doubling
+            flow_map: HashMap::with_capacity(settings.flow_table_capacity.unwrap_or(0)),
+            flow_ages: VecDeque::new(),
             active_timeout,
             idle_timeout,
+            max_flow_duration: None,
             early_export,
+            export_window: settings.export_window,
+            split_on_syn: false,
+            sequence_reset_threshold: None,
+            export_order: ExportOrder::Start,
             export_channel,
             next_check_time: None,
             expiration_check_interval: TimeDelta::seconds(expiration_check_interval as i64),
+            config_updates: None,
+            reported_flows: 0,
+            shard_metrics: metrics::register_shard(),
+            error_handler: export_error::default_handler(),
+            settings,
         }
     }
 
+    /// Applies reloaded timeouts from the given channel to this flow table.
+    ///
+    /// Existing flows are evaluated against the new timeouts from the next packet on.
+    pub fn watch_config(&mut self, config_updates: watch::Receiver<ReloadableConfig>) {
+        self.config_updates = Some(config_updates);
+    }
+
+    /// Passes the flows that couldn't be exported to the given handler instead of logging them.
+    pub fn on_export_error(&mut self, error_handler: ExportErrorHandler) {
+        self.error_handler = error_handler;
+    }
+
+    /// Takes over the timeouts, the flow splitting settings and the export order of a
+    /// configuration.
+    pub fn apply_config(&mut self, config: &ReloadableConfig) {
+        self.active_timeout = config.active_timeout;
+        self.idle_timeout = config.idle_timeout;
+        self.max_flow_duration = config.max_flow_duration;
+        self.early_export = config.early_export;
+        self.split_on_syn = config.split_on_syn;
+        self.sequence_reset_threshold = config.sequence_reset_threshold;
+        self.export_order = config.export_order;
+        self.expiration_check_interval =
+            TimeDelta::seconds(config.expiration_check_interval as i64);
+    }
+
+    /// Takes over the reloaded timeouts if the configuration changed.
+    ///
+    /// The reloaded packet filters and persistent flow rules replace those of the settings of
+    /// the table, for the next packet and the existing flows. The flows keep the settings they
+    /// were created with, the rules are only looked up in those of the table.
+    fn apply_config_updates(&mut self) {
+        let Some(config_updates) = self.config_updates.as_mut() else {
+            return;
+        };
+        if !config_updates.has_changed().unwrap_or(false) {
+            return;
+        }
+
+        let config = config_updates.borrow_and_update().clone();
+        self.apply_config(&config);
+        if self.settings.packet_filters != config.packet_filters
+            || self.settings.persistent_flows != config.persistent_flows
+        {
+            self.settings = Arc::new(FlowSettings {
+                packet_filters: config.packet_filters.clone(),
+                persistent_flows: config.persistent_flows.clone(),
+                ..FlowSettings::clone(&self.settings)
+            });
+        }
+        // Evaluate the existing flows against the new timeouts right away
+        self.next_check_time = None;
+        debug!("Flow table timeouts updated: {:?}", config);
+    }
+
     /// Processes a packet (either IPv4 or IPv6) and updates the flow map.
     pub async fn process_packet(&mut self, packet: &PacketFeatures) {
+        self.shard_metrics.add_packets(1);
+        // Reloaded packet filters apply to this packet already
+        self.apply_config_updates();
+        if !self.settings.is_packet_accepted(packet) {
+            metrics::add_filtered_packets(1);
+            return;
+        }
+
         // Check if enough virtual time has passed to trigger flow expiration checks
         self.check_and_export_expired_flows(packet.timestamp).await;
 
         // Determine the flow direction and key
-        let flow_key = if self.flow_map.contains_key(&packet.flow_key_bwd()) {
-            packet.flow_key_bwd()
+        let strategy = self.settings.flow_key;
+        let flow_key = if self.flow_map.contains_key(&packet.flow_key_bwd(strategy)) {
+            packet.flow_key_bwd(strategy)
         } else {
-            packet.flow_key()
+            packet.flow_key(strategy)
         };
 
         // Update the flow if it exists, otherwise create a new flow
         if let Some(mut flow) = self.flow_map.remove(&flow_key) {
-            if flow.is_expired(packet.timestamp, self.active_timeout, self.idle_timeout) {
-                self.export_flow(flow).await;
+            let is_forward = *flow.flow_key() == packet.flow_key(strategy);
+            let end_reason = self.expiry_reason(&flow, packet.timestamp).or_else(|| {
+                flow.basic_flow()
+                    .is_reused_connection(
+                        packet,
+                        is_forward,
+                        self.split_on_syn,
+                        self.sequence_reset_threshold,
+                    )
+                    .then_some(EndReason::EndOfFlow)
+            });
+            if let Some(end_reason) = end_reason {
+                self.export_ended_flow(flow, end_reason).await;
                 self.create_and_insert_flow(packet).await;
             } else {
                 let is_terminated = self.update_flow_with_packet(&mut flow, packet).await;
@@ -63,12 +182,30 @@ where
         } else {
             self.create_and_insert_flow(packet).await;
         }
+
+        self.report_active_flows();
+    }
+
+    /// Publishes the change of the number of flows in this table to the metrics.
+    fn report_active_flows(&mut self) {
+        let flows = self.flow_map.len();
+        if flows != self.reported_flows {
+            metrics::add_active_flows(flows as i64 - self.reported_flows as i64);
+            self.shard_metrics.set_active_flows(flows as u64);
+            self.reported_flows = flows;
+        }
     }
 
     /// Create and insert a new flow for the given packet.
+    ///
+    /// Flows left out by the flow sampling are not created, so their packets are dropped.
     async fn create_and_insert_flow(&mut self, packet: &PacketFeatures) {
+        if !self.settings.is_flow_sampled(packet) {
+            return;
+        }
         let mut new_flow = T::new(
-            packet.flow_key(),
+            Arc::clone(&self.settings),
+            packet.flow_key(self.settings.flow_key),
             packet.source_ip,
             packet.source_port,
             packet.destination_ip,
@@ -76,52 +213,147 @@ where
             packet.protocol,
             packet.timestamp,
         );
+        if self.settings.track_expectations {
+            if let Some(session_id) = expectations::shared().lock().unwrap().take(packet) {
+                new_flow.set_session_id(session_id);
+            }
+        }
+        self.release_feature_memory();
         self.update_flow_with_packet(&mut new_flow, packet).await;
-        self.flow_map.insert(packet.flow_key(), new_flow);
+        if self.settings.feature_memory_limit.is_some() {
+            self.flow_ages.push_back((new_flow.flow_key().clone(), packet.timestamp));
+        }
+        self.flow_map.insert(packet.flow_key(self.settings.flow_key), new_flow);
+    }
+
+    /// Takes the optional features of the oldest flows of this table until those of a new
+    /// flow fit into the feature memory limit.
+    ///
+    /// The flows of the other shards are left alone, a new flow goes without the features if
+    /// they hold the budget.
+    fn release_feature_memory(&mut self) {
+        let Some(limit) = self.settings.feature_memory_limit else {
+            return;
+        };
+        let bytes = BasicFlow::feature_memory_bytes(&self.settings);
+        while bytes > 0 && !feature_memory::fits(bytes, Some(limit)) {
+            let Some((flow_key, first_timestamp)) = self.flow_ages.pop_front() else {
+                break;
+            };
+            // Ended flows are only dropped from the queue here, or when it outgrows the table
+            if let Some(flow) = self.flow_map.get_mut(&flow_key) {
+                if flow.get_first_timestamp() == first_timestamp {
+                    flow.basic_flow_mut().release_feature_memory();
+                }
+            }
+        }
+
+        if self.flow_ages.len() > 2 * self.flow_map.len() + 1024 {
+            let flow_map = &self.flow_map;
+            self.flow_ages.retain(|(flow_key, first_timestamp)| {
+                flow_map
+                    .get(flow_key)
+                    .is_some_and(|flow| flow.get_first_timestamp() == *first_timestamp)
+            });
+        }
     }
 
     /// Updates a flow with a packet and exports flow if terminated.
     ///
     /// Returns a boolean indicating if the flow is terminated.
     async fn update_flow_with_packet(&mut self, flow: &mut T, packet: &PacketFeatures) -> bool {
-        let is_forward = *flow.flow_key() == packet.flow_key();
+        let is_forward = *flow.flow_key() == packet.flow_key(self.settings.flow_key);
+        if self.settings.exclude_keepalives && flow.basic_flow().is_keepalive(packet, is_forward) {
+            // Only counted, so they don't distort the IAT, active/idle and length statistics
+            flow.basic_flow_mut().record_keepalive(packet, is_forward);
+            return false;
+        }
+        let previous_timestamp = flow.basic_flow().last_timestamp;
         let flow_terminated = flow.update_flow(&packet, is_forward);
 
+        if let Some(expected_flow) = packet.expected_flow {
+            // The control flow starts the session the announced flow will be linked to
+            flow.set_session_id(flow.flow_key().clone());
+            expectations::shared().lock().unwrap().insert(
+                expected_flow,
+                flow.flow_key().clone(),
+                packet.timestamp,
+            );
+        }
+
         if flow_terminated {
             // If terminated, export the flow
-            self.export_flow(flow.clone()).await;
+            self.export_ended_flow(flow.clone(), EndReason::EndOfFlow).await;
+        } else if let Some(rule) = self.settings.persistent_flow_rule(flow.basic_flow()) {
+            // Persistent flows are exported once every snapshot interval they have been running
+            let first_timestamp = flow.get_first_timestamp();
+            let interval = rule.snapshot_interval as i64;
+            let previous_snapshot = (previous_timestamp - first_timestamp).num_seconds() / interval;
+            let snapshot = (packet.timestamp - first_timestamp).num_seconds() / interval;
+            if snapshot > previous_snapshot {
+                self.export_flow(interim_record(flow, packet.timestamp)).await;
+            }
         } else if let Some(early_export) = self.early_export {
             // If flow duration is greater than early export, export the flow immediately (without deletion from the flow table)
             if (packet.timestamp - flow.get_first_timestamp()).num_seconds() as u64 > early_export {
-                self.export_flow(flow.clone()).await;
+                self.export_flow(interim_record(flow, packet.timestamp)).await;
             }
         }
         flow_terminated
     }
 
-    /// Export all flows in the flow map in order of first packet arrival.
+    /// Returns the time a flow is sorted by among the flows exported together.
+    fn export_order_time(&self, flow: &T) -> DateTime<Utc> {
+        match self.export_order {
+            ExportOrder::Start => flow.get_first_timestamp(),
+            ExportOrder::End => flow.basic_flow().last_timestamp,
+        }
+    }
+
+    /// Export all flows in the flow map in the export order, of their first or last packet.
     pub async fn export_all_flows(&mut self) {
         let mut flows_to_export: Vec<_> = self
             .flow_map
             .drain() // Drain all entries from the map
-            .map(|(_, flow)| flow) // Collect all flows
             .collect();
 
-        // Sort flows by `first_timestamp`
-        flows_to_export.sort_by_key(|flow| flow.get_first_timestamp());
+        // Flows at the same time are sorted by key so the order doesn't depend on the hash map
+        flows_to_export.sort_by(|(key, flow), (other_key, other_flow)| {
+            (self.export_order_time(flow), key)
+                .cmp(&(self.export_order_time(other_flow), other_key))
+        });
+
+        self.report_active_flows();
 
-        // Export each flow in order of `first_timestamp`
-        for flow in flows_to_export {
-            self.export_flow(flow).await;
+        for (_, flow) in flows_to_export {
+            self.export_ended_flow(flow, EndReason::ForcedEnd).await;
         }
     }
 
-    /// Exports a single flow.
-    pub async fn export_flow(&self, flow: T) {
-        if self.export_channel.is_closed() {
-            error!("Failed to send flow: export channel is closed");
-        } else if let Err(e) = self.export_channel.send(flow).await {
-            error!("Failed to send flow: {}", e);
+    /// Exports a flow that leaves the flow table, with the reason it ended.
+    async fn export_ended_flow(&self, mut flow: T, end_reason: EndReason) {
+        flow.basic_flow_mut().end_reason = Some(end_reason);
+        self.export_flow(flow).await;
+    }
+
+    /// Exports a single flow, a flow that can't be exported goes to the error handler.
+    pub async fn export_flow(&self, mut flow: T) {
+        // Taken from the clock, the packet timestamps of a replayed capture lie in the past
+        flow.basic_flow_mut().export_time = Some(Utc::now());
+        match self.export_channel.try_send(flow) {
+            Ok(()) => metrics::record_exported_flow(),
+            Err(TrySendError::Full(flow)) => {
+                // The sink can't keep up, measure how long packet processing stalls
+                let start = Instant::now();
+                match self.export_channel.send(flow).await {
+                    Ok(()) => metrics::record_exported_flow(),
+                    Err(_) => export_error::report(&self.error_handler, ExportError::ChannelClosed),
+                }
+                metrics::record_blocked_export(start.elapsed());
+            }
+            Err(TrySendError::Closed(_)) => {
+                export_error::report(&self.error_handler, ExportError::ChannelClosed);
+            }
         }
     }
 
@@ -132,7 +364,14 @@ where
             .map_or(true, |next_check| current_time >= next_check)
         {
             self.export_expired_flows(current_time).await;
-            self.next_check_time = Some(current_time + self.expiration_check_interval);
+            // Check at least once per export window, so the interim records are on time
+            let interval = match self.export_window {
+                Some(window) => self
+                    .expiration_check_interval
+                    .min(TimeDelta::seconds(window as i64)),
+                None => self.expiration_check_interval,
+            };
+            self.next_check_time = Some(current_time + interval);
             debug!(
                 "Next flow expiration check scheduled at: {:?}",
                 self.next_check_time
@@ -140,26 +379,664 @@ where
         }
     }
 
+    /// Checks whether a flow expired and returns the timeout that expired it.
+    ///
+    /// Flows matching a persistent flow rule are not subject to the active timeout, the maximum
+    /// flow duration applies to all flows.
+    fn expiry_reason(&self, flow: &T, timestamp: DateTime<Utc>) -> Option<EndReason> {
+        let duration = (timestamp - flow.get_first_timestamp()).num_seconds() as u64;
+        if self
+            .max_flow_duration
+            .is_some_and(|max_flow_duration| duration > max_flow_duration)
+        {
+            return Some(EndReason::MaxDuration);
+        }
+
+        let active_timeout = if self.settings.persistent_flow_rule(flow.basic_flow()).is_some() {
+            u64::MAX
+        } else {
+            self.active_timeout
+        };
+        if !flow.is_expired(timestamp, active_timeout, self.idle_timeout) {
+            None
+        } else if duration > active_timeout {
+            Some(EndReason::ActiveTimeout)
+        } else {
+            Some(EndReason::IdleTimeout)
+        }
+    }
+
     /// Export all expired flows.
     pub async fn export_expired_flows(&mut self, timestamp: DateTime<Utc>) {
-        // Export all expired flows
-        let expired_flows: Vec<_> = self
+        // Export all expired flows, in the order of `export_all_flows`
+        let mut expired_flows: Vec<_> = self
             .flow_map
             .iter()
             .filter_map(|(key, flow)| {
-                if flow.is_expired(timestamp, self.active_timeout, self.idle_timeout) {
-                    Some(key.clone())
-                } else {
-                    None
-                }
+                self.expiry_reason(flow, timestamp)
+                    .map(|end_reason| (self.export_order_time(flow), key.clone(), end_reason))
             })
             .collect();
+        expired_flows.sort_by(|(time, key, _), (other_time, other_key, _)| {
+            (time, key).cmp(&(other_time, other_key))
+        });
+
+        if self.settings.track_expectations {
+            expectations::shared().lock().unwrap().expire(timestamp);
+        }
 
         debug!("Exporting {} expired flows", expired_flows.len());
-        for key in expired_flows {
+        for (_, key, end_reason) in expired_flows {
             if let Some(flow) = self.flow_map.remove(&key) {
-                self.export_flow(flow).await;
+                self.export_ended_flow(flow, end_reason).await;
+            }
+        }
+
+        if let Some(window) = self.export_window {
+            self.export_windows(timestamp, window).await;
+        }
+    }
+
+    /// Exports an interim record of every active flow whose export window elapsed and starts
+    /// its next window.
+    async fn export_windows(&mut self, timestamp: DateTime<Utc>, window: u64) {
+        let window = TimeDelta::seconds(window as i64);
+        let mut records = Vec::new();
+        for flow in self.flow_map.values_mut() {
+            if timestamp - flow.basic_flow().window_start >= window {
+                records.push(interim_record(flow, timestamp));
+                flow.basic_flow_mut().start_window(timestamp);
+            }
+        }
+        records.sort_by(|record, other| {
+            let key = (self.export_order_time(record), &record.basic_flow().flow_key);
+            key.cmp(&(self.export_order_time(other), &other.basic_flow().flow_key))
+        });
+
+        debug!("Exporting {} interim records", records.len());
+        for record in records {
+            self.export_flow(record).await;
+        }
+    }
+}
+
+/// Copies a flow that stays active into an interim record taken at the given time.
+pub(crate) fn interim_record<T: Flow>(flow: &T, timestamp: DateTime<Utc>) -> T {
+    let mut record = flow.clone();
+    record.basic_flow_mut().snapshot_time = Some(timestamp);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+    use tokio::sync::{mpsc, watch};
+
+    use pnet::packet::ipv4::Ipv4Packet;
+
+    use super::FlowTable;
+    use crate::{
+        args::{
+            ExportConfig, ExportOrder, FilterAction, OutputConfig, PacketFilterRule, PortRange,
+            PersistentFlowRule,
+        },
+        export_error::ExportError,
+        flows::{
+            basic_flow::{BasicFlow, EndReason},
+            cic_flow::CicFlow,
+            flow::Flow,
+            registry::{self, Value},
+            settings::FlowSettings,
+        },
+        packet_features::PacketFeatures,
+        reload::ReloadableConfig,
+    };
+
+    /// A virtual clock for the packet timestamps.
+    ///
+    /// Expiry is driven by packet timestamps only, so advancing this clock between packets
+    /// controls exactly when flows are checked and exported.
+    struct TestClock {
+        now: DateTime<Utc>,
+    }
+
+    impl TestClock {
+        fn new() -> Self {
+            TestClock {
+                now: DateTime::from_timestamp(0, 0).unwrap(),
             }
         }
+
+        fn advance(&mut self, seconds: i64) {
+            self.now += TimeDelta::seconds(seconds);
+        }
+
+        /// Crafts a UDP packet from 10.0.0.1:`source_port` to 10.0.0.2:53 at the current time.
+        fn packet(&self, source_port: u16) -> PacketFeatures {
+            PacketFeatures {
+                source_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                destination_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+                source_port,
+                destination_port: 53,
+                protocol: 17,
+                timestamp: self.now,
+                fin_flag: 0,
+                syn_flag: 0,
+                rst_flag: 0,
+                psh_flag: 0,
+                ack_flag: 0,
+                urg_flag: 0,
+                cwe_flag: 0,
+                ece_flag: 0,
+                data_length: 32,
+                header_length: 8,
+                length: 60,
+                window_size: 0,
+                sequence_number: 0,
+                sequence_number_ack: 0,
+                extension_header_count: 0,
+                ttl: 64,
+                ip_options_length: 0,
+                ip_flags: 0,
+                tcp_options_mask: 0,
+                flow_label: 0,
+                expected_flow: None,
+                vlan_id: None,
+                payload_snapshot: None,
+                capture_latency: None,
+            }
+        }
+    }
+
+    fn setup_flow_table(
+        active_timeout: u64,
+        idle_timeout: u64,
+        expiration_check_interval: u64,
+    ) -> (FlowTable<BasicFlow>, mpsc::Receiver<BasicFlow>) {
+        setup_flow_table_with_settings(
+            FlowSettings::default(),
+            active_timeout,
+            idle_timeout,
+            expiration_check_interval,
+        )
+    }
+
+    fn setup_flow_table_with_settings(
+        settings: FlowSettings,
+        active_timeout: u64,
+        idle_timeout: u64,
+        expiration_check_interval: u64,
+    ) -> (FlowTable<BasicFlow>, mpsc::Receiver<BasicFlow>) {
+        let (sender, receiver) = mpsc::channel(100);
+        let flow_table = FlowTable::new(
+            Arc::new(settings),
+            active_timeout,
+            idle_timeout,
+            None,
+            sender,
+            expiration_check_interval,
+        );
+        (flow_table, receiver)
+    }
+
+    /// Collects the flows exported so far.
+    fn exported_flows(receiver: &mut mpsc::Receiver<BasicFlow>) -> Vec<BasicFlow> {
+        let mut flows = Vec::new();
+        while let Ok(flow) = receiver.try_recv() {
+            flows.push(flow);
+        }
+        flows
+    }
+
+    #[tokio::test]
+    async fn test_idle_flow_exported_at_next_check() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 10, 5);
+
+        // The first packet runs a check at t=0 and schedules the next one at t=5
+        flow_table.process_packet(&clock.packet(1000)).await;
+
+        // Flow 1000 is idle for 10 seconds at t=10, which doesn't exceed the timeout yet
+        clock.advance(10);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        assert!(exported_flows(&mut receiver).is_empty());
+
+        // The check at t=15 is the first with flow 1000 idle for longer than 10 seconds
+        clock.advance(5);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].port_source, 1000);
+        assert_eq!(flows[0].get_packet_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_export_between_checks() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 20, 60);
+
+        flow_table.process_packet(&clock.packet(1000)).await;
+
+        // Flow 1000 is idle long enough at t=30, but the next check is only due at t=60
+        clock.advance(30);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        clock.advance(15);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        assert!(exported_flows(&mut receiver).is_empty());
+
+        // Flow 2000 was last seen at t=45, so only flow 1000 is idle at the check at t=60
+        clock.advance(15);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].port_source, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_packet_on_expired_flow_starts_new_flow() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 10, 3600);
+
+        flow_table.process_packet(&clock.packet(1000)).await;
+        clock.advance(1);
+        flow_table.process_packet(&clock.packet(1000)).await;
+
+        // No check is due, the packet itself finds the flow expired
+        clock.advance(20);
+        flow_table.process_packet(&clock.packet(1000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 2);
+
+        flow_table.export_all_flows().await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 1);
+        assert_eq!(flows[0].get_first_timestamp(), clock.now);
+    }
+
+    #[tokio::test]
+    async fn test_active_timeout() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(30, 10, 3600);
+
+        // Packets every 5 seconds keep the flow from going idle
+        for _ in 0..7 {
+            flow_table.process_packet(&clock.packet(1000)).await;
+            clock.advance(5);
+        }
+        assert!(exported_flows(&mut receiver).is_empty());
+
+        // At t=35 the flow lasted longer than the active timeout
+        flow_table.process_packet(&clock.packet(1000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 7);
+        assert_eq!(flows[0].end_reason, Some(EndReason::ActiveTimeout));
+    }
+
+    #[tokio::test]
+    async fn test_max_flow_duration() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 10, 5);
+        flow_table.max_flow_duration = Some(20);
+
+        // The flow never goes idle, but is cut off once it lasted longer than 20 seconds
+        for _ in 0..5 {
+            flow_table.process_packet(&clock.packet(1000)).await;
+            clock.advance(5);
+        }
+        assert!(exported_flows(&mut receiver).is_empty());
+        clock.advance(1);
+        flow_table.process_packet(&clock.packet(2000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 5);
+        assert_eq!(flows[0].end_reason, Some(EndReason::MaxDuration));
+
+        flow_table.export_all_flows().await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].end_reason, Some(EndReason::ForcedEnd));
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_config() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 3600, 5);
+        let config = ReloadableConfig::new(&ExportConfig::default(), &OutputConfig::default());
+        let (sender, config_updates) = watch::channel(config.clone());
+        flow_table.watch_config(config_updates);
+        flow_table.process_packet(&clock.packet(40000)).await;
+        flow_table.process_packet(&clock.packet(40001)).await;
+
+        // A shorter idle timeout, a filter dropping a port and a persistent flow
+        sender.send_replace(ReloadableConfig {
+            idle_timeout: 10,
+            expiration_check_interval: 5,
+            packet_filters: vec![PacketFilterRule {
+                action: FilterAction::Exclude,
+                source_ip: None,
+                source_port: Some(PortRange {
+                    start: 40002,
+                    end: 40002,
+                }),
+                destination_ip: None,
+                destination_port: None,
+                protocol: None,
+            }],
+            persistent_flows: vec![PersistentFlowRule {
+                source_ip: None,
+                source_port: Some(40001),
+                destination_ip: None,
+                destination_port: None,
+                protocol: None,
+                snapshot_interval: 5,
+            }],
+            ..config
+        });
+        clock.advance(8);
+        // The existing persistent flow is exported as a snapshot on its next packet
+        flow_table.process_packet(&clock.packet(40001)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].port_source, 40001);
+
+        // The filtered packet doesn't create a flow, the next one expires the idle flows
+        clock.advance(12);
+        flow_table.process_packet(&clock.packet(40002)).await;
+        assert!(exported_flows(&mut receiver).is_empty());
+        flow_table.process_packet(&clock.packet(40003)).await;
+        let flows = exported_flows(&mut receiver);
+        let ports: Vec<u16> = flows.iter().map(|flow| flow.port_source).collect();
+        assert_eq!(ports, [40000, 40001]);
+        assert!(flows.iter().all(|flow| flow.end_reason == Some(EndReason::IdleTimeout)));
+        flow_table.export_all_flows().await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].port_source, 40003);
+    }
+
+    #[tokio::test]
+    async fn test_export_error_handler() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, receiver) = setup_flow_table(3600, 120, 3600);
+        let errors = Arc::new(AtomicUsize::new(0));
+        let handler_errors = errors.clone();
+        flow_table.on_export_error(Arc::new(move |error: &ExportError| {
+            assert!(matches!(error, ExportError::ChannelClosed));
+            handler_errors.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        // The output stopped, the flows can't be handed over anymore
+        drop(receiver);
+        for source_port in [1000, 2000] {
+            flow_table.process_packet(&clock.packet(source_port)).await;
+            clock.advance(1);
+        }
+        flow_table.export_all_flows().await;
+        assert_eq!(errors.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_all_flows_in_arrival_order() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 120, 3600);
+
+        for source_port in [3000, 1000, 2000] {
+            flow_table.process_packet(&clock.packet(source_port)).await;
+            clock.advance(1);
+        }
+
+        flow_table.export_all_flows().await;
+        let ports: Vec<_> = exported_flows(&mut receiver)
+            .iter()
+            .map(|flow| flow.port_source)
+            .collect();
+        assert_eq!(ports, vec![3000, 1000, 2000]);
+    }
+
+    #[tokio::test]
+    async fn test_export_order_by_end() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 120, 3600);
+        flow_table.export_order = ExportOrder::End;
+
+        for source_port in [3000, 1000, 2000, 3000] {
+            flow_table.process_packet(&clock.packet(source_port)).await;
+            clock.advance(1);
+        }
+
+        // The flow that started first saw the last packet
+        flow_table.export_all_flows().await;
+        let ports: Vec<_> = exported_flows(&mut receiver)
+            .iter()
+            .map(|flow| flow.port_source)
+            .collect();
+        assert_eq!(ports, vec![1000, 2000, 3000]);
+    }
+
+    #[tokio::test]
+    async fn test_reused_connection_starts_new_flow() {
+        let clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 120, 3600);
+        flow_table.split_on_syn = true;
+        flow_table.sequence_reset_threshold = Some(1_000_000);
+
+        let tcp_packet = |syn: bool, sequence: u32| {
+            let mut packet = clock.packet(1000);
+            packet.protocol = 6;
+            packet.syn_flag = u8::from(syn);
+            packet.ack_flag = u8::from(!syn);
+            packet.sequence_number = sequence;
+            packet
+        };
+
+        // A retransmitted SYN belongs to the same handshake
+        flow_table.process_packet(&tcp_packet(true, 1000)).await;
+        flow_table.process_packet(&tcp_packet(true, 1000)).await;
+        flow_table.process_packet(&tcp_packet(false, 1001)).await;
+        // A small step back is a retransmission
+        flow_table.process_packet(&tcp_packet(false, 900)).await;
+        assert!(exported_flows(&mut receiver).is_empty());
+
+        // A SYN after the handshake is a new connection
+        flow_table.process_packet(&tcp_packet(true, 5000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 4);
+
+        // So is a jump far back, here across the wraparound
+        flow_table.process_packet(&tcp_packet(false, 5001)).await;
+        flow_table
+            .process_packet(&tcp_packet(false, 5001u32.wrapping_sub(2_000_000)))
+            .await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_window_interim_records() {
+        let mut clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 120, 60);
+        flow_table.export_window = Some(10);
+
+        // The check at t=0 schedules the next one after the window, not the check interval
+        flow_table.process_packet(&clock.packet(1000)).await;
+        clock.advance(5);
+        flow_table.process_packet(&clock.packet(1000)).await;
+        assert!(exported_flows(&mut receiver).is_empty());
+
+        // The check at t=10 runs before the packet is counted
+        clock.advance(5);
+        flow_table.process_packet(&clock.packet(1000)).await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].snapshot_time, Some(clock.now));
+        assert_eq!(
+            registry::dump_features(&BasicFlow::window_features(), &flows[0]),
+            "interim,1970-01-01 00:00:00 UTC,1970-01-01 00:00:10 UTC,2,0,64,0"
+        );
+
+        // The final record holds the packets since the interim record
+        clock.advance(2);
+        flow_table.process_packet(&clock.packet(1000)).await;
+        flow_table.export_all_flows().await;
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].get_packet_count(), 4);
+        assert_eq!(
+            registry::dump_features(&BasicFlow::window_features(), &flows[0]),
+            "final,1970-01-01 00:00:10 UTC,1970-01-01 00:00:12 UTC,2,0,64,0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dccp_connections_are_separate_flows() {
+        let clock = TestClock::new();
+        let (mut flow_table, mut receiver) = setup_flow_table(3600, 120, 3600);
+
+        // An IPv4 DCCP-Data packet from 10.0.0.1:`source_port` to 10.0.0.2:5001 with 8 bytes
+        let dccp_packet = |source_port: u16| {
+            let mut packet = vec![
+                0x45, 0, 0, 44, 0, 0, 0, 0, 64, 33, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+            ];
+            packet.extend_from_slice(&source_port.to_be_bytes());
+            packet.extend_from_slice(&5001u16.to_be_bytes());
+            // Data offset of 4 words, long sequence numbers (X = 1), DCCP-Data
+            packet.extend_from_slice(&[4, 0, 0, 0, 0x05, 0, 0, 0, 0, 0, 0, 1]);
+            packet.extend_from_slice(&[0; 8]);
+            let packet = Ipv4Packet::new(&packet).unwrap();
+            PacketFeatures::from_ipv4_packet(&packet, clock.now, &FlowSettings::default()).unwrap()
+        };
+
+        for source_port in [40000, 40001, 40000] {
+            flow_table.process_packet(&dccp_packet(source_port)).await;
+        }
+        flow_table.export_all_flows().await;
+
+        let flows = exported_flows(&mut receiver);
+        assert_eq!(flows.len(), 2);
+        let mut ports: Vec<_> = flows
+            .iter()
+            .map(|flow| (flow.port_source, flow.port_destination, flow.get_packet_count()))
+            .collect();
+        ports.sort();
+        assert_eq!(ports, vec![(40000, 5001, 2), (40001, 5001, 1)]);
+        assert_eq!(flows[0].protocol, 33);
+        assert_eq!(flows[0].fwd_payload_bytes + flows[1].fwd_payload_bytes, 24);
+    }
+
+    #[tokio::test]
+    async fn test_exclude_keepalives() {
+        // A TCP segment of 32 bytes from 10.0.0.1:40000 at the current time
+        let segment = |clock: &TestClock, sequence_number, data_length| PacketFeatures {
+            protocol: 6,
+            ack_flag: 1,
+            sequence_number,
+            data_length,
+            length: 28 + data_length,
+            ..clock.packet(40000)
+        };
+
+        for exclude_keepalives in [false, true] {
+            let mut clock = TestClock::new();
+            let settings = FlowSettings {
+                exclude_keepalives,
+                ..FlowSettings::default()
+            };
+            let (mut flow_table, mut receiver) =
+                setup_flow_table_with_settings(settings, 3600, 120, 3600);
+            flow_table.process_packet(&segment(&clock, 1000, 32)).await;
+            // A keepalive repeats the last byte of the segment without any data, 60 s later
+            clock.advance(60);
+            flow_table.process_packet(&segment(&clock, 1031, 0)).await;
+            flow_table.export_all_flows().await;
+
+            let flows = exported_flows(&mut receiver);
+            assert_eq!(flows.len(), 1);
+            assert_eq!(flows[0].keepalive_packets(), 1);
+            // Excluded, the keepalive is only counted, but still keeps the flow active
+            let packets = if exclude_keepalives { 1 } else { 2 };
+            assert_eq!(flows[0].get_packet_count(), packets);
+            assert_eq!(flows[0].last_timestamp, clock.now);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exclude_keepalives_flow_iat() {
+        let segment = |clock: &TestClock, sequence_number, data_length| PacketFeatures {
+            protocol: 6,
+            ack_flag: 1,
+            sequence_number,
+            data_length,
+            length: 28 + data_length,
+            ..clock.packet(40000)
+        };
+        let flow_iat_max = CicFlow::features()
+            .iter()
+            .find(|feature| feature.info.name == "FLOW_IAT_MAX")
+            .unwrap();
+
+        for (exclude_keepalives, seconds) in [(false, 60.0), (true, 61.0)] {
+            let mut clock = TestClock::new();
+            let settings = FlowSettings {
+                exclude_keepalives,
+                ..FlowSettings::default()
+            };
+            let (sender, mut receiver) = mpsc::channel(100);
+            let mut flow_table =
+                FlowTable::<CicFlow>::new(Arc::new(settings), 3600, 120, None, sender, 3600);
+            flow_table.process_packet(&segment(&clock, 1000, 32)).await;
+            clock.advance(60);
+            flow_table.process_packet(&segment(&clock, 1031, 0)).await;
+            clock.advance(1);
+            flow_table.process_packet(&segment(&clock, 1032, 10)).await;
+            flow_table.export_all_flows().await;
+
+            // An excluded keepalive doesn't split the inter-arrival time it falls into
+            let flow = receiver.try_recv().unwrap();
+            let value = flow_iat_max.value(&flow, flow.basic_flow().settings.disabled_features);
+            assert_eq!(value, Value::Double(seconds * 1_000_000.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_feature_memory_taken_from_oldest_flow() {
+        let mut clock = TestClock::new();
+        // Five sequences of 200 KiB fit into the limit of 1 MiB, with a margin for other tests
+        let settings = FlowSettings {
+            packet_sequence_length: Some(51200),
+            feature_memory_limit: Some(1),
+            ..FlowSettings::default()
+        };
+        let (mut flow_table, _receiver) = setup_flow_table_with_settings(settings, 3600, 120, 3600);
+        for source_port in 1000..1006 {
+            flow_table.process_packet(&clock.packet(source_port)).await;
+            clock.advance(1);
+        }
+        // The oldest flow loses its sequence to the new flow and doesn't get it back
+        flow_table.process_packet(&clock.packet(1000)).await;
+
+        let sequence_lengths: Vec<_> = (1000..1006)
+            .map(|source_port| {
+                let flow_key = clock.packet(source_port).flow_key(flow_table.settings.flow_key);
+                let flow = &flow_table.flow_map[&flow_key];
+                (flow.packet_sequence.len(), flow.feature_memory.is_truncated())
+            })
+            .collect();
+        assert_eq!(
+            sequence_lengths,
+            [(0, true), (1, false), (1, false), (1, false), (1, false), (1, false)]
+        );
     }
 }
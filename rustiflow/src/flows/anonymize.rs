@@ -0,0 +1,225 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::args::Anonymization;
+
+/// Pseudonymizes IP addresses consistently for one key.
+///
+/// The same address always maps to the same pseudonym with the same key, while different
+/// keys give unrelated pseudonyms.
+#[derive(Clone)]
+pub enum IpAnonymizer {
+    /// Crypto-PAn, addresses sharing a prefix map to pseudonyms sharing a prefix of the same length.
+    PrefixPreserving { cipher: Box<Aes128>, pad: u128 },
+    /// A keyed hash (HMAC-SHA256) of the address, truncated to the address length.
+    Hash { key: [u8; 32] },
+}
+
+impl fmt::Debug for IpAnonymizer {
+    // Never print the key material
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAnonymizer::PrefixPreserving { .. } => f.write_str("PrefixPreserving"),
+            IpAnonymizer::Hash { .. } => f.write_str("Hash"),
+        }
+    }
+}
+
+impl IpAnonymizer {
+    /// Creates an anonymizer from a key of any length.
+    ///
+    /// The key is stretched to the 32 bytes of key material with SHA-256.
+    ///
+    /// ### Arguments
+    ///
+    /// * `method` - The anonymization method.
+    /// * `key` - The secret key, e.g. a passphrase.
+    pub fn new(method: Anonymization, key: &str) -> Self {
+        Self::from_key_bytes(method, Sha256::digest(key.as_bytes()).into())
+    }
+
+    /// Creates an anonymizer from 32 bytes of key material.
+    ///
+    /// For Crypto-PAn, the first 16 bytes are the AES key and the last 16 bytes the pad.
+    pub fn from_key_bytes(method: Anonymization, key: [u8; 32]) -> Self {
+        match method {
+            Anonymization::PrefixPreserving => {
+                let cipher = Aes128::new(GenericArray::from_slice(&key[..16]));
+                let mut pad = GenericArray::clone_from_slice(&key[16..]);
+                cipher.encrypt_block(&mut pad);
+                IpAnonymizer::PrefixPreserving {
+                    cipher: Box::new(cipher),
+                    pad: u128::from_be_bytes(pad.into()),
+                }
+            }
+            Anonymization::Hash => IpAnonymizer::Hash { key },
+        }
+    }
+
+    /// Returns the pseudonym of an IP address, of the same address family.
+    pub fn anonymize(&self, ip: IpAddr) -> IpAddr {
+        match (self, ip) {
+            (IpAnonymizer::PrefixPreserving { cipher, pad }, IpAddr::V4(ip)) => {
+                let address = prefix_preserving(cipher, *pad, u128::from(u32::from(ip)), 32);
+                IpAddr::V4(Ipv4Addr::from(address as u32))
+            }
+            (IpAnonymizer::PrefixPreserving { cipher, pad }, IpAddr::V6(ip)) => {
+                IpAddr::V6(Ipv6Addr::from(prefix_preserving(cipher, *pad, u128::from(ip), 128)))
+            }
+            (IpAnonymizer::Hash { key }, IpAddr::V4(ip)) => {
+                let digest = keyed_hash(key, &ip.octets());
+                IpAddr::V4(Ipv4Addr::new(digest[0], digest[1], digest[2], digest[3]))
+            }
+            (IpAnonymizer::Hash { key }, IpAddr::V6(ip)) => {
+                let digest = keyed_hash(key, &ip.octets());
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&digest[..16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        }
+    }
+
+    /// Replaces the IP addresses of a flow key (`ip:port-ip:port-protocol`) with their pseudonyms.
+    ///
+    /// Parts that are not an endpoint are kept as they are. Without ports, the endpoints are
+    /// plain IP addresses (an IPv6 address can't be told apart from an `ip:port` one).
+    pub fn anonymize_flow_key(&self, flow_key: &str, has_ports: bool) -> String {
+        flow_key
+            .split('-')
+            .map(|part| {
+                if !has_ports {
+                    return match part.parse::<IpAddr>() {
+                        Ok(ip) => self.anonymize(ip).to_string(),
+                        Err(_) => part.to_string(),
+                    };
+                }
+                // The port follows the last colon, IPv6 addresses contain colons themselves
+                match part
+                    .rsplit_once(':')
+                    .and_then(|(ip, port)| Some((ip.parse::<IpAddr>().ok()?, port)))
+                {
+                    Some((ip, port)) => format!("{}:{}", self.anonymize(ip), port),
+                    None => part.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// Crypto-PAn of the lowest `bits` bits of `address`.
+///
+/// Bit `i` of the pseudonym is bit `i` of the address flipped by the first bit of the
+/// encryption of the address's first `i` bits, padded with the pad.
+fn prefix_preserving(cipher: &Aes128, pad: u128, address: u128, bits: u32) -> u128 {
+    let address = address << (128 - bits);
+    let mut flips = 0;
+    for position in 0..bits {
+        let prefix_mask = u128::MAX.checked_shl(128 - position).unwrap_or(0);
+        let mut block =
+            GenericArray::from(((address & prefix_mask) | (pad & !prefix_mask)).to_be_bytes());
+        cipher.encrypt_block(&mut block);
+        flips |= u128::from(block[0] >> 7) << (127 - position);
+    }
+    (address ^ flips) >> (128 - bits)
+}
+
+fn keyed_hash(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::IpAnonymizer;
+    use crate::args::Anonymization;
+
+    // Key of the Crypto-PAn reference implementation's sample
+    const CRYPTOPAN_KEY: [u8; 32] = [
+        21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152, 143, 131,
+        121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+    ];
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn test_prefix_preserving_reference_values() {
+        let anonymizer =
+            IpAnonymizer::from_key_bytes(Anonymization::PrefixPreserving, CRYPTOPAN_KEY);
+
+        for (original, anonymized) in [
+            ("128.11.68.132", "135.242.180.132"),
+            ("129.118.74.4", "134.136.186.123"),
+            ("130.132.252.244", "133.68.164.234"),
+            ("141.223.7.43", "141.167.8.160"),
+            ("141.233.145.108", "141.129.237.235"),
+            ("152.163.225.39", "151.140.114.167"),
+        ] {
+            assert_eq!(anonymizer.anonymize(ip(original)), ip(anonymized));
+        }
+    }
+
+    #[test]
+    fn test_prefix_preserving_ipv6() {
+        let anonymizer = IpAnonymizer::new(Anonymization::PrefixPreserving, "secret");
+
+        let IpAddr::V6(first) = anonymizer.anonymize(ip("2001:db8:1::1")) else {
+            panic!("IPv6 address anonymized to IPv4");
+        };
+        let IpAddr::V6(second) = anonymizer.anonymize(ip("2001:db8:1::2")) else {
+            panic!("IPv6 address anonymized to IPv4");
+        };
+        // Both share the first 126 bits
+        assert_eq!(u128::from(first) >> 2, u128::from(second) >> 2);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_hash_anonymization() {
+        let anonymizer = IpAnonymizer::new(Anonymization::Hash, "secret");
+        let other_key = IpAnonymizer::new(Anonymization::Hash, "other secret");
+
+        let pseudonym = anonymizer.anonymize(ip("10.0.0.1"));
+        assert!(pseudonym.is_ipv4());
+        assert_ne!(pseudonym, ip("10.0.0.1"));
+        assert_eq!(anonymizer.anonymize(ip("10.0.0.1")), pseudonym);
+        assert_ne!(other_key.anonymize(ip("10.0.0.1")), pseudonym);
+        assert!(anonymizer.anonymize(ip("::1")).is_ipv6());
+    }
+
+    #[test]
+    fn test_anonymize_flow_key() {
+        let anonymizer = IpAnonymizer::new(Anonymization::Hash, "secret");
+
+        let flow_key = anonymizer.anonymize_flow_key("10.0.0.1:443-2001:db8::2:51000-6", true);
+        let expected = format!(
+            "{}:443-{}:51000-6",
+            anonymizer.anonymize(ip("10.0.0.1")),
+            anonymizer.anonymize(ip("2001:db8::2"))
+        );
+        assert_eq!(flow_key, expected);
+
+        let flow_key = anonymizer.anonymize_flow_key("10.0.0.1-2001:db8::2:1-6", false);
+        let expected = format!(
+            "{}-{}-6",
+            anonymizer.anonymize(ip("10.0.0.1")),
+            anonymizer.anonymize(ip("2001:db8::2:1"))
+        );
+        assert_eq!(flow_key, expected);
+    }
+}
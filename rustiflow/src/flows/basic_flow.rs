@@ -1,10 +1,25 @@
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
+use common::parse::{ICMP, ICMPV6};
 
-use crate::packet_features::PacketFeatures;
+use crate::{
+    args::{ByteCount, TimestampFormat},
+    packet_features::PacketFeatures,
+};
 
-use super::flow::Flow;
+use super::{
+    feature_memory::FeatureMemory,
+    flow::Flow,
+    registry::{self, Feature, FeatureType, Unit, Value},
+    settings::FlowSettings,
+    signatures::PayloadSnapshot,
+    util::port_category,
+};
 
 #[derive(Clone, PartialEq)]
 enum FlowState {
@@ -13,9 +28,39 @@ enum FlowState {
     FinAcked,
 }
 
+/// Why a flow left the flow table, after the flow end reasons of IPFIX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndReason {
+    /// No packet within the idle timeout.
+    IdleTimeout,
+    /// The flow lasted longer than the active timeout.
+    ActiveTimeout,
+    /// The flow lasted longer than the maximum flow duration.
+    MaxDuration,
+    /// The connection was closed, or a new connection reused its 5-tuple.
+    EndOfFlow,
+    /// The flow was still active when the capture ended.
+    ForcedEnd,
+}
+
+impl std::fmt::Display for EndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            EndReason::IdleTimeout => "idle-timeout",
+            EndReason::ActiveTimeout => "active-timeout",
+            EndReason::MaxDuration => "max-duration",
+            EndReason::EndOfFlow => "end-of-flow",
+            EndReason::ForcedEnd => "forced-end",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
 /// A basic flow that stores the basic features of a flow.
 #[derive(Clone)]
 pub struct BasicFlow {
+    /// The settings of the run, how the flow computes and dumps its features.
+    pub settings: Arc<FlowSettings>,
     /// The unique identifier of the flow.
     pub flow_key: String,
     /// The destination IP address of the flow.
@@ -32,6 +77,9 @@ pub struct BasicFlow {
     pub first_timestamp: DateTime<Utc>,
     /// The last timestamp of the flow.
     pub last_timestamp: DateTime<Utc>,
+    /// The timestamp of the last packet counted in the features, unlike `last_timestamp`
+    /// without the excluded keepalives, the start of the next inter-arrival time.
+    pub last_counted_timestamp: DateTime<Utc>,
     /// The last ACK of the flow.
     pub flow_end_of_flow_ack: u8,
     /// The number of FIN flags in the forward direction.
@@ -51,7 +99,11 @@ pub struct BasicFlow {
     /// The number of ECE flags in the forward direction.
     pub fwd_ece_flag_count: u32,
     /// The number of packets in the forward direction.
-    pub fwd_packet_count: u32,
+    pub fwd_packet_count: u64,
+    /// The total number of bytes (IP length) in the forward direction.
+    pub fwd_total_bytes: u64,
+    /// The number of payload bytes in the forward direction.
+    pub fwd_payload_bytes: u64,
     /// The number of FIN flags in the backward direction.
     pub bwd_fin_flag_count: u32,
     /// The number of SYN flags in the backward direction.
@@ -69,15 +121,194 @@ pub struct BasicFlow {
     /// The number of ECE flags in the backward direction.
     pub bwd_ece_flag_count: u32,
     /// The number of packets in the backward direction.
-    pub bwd_packet_count: u32,
+    pub bwd_packet_count: u64,
+    /// The total number of bytes (IP length) in the backward direction.
+    pub bwd_total_bytes: u64,
+    /// The number of payload bytes in the backward direction.
+    pub bwd_payload_bytes: u64,
+    /// The number of TCP keepalives and zero window probes in the forward direction.
+    pub fwd_keepalive_packets: u32,
+    /// The number of TCP keepalives and zero window probes in the backward direction.
+    pub bwd_keepalive_packets: u32,
+    /// The start of the current export window.
+    pub window_start: DateTime<Utc>,
+    /// The time an interim record of the flow was taken, `None` for the final record.
+    pub snapshot_time: Option<DateTime<Utc>>,
+    /// The wall clock time the flow left the flow table.
+    pub export_time: Option<DateTime<Utc>>,
+    /// How long the first packet took from the kernel to userspace, see
+    /// `PacketFeatures::capture_latency`.
+    pub capture_latency: Option<Duration>,
+    /// Why the flow was exported, `None` for interim records.
+    pub end_reason: Option<EndReason>,
+    /// The total IP lengths of the first packets, negative for backward packets.
+    ///
+    /// Only recorded up to `packet_sequence_length` packets, if that's set.
+    pub packet_sequence: Vec<i32>,
+    /// The memory the packet sequence and the payload snapshots hold, against the feature
+    /// memory limit.
+    pub feature_memory: FeatureMemory,
+    /// The first bytes of the first forward payload, kept for the signatures and the entropy.
+    pub fwd_payload_snapshot: Option<Box<PayloadSnapshot>>,
+    /// The first bytes of the first backward payload, kept for the signatures and the entropy.
+    pub bwd_payload_snapshot: Option<Box<PayloadSnapshot>>,
+    /// The OR of the TCP flag bytes of the forward packets.
+    pub fwd_raw_flags: u8,
+    /// The OR of the TCP flag bytes of the backward packets.
+    pub bwd_raw_flags: u8,
+    // Packet and byte counts (forward, backward) at the start of the export window
+    window_start_packets: (u64, u64),
+    window_start_bytes: (u64, u64),
     // Tracking TCP Flow Termination
     state_fwd: FlowState,
     state_bwd: FlowState,
     expected_ack_seq_fwd: Option<u32>,
     expected_ack_seq_bwd: Option<u32>,
+    // Detecting reused TCP connections
+    fwd_last_sequence: Option<u32>,
+    bwd_last_sequence: Option<u32>,
+    // Detecting keepalives, the sequence number after the highest segment and the last window
+    fwd_next_sequence: Option<u32>,
+    bwd_next_sequence: Option<u32>,
+    fwd_last_window: Option<u16>,
+    bwd_last_window: Option<u16>,
 }
 
 impl BasicFlow {
+    /// Checks if a packet belongs to a new connection reusing the 5-tuple of this TCP flow.
+    ///
+    /// This happens when the end of the previous connection was not captured. Both heuristics
+    /// can split a single connection: a SYN is only taken as a new handshake once the flow
+    /// carried packets without SYN, so retransmitted SYNs don't split the flow, and the
+    /// threshold should stay well above the receive window, so retransmissions don't either.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The packet to be checked.
+    /// * `forward` - The direction of the packet.
+    /// * `split_on_syn` - Whether a new handshake starts a new connection.
+    /// * `sequence_reset_threshold` - The backwards sequence number jump that starts a new connection.
+    ///
+    /// ### Returns
+    ///
+    /// A boolean indicating if the packet starts a new connection.
+    pub fn is_reused_connection(
+        &self,
+        packet: &PacketFeatures,
+        forward: bool,
+        split_on_syn: bool,
+        sequence_reset_threshold: Option<u32>,
+    ) -> bool {
+        if self.protocol != 6 || packet.protocol != 6 {
+            return false;
+        }
+
+        if split_on_syn && packet.syn_flag == 1 && packet.ack_flag == 0 {
+            let packets = self.fwd_packet_count + self.bwd_packet_count;
+            let syn_packets = u64::from(self.fwd_syn_flag_count + self.bwd_syn_flag_count);
+            if packets > syn_packets {
+                return true;
+            }
+        }
+
+        let last_sequence = if forward {
+            self.fwd_last_sequence
+        } else {
+            self.bwd_last_sequence
+        };
+        if let (Some(threshold), Some(last_sequence)) = (sequence_reset_threshold, last_sequence) {
+            // Serial number arithmetic, anything less than half the sequence space back is backwards
+            let backwards = last_sequence.wrapping_sub(packet.sequence_number);
+            if backwards > threshold && backwards < 1 << 31 {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Checks if a packet is a TCP keepalive or a zero window probe.
+    ///
+    /// Keepalives carry no flags besides ACK and at most one (garbage) byte, at the sequence
+    /// number just before the next expected one, which makes the receiver repeat its ACK.
+    /// Zero window probes carry the next byte, while the other side announces a zero window.
+    /// Linux sends its zero window probes like keepalives. A retransmitted last byte looks
+    /// like a keepalive as well.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The packet to be checked.
+    /// * `forward` - The direction of the packet.
+    ///
+    /// ### Returns
+    ///
+    /// A boolean indicating if the packet only probes the connection.
+    pub fn is_keepalive(&self, packet: &PacketFeatures, forward: bool) -> bool {
+        if packet.protocol != 6
+            || packet.data_length > 1
+            || packet.syn_flag + packet.fin_flag + packet.rst_flag > 0
+        {
+            return false;
+        }
+
+        let (next_sequence, peer_window) = if forward {
+            (self.fwd_next_sequence, self.bwd_last_window)
+        } else {
+            (self.bwd_next_sequence, self.fwd_last_window)
+        };
+        let Some(next_sequence) = next_sequence else {
+            return false;
+        };
+        packet.sequence_number == next_sequence.wrapping_sub(1)
+            || (packet.data_length == 1
+                && packet.sequence_number == next_sequence
+                && peer_window == Some(0))
+    }
+
+    /// Counts a keepalive without updating the other features, see `is_keepalive`.
+    ///
+    /// The flow still counts as active, so keepalives keep it from the idle timeout, but the
+    /// inter-arrival times span them, see `last_counted_timestamp`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The keepalive packet.
+    /// * `forward` - The direction of the packet.
+    pub fn record_keepalive(&mut self, packet: &PacketFeatures, forward: bool) {
+        self.last_timestamp = packet.timestamp;
+        if forward {
+            self.fwd_keepalive_packets += 1;
+            self.fwd_last_window = Some(packet.window_size);
+        } else {
+            self.bwd_keepalive_packets += 1;
+            self.bwd_last_window = Some(packet.window_size);
+        }
+    }
+
+    /// Returns the number of TCP keepalives and zero window probes in both directions.
+    pub fn keepalive_packets(&self) -> u32 {
+        self.fwd_keepalive_packets + self.bwd_keepalive_packets
+    }
+
+    /// Tracks the sequence number following the highest segment and the window of a direction.
+    fn track_segment(&mut self, packet: &PacketFeatures, forward: bool) {
+        // SYN and FIN take up one sequence number
+        let segment_length =
+            u32::from(packet.data_length) + u32::from(packet.syn_flag) + u32::from(packet.fin_flag);
+        let segment_end = packet.sequence_number.wrapping_add(segment_length);
+        let (next_sequence, last_window) = if forward {
+            (&mut self.fwd_next_sequence, &mut self.fwd_last_window)
+        } else {
+            (&mut self.bwd_next_sequence, &mut self.bwd_last_window)
+        };
+        *last_window = Some(packet.window_size);
+        // Retransmissions of earlier segments don't move it back
+        match next_sequence {
+            Some(next) if (segment_end.wrapping_sub(*next) as i32) <= 0 => {}
+            _ => *next_sequence = Some(segment_end),
+        }
+    }
+
     /// Checks if the flow is finished.
     ///
     /// A flow is considered finished when both FIN flags are set and the last ACK is received,
@@ -132,10 +363,360 @@ impl BasicFlow {
             .num_microseconds()
             .unwrap() as f64
     }
+
+    /// Checks whether the flow is a TCP connection attempt, a flow with a forward SYN.
+    ///
+    /// ### Returns
+    ///
+    /// `true` for TCP flows with a forward SYN, whether the connection succeeded or not.
+    pub fn is_connection_attempt(&self) -> bool {
+        self.protocol == 6 && self.fwd_syn_flag_count > 0
+    }
+
+    /// Returns the total number of packets seen in both directions.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets of the flow.
+    pub fn get_packet_count(&self) -> u64 {
+        self.fwd_packet_count + self.bwd_packet_count
+    }
+
+    /// Returns the number of bytes seen in both directions.
+    ///
+    /// Depending on the configured byte count, this is either the payload or the total IP length.
+    ///
+    /// ### Returns
+    ///
+    /// The number of bytes of the flow.
+    pub fn get_bytes(&self) -> u64 {
+        match self.settings.byte_count {
+            ByteCount::Payload => self.fwd_payload_bytes + self.bwd_payload_bytes,
+            ByteCount::Total => self.fwd_total_bytes + self.bwd_total_bytes,
+        }
+    }
+
+    /// Returns the likely port of the server side of the flow.
+    ///
+    /// The port in the lower IANA range (well-known, registered, ephemeral) is taken as the
+    /// one of the service, the port of the responder if both are in the same range, as the
+    /// initiator is usually the client.
+    ///
+    /// ### Returns
+    ///
+    /// The server port, or `None` for ICMP flows, which have no ports.
+    pub fn server_port(&self) -> Option<u16> {
+        if matches!(self.protocol, ICMP | ICMPV6) {
+            return None;
+        }
+        if port_category(self.port_source) == port_category(self.port_destination) {
+            Some(self.port_destination)
+        } else {
+            Some(self.port_source.min(self.port_destination))
+        }
+    }
+
+    /// Returns the bytes seen in the forward and the backward direction, see `get_bytes`.
+    pub fn get_directional_bytes(&self) -> (u64, u64) {
+        match self.settings.byte_count {
+            ByteCount::Payload => (self.fwd_payload_bytes, self.bwd_payload_bytes),
+            ByteCount::Total => (self.fwd_total_bytes, self.bwd_total_bytes),
+        }
+    }
+
+    /// Starts a new export window, the window features count the packets from here on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `timestamp` - The start of the new window.
+    pub fn start_window(&mut self, timestamp: DateTime<Utc>) {
+        self.window_start = timestamp;
+        self.window_start_packets = (self.fwd_packet_count, self.bwd_packet_count);
+        self.window_start_bytes = self.get_directional_bytes();
+    }
+
+    /// Returns the most memory the optional features of one flow reserve against the feature
+    /// memory limit in bytes.
+    pub fn feature_memory_bytes(settings: &FlowSettings) -> u64 {
+        let sequence = settings.packet_sequence_length.unwrap_or(0) * std::mem::size_of::<i32>();
+        // A snapshot per direction
+        let snapshots = if settings.payload_snapshots {
+            2 * std::mem::size_of::<PayloadSnapshot>()
+        } else {
+            0
+        };
+        (sequence + snapshots) as u64
+    }
+
+    /// Drops the packet sequence and the payload snapshots and returns their memory, for a
+    /// younger flow at the feature memory limit.
+    ///
+    /// The flow is then exported like one that started without memory.
+    pub fn release_feature_memory(&mut self) {
+        self.packet_sequence = Vec::new();
+        self.fwd_payload_snapshot = None;
+        self.bwd_payload_snapshot = None;
+        self.feature_memory.release(self.settings.feature_memory_limit);
+    }
+
+    /// Returns the features of the basic flow, see `Flow::features`.
+    pub fn feature_list() -> Vec<Feature<BasicFlow>> {
+        type F = Feature<BasicFlow>;
+        vec![
+            registry::flow_key_feature("FLOW_ID"),
+            registry::ip_feature("IP_SOURCE", true),
+            registry::port_feature("PORT_SOURCE", true),
+            registry::ip_feature("IP_DESTINATION", false),
+            registry::port_feature("PORT_DESTINATION", false),
+            F::new("PROTOCOL", Unit::None, "The IP protocol number", |flow| flow.protocol),
+            F::timestamp(
+                "FIRST_TIMESTAMP",
+                "The time of the first packet",
+                TimestampFormat::Text,
+                |flow| flow.first_timestamp,
+            )
+            .contaminant(),
+            F::timestamp(
+                "LAST_TIMESTAMP",
+                "The time of the last packet",
+                TimestampFormat::Text,
+                |flow| flow.last_timestamp,
+            )
+            .contaminant(),
+            F::new(
+                "DURATION",
+                Unit::Microseconds,
+                "The time from the first to the last packet",
+                |flow| flow.get_flow_duration_usec(),
+            ),
+            F::new(
+                "FLOW_END_OF_FLOW_ACK",
+                Unit::None,
+                "1 if the TCP connection was closed by both sides",
+                |flow| flow.flow_end_of_flow_ack,
+            ),
+            F::new("FWD_FIN_FLAG_COUNT", Unit::Packets, "The forward packets with FIN", |flow| {
+                flow.fwd_fin_flag_count
+            }),
+            F::new("FWD_SYN_FLAG_COUNT", Unit::Packets, "The forward packets with SYN", |flow| {
+                flow.fwd_syn_flag_count
+            }),
+            F::new("FWD_RST_FLAG_COUNT", Unit::Packets, "The forward packets with RST", |flow| {
+                flow.fwd_rst_flag_count
+            }),
+            F::new("FWD_PSH_FLAG_COUNT", Unit::Packets, "The forward packets with PSH", |flow| {
+                flow.fwd_psh_flag_count
+            }),
+            F::new("FWD_ACK_FLAG_COUNT", Unit::Packets, "The forward packets with ACK", |flow| {
+                flow.fwd_ack_flag_count
+            }),
+            F::new("FWD_URG_FLAG_COUNT", Unit::Packets, "The forward packets with URG", |flow| {
+                flow.fwd_urg_flag_count
+            }),
+            F::new("FWD_CWE_FLAG_COUNT", Unit::Packets, "The forward packets with CWR", |flow| {
+                flow.fwd_cwe_flag_count
+            }),
+            F::new("FWD_ECE_FLAG_COUNT", Unit::Packets, "The forward packets with ECE", |flow| {
+                flow.fwd_ece_flag_count
+            }),
+            F::new("FWD_PACKET_COUNT", Unit::Packets, "The forward packets", |flow| {
+                flow.fwd_packet_count
+            }),
+            F::new("BWD_FIN_FLAG_COUNT", Unit::Packets, "The backward packets with FIN", |flow| {
+                flow.bwd_fin_flag_count
+            }),
+            F::new("BWD_SYN_FLAG_COUNT", Unit::Packets, "The backward packets with SYN", |flow| {
+                flow.bwd_syn_flag_count
+            }),
+            F::new("BWD_RST_FLAG_COUNT", Unit::Packets, "The backward packets with RST", |flow| {
+                flow.bwd_rst_flag_count
+            }),
+            F::new("BWD_PSH_FLAG_COUNT", Unit::Packets, "The backward packets with PSH", |flow| {
+                flow.bwd_psh_flag_count
+            }),
+            F::new("BWD_ACK_FLAG_COUNT", Unit::Packets, "The backward packets with ACK", |flow| {
+                flow.bwd_ack_flag_count
+            }),
+            F::new("BWD_URG_FLAG_COUNT", Unit::Packets, "The backward packets with URG", |flow| {
+                flow.bwd_urg_flag_count
+            }),
+            F::new("BWD_CWE_FLAG_COUNT", Unit::Packets, "The backward packets with CWR", |flow| {
+                flow.bwd_cwe_flag_count
+            }),
+            F::new("BWD_ECE_FLAG_COUNT", Unit::Packets, "The backward packets with ECE", |flow| {
+                flow.bwd_ece_flag_count
+            }),
+            F::new("BWD_PACKET_COUNT", Unit::Packets, "The backward packets", |flow| {
+                flow.bwd_packet_count
+            }),
+        ]
+    }
+
+    /// Returns the record type and the packets and bytes of the current export window.
+    ///
+    /// The window of a final record ends with the last packet of the flow.
+    pub fn window_features() -> Vec<Feature<BasicFlow>> {
+        type F = Feature<BasicFlow>;
+        vec![
+            F::new("RECORD_TYPE", Unit::None, "`interim` or `final`", |flow| {
+                if flow.snapshot_time.is_some() {
+                    "interim"
+                } else {
+                    "final"
+                }
+            }),
+            F::timestamp(
+                "WINDOW_START",
+                "The start of the export window",
+                TimestampFormat::Text,
+                |flow| flow.window_start,
+            ),
+            F::timestamp(
+                "WINDOW_END",
+                "The end of the export window",
+                TimestampFormat::Text,
+                |flow| flow.snapshot_time.unwrap_or(flow.last_timestamp),
+            ),
+            F::new(
+                "WINDOW_FWD_PACKETS",
+                Unit::Packets,
+                "The forward packets of the window",
+                |flow| flow.fwd_packet_count - flow.window_start_packets.0,
+            ),
+            F::new(
+                "WINDOW_BWD_PACKETS",
+                Unit::Packets,
+                "The backward packets of the window",
+                |flow| flow.bwd_packet_count - flow.window_start_packets.1,
+            ),
+            F::new("WINDOW_FWD_BYTES", Unit::Bytes, "The forward bytes of the window", |flow| {
+                flow.get_directional_bytes().0 - flow.window_start_bytes.0
+            }),
+            F::new("WINDOW_BWD_BYTES", Unit::Bytes, "The backward bytes of the window", |flow| {
+                flow.get_directional_bytes().1 - flow.window_start_bytes.1
+            }),
+        ]
+    }
+
+    /// Returns the signed lengths of the first packets, `PACKET_SEQUENCE_1` to
+    /// `PACKET_SEQUENCE_N`, padded with 0.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sequence_length` - The number of dumped packets.
+    pub fn packet_sequence_features(sequence_length: usize) -> Vec<Feature<BasicFlow>> {
+        (0..sequence_length)
+            .map(|index| {
+                let name = format!("PACKET_SEQUENCE_{}", index + 1);
+                let description = format!(
+                    "The total IP length of packet {} of the flow, negative if it is backward",
+                    index + 1
+                );
+                Feature::new(name, Unit::Bytes, description, move |flow: &BasicFlow| {
+                    flow.packet_sequence.get(index).copied().unwrap_or(0)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the raw TCP flag bytes of both directions, with the bits of the TCP header
+    /// (FIN is 1, SYN 2, ..., CWR 128).
+    pub fn raw_flags_features() -> Vec<Feature<BasicFlow>> {
+        type F = Feature<BasicFlow>;
+        vec![
+            F::new("FWD_RAW_FLAGS", Unit::None, "The OR of the forward TCP flags", |flow| {
+                flow.fwd_raw_flags
+            }),
+            F::new("BWD_RAW_FLAGS", Unit::None, "The OR of the backward TCP flags", |flow| {
+                flow.bwd_raw_flags
+            }),
+        ]
+    }
+
+    /// Returns the optional features enabled in the settings, which every feature set exports
+    /// after its own features.
+    ///
+    /// ### Arguments
+    ///
+    /// * `settings` - The settings of the run, those of the dumped flows.
+    pub fn optional_features(settings: &FlowSettings) -> Vec<Feature<BasicFlow>> {
+        type F = Feature<BasicFlow>;
+        let mut features = Vec::new();
+        if settings.export_window.is_some() {
+            features.extend(Self::window_features());
+        }
+        if let Some(sequence_length) = settings.packet_sequence_length {
+            features.extend(Self::packet_sequence_features(sequence_length));
+        }
+        if settings.raw_flags {
+            features.extend(Self::raw_flags_features());
+        }
+        if settings.canonical_endpoints {
+            // Which of the canonical endpoints the forward features belong to
+            let description = "Whether the source is the initiator of the flow";
+            features.push(F::new("SOURCE_IS_INITIATOR", Unit::None, description, |flow| {
+                flow.settings.is_source_initiator(flow)
+            }));
+        }
+        if settings.export_time {
+            let feature_type = FeatureType::Timestamp(TimestampFormat::Text);
+            let description = "The wall clock time the flow was exported at";
+            features.push(
+                F::with_type("EXPORT_TIME", feature_type, Unit::None, description, |flow| {
+                    flow.export_time
+                        .map_or(Value::Null, |time| Value::Timestamp(time, TimestampFormat::Text))
+                })
+                .nullable(),
+            );
+        }
+        if settings.end_reason {
+            let description = "Why the flow was exported, e.g. `idle-timeout`";
+            features.push(F::optional("END_REASON", Unit::None, description, |flow| {
+                flow.end_reason.map(|reason| reason.to_string())
+            }));
+        }
+        if settings.payload_signatures.is_some() {
+            let description = "The labels of the payload signatures the flow matches";
+            features.push(
+                F::with_type(
+                    "PAYLOAD_SIGNATURES",
+                    FeatureType::Text,
+                    Unit::None,
+                    description,
+                    |flow| flow.payload_signatures_value(),
+                )
+                .nullable(),
+            );
+        }
+        if settings.sensor_id.is_some() {
+            let description = "The sensor that captured the flow";
+            features.push(F::new("SENSOR_ID", Unit::None, description, |flow| {
+                flow.settings.sensor_id.clone().unwrap_or_default()
+            }));
+        }
+        features
+    }
+
+    /// Returns the labels of the payload signatures the flow matches, separated by `;`, missing
+    /// if none matches.
+    pub fn payload_signatures_value(&self) -> Value {
+        let labels = self
+            .settings
+            .payload_signatures
+            .as_ref()
+            .map(|signatures| signatures.matches(self))
+            .unwrap_or_default();
+        if labels.is_empty() {
+            Value::Null
+        } else {
+            Value::Text(labels.join(";").into())
+        }
+    }
 }
 
 impl Flow for BasicFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_id: String,
         ip_source: IpAddr,
         port_source: u16,
@@ -145,6 +726,7 @@ impl Flow for BasicFlow {
         first_timestamp: DateTime<Utc>,
     ) -> Self {
         BasicFlow {
+            settings,
             flow_key: flow_id,
             ip_destination,
             ip_source,
@@ -153,6 +735,7 @@ impl Flow for BasicFlow {
             protocol,
             first_timestamp,
             last_timestamp: first_timestamp,
+            last_counted_timestamp: first_timestamp,
             flow_end_of_flow_ack: 0,
             fwd_fin_flag_count: 0,
             fwd_syn_flag_count: 0,
@@ -163,6 +746,8 @@ impl Flow for BasicFlow {
             fwd_cwe_flag_count: 0,
             fwd_ece_flag_count: 0,
             fwd_packet_count: 0,
+            fwd_total_bytes: 0,
+            fwd_payload_bytes: 0,
             bwd_fin_flag_count: 0,
             bwd_syn_flag_count: 0,
             bwd_rst_flag_count: 0,
@@ -172,22 +757,92 @@ impl Flow for BasicFlow {
             bwd_cwe_flag_count: 0,
             bwd_ece_flag_count: 0,
             bwd_packet_count: 0,
+            bwd_total_bytes: 0,
+            bwd_payload_bytes: 0,
+            fwd_keepalive_packets: 0,
+            bwd_keepalive_packets: 0,
+            window_start: first_timestamp,
+            snapshot_time: None,
+            export_time: None,
+            capture_latency: None,
+            end_reason: None,
+            packet_sequence: Vec::new(),
+            feature_memory: FeatureMemory::default(),
+            fwd_payload_snapshot: None,
+            bwd_payload_snapshot: None,
+            fwd_raw_flags: 0,
+            bwd_raw_flags: 0,
+            window_start_packets: (0, 0),
+            window_start_bytes: (0, 0),
             state_fwd: FlowState::Established,
             state_bwd: FlowState::Established,
             expected_ack_seq_fwd: None,
             expected_ack_seq_bwd: None,
+            fwd_last_sequence: None,
+            bwd_last_sequence: None,
+            fwd_next_sequence: None,
+            bwd_next_sequence: None,
+            fwd_last_window: None,
+            bwd_last_window: None,
         }
     }
 
     fn update_flow(&mut self, packet: &PacketFeatures, fwd: bool) -> bool {
         self.last_timestamp = packet.timestamp;
+        self.last_counted_timestamp = packet.timestamp;
+        self.capture_latency = self.capture_latency.or(packet.capture_latency);
+        if fwd {
+            self.fwd_raw_flags |= packet.combined_flags();
+        } else {
+            self.bwd_raw_flags |= packet.combined_flags();
+        }
+
+        if packet.protocol == 6 {
+            // Keepalives point one byte back, they would move the next sequence number back
+            if self.is_keepalive(packet, fwd) {
+                self.record_keepalive(packet, fwd);
+            } else {
+                self.track_segment(packet, fwd);
+            }
+        }
 
         if self.is_tcp_finished(packet, fwd) {
             self.flow_end_of_flow_ack = 1;
         }
 
+        if let Some(sequence_length) = self.settings.packet_sequence_length {
+            // The whole sequence is reserved with the first packet, or left out without memory
+            if self.packet_sequence.capacity() == 0 && !self.feature_memory.is_truncated() {
+                let bytes = sequence_length * std::mem::size_of::<i32>();
+                if self.feature_memory.reserve(bytes as u64, self.settings.feature_memory_limit) {
+                    self.packet_sequence.reserve_exact(sequence_length);
+                }
+            }
+            if self.packet_sequence.capacity() > 0 && self.packet_sequence.len() < sequence_length {
+                let length = i32::from(packet.length);
+                self.packet_sequence.push(if fwd { length } else { -length });
+            }
+        }
+
+        let payload_snapshot = if fwd {
+            &mut self.fwd_payload_snapshot
+        } else {
+            &mut self.bwd_payload_snapshot
+        };
+        if let (None, Some(snapshot)) = (&payload_snapshot, &packet.payload_snapshot) {
+            // Only the first snapshot of a direction is kept, or none without memory
+            let bytes = std::mem::size_of::<PayloadSnapshot>();
+            if !self.feature_memory.is_truncated()
+                && self.feature_memory.reserve(bytes as u64, self.settings.feature_memory_limit)
+            {
+                *payload_snapshot = Some(Box::new(*snapshot));
+            }
+        }
+
         if fwd {
             self.fwd_packet_count += 1;
+            self.fwd_total_bytes += u64::from(packet.length);
+            self.fwd_payload_bytes += u64::from(packet.data_length);
             self.fwd_fin_flag_count += u32::from(packet.fin_flag);
             self.fwd_syn_flag_count += u32::from(packet.syn_flag);
             self.fwd_rst_flag_count += u32::from(packet.rst_flag);
@@ -196,8 +851,13 @@ impl Flow for BasicFlow {
             self.fwd_urg_flag_count += u32::from(packet.urg_flag);
             self.fwd_cwe_flag_count += u32::from(packet.cwe_flag);
             self.fwd_ece_flag_count += u32::from(packet.ece_flag);
+            if packet.protocol == 6 {
+                self.fwd_last_sequence = Some(packet.sequence_number);
+            }
         } else {
             self.bwd_packet_count += 1;
+            self.bwd_total_bytes += u64::from(packet.length);
+            self.bwd_payload_bytes += u64::from(packet.data_length);
             self.bwd_fin_flag_count += u32::from(packet.fin_flag);
             self.bwd_syn_flag_count += u32::from(packet.syn_flag);
             self.bwd_rst_flag_count += u32::from(packet.rst_flag);
@@ -206,6 +866,9 @@ impl Flow for BasicFlow {
             self.bwd_urg_flag_count += u32::from(packet.urg_flag);
             self.bwd_cwe_flag_count += u32::from(packet.cwe_flag);
             self.bwd_ece_flag_count += u32::from(packet.ece_flag);
+            if packet.protocol == 6 {
+                self.bwd_last_sequence = Some(packet.sequence_number);
+            }
         }
 
         if self.flow_end_of_flow_ack > 0
@@ -218,90 +881,9 @@ impl Flow for BasicFlow {
         false
     }
 
-    fn dump(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},\
-        {},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            self.flow_key,
-            self.ip_source,
-            self.port_source,
-            self.ip_destination,
-            self.port_destination,
-            self.protocol,
-            self.first_timestamp,
-            self.last_timestamp,
-            self.get_flow_duration_usec(),
-            self.flow_end_of_flow_ack,
-            self.fwd_fin_flag_count,
-            self.fwd_syn_flag_count,
-            self.fwd_rst_flag_count,
-            self.fwd_psh_flag_count,
-            self.fwd_ack_flag_count,
-            self.fwd_urg_flag_count,
-            self.fwd_cwe_flag_count,
-            self.fwd_ece_flag_count,
-            self.fwd_packet_count,
-            self.bwd_fin_flag_count,
-            self.bwd_syn_flag_count,
-            self.bwd_rst_flag_count,
-            self.bwd_psh_flag_count,
-            self.bwd_ack_flag_count,
-            self.bwd_urg_flag_count,
-            self.bwd_cwe_flag_count,
-            self.bwd_ece_flag_count,
-            self.bwd_packet_count
-        )
-    }
-
-    fn get_features() -> String {
-        format!(
-            "FLOW_ID,IP_SOURCE,PORT_SOURCE,IP_DESTINATION,PORT_DESTINATION,PROTOCOL,\
-            FIRST_TIMESTAMP,LAST_TIMESTAMP,DURATION,FLOW_END_OF_FLOW_ACK,\
-            FWD_FIN_FLAG_COUNT,FWD_SYN_FLAG_COUNT,FWD_RST_FLAG_COUNT,FWD_PSH_FLAG_COUNT,\
-            FWD_ACK_FLAG_COUNT,FWD_URG_FLAG_COUNT,FWD_CWE_FLAG_COUNT,FWD_ECE_FLAG_COUNT,\
-            FWD_PACKET_COUNT,BWD_FIN_FLAG_COUNT,BWD_SYN_FLAG_COUNT,BWD_RST_FLAG_COUNT,\
-            BWD_PSH_FLAG_COUNT,BWD_ACK_FLAG_COUNT,BWD_URG_FLAG_COUNT,BWD_CWE_FLAG_COUNT,\
-            BWD_ECE_FLAG_COUNT,BWD_PACKET_COUNT"
-        )
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{}",
-            self.protocol,
-            self.get_flow_duration_usec(),
-            self.flow_end_of_flow_ack,
-            self.fwd_fin_flag_count,
-            self.fwd_syn_flag_count,
-            self.fwd_rst_flag_count,
-            self.fwd_psh_flag_count,
-            self.fwd_ack_flag_count,
-            self.fwd_urg_flag_count,
-            self.fwd_cwe_flag_count,
-            self.fwd_ece_flag_count,
-            self.fwd_packet_count,
-            self.bwd_fin_flag_count,
-            self.bwd_syn_flag_count,
-            self.bwd_rst_flag_count,
-            self.bwd_psh_flag_count,
-            self.bwd_ack_flag_count,
-            self.bwd_urg_flag_count,
-            self.bwd_cwe_flag_count,
-            self.bwd_ece_flag_count,
-            self.bwd_packet_count
-        )
-    }
-
-    fn get_features_without_contamination() -> String {
-        format!(
-            "PROTOCOL,DURATION,FLOW_END_OF_FLOW_ACK,\
-            FWD_FIN_FLAG_COUNT,FWD_SYN_FLAG_COUNT,FWD_RST_FLAG_COUNT,FWD_PSH_FLAG_COUNT,\
-            FWD_ACK_FLAG_COUNT,FWD_URG_FLAG_COUNT,FWD_CWE_FLAG_COUNT,FWD_ECE_FLAG_COUNT,\
-            FWD_PACKET_COUNT,BWD_FIN_FLAG_COUNT,BWD_SYN_FLAG_COUNT,BWD_RST_FLAG_COUNT,\
-            BWD_PSH_FLAG_COUNT,BWD_ACK_FLAG_COUNT,BWD_URG_FLAG_COUNT,BWD_CWE_FLAG_COUNT,\
-            BWD_ECE_FLAG_COUNT,BWD_PACKET_COUNT"
-        )
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<BasicFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(BasicFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -323,4 +905,96 @@ impl Flow for BasicFlow {
     fn flow_key(&self) -> &String {
         &self.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        self
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::DateTime;
+
+    use super::BasicFlow;
+    use crate::flows::{flow::Flow, registry, settings::FlowSettings};
+
+    #[test]
+    fn test_packet_sequence() {
+        let mut basic_flow = BasicFlow::new(
+            Arc::default(),
+            "10.0.0.1:40000-10.0.0.2:443-6".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            443,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        );
+        // A request and its response, the backward lengths are negative
+        basic_flow.packet_sequence = vec![60, -60, 52, 569, -1500];
+
+        let features = BasicFlow::packet_sequence_features(3);
+        assert_eq!(registry::dump_features(&features, &basic_flow), "60,-60,52");
+        // Short flows are padded to the fixed width
+        let padded = BasicFlow::packet_sequence_features(7);
+        assert_eq!(registry::dump_features(&padded, &basic_flow), "60,-60,52,569,-1500,0,0");
+        assert_eq!(
+            registry::feature_names(&features),
+            "PACKET_SEQUENCE_1,PACKET_SEQUENCE_2,PACKET_SEQUENCE_3"
+        );
+    }
+
+    #[test]
+    fn test_raw_flags() {
+        let mut basic_flow = BasicFlow::new(
+            Arc::default(),
+            "10.0.0.1:40000-10.0.0.2:443-6".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            443,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        );
+        let features = BasicFlow::raw_flags_features();
+        assert_eq!(registry::dump_features(&features, &basic_flow), "0,0");
+
+        // SYN, ACK and FIN forward, SYN-ACK and RST backward
+        basic_flow.fwd_raw_flags = 0x02 | 0x10 | 0x01;
+        basic_flow.bwd_raw_flags = 0x12 | 0x04;
+        assert_eq!(registry::dump_features(&features, &basic_flow), "19,22");
+        assert_eq!(registry::feature_names(&features), "FWD_RAW_FLAGS,BWD_RAW_FLAGS");
+    }
+
+    #[test]
+    fn test_export_time() {
+        let mut basic_flow = BasicFlow::new(
+            Arc::default(),
+            "10.0.0.1:40000-10.0.0.2:443-6".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            443,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        );
+        let features = BasicFlow::optional_features(&FlowSettings {
+            export_time: true,
+            ..FlowSettings::default()
+        });
+        assert_eq!(registry::dump_features(&features, &basic_flow), "");
+
+        // Independent of the packet timestamps
+        basic_flow.export_time = DateTime::from_timestamp(1_700_000_000, 0);
+        assert_eq!(registry::dump_features(&features, &basic_flow), "2023-11-14 22:13:20 UTC");
+    }
 }
@@ -1,12 +1,20 @@
 use chrono::{DateTime, Utc};
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
-use crate::packet_features::PacketFeatures;
+use crate::{
+    args::{FeatureGroup, TimestampFormat},
+    packet_features::PacketFeatures,
+};
 
 use super::{
     basic_flow::BasicFlow,
     flow::Flow,
-    util::{calculate_mean, calculate_std},
+    registry::{self, Feature, Unit},
+    settings::FlowSettings,
+    util::{calculate_mean, calculate_std, combine_variances},
 };
 
 /// Represents a CIC Flow, encapsulating various metrics and states of a network flow.
@@ -54,9 +62,10 @@ pub struct CicFlow {
     /// The timestamp of the last packet in the forward flow.
     pub fwd_last_timestamp: Option<DateTime<Utc>>,
     /// The total length of packets in the forward flow.
-    pub fwd_pkt_len_tot: u32,
-    /// The total length of transport layer segments in the forward flow (tcp/udp header + data).
-    pub fwd_seg_len_tot: u32,
+    pub fwd_pkt_len_tot: u64,
+    /// The total length of transport layer segments in the forward flow, counted like
+    /// `fwd_pkt_len_tot` (see `FlowSettings::packet_byte_count`) as CICFlowMeter does.
+    pub fwd_seg_len_tot: u64,
     /// The maximum length of packets in the forward flow.
     pub fwd_pkt_len_max: u32,
     /// The minimum length of packets in the forward flow.
@@ -76,13 +85,13 @@ pub struct CicFlow {
     /// The minimum inter-arrival time of packets in the forward flow.
     fwd_iat_min: f64,
     /// The total header length of the forward flow.
-    pub fwd_header_length: u32,
+    pub fwd_header_length: u64,
     /// The total duration of bulk packets in the forward flow.
     pub fwd_bulk_duration: f64,
     /// The number of bulk packets in the forward flow.
     pub fwd_bulk_packet_count: u64,
     /// The total size of bulk packets in the forward flow.
-    pub fwd_bulk_size_total: u32,
+    pub fwd_bulk_size_total: u64,
     /// The number of bulk states in the forward flow.
     pub fwd_bulk_state_count: u64,
     /// Helper variable for bulk packet count.
@@ -90,7 +99,7 @@ pub struct CicFlow {
     /// Helper variable for bulk start timestamp.
     fwd_bulk_start_help: Option<DateTime<Utc>>,
     /// Helper variable for bulk size.
-    fwd_bulk_size_help: u32,
+    fwd_bulk_size_help: u64,
     /// The timestamp of the last bulk packet in the forward flow.
     pub fwd_last_bulk_timestamp: Option<DateTime<Utc>>,
     /// The initial window size of the backward flow.
@@ -98,9 +107,9 @@ pub struct CicFlow {
     /// The timestamp of the last packet in the backward flow.
     pub bwd_last_timestamp: Option<DateTime<Utc>>,
     /// The total length of packets in the backward flow.
-    pub bwd_pkt_len_tot: u32,
-    /// The total length of transport layer segments in the backward flow (tcp/udp header + data).
-    pub bwd_seg_len_tot: u32,
+    pub bwd_pkt_len_tot: u64,
+    /// The total length of transport layer segments in the backward flow, see `fwd_seg_len_tot`.
+    pub bwd_seg_len_tot: u64,
     /// The maximum length of packets in the backward flow.
     pub bwd_pkt_len_max: u32,
     /// The minimum length of packets in the backward flow.
@@ -120,13 +129,13 @@ pub struct CicFlow {
     /// The minimum inter-arrival time of packets in the backward flow.
     bwd_iat_min: f64,
     /// The total header length of the backward flow.
-    pub bwd_header_length: u32,
+    pub bwd_header_length: u64,
     /// The total duration of bulk packets in the backward flow.
     pub bwd_bulk_duration: f64,
     /// The number of bulk packets in the backward flow.
     pub bwd_bulk_packet_count: u64,
     /// The total size of bulk packets in the backward flow.
-    pub bwd_bulk_size_total: u32,
+    pub bwd_bulk_size_total: u64,
     /// The number of bulk states in the backward flow.
     pub bwd_bulk_state_count: u64,
     /// Helper variable for bulk packet count.
@@ -134,9 +143,17 @@ pub struct CicFlow {
     /// Helper variable for bulk start timestamp.
     bwd_bulk_start_help: Option<DateTime<Utc>>,
     /// Helper variable for bulk size.
-    bwd_bulk_size_help: u32,
+    bwd_bulk_size_help: u64,
     /// The timestamp of the last bulk packet in the backward flow.
     bwd_last_bulk_timestamp: Option<DateTime<Utc>>,
+    /// The mean inter-arrival time of consecutive packets of the flow, in either direction.
+    pub flow_iat_mean: f64,
+    /// The standard deviation of the inter-arrival time of consecutive packets of the flow.
+    pub flow_iat_std: f64,
+    /// The maximum inter-arrival time of consecutive packets of the flow.
+    pub flow_iat_max: f64,
+    /// The minimum inter-arrival time of consecutive packets of the flow.
+    flow_iat_min: f64,
 }
 
 impl CicFlow {
@@ -149,7 +166,7 @@ impl CicFlow {
     ///
     /// * `len` - The length to be added to the forward header length.
     fn increase_fwd_header_length(&mut self, len: u32) {
-        self.fwd_header_length += len;
+        self.fwd_header_length += u64::from(len);
     }
 
     /// Increases the length of the backward header.
@@ -162,7 +179,7 @@ impl CicFlow {
     ///
     /// * `len` - The length to be added to the backward header length.
     fn increase_bwd_header_length(&mut self, len: u32) {
-        self.bwd_header_length += len;
+        self.bwd_header_length += u64::from(len);
     }
 
     /// Updates the minimum length of the forward header.
@@ -189,6 +206,12 @@ impl CicFlow {
     ///
     /// * `len` - The length of the new packet to be incorporated into the statistics.
     fn update_fwd_pkt_len_stats(&mut self, len: u32) {
+        // update total, it's the byte count of the direction
+        self.fwd_pkt_len_tot += u64::from(len);
+        if !self.basic_flow.settings.is_feature_group_enabled(FeatureGroup::PacketLength) {
+            return;
+        }
+
         // update max and min
         if len > self.fwd_pkt_len_max {
             self.fwd_pkt_len_max = len;
@@ -197,17 +220,14 @@ impl CicFlow {
             self.fwd_pkt_len_min = len;
         }
 
-        // update total
-        self.fwd_pkt_len_tot += len;
-
         // update mean and std
         let new_fwd_pkt_len_mean = calculate_mean(
-            self.basic_flow.fwd_packet_count as u64,
+            self.basic_flow.fwd_packet_count,
             self.fwd_pkt_len_mean as f64,
             len as f64,
         ) as f32;
         self.fwd_pkt_len_std = calculate_std(
-            self.basic_flow.fwd_packet_count as u64,
+            self.basic_flow.fwd_packet_count,
             self.fwd_pkt_len_std as f64,
             self.fwd_pkt_len_mean as f64,
             new_fwd_pkt_len_mean as f64,
@@ -225,6 +245,12 @@ impl CicFlow {
     ///
     /// * `len` - The length of the new backward packet to be included in the stats.
     fn update_bwd_pkt_len_stats(&mut self, len: u32) {
+        // update total, it's the byte count of the direction
+        self.bwd_pkt_len_tot += u64::from(len);
+        if !self.basic_flow.settings.is_feature_group_enabled(FeatureGroup::PacketLength) {
+            return;
+        }
+
         // update max and min
         if len > self.bwd_pkt_len_max {
             self.bwd_pkt_len_max = len;
@@ -233,17 +259,14 @@ impl CicFlow {
             self.bwd_pkt_len_min = len;
         }
 
-        // update total
-        self.bwd_pkt_len_tot += len;
-
         // update mean and std
         let new_bwd_pkt_len_mean = calculate_mean(
-            self.basic_flow.bwd_packet_count as u64,
+            self.basic_flow.bwd_packet_count,
             self.bwd_pkt_len_mean as f64,
             len as f64,
         ) as f32;
         self.bwd_pkt_len_std = calculate_std(
-            self.basic_flow.bwd_packet_count as u64,
+            self.basic_flow.bwd_packet_count,
             self.bwd_pkt_len_std as f64,
             self.bwd_pkt_len_mean as f64,
             new_bwd_pkt_len_mean as f64,
@@ -273,12 +296,12 @@ impl CicFlow {
 
         // update mean and std
         let new_fwd_iat_mean = calculate_mean(
-            (self.basic_flow.fwd_packet_count - 1) as u64,
+            self.basic_flow.fwd_packet_count - 1,
             self.fwd_iat_mean,
             iat,
         );
         self.fwd_iat_std = calculate_std(
-            (self.basic_flow.fwd_packet_count - 1) as u64,
+            self.basic_flow.fwd_packet_count - 1,
             self.fwd_iat_std,
             self.fwd_iat_mean,
             new_fwd_iat_mean,
@@ -308,12 +331,12 @@ impl CicFlow {
 
         // update mean and std
         let new_bwd_iat_mean = calculate_mean(
-            (self.basic_flow.bwd_packet_count - 1) as u64,
+            self.basic_flow.bwd_packet_count - 1,
             self.bwd_iat_mean,
             iat,
         );
         self.bwd_iat_std = calculate_std(
-            (self.basic_flow.bwd_packet_count - 1) as u64,
+            self.basic_flow.bwd_packet_count - 1,
             self.bwd_iat_std,
             self.bwd_iat_mean,
             new_bwd_iat_mean,
@@ -322,6 +345,33 @@ impl CicFlow {
         self.bwd_iat_mean = new_bwd_iat_mean;
     }
 
+    /// Updates the inter-arrival time statistics of the flow with the time since the previous
+    /// packet of either direction, like CICFlowMeter does.
+    ///
+    /// ### Arguments
+    ///
+    /// * `iat` - The time since the previous packet of the flow, in microseconds.
+    fn update_flow_iat_stats(&mut self, iat: f64) {
+        if iat > self.flow_iat_max {
+            self.flow_iat_max = iat;
+        }
+        if iat < self.flow_iat_min {
+            self.flow_iat_min = iat;
+        }
+
+        // The first packet has no inter-arrival time
+        let iat_count = self.basic_flow.get_packet_count() - 1;
+        let new_flow_iat_mean = calculate_mean(iat_count, self.flow_iat_mean, iat);
+        self.flow_iat_std = calculate_std(
+            iat_count,
+            self.flow_iat_std,
+            self.flow_iat_mean,
+            new_flow_iat_mean,
+            iat,
+        );
+        self.flow_iat_mean = new_flow_iat_mean;
+    }
+
     /// Updates the statistics for active flow periods.
     ///
     /// This method updates the count, mean, standard deviation, and max/min values
@@ -405,7 +455,7 @@ impl CicFlow {
         if self.fwd_bulk_start_help == None {
             self.fwd_bulk_start_help = Some(*timestamp);
             self.fwd_bulk_packet_count_help = 1;
-            self.fwd_bulk_size_help = len;
+            self.fwd_bulk_size_help = u64::from(len);
             self.fwd_last_bulk_timestamp = Some(*timestamp);
         } else {
             // too much idle time -> new bulk
@@ -417,10 +467,10 @@ impl CicFlow {
                 self.fwd_bulk_start_help = Some(*timestamp);
                 self.fwd_last_bulk_timestamp = Some(*timestamp);
                 self.fwd_bulk_packet_count_help = 1;
-                self.fwd_bulk_size_help = len;
+                self.fwd_bulk_size_help = u64::from(len);
             } else {
                 self.fwd_bulk_packet_count_help += 1;
-                self.fwd_bulk_size_help += len;
+                self.fwd_bulk_size_help += u64::from(len);
                 // new bulk
                 if self.fwd_bulk_packet_count_help == 4 {
                     self.fwd_bulk_state_count += 1;
@@ -434,7 +484,7 @@ impl CicFlow {
                 // continu bulk
                 else if self.fwd_bulk_packet_count_help > 4 {
                     self.fwd_bulk_packet_count += 1;
-                    self.fwd_bulk_size_total += len;
+                    self.fwd_bulk_size_total += u64::from(len);
                     self.fwd_bulk_duration += timestamp
                         .signed_duration_since(self.fwd_bulk_start_help.unwrap())
                         .num_microseconds()
@@ -465,7 +515,7 @@ impl CicFlow {
         if self.bwd_bulk_start_help == None {
             self.bwd_bulk_start_help = Some(*timestamp);
             self.bwd_bulk_packet_count_help = 1;
-            self.bwd_bulk_size_help = len;
+            self.bwd_bulk_size_help = u64::from(len);
             self.bwd_last_bulk_timestamp = Some(*timestamp);
         } else {
             // too much idle time -> new bulk
@@ -477,10 +527,10 @@ impl CicFlow {
                 self.bwd_bulk_start_help = Some(*timestamp);
                 self.bwd_last_bulk_timestamp = Some(*timestamp);
                 self.bwd_bulk_packet_count_help = 1;
-                self.bwd_bulk_size_help = len;
+                self.bwd_bulk_size_help = u64::from(len);
             } else {
                 self.bwd_bulk_packet_count_help += 1;
-                self.bwd_bulk_size_help += len;
+                self.bwd_bulk_size_help += u64::from(len);
                 // new bulk
                 if self.bwd_bulk_packet_count_help == 4 {
                     self.bwd_bulk_state_count += 1;
@@ -494,7 +544,7 @@ impl CicFlow {
                 // continu bulk
                 else if self.bwd_bulk_packet_count_help > 4 {
                     self.bwd_bulk_packet_count += 1;
-                    self.bwd_bulk_size_total += len;
+                    self.bwd_bulk_size_total += u64::from(len);
                     self.bwd_bulk_duration += timestamp
                         .signed_duration_since(self.bwd_bulk_start_help.unwrap())
                         .num_microseconds()
@@ -579,78 +629,46 @@ impl CicFlow {
         }
     }
 
-    /// Calculates the pooled standard deviation of inter-arrival times (IAT) for the flow.
+    /// Retrieves the standard deviation of the inter-arrival times (IAT) of the flow.
     ///
-    /// This method considers both forward and backward packet inter-arrival times to compute
-    /// a pooled variance, which is then square-rooted to get the standard deviation.
+    /// The IATs are the times between consecutive packets of the flow in either direction, as
+    /// in CICFlowMeter, but the standard deviation is the population one.
     ///
     /// ### Returns
     ///
-    /// Pooled standard deviation of the flow's IATs.
+    /// Standard deviation of the flow's IATs, 0 with fewer than two packets.
     pub fn get_flow_iat_std(&self) -> f64 {
-        if self.basic_flow.fwd_packet_count < 1
-            || self.basic_flow.bwd_packet_count < 1
-            || self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count < 3
-        {
-            return 0.0;
-        }
-
-        let fwd_iat_std_squared = self.fwd_iat_std.powi(2);
-        let bwd_iat_std_squared = self.bwd_iat_std.powi(2);
-
-        let pooled_variance = ((self.basic_flow.fwd_packet_count - 1) as f64 * fwd_iat_std_squared
-            + (self.basic_flow.bwd_packet_count - 1) as f64 * bwd_iat_std_squared)
-            / (self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count - 2) as f64;
-
-        pooled_variance.sqrt()
+        self.flow_iat_std
     }
 
-    /// Calculates the mean inter-arrival time (IAT) for the flow.
-    ///
-    /// Averages the IATs of forward and backward packets to compute the overall mean IAT of the flow.
+    /// Retrieves the mean inter-arrival time (IAT) of the flow, see `get_flow_iat_std`.
     ///
     /// ### Returns
     ///
-    /// Mean inter-arrival time of the flow.
+    /// Mean inter-arrival time of the flow, 0 with fewer than two packets.
     pub fn get_flow_iat_mean(&self) -> f64 {
-        (self.fwd_iat_mean * self.basic_flow.fwd_packet_count as f64
-            + self.bwd_iat_mean * self.basic_flow.bwd_packet_count as f64)
-            / (self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count) as f64
+        self.flow_iat_mean
     }
 
-    /// Retrieves the maximum inter-arrival time (IAT) observed in the flow.
-    ///
-    /// Compares the maximum IATs in both directions (forward and backward) and returns the larger one.
+    /// Retrieves the maximum inter-arrival time (IAT) of the flow, see `get_flow_iat_std`.
     ///
     /// ### Returns
     ///
-    /// Maximum inter-arrival time observed in the flow.
+    /// Maximum inter-arrival time of the flow, 0 with fewer than two packets.
     pub fn get_flow_iat_max(&self) -> f64 {
-        if self.fwd_iat_max > self.bwd_iat_max {
-            return self.fwd_iat_max;
-        }
-        self.bwd_iat_max
+        self.flow_iat_max
     }
 
-    /// Retrieves the minimum inter-arrival time (IAT) observed in the flow.
-    ///
-    /// Compares the minimum IATs in both directions and returns the smaller one, or 0 if not set.
+    /// Retrieves the minimum inter-arrival time (IAT) of the flow, see `get_flow_iat_std`.
     ///
     /// ### Returns
     ///
-    /// Minimum inter-arrival time observed in the flow or 0 if not set.
+    /// Minimum inter-arrival time of the flow or 0 if not set.
     pub fn get_flow_iat_min(&self) -> f64 {
-        if self.fwd_iat_min < self.bwd_iat_min {
-            if self.fwd_iat_min == f64::MAX {
-                return 0.0;
-            }
-            self.fwd_iat_min
-        } else {
-            if self.bwd_iat_min == f64::MAX {
-                return 0.0;
-            }
-            self.bwd_iat_min
+        if self.flow_iat_min == f64::MAX {
+            return 0.0;
         }
+        self.flow_iat_min
     }
 
     /// Retrieves the minimum IAT of packets in the forward flow.
@@ -798,6 +816,83 @@ impl CicFlow {
             / (self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count - 2) as f64
     }
 
+    /// Retrieves the variance of the forward inter-arrival times (IAT).
+    ///
+    /// Like all variance getters below, this is the population variance (divided by the
+    /// number of values), the square of the matching standard deviation feature.
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the forward IATs.
+    pub fn fwd_iat_variance(&self) -> f64 {
+        self.fwd_iat_std.powi(2)
+    }
+
+    /// Retrieves the variance of the backward inter-arrival times (IAT).
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the backward IATs.
+    pub fn bwd_iat_variance(&self) -> f64 {
+        self.bwd_iat_std.powi(2)
+    }
+
+    /// Retrieves the variance of the forward and backward inter-arrival times (IAT) together.
+    ///
+    /// Unlike `get_flow_iat_std`, which is taken over consecutive packets, this is the exact
+    /// population variance of all forward and backward IATs.
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the flow's IATs.
+    pub fn flow_iat_variance(&self) -> f64 {
+        combine_variances(
+            self.basic_flow.fwd_packet_count.saturating_sub(1),
+            self.fwd_iat_mean,
+            self.fwd_iat_variance(),
+            self.basic_flow.bwd_packet_count.saturating_sub(1),
+            self.bwd_iat_mean,
+            self.bwd_iat_variance(),
+        )
+    }
+
+    /// Retrieves the variance of the forward packet lengths.
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the forward packet lengths.
+    pub fn fwd_packet_length_variance(&self) -> f64 {
+        (self.fwd_pkt_len_std as f64).powi(2)
+    }
+
+    /// Retrieves the variance of the backward packet lengths.
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the backward packet lengths.
+    pub fn bwd_packet_length_variance(&self) -> f64 {
+        (self.bwd_pkt_len_std as f64).powi(2)
+    }
+
+    /// Retrieves the variance of all packet lengths of the flow.
+    ///
+    /// Unlike the pooled `get_flow_packet_length_variance` kept for CICFlowMeter parity,
+    /// this is the exact population variance of all packet lengths.
+    ///
+    /// ### Returns
+    ///
+    /// Variance of the flow's packet lengths.
+    pub fn packet_length_variance(&self) -> f64 {
+        combine_variances(
+            self.basic_flow.fwd_packet_count,
+            self.fwd_pkt_len_mean as f64,
+            self.fwd_packet_length_variance(),
+            self.basic_flow.bwd_packet_count,
+            self.bwd_pkt_len_mean as f64,
+            self.bwd_packet_length_variance(),
+        )
+    }
+
     /// Retrieves the standard deviation of packet lengths in the flow.
     ///
     /// Utilizes the calculated variance of packet lengths to compute the standard deviation.
@@ -866,8 +961,7 @@ impl CicFlow {
     /// Bytes per second rate of the flow.
     fn get_flow_bytes_s(&self) -> f64 {
         (self.fwd_pkt_len_tot + self.bwd_pkt_len_tot) as f64
-            / self.basic_flow.get_flow_duration_usec()
-            / 1_000_000.0
+            / (self.basic_flow.get_flow_duration_usec() / 1_000_000.0)
     }
 
     /// Calculates the packets per second rate of the flow.
@@ -880,8 +974,7 @@ impl CicFlow {
     /// Packets per second rate of the flow.
     fn get_flow_packets_s(&self) -> f64 {
         (self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count) as f64
-            / self.basic_flow.get_flow_duration_usec()
-            / 1_000_000.0
+            / (self.basic_flow.get_flow_duration_usec() / 1_000_000.0)
     }
 
     /// Calculates the forward packets per second rate of the flow.
@@ -894,8 +987,7 @@ impl CicFlow {
     /// Forward packets per second rate of the flow.
     pub fn get_fwd_packets_s(&self) -> f64 {
         self.basic_flow.fwd_packet_count as f64
-            / self.basic_flow.get_flow_duration_usec()
-            / 1_000_000.0
+            / (self.basic_flow.get_flow_duration_usec() / 1_000_000.0)
     }
 
     /// Calculates the backward packets per second rate of the flow.
@@ -908,8 +1000,7 @@ impl CicFlow {
     /// Backward packets per second rate of the flow.
     pub fn get_bwd_packets_s(&self) -> f64 {
         self.basic_flow.bwd_packet_count as f64
-            / self.basic_flow.get_flow_duration_usec()
-            / 1_000_000.0
+            / (self.basic_flow.get_flow_duration_usec() / 1_000_000.0)
     }
 
     /// Retrieves the average size of bulk transfers in the forward direction.
@@ -1096,10 +1187,483 @@ impl CicFlow {
             self.idle_min
         }
     }
+
+    /// Retrieves the fraction of the flow duration spent in idle periods.
+    ///
+    /// The idle time is the sum of the idle periods, so a flow without a gap longer than the
+    /// activity threshold has a ratio of 0, as does a flow without duration.
+    ///
+    /// ### Returns
+    ///
+    /// The total idle time divided by the flow duration, between 0 and 1.
+    pub fn idle_ratio(&self) -> f64 {
+        let duration = self.basic_flow.get_flow_duration_usec();
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        (self.idle_mean * self.idle_count as f64 / duration).min(1.0)
+    }
+
+    /// Returns the features of the CIC flow, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<CicFlow>> {
+        type F = Feature<CicFlow>;
+        let basic: fn(&CicFlow) -> &BasicFlow = |flow| &flow.basic_flow;
+        vec![
+            registry::flow_key_feature("FLOW_ID").map(basic),
+            registry::ip_feature("IP_SOURCE", true).map(basic),
+            registry::port_feature("PORT_SOURCE", true).map(basic),
+            registry::ip_feature("IP_DESTINATION", false).map(basic),
+            registry::port_feature("PORT_DESTINATION", false).map(basic),
+            F::new("PROTOCOL", Unit::None, "The IP protocol number", |flow| {
+                flow.basic_flow.protocol
+            })
+            .contaminant(),
+            F::timestamp(
+                "FIRST_TIMESTAMP",
+                "The time of the first packet",
+                TimestampFormat::Text,
+                |flow| flow.basic_flow.first_timestamp,
+            )
+            .contaminant(),
+            F::timestamp(
+                "LAST_TIMESTAMP",
+                "The time of the last packet",
+                TimestampFormat::Text,
+                |flow| flow.basic_flow.last_timestamp,
+            )
+            .contaminant(),
+            F::new(
+                "DURATION",
+                Unit::Microseconds,
+                "The time from the first to the last packet",
+                |flow| flow.basic_flow.get_flow_duration_usec(),
+            ),
+            F::new("FWD_PACKET_COUNT", Unit::Packets, "The forward packets", |flow| {
+                flow.basic_flow.fwd_packet_count
+            }),
+            F::new("BWD_PACKET_COUNT", Unit::Packets, "The backward packets", |flow| {
+                flow.basic_flow.bwd_packet_count
+            }),
+            F::new(
+                "FWD_PKT_LEN_TOT",
+                Unit::Bytes,
+                "The total length of the forward packets",
+                |flow| flow.fwd_pkt_len_tot,
+            ),
+            F::new(
+                "BWD_PKT_LEN_TOT",
+                Unit::Bytes,
+                "The total length of the backward packets",
+                |flow| flow.bwd_pkt_len_tot,
+            ),
+            F::new("FWD_PKT_LEN_MAX", Unit::Bytes, "The longest forward packet", |flow| {
+                flow.fwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FWD_PKT_LEN_MIN", Unit::Bytes, "The shortest forward packet", |flow| {
+                flow.get_fwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength)
+            .contaminant(),
+            F::new(
+                "FWD_PKT_LEN_MEAN",
+                Unit::Bytes,
+                "The mean length of the forward packets",
+                |flow| flow.fwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PKT_LEN_STD",
+                Unit::Bytes,
+                "The standard deviation of the forward packet lengths",
+                |flow| flow.fwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PKT_LEN_MAX", Unit::Bytes, "The longest backward packet", |flow| {
+                flow.bwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PKT_LEN_MIN", Unit::Bytes, "The shortest backward packet", |flow| {
+                flow.get_bwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength)
+            .contaminant(),
+            F::new(
+                "BWD_PKT_LEN_MEAN",
+                Unit::Bytes,
+                "The mean length of the backward packets",
+                |flow| flow.bwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PKT_LEN_STD",
+                Unit::Bytes,
+                "The standard deviation of the backward packet lengths",
+                |flow| flow.bwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FLOW_BYTES_S",
+                Unit::BytesPerSecond,
+                "The bytes of both directions per second",
+                |flow| flow.get_flow_bytes_s(),
+            ),
+            F::new(
+                "FLOW_PACKETS_S",
+                Unit::PacketsPerSecond,
+                "The packets of both directions per second",
+                |flow| flow.get_flow_packets_s(),
+            ),
+            F::new(
+                "FLOW_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean inter arrival time of the packets",
+                |flow| flow.get_flow_iat_mean(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the inter arrival times",
+                |flow| flow.get_flow_iat_std(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_IAT_MAX", Unit::Microseconds, "The longest inter arrival time", |flow| {
+                flow.get_flow_iat_max()
+            })
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_IAT_MIN", Unit::Microseconds, "The shortest inter arrival time", |flow| {
+                flow.get_flow_iat_min()
+            })
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_TOTAL",
+                Unit::Microseconds,
+                "The sum of the forward inter arrival times",
+                |flow| flow.fwd_iat_total,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean forward inter arrival time",
+                |flow| flow.fwd_iat_mean,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the forward inter arrival times",
+                |flow| flow.fwd_iat_std,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MAX",
+                Unit::Microseconds,
+                "The longest forward inter arrival time",
+                |flow| flow.fwd_iat_max,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MIN",
+                Unit::Microseconds,
+                "The shortest forward inter arrival time",
+                |flow| flow.get_fwd_iat_min(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_TOTAL",
+                Unit::Microseconds,
+                "The sum of the backward inter arrival times",
+                |flow| flow.bwd_iat_total,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean backward inter arrival time",
+                |flow| flow.bwd_iat_mean,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the backward inter arrival times",
+                |flow| flow.bwd_iat_std,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MAX",
+                Unit::Microseconds,
+                "The longest backward inter arrival time",
+                |flow| flow.bwd_iat_max,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MIN",
+                Unit::Microseconds,
+                "The shortest backward inter arrival time",
+                |flow| flow.get_bwd_iat_min(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new("FWD_PSH_FLAG_COUNT", Unit::Packets, "The forward packets with PSH", |flow| {
+                flow.basic_flow.fwd_psh_flag_count
+            }),
+            F::new("BWD_PSH_FLAG_COUNT", Unit::Packets, "The backward packets with PSH", |flow| {
+                flow.basic_flow.bwd_psh_flag_count
+            }),
+            F::new("FWD_URG_FLAG_COUNT", Unit::Packets, "The forward packets with URG", |flow| {
+                flow.basic_flow.fwd_urg_flag_count
+            }),
+            F::new("BWD_URG_FLAG_COUNT", Unit::Packets, "The backward packets with URG", |flow| {
+                flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new(
+                "FWD_HEADER_LENGTH",
+                Unit::Bytes,
+                "The total header length of the forward packets",
+                |flow| flow.fwd_header_length,
+            ),
+            F::new(
+                "BWD_HEADER_LENGTH",
+                Unit::Bytes,
+                "The total header length of the backward packets",
+                |flow| flow.bwd_header_length,
+            ),
+            F::new(
+                "FWD_PACKETS_S",
+                Unit::PacketsPerSecond,
+                "The forward packets per second",
+                |flow| flow.get_fwd_packets_s(),
+            ),
+            F::new(
+                "BWD_PACKETS_S",
+                Unit::PacketsPerSecond,
+                "The backward packets per second",
+                |flow| flow.get_bwd_packets_s(),
+            ),
+            F::new("FLOW_PACKET_LENGTH_MIN", Unit::Bytes, "The shortest packet", |flow| {
+                flow.get_flow_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength)
+            .contaminant(),
+            F::new("FLOW_PACKET_LENGTH_MAX", Unit::Bytes, "The longest packet", |flow| {
+                flow.get_flow_packet_length_max()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FLOW_PACKET_LENGTH_MEAN", Unit::Bytes, "The mean packet length", |flow| {
+                flow.get_flow_packet_length_mean()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FLOW_PACKET_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the packet lengths",
+                |flow| flow.get_flow_packet_length_std(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FLOW_PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the packet lengths",
+                |flow| flow.get_flow_packet_length_variance(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("FLOW_FIN_COUNT", Unit::Packets, "The packets with FIN", |flow| {
+                flow.basic_flow.fwd_fin_flag_count + flow.basic_flow.bwd_fin_flag_count
+            }),
+            F::new("FLOW_SYN_COUNT", Unit::Packets, "The packets with SYN", |flow| {
+                flow.basic_flow.fwd_syn_flag_count + flow.basic_flow.bwd_syn_flag_count
+            }),
+            F::new("FLOW_RST_COUNT", Unit::Packets, "The packets with RST", |flow| {
+                flow.basic_flow.fwd_rst_flag_count + flow.basic_flow.bwd_rst_flag_count
+            })
+            .contaminant(),
+            F::new("FLOW_PSH_COUNT", Unit::Packets, "The packets with PSH", |flow| {
+                flow.basic_flow.fwd_psh_flag_count + flow.basic_flow.bwd_psh_flag_count
+            })
+            .contaminant(),
+            F::new("FLOW_ACK_COUNT", Unit::Packets, "The packets with ACK", |flow| {
+                flow.basic_flow.fwd_ack_flag_count + flow.basic_flow.bwd_ack_flag_count
+            })
+            .contaminant(),
+            F::new("FLOW_URG_COUNT", Unit::Packets, "The packets with URG", |flow| {
+                flow.basic_flow.fwd_urg_flag_count + flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new("FLOW_CWE_COUNT", Unit::Packets, "The packets with CWR", |flow| {
+                flow.basic_flow.fwd_cwe_flag_count + flow.basic_flow.bwd_cwe_flag_count
+            }),
+            F::new("FLOW_ECE_COUNT", Unit::Packets, "The packets with ECE", |flow| {
+                flow.basic_flow.fwd_ece_flag_count + flow.basic_flow.bwd_ece_flag_count
+            })
+            .contaminant(),
+            F::new("DOWN_UP_RATIO", Unit::None, "The backward packets per forward packet", |flow| {
+                flow.get_down_up_ratio()
+            })
+            .contaminant(),
+            // This is a duplicate feature
+            F::new(
+                "FLOW_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the packets",
+                |flow| flow.get_flow_segment_length_mean(),
+            )
+            .contaminant(),
+            F::new(
+                "FWD_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the forward packets",
+                |flow| flow.get_fwd_segment_length_mean(),
+            ),
+            F::new(
+                "BWD_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the backward packets",
+                |flow| flow.get_bwd_segment_length_mean(),
+            ),
+            // This is a duplicate feature
+            F::new(
+                "FWD_HEADER_LENGTH",
+                Unit::Bytes,
+                "The total header length of the forward packets",
+                |flow| flow.fwd_header_length,
+            )
+            .contaminant(),
+            F::new(
+                "FWD_BYTES_BULK",
+                Unit::Bytes,
+                "The mean size of the forward bulk transfers",
+                |flow| flow.get_fwd_bytes_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_PACKETS_BULK",
+                Unit::Packets,
+                "The mean packets of the forward bulk transfers",
+                |flow| flow.get_fwd_packets_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_BULK_RATE",
+                Unit::BytesPerSecond,
+                "The rate of the forward bulk transfers",
+                |flow| flow.get_fwd_bulk_rate(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BYTES_BULK",
+                Unit::Bytes,
+                "The mean size of the backward bulk transfers",
+                |flow| flow.get_bwd_bytes_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_PACKETS_BULK",
+                Unit::Packets,
+                "The mean packets of the backward bulk transfers",
+                |flow| flow.get_bwd_packets_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BULK_RATE",
+                Unit::BytesPerSecond,
+                "The rate of the backward bulk transfers",
+                |flow| flow.get_bwd_bulk_rate(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "SF_FWD_PACKETS",
+                Unit::Packets,
+                "The mean forward packets of the subflows",
+                |flow| flow.get_sf_fwd_packets(),
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new("SF_FWD_BYTES", Unit::Bytes, "The mean forward bytes of the subflows", |flow| {
+                flow.get_sf_fwd_bytes()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "SF_BWD_PACKETS",
+                Unit::Packets,
+                "The mean backward packets of the subflows",
+                |flow| flow.get_sf_bwd_packets(),
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new("SF_BWD_BYTES", Unit::Bytes, "The mean backward bytes of the subflows", |flow| {
+                flow.get_sf_bwd_bytes()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "FWD_INIT_WIN_BYTES",
+                Unit::Bytes,
+                "The TCP window of the first forward packet",
+                |flow| flow.fwd_init_win_bytes,
+            ),
+            F::new(
+                "BWD_INIT_WIN_BYTES",
+                Unit::Bytes,
+                "The TCP window of the first backward packet",
+                |flow| flow.bwd_init_win_bytes,
+            ),
+            F::new("FWD_ACT_DATA_PKT", Unit::Packets, "The forward packets with a payload", |flow| {
+                flow.fwd_act_data_pkt
+            }),
+            // Known as min_seg_size_forward, but this is actually the min header length
+            // in the forward direction
+            F::new(
+                "FWD_HEADER_LEN_MIN",
+                Unit::Bytes,
+                "The shortest header of the forward packets",
+                |flow| flow.get_fwd_header_len_min(),
+            ),
+            F::new(
+                "ACTIVE_MEAN",
+                Unit::Microseconds,
+                "The mean time the flow was active before going idle",
+                |flow| flow.active_mean,
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "ACTIVE_STD",
+                Unit::Microseconds,
+                "The standard deviation of the active times",
+                |flow| flow.active_std,
+            )
+            .group(FeatureGroup::ActiveIdle)
+            .contaminant(),
+            F::new("ACTIVE_MAX", Unit::Microseconds, "The longest active time", |flow| {
+                flow.active_max
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("ACTIVE_MIN", Unit::Microseconds, "The shortest active time", |flow| {
+                flow.get_active_min()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("IDLE_MEAN", Unit::Microseconds, "The mean time the flow was idle", |flow| {
+                flow.idle_mean
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "IDLE_STD",
+                Unit::Microseconds,
+                "The standard deviation of the idle times",
+                |flow| flow.idle_std,
+            )
+            .group(FeatureGroup::ActiveIdle)
+            .contaminant(),
+            F::new("IDLE_MAX", Unit::Microseconds, "The longest idle time", |flow| flow.idle_max)
+                .group(FeatureGroup::ActiveIdle),
+            F::new("IDLE_MIN", Unit::Microseconds, "The shortest idle time", |flow| {
+                flow.get_idle_min()
+            })
+            .group(FeatureGroup::ActiveIdle),
+        ]
+    }
 }
 
 impl Flow for CicFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_key: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -1110,6 +1674,7 @@ impl Flow for CicFlow {
     ) -> Self {
         CicFlow {
             basic_flow: BasicFlow::new(
+                settings,
                 flow_key,
                 ipv4_source,
                 port_source,
@@ -1178,20 +1743,42 @@ impl Flow for CicFlow {
             bwd_bulk_start_help: None,
             bwd_bulk_size_help: 0,
             bwd_last_bulk_timestamp: None,
+            flow_iat_mean: 0.0,
+            flow_iat_std: 0.0,
+            flow_iat_max: 0.0,
+            flow_iat_min: f64::MAX,
         }
     }
 
     fn update_flow(&mut self, packet: &PacketFeatures, fwd: bool) -> bool {
+        let previous_timestamp = self.basic_flow.last_counted_timestamp;
         let is_terminated = self.basic_flow.update_flow(packet, fwd);
-        self.update_subflows(&packet.timestamp);
+        let settings = &self.basic_flow.settings;
+        let active_idle = settings.is_feature_group_enabled(FeatureGroup::ActiveIdle);
+        let iat = settings.is_feature_group_enabled(FeatureGroup::Iat);
+        let bulk = settings.is_feature_group_enabled(FeatureGroup::Bulk);
+        let byte_count = settings.packet_byte_count(packet);
+        if active_idle {
+            self.update_subflows(&packet.timestamp);
+        }
+        if iat && self.basic_flow.get_packet_count() > 1 {
+            self.update_flow_iat_stats(
+                packet
+                    .timestamp
+                    .signed_duration_since(previous_timestamp)
+                    .num_nanoseconds()
+                    .unwrap() as f64
+                    / 1000.0,
+            );
+        }
 
         if fwd {
-            self.update_fwd_pkt_len_stats(packet.data_length as u32);
+            self.update_fwd_pkt_len_stats(byte_count);
             self.update_fwd_header_len_min(packet.header_length as u32);
 
-            self.fwd_seg_len_tot += packet.length as u32;
+            self.fwd_seg_len_tot += u64::from(byte_count);
 
-            if self.basic_flow.fwd_packet_count > 1 {
+            if iat && self.basic_flow.fwd_packet_count > 1 {
                 self.update_fwd_iat_stats(
                     packet
                         .timestamp
@@ -1210,15 +1797,17 @@ impl Flow for CicFlow {
                 self.fwd_act_data_pkt += 1;
             }
 
-            self.update_fwd_bulk_stats(&packet.timestamp, packet.data_length as u32);
+            if bulk {
+                self.update_fwd_bulk_stats(&packet.timestamp, packet.data_length as u32);
+            }
             self.increase_fwd_header_length(packet.header_length as u32);
             self.fwd_last_timestamp = Some(packet.timestamp);
         } else {
-            self.update_bwd_pkt_len_stats(packet.data_length as u32);
+            self.update_bwd_pkt_len_stats(byte_count);
 
-            self.bwd_seg_len_tot += packet.length as u32;
+            self.bwd_seg_len_tot += u64::from(byte_count);
 
-            if self.basic_flow.bwd_packet_count > 1 {
+            if iat && self.basic_flow.bwd_packet_count > 1 {
                 self.update_bwd_iat_stats(
                     packet
                         .timestamp
@@ -1233,7 +1822,9 @@ impl Flow for CicFlow {
                 self.bwd_init_win_bytes = packet.window_size;
             }
 
-            self.update_bwd_bulk_stats(&packet.timestamp, packet.data_length as u32);
+            if bulk {
+                self.update_bwd_bulk_stats(&packet.timestamp, packet.data_length as u32);
+            }
             self.increase_bwd_header_length(packet.header_length as u32);
             self.bwd_last_timestamp = Some(packet.timestamp);
         }
@@ -1241,210 +1832,9 @@ impl Flow for CicFlow {
         is_terminated
     }
 
-    fn dump(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{}",
-            self.basic_flow.flow_key,
-            self.basic_flow.ip_source,
-            self.basic_flow.port_source,
-            self.basic_flow.ip_destination,
-            self.basic_flow.port_destination,
-            self.basic_flow.protocol,
-            self.basic_flow.first_timestamp,
-            self.basic_flow.last_timestamp,
-            self.basic_flow.get_flow_duration_usec(),
-            self.basic_flow.fwd_packet_count,
-            self.basic_flow.bwd_packet_count,
-            self.fwd_pkt_len_tot,
-            self.bwd_pkt_len_tot,
-            self.fwd_pkt_len_max,
-            self.get_fwd_packet_length_min(),
-            self.fwd_pkt_len_mean,
-            self.fwd_pkt_len_std,
-            self.bwd_pkt_len_max,
-            self.get_bwd_packet_length_min(),
-            self.bwd_pkt_len_mean,
-            self.bwd_pkt_len_std,
-            self.get_flow_bytes_s(),
-            self.get_flow_packets_s(),
-            self.get_flow_iat_mean(),
-            self.get_flow_iat_std(),
-            self.get_flow_iat_max(),
-            self.get_flow_iat_min(),
-            self.fwd_iat_total,
-            self.fwd_iat_mean,
-            self.fwd_iat_std,
-            self.fwd_iat_max,
-            self.get_fwd_iat_min(),
-            self.bwd_iat_total,
-            self.bwd_iat_mean,
-            self.bwd_iat_std,
-            self.bwd_iat_max,
-            self.get_bwd_iat_min(),
-            self.basic_flow.fwd_psh_flag_count,
-            self.basic_flow.bwd_psh_flag_count,
-            self.basic_flow.fwd_urg_flag_count,
-            self.basic_flow.bwd_urg_flag_count,
-            self.fwd_header_length,
-            self.bwd_header_length,
-            self.get_fwd_packets_s(),
-            self.get_bwd_packets_s(),
-            self.get_flow_packet_length_min(),
-            self.get_flow_packet_length_max(),
-            self.get_flow_packet_length_mean(),
-            self.get_flow_packet_length_std(),
-            self.get_flow_packet_length_variance(),
-            self.basic_flow.fwd_fin_flag_count + self.basic_flow.bwd_fin_flag_count,
-            self.basic_flow.fwd_syn_flag_count + self.basic_flow.bwd_syn_flag_count,
-            self.basic_flow.fwd_rst_flag_count + self.basic_flow.bwd_rst_flag_count,
-            self.basic_flow.fwd_psh_flag_count + self.basic_flow.bwd_psh_flag_count,
-            self.basic_flow.fwd_ack_flag_count + self.basic_flow.bwd_ack_flag_count,
-            self.basic_flow.fwd_urg_flag_count + self.basic_flow.bwd_urg_flag_count,
-            self.basic_flow.fwd_cwe_flag_count + self.basic_flow.bwd_cwe_flag_count,
-            self.basic_flow.fwd_ece_flag_count + self.basic_flow.bwd_ece_flag_count,
-            self.get_down_up_ratio(),
-            self.get_flow_segment_length_mean(), // this is a duplicate feature
-            self.get_fwd_segment_length_mean(),
-            self.get_bwd_segment_length_mean(),
-            self.fwd_header_length, // this is a duplicate feature
-            self.get_fwd_bytes_bulk(),
-            self.get_fwd_packets_bulk(),
-            self.get_fwd_bulk_rate(),
-            self.get_bwd_bytes_bulk(),
-            self.get_bwd_packets_bulk(),
-            self.get_bwd_bulk_rate(),
-            self.get_sf_fwd_packets(),
-            self.get_sf_fwd_bytes(),
-            self.get_sf_bwd_packets(),
-            self.get_sf_bwd_bytes(),
-            self.fwd_init_win_bytes,
-            self.bwd_init_win_bytes,
-            self.fwd_act_data_pkt,
-            self.get_fwd_header_len_min(), // known as min_seg_size_forward but this is actually the min header length in the forward direction
-            self.active_mean,
-            self.active_std,
-            self.active_max,
-            self.get_active_min(),
-            self.idle_mean,
-            self.idle_std,
-            self.idle_max,
-            self.get_idle_min(),
-        )
-    }
-
-    fn get_features() -> String {
-        format!(
-            "FLOW_ID,IP_SOURCE,PORT_SOURCE,IP_DESTINATION,PORT_DESTINATION,PROTOCOL,\
-            FIRST_TIMESTAMP,LAST_TIMESTAMP,DURATION,FWD_PACKET_COUNT,BWD_PACKET_COUNT,\
-            FWD_PKT_LEN_TOT,BWD_PKT_LEN_TOT,FWD_PKT_LEN_MAX,FWD_PKT_LEN_MIN,FWD_PKT_LEN_MEAN,\
-            FWD_PKT_LEN_STD,BWD_PKT_LEN_MAX,BWD_PKT_LEN_MIN,BWD_PKT_LEN_MEAN,BWD_PKT_LEN_STD,\
-            FLOW_BYTES_S,FLOW_PACKETS_S,FLOW_IAT_MEAN,FLOW_IAT_STD,FLOW_IAT_MAX,FLOW_IAT_MIN,\
-            FWD_IAT_TOTAL,FWD_IAT_MEAN,FWD_IAT_STD,FWD_IAT_MAX,FWD_IAT_MIN,BWD_IAT_TOTAL,\
-            BWD_IAT_MEAN,BWD_IAT_STD,BWD_IAT_MAX,BWD_IAT_MIN,FWD_PSH_FLAG_COUNT,BWD_PSH_FLAG_COUNT,\
-            FWD_URG_FLAG_COUNT,BWD_URG_FLAG_COUNT,FWD_HEADER_LENGTH,BWD_HEADER_LENGTH,FWD_PACKETS_S,\
-            BWD_PACKETS_S,FLOW_PACKET_LENGTH_MIN,FLOW_PACKET_LENGTH_MAX,FLOW_PACKET_LENGTH_MEAN,\
-            FLOW_PACKET_LENGTH_STD,FLOW_PACKET_LENGTH_VARIANCE,FLOW_FIN_COUNT,FLOW_SYN_COUNT,\
-            FLOW_RST_COUNT,FLOW_PSH_COUNT,FLOW_ACK_COUNT,FLOW_URG_COUNT,FLOW_CWE_COUNT,FLOW_ECE_COUNT,\
-            DOWN_UP_RATIO,FLOW_SEGMENT_LENGTH_MEAN,FWD_SEGMENT_LENGTH_MEAN,BWD_SEGMENT_LENGTH_MEAN,\
-            FWD_HEADER_LENGTH,FWD_BYTES_BULK,FWD_PACKETS_BULK,FWD_BULK_RATE,BWD_BYTES_BULK,\
-            BWD_PACKETS_BULK,BWD_BULK_RATE,SF_FWD_PACKETS,SF_FWD_BYTES,SF_BWD_PACKETS,SF_BWD_BYTES,\
-            FWD_INIT_WIN_BYTES,BWD_INIT_WIN_BYTES,FWD_ACT_DATA_PKT,FWD_HEADER_LEN_MIN,ACTIVE_MEAN,\
-            ACTIVE_STD,ACTIVE_MAX,ACTIVE_MIN,IDLE_MEAN,IDLE_STD,IDLE_MAX,IDLE_MIN")
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{}",
-            self.basic_flow.get_flow_duration_usec(),
-            self.basic_flow.fwd_packet_count,
-            self.basic_flow.bwd_packet_count,
-            self.fwd_pkt_len_tot,
-            self.bwd_pkt_len_tot,
-            self.fwd_pkt_len_max,
-            self.fwd_pkt_len_mean,
-            self.fwd_pkt_len_std,
-            self.bwd_pkt_len_max,
-            self.bwd_pkt_len_mean,
-            self.bwd_pkt_len_std,
-            self.get_flow_bytes_s(),
-            self.get_flow_packets_s(),
-            self.get_flow_iat_mean(),
-            self.get_flow_iat_std(),
-            self.get_flow_iat_max(),
-            self.get_flow_iat_min(),
-            self.fwd_iat_total,
-            self.fwd_iat_mean,
-            self.fwd_iat_std,
-            self.fwd_iat_max,
-            self.get_fwd_iat_min(),
-            self.bwd_iat_total,
-            self.bwd_iat_mean,
-            self.bwd_iat_std,
-            self.bwd_iat_max,
-            self.get_bwd_iat_min(),
-            self.basic_flow.fwd_psh_flag_count,
-            self.basic_flow.bwd_psh_flag_count,
-            self.basic_flow.fwd_urg_flag_count,
-            self.basic_flow.bwd_urg_flag_count,
-            self.fwd_header_length,
-            self.bwd_header_length,
-            self.get_fwd_packets_s(),
-            self.get_bwd_packets_s(),
-            self.get_flow_packet_length_max(),
-            self.get_flow_packet_length_mean(),
-            self.get_flow_packet_length_std(),
-            self.get_flow_packet_length_variance(),
-            self.basic_flow.fwd_fin_flag_count + self.basic_flow.bwd_fin_flag_count,
-            self.basic_flow.fwd_syn_flag_count + self.basic_flow.bwd_syn_flag_count,
-            self.basic_flow.fwd_urg_flag_count + self.basic_flow.bwd_urg_flag_count,
-            self.basic_flow.fwd_cwe_flag_count + self.basic_flow.bwd_cwe_flag_count,
-            self.get_fwd_segment_length_mean(),
-            self.get_bwd_segment_length_mean(),
-            self.get_fwd_bytes_bulk(),
-            self.get_fwd_packets_bulk(),
-            self.get_fwd_bulk_rate(),
-            self.get_bwd_bytes_bulk(),
-            self.get_bwd_packets_bulk(),
-            self.get_bwd_bulk_rate(),
-            self.get_sf_fwd_packets(),
-            self.get_sf_fwd_bytes(),
-            self.get_sf_bwd_packets(),
-            self.get_sf_bwd_bytes(),
-            self.fwd_init_win_bytes,
-            self.bwd_init_win_bytes,
-            self.fwd_act_data_pkt,
-            self.get_fwd_header_len_min(), // known as min_seg_size_forward but this is actually the min header length in the forward direction
-            self.active_mean,
-            self.active_max,
-            self.get_active_min(),
-            self.idle_mean,
-            self.idle_max,
-            self.get_idle_min(),
-        )
-    }
-
-    fn get_features_without_contamination() -> String {
-        format!(
-            "DURATION,FWD_PACKET_COUNT,BWD_PACKET_COUNT,FWD_PKT_LEN_TOT,BWD_PKT_LEN_TOT,\
-            FWD_PKT_LEN_MAX,FWD_PKT_LEN_MEAN,FWD_PKT_LEN_STD,BWD_PKT_LEN_MAX,BWD_PKT_LEN_MEAN,\
-            BWD_PKT_LEN_STD,FLOW_BYTES_S,FLOW_PACKETS_S,FLOW_IAT_MEAN,FLOW_IAT_STD,FLOW_IAT_MAX,\
-            FLOW_IAT_MIN,FWD_IAT_TOTAL,FWD_IAT_MEAN,FWD_IAT_STD,FWD_IAT_MAX,FWD_IAT_MIN,BWD_IAT_TOTAL,\
-            BWD_IAT_MEAN,BWD_IAT_STD,BWD_IAT_MAX,BWD_IAT_MIN,FWD_PSH_FLAG_COUNT,BWD_PSH_FLAG_COUNT,\
-            FWD_URG_FLAG_COUNT,BWD_URG_FLAG_COUNT,FWD_HEADER_LENGTH,BWD_HEADER_LENGTH,FWD_PACKETS_S,\
-            BWD_PACKETS_S,FLOW_PACKET_LENGTH_MAX,FLOW_PACKET_LENGTH_MEAN,FLOW_PACKET_LENGTH_STD,\
-            FLOW_PACKET_LENGTH_VARIANCE,FLOW_FIN_COUNT,FLOW_SYN_COUNT,FLOW_URG_COUNT,FLOW_CWE_COUNT,\
-            FWD_SEGMENT_LENGTH_MEAN,BWD_SEGMENT_LENGTH_MEAN,FWD_BYTES_BULK,FWD_PACKETS_BULK,FWD_BULK_RATE,\
-            BWD_BYTES_BULK,BWD_PACKETS_BULK,BWD_BULK_RATE,SF_FWD_PACKETS,SF_FWD_BYTES,SF_BWD_PACKETS,\
-            SF_BWD_BYTES,FWD_INIT_WIN_BYTES,BWD_INIT_WIN_BYTES,FWD_ACT_DATA_PKT,FWD_HEADER_LEN_MIN,\
-            ACTIVE_MEAN,ACTIVE_MAX,ACTIVE_MIN,IDLE_MEAN,IDLE_MAX,IDLE_MIN"
-        )
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<CicFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(CicFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -1459,6 +1849,14 @@ impl Flow for CicFlow {
     fn flow_key(&self) -> &String {
         &self.basic_flow.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.basic_flow
+    }
 }
 
 // #[cfg(test)]
@@ -1,9 +1,17 @@
 use chrono::{DateTime, Utc};
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
-use crate::packet_features::PacketFeatures;
+use crate::{args::TimestampFormat, packet_features::PacketFeatures};
 
-use super::{basic_flow::BasicFlow, flow::Flow};
+use super::{
+    basic_flow::BasicFlow,
+    flow::Flow,
+    registry::{self, Feature, Unit},
+    settings::FlowSettings,
+};
 
 /// Represents a CIDDS Flow, encapsulating various metrics and states of a network flow.
 ///
@@ -12,8 +20,9 @@ use super::{basic_flow::BasicFlow, flow::Flow};
 pub struct CiddsFlow {
     /// The basic flow information.
     pub basic_flow: BasicFlow,
-    /// The number of bytes in the flow.
-    bytes: u32,
+    /// The number of bytes in the flow, the total IP lengths of its packets as the CIDDS
+    /// dataset defines them, whatever `settings.byte_count` is.
+    bytes: u64,
 }
 
 impl CiddsFlow {
@@ -61,10 +70,69 @@ impl CiddsFlow {
 
         flags
     }
+
+    /// Returns the name of the transport protocol of the flow, `OTHER` if not TCP, UDP or ICMP.
+    fn get_protocol_name(&self) -> &'static str {
+        match self.basic_flow.protocol {
+            6 => "TCP",
+            17 => "UDP",
+            1 => "ICMP",
+            _ => "OTHER",
+        }
+    }
+
+    /// Returns the features of the CIDDS flow, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<CiddsFlow>> {
+        type F = Feature<CiddsFlow>;
+        let basic: fn(&CiddsFlow) -> &BasicFlow = |flow| &flow.basic_flow;
+        vec![
+            F::timestamp(
+                "FIRST_TIMESTAMP",
+                "The time of the first packet",
+                TimestampFormat::Text,
+                |flow| flow.basic_flow.first_timestamp,
+            )
+            .contaminant(),
+            F::new(
+                "DURATION",
+                Unit::Milliseconds,
+                "The time from the first to the last packet",
+                |flow| {
+                    flow.basic_flow
+                        .last_timestamp
+                        .signed_duration_since(flow.basic_flow.first_timestamp)
+                        .num_milliseconds()
+                },
+            ),
+            F::new("PROTOCOL", Unit::None, "TCP, UDP, ICMP or OTHER", |flow| {
+                flow.get_protocol_name()
+            }),
+            registry::ip_feature("SOURCE_IP", true).map(basic),
+            registry::port_feature("SOURCE_PORT", true).map(basic),
+            registry::ip_feature("DESTINATION_IP", false).map(basic),
+            registry::port_feature("DESTINATION_PORT", false).map(basic),
+            F::new("PACKET_COUNT", Unit::Packets, "The packets of both directions", |flow| {
+                flow.basic_flow.fwd_packet_count + flow.basic_flow.bwd_packet_count
+            }),
+            F::new(
+                "BYTES",
+                Unit::Bytes,
+                "The total IP length of the packets of both directions",
+                |flow| flow.bytes,
+            ),
+            F::new(
+                "FLAGS",
+                Unit::None,
+                "The TCP flags seen in the flow as UAPRSF, a dot for each flag not seen",
+                |flow| flow.get_flags_string(),
+            ),
+        ]
+    }
 }
 
 impl Flow for CiddsFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_id: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -75,6 +143,7 @@ impl Flow for CiddsFlow {
     ) -> Self {
         CiddsFlow {
             basic_flow: BasicFlow::new(
+                settings,
                 flow_id,
                 ipv4_source,
                 port_source,
@@ -88,68 +157,13 @@ impl Flow for CiddsFlow {
     }
 
     fn update_flow(&mut self, packet: &PacketFeatures, fwd: bool) -> bool {
-        self.bytes += packet.length as u32;
+        self.bytes += u64::from(packet.length);
         self.basic_flow.update_flow(packet, fwd)
     }
 
-    fn dump(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{}",
-            self.basic_flow.first_timestamp,
-            self.basic_flow
-                .last_timestamp
-                .signed_duration_since(self.basic_flow.first_timestamp)
-                .num_milliseconds(),
-            if self.basic_flow.protocol == 6 {
-                "TCP"
-            } else if self.basic_flow.protocol == 17 {
-                "UDP"
-            } else if self.basic_flow.protocol == 1 {
-                "ICMP"
-            } else {
-                "OTHER"
-            },
-            self.basic_flow.ip_source,
-            self.basic_flow.port_source,
-            self.basic_flow.ip_destination,
-            self.basic_flow.port_destination,
-            self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count,
-            self.bytes,
-            self.get_flags_string(),
-        )
-    }
-
-    fn get_features() -> String {
-        format!(
-            "FIRST_TIMESTAMP,LAST_TIMESTAMP,PROTOCOL,SOURCE_IP,SOURCE_PORT,DESTINATION_IP,\
-            DESTINATION_PORT,PACKET_COUNT,BYTES,FLAGS"
-        )
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        format!(
-            "{},{},{},{},{}",
-            self.basic_flow
-                .last_timestamp
-                .signed_duration_since(self.basic_flow.first_timestamp)
-                .num_milliseconds(),
-            if self.basic_flow.protocol == 6 {
-                "TCP"
-            } else if self.basic_flow.protocol == 17 {
-                "UDP"
-            } else if self.basic_flow.protocol == 1 {
-                "ICMP"
-            } else {
-                "OTHER"
-            },
-            self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count,
-            self.bytes,
-            self.get_flags_string(),
-        )
-    }
-
-    fn get_features_without_contamination() -> String {
-        format!("DURATION,PROTOCOL,PACKET_COUNT,BYTES,FLAGS")
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<CiddsFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(CiddsFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -164,18 +178,35 @@ impl Flow for CiddsFlow {
     fn flow_key(&self) -> &String {
         &self.basic_flow.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.basic_flow
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::{TimeDelta, Utc};
 
-    use crate::flows::flow::Flow;
+    use crate::{
+        flows::flow::Flow,
+        packet_features::PacketFeatures,
+    };
 
     use super::CiddsFlow;
 
     fn setup_ciddsflow() -> CiddsFlow {
         CiddsFlow::new(
+            Arc::default(),
             "".to_string(),
             IpAddr::V4(Ipv4Addr::from(1)),
             80,
@@ -186,6 +217,64 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_bytes() {
+        let mut flow = setup_ciddsflow();
+        let packet = PacketFeatures {
+            source_ip: IpAddr::V4(Ipv4Addr::from(1)),
+            destination_ip: IpAddr::V4(Ipv4Addr::from(2)),
+            source_port: 80,
+            destination_port: 8080,
+            protocol: 6,
+            timestamp: Utc::now(),
+            fin_flag: 0,
+            syn_flag: 0,
+            rst_flag: 0,
+            psh_flag: 0,
+            ack_flag: 1,
+            urg_flag: 0,
+            cwe_flag: 0,
+            ece_flag: 0,
+            data_length: 100,
+            header_length: 20,
+            length: 140,
+            window_size: 1024,
+            sequence_number: 1,
+            sequence_number_ack: 1,
+            extension_header_count: 0,
+            ttl: 64,
+            ip_options_length: 0,
+            ip_flags: 0,
+            tcp_options_mask: 0,
+            flow_label: 0,
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
+        };
+        flow.update_flow(&packet, true);
+        flow.update_flow(&packet, false);
+
+        // The total IP lengths, not the payload the byte features count by default
+        assert_eq!(flow.basic_flow.settings.packet_byte_count(&packet), 100);
+        assert_eq!(flow.bytes, 280);
+    }
+
+    #[test]
+    fn test_duration_feature() {
+        let mut flow = setup_ciddsflow();
+        flow.basic_flow.last_timestamp =
+            flow.basic_flow.first_timestamp + TimeDelta::milliseconds(1500);
+
+        // The second column is the duration, also with the contaminant features
+        let features = CiddsFlow::get_features();
+        let record = flow.dump();
+        assert_eq!(features.split(',').nth(1), Some("DURATION"));
+        assert_eq!(record.split(',').nth(1), Some("1500"));
+        assert!(CiddsFlow::get_features_without_contamination().starts_with("DURATION,"));
+        assert!(flow.dump_without_contamination().starts_with("1500,"));
+    }
+
     #[test]
     fn test_get_flags_string() {
         let mut flow = setup_ciddsflow();
@@ -1,9 +1,17 @@
 use chrono::{DateTime, Utc};
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
 use crate::packet_features::PacketFeatures;
 
-use super::{basic_flow::BasicFlow, flow::Flow};
+use super::{
+    basic_flow::BasicFlow,
+    flow::Flow,
+    registry::{self, Feature, Unit},
+    settings::FlowSettings,
+};
 
 /// Represents a Custom Flow, encapsulating various metrics and states of a network flow.
 ///
@@ -24,7 +32,7 @@ impl CustomFlow {
         if (self.basic_flow.fwd_packet_count + self.basic_flow.bwd_packet_count) > 10 {
             let iat = packet
                 .timestamp
-                .signed_duration_since(self.basic_flow.last_timestamp)
+                .signed_duration_since(self.basic_flow.last_counted_timestamp)
                 .num_nanoseconds()
                 .unwrap() as f64
                 / 1000.0;
@@ -32,10 +40,26 @@ impl CustomFlow {
             self.inter_arrival_time_total += iat;
         }
     }
+
+    /// Returns the features of the custom flow, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<CustomFlow>> {
+        type F = Feature<CustomFlow>;
+        vec![
+            // Add here the features of the custom flow, the contaminant ones marked as such.
+            registry::flow_key_feature("FLOW_KEY").map(|flow: &CustomFlow| &flow.basic_flow),
+            F::new(
+                "INTER_ARRIVAL_TIME_TOTAL",
+                Unit::Microseconds,
+                "The sum of the inter arrival times after the first 10 packets",
+                |flow| flow.inter_arrival_time_total,
+            ),
+        ]
+    }
 }
 
 impl Flow for CustomFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_id: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -46,6 +70,7 @@ impl Flow for CustomFlow {
     ) -> Self {
         CustomFlow {
             basic_flow: BasicFlow::new(
+                settings,
                 flow_id,
                 ipv4_source,
                 port_source,
@@ -72,27 +97,9 @@ impl Flow for CustomFlow {
         is_terminated
     }
 
-    fn dump(&self) -> String {
-        // Add here the dump of the custom flow.
-        format!(
-            "{},{}",
-            self.basic_flow.flow_key, self.inter_arrival_time_total
-        )
-    }
-
-    fn get_features() -> String {
-        // Add here the features of the custom flow.
-        format!("FLOW_KEY,INTER_ARRIVAL_TIME_TOTAL")
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        // Add here the dump of the custom flow without contaminant features.
-        format!("{}", self.inter_arrival_time_total)
-    }
-
-    fn get_features_without_contamination() -> String {
-        // Add here the features of the custom flow without contaminant features.
-        format!("INTER_ARRIVAL_TIME_TOTAL")
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<CustomFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(CustomFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -107,4 +114,12 @@ impl Flow for CustomFlow {
     fn flow_key(&self) -> &String {
         &self.basic_flow.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.basic_flow
+    }
 }
@@ -0,0 +1,140 @@
+//! Accounting of the memory held by the optional variable-length features, like the packet
+//! sequences, against a budget shared by all flows of a run.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use log::warn;
+
+use crate::metrics;
+
+/// The bytes reserved by the optional features of all flows.
+static USED_BYTES: AtomicU64 = AtomicU64::new(0);
+static LIMIT_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// The memory the optional features of one flow hold, released when the flow is dropped.
+///
+/// A copy of a flow (e.g. an interim record) reserves nothing, it only lives until it is
+/// exported.
+#[derive(Debug, Default)]
+pub struct FeatureMemory {
+    reserved: u64,
+    truncated: bool,
+}
+
+impl FeatureMemory {
+    /// Reserves memory for the state of an optional feature.
+    ///
+    /// The flow tables take the features of their oldest flows before a new flow starts at
+    /// the limit, so this only fails if the flows of the other shards hold the budget.
+    ///
+    /// ### Arguments
+    ///
+    /// * `bytes` - The memory the feature is about to allocate.
+    /// * `limit` - The feature memory limit of the run in MiB, if one is set.
+    ///
+    /// ### Returns
+    ///
+    /// `false` if the budget of the run is used up, the feature is then left out for this
+    /// flow and counted in the metrics.
+    pub fn reserve(&mut self, bytes: u64, limit: Option<u64>) -> bool {
+        if try_reserve(&USED_BYTES, limit_bytes(limit), bytes) {
+            self.reserved += bytes;
+            return true;
+        }
+        self.truncate(limit);
+        false
+    }
+
+    /// Returns the memory of the optional features to the budget, once the flow dropped them
+    /// for a younger flow.
+    pub fn release(&mut self, limit: Option<u64>) {
+        USED_BYTES.fetch_sub(std::mem::take(&mut self.reserved), Ordering::Relaxed);
+        self.truncate(limit);
+    }
+
+    /// Marks the flow as truncated, counting it in the metrics the first time.
+    fn truncate(&mut self, limit: Option<u64>) {
+        if self.truncated {
+            return;
+        }
+        self.truncated = true;
+        metrics::add_truncated_feature_flows(1);
+        if !LIMIT_WARNED.swap(true, Ordering::Relaxed) {
+            warn!(
+                "The optional features of the flows use up the feature memory limit of {} MiB, \
+                the oldest flows give them up for the new ones",
+                limit.unwrap_or_default()
+            );
+        }
+    }
+
+    /// Checks whether an optional feature of the flow was left out for lack of memory.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Clone for FeatureMemory {
+    fn clone(&self) -> Self {
+        FeatureMemory {
+            reserved: 0,
+            truncated: self.truncated,
+        }
+    }
+}
+
+impl Drop for FeatureMemory {
+    fn drop(&mut self) {
+        USED_BYTES.fetch_sub(self.reserved, Ordering::Relaxed);
+    }
+}
+
+/// Returns the memory the optional features of all flows hold in bytes.
+pub fn used_bytes() -> u64 {
+    USED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Checks whether `bytes` more fit into the feature memory limit in MiB, if one is set.
+pub fn fits(bytes: u64, limit: Option<u64>) -> bool {
+    limit_bytes(limit).is_none_or(|limit| used_bytes().saturating_add(bytes) <= limit)
+}
+
+fn limit_bytes(limit: Option<u64>) -> Option<u64> {
+    limit.map(|limit| limit.saturating_mul(1024 * 1024))
+}
+
+/// Adds `bytes` to `used` unless that exceeds `limit`.
+fn try_reserve(used: &AtomicU64, limit: Option<u64>, bytes: u64) -> bool {
+    match limit {
+        Some(limit) => used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|total| *total <= limit)
+            })
+            .is_ok(),
+        None => {
+            used.fetch_add(bytes, Ordering::Relaxed);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::try_reserve;
+
+    #[test]
+    fn test_try_reserve() {
+        let used = AtomicU64::new(0);
+        assert!(try_reserve(&used, Some(100), 80));
+        assert!(try_reserve(&used, Some(100), 20));
+        // A reservation beyond the limit is refused and reserves nothing
+        assert!(!try_reserve(&used, Some(100), 1));
+        assert_eq!(used.load(Ordering::Relaxed), 100);
+
+        // Without a limit, the memory is still counted
+        assert!(try_reserve(&used, None, 1000));
+        assert_eq!(used.load(Ordering::Relaxed), 1100);
+    }
+}
@@ -1,13 +1,21 @@
 use chrono::{DateTime, Utc};
-use std::net::IpAddr;
+use std::{net::IpAddr, sync::Arc};
 
 use crate::packet_features::PacketFeatures;
 
+use super::{
+    basic_flow::BasicFlow,
+    registry::{self, Feature},
+    settings::FlowSettings,
+};
+
 /// `Flow` defines the behavior of a network flow.
 ///
 /// This trait should be implemented by structures that represent
 /// a network flow, providing mechanisms to update the flow state
 /// and to dump its current state into a string format.
+///
+/// The dumped features are those of the registry of the flow type, see `features`.
 pub trait Flow: Send + Sync + 'static + Clone {
     /// Constructs a new `Flow`.
     ///
@@ -16,6 +24,7 @@ pub trait Flow: Send + Sync + 'static + Clone {
     ///
     /// ### Arguments
     ///
+    /// * `settings` - The settings of the run, shared by all flows.
     /// * `flow_key` - A unique identifier for the flow.
     /// * `ipv4_source` - The source IPv4 address.
     /// * `port_source` - The source port.
@@ -28,6 +37,7 @@ pub trait Flow: Send + Sync + 'static + Clone {
     ///
     /// Returns a new instance of `Flow`.
     fn new(
+        settings: Arc<FlowSettings>,
         flow_key: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -40,6 +50,30 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// Returns the flow key.
     fn flow_key(&self) -> &String;
 
+    /// Returns the basic flow that every flow type builds upon.
+    ///
+    /// This gives access to the features shared by all flow types, such as the
+    /// endpoints, timestamps and packet counts, without knowing the concrete type.
+    ///
+    /// ### Returns
+    ///
+    /// Returns a reference to the underlying `BasicFlow`.
+    fn basic_flow(&self) -> &BasicFlow;
+
+    /// Returns the basic flow that every flow type builds upon, for changes shared by all
+    /// flow types such as marking an interim record.
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow;
+
+    /// Links the flow to the session of a related flow, e.g. an FTP data connection to its
+    /// control connection.
+    ///
+    /// Flow types without a session feature ignore the session id.
+    ///
+    /// ### Arguments
+    ///
+    /// * `session_id` - The flow key of the flow that started the session.
+    fn set_session_id(&mut self, _session_id: String) {}
+
     /// Updates the flow with a new packet.
     ///
     /// This method processes a packet and updates the internal state of the flow
@@ -55,6 +89,17 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// Returns an `boolean` indicating if the flow is terminated.
     fn update_flow(&mut self, packet: &PacketFeatures, fwd: bool) -> bool;
 
+    /// Returns the features of the flow type, the entries of the feature registry.
+    ///
+    /// The entries are built once and kept for the run, in the order the features are
+    /// exported. Each has the name, type, unit and description of a feature and takes its
+    /// value from a flow, see `registry::Feature`.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the features of the flow type in their order.
+    fn features() -> &'static [Feature<Self>];
+
     /// Dumps the current state of the flow.
     ///
     /// This method returns a string representation of the current state of the flow.
@@ -62,7 +107,9 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// ### Returns
     ///
     /// Returns a `String` that represents the current state of the flow.
-    fn dump(&self) -> String;
+    fn dump(&self) -> String {
+        registry::dump_features(registry::flow_features::<Self>(false), self)
+    }
 
     /// Dumps the current state of the flow without contaminant features.
     ///
@@ -71,7 +118,9 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// ### Returns
     ///
     /// Returns a `String` that represents the current state of the flow without contaminant features.
-    fn dump_without_contamination(&self) -> String;
+    fn dump_without_contamination(&self) -> String {
+        registry::dump_features(registry::flow_features::<Self>(true), self)
+    }
 
     /// Returns the first timestamp of the flow.
     ///
@@ -89,7 +138,9 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// ### Returns
     ///
     /// Returns a `String` that represents the features of the flow.
-    fn get_features() -> String;
+    fn get_features() -> String {
+        registry::feature_names(registry::flow_features::<Self>(false))
+    }
 
     /// Returns a first record with the features of the flow without contaminant features.
     ///
@@ -98,7 +149,9 @@ pub trait Flow: Send + Sync + 'static + Clone {
     /// ### Returns
     ///
     /// Returns a `String` that represents the features of the flow without contaminant features.
-    fn get_features_without_contamination() -> String;
+    fn get_features_without_contamination() -> String {
+        registry::feature_names(registry::flow_features::<Self>(true))
+    }
 
     /// Checks if the flow is expired.
     ///
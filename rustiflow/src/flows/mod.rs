@@ -1,8 +1,14 @@
+pub mod anonymize;
 pub mod basic_flow;
 pub mod cic_flow;
 pub mod cidds_flow;
 pub mod custom_flow;
+pub mod feature_memory;
 pub mod flow;
 pub mod nf_flow;
 pub mod ntl_flow;
+pub mod registry;
+pub mod rusti_flow;
+pub mod settings;
+pub mod signatures;
 pub mod util;
@@ -1,10 +1,22 @@
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
 use chrono::{DateTime, Utc};
 
-use crate::packet_features::PacketFeatures;
+use crate::{
+    args::{FeatureGroup, TimestampFormat},
+    packet_features::PacketFeatures,
+};
 
-use super::{cic_flow::CicFlow, flow::Flow};
+use super::{
+    basic_flow::BasicFlow,
+    cic_flow::CicFlow,
+    flow::Flow,
+    registry::{self, Feature, FeatureType, Unit, Value},
+    settings::FlowSettings,
+};
 
 /// Represents a Nfstream inspired Flow, encapsulating various metrics and states of a network flow.
 ///
@@ -33,25 +45,364 @@ impl NfFlow {
         .num_milliseconds()
     }
 
-    fn get_first_bwd_timestamp(&self) -> i64 {
-        if self.bwd_first_timestamp.is_none() {
-            return 0;
-        }
+    fn get_first_bwd_timestamp(&self) -> Value {
+        dump_timestamp(&self.cic_flow.basic_flow.settings, self.bwd_first_timestamp)
+    }
 
-        self.bwd_first_timestamp.unwrap().timestamp_millis()
+    fn get_bwd_last_timestamp(&self) -> Value {
+        dump_timestamp(&self.cic_flow.basic_flow.settings, self.bwd_last_timestamp)
     }
 
-    fn get_bwd_last_timestamp(&self) -> i64 {
-        if self.bwd_last_timestamp.is_none() {
-            return 0;
-        }
+    /// Returns the features of the nfstream flow, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<NfFlow>> {
+        type F = Feature<NfFlow>;
+        let basic: fn(&NfFlow) -> &BasicFlow = |flow| &flow.cic_flow.basic_flow;
+        // In epoch milliseconds like nfstream, see `dump_timestamp`
+        let timestamp = FeatureType::Timestamp(TimestampFormat::EpochMillis);
+        vec![
+            registry::flow_key_feature("FLOW_ID").map(basic),
+            registry::ip_feature("IP_SRC", true).map(basic),
+            registry::port_feature("PORT_SRC", true).map(basic),
+            registry::ip_feature("IP_DST", false).map(basic),
+            registry::port_feature("PORT_DST", false).map(basic),
+            F::new("PROTOCOL", Unit::None, "The IP protocol number", |flow| {
+                flow.cic_flow.basic_flow.protocol
+            }),
+            F::with_type(
+                "FIRST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the first packet",
+                |flow| dump_timestamp(&flow.cic_flow.basic_flow.settings, Some(flow.first_timestamp)),
+            )
+            .contaminant(),
+            F::with_type(
+                "LAST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the last packet",
+                |flow| dump_timestamp(&flow.cic_flow.basic_flow.settings, Some(flow.last_timestamp)),
+            )
+            .contaminant(),
+            F::new(
+                "DURATION",
+                Unit::Milliseconds,
+                "The time from the first to the last packet",
+                |flow| {
+                    flow.last_timestamp
+                        .signed_duration_since(flow.first_timestamp)
+                        .num_milliseconds()
+                },
+            ),
+            F::new("FLOW_PKTS", Unit::Packets, "The packets of both directions", |flow| {
+                flow.cic_flow.basic_flow.fwd_packet_count
+                    + flow.cic_flow.basic_flow.bwd_packet_count
+            }),
+            F::new("FLOW_BYTES", Unit::Bytes, "The bytes of both directions", |flow| {
+                flow.cic_flow.fwd_pkt_len_tot + flow.cic_flow.bwd_pkt_len_tot
+            }),
+            F::with_type(
+                "FWD_FIRST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the first forward packet",
+                |flow| dump_timestamp(&flow.cic_flow.basic_flow.settings, Some(flow.fwd_first_timestamp)),
+            )
+            .contaminant(),
+            F::with_type(
+                "FWD_LAST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the last forward packet",
+                |flow| dump_timestamp(&flow.cic_flow.basic_flow.settings, Some(flow.fwd_last_timestamp)),
+            )
+            .contaminant(),
+            F::new(
+                "FWD_DURATION",
+                Unit::Milliseconds,
+                "The time from the first to the last forward packet",
+                |flow| {
+                    flow.fwd_last_timestamp
+                        .signed_duration_since(flow.fwd_first_timestamp)
+                        .num_milliseconds()
+                },
+            ),
+            F::new("FWD_PKTS", Unit::Packets, "The forward packets", |flow| {
+                flow.cic_flow.basic_flow.fwd_packet_count
+            }),
+            F::new("FWD_BYTES", Unit::Bytes, "The forward bytes", |flow| {
+                flow.cic_flow.fwd_pkt_len_tot
+            }),
+            F::with_type(
+                "BWD_FIRST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the first backward packet",
+                |flow| flow.get_first_bwd_timestamp(),
+            )
+            .nullable()
+            .contaminant(),
+            F::with_type(
+                "BWD_LAST_TS",
+                timestamp,
+                Unit::None,
+                "The time of the last backward packet",
+                |flow| flow.get_bwd_last_timestamp(),
+            )
+            .nullable()
+            .contaminant(),
+            F::new(
+                "BWD_DURATION",
+                Unit::Milliseconds,
+                "The time from the first to the last backward packet",
+                |flow| flow.get_bwd_duration(),
+            ),
+            F::new("BWD_PKTS", Unit::Packets, "The backward packets", |flow| {
+                flow.cic_flow.basic_flow.bwd_packet_count
+            }),
+            F::new("BWD_BYTES", Unit::Bytes, "The backward bytes", |flow| {
+                flow.cic_flow.bwd_pkt_len_tot
+            }),
+            F::new("FLOW_PKT_LEN_MIN", Unit::Bytes, "The shortest packet", |flow| {
+                flow.cic_flow.get_flow_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FLOW_PKT_LEN_MEAN", Unit::Bytes, "The mean packet length", |flow| {
+                flow.cic_flow.get_flow_packet_length_mean()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FLOW_PKT_LEN_STD",
+                Unit::Bytes,
+                "The standard deviation of the packet lengths",
+                |flow| flow.cic_flow.get_flow_packet_length_std(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("FLOW_PKT_LEN_MAX", Unit::Bytes, "The longest packet", |flow| {
+                flow.cic_flow.get_flow_packet_length_max()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FWD_PKT_LEN_MIN", Unit::Bytes, "The shortest forward packet", |flow| {
+                flow.cic_flow.get_fwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PKT_LEN_MEAN",
+                Unit::Bytes,
+                "The mean length of the forward packets",
+                |flow| flow.cic_flow.fwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PKT_LEN_STD",
+                Unit::Bytes,
+                "The standard deviation of the forward packet lengths",
+                |flow| flow.cic_flow.fwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("FWD_PKT_LEN_MAX", Unit::Bytes, "The longest forward packet", |flow| {
+                flow.cic_flow.fwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PKT_LEN_MIN", Unit::Bytes, "The shortest backward packet", |flow| {
+                flow.cic_flow.get_bwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PKT_LEN_MEAN",
+                Unit::Bytes,
+                "The mean length of the backward packets",
+                |flow| flow.cic_flow.bwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PKT_LEN_STD",
+                Unit::Bytes,
+                "The standard deviation of the backward packet lengths",
+                |flow| flow.cic_flow.bwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PKT_LEN_MAX", Unit::Bytes, "The longest backward packet", |flow| {
+                flow.cic_flow.bwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FLOW_IAT_MIN", Unit::Milliseconds, "The shortest inter arrival time", |flow| {
+                flow.cic_flow.get_flow_iat_min() / 1000.0
+            })
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_MEAN",
+                Unit::Milliseconds,
+                "The mean inter arrival time of the packets",
+                |flow| flow.cic_flow.get_flow_iat_mean() / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_STD",
+                Unit::Milliseconds,
+                "The standard deviation of the inter arrival times",
+                |flow| flow.cic_flow.get_flow_iat_std() / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_IAT_MAX", Unit::Milliseconds, "The longest inter arrival time", |flow| {
+                flow.cic_flow.get_flow_iat_max() / 1000.0
+            })
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MIN",
+                Unit::Milliseconds,
+                "The shortest forward inter arrival time",
+                |flow| flow.cic_flow.get_fwd_iat_min() / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MEAN",
+                Unit::Milliseconds,
+                "The mean forward inter arrival time",
+                |flow| flow.cic_flow.fwd_iat_mean / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_STD",
+                Unit::Milliseconds,
+                "The standard deviation of the forward inter arrival times",
+                |flow| flow.cic_flow.fwd_iat_std / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MAX",
+                Unit::Milliseconds,
+                "The longest forward inter arrival time",
+                |flow| flow.cic_flow.fwd_iat_max / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MIN",
+                Unit::Milliseconds,
+                "The shortest backward inter arrival time",
+                |flow| flow.cic_flow.get_bwd_iat_min() / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MEAN",
+                Unit::Milliseconds,
+                "The mean backward inter arrival time",
+                |flow| flow.cic_flow.bwd_iat_mean / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_STD",
+                Unit::Milliseconds,
+                "The standard deviation of the backward inter arrival times",
+                |flow| flow.cic_flow.bwd_iat_std / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MAX",
+                Unit::Milliseconds,
+                "The longest backward inter arrival time",
+                |flow| flow.cic_flow.bwd_iat_max / 1000.0,
+            )
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_SYN", Unit::Packets, "The packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.fwd_syn_flag_count
+                    + flow.cic_flow.basic_flow.bwd_syn_flag_count
+            }),
+            F::new("FLOW_CWE", Unit::Packets, "The packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.fwd_cwe_flag_count
+                    + flow.cic_flow.basic_flow.bwd_cwe_flag_count
+            }),
+            F::new("FLOW_ECE", Unit::Packets, "The packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.fwd_ece_flag_count
+                    + flow.cic_flow.basic_flow.bwd_ece_flag_count
+            }),
+            F::new("FLOW_URG", Unit::Packets, "The packets with URG", |flow| {
+                flow.cic_flow.basic_flow.fwd_urg_flag_count
+                    + flow.cic_flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new("FLOW_ACK", Unit::Packets, "The packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.fwd_ack_flag_count
+                    + flow.cic_flow.basic_flow.bwd_ack_flag_count
+            }),
+            F::new("FLOW_PSH", Unit::Packets, "The packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.fwd_psh_flag_count
+                    + flow.cic_flow.basic_flow.bwd_psh_flag_count
+            }),
+            F::new("FLOW_RST", Unit::Packets, "The packets with RST", |flow| {
+                flow.cic_flow.basic_flow.fwd_rst_flag_count
+                    + flow.cic_flow.basic_flow.bwd_rst_flag_count
+            }),
+            F::new("FLOW_FIN", Unit::Packets, "The packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.fwd_fin_flag_count
+                    + flow.cic_flow.basic_flow.bwd_fin_flag_count
+            }),
+            F::new("FWD_SYN", Unit::Packets, "The forward packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.fwd_syn_flag_count
+            }),
+            F::new("FWD_CWE", Unit::Packets, "The forward packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.fwd_cwe_flag_count
+            }),
+            F::new("FWD_ECE", Unit::Packets, "The forward packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.fwd_ece_flag_count
+            }),
+            F::new("FWD_URG", Unit::Packets, "The forward packets with URG", |flow| {
+                flow.cic_flow.basic_flow.fwd_urg_flag_count
+            }),
+            F::new("FWD_ACK", Unit::Packets, "The forward packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.fwd_ack_flag_count
+            }),
+            F::new("FWD_PSH", Unit::Packets, "The forward packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.fwd_psh_flag_count
+            }),
+            F::new("FWD_RST", Unit::Packets, "The forward packets with RST", |flow| {
+                flow.cic_flow.basic_flow.fwd_rst_flag_count
+            }),
+            F::new("FWD_FIN", Unit::Packets, "The forward packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.fwd_fin_flag_count
+            }),
+            F::new("BWD_SYN", Unit::Packets, "The backward packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.bwd_syn_flag_count
+            }),
+            F::new("BWD_CWE", Unit::Packets, "The backward packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.bwd_cwe_flag_count
+            }),
+            F::new("BWD_ECE", Unit::Packets, "The backward packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.bwd_ece_flag_count
+            }),
+            F::new("BWD_URG", Unit::Packets, "The backward packets with URG", |flow| {
+                flow.cic_flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new("BWD_ACK", Unit::Packets, "The backward packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.bwd_ack_flag_count
+            }),
+            F::new("BWD_PSH", Unit::Packets, "The backward packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.bwd_psh_flag_count
+            }),
+            F::new("BWD_RST", Unit::Packets, "The backward packets with RST", |flow| {
+                flow.cic_flow.basic_flow.bwd_rst_flag_count
+            }),
+            F::new("BWD_FIN", Unit::Packets, "The backward packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.bwd_fin_flag_count
+            }),
+        ]
+    }
+}
 
-        self.bwd_last_timestamp.unwrap().timestamp_millis()
+/// Returns the value of a timestamp, in epoch milliseconds like nfstream unless another format
+/// is configured.
+///
+/// A missing timestamp is 0 like in nfstream, or the null value with a configured format.
+fn dump_timestamp(settings: &FlowSettings, timestamp: Option<DateTime<Utc>>) -> Value {
+    match timestamp {
+        Some(timestamp) => Value::Timestamp(timestamp, TimestampFormat::EpochMillis),
+        None if settings.timestamp_format == TimestampFormat::Default => Value::Signed(0),
+        None => Value::Null,
     }
 }
 
 impl Flow for NfFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_id: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -62,6 +413,7 @@ impl Flow for NfFlow {
     ) -> Self {
         NfFlow {
             cic_flow: CicFlow::new(
+                settings,
                 flow_id,
                 ipv4_source,
                 port_source,
@@ -92,186 +444,9 @@ impl Flow for NfFlow {
         is_terminated
     }
 
-    fn dump(&self) -> String {
-        format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            self.cic_flow.basic_flow.flow_key,
-            self.cic_flow.basic_flow.ip_source,
-            self.cic_flow.basic_flow.port_source,
-            self.cic_flow.basic_flow.ip_destination,
-            self.cic_flow.basic_flow.port_destination,
-            self.cic_flow.basic_flow.protocol,
-            self.first_timestamp.timestamp_millis(),
-            self.last_timestamp.timestamp_millis(),
-            self.last_timestamp.signed_duration_since(self.first_timestamp).num_milliseconds(),
-            self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot,
-            self.fwd_first_timestamp.timestamp_millis(),
-            self.fwd_last_timestamp.timestamp_millis(),
-            self.fwd_last_timestamp.signed_duration_since(self.fwd_first_timestamp).num_milliseconds(),
-            self.cic_flow.basic_flow.fwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot,
-            self.get_first_bwd_timestamp(),
-            self.get_bwd_last_timestamp(),
-            self.get_bwd_duration(),
-            self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.get_flow_packet_length_min(),
-            self.cic_flow.get_flow_packet_length_mean(),
-            self.cic_flow.get_flow_packet_length_std(),
-            self.cic_flow.get_flow_packet_length_max(),
-            self.cic_flow.get_fwd_packet_length_min(),
-            self.cic_flow.fwd_pkt_len_mean,
-            self.cic_flow.fwd_pkt_len_std,
-            self.cic_flow.fwd_pkt_len_max,
-            self.cic_flow.get_bwd_packet_length_min(),
-            self.cic_flow.bwd_pkt_len_mean,
-            self.cic_flow.bwd_pkt_len_std,
-            self.cic_flow.bwd_pkt_len_max,
-            self.cic_flow.get_flow_iat_min() / 1000.0,
-            self.cic_flow.get_flow_iat_mean() / 1000.0,
-            self.cic_flow.get_flow_iat_std() / 1000.0,
-            self.cic_flow.get_flow_iat_max() / 1000.0,
-            self.cic_flow.get_fwd_iat_min() / 1000.0,
-            self.cic_flow.fwd_iat_mean / 1000.0,
-            self.cic_flow.fwd_iat_std / 1000.0,
-            self.cic_flow.fwd_iat_max / 1000.0,
-            self.cic_flow.get_bwd_iat_min() / 1000.0,
-            self.cic_flow.bwd_iat_mean / 1000.0,
-            self.cic_flow.bwd_iat_std / 1000.0,
-            self.cic_flow.bwd_iat_max / 1000.0,
-            self.cic_flow.basic_flow.fwd_syn_flag_count + self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count + self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count + self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count + self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count + self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count + self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count + self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count + self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count,
-            self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.bwd_fin_flag_count,
-        )
-    }
-
-    fn get_features() -> String {
-        format!(
-            "FLOW_ID,IP_SRC,PORT_SRC,IP_DST,PORT_DST,PROTOCOL,FIRST_TS,LAST_TS,\
-            DURATION,FLOW_PKTS,FLOW_BYTES,FWD_FIRST_TS,FWD_LAST_TS,FWD_DURATION,FWD_PKTS,\
-            FWD_BYTES,BWD_FIRST_TS,BWD_LAST_TS,BWD_DURATION,BWD_PKTS,BWD_BYTES,FLOW_PKT_LEN_MIN,\
-            FLOW_PKT_LEN_MEAN,FLOW_PKT_LEN_STD,FLOW_PKT_LEN_MAX,FWD_PKT_LEN_MIN,FWD_PKT_LEN_MEAN,\
-            FWD_PKT_LEN_STD,FWD_PKT_LEN_MAX,BWD_PKT_LEN_MIN,BWD_PKT_LEN_MEAN,BWD_PKT_LEN_STD,\
-            BWD_PKT_LEN_MAX,FLOW_IAT_MIN,FLOW_IAT_MEAN,FLOW_IAT_STD,FLOW_IAT_MAX,FWD_IAT_MIN,\
-            FWD_IAT_MEAN,FWD_IAT_STD,FWD_IAT_MAX,BWD_IAT_MIN,BWD_IAT_MEAN,BWD_IAT_STD,\
-            BWD_IAT_MAX,FLOW_SYN,FLOW_CWE,FLOW_ECE,FLOW_URG,FLOW_ACK,FLOW_PSH,FLOW_RST,\
-            FLOW_FIN,FWD_SYN,FWD_CWE,FWD_ECE,FWD_URG,FWD_ACK,FWD_PSH,FWD_RST,FWD_FIN,\
-            BWD_SYN,BWD_CWE,BWD_ECE,BWD_URG,BWD_ACK,BWD_PSH,BWD_RST,BWD_FIN"
-        )
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},\
-        {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-        {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-        {},{},{},{},{},{},{},{},{},{},{},{},{}",
-            self.cic_flow.basic_flow.protocol,
-            self.last_timestamp
-                .signed_duration_since(self.first_timestamp)
-                .num_milliseconds(),
-            self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot,
-            self.fwd_last_timestamp
-                .signed_duration_since(self.fwd_first_timestamp)
-                .num_milliseconds(),
-            self.cic_flow.basic_flow.fwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot,
-            self.get_bwd_duration(),
-            self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.get_flow_packet_length_min(),
-            self.cic_flow.get_flow_packet_length_mean(),
-            self.cic_flow.get_flow_packet_length_std(),
-            self.cic_flow.get_flow_packet_length_max(),
-            self.cic_flow.get_fwd_packet_length_min(),
-            self.cic_flow.fwd_pkt_len_mean,
-            self.cic_flow.fwd_pkt_len_std,
-            self.cic_flow.fwd_pkt_len_max,
-            self.cic_flow.get_bwd_packet_length_min(),
-            self.cic_flow.bwd_pkt_len_mean,
-            self.cic_flow.bwd_pkt_len_std,
-            self.cic_flow.bwd_pkt_len_max,
-            self.cic_flow.get_flow_iat_min() / 1000.0,
-            self.cic_flow.get_flow_iat_mean() / 1000.0,
-            self.cic_flow.get_flow_iat_std() / 1000.0,
-            self.cic_flow.get_flow_iat_max() / 1000.0,
-            self.cic_flow.get_fwd_iat_min() / 1000.0,
-            self.cic_flow.fwd_iat_mean / 1000.0,
-            self.cic_flow.fwd_iat_std / 1000.0,
-            self.cic_flow.fwd_iat_max / 1000.0,
-            self.cic_flow.get_bwd_iat_min() / 1000.0,
-            self.cic_flow.bwd_iat_mean / 1000.0,
-            self.cic_flow.bwd_iat_std / 1000.0,
-            self.cic_flow.bwd_iat_max / 1000.0,
-            self.cic_flow.basic_flow.fwd_syn_flag_count
-                + self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count
-                + self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count
-                + self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count
-                + self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count
-                + self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count
-                + self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count
-                + self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count
-                + self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count,
-            self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.bwd_fin_flag_count,
-        )
-    }
-
-    fn get_features_without_contamination() -> String {
-        format!(
-            "PROTOCOL,DURATION,FLOW_PKTS,FLOW_BYTES,FWD_DURATION,FWD_PKTS,FWD_BYTES,\
-            BWD_DURATION,BWD_PKTS,BWD_BYTES,FLOW_PKT_LEN_MIN,FLOW_PKT_LEN_MEAN,FLOW_PKT_LEN_STD,\
-            FLOW_PKT_LEN_MAX,FWD_PKT_LEN_MIN,FWD_PKT_LEN_MEAN,FWD_PKT_LEN_STD,FWD_PKT_LEN_MAX,\
-            BWD_PKT_LEN_MIN,BWD_PKT_LEN_MEAN,BWD_PKT_LEN_STD,BWD_PKT_LEN_MAX,FLOW_IAT_MIN,\
-            FLOW_IAT_MEAN,FLOW_IAT_STD,FLOW_IAT_MAX,FWD_IAT_MIN,FWD_IAT_MEAN,FWD_IAT_STD,\
-            FWD_IAT_MAX,BWD_IAT_MIN,BWD_IAT_MEAN,BWD_IAT_STD,BWD_IAT_MAX,FLOW_SYN,FLOW_CWE,\
-            FLOW_ECE,FLOW_URG,FLOW_ACK,FLOW_PSH,FLOW_RST,FLOW_FIN,FWD_SYN,FWD_CWE,FWD_ECE,\
-            FWD_URG,FWD_ACK,FWD_PSH,FWD_RST,FWD_FIN,BWD_SYN,BWD_CWE,BWD_ECE,BWD_URG,BWD_ACK,\
-            BWD_PSH,BWD_RST,BWD_FIN"
-        )
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<NfFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(NfFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -286,4 +461,12 @@ impl Flow for NfFlow {
     fn flow_key(&self) -> &String {
         &self.cic_flow.basic_flow.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.cic_flow.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.cic_flow.basic_flow
+    }
 }
@@ -1,11 +1,17 @@
-use chrono::{DateTime, Utc};
-use std::net::IpAddr;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
-use crate::packet_features::PacketFeatures;
+use crate::{args::FeatureGroup, packet_features::PacketFeatures};
 
 use super::{
+    basic_flow::BasicFlow,
     cic_flow::CicFlow,
     flow::Flow,
+    registry::{self, Feature, Unit},
+    settings::FlowSettings,
     util::{calculate_mean, calculate_std},
 };
 
@@ -54,16 +60,16 @@ impl NTLFlow {
         }
 
         // update total
-        self.cic_flow.fwd_header_length += len;
+        self.cic_flow.fwd_header_length += u64::from(len);
 
         // update mean and std
         let new_fwd_header_len_mean = calculate_mean(
-            self.cic_flow.basic_flow.fwd_packet_count as u64,
+            self.cic_flow.basic_flow.fwd_packet_count,
             self.fwd_header_len_mean as f64,
             len as f64,
         ) as f32;
         self.fwd_header_len_std = calculate_std(
-            self.cic_flow.basic_flow.fwd_packet_count as u64,
+            self.cic_flow.basic_flow.fwd_packet_count,
             self.fwd_header_len_std as f64,
             self.fwd_header_len_mean as f64,
             new_fwd_header_len_mean as f64,
@@ -91,16 +97,16 @@ impl NTLFlow {
         }
 
         // update total
-        self.cic_flow.bwd_header_length += len;
+        self.cic_flow.bwd_header_length += u64::from(len);
 
         // update mean and std
         let new_bwd_header_len_mean = calculate_mean(
-            self.cic_flow.basic_flow.bwd_packet_count as u64,
+            self.cic_flow.basic_flow.bwd_packet_count,
             self.bwd_header_len_mean as f64,
             len as f64,
         ) as f32;
         self.bwd_header_len_std = calculate_std(
-            self.cic_flow.basic_flow.bwd_packet_count as u64,
+            self.cic_flow.basic_flow.bwd_packet_count,
             self.bwd_header_len_std as f64,
             self.bwd_header_len_mean as f64,
             new_bwd_header_len_mean as f64,
@@ -227,10 +233,623 @@ impl NTLFlow {
     pub fn get_flow_header_length_std(&self) -> f64 {
         self.get_flow_header_length_variance().sqrt()
     }
+
+    /// Returns the time from the first to the last packet of the flow.
+    fn get_duration(&self) -> TimeDelta {
+        self.cic_flow.basic_flow.last_timestamp - self.cic_flow.basic_flow.first_timestamp
+    }
+
+    /// Returns a count per millisecond of the flow duration, divided by 1000, as the NTL rates
+    /// are computed.
+    fn get_rate(&self, count: u64) -> f64 {
+        count as f64 / self.get_duration().num_milliseconds() as f64 / 1000.0
+    }
+
+    /// Returns the features of the NTL flow, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<NTLFlow>> {
+        type F = Feature<NTLFlow>;
+        let basic: fn(&NTLFlow) -> &BasicFlow = |flow| &flow.cic_flow.basic_flow;
+        vec![
+            registry::flow_key_feature("FLOW_ID").map(basic),
+            registry::ip_feature("IP_SOURCE", true).map(basic),
+            registry::port_feature("PORT_SOURCE", true).map(basic),
+            registry::ip_feature("IP_DESTINATION", false).map(basic),
+            registry::port_feature("PORT_DESTINATION", false).map(basic),
+            F::new("PROTOCOL", Unit::None, "The IP protocol number", |flow| {
+                flow.cic_flow.basic_flow.protocol
+            })
+            .contaminant(),
+            F::new(
+                "DURATION",
+                Unit::Microseconds,
+                "The time from the first to the last packet",
+                |flow| flow.get_duration().num_microseconds().unwrap(),
+            ),
+            F::new("TOTAL_PACKETS", Unit::Packets, "The packets of both directions", |flow| {
+                flow.cic_flow.basic_flow.fwd_packet_count
+                    + flow.cic_flow.basic_flow.bwd_packet_count
+            }),
+            F::new("FWD_PACKETS", Unit::Packets, "The forward packets", |flow| {
+                flow.cic_flow.basic_flow.fwd_packet_count
+            }),
+            F::new("BWD_PACKETS", Unit::Packets, "The backward packets", |flow| {
+                flow.cic_flow.basic_flow.bwd_packet_count
+            }),
+            F::new("TOTAL_BYTES", Unit::Bytes, "The bytes of both directions", |flow| {
+                flow.cic_flow.fwd_pkt_len_tot + flow.cic_flow.bwd_pkt_len_tot
+            }),
+            F::new("FWD_BYTES", Unit::Bytes, "The forward bytes", |flow| {
+                flow.cic_flow.fwd_pkt_len_tot
+            }),
+            F::new("BWD_BYTES", Unit::Bytes, "The backward bytes", |flow| {
+                flow.cic_flow.bwd_pkt_len_tot
+            }),
+            F::new("PACKET_LENGTH_MAX", Unit::Bytes, "The longest packet", |flow| {
+                flow.cic_flow.get_flow_packet_length_max()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("PACKET_LENGTH_MIN", Unit::Bytes, "The shortest packet", |flow| {
+                flow.cic_flow.get_flow_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("PACKET_LENGTH_MEAN", Unit::Bytes, "The mean packet length", |flow| {
+                flow.cic_flow.get_flow_packet_length_mean()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "PACKET_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the packet lengths",
+                |flow| flow.cic_flow.get_flow_packet_length_std(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the packet lengths",
+                |flow| flow.cic_flow.get_flow_packet_length_variance(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("FWD_PACKET_LENGTH_MAX", Unit::Bytes, "The longest forward packet", |flow| {
+                flow.cic_flow.fwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("FWD_PACKET_LENGTH_MIN", Unit::Bytes, "The shortest forward packet", |flow| {
+                flow.cic_flow.get_fwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PACKET_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the forward packets",
+                |flow| flow.cic_flow.fwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PACKET_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the forward packet lengths",
+                |flow| flow.cic_flow.fwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "FWD_PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the forward packet lengths",
+                |flow| flow.cic_flow.fwd_pkt_len_std.powf(2.0),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PACKET_LENGTH_MAX", Unit::Bytes, "The longest backward packet", |flow| {
+                flow.cic_flow.bwd_pkt_len_max
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new("BWD_PACKET_LENGTH_MIN", Unit::Bytes, "The shortest backward packet", |flow| {
+                flow.cic_flow.get_bwd_packet_length_min()
+            })
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PACKET_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the backward packets",
+                |flow| flow.cic_flow.bwd_pkt_len_mean,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PACKET_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the backward packet lengths",
+                |flow| flow.cic_flow.bwd_pkt_len_std,
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the backward packet lengths",
+                |flow| flow.cic_flow.bwd_pkt_len_std.powf(2.0),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "HEADER_LENGTH_TOTAL",
+                Unit::Bytes,
+                "The total header length of the packets",
+                |flow| flow.cic_flow.fwd_header_length + flow.cic_flow.bwd_header_length,
+            ),
+            F::new("HEADER_LENGTH_MAX", Unit::Bytes, "The longest header", |flow| {
+                flow.get_flow_header_length_max()
+            }),
+            F::new("HEADER_LENGTH_MIN", Unit::Bytes, "The shortest header", |flow| {
+                flow.get_flow_header_length_min()
+            }),
+            F::new("HEADER_LENGTH_MEAN", Unit::Bytes, "The mean header length", |flow| {
+                flow.get_flow_header_length_mean()
+            }),
+            F::new(
+                "HEADER_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the header lengths",
+                |flow| flow.get_flow_header_length_std(),
+            ),
+            F::new(
+                "FWD_HEADER_LENGTH_TOTAL",
+                Unit::Bytes,
+                "The total header length of the forward packets",
+                |flow| flow.cic_flow.fwd_header_length,
+            ),
+            F::new("FWD_HEADER_LENGTH_MAX", Unit::Bytes, "The longest forward header", |flow| {
+                flow.fwd_header_len_max
+            }),
+            F::new("FWD_HEADER_LENGTH_MIN", Unit::Bytes, "The shortest forward header", |flow| {
+                flow.get_fwd_header_length_min()
+            }),
+            F::new(
+                "FWD_HEADER_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the forward headers",
+                |flow| flow.fwd_header_len_mean,
+            ),
+            F::new(
+                "FWD_HEADER_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the forward header lengths",
+                |flow| flow.fwd_header_len_std,
+            ),
+            F::new(
+                "BWD_HEADER_LENGTH_TOTAL",
+                Unit::Bytes,
+                "The total header length of the backward packets",
+                |flow| flow.cic_flow.bwd_header_length,
+            ),
+            F::new("BWD_HEADER_LENGTH_MAX", Unit::Bytes, "The longest backward header", |flow| {
+                flow.bwd_header_len_max
+            }),
+            F::new("BWD_HEADER_LENGTH_MIN", Unit::Bytes, "The shortest backward header", |flow| {
+                flow.get_bwd_header_length_min()
+            }),
+            F::new(
+                "BWD_HEADER_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean length of the backward headers",
+                |flow| flow.bwd_header_len_mean,
+            ),
+            F::new(
+                "BWD_HEADER_LENGTH_STD",
+                Unit::Bytes,
+                "The standard deviation of the backward header lengths",
+                |flow| flow.bwd_header_len_std,
+            ),
+            F::new(
+                "FWD_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean payload length of the forward packets",
+                |flow| flow.cic_flow.get_fwd_segment_length_mean(),
+            ),
+            F::new(
+                "BWD_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean payload length of the backward packets",
+                |flow| flow.cic_flow.get_bwd_segment_length_mean(),
+            ),
+            F::new(
+                "FLOW_SEGMENT_LENGTH_MEAN",
+                Unit::Bytes,
+                "The mean payload length of the packets",
+                |flow| flow.cic_flow.get_flow_segment_length_mean(),
+            ),
+            F::new(
+                "FWD_INIT_WIN_BYTES",
+                Unit::Bytes,
+                "The TCP window of the first forward packet",
+                |flow| flow.cic_flow.fwd_init_win_bytes,
+            ),
+            F::new(
+                "BWD_INIT_WIN_BYTES",
+                Unit::Bytes,
+                "The TCP window of the first backward packet",
+                |flow| flow.cic_flow.bwd_init_win_bytes,
+            ),
+            F::new("ACTIVE_MIN", Unit::Microseconds, "The shortest active time", |flow| {
+                flow.cic_flow.get_active_min()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("ACTIVE_MAX", Unit::Microseconds, "The longest active time", |flow| {
+                flow.cic_flow.active_max
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "ACTIVE_MEAN",
+                Unit::Microseconds,
+                "The mean time the flow was active before going idle",
+                |flow| flow.cic_flow.active_mean,
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "ACTIVE_STD",
+                Unit::Microseconds,
+                "The standard deviation of the active times",
+                |flow| flow.cic_flow.active_std,
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new("IDLE_MIN", Unit::Microseconds, "The shortest idle time", |flow| {
+                flow.cic_flow.get_idle_min()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("IDLE_MAX", Unit::Microseconds, "The longest idle time", |flow| {
+                flow.cic_flow.idle_max
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("IDLE_MEAN", Unit::Microseconds, "The mean time the flow was idle", |flow| {
+                flow.cic_flow.idle_mean
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "IDLE_STD",
+                Unit::Microseconds,
+                "The standard deviation of the idle times",
+                |flow| flow.cic_flow.idle_std,
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "BYTES_RATE",
+                Unit::None,
+                "The bytes of both directions per millisecond, divided by 1000",
+                |flow| flow.get_rate(flow.cic_flow.fwd_pkt_len_tot + flow.cic_flow.bwd_pkt_len_tot),
+            ),
+            F::new(
+                "FWD_BYTES_RATE",
+                Unit::None,
+                "The forward bytes per millisecond, divided by 1000",
+                |flow| flow.get_rate(flow.cic_flow.fwd_pkt_len_tot),
+            ),
+            F::new(
+                "BWD_BYTES_RATE",
+                Unit::None,
+                "The backward bytes per millisecond, divided by 1000",
+                |flow| flow.get_rate(flow.cic_flow.bwd_pkt_len_tot),
+            ),
+            F::new(
+                "PACKETS_RATE",
+                Unit::None,
+                "The packets of both directions per millisecond, divided by 1000",
+                |flow| {
+                    let basic_flow = &flow.cic_flow.basic_flow;
+                    flow.get_rate(basic_flow.fwd_packet_count + basic_flow.bwd_packet_count)
+                },
+            ),
+            F::new(
+                "FWD_PACKETS_RATE",
+                Unit::None,
+                "The forward packets per millisecond, divided by 1000",
+                |flow| flow.get_rate(flow.cic_flow.basic_flow.fwd_packet_count),
+            ),
+            F::new(
+                "BWD_PACKETS_RATE",
+                Unit::None,
+                "The backward packets per millisecond, divided by 1000",
+                |flow| flow.get_rate(flow.cic_flow.basic_flow.bwd_packet_count),
+            ),
+            F::new("DOWN_UP_RATIO", Unit::None, "The backward packets per forward packet", |flow| {
+                flow.cic_flow.get_down_up_ratio()
+            }),
+            F::new(
+                "FWD_BYTES_BULK",
+                Unit::Bytes,
+                "The mean size of the forward bulk transfers",
+                |flow| flow.cic_flow.get_fwd_bytes_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_PACKETS_BULK",
+                Unit::Packets,
+                "The mean packets of the forward bulk transfers",
+                |flow| flow.cic_flow.get_fwd_packets_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_BULK_RATE",
+                Unit::BytesPerSecond,
+                "The rate of the forward bulk transfers",
+                |flow| flow.cic_flow.get_fwd_bulk_rate(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BYTES_BULK",
+                Unit::Bytes,
+                "The mean size of the backward bulk transfers",
+                |flow| flow.cic_flow.get_bwd_bytes_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_PACKETS_BULK",
+                Unit::Packets,
+                "The mean packets of the backward bulk transfers",
+                |flow| flow.cic_flow.get_bwd_packets_bulk(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BULK_RATE",
+                Unit::BytesPerSecond,
+                "The rate of the backward bulk transfers",
+                |flow| flow.cic_flow.get_bwd_bulk_rate(),
+            )
+            .group(FeatureGroup::Bulk),
+            F::new("FWD_BULK_STATE_COUNT", Unit::None, "The forward bulk transfers", |flow| {
+                flow.cic_flow.fwd_bulk_state_count
+            })
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_BULK_SIZE_TOTAL",
+                Unit::Bytes,
+                "The bytes of the forward bulk transfers",
+                |flow| flow.cic_flow.fwd_bulk_size_total,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_BULK_PACKET_COUNT",
+                Unit::Packets,
+                "The packets of the forward bulk transfers",
+                |flow| flow.cic_flow.fwd_bulk_packet_count,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "FWD_BULK_DURATION",
+                Unit::Microseconds,
+                "The duration of the forward bulk transfers",
+                |flow| flow.cic_flow.fwd_bulk_duration,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new("BWD_BULK_STATE_COUNT", Unit::None, "The backward bulk transfers", |flow| {
+                flow.cic_flow.bwd_bulk_state_count
+            })
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BULK_SIZE_TOTAL",
+                Unit::Bytes,
+                "The bytes of the backward bulk transfers",
+                |flow| flow.cic_flow.bwd_bulk_size_total,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BULK_PACKET_COUNT",
+                Unit::Packets,
+                "The packets of the backward bulk transfers",
+                |flow| flow.cic_flow.bwd_bulk_packet_count,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new(
+                "BWD_BULK_DURATION",
+                Unit::Microseconds,
+                "The duration of the backward bulk transfers",
+                |flow| flow.cic_flow.bwd_bulk_duration,
+            )
+            .group(FeatureGroup::Bulk),
+            F::new("FLOW_FIN_COUNT", Unit::Packets, "The packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.fwd_fin_flag_count
+                    + flow.cic_flow.basic_flow.bwd_fin_flag_count
+            }),
+            F::new("FLOW_PSH_COUNT", Unit::Packets, "The packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.fwd_psh_flag_count
+                    + flow.cic_flow.basic_flow.bwd_psh_flag_count
+            }),
+            F::new("FLOW_URG_COUNT", Unit::Packets, "The packets with URG", |flow| {
+                flow.cic_flow.basic_flow.fwd_urg_flag_count
+                    + flow.cic_flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new("FLOW_ECE_COUNT", Unit::Packets, "The packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.fwd_ece_flag_count
+                    + flow.cic_flow.basic_flow.bwd_ece_flag_count
+            }),
+            F::new("FLOW_SYN_COUNT", Unit::Packets, "The packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.fwd_syn_flag_count
+                    + flow.cic_flow.basic_flow.bwd_syn_flag_count
+            }),
+            F::new("FLOW_ACK_COUNT", Unit::Packets, "The packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.fwd_ack_flag_count
+                    + flow.cic_flow.basic_flow.bwd_ack_flag_count
+            }),
+            F::new("FLOW_CWE_COUNT", Unit::Packets, "The packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.fwd_cwe_flag_count
+                    + flow.cic_flow.basic_flow.bwd_cwe_flag_count
+            }),
+            F::new("FLOW_RST_COUNT", Unit::Packets, "The packets with RST", |flow| {
+                flow.cic_flow.basic_flow.fwd_rst_flag_count
+                    + flow.cic_flow.basic_flow.bwd_rst_flag_count
+            }),
+            F::new("FWD_FIN_COUNT", Unit::Packets, "The forward packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.fwd_fin_flag_count
+            }),
+            F::new("FWD_PSH_COUNT", Unit::Packets, "The forward packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.fwd_psh_flag_count
+            }),
+            F::new("FWD_URG_COUNT", Unit::Packets, "The forward packets with URG", |flow| {
+                flow.cic_flow.basic_flow.fwd_urg_flag_count
+            }),
+            F::new("FWD_ECE_COUNT", Unit::Packets, "The forward packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.fwd_ece_flag_count
+            }),
+            F::new("FWD_SYN_COUNT", Unit::Packets, "The forward packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.fwd_syn_flag_count
+            }),
+            F::new("FWD_ACK_COUNT", Unit::Packets, "The forward packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.fwd_ack_flag_count
+            }),
+            F::new("FWD_CWE_COUNT", Unit::Packets, "The forward packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.fwd_cwe_flag_count
+            }),
+            F::new("FWD_RST_COUNT", Unit::Packets, "The forward packets with RST", |flow| {
+                flow.cic_flow.basic_flow.fwd_rst_flag_count
+            }),
+            F::new("BWD_FIN_COUNT", Unit::Packets, "The backward packets with FIN", |flow| {
+                flow.cic_flow.basic_flow.bwd_fin_flag_count
+            }),
+            F::new("BWD_PSH_COUNT", Unit::Packets, "The backward packets with PSH", |flow| {
+                flow.cic_flow.basic_flow.bwd_psh_flag_count
+            }),
+            F::new("BWD_URG_COUNT", Unit::Packets, "The backward packets with URG", |flow| {
+                flow.cic_flow.basic_flow.bwd_urg_flag_count
+            }),
+            F::new("BWD_ECE_COUNT", Unit::Packets, "The backward packets with ECE", |flow| {
+                flow.cic_flow.basic_flow.bwd_ece_flag_count
+            }),
+            F::new("BWD_SYN_COUNT", Unit::Packets, "The backward packets with SYN", |flow| {
+                flow.cic_flow.basic_flow.bwd_syn_flag_count
+            }),
+            F::new("BWD_ACK_COUNT", Unit::Packets, "The backward packets with ACK", |flow| {
+                flow.cic_flow.basic_flow.bwd_ack_flag_count
+            }),
+            F::new("BWD_CWE_COUNT", Unit::Packets, "The backward packets with CWR", |flow| {
+                flow.cic_flow.basic_flow.bwd_cwe_flag_count
+            }),
+            F::new("BWD_RST_COUNT", Unit::Packets, "The backward packets with RST", |flow| {
+                flow.cic_flow.basic_flow.bwd_rst_flag_count
+            }),
+            F::new(
+                "FLOW_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean inter arrival time of the packets",
+                |flow| flow.cic_flow.get_flow_iat_mean(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the inter arrival times",
+                |flow| flow.cic_flow.get_flow_iat_std(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_IAT_MAX", Unit::Microseconds, "The longest inter arrival time", |flow| {
+                flow.cic_flow.get_flow_iat_max()
+            })
+            .group(FeatureGroup::Iat),
+            F::new("FLOW_IAT_MIN", Unit::Microseconds, "The shortest inter arrival time", |flow| {
+                flow.cic_flow.get_flow_iat_min()
+            })
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_SUM",
+                Unit::Microseconds,
+                "The sum of the inter arrival times of both directions",
+                |flow| flow.cic_flow.fwd_iat_total + flow.cic_flow.bwd_iat_total,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean forward inter arrival time",
+                |flow| flow.cic_flow.fwd_iat_mean,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the forward inter arrival times",
+                |flow| flow.cic_flow.fwd_iat_std,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MAX",
+                Unit::Microseconds,
+                "The longest forward inter arrival time",
+                |flow| flow.cic_flow.fwd_iat_max,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_MIN",
+                Unit::Microseconds,
+                "The shortest forward inter arrival time",
+                |flow| flow.cic_flow.get_fwd_iat_min(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_IAT_SUM",
+                Unit::Microseconds,
+                "The sum of the forward inter arrival times",
+                |flow| flow.cic_flow.fwd_iat_total,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MEAN",
+                Unit::Microseconds,
+                "The mean backward inter arrival time",
+                |flow| flow.cic_flow.bwd_iat_mean,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_STD",
+                Unit::Microseconds,
+                "The standard deviation of the backward inter arrival times",
+                |flow| flow.cic_flow.bwd_iat_std,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MAX",
+                Unit::Microseconds,
+                "The longest backward inter arrival time",
+                |flow| flow.cic_flow.bwd_iat_max,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_MIN",
+                Unit::Microseconds,
+                "The shortest backward inter arrival time",
+                |flow| flow.cic_flow.get_bwd_iat_min(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_SUM",
+                Unit::Microseconds,
+                "The sum of the backward inter arrival times",
+                |flow| flow.cic_flow.bwd_iat_total,
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "SF_FWD_PACKETS",
+                Unit::Packets,
+                "The mean forward packets of the subflows",
+                |flow| flow.cic_flow.get_sf_fwd_packets(),
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new(
+                "SF_BWD_PACKETS",
+                Unit::Packets,
+                "The mean backward packets of the subflows",
+                |flow| flow.cic_flow.get_sf_bwd_packets(),
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::new("SF_FWD_BYTES", Unit::Bytes, "The mean forward bytes of the subflows", |flow| {
+                flow.cic_flow.get_sf_fwd_bytes()
+            })
+            .group(FeatureGroup::ActiveIdle),
+            F::new("SF_BWD_BYTES", Unit::Bytes, "The mean backward bytes of the subflows", |flow| {
+                flow.cic_flow.get_sf_bwd_bytes()
+            })
+            .group(FeatureGroup::ActiveIdle),
+        ]
+    }
 }
 
 impl Flow for NTLFlow {
     fn new(
+        settings: Arc<FlowSettings>,
         flow_id: String,
         ipv4_source: IpAddr,
         port_source: u16,
@@ -241,6 +860,7 @@ impl Flow for NTLFlow {
     ) -> Self {
         NTLFlow {
             cic_flow: CicFlow::new(
+                settings,
                 flow_id,
                 ipv4_source,
                 port_source,
@@ -270,364 +890,9 @@ impl Flow for NTLFlow {
         is_terminated
     }
 
-    fn dump(&self) -> String {
-        let flow_duration =
-            self.cic_flow.basic_flow.last_timestamp - self.cic_flow.basic_flow.first_timestamp;
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            self.cic_flow.basic_flow.flow_key,
-            self.cic_flow.basic_flow.ip_source,
-            self.cic_flow.basic_flow.port_source,
-            self.cic_flow.basic_flow.ip_destination,
-            self.cic_flow.basic_flow.port_destination,
-            self.cic_flow.basic_flow.protocol,
-            flow_duration.num_microseconds().unwrap(),
-            self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.basic_flow.fwd_packet_count,
-            self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.fwd_pkt_len_tot,
-            self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.get_flow_packet_length_max(),
-            self.cic_flow.get_flow_packet_length_min(),
-            self.cic_flow.get_flow_packet_length_mean(),
-            self.cic_flow.get_flow_packet_length_std(),
-            self.cic_flow.get_flow_packet_length_variance(),
-            self.cic_flow.fwd_pkt_len_max,
-            self.cic_flow.get_fwd_packet_length_min(),
-            self.cic_flow.fwd_pkt_len_mean,
-            self.cic_flow.fwd_pkt_len_std,
-            self.cic_flow.fwd_pkt_len_std.powf(2.0),
-            self.cic_flow.bwd_pkt_len_max,
-            self.cic_flow.get_bwd_packet_length_min(),
-            self.cic_flow.bwd_pkt_len_mean,
-            self.cic_flow.bwd_pkt_len_std,
-            self.cic_flow.bwd_pkt_len_std.powf(2.0),
-            self.cic_flow.fwd_header_length + self.cic_flow.bwd_header_length,
-            self.get_flow_header_length_max(),
-            self.get_flow_header_length_min(),
-            self.get_flow_header_length_mean(),
-            self.get_flow_header_length_std(),
-            self.cic_flow.fwd_header_length,
-            self.fwd_header_len_max,
-            self.get_fwd_header_length_min(),
-            self.fwd_header_len_mean,
-            self.fwd_header_len_std,
-            self.cic_flow.bwd_header_length,
-            self.bwd_header_len_max,
-            self.get_bwd_header_length_min(),
-            self.bwd_header_len_mean,
-            self.bwd_header_len_std,
-            self.cic_flow.get_fwd_segment_length_mean(),
-            self.cic_flow.get_bwd_segment_length_mean(),
-            self.cic_flow.get_flow_segment_length_mean(),
-            self.cic_flow.fwd_init_win_bytes,
-            self.cic_flow.bwd_init_win_bytes,
-            self.cic_flow.get_active_min(),
-            self.cic_flow.active_max,
-            self.cic_flow.active_mean,
-            self.cic_flow.active_std,
-            self.cic_flow.get_idle_min(),
-            self.cic_flow.idle_max,
-            self.cic_flow.idle_mean,
-            self.cic_flow.idle_std,
-            (self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot) as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.fwd_pkt_len_tot as f64 / flow_duration.num_milliseconds() as f64 / 1000.0,
-            self.cic_flow.bwd_pkt_len_tot as f64 / flow_duration.num_milliseconds() as f64 / 1000.0,
-            (self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count)
-                as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.basic_flow.bwd_packet_count as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.basic_flow.fwd_packet_count as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.get_down_up_ratio(),
-            self.cic_flow.get_fwd_bytes_bulk(),
-            self.cic_flow.get_fwd_packets_bulk(),
-            self.cic_flow.get_fwd_bulk_rate(),
-            self.cic_flow.get_bwd_bytes_bulk(),
-            self.cic_flow.get_bwd_packets_bulk(),
-            self.cic_flow.get_bwd_bulk_rate(),
-            self.cic_flow.fwd_bulk_state_count,
-            self.cic_flow.fwd_bulk_size_total,
-            self.cic_flow.fwd_bulk_packet_count,
-            self.cic_flow.fwd_bulk_duration,
-            self.cic_flow.bwd_bulk_state_count,
-            self.cic_flow.bwd_bulk_size_total,
-            self.cic_flow.bwd_bulk_packet_count,
-            self.cic_flow.bwd_bulk_duration,
-            self.cic_flow.basic_flow.fwd_fin_flag_count
-                + self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count
-                + self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count
-                + self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count
-                + self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count
-                + self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count
-                + self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count
-                + self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count
-                + self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count,
-            self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.get_flow_iat_mean(),
-            self.cic_flow.get_flow_iat_std(),
-            self.cic_flow.get_flow_iat_max(),
-            self.cic_flow.get_flow_iat_min(),
-            self.cic_flow.fwd_iat_total + self.cic_flow.bwd_iat_total,
-            self.cic_flow.fwd_iat_mean,
-            self.cic_flow.fwd_iat_std,
-            self.cic_flow.fwd_iat_max,
-            self.cic_flow.get_fwd_iat_min(),
-            self.cic_flow.fwd_iat_total,
-            self.cic_flow.fwd_iat_total,
-            self.cic_flow.bwd_iat_mean,
-            self.cic_flow.bwd_iat_std,
-            self.cic_flow.bwd_iat_max,
-            self.cic_flow.get_bwd_iat_min(),
-            self.cic_flow.bwd_iat_total,
-            self.cic_flow.get_sf_fwd_packets(),
-            self.cic_flow.get_sf_bwd_packets(),
-            self.cic_flow.get_sf_fwd_bytes(),
-            self.cic_flow.get_sf_bwd_bytes(),
-        )
-    }
-
-    fn get_features() -> String {
-        format!(
-            "FLOW_ID,IP_SOURCE,PORT_SOURCE,IP_DESTINATION,PORT_DESTINATION,PROTOCOL,\
-            DURATION,TOTAL_PACKETS,FWD_PACKETS,BWD_PACKETS,TOTAL_BYTES,FWD_BYTES,BWD_BYTES,\
-            PACKET_LENGTH_MAX,PACKET_LENGTH_MIN,PACKET_LENGTH_MEAN,PACKET_LENGTH_STD,\
-            PACKET_LENGTH_VARIANCE,FWD_PACKET_LENGTH_MAX,FWD_PACKET_LENGTH_MIN,\
-            FWD_PACKET_LENGTH_MEAN,FWD_PACKET_LENGTH_STD,FWD_PACKET_LENGTH_VARIANCE,\
-            BWD_PACKET_LENGTH_MAX,BWD_PACKET_LENGTH_MIN,BWD_PACKET_LENGTH_MEAN,\
-            BWD_PACKET_LENGTH_STD,BWD_PACKET_LENGTH_VARIANCE,HEADER_LENGTH_TOTAL,\
-            HEADER_LENGTH_MAX,HEADER_LENGTH_MIN,HEADER_LENGTH_MEAN,HEADER_LENGTH_STD,\
-            FWD_HEADER_LENGTH_TOTAL,FWD_HEADER_LENGTH_MAX,FWD_HEADER_LENGTH_MIN,\
-            FWD_HEADER_LENGTH_MEAN,FWD_HEADER_LENGTH_STD,BWD_HEADER_LENGTH_TOTAL,\
-            BWD_HEADER_LENGTH_MAX,BWD_HEADER_LENGTH_MIN,BWD_HEADER_LENGTH_MEAN,\
-            BWD_HEADER_LENGTH_STD,FWD_SEGMENT_LENGTH_MEAN,BWD_SEGMENT_LENGTH_MEAN,\
-            FLOW_SEGMENT_LENGTH_MEAN,FWD_INIT_WIN_BYTES,BWD_INIT_WIN_BYTES,ACTIVE_MIN,\
-            ACTIVE_MAX,ACTIVE_MEAN,ACTIVE_STD,IDLE_MIN,IDLE_MAX,IDLE_MEAN,IDLE_STD,\
-            BYTES_RATE,FWD_BYTES_RATE,BWD_BYTES_RATE,\
-            PACKETS_RATE,FWD_PACKETS_RATE,BWD_PACKETS_RATE,\
-            DOWN_UP_RATIO,FWD_BYTES_BULK,FWD_PACKETS_BULK,FWD_BULK_RATE,\
-            BWD_BYTES_BULK,BWD_PACKETS_BULK,BWD_BULK_RATE,FWD_BULK_STATE_COUNT,\
-            FWD_BULK_SIZE_TOTAL,FWD_BULK_PACKET_COUNT,FWD_BULK_DURATION,BWD_BULK_STATE_COUNT,\
-            BWD_BULK_SIZE_TOTAL,BWD_BULK_PACKET_COUNT,BWD_BULK_DURATION,FLOW_FIN_COUNT,\
-            FLOW_PSH_COUNT,FLOW_URG_COUNT,FLOW_ECE_COUNT,FLOW_SYN_COUNT,FLOW_ACK_COUNT,\
-            FLOW_CWE_COUNT,FLOW_RST_COUNT,FWD_FIN_COUNT,FWD_PSH_COUNT,FWD_URG_COUNT,\
-            FWD_ECE_COUNT,FWD_SYN_COUNT,FWD_ACK_COUNT,FWD_CWE_COUNT,FWD_RST_COUNT,\
-            BWD_FIN_COUNT,BWD_PSH_COUNT,BWD_URG_COUNT,BWD_ECE_COUNT,BWD_SYN_COUNT,\
-            BWD_ACK_COUNT,BWD_CWE_COUNT,BWD_RST_COUNT,FLOW_IAT_MEAN,FLOW_IAT_STD,\
-            FLOW_IAT_MAX,FLOW_IAT_MIN,FLOW_IAT_SUM,FWD_IAT_MEAN,\
-            FWD_IAT_STD,FWD_IAT_MAX,FWD_IAT_MIN,FWD_IAT_SUM,BWD_IAT_MEAN,BWD_IAT_STD,BWD_IAT_MAX,\
-            BWD_IAT_MIN,BWD_IAT_SUM,SF_FWD_PACKETS,SF_BWD_PACKETS,SF_FWD_BYTES,SF_BWD_BYTES"
-        )
-    }
-
-    fn dump_without_contamination(&self) -> String {
-        let flow_duration =
-            self.cic_flow.basic_flow.last_timestamp - self.cic_flow.basic_flow.first_timestamp;
-        // Can be further updated after more research
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
-            {},{},{},{},{},{},{},{},{},{},{},{}",
-            flow_duration.num_microseconds().unwrap(),
-            self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.basic_flow.fwd_packet_count,
-            self.cic_flow.basic_flow.bwd_packet_count,
-            self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.fwd_pkt_len_tot,
-            self.cic_flow.bwd_pkt_len_tot,
-            self.cic_flow.get_flow_packet_length_max(),
-            self.cic_flow.get_flow_packet_length_min(),
-            self.cic_flow.get_flow_packet_length_mean(),
-            self.cic_flow.get_flow_packet_length_std(),
-            self.cic_flow.get_flow_packet_length_variance(),
-            self.cic_flow.fwd_pkt_len_max,
-            self.cic_flow.get_fwd_packet_length_min(),
-            self.cic_flow.fwd_pkt_len_mean,
-            self.cic_flow.fwd_pkt_len_std,
-            self.cic_flow.fwd_pkt_len_std.powf(2.0),
-            self.cic_flow.bwd_pkt_len_max,
-            self.cic_flow.get_bwd_packet_length_min(),
-            self.cic_flow.bwd_pkt_len_mean,
-            self.cic_flow.bwd_pkt_len_std,
-            self.cic_flow.bwd_pkt_len_std.powf(2.0),
-            self.cic_flow.fwd_header_length + self.cic_flow.bwd_header_length,
-            self.get_flow_header_length_max(),
-            self.get_flow_header_length_min(),
-            self.get_flow_header_length_mean(),
-            self.get_flow_header_length_std(),
-            self.cic_flow.fwd_header_length,
-            self.fwd_header_len_max,
-            self.get_fwd_header_length_min(),
-            self.fwd_header_len_mean,
-            self.fwd_header_len_std,
-            self.cic_flow.bwd_header_length,
-            self.bwd_header_len_max,
-            self.get_bwd_header_length_min(),
-            self.bwd_header_len_mean,
-            self.bwd_header_len_std,
-            self.cic_flow.get_fwd_segment_length_mean(),
-            self.cic_flow.get_bwd_segment_length_mean(),
-            self.cic_flow.get_flow_segment_length_mean(),
-            self.cic_flow.fwd_init_win_bytes,
-            self.cic_flow.bwd_init_win_bytes,
-            self.cic_flow.get_active_min(),
-            self.cic_flow.active_max,
-            self.cic_flow.active_mean,
-            self.cic_flow.active_std,
-            self.cic_flow.get_idle_min(),
-            self.cic_flow.idle_max,
-            self.cic_flow.idle_mean,
-            self.cic_flow.idle_std,
-            (self.cic_flow.fwd_pkt_len_tot + self.cic_flow.bwd_pkt_len_tot) as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.fwd_pkt_len_tot as f64 / flow_duration.num_milliseconds() as f64 / 1000.0,
-            self.cic_flow.bwd_pkt_len_tot as f64 / flow_duration.num_milliseconds() as f64 / 1000.0,
-            (self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count)
-                as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.basic_flow.bwd_packet_count as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.basic_flow.fwd_packet_count as f64
-                / flow_duration.num_milliseconds() as f64
-                / 1000.0,
-            self.cic_flow.get_down_up_ratio(),
-            self.cic_flow.get_fwd_bytes_bulk(),
-            self.cic_flow.get_fwd_packets_bulk(),
-            self.cic_flow.get_fwd_bulk_rate(),
-            self.cic_flow.get_bwd_bytes_bulk(),
-            self.cic_flow.get_bwd_packets_bulk(),
-            self.cic_flow.get_bwd_bulk_rate(),
-            self.cic_flow.fwd_bulk_state_count,
-            self.cic_flow.fwd_bulk_size_total,
-            self.cic_flow.fwd_bulk_packet_count,
-            self.cic_flow.fwd_bulk_duration,
-            self.cic_flow.bwd_bulk_state_count,
-            self.cic_flow.bwd_bulk_size_total,
-            self.cic_flow.bwd_bulk_packet_count,
-            self.cic_flow.bwd_bulk_duration,
-            self.cic_flow.basic_flow.fwd_fin_flag_count
-                + self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count
-                + self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count
-                + self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count
-                + self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count
-                + self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count
-                + self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count
-                + self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count
-                + self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.basic_flow.fwd_fin_flag_count,
-            self.cic_flow.basic_flow.fwd_psh_flag_count,
-            self.cic_flow.basic_flow.fwd_urg_flag_count,
-            self.cic_flow.basic_flow.fwd_ece_flag_count,
-            self.cic_flow.basic_flow.fwd_syn_flag_count,
-            self.cic_flow.basic_flow.fwd_ack_flag_count,
-            self.cic_flow.basic_flow.fwd_cwe_flag_count,
-            self.cic_flow.basic_flow.fwd_rst_flag_count,
-            self.cic_flow.basic_flow.bwd_fin_flag_count,
-            self.cic_flow.basic_flow.bwd_psh_flag_count,
-            self.cic_flow.basic_flow.bwd_urg_flag_count,
-            self.cic_flow.basic_flow.bwd_ece_flag_count,
-            self.cic_flow.basic_flow.bwd_syn_flag_count,
-            self.cic_flow.basic_flow.bwd_ack_flag_count,
-            self.cic_flow.basic_flow.bwd_cwe_flag_count,
-            self.cic_flow.basic_flow.bwd_rst_flag_count,
-            self.cic_flow.get_flow_iat_mean(),
-            self.cic_flow.get_flow_iat_std(),
-            self.cic_flow.get_flow_iat_max(),
-            self.cic_flow.get_flow_iat_min(),
-            self.cic_flow.fwd_iat_total + self.cic_flow.bwd_iat_total,
-            self.cic_flow.fwd_iat_mean,
-            self.cic_flow.fwd_iat_std,
-            self.cic_flow.fwd_iat_max,
-            self.cic_flow.get_fwd_iat_min(),
-            self.cic_flow.fwd_iat_total,
-            self.cic_flow.bwd_iat_mean,
-            self.cic_flow.bwd_iat_std,
-            self.cic_flow.bwd_iat_max,
-            self.cic_flow.get_bwd_iat_min(),
-            self.cic_flow.bwd_iat_total,
-            self.cic_flow.get_sf_fwd_packets(),
-            self.cic_flow.get_sf_bwd_packets(),
-            self.cic_flow.get_sf_fwd_bytes(),
-            self.cic_flow.get_sf_bwd_bytes(),
-        )
-    }
-
-    fn get_features_without_contamination() -> String {
-        format!(
-            "DURATION,TOTAL_PACKETS,FWD_PACKETS,BWD_PACKETS,TOTAL_BYTES,FWD_BYTES,BWD_BYTES,\
-            PACKET_LENGTH_MAX,PACKET_LENGTH_MIN,PACKET_LENGTH_MEAN,PACKET_LENGTH_STD,\
-            PACKET_LENGTH_VARIANCE,FWD_PACKET_LENGTH_MAX,FWD_PACKET_LENGTH_MIN,\
-            FWD_PACKET_LENGTH_MEAN,FWD_PACKET_LENGTH_STD,FWD_PACKET_LENGTH_VARIANCE,\
-            BWD_PACKET_LENGTH_MAX,BWD_PACKET_LENGTH_MIN,BWD_PACKET_LENGTH_MEAN,\
-            BWD_PACKET_LENGTH_STD,BWD_PACKET_LENGTH_VARIANCE,HEADER_LENGTH_TOTAL,\
-            HEADER_LENGTH_MAX,HEADER_LENGTH_MIN,HEADER_LENGTH_MEAN,HEADER_LENGTH_STD,\
-            FWD_HEADER_LENGTH_TOTAL,FWD_HEADER_LENGTH_MAX,FWD_HEADER_LENGTH_MIN,\
-            FWD_HEADER_LENGTH_MEAN,FWD_HEADER_LENGTH_STD,BWD_HEADER_LENGTH_TOTAL,\
-            BWD_HEADER_LENGTH_MAX,BWD_HEADER_LENGTH_MIN,BWD_HEADER_LENGTH_MEAN,\
-            BWD_HEADER_LENGTH_STD,FWD_SEGMENT_LENGTH_MEAN,BWD_SEGMENT_LENGTH_MEAN,\
-            FLOW_SEGMENT_LENGTH_MEAN,FWD_INIT_WIN_BYTES,BWD_INIT_WIN_BYTES,ACTIVE_MIN,\
-            ACTIVE_MAX,ACTIVE_MEAN,ACTIVE_STD,IDLE_MIN,IDLE_MAX,IDLE_MEAN,IDLE_STD,\
-            BYTES_RATE,FWD_BYTES_RATE,BWD_BYTES_RATE,PACKETS_RATE,FWD_PACKETS_RATE,\
-            BWD_PACKETS_RATE,DOWN_UP_RATIO,FWD_BYTES_BULK,FWD_PACKETS_BULK,FWD_BULK_RATE,\
-            BWD_BYTES_BULK,BWD_PACKETS_BULK,BWD_BULK_RATE,FWD_BULK_STATE_COUNT,\
-            FWD_BULK_SIZE_TOTAL,FWD_BULK_PACKET_COUNT,FWD_BULK_DURATION,BWD_BULK_STATE_COUNT,\
-            BWD_BULK_SIZE_TOTAL,BWD_BULK_PACKET_COUNT,BWD_BULK_DURATION,FLOW_FIN_COUNT,\
-            FLOW_PSH_COUNT,FLOW_URG_COUNT,FLOW_ECE_COUNT,FLOW_SYN_COUNT,FLOW_ACK_COUNT,\
-            FLOW_CWE_COUNT,FLOW_RST_COUNT,FWD_FIN_COUNT,FWD_PSH_COUNT,FWD_URG_COUNT,\
-            FWD_ECE_COUNT,FWD_SYN_COUNT,FWD_ACK_COUNT,FWD_CWE_COUNT,FWD_RST_COUNT,\
-            BWD_FIN_COUNT,BWD_PSH_COUNT,BWD_URG_COUNT,BWD_ECE_COUNT,BWD_SYN_COUNT,\
-            BWD_ACK_COUNT,BWD_CWE_COUNT,BWD_RST_COUNT,FLOW_IAT_MEAN,FLOW_IAT_STD,\
-            FLOW_IAT_MAX,FLOW_IAT_MIN,FLOW_IAT_SUM,FWD_IAT_MEAN,\
-            FWD_IAT_STD,FWD_IAT_MAX,FWD_IAT_MIN,FWD_IAT_SUM,BWD_IAT_MEAN,BWD_IAT_STD,BWD_IAT_MAX,\
-            BWD_IAT_MIN,BWD_IAT_SUM,SF_FWD_PACKETS,SF_BWD_PACKETS,SF_FWD_BYTES,SF_BWD_BYTES"
-        )
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<NTLFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(NTLFlow::feature_list)
     }
 
     fn get_first_timestamp(&self) -> DateTime<Utc> {
@@ -642,11 +907,24 @@ impl Flow for NTLFlow {
     fn flow_key(&self) -> &String {
         &self.cic_flow.basic_flow.flow_key
     }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.cic_flow.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.cic_flow.basic_flow
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::TimeDelta;
 
     use crate::flows::flow::Flow;
 
@@ -654,6 +932,7 @@ mod tests {
 
     fn setup_ntl_flow() -> NTLFlow {
         NTLFlow::new(
+            Arc::default(),
             "".to_string(),
             IpAddr::V4(Ipv4Addr::from(1)),
             80,
@@ -664,6 +943,50 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_dump_matches_features() {
+        let mut ntl_flow = setup_ntl_flow();
+        ntl_flow.cic_flow.fwd_iat_total = 30.0;
+        ntl_flow.cic_flow.bwd_iat_mean = 7.0;
+
+        // A value for every feature of the header, the forward IAT sum once
+        let features = NTLFlow::get_features();
+        let names: Vec<&str> = features.split(',').collect();
+        let record = ntl_flow.dump();
+        let values: Vec<&str> = record.split(',').collect();
+        assert_eq!(values.len(), names.len());
+        let fwd_iat_sum = names.iter().position(|name| *name == "FWD_IAT_SUM").unwrap();
+        assert_eq!(values[fwd_iat_sum], "30");
+        assert_eq!(names[fwd_iat_sum + 1], "BWD_IAT_MEAN");
+        assert_eq!(values[fwd_iat_sum + 1], "7");
+    }
+
+    #[test]
+    fn test_packet_rates() {
+        let mut ntl_flow = setup_ntl_flow();
+        let basic_flow = &mut ntl_flow.cic_flow.basic_flow;
+        basic_flow.fwd_packet_count = 3;
+        basic_flow.bwd_packet_count = 1;
+        basic_flow.last_timestamp = basic_flow.first_timestamp + TimeDelta::milliseconds(2);
+
+        for (features, record) in [
+            (NTLFlow::get_features(), ntl_flow.dump()),
+            (
+                NTLFlow::get_features_without_contamination(),
+                ntl_flow.dump_without_contamination(),
+            ),
+        ] {
+            let names: Vec<&str> = features.split(',').collect();
+            let values: Vec<&str> = record.split(',').collect();
+            let rate = |name| {
+                let index = names.iter().position(|feature| *feature == name).unwrap();
+                values[index].parse::<f64>().unwrap()
+            };
+            assert_eq!(rate("FWD_PACKETS_RATE"), 3.0 / 2.0 / 1000.0);
+            assert_eq!(rate("BWD_PACKETS_RATE"), 1.0 / 2.0 / 1000.0);
+        }
+    }
+
     #[test]
     fn test_update_fwd_pkt_len_stats() {
         let mut ntl_flow = setup_ntl_flow();
@@ -0,0 +1,615 @@
+//! The feature registry, the single source of truth of the exported features.
+//!
+//! Every feature set lists its features as `Feature` entries in the order they are exported,
+//! each with its name, type, unit and description and the function taking its value from a
+//! flow. The header and the records of all outputs, the JSON Schema and the Protocol Buffers
+//! schema are built from these entries, and the contaminant features and the feature groups
+//! select from them, so the formats can't drift apart and a new feature is a new entry.
+
+use std::{borrow::Cow, fmt::Write, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    args::{FeatureGroup, TimestampFormat},
+    packet_features::reverse_flow_key,
+};
+
+use super::{
+    basic_flow::BasicFlow,
+    flow::Flow,
+    settings::{FeatureGroups, FlowSettings},
+};
+
+/// The type of the values of a feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeatureType {
+    /// Whole numbers, e.g. counts, ports and flags written as 0 or 1.
+    Integer,
+    /// Numbers with a fraction, e.g. means, rates and ratios.
+    Number,
+    /// `true` or `false`.
+    Boolean,
+    /// Text, e.g. addresses, flow keys and labels.
+    Text,
+    /// Timestamps in the configured timestamp format, or in this format of the feature set.
+    Timestamp(TimestampFormat),
+}
+
+/// The unit of the values of a feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    /// Identifiers, counts of events, ratios and the like.
+    None,
+    Packets,
+    Bytes,
+    Microseconds,
+    Milliseconds,
+    BytesPerSecond,
+    PacketsPerSecond,
+}
+
+impl Unit {
+    /// Returns the name of the unit in the schemas, `None` for features without a unit.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Unit::None => None,
+            Unit::Packets => Some("packets"),
+            Unit::Bytes => Some("bytes"),
+            Unit::Microseconds => Some("microseconds"),
+            Unit::Milliseconds => Some("milliseconds"),
+            Unit::BytesPerSecond => Some("bytes/s"),
+            Unit::PacketsPerSecond => Some("packets/s"),
+        }
+    }
+}
+
+/// The value of a feature of a flow, before it is dumped as text or written typed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The flow has no value for the feature, dumped as the configured null value.
+    Null,
+    Unsigned(u64),
+    Signed(i64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    Text(Cow<'static, str>),
+    Ip(IpAddr),
+    /// A timestamp, dumped in the configured timestamp format or in this format of the
+    /// feature set.
+    Timestamp(DateTime<Utc>, TimestampFormat),
+}
+
+impl Value {
+    /// Appends the value as it is dumped into the records, with the settings of its flow.
+    pub fn write(&self, settings: &FlowSettings, out: &mut String) {
+        // Writing to a `String` can't fail
+        let _ = match self {
+            Value::Null => out.write_str(settings.null_value.as_str()),
+            Value::Unsigned(value) => write!(out, "{}", value),
+            Value::Signed(value) => write!(out, "{}", value),
+            Value::Float(value) => write!(out, "{}", value),
+            Value::Double(value) => write!(out, "{}", value),
+            Value::Boolean(value) => write!(out, "{}", value),
+            Value::Text(value) => out.write_str(value),
+            Value::Ip(value) => write!(out, "{}", value),
+            Value::Timestamp(timestamp, default) => {
+                settings.write_timestamp_feature(*timestamp, *default, out)
+            }
+        };
+    }
+}
+
+/// A value a feature is taken from, its Rust type gives the type of the feature.
+pub trait FeatureValue {
+    const TYPE: FeatureType;
+
+    fn into_value(self) -> Value;
+}
+
+macro_rules! feature_values {
+    ($feature_type:ident, $variant:ident: $($value:ty),+) => {
+        $(impl FeatureValue for $value {
+            const TYPE: FeatureType = FeatureType::$feature_type;
+
+            fn into_value(self) -> Value {
+                Value::$variant(self.into())
+            }
+        })+
+    };
+}
+
+feature_values!(Integer, Unsigned: u8, u16, u32, u64);
+feature_values!(Integer, Signed: i32, i64);
+feature_values!(Number, Float: f32);
+feature_values!(Number, Double: f64);
+feature_values!(Boolean, Boolean: bool);
+feature_values!(Text, Text: String, &'static str);
+feature_values!(Text, Ip: IpAddr);
+
+impl FeatureValue for usize {
+    const TYPE: FeatureType = FeatureType::Integer;
+
+    fn into_value(self) -> Value {
+        Value::Unsigned(self as u64)
+    }
+}
+
+/// The description of a feature, everything of it but its value.
+#[derive(Debug, Clone)]
+pub struct FeatureInfo {
+    /// The name of the feature, its column of the header.
+    pub name: Cow<'static, str>,
+    pub feature_type: FeatureType,
+    pub unit: Unit,
+    /// What the feature holds, for the schemas.
+    pub description: Cow<'static, str>,
+    /// The group the feature is computed with, it has no value while the group is disabled.
+    pub group: Option<FeatureGroup>,
+    /// Whether the feature is dropped with `--drop-contaminant-features`, e.g. as it identifies
+    /// the endpoints or the time of a flow rather than its behavior.
+    pub contaminant: bool,
+    /// Whether a flow can have no value for the feature, regardless of its group.
+    pub optional: bool,
+}
+
+impl FeatureInfo {
+    /// Checks whether the feature can be without a value with the given settings, the null
+    /// value.
+    pub fn is_nullable(&self, settings: &FlowSettings) -> bool {
+        self.optional || self.group.is_some_and(|group| !settings.is_feature_group_enabled(group))
+    }
+}
+
+/// A feature of the flows of type `T`, its description and how its value is taken.
+pub struct Feature<T> {
+    pub info: FeatureInfo,
+    value: Box<dyn Fn(&T) -> Value + Send + Sync>,
+}
+
+impl<T: Flow> Feature<T> {
+    /// Creates a feature of the value `value` takes from a flow, of the type of that value.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name` - The name of the feature.
+    /// * `unit` - The unit of the values.
+    /// * `description` - What the feature holds.
+    /// * `value` - Takes the value of the feature from a flow.
+    pub fn new<V: FeatureValue>(
+        name: impl Into<Cow<'static, str>>,
+        unit: Unit,
+        description: impl Into<Cow<'static, str>>,
+        value: impl Fn(&T) -> V + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_type(name, V::TYPE, unit, description, move |flow| value(flow).into_value())
+    }
+
+    /// Creates a feature some flows have no value for, dumped as the null value then.
+    pub fn optional<V: FeatureValue>(
+        name: impl Into<Cow<'static, str>>,
+        unit: Unit,
+        description: impl Into<Cow<'static, str>>,
+        value: impl Fn(&T) -> Option<V> + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_type(name, V::TYPE, unit, description, move |flow| {
+            value(flow).map_or(Value::Null, FeatureValue::into_value)
+        })
+        .nullable()
+    }
+
+    /// Creates a timestamp feature, dumped in the configured timestamp format or in `default`.
+    pub fn timestamp(
+        name: impl Into<Cow<'static, str>>,
+        description: impl Into<Cow<'static, str>>,
+        default: TimestampFormat,
+        value: impl Fn(&T) -> DateTime<Utc> + Send + Sync + 'static,
+    ) -> Self {
+        let feature_type = FeatureType::Timestamp(default);
+        Self::with_type(name, feature_type, Unit::None, description, move |flow| {
+            Value::Timestamp(value(flow), default)
+        })
+    }
+
+    /// Creates a feature of the given type, `value` takes it from a flow.
+    pub fn with_type(
+        name: impl Into<Cow<'static, str>>,
+        feature_type: FeatureType,
+        unit: Unit,
+        description: impl Into<Cow<'static, str>>,
+        value: impl Fn(&T) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        Feature {
+            info: FeatureInfo {
+                name: name.into(),
+                feature_type,
+                unit,
+                description: description.into(),
+                group: None,
+                contaminant: false,
+                optional: false,
+            },
+            value: Box::new(value),
+        }
+    }
+
+    /// Puts the feature into a feature group, see `FeatureInfo::group`.
+    pub fn group(mut self, group: FeatureGroup) -> Self {
+        self.info.group = Some(group);
+        self
+    }
+
+    /// Marks the feature as contaminant, see `FeatureInfo::contaminant`.
+    pub fn contaminant(mut self) -> Self {
+        self.info.contaminant = true;
+        self
+    }
+
+    /// Marks the feature as optional, for the values of `with_type` that can be the null value.
+    pub fn nullable(mut self) -> Self {
+        self.info.optional = true;
+        self
+    }
+
+    /// Turns the feature into one of the flows of type `U`, which hold a flow of type `T`.
+    ///
+    /// The feature sets building upon another one share its features this way, e.g. the
+    /// RustiFlow features start with the CIC ones.
+    ///
+    /// ### Arguments
+    ///
+    /// * `get` - Returns the flow of type `T` of a flow of type `U`.
+    pub fn map<U: Flow>(self, get: fn(&U) -> &T) -> Feature<U> {
+        let value = self.value;
+        Feature {
+            info: self.info,
+            value: Box::new(move |flow| value(get(flow))),
+        }
+    }
+
+    /// Takes the value of the feature from a flow, `Value::Null` while its group is disabled.
+    ///
+    /// ### Arguments
+    ///
+    /// * `flow` - The flow the value is taken from.
+    /// * `disabled_groups` - The disabled feature groups of the settings of the flow.
+    pub fn value(&self, flow: &T, disabled_groups: FeatureGroups) -> Value {
+        match self.info.group {
+            Some(group) if disabled_groups.contains(group) => Value::Null,
+            _ => (self.value)(flow),
+        }
+    }
+}
+
+/// Returns the names of the features, separated by commas like the header.
+pub fn feature_names<'a, T: Flow>(features: impl IntoIterator<Item = &'a Feature<T>>) -> String {
+    features
+        .into_iter()
+        .map(|feature| feature.info.name.as_ref())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Dumps the values of the features of a flow, separated by commas like a record.
+pub fn dump_features<'a, T: Flow>(
+    features: impl IntoIterator<Item = &'a Feature<T>>,
+    flow: &T,
+) -> String {
+    let mut record = String::new();
+    write_features(features, flow, &mut record);
+    record
+}
+
+/// Appends the values of the features of a flow to a record, separated by commas.
+///
+/// The disabled feature groups are looked up once for all the features, and the values are
+/// written into the record without a string of their own.
+fn write_features<'a, T: Flow>(
+    features: impl IntoIterator<Item = &'a Feature<T>>,
+    flow: &T,
+    record: &mut String,
+) {
+    let settings = &flow.basic_flow().settings;
+    let disabled_groups = settings.disabled_features;
+    for (index, feature) in features.into_iter().enumerate() {
+        if index > 0 {
+            record.push(',');
+        }
+        feature.value(flow, disabled_groups).write(settings, record);
+    }
+}
+
+/// Creates the contaminant flow key feature, reversed with the endpoints if the responder is
+/// dumped as the source, see `FlowSettings::is_source_initiator`.
+pub fn flow_key_feature(name: &'static str) -> Feature<BasicFlow> {
+    let description = "The flow key, the endpoints and the protocol of the flow";
+    Feature::new(name, Unit::None, description, |flow: &BasicFlow| {
+        if flow.settings.is_source_initiator(flow) {
+            flow.settings.flow_key_feature(&flow.flow_key)
+        } else {
+            flow.settings.flow_key_feature(&reverse_flow_key(&flow.flow_key))
+        }
+    })
+    .contaminant()
+}
+
+/// Creates the contaminant IP address feature of the source or the destination, which are
+/// the initiator and the responder unless the endpoints are canonical.
+pub fn ip_feature(name: &'static str, source: bool) -> Feature<BasicFlow> {
+    let description = if source {
+        "The IP address of the source"
+    } else {
+        "The IP address of the destination"
+    };
+    Feature::new(name, Unit::None, description, move |flow: &BasicFlow| {
+        let ip = if source == flow.settings.is_source_initiator(flow) {
+            flow.ip_source
+        } else {
+            flow.ip_destination
+        };
+        flow.settings.ip_feature(ip)
+    })
+    .contaminant()
+}
+
+/// Creates the contaminant port feature of the source or the destination, see `ip_feature`.
+pub fn port_feature(name: &'static str, source: bool) -> Feature<BasicFlow> {
+    let description = if source {
+        "The transport port of the source"
+    } else {
+        "The transport port of the destination"
+    };
+    Feature::new(name, Unit::None, description, move |flow: &BasicFlow| {
+        if source == flow.settings.is_source_initiator(flow) {
+            flow.port_source
+        } else {
+            flow.port_destination
+        }
+    })
+    .contaminant()
+}
+
+/// Returns the features of the feature set `T`, without the contaminant features if they are
+/// dropped.
+///
+/// ### Arguments
+///
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn flow_features<T: Flow>(
+    skip_contaminant_features: bool,
+) -> impl Iterator<Item = &'static Feature<T>> {
+    T::features()
+        .iter()
+        .filter(move |feature| !(skip_contaminant_features && feature.info.contaminant))
+}
+
+/// Returns the features exported for the feature set `T` in their order: those of the feature
+/// set, see `flow_features`, followed by the optional features enabled in the settings, see
+/// `BasicFlow::optional_features`.
+///
+/// ### Arguments
+///
+/// * `settings` - The settings of the run, which select the optional features.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn exported_features<T: Flow>(
+    settings: &FlowSettings,
+    skip_contaminant_features: bool,
+) -> Vec<FeatureInfo> {
+    flow_features::<T>(skip_contaminant_features)
+        .map(|feature| feature.info.clone())
+        .chain(BasicFlow::optional_features(settings).into_iter().map(|feature| feature.info))
+        .collect()
+}
+
+/// Returns the header of the records of the feature set `T`, see `exported_features`.
+pub fn header<T: Flow>(settings: &FlowSettings, skip_contaminant_features: bool) -> String {
+    let features = if skip_contaminant_features {
+        T::get_features_without_contamination()
+    } else {
+        T::get_features()
+    };
+    let optional_features = BasicFlow::optional_features(settings);
+    with_optional_features(features, &optional_features)
+}
+
+/// Dumps the record of a flow, the values of the features of `header`.
+///
+/// ### Arguments
+///
+/// * `flow` - The flow to be dumped.
+/// * `optional_features` - The optional features of the settings of the flow, see
+///   `BasicFlow::optional_features`, built once for all records.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn record<T: Flow>(
+    flow: &T,
+    optional_features: &[Feature<BasicFlow>],
+    skip_contaminant_features: bool,
+) -> String {
+    let mut record = if skip_contaminant_features {
+        flow.dump_without_contamination()
+    } else {
+        flow.dump()
+    };
+    if !optional_features.is_empty() {
+        record.push(',');
+        write_features(optional_features, flow.basic_flow(), &mut record);
+    }
+    record
+}
+
+/// Takes the values of the exported features of a flow, those of `record` in its order, for
+/// the outputs writing typed columns.
+///
+/// ### Arguments
+///
+/// * `flow` - The flow the values are taken from.
+/// * `optional_features` - The optional features of the settings of the flow, see `record`.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn record_values<T: Flow>(
+    flow: &T,
+    optional_features: &[Feature<BasicFlow>],
+    skip_contaminant_features: bool,
+) -> Vec<Value> {
+    let disabled_groups = flow.basic_flow().settings.disabled_features;
+    flow_features::<T>(skip_contaminant_features)
+        .map(|feature| feature.value(flow, disabled_groups))
+        .chain(
+            optional_features
+                .iter()
+                .map(|feature| feature.value(flow.basic_flow(), disabled_groups)),
+        )
+        .collect()
+}
+
+/// Dumps the values of a flow, see `record_values`, as its record.
+pub fn dump_values(values: &[Value], settings: &FlowSettings) -> String {
+    let mut record = String::new();
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            record.push(',');
+        }
+        value.write(settings, &mut record);
+    }
+    record
+}
+
+/// Takes the values of a dumped record, e.g. a replayed one, as text to be parsed by the
+/// output. The configured null value is taken as a missing value.
+pub fn dumped_values(record: &str, settings: &FlowSettings) -> Vec<Value> {
+    record
+        .split(',')
+        .map(|field| {
+            if field == settings.null_value.as_str() {
+                Value::Null
+            } else {
+                Value::Text(Cow::Owned(field.to_string()))
+            }
+        })
+        .collect()
+}
+
+/// Appends the names of the optional features to those of a feature set, if any is enabled.
+fn with_optional_features(features: String, optional_features: &[Feature<BasicFlow>]) -> String {
+    if optional_features.is_empty() {
+        features
+    } else {
+        format!("{},{}", features, feature_names(optional_features))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, TimeDelta};
+    use clap::ValueEnum;
+
+    use super::dump_features;
+    use crate::{
+        args::FeatureGroup,
+        flows::{
+            cic_flow::CicFlow, flow::Flow, nf_flow::NfFlow, ntl_flow::NTLFlow,
+            rusti_flow::RustiFlow, settings::FlowSettings,
+        },
+        packet_features::PacketFeatures,
+    };
+
+    /// Feeds the same packets of both directions to a flow of each of the settings.
+    fn setup_flows<T: Flow>(settings: [FlowSettings; 2]) -> [T; 2] {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let server = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let mut flows = settings.map(|settings| {
+            T::new(Arc::new(settings), String::new(), client, 40000, server, 443, 6, start)
+        });
+        for i in 0..6_u16 {
+            let fwd = i % 3 != 1;
+            let packet = PacketFeatures {
+                source_ip: if fwd { client } else { server },
+                destination_ip: if fwd { server } else { client },
+                source_port: if fwd { 40000 } else { 443 },
+                destination_port: if fwd { 443 } else { 40000 },
+                protocol: 6,
+                timestamp: start + TimeDelta::milliseconds(i64::from(i * i) * 10),
+                fin_flag: 0,
+                syn_flag: u8::from(i == 0),
+                rst_flag: 0,
+                psh_flag: u8::from(i > 1),
+                ack_flag: u8::from(i > 0),
+                urg_flag: 0,
+                cwe_flag: 0,
+                ece_flag: 0,
+                data_length: 100 * i,
+                header_length: 20,
+                length: 100 * i + 40,
+                window_size: 1024,
+                sequence_number: 1 + 100 * u32::from(i),
+                sequence_number_ack: 1,
+                extension_header_count: 0,
+                ttl: 64,
+                ip_options_length: 0,
+                ip_flags: 0,
+                tcp_options_mask: 0,
+                flow_label: 0,
+                expected_flow: None,
+                vlan_id: None,
+                payload_snapshot: None,
+                capture_latency: None,
+            };
+            for flow in &mut flows {
+                flow.update_flow(&packet, fwd);
+            }
+        }
+        flows
+    }
+
+    /// Checks for every feature group that the features of the group are dumped as the null
+    /// value while it is disabled, and that the other features keep their values.
+    fn assert_disabled_groups<T: Flow>() {
+        for &group in FeatureGroup::value_variants() {
+            let disabled = FlowSettings {
+                disabled_features: [group].into_iter().collect(),
+                ..FlowSettings::default()
+            };
+            let [enabled_flow, disabled_flow] =
+                setup_flows::<T>([FlowSettings::default(), disabled]);
+            for feature in T::features() {
+                let name = &feature.info.name;
+                let dump = |flow| dump_features([feature], flow);
+                if feature.info.group == Some(group) {
+                    assert_eq!(dump(&disabled_flow), "", "{name} without {group:?}");
+                } else {
+                    assert_eq!(
+                        dump(&disabled_flow),
+                        dump(&enabled_flow),
+                        "{name} without {group:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_disabled_groups_cic() {
+        assert_disabled_groups::<CicFlow>();
+    }
+
+    #[test]
+    fn test_disabled_groups_ntl() {
+        assert_disabled_groups::<NTLFlow>();
+    }
+
+    #[test]
+    fn test_disabled_groups_nfstream() {
+        assert_disabled_groups::<NfFlow>();
+    }
+
+    #[test]
+    fn test_disabled_groups_rustiflow() {
+        assert_disabled_groups::<RustiFlow>();
+    }
+}
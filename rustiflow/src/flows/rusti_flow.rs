@@ -0,0 +1,2828 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use common::parse::{IPV4_DONT_FRAGMENT, IPV4_MORE_FRAGMENTS};
+use std::{
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{args::FeatureGroup, packet_features::PacketFeatures};
+
+use super::{
+    basic_flow::BasicFlow,
+    cic_flow::CicFlow,
+    flow::Flow,
+    registry::{Feature, Unit},
+    settings::FlowSettings,
+    util::{
+        calculate_mean, calculate_std, endpoint_locality, normalized_entropy, port_category,
+        same_subnet,
+    },
+};
+
+/// The largest packet of a standard Ethernet MTU, in bytes of total IP length.
+pub const ETHERNET_MTU: u16 = 1500;
+
+/// The largest jumbo frame packet, the 9000 byte MTU most jumbo frame setups use.
+pub const JUMBO_MTU: u16 = 9000;
+
+/// Inclusive upper bounds of the packet size histogram buckets, in bytes of total IP length.
+///
+/// The buckets are [0-64, 65-128, 129-256, 257-512, 513-1024, 1025-1500, 1501-9000, >9000].
+/// Jumbo frames get a bucket of their own, the last one only holds packets merged by
+/// segmentation offload. They are fixed so the histogram columns of different runs can be compared.
+pub const PACKET_SIZE_BUCKET_EDGES: [u16; 7] = [64, 128, 256, 512, 1024, ETHERNET_MTU, JUMBO_MTU];
+
+/// The number of packet size buckets, one more than the edges for the packets above the last edge.
+pub const PACKET_SIZE_BUCKETS: usize = PACKET_SIZE_BUCKET_EDGES.len() + 1;
+
+/// A direction dominates a flow when it carries at least this many times the bytes of the other.
+pub const DOMINANT_DIRECTION_RATIO: f64 = 2.0;
+
+/// The TTLs of a direction spreading wider than this are inconsistent, a few hops of jitter
+/// come from load balanced paths.
+pub const TTL_SPREAD_THRESHOLD: u8 = 5;
+
+/// Tracks the TCP sequence and acknowledgment numbers of one direction of a flow.
+#[derive(Clone, Default)]
+pub struct SequenceTracker {
+    /// The sequence number following the highest segment seen.
+    next_sequence_number: Option<u32>,
+    /// The acknowledgment number of the previous packet.
+    last_ack_number: Option<u32>,
+    /// The number of segments starting below the next expected sequence number.
+    pub out_of_order_packets: u32,
+    /// The number of pure ACKs repeating the previous acknowledgment number.
+    pub duplicate_acks: u32,
+    /// The payload bytes at or below the highest sequence number seen, i.e. sent before.
+    pub retransmitted_bytes: u64,
+}
+
+impl SequenceTracker {
+    /// Updates the tracker with a TCP packet of its direction.
+    ///
+    /// Sequence numbers are compared with wraparound, as in RFC 1982.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The TCP packet to be tracked.
+    fn update(&mut self, packet: &PacketFeatures) {
+        // SYN and FIN take up one sequence number
+        let segment_length =
+            u32::from(packet.data_length) + u32::from(packet.syn_flag) + u32::from(packet.fin_flag);
+
+        if let Some(next) = self.next_sequence_number {
+            // The payload follows the sequence number taken by a SYN, a segment can
+            // overlap the data sent before only partly
+            let payload_start = packet.sequence_number.wrapping_add(u32::from(packet.syn_flag));
+            let sent_before = next.wrapping_sub(payload_start) as i32;
+            if sent_before > 0 {
+                self.retransmitted_bytes +=
+                    u64::from((sent_before as u32).min(u32::from(packet.data_length)));
+            }
+        }
+
+        if segment_length > 0 {
+            let segment_end = packet.sequence_number.wrapping_add(segment_length);
+            match self.next_sequence_number {
+                Some(next) if (packet.sequence_number.wrapping_sub(next) as i32) < 0 => {
+                    self.out_of_order_packets += 1;
+                    if (segment_end.wrapping_sub(next) as i32) > 0 {
+                        self.next_sequence_number = Some(segment_end);
+                    }
+                }
+                _ => self.next_sequence_number = Some(segment_end),
+            }
+        }
+
+        if packet.ack_flag == 1 {
+            if packet.data_length == 0
+                && packet.syn_flag == 0
+                && packet.fin_flag == 0
+                && packet.rst_flag == 0
+                && self.last_ack_number == Some(packet.sequence_number_ack)
+            {
+                self.duplicate_acks += 1;
+            }
+            self.last_ack_number = Some(packet.sequence_number_ack);
+        }
+    }
+}
+
+/// Tracks the zero windows a direction of a flow advertises.
+#[derive(Clone, Default)]
+pub struct ZeroWindowTracker {
+    /// Whether the previous packet of the direction advertised a zero window.
+    in_zero_window: bool,
+    /// The number of packets advertising a zero window.
+    pub zero_window_packets: u32,
+    /// The number of times the window closed, i.e. packets advertising a zero window after a
+    /// packet with an open one.
+    pub zero_window_events: u32,
+}
+
+impl ZeroWindowTracker {
+    /// Updates the tracker with a TCP packet of its direction.
+    ///
+    /// Resets carry no meaningful window and are skipped, they often advertise zero.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The TCP packet to be tracked.
+    fn update(&mut self, packet: &PacketFeatures) {
+        if packet.rst_flag == 1 {
+            return;
+        }
+
+        let zero_window = packet.window_size == 0;
+        if zero_window {
+            self.zero_window_packets += 1;
+            if !self.in_zero_window {
+                self.zero_window_events += 1;
+            }
+        }
+        self.in_zero_window = zero_window;
+    }
+}
+
+/// Tracks the IP TTLs (IPv6 hop limits) of one direction of a flow.
+#[derive(Clone, Default)]
+pub struct TtlTracker {
+    /// The TTL of the first packet of the direction.
+    pub first: Option<u8>,
+    /// The lowest TTL of the direction.
+    pub min: Option<u8>,
+    /// The highest TTL of the direction.
+    pub max: Option<u8>,
+}
+
+impl TtlTracker {
+    /// Updates the tracker with the TTL of a packet of its direction.
+    fn update(&mut self, ttl: u8) {
+        self.first.get_or_insert(ttl);
+        self.min = Some(self.min.map_or(ttl, |min| min.min(ttl)));
+        self.max = Some(self.max.map_or(ttl, |max| max.max(ttl)));
+    }
+}
+
+/// Tracks the ratios of the packet lengths of one direction of a flow to the previous ones.
+#[derive(Clone, Default)]
+pub struct SizeRatioTracker {
+    /// The IP length of the previous packet of the direction.
+    last_length: Option<u16>,
+    /// The number of ratios.
+    pub count: u64,
+    /// The mean of the ratios.
+    pub mean: f64,
+    /// The standard deviation of the ratios.
+    pub std: f64,
+}
+
+impl SizeRatioTracker {
+    /// Updates the tracker with the IP length of a packet of its direction.
+    ///
+    /// A previous packet without length gives no ratio, but the next packet is compared to it.
+    fn update(&mut self, length: u16) {
+        if let Some(last_length) = self.last_length.filter(|last_length| *last_length > 0) {
+            let ratio = f64::from(length) / f64::from(last_length);
+            self.count += 1;
+            let mean = calculate_mean(self.count, self.mean, ratio);
+            self.std = calculate_std(self.count, self.std, self.mean, mean, ratio);
+            self.mean = mean;
+        }
+        self.last_length = Some(length);
+    }
+}
+
+/// Tracks the time between the direction switches of a flow, the turnaround times.
+#[derive(Clone, Default)]
+pub struct TurnaroundTracker {
+    /// The timestamp of the first packet in the direction of the previous packet.
+    run_start: Option<DateTime<Utc>>,
+    /// The number of turnaround times.
+    pub count: u64,
+    /// The mean of the turnaround times in microseconds.
+    pub mean: f64,
+    /// The standard deviation of the turnaround times in microseconds.
+    pub std: f64,
+}
+
+impl TurnaroundTracker {
+    /// Updates the tracker with the timestamp of a packet.
+    ///
+    /// A packet switching the direction gives the time since the previous switch, or since
+    /// the first packet for the first switch.
+    fn update(&mut self, timestamp: DateTime<Utc>, switched: bool) {
+        let Some(run_start) = self.run_start else {
+            self.run_start = Some(timestamp);
+            return;
+        };
+        if !switched {
+            return;
+        }
+
+        if let Some(turnaround) = timestamp.signed_duration_since(run_start).num_microseconds() {
+            let turnaround = turnaround as f64;
+            self.count += 1;
+            let mean = calculate_mean(self.count, self.mean, turnaround);
+            self.std = calculate_std(self.count, self.std, self.mean, mean, turnaround);
+            self.mean = mean;
+        }
+        self.run_start = Some(timestamp);
+    }
+}
+
+/// Tracks the largest gap between two consecutive packets of a flow, in either direction.
+#[derive(Clone, Default)]
+pub struct IdleGapTracker {
+    /// The timestamp of the previous packet.
+    last_timestamp: Option<DateTime<Utc>>,
+    /// The number of packets so far.
+    packets: u64,
+    /// The largest gap in microseconds.
+    pub max_gap: f64,
+    /// The number of the packet the largest gap followed, 0 before the second packet.
+    pub position: u64,
+}
+
+impl IdleGapTracker {
+    /// Updates the tracker with the timestamp of a packet, the first of equal gaps is kept.
+    fn update(&mut self, timestamp: DateTime<Utc>) {
+        if let Some(gap) = self
+            .last_timestamp
+            .and_then(|last| timestamp.signed_duration_since(last).num_microseconds())
+        {
+            let gap = gap as f64;
+            // A packet reordered before the previous one is no gap
+            if gap >= 0.0 && (self.position == 0 || gap > self.max_gap) {
+                self.max_gap = gap;
+                self.position = self.packets;
+            }
+        }
+        self.packets += 1;
+        self.last_timestamp = Some(timestamp);
+    }
+}
+
+/// Tracks the packets and bytes of each direction within the first window of a flow.
+#[derive(Clone, Default)]
+pub struct FirstWindowTracker {
+    /// The number of forward packets within the window.
+    pub fwd_packets: u32,
+    /// The number of backward packets within the window.
+    pub bwd_packets: u32,
+    /// The bytes of the forward packets within the window.
+    pub fwd_bytes: u64,
+    /// The bytes of the backward packets within the window.
+    pub bwd_bytes: u64,
+}
+
+impl FirstWindowTracker {
+    /// Counts a packet that arrived `elapsed` after the first packet of the flow, unless that
+    /// is later than `window`.
+    fn update(&mut self, elapsed: TimeDelta, window: TimeDelta, bytes: u32, fwd: bool) {
+        if elapsed > window {
+            return;
+        }
+        if fwd {
+            self.fwd_packets += 1;
+            self.fwd_bytes += u64::from(bytes);
+        } else {
+            self.bwd_packets += 1;
+            self.bwd_bytes += u64::from(bytes);
+        }
+    }
+}
+
+/// Tracks the bytes of the IPv4 options (IPv6 extension headers) of the packets of a flow.
+#[derive(Clone, Default)]
+pub struct IpOptionsTracker {
+    /// The number of packets.
+    pub packets: u32,
+    /// The option bytes of all packets.
+    pub total: u64,
+    /// The most option bytes of a single packet.
+    pub max: u16,
+}
+
+impl IpOptionsTracker {
+    /// Updates the tracker with the option bytes of a packet.
+    fn update(&mut self, length: u16) {
+        self.packets += 1;
+        self.total += u64::from(length);
+        self.max = self.max.max(length);
+    }
+}
+
+/// Tracks the steps of the TCP three-way handshake of a flow.
+#[derive(Clone, Default)]
+pub struct HandshakeTracker {
+    /// Whether a forward SYN without ACK was seen.
+    pub syn: bool,
+    /// Whether a backward SYN-ACK answered the SYN.
+    pub syn_ack: bool,
+    /// Whether a forward ACK followed the SYN-ACK.
+    pub ack: bool,
+    /// Whether a backward RST answered the SYN instead of a SYN-ACK.
+    pub refused: bool,
+    /// Whether any other RST was seen, in either direction.
+    pub reset: bool,
+    /// The timestamp of the first forward SYN.
+    pub syn_timestamp: Option<DateTime<Utc>>,
+}
+
+impl HandshakeTracker {
+    /// Updates the tracker with the flags of a TCP packet.
+    fn update(&mut self, packet: &PacketFeatures, fwd: bool) {
+        if packet.rst_flag > 0 {
+            if !fwd && self.syn && !self.syn_ack {
+                self.refused = true;
+            } else {
+                self.reset = true;
+            }
+            return;
+        }
+        match (fwd, packet.syn_flag > 0, packet.ack_flag > 0) {
+            (true, true, false) => {
+                self.syn = true;
+                self.syn_timestamp.get_or_insert(packet.timestamp);
+            }
+            (false, true, true) if self.syn => self.syn_ack = true,
+            (true, false, true) if self.syn_ack => self.ack = true,
+            _ => {}
+        }
+    }
+
+    /// Whether a forward SYN was seen without the SYN-ACK and the final ACK following it.
+    fn is_incomplete(&self) -> bool {
+        self.syn && !(self.syn_ack && self.ack)
+    }
+}
+
+/// Represents the RustiFlow Flow, extending the CIC features with additional features.
+///
+/// The CIC features are kept as they are, so they stay comparable with CICFlowMeter,
+/// and the additional features are appended after them.
+#[derive(Clone)]
+pub struct RustiFlow {
+    /// The CIC flow information.
+    pub cic_flow: CicFlow,
+    /// The number of forward packets carrying IPv6 extension headers.
+    pub fwd_extension_header_packets: u32,
+    /// The number of backward packets carrying IPv6 extension headers.
+    pub bwd_extension_header_packets: u32,
+    /// Presence bitmap of the TCP flag combinations seen, one bit per combination.
+    pub flag_combinations: [u64; 4],
+    /// All TCP flags seen in the flow, combined with a bitwise OR.
+    pub all_flags: u8,
+    /// The timestamp of the first forward packet carrying payload.
+    pub fwd_first_data_timestamp: Option<DateTime<Utc>>,
+    /// The timestamp of the first backward packet carrying payload.
+    pub bwd_first_data_timestamp: Option<DateTime<Utc>>,
+    /// The timestamp of the last packet carrying payload, in either direction.
+    pub last_data_timestamp: Option<DateTime<Utc>>,
+    /// Sequence tracking of the forward TCP packets.
+    pub fwd_sequence: SequenceTracker,
+    /// Sequence tracking of the backward TCP packets.
+    pub bwd_sequence: SequenceTracker,
+    /// The zero windows advertised by the forward TCP packets.
+    pub fwd_zero_window: ZeroWindowTracker,
+    /// The zero windows advertised by the backward TCP packets.
+    pub bwd_zero_window: ZeroWindowTracker,
+    /// The TTLs of the forward packets.
+    pub fwd_ttl: TtlTracker,
+    /// The TTLs of the backward packets.
+    pub bwd_ttl: TtlTracker,
+    /// The ratios of the forward packet lengths to the previous ones.
+    pub fwd_size_ratio: SizeRatioTracker,
+    /// The ratios of the backward packet lengths to the previous ones.
+    pub bwd_size_ratio: SizeRatioTracker,
+    /// The flow key of the control flow this flow belongs to, if expectations are tracked.
+    pub session_id: Option<String>,
+    /// The number of forward packets per size bucket, see `PACKET_SIZE_BUCKET_EDGES`.
+    pub fwd_packet_size_histogram: [u32; PACKET_SIZE_BUCKETS],
+    /// The number of backward packets per size bucket, see `PACKET_SIZE_BUCKET_EDGES`.
+    pub bwd_packet_size_histogram: [u32; PACKET_SIZE_BUCKETS],
+    /// The number of forward packets carrying payload.
+    pub fwd_payload_packets: u32,
+    /// The number of forward packets without payload, e.g. pure ACKs.
+    pub fwd_zero_payload_packets: u32,
+    /// The number of backward packets carrying payload.
+    pub bwd_payload_packets: u32,
+    /// The number of backward packets without payload, e.g. pure ACKs.
+    pub bwd_zero_payload_packets: u32,
+    /// The number of packets larger than a standard Ethernet MTU, in both directions.
+    pub jumbo_packets: u32,
+    /// The direction of the previous packet, `true` for forward.
+    pub last_direction_forward: Option<bool>,
+    /// The number of consecutive packets in the direction of the previous packet.
+    pub current_run: u32,
+    /// The most consecutive forward packets without a backward packet in between.
+    pub max_fwd_run: u32,
+    /// The most consecutive backward packets without a forward packet in between.
+    pub max_bwd_run: u32,
+    /// The number of packets in the other direction than the packet before them.
+    pub direction_switches: u32,
+    /// The times between the direction switches.
+    pub turnaround: TurnaroundTracker,
+    /// The largest gap between consecutive packets.
+    pub idle_gap: IdleGapTracker,
+    /// The IPv4 option (IPv6 extension header) bytes of the packets in both directions.
+    pub ip_options: IpOptionsTracker,
+    /// The packets and bytes of both directions within the first window.
+    pub first_window: FirstWindowTracker,
+    /// The steps of the TCP handshake.
+    pub handshake: HandshakeTracker,
+    /// The number of forward IPv4 packets with the Don't Fragment bit set.
+    pub fwd_df_packets: u32,
+    /// The number of backward IPv4 packets with the Don't Fragment bit set.
+    pub bwd_df_packets: u32,
+    /// The number of forward IPv4 packets that are the first fragment of a fragmented packet.
+    pub fwd_fragments: u32,
+    /// The number of backward IPv4 packets that are the first fragment of a fragmented packet.
+    pub bwd_fragments: u32,
+    /// The TCP option kinds of the forward SYNs, see `parse::tcp_option_bit`.
+    pub fwd_tcp_options_mask: u8,
+    /// The TCP option kinds of the backward SYNs, the SYN-ACK of the responder.
+    pub bwd_tcp_options_mask: u8,
+    /// The first IPv6 flow label the forward packets set.
+    pub fwd_flow_label: Option<u32>,
+    /// The first IPv6 flow label the backward packets set.
+    pub bwd_flow_label: Option<u32>,
+}
+
+impl RustiFlow {
+    /// Records the TCP flag combination of a packet.
+    ///
+    /// ### Arguments
+    ///
+    /// * `combined_flags` - The TCP flags of the packet as a single byte.
+    fn update_flag_combinations(&mut self, combined_flags: u8) {
+        self.flag_combinations[usize::from(combined_flags / 64)] |= 1 << (combined_flags % 64);
+        self.all_flags |= combined_flags;
+    }
+
+    /// Retrieves the number of distinct TCP flag combinations seen in the flow.
+    ///
+    /// ### Returns
+    ///
+    /// The number of distinct flag combinations, 0 for non-TCP flows.
+    pub fn distinct_flag_combinations(&self) -> u32 {
+        self.flag_combinations
+            .iter()
+            .map(|bits| bits.count_ones())
+            .sum()
+    }
+
+    /// Retrieves all TCP flags seen in the flow.
+    ///
+    /// Uses the bit positions of the TCP header, a bit is set if any packet had that flag.
+    ///
+    /// ### Returns
+    ///
+    /// The TCP flags seen in the flow as a single byte.
+    pub fn cumulative_flags(&self) -> u8 {
+        self.all_flags
+    }
+
+    /// Retrieves the time from the flow start to the first forward packet carrying payload.
+    ///
+    /// ### Returns
+    ///
+    /// The offset in microseconds, or `None` if no forward packet carried payload.
+    pub fn fwd_time_to_first_byte(&self) -> Option<i64> {
+        self.time_since_flow_start(self.fwd_first_data_timestamp)
+    }
+
+    /// Retrieves the time from the first forward SYN to the first forward packet carrying
+    /// payload.
+    ///
+    /// Besides the handshake round trip, this holds the think time of the client before its
+    /// first request, e.g. for a TLS library or a user to get going.
+    ///
+    /// ### Returns
+    ///
+    /// The time in microseconds, or `None` without a forward SYN or forward payload after it.
+    pub fn syn_to_first_data_time(&self) -> Option<i64> {
+        let syn_timestamp = self.handshake.syn_timestamp?;
+        let first_data_timestamp = self.fwd_first_data_timestamp?;
+        first_data_timestamp
+            .signed_duration_since(syn_timestamp)
+            .num_microseconds()
+            .filter(|time| *time >= 0)
+    }
+
+    /// Retrieves the time from the flow start to the first backward packet carrying payload.
+    ///
+    /// ### Returns
+    ///
+    /// The offset in microseconds, or `None` if no backward packet carried payload.
+    pub fn bwd_time_to_first_byte(&self) -> Option<i64> {
+        self.time_since_flow_start(self.bwd_first_data_timestamp)
+    }
+
+    /// Retrieves the number of forward TCP segments that arrived out of order.
+    ///
+    /// A segment is out of order if it starts below the next expected sequence number,
+    /// which includes retransmissions.
+    ///
+    /// ### Returns
+    ///
+    /// The number of out-of-order forward segments.
+    pub fn fwd_out_of_order_packets(&self) -> u32 {
+        self.fwd_sequence.out_of_order_packets
+    }
+
+    /// Retrieves the number of backward TCP segments that arrived out of order.
+    ///
+    /// ### Returns
+    ///
+    /// The number of out-of-order backward segments.
+    pub fn bwd_out_of_order_packets(&self) -> u32 {
+        self.bwd_sequence.out_of_order_packets
+    }
+
+    /// Retrieves the number of duplicate ACKs sent in the forward direction.
+    ///
+    /// A duplicate ACK repeats the previous acknowledgment number without carrying data.
+    ///
+    /// ### Returns
+    ///
+    /// The number of forward duplicate ACKs.
+    pub fn fwd_duplicate_acks(&self) -> u32 {
+        self.fwd_sequence.duplicate_acks
+    }
+
+    /// Retrieves the number of duplicate ACKs sent in the backward direction.
+    ///
+    /// ### Returns
+    ///
+    /// The number of backward duplicate ACKs.
+    pub fn bwd_duplicate_acks(&self) -> u32 {
+        self.bwd_sequence.duplicate_acks
+    }
+
+    /// Retrieves the forward TCP payload bytes that were sent before, i.e. retransmitted.
+    ///
+    /// Bytes count as retransmitted if they lie at or below the highest sequence number seen,
+    /// so segments that only arrive out of order count as well.
+    ///
+    /// ### Returns
+    ///
+    /// The number of retransmitted forward bytes.
+    pub fn fwd_retransmitted_bytes(&self) -> u64 {
+        self.fwd_sequence.retransmitted_bytes
+    }
+
+    /// Retrieves the backward TCP payload bytes that were sent before, i.e. retransmitted.
+    ///
+    /// ### Returns
+    ///
+    /// The number of retransmitted backward bytes.
+    pub fn bwd_retransmitted_bytes(&self) -> u64 {
+        self.bwd_sequence.retransmitted_bytes
+    }
+
+    /// Retrieves the forward goodput, the payload bytes per second without retransmissions.
+    ///
+    /// Measured over the flow duration, so a lossy path shows a goodput well below the
+    /// forward byte rate.
+    ///
+    /// ### Returns
+    ///
+    /// The unique payload bytes per second, or `None` if the flow has no duration.
+    pub fn fwd_goodput(&self) -> Option<f64> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        self.goodput(basic_flow.fwd_payload_bytes, self.fwd_retransmitted_bytes())
+    }
+
+    /// Retrieves the backward goodput, the payload bytes per second without retransmissions.
+    ///
+    /// ### Returns
+    ///
+    /// The unique payload bytes per second, or `None` if the flow has no duration.
+    pub fn bwd_goodput(&self) -> Option<f64> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        self.goodput(basic_flow.bwd_payload_bytes, self.bwd_retransmitted_bytes())
+    }
+
+    fn goodput(&self, payload_bytes: u64, retransmitted_bytes: u64) -> Option<f64> {
+        let seconds = self.cic_flow.basic_flow.get_flow_duration_usec() / 1_000_000.0;
+        // Excluded keepalives are retransmissions missing from the payload bytes
+        let unique_bytes = payload_bytes.saturating_sub(retransmitted_bytes);
+        (seconds > 0.0).then(|| unique_bytes as f64 / seconds)
+    }
+
+    /// Retrieves the number of forward TCP packets advertising a zero window.
+    ///
+    /// A zero window tells the other side to stop sending, the receiving application of the
+    /// initiator doesn't keep up.
+    ///
+    /// ### Returns
+    ///
+    /// The number of forward zero window packets.
+    pub fn fwd_zero_window_packets(&self) -> u32 {
+        self.fwd_zero_window.zero_window_packets
+    }
+
+    /// Retrieves the number of backward TCP packets advertising a zero window.
+    ///
+    /// ### Returns
+    ///
+    /// The number of backward zero window packets.
+    pub fn bwd_zero_window_packets(&self) -> u32 {
+        self.bwd_zero_window.zero_window_packets
+    }
+
+    /// Retrieves the number of times the forward TCP packets closed the window.
+    ///
+    /// Repeated window updates of a stalled receiver count once, until the window opens again.
+    ///
+    /// ### Returns
+    ///
+    /// The number of forward transitions into a zero window.
+    pub fn fwd_zero_window_events(&self) -> u32 {
+        self.fwd_zero_window.zero_window_events
+    }
+
+    /// Retrieves the number of times the backward TCP packets closed the window.
+    ///
+    /// ### Returns
+    ///
+    /// The number of backward transitions into a zero window.
+    pub fn bwd_zero_window_events(&self) -> u32 {
+        self.bwd_zero_window.zero_window_events
+    }
+
+    /// Retrieves whether the TTLs of a direction vary by more than `TTL_SPREAD_THRESHOLD`.
+    ///
+    /// The packets of one host travel the same path and start with the same TTL, so a wide
+    /// spread hints at spoofed packets or a route change.
+    ///
+    /// ### Returns
+    ///
+    /// `true` if the TTLs of either direction are inconsistent.
+    pub fn is_ttl_inconsistent(&self) -> bool {
+        [&self.fwd_ttl, &self.bwd_ttl].iter().any(|ttl| match (ttl.min, ttl.max) {
+            (Some(min), Some(max)) => max - min > TTL_SPREAD_THRESHOLD,
+            _ => false,
+        })
+    }
+
+    /// Retrieves the mean ratio of the forward packet lengths to the previous forward ones.
+    ///
+    /// Above 1 for lengths growing within bursts, e.g. the requests of an encrypted protocol,
+    /// 1 for packets of a steady size.
+    ///
+    /// ### Returns
+    ///
+    /// The mean ratio, or `None` with fewer than two forward packets.
+    pub fn fwd_size_ratio_mean(&self) -> Option<f64> {
+        (self.fwd_size_ratio.count > 0).then_some(self.fwd_size_ratio.mean)
+    }
+
+    /// Retrieves the standard deviation of the ratios of the forward packet lengths.
+    ///
+    /// ### Returns
+    ///
+    /// The standard deviation, or `None` with fewer than two forward packets.
+    pub fn fwd_size_ratio_std(&self) -> Option<f64> {
+        (self.fwd_size_ratio.count > 0).then_some(self.fwd_size_ratio.std)
+    }
+
+    /// Retrieves the mean ratio of the backward packet lengths to the previous backward ones.
+    ///
+    /// ### Returns
+    ///
+    /// The mean ratio, or `None` with fewer than two backward packets.
+    pub fn bwd_size_ratio_mean(&self) -> Option<f64> {
+        (self.bwd_size_ratio.count > 0).then_some(self.bwd_size_ratio.mean)
+    }
+
+    /// Retrieves the standard deviation of the ratios of the backward packet lengths.
+    ///
+    /// ### Returns
+    ///
+    /// The standard deviation, or `None` with fewer than two backward packets.
+    pub fn bwd_size_ratio_std(&self) -> Option<f64> {
+        (self.bwd_size_ratio.count > 0).then_some(self.bwd_size_ratio.std)
+    }
+
+    /// Retrieves the IANA range of the exported source port, see `port_category`.
+    ///
+    /// ### Returns
+    ///
+    /// The category, or `None` for ICMP flows, which have no ports.
+    pub fn source_port_category(&self) -> Option<&'static str> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        let port = if basic_flow.settings.is_source_initiator(basic_flow) {
+            basic_flow.port_source
+        } else {
+            basic_flow.port_destination
+        };
+        basic_flow.server_port().map(|_| port_category(port))
+    }
+
+    /// Retrieves the IANA range of the exported destination port, see `port_category`.
+    ///
+    /// ### Returns
+    ///
+    /// The category, or `None` for ICMP flows, which have no ports.
+    pub fn destination_port_category(&self) -> Option<&'static str> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        let port = if basic_flow.settings.is_source_initiator(basic_flow) {
+            basic_flow.port_destination
+        } else {
+            basic_flow.port_source
+        };
+        basic_flow.server_port().map(|_| port_category(port))
+    }
+
+    /// Retrieves the entropy of the first forward payload bytes, normalized to [0, 1].
+    ///
+    /// Encrypted and compressed payloads are close to 1, plain text protocols lower. Only the
+    /// first 64 bytes of the first payload packet are kept for it.
+    ///
+    /// ### Returns
+    ///
+    /// The entropy, or `None` if no forward payload was captured.
+    pub fn fwd_payload_entropy(&self) -> Option<f64> {
+        let snapshot = self.cic_flow.basic_flow.fwd_payload_snapshot.as_deref();
+        snapshot.map(|snapshot| normalized_entropy(snapshot.as_bytes()))
+    }
+
+    /// Retrieves the entropy of the first backward payload bytes, normalized to [0, 1].
+    ///
+    /// ### Returns
+    ///
+    /// The entropy, or `None` if no backward payload was captured.
+    pub fn bwd_payload_entropy(&self) -> Option<f64> {
+        let snapshot = self.cic_flow.basic_flow.bwd_payload_snapshot.as_deref();
+        snapshot.map(|snapshot| normalized_entropy(snapshot.as_bytes()))
+    }
+
+    /// Retrieves whether both endpoints are in the same local subnet, e.g. east-west traffic
+    /// between the hosts of a datacenter, rather than north-south traffic leaving it.
+    ///
+    /// ### Returns
+    ///
+    /// `Some(true)` if one of the `local_subnets` contains both endpoints, `Some(false)`
+    /// otherwise and `None` without local subnets.
+    pub fn is_same_subnet(&self) -> Option<bool> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        let subnets = &basic_flow.settings.local_subnets;
+        (!subnets.is_empty())
+            .then(|| same_subnet(subnets, basic_flow.ip_source, basic_flow.ip_destination))
+    }
+
+    /// Retrieves the fraction of forward packets carrying payload.
+    ///
+    /// Close to 1 for bulk transfers, low for control traffic made of pure ACKs.
+    ///
+    /// ### Returns
+    ///
+    /// The fraction of payload packets, or `None` if there are no forward packets.
+    pub fn fwd_payload_packet_ratio(&self) -> Option<f64> {
+        payload_packet_ratio(self.fwd_payload_packets, self.fwd_zero_payload_packets)
+    }
+
+    /// Retrieves the fraction of backward packets carrying payload.
+    ///
+    /// ### Returns
+    ///
+    /// The fraction of payload packets, or `None` if there are no backward packets.
+    pub fn bwd_payload_packet_ratio(&self) -> Option<f64> {
+        payload_packet_ratio(self.bwd_payload_packets, self.bwd_zero_payload_packets)
+    }
+
+    /// Records the direction of a packet in the runs of consecutive packets.
+    ///
+    /// ### Arguments
+    ///
+    /// * `fwd` - Whether the packet is a forward packet.
+    /// * `timestamp` - The timestamp of the packet.
+    fn update_direction_runs(&mut self, fwd: bool, timestamp: DateTime<Utc>) {
+        let switched = self.last_direction_forward.is_some_and(|last| last != fwd);
+        match self.last_direction_forward {
+            Some(last) if last == fwd => self.current_run += 1,
+            Some(_) => {
+                self.direction_switches += 1;
+                self.current_run = 1;
+            }
+            None => self.current_run = 1,
+        }
+        self.last_direction_forward = Some(fwd);
+        self.turnaround.update(timestamp, switched);
+
+        let max_run = if fwd {
+            &mut self.max_fwd_run
+        } else {
+            &mut self.max_bwd_run
+        };
+        *max_run = (*max_run).max(self.current_run);
+    }
+
+    /// Retrieves the longest run of consecutive forward packets.
+    ///
+    /// Long runs are bulk uploads, chatty request/response protocols alternate quickly.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets of the longest forward run.
+    pub fn max_fwd_run(&self) -> u32 {
+        self.max_fwd_run
+    }
+
+    /// Retrieves the longest run of consecutive backward packets.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets of the longest backward run.
+    pub fn max_bwd_run(&self) -> u32 {
+        self.max_bwd_run
+    }
+
+    /// Retrieves how often the direction changed from one packet to the next.
+    ///
+    /// ### Returns
+    ///
+    /// The number of direction switches.
+    pub fn direction_switches(&self) -> u32 {
+        self.direction_switches
+    }
+
+    /// Retrieves the largest gap between two consecutive packets of the flow, in either
+    /// direction. A stall within a flow, e.g. a transfer that hung for a minute, far exceeds
+    /// the mean IAT but hardly shows in the statistics of the IATs of each direction.
+    ///
+    /// ### Returns
+    ///
+    /// The gap in microseconds, or `None` with fewer than two packets.
+    pub fn max_idle_gap(&self) -> Option<f64> {
+        (self.idle_gap.position > 0).then_some(self.idle_gap.max_gap)
+    }
+
+    /// Retrieves the position of the largest gap between two consecutive packets, see
+    /// `max_idle_gap`.
+    ///
+    /// ### Returns
+    ///
+    /// The number of the packet the gap followed, 1 for the gap after the first packet, or
+    /// `None` with fewer than two packets.
+    pub fn max_idle_gap_position(&self) -> Option<u64> {
+        (self.idle_gap.position > 0).then_some(self.idle_gap.position)
+    }
+
+    /// Retrieves the TCP option kinds of the forward SYNs, a passive fingerprint of the stack
+    /// of the initiator next to its TTL and window size.
+    ///
+    /// ### Returns
+    ///
+    /// The bits of the kinds, see `parse::tcp_option_bit`, or 0 without a forward SYN.
+    pub fn fwd_tcp_options_mask(&self) -> u8 {
+        self.fwd_tcp_options_mask
+    }
+
+    /// Retrieves the TCP option kinds of the backward SYNs, those of the SYN-ACK of the
+    /// responder.
+    ///
+    /// ### Returns
+    ///
+    /// The bits of the kinds, see `parse::tcp_option_bit`, or 0 without a backward SYN.
+    pub fn bwd_tcp_options_mask(&self) -> u8 {
+        self.bwd_tcp_options_mask
+    }
+
+    /// Retrieves the number of forward packets within the first window of the flow, the first
+    /// `first_window` milliseconds after its first packet.
+    ///
+    /// The start of a connection, e.g. the burst of an exfiltration or of a scanner, tells the
+    /// flows apart before they end, and is comparable between flows of different lengths.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets.
+    pub fn fwd_packets_first_window(&self) -> u32 {
+        self.first_window.fwd_packets
+    }
+
+    /// Retrieves the number of backward packets within the first window of the flow.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets.
+    pub fn bwd_packets_first_window(&self) -> u32 {
+        self.first_window.bwd_packets
+    }
+
+    /// Retrieves the bytes of the forward packets within the first window of the flow, their
+    /// payload or total length as the byte count setting selects.
+    ///
+    /// ### Returns
+    ///
+    /// The number of bytes.
+    pub fn fwd_bytes_first_window(&self) -> u64 {
+        self.first_window.fwd_bytes
+    }
+
+    /// Retrieves the bytes of the backward packets within the first window of the flow.
+    ///
+    /// ### Returns
+    ///
+    /// The number of bytes.
+    pub fn bwd_bytes_first_window(&self) -> u64 {
+        self.first_window.bwd_bytes
+    }
+
+    /// Retrieves the first IPv6 flow label of the forward packets. Senders pick a label per
+    /// connection for ECMP and QoS, e.g. a hash of the 5-tuple, so another label within a flow
+    /// hints at a rehash or a spoofed packet.
+    ///
+    /// ### Returns
+    ///
+    /// The 20 bit label, or `None` for IPv4 and as long as the label is unset (0).
+    pub fn fwd_flow_label(&self) -> Option<u32> {
+        self.fwd_flow_label
+    }
+
+    /// Retrieves the first IPv6 flow label of the backward packets, the one of the responder.
+    ///
+    /// ### Returns
+    ///
+    /// The 20 bit label, or `None` for IPv4 and as long as the label is unset (0).
+    pub fn bwd_flow_label(&self) -> Option<u32> {
+        self.bwd_flow_label
+    }
+
+    /// Retrieves the mean time between two direction switches, the first one measured from
+    /// the first packet.
+    ///
+    /// Interactive request/response protocols turn around quickly and at a steady pace, bulk
+    /// transfers rarely and after long runs.
+    ///
+    /// ### Returns
+    ///
+    /// The mean turnaround time in microseconds, or `None` if the direction never switched.
+    pub fn turnaround_time_mean(&self) -> Option<f64> {
+        (self.turnaround.count > 0).then_some(self.turnaround.mean)
+    }
+
+    /// Retrieves the standard deviation of the times between two direction switches.
+    ///
+    /// ### Returns
+    ///
+    /// The standard deviation in microseconds, or `None` if the direction never switched.
+    pub fn turnaround_time_std(&self) -> Option<f64> {
+        (self.turnaround.count > 0).then_some(self.turnaround.std)
+    }
+
+    /// Retrieves the mean bytes of IPv4 options or IPv6 extension headers per packet.
+    ///
+    /// Options are rare in normal traffic, so high values point at source routing, timestamp
+    /// or padding tricks used for evasion and covert channels.
+    ///
+    /// ### Returns
+    ///
+    /// The mean option bytes, or `None` without packets.
+    pub fn ip_options_bytes_mean(&self) -> Option<f64> {
+        (self.ip_options.packets > 0)
+            .then(|| self.ip_options.total as f64 / f64::from(self.ip_options.packets))
+    }
+
+    /// Retrieves the most bytes of IPv4 options or IPv6 extension headers of a single packet.
+    ///
+    /// ### Returns
+    ///
+    /// The option bytes of the packet with the most options.
+    pub fn ip_options_bytes_max(&self) -> u16 {
+        self.ip_options.max
+    }
+
+    /// Counts the Don't Fragment packets and the fragments of a direction.
+    ///
+    /// Only the first fragment of a fragmented packet has the transport header and is seen,
+    /// it has the More Fragments bit set.
+    fn update_fragmentation(&mut self, ip_flags: u8, fwd: bool) {
+        let (df_packets, fragments) = if fwd {
+            (&mut self.fwd_df_packets, &mut self.fwd_fragments)
+        } else {
+            (&mut self.bwd_df_packets, &mut self.bwd_fragments)
+        };
+        if ip_flags & IPV4_DONT_FRAGMENT != 0 {
+            *df_packets += 1;
+        }
+        if ip_flags & IPV4_MORE_FRAGMENTS != 0 {
+            *fragments += 1;
+        }
+    }
+
+    /// Retrieves the number of forward packets with the Don't Fragment bit set.
+    ///
+    /// Path MTU discovery sets it on every packet, so a path dropping the ICMP "fragmentation
+    /// needed" messages blackholes the large packets of these flows.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets, or `None` for IPv6 flows, which have no such bit.
+    pub fn fwd_df_packets(&self) -> Option<u32> {
+        self.cic_flow.basic_flow.ip_source.is_ipv4().then_some(self.fwd_df_packets)
+    }
+
+    /// Retrieves the number of backward packets with the Don't Fragment bit set.
+    ///
+    /// ### Returns
+    ///
+    /// The number of packets, or `None` for IPv6 flows, which have no such bit.
+    pub fn bwd_df_packets(&self) -> Option<u32> {
+        self.cic_flow.basic_flow.ip_source.is_ipv4().then_some(self.bwd_df_packets)
+    }
+
+    /// Retrieves whether a direction was fragmented although its packets set Don't Fragment.
+    ///
+    /// A router may not fragment these packets, a fragment among them hints at a host mixing
+    /// the bit on and off, at a tunnel clearing it, or at crafted packets.
+    ///
+    /// ### Returns
+    ///
+    /// `Some(true)` if a direction has fragments and Don't Fragment packets, `Some(false)`
+    /// otherwise and `None` for IPv6 flows.
+    pub fn is_fragmented_despite_df(&self) -> Option<bool> {
+        self.cic_flow.basic_flow.ip_source.is_ipv4().then_some(
+            (self.fwd_df_packets > 0 && self.fwd_fragments > 0)
+                || (self.bwd_df_packets > 0 && self.bwd_fragments > 0),
+        )
+    }
+
+    /// Retrieves whether the flow started a TCP handshake that didn't complete.
+    ///
+    /// A forward SYN without a backward SYN-ACK, or without the final forward ACK, hints at
+    /// a SYN scan, a SYN flood or a dropped connection attempt.
+    ///
+    /// ### Returns
+    ///
+    /// `Some(true)` if the handshake is incomplete, `Some(false)` if it completed or wasn't
+    /// seen from its start, `None` for flows other than TCP.
+    pub fn is_handshake_incomplete(&self) -> Option<bool> {
+        (self.cic_flow.basic_flow.protocol == 6).then(|| self.handshake.is_incomplete())
+    }
+
+    /// Retrieves the outcome of the TCP connection of the flow, derived from its flags.
+    ///
+    /// ### Returns
+    ///
+    /// `refused` if a RST answered the SYN, `reset` if the connection was reset later,
+    /// `unanswered` for a SYN without answer, `incomplete` for a SYN-ACK without the final
+    /// ACK, `established` for a completed handshake and `midstream` if the handshake wasn't
+    /// seen. `None` for flows other than TCP.
+    pub fn connection_status(&self) -> Option<&'static str> {
+        if self.cic_flow.basic_flow.protocol != 6 {
+            return None;
+        }
+        let handshake = &self.handshake;
+        Some(if handshake.refused {
+            "refused"
+        } else if handshake.reset {
+            "reset"
+        } else if !handshake.syn {
+            "midstream"
+        } else if !handshake.syn_ack {
+            "unanswered"
+        } else if !handshake.ack {
+            "incomplete"
+        } else {
+            "established"
+        })
+    }
+
+    /// Retrieves the ratio of the forward to the backward bytes per second.
+    ///
+    /// Both directions share the flow duration, so this is the ratio of their byte counts:
+    /// above 1 for uploads, below 1 for downloads.
+    ///
+    /// ### Returns
+    ///
+    /// The ratio, or `None` if there are no backward bytes.
+    pub fn byte_rate_ratio(&self) -> Option<f64> {
+        let (fwd_bytes, bwd_bytes) = self.cic_flow.basic_flow.get_directional_bytes();
+        (bwd_bytes > 0).then(|| fwd_bytes as f64 / bwd_bytes as f64)
+    }
+
+    /// Retrieves the forward minus the backward bytes per second.
+    ///
+    /// ### Returns
+    ///
+    /// The difference in bytes per second, or `None` if the flow has no duration.
+    pub fn byte_rate_difference(&self) -> Option<f64> {
+        let (fwd_bytes, bwd_bytes) = self.cic_flow.basic_flow.get_directional_bytes();
+        self.rate_difference(fwd_bytes as f64, bwd_bytes as f64)
+    }
+
+    /// Retrieves the ratio of the forward to the backward packets per second.
+    ///
+    /// ### Returns
+    ///
+    /// The ratio, or `None` if there are no backward packets.
+    pub fn packet_rate_ratio(&self) -> Option<f64> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        (basic_flow.bwd_packet_count > 0).then(|| {
+            basic_flow.fwd_packet_count as f64 / basic_flow.bwd_packet_count as f64
+        })
+    }
+
+    /// Retrieves the forward minus the backward packets per second.
+    ///
+    /// ### Returns
+    ///
+    /// The difference in packets per second, or `None` if the flow has no duration.
+    pub fn packet_rate_difference(&self) -> Option<f64> {
+        let basic_flow = &self.cic_flow.basic_flow;
+        self.rate_difference(
+            basic_flow.fwd_packet_count as f64,
+            basic_flow.bwd_packet_count as f64,
+        )
+    }
+
+    fn rate_difference(&self, fwd: f64, bwd: f64) -> Option<f64> {
+        let seconds = self.cic_flow.basic_flow.get_flow_duration_usec() / 1_000_000.0;
+        (seconds > 0.0).then(|| (fwd - bwd) / seconds)
+    }
+
+    /// Retrieves the direction that carried most of the flow, see `dominant_direction`.
+    ///
+    /// The bytes decide, or the packets if no packet carried bytes.
+    pub fn dominant_direction(&self) -> &'static str {
+        let basic_flow = &self.cic_flow.basic_flow;
+        match basic_flow.get_directional_bytes() {
+            (0, 0) => dominant_direction(
+                basic_flow.fwd_packet_count,
+                basic_flow.bwd_packet_count,
+            ),
+            (fwd_bytes, bwd_bytes) => dominant_direction(fwd_bytes, bwd_bytes),
+        }
+    }
+
+    /// Retrieves the time from the first to the last packet carrying payload.
+    ///
+    /// Unlike the flow duration, this leaves out the handshake, the teardown and idle time
+    /// before and after the data, e.g. for throughput calculations.
+    ///
+    /// ### Returns
+    ///
+    /// The duration in microseconds, or `None` if no packet carried payload.
+    pub fn data_transfer_duration(&self) -> Option<i64> {
+        let first_data_timestamp = [self.fwd_first_data_timestamp, self.bwd_first_data_timestamp]
+            .into_iter()
+            .flatten()
+            .min()?;
+        self.last_data_timestamp?
+            .signed_duration_since(first_data_timestamp)
+            .num_microseconds()
+    }
+
+    /// Retrieves the coefficient of variation (standard deviation / mean) of the flow's IATs.
+    ///
+    /// Uses the forward and backward IATs together. Beacons, e.g. of C2 implants, send at
+    /// a fixed interval with little jitter and have a coefficient of variation close to 0.
+    ///
+    /// ### Returns
+    ///
+    /// The coefficient of variation, or `None` with fewer than two IATs or a mean of 0.
+    pub fn iat_coefficient_of_variation(&self) -> Option<f64> {
+        let fwd_iats = self.cic_flow.basic_flow.fwd_packet_count.saturating_sub(1);
+        let bwd_iats = self.cic_flow.basic_flow.bwd_packet_count.saturating_sub(1);
+        if fwd_iats + bwd_iats < 2 {
+            return None;
+        }
+
+        let mean = (fwd_iats as f64 * self.cic_flow.fwd_iat_mean
+            + bwd_iats as f64 * self.cic_flow.bwd_iat_mean)
+            / (fwd_iats + bwd_iats) as f64;
+        (mean > 0.0).then(|| self.cic_flow.flow_iat_variance().sqrt() / mean)
+    }
+
+    /// Checks whether the flow is periodic, with the configured threshold and minimum packets.
+    pub fn is_periodic(&self) -> bool {
+        let settings = &self.cic_flow.basic_flow.settings;
+        self.is_periodic_with(settings.periodicity_threshold, settings.periodicity_min_packets)
+    }
+
+    /// Checks whether the flow has at least `min_packets` packets and an IAT coefficient of
+    /// variation below `threshold`.
+    fn is_periodic_with(&self, threshold: f64, min_packets: u32) -> bool {
+        let packets =
+            self.cic_flow.basic_flow.fwd_packet_count + self.cic_flow.basic_flow.bwd_packet_count;
+        packets >= u64::from(min_packets)
+            && self
+                .iat_coefficient_of_variation()
+                .is_some_and(|coefficient| coefficient < threshold)
+    }
+
+    fn time_since_flow_start(&self, timestamp: Option<DateTime<Utc>>) -> Option<i64> {
+        timestamp.and_then(|timestamp| {
+            timestamp
+                .signed_duration_since(self.cic_flow.basic_flow.first_timestamp)
+                .num_microseconds()
+        })
+    }
+
+    /// Returns the features of the RustiFlow flow, the CIC features followed by the additional
+    /// ones, in the order they are exported.
+    pub fn feature_list() -> Vec<Feature<RustiFlow>> {
+        type F = Feature<RustiFlow>;
+        let mut features: Vec<F> = CicFlow::feature_list()
+            .into_iter()
+            .map(|feature| feature.map(|flow: &RustiFlow| &flow.cic_flow))
+            .collect();
+        features.extend([
+            F::new(
+                "FWD_EXTENSION_HEADER_PACKETS",
+                Unit::Packets,
+                "The forward IPv6 packets with extension headers",
+                |flow| flow.fwd_extension_header_packets,
+            ),
+            F::new(
+                "BWD_EXTENSION_HEADER_PACKETS",
+                Unit::Packets,
+                "The backward IPv6 packets with extension headers",
+                |flow| flow.bwd_extension_header_packets,
+            ),
+            F::new(
+                "DISTINCT_FLAG_COMBINATIONS",
+                Unit::None,
+                "The distinct combinations of TCP flags of the packets",
+                |flow| flow.distinct_flag_combinations(),
+            ),
+            F::new(
+                "CUMULATIVE_FLAGS",
+                Unit::None,
+                "The OR of the TCP flags of all packets",
+                |flow| flow.cumulative_flags(),
+            ),
+            F::optional(
+                "FWD_TIME_TO_FIRST_BYTE",
+                Unit::Microseconds,
+                "The time from the first packet to the first forward payload",
+                |flow| flow.fwd_time_to_first_byte(),
+            ),
+            F::optional(
+                "BWD_TIME_TO_FIRST_BYTE",
+                Unit::Microseconds,
+                "The time from the first packet to the first backward payload",
+                |flow| flow.bwd_time_to_first_byte(),
+            ),
+            F::new(
+                "FWD_OUT_OF_ORDER_PACKETS",
+                Unit::Packets,
+                "The forward TCP segments out of order",
+                |flow| flow.fwd_out_of_order_packets(),
+            ),
+            F::new(
+                "BWD_OUT_OF_ORDER_PACKETS",
+                Unit::Packets,
+                "The backward TCP segments out of order",
+                |flow| flow.bwd_out_of_order_packets(),
+            ),
+            F::new("FWD_DUPLICATE_ACKS", Unit::Packets, "The forward duplicate TCP ACKs", |flow| {
+                flow.fwd_duplicate_acks()
+            }),
+            F::new("BWD_DUPLICATE_ACKS", Unit::Packets, "The backward duplicate TCP ACKs", |flow| {
+                flow.bwd_duplicate_acks()
+            }),
+            F::new(
+                "FWD_IAT_VARIANCE",
+                Unit::None,
+                "The variance of the forward inter arrival times",
+                |flow| flow.cic_flow.fwd_iat_variance(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "BWD_IAT_VARIANCE",
+                Unit::None,
+                "The variance of the backward inter arrival times",
+                |flow| flow.cic_flow.bwd_iat_variance(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FLOW_IAT_VARIANCE",
+                Unit::None,
+                "The variance of the inter arrival times",
+                |flow| flow.cic_flow.flow_iat_variance(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the forward packet lengths",
+                |flow| flow.cic_flow.fwd_packet_length_variance(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "BWD_PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the backward packet lengths",
+                |flow| flow.cic_flow.bwd_packet_length_variance(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::new(
+                "PACKET_LENGTH_VARIANCE",
+                Unit::None,
+                "The variance of the packet lengths",
+                |flow| flow.cic_flow.packet_length_variance(),
+            )
+            .group(FeatureGroup::PacketLength),
+            F::optional(
+                "SESSION_ID",
+                Unit::None,
+                "The flow key of the first flow of the session",
+                |flow| {
+                    let settings = &flow.cic_flow.basic_flow.settings;
+                    flow.session_id.as_deref().map(|id| settings.flow_key_feature(id))
+                },
+            ),
+        ]);
+        features.extend(packet_size_histogram_features("FWD", |flow| {
+            &flow.fwd_packet_size_histogram
+        }));
+        features.extend(packet_size_histogram_features("BWD", |flow| {
+            &flow.bwd_packet_size_histogram
+        }));
+        features.extend([
+            F::new("SAMPLING_RATE", Unit::None, "Every how many flows one was tracked", |flow| {
+                flow.cic_flow.basic_flow.settings.sampling_rate()
+            }),
+            F::new(
+                "ENDPOINT_LOCALITY",
+                Unit::None,
+                "Whether the endpoints are private or public, e.g. `private-to-public`",
+                |flow| {
+                    let basic_flow = &flow.cic_flow.basic_flow;
+                    endpoint_locality(basic_flow.ip_source, basic_flow.ip_destination)
+                },
+            ),
+            F::optional(
+                "FWD_PAYLOAD_PACKET_RATIO",
+                Unit::None,
+                "The fraction of the forward packets with payload",
+                |flow| flow.fwd_payload_packet_ratio(),
+            ),
+            F::optional(
+                "BWD_PAYLOAD_PACKET_RATIO",
+                Unit::None,
+                "The fraction of the backward packets with payload",
+                |flow| flow.bwd_payload_packet_ratio(),
+            ),
+            F::new(
+                "JUMBO_PACKETS",
+                Unit::Packets,
+                "The packets longer than the Ethernet MTU",
+                |flow| flow.jumbo_packets,
+            ),
+            F::optional(
+                "DATA_TRANSFER_DURATION",
+                Unit::Microseconds,
+                "The time from the first to the last packet with payload",
+                |flow| flow.data_transfer_duration(),
+            ),
+            F::optional(
+                "IAT_COEFFICIENT_OF_VARIATION",
+                Unit::None,
+                "The standard deviation of the inter arrival times over their mean",
+                |flow| flow.iat_coefficient_of_variation(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "IS_PERIODIC",
+                Unit::None,
+                "1 if the inter arrival times hardly vary, e.g. of a beacon",
+                |flow| u8::from(flow.is_periodic()),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "KEEPALIVE_PACKETS",
+                Unit::Packets,
+                "The TCP keepalives and zero window probes",
+                |flow| flow.cic_flow.basic_flow.keepalive_packets(),
+            ),
+            F::new("MAX_FWD_RUN", Unit::Packets, "The most forward packets in a row", |flow| {
+                flow.max_fwd_run()
+            }),
+            F::new("MAX_BWD_RUN", Unit::Packets, "The most backward packets in a row", |flow| {
+                flow.max_bwd_run()
+            }),
+            F::new(
+                "DIRECTION_SWITCHES",
+                Unit::None,
+                "How often the direction of the packets switched",
+                |flow| flow.direction_switches(),
+            ),
+            F::optional(
+                "BYTE_RATE_RATIO",
+                Unit::None,
+                "The forward over the backward bytes per second",
+                |flow| flow.byte_rate_ratio(),
+            ),
+            F::optional(
+                "BYTE_RATE_DIFFERENCE",
+                Unit::BytesPerSecond,
+                "The forward minus the backward bytes per second",
+                |flow| flow.byte_rate_difference(),
+            ),
+            F::optional(
+                "PACKET_RATE_RATIO",
+                Unit::None,
+                "The forward over the backward packets per second",
+                |flow| flow.packet_rate_ratio(),
+            ),
+            F::optional(
+                "PACKET_RATE_DIFFERENCE",
+                Unit::PacketsPerSecond,
+                "The forward minus the backward packets per second",
+                |flow| flow.packet_rate_difference(),
+            ),
+            F::new(
+                "DOMINANT_DIRECTION",
+                Unit::None,
+                "`fwd`, `bwd` or `balanced`, the direction carrying most of the flow",
+                |flow| flow.dominant_direction(),
+            ),
+            F::new(
+                "FWD_ZERO_WINDOW_PACKETS",
+                Unit::Packets,
+                "The forward packets with a zero TCP window",
+                |flow| flow.fwd_zero_window_packets(),
+            ),
+            F::new(
+                "BWD_ZERO_WINDOW_PACKETS",
+                Unit::Packets,
+                "The backward packets with a zero TCP window",
+                |flow| flow.bwd_zero_window_packets(),
+            ),
+            F::new(
+                "FWD_ZERO_WINDOW_EVENTS",
+                Unit::None,
+                "How often the forward TCP window became zero",
+                |flow| flow.fwd_zero_window_events(),
+            ),
+            F::new(
+                "BWD_ZERO_WINDOW_EVENTS",
+                Unit::None,
+                "How often the backward TCP window became zero",
+                |flow| flow.bwd_zero_window_events(),
+            ),
+            F::optional("FWD_FIRST_TTL", Unit::None, "The TTL of the first forward packet", |flow| {
+                flow.fwd_ttl.first
+            }),
+            F::optional("FWD_MIN_TTL", Unit::None, "The lowest TTL of the forward packets", |flow| {
+                flow.fwd_ttl.min
+            }),
+            F::optional(
+                "FWD_MAX_TTL",
+                Unit::None,
+                "The highest TTL of the forward packets",
+                |flow| flow.fwd_ttl.max,
+            ),
+            F::optional(
+                "BWD_FIRST_TTL",
+                Unit::None,
+                "The TTL of the first backward packet",
+                |flow| flow.bwd_ttl.first,
+            ),
+            F::optional(
+                "BWD_MIN_TTL",
+                Unit::None,
+                "The lowest TTL of the backward packets",
+                |flow| flow.bwd_ttl.min,
+            ),
+            F::optional(
+                "BWD_MAX_TTL",
+                Unit::None,
+                "The highest TTL of the backward packets",
+                |flow| flow.bwd_ttl.max,
+            ),
+            F::new(
+                "TTL_INCONSISTENT",
+                Unit::None,
+                "1 if the TTLs of a direction spread wider than a few hops",
+                |flow| u8::from(flow.is_ttl_inconsistent()),
+            ),
+            F::new(
+                "FWD_RETRANSMITTED_BYTES",
+                Unit::Bytes,
+                "The retransmitted forward payload bytes",
+                |flow| flow.fwd_retransmitted_bytes(),
+            ),
+            F::new(
+                "BWD_RETRANSMITTED_BYTES",
+                Unit::Bytes,
+                "The retransmitted backward payload bytes",
+                |flow| flow.bwd_retransmitted_bytes(),
+            ),
+            F::optional(
+                "FWD_GOODPUT",
+                Unit::BytesPerSecond,
+                "The unique forward payload bytes per second",
+                |flow| flow.fwd_goodput(),
+            ),
+            F::optional(
+                "BWD_GOODPUT",
+                Unit::BytesPerSecond,
+                "The unique backward payload bytes per second",
+                |flow| flow.bwd_goodput(),
+            ),
+            F::optional(
+                "FWD_SIZE_RATIO_MEAN",
+                Unit::None,
+                "The mean length ratio of consecutive forward packets",
+                |flow| flow.fwd_size_ratio_mean(),
+            ),
+            F::optional(
+                "FWD_SIZE_RATIO_STD",
+                Unit::None,
+                "The standard deviation of the forward length ratios",
+                |flow| flow.fwd_size_ratio_std(),
+            ),
+            F::optional(
+                "BWD_SIZE_RATIO_MEAN",
+                Unit::None,
+                "The mean length ratio of consecutive backward packets",
+                |flow| flow.bwd_size_ratio_mean(),
+            ),
+            F::optional(
+                "BWD_SIZE_RATIO_STD",
+                Unit::None,
+                "The standard deviation of the backward length ratios",
+                |flow| flow.bwd_size_ratio_std(),
+            ),
+            F::optional(
+                "SOURCE_PORT_CATEGORY",
+                Unit::None,
+                "`well-known`, `registered` or `dynamic`, the range of the source port",
+                |flow| flow.source_port_category(),
+            ),
+            F::optional(
+                "DESTINATION_PORT_CATEGORY",
+                Unit::None,
+                "The range of the destination port, like SOURCE_PORT_CATEGORY",
+                |flow| flow.destination_port_category(),
+            ),
+            F::optional("SERVER_PORT", Unit::None, "The port of the server of the flow", |flow| {
+                flow.cic_flow.basic_flow.server_port()
+            }),
+            F::optional(
+                "TURNAROUND_TIME_MEAN",
+                Unit::Microseconds,
+                "The mean time between two direction switches",
+                |flow| flow.turnaround_time_mean(),
+            ),
+            F::optional(
+                "TURNAROUND_TIME_STD",
+                Unit::Microseconds,
+                "The standard deviation of the times between direction switches",
+                |flow| flow.turnaround_time_std(),
+            ),
+            F::optional(
+                "IP_OPTIONS_BYTES_MEAN",
+                Unit::Bytes,
+                "The mean bytes of IPv4 options or IPv6 extension headers per packet",
+                |flow| flow.ip_options_bytes_mean(),
+            ),
+            F::new(
+                "IP_OPTIONS_BYTES_MAX",
+                Unit::Bytes,
+                "The most bytes of IP options of a packet",
+                |flow| flow.ip_options_bytes_max(),
+            ),
+            F::optional(
+                "HANDSHAKE_INCOMPLETE",
+                Unit::None,
+                "1 if the TCP handshake started but didn't complete",
+                |flow| flow.is_handshake_incomplete().map(u8::from),
+            ),
+            F::optional(
+                "CONNECTION_STATUS",
+                Unit::None,
+                "The outcome of the TCP connection, e.g. `established` or `refused`",
+                |flow| flow.connection_status(),
+            ),
+            F::optional(
+                "SYN_TO_FIRST_DATA_TIME",
+                Unit::Microseconds,
+                "The time from the forward SYN to the first forward payload",
+                |flow| flow.syn_to_first_data_time(),
+            ),
+            F::new(
+                "IDLE_RATIO",
+                Unit::None,
+                "The fraction of the duration the flow was idle",
+                |flow| flow.cic_flow.idle_ratio(),
+            )
+            .group(FeatureGroup::ActiveIdle),
+            F::optional(
+                "FWD_DF_PACKETS",
+                Unit::Packets,
+                "The forward IPv4 packets with Don't Fragment",
+                |flow| flow.fwd_df_packets(),
+            ),
+            F::optional(
+                "BWD_DF_PACKETS",
+                Unit::Packets,
+                "The backward IPv4 packets with Don't Fragment",
+                |flow| flow.bwd_df_packets(),
+            ),
+            F::optional(
+                "FRAGMENTED_DESPITE_DF",
+                Unit::None,
+                "1 if a direction was fragmented although it set Don't Fragment",
+                |flow| flow.is_fragmented_despite_df().map(u8::from),
+            ),
+            F::optional(
+                "FWD_PAYLOAD_ENTROPY",
+                Unit::None,
+                "The normalized entropy of the first forward payload",
+                |flow| flow.fwd_payload_entropy(),
+            )
+            .group(FeatureGroup::Payload),
+            F::optional(
+                "BWD_PAYLOAD_ENTROPY",
+                Unit::None,
+                "The normalized entropy of the first backward payload",
+                |flow| flow.bwd_payload_entropy(),
+            )
+            .group(FeatureGroup::Payload),
+            F::optional(
+                "SAME_SUBNET",
+                Unit::None,
+                "1 if a local subnet contains both endpoints",
+                |flow| flow.is_same_subnet().map(u8::from),
+            ),
+            F::optional(
+                "MAX_IDLE_GAP",
+                Unit::Microseconds,
+                "The longest time between two packets",
+                |flow| flow.max_idle_gap(),
+            )
+            .group(FeatureGroup::Iat),
+            F::optional(
+                "MAX_IDLE_GAP_POSITION",
+                Unit::None,
+                "The number of the packet the longest gap followed",
+                |flow| flow.max_idle_gap_position(),
+            )
+            .group(FeatureGroup::Iat),
+            F::new(
+                "FWD_TCP_OPTIONS_MASK",
+                Unit::None,
+                "The kinds of the TCP options of the forward SYN as bits",
+                |flow| flow.fwd_tcp_options_mask(),
+            ),
+            F::new(
+                "BWD_TCP_OPTIONS_MASK",
+                Unit::None,
+                "The kinds of the TCP options of the backward SYN as bits",
+                |flow| flow.bwd_tcp_options_mask(),
+            ),
+            F::new(
+                "FWD_PACKETS_FIRST_WINDOW",
+                Unit::Packets,
+                "The forward packets within the first window of the flow",
+                |flow| flow.fwd_packets_first_window(),
+            ),
+            F::new(
+                "BWD_PACKETS_FIRST_WINDOW",
+                Unit::Packets,
+                "The backward packets within the first window of the flow",
+                |flow| flow.bwd_packets_first_window(),
+            ),
+            F::new(
+                "FWD_BYTES_FIRST_WINDOW",
+                Unit::Bytes,
+                "The forward bytes within the first window of the flow",
+                |flow| flow.fwd_bytes_first_window(),
+            ),
+            F::new(
+                "BWD_BYTES_FIRST_WINDOW",
+                Unit::Bytes,
+                "The backward bytes within the first window of the flow",
+                |flow| flow.bwd_bytes_first_window(),
+            ),
+            F::optional(
+                "FWD_FLOW_LABEL",
+                Unit::None,
+                "The first IPv6 flow label of the forward packets",
+                |flow| flow.fwd_flow_label(),
+            ),
+            F::optional(
+                "BWD_FLOW_LABEL",
+                Unit::None,
+                "The first IPv6 flow label of the backward packets",
+                |flow| flow.bwd_flow_label(),
+            ),
+        ]);
+        features
+    }
+}
+
+/// Returns the fraction of packets with payload, `None` without packets.
+fn payload_packet_ratio(payload_packets: u32, zero_payload_packets: u32) -> Option<f64> {
+    let packets = payload_packets + zero_payload_packets;
+    (packets > 0).then(|| f64::from(payload_packets) / f64::from(packets))
+}
+
+/// Returns `fwd` or `bwd` if that direction has at least `DOMINANT_DIRECTION_RATIO` times the
+/// amount of the other one, `balanced` otherwise.
+fn dominant_direction(fwd: u64, bwd: u64) -> &'static str {
+    if fwd as f64 >= DOMINANT_DIRECTION_RATIO * bwd as f64 && fwd > 0 {
+        "fwd"
+    } else if bwd as f64 >= DOMINANT_DIRECTION_RATIO * fwd as f64 && bwd > 0 {
+        "bwd"
+    } else {
+        "balanced"
+    }
+}
+
+/// Returns the histogram bucket of a packet with the given total IP length.
+fn packet_size_bucket(length: u16) -> usize {
+    PACKET_SIZE_BUCKET_EDGES
+        .iter()
+        .position(|&edge| length <= edge)
+        .unwrap_or(PACKET_SIZE_BUCKET_EDGES.len())
+}
+
+/// Returns the packet size histogram features of one direction, one per bucket, e.g.
+/// `FWD_PACKET_SIZE_65_128`.
+///
+/// ### Arguments
+///
+/// * `direction` - The prefix of the names, `FWD` or `BWD`.
+/// * `histogram` - Returns the histogram of the direction of a flow.
+fn packet_size_histogram_features(
+    direction: &'static str,
+    histogram: fn(&RustiFlow) -> &[u32; PACKET_SIZE_BUCKETS],
+) -> Vec<Feature<RustiFlow>> {
+    let mut lower = 0;
+    let mut buckets = Vec::with_capacity(PACKET_SIZE_BUCKETS);
+    for edge in PACKET_SIZE_BUCKET_EDGES {
+        buckets.push((
+            format!("{}_PACKET_SIZE_{}_{}", direction, lower, edge),
+            format!("The {} packets of {} to {} bytes", direction, lower, edge),
+        ));
+        lower = edge + 1;
+    }
+    buckets.push((
+        format!("{}_PACKET_SIZE_ABOVE_{}", direction, lower - 1),
+        format!("The {} packets above {} bytes", direction, lower - 1),
+    ));
+    buckets
+        .into_iter()
+        .enumerate()
+        .map(|(bucket, (name, description))| {
+            Feature::new(name, Unit::Packets, description, move |flow| histogram(flow)[bucket])
+                .group(FeatureGroup::Histograms)
+        })
+        .collect()
+}
+
+impl Flow for RustiFlow {
+    fn new(
+        settings: Arc<FlowSettings>,
+        flow_id: String,
+        ip_source: IpAddr,
+        port_source: u16,
+        ip_destination: IpAddr,
+        port_destination: u16,
+        protocol: u8,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        RustiFlow {
+            cic_flow: CicFlow::new(
+                settings,
+                flow_id,
+                ip_source,
+                port_source,
+                ip_destination,
+                port_destination,
+                protocol,
+                timestamp,
+            ),
+            fwd_extension_header_packets: 0,
+            bwd_extension_header_packets: 0,
+            flag_combinations: [0; 4],
+            all_flags: 0,
+            fwd_first_data_timestamp: None,
+            bwd_first_data_timestamp: None,
+            last_data_timestamp: None,
+            fwd_sequence: SequenceTracker::default(),
+            bwd_sequence: SequenceTracker::default(),
+            fwd_zero_window: ZeroWindowTracker::default(),
+            bwd_zero_window: ZeroWindowTracker::default(),
+            fwd_ttl: TtlTracker::default(),
+            bwd_ttl: TtlTracker::default(),
+            fwd_size_ratio: SizeRatioTracker::default(),
+            bwd_size_ratio: SizeRatioTracker::default(),
+            session_id: None,
+            fwd_packet_size_histogram: [0; PACKET_SIZE_BUCKETS],
+            bwd_packet_size_histogram: [0; PACKET_SIZE_BUCKETS],
+            fwd_payload_packets: 0,
+            fwd_zero_payload_packets: 0,
+            bwd_payload_packets: 0,
+            bwd_zero_payload_packets: 0,
+            jumbo_packets: 0,
+            last_direction_forward: None,
+            current_run: 0,
+            max_fwd_run: 0,
+            max_bwd_run: 0,
+            direction_switches: 0,
+            turnaround: TurnaroundTracker::default(),
+            idle_gap: IdleGapTracker::default(),
+            ip_options: IpOptionsTracker::default(),
+            first_window: FirstWindowTracker::default(),
+            handshake: HandshakeTracker::default(),
+            fwd_df_packets: 0,
+            bwd_df_packets: 0,
+            fwd_fragments: 0,
+            bwd_fragments: 0,
+            fwd_tcp_options_mask: 0,
+            bwd_tcp_options_mask: 0,
+            fwd_flow_label: None,
+            bwd_flow_label: None,
+        }
+    }
+
+    fn update_flow(&mut self, packet: &PacketFeatures, fwd: bool) -> bool {
+        let is_terminated = self.cic_flow.update_flow(packet, fwd);
+        let settings = &self.cic_flow.basic_flow.settings;
+        let iat = settings.is_feature_group_enabled(FeatureGroup::Iat);
+        let histograms = settings.is_feature_group_enabled(FeatureGroup::Histograms);
+        let first_window = TimeDelta::milliseconds(settings.first_window as i64);
+        let byte_count = settings.packet_byte_count(packet);
+
+        if iat {
+            self.idle_gap.update(packet.timestamp);
+        }
+        if histograms {
+            let bucket = packet_size_bucket(packet.length);
+            if fwd {
+                self.fwd_packet_size_histogram[bucket] += 1;
+            } else {
+                self.bwd_packet_size_histogram[bucket] += 1;
+            }
+        }
+        if fwd {
+            self.fwd_ttl.update(packet.ttl);
+            self.fwd_size_ratio.update(packet.length);
+        } else {
+            self.bwd_ttl.update(packet.ttl);
+            self.bwd_size_ratio.update(packet.length);
+        }
+        if packet.length > ETHERNET_MTU {
+            self.jumbo_packets += 1;
+        }
+        self.update_direction_runs(fwd, packet.timestamp);
+        self.ip_options.update(packet.ip_options_length);
+        self.first_window.update(
+            packet.timestamp - self.cic_flow.basic_flow.first_timestamp,
+            first_window,
+            byte_count,
+            fwd,
+        );
+        self.update_fragmentation(packet.ip_flags, fwd);
+
+        // A flow label of 0 is unset, it is always 0 for IPv4
+        if packet.flow_label != 0 {
+            let flow_label = if fwd {
+                &mut self.fwd_flow_label
+            } else {
+                &mut self.bwd_flow_label
+            };
+            flow_label.get_or_insert(packet.flow_label);
+        }
+
+        if packet.extension_header_count > 0 {
+            if fwd {
+                self.fwd_extension_header_packets += 1;
+            } else {
+                self.bwd_extension_header_packets += 1;
+            }
+        }
+
+        if packet.protocol == 6 {
+            self.update_flag_combinations(packet.combined_flags());
+            self.handshake.update(packet, fwd);
+            if fwd {
+                self.fwd_sequence.update(packet);
+                self.fwd_zero_window.update(packet);
+                self.fwd_tcp_options_mask |= packet.tcp_options_mask;
+            } else {
+                self.bwd_sequence.update(packet);
+                self.bwd_zero_window.update(packet);
+                self.bwd_tcp_options_mask |= packet.tcp_options_mask;
+            }
+        }
+
+        match (fwd, packet.data_length > 0) {
+            (true, true) => self.fwd_payload_packets += 1,
+            (true, false) => self.fwd_zero_payload_packets += 1,
+            (false, true) => self.bwd_payload_packets += 1,
+            (false, false) => self.bwd_zero_payload_packets += 1,
+        }
+
+        if packet.data_length > 0 {
+            let first_data_timestamp = if fwd {
+                &mut self.fwd_first_data_timestamp
+            } else {
+                &mut self.bwd_first_data_timestamp
+            };
+            first_data_timestamp.get_or_insert(packet.timestamp);
+            self.last_data_timestamp = Some(packet.timestamp);
+        }
+
+        is_terminated
+    }
+
+    fn features() -> &'static [Feature<Self>] {
+        static FEATURES: OnceLock<Vec<Feature<RustiFlow>>> = OnceLock::new();
+        FEATURES.get_or_init(RustiFlow::feature_list)
+    }
+
+    fn get_first_timestamp(&self) -> DateTime<Utc> {
+        self.cic_flow.get_first_timestamp()
+    }
+
+    fn is_expired(&self, timestamp: DateTime<Utc>, active_timeout: u64, idle_timeout: u64) -> bool {
+        self.cic_flow
+            .is_expired(timestamp, active_timeout, idle_timeout)
+    }
+
+    fn flow_key(&self) -> &String {
+        &self.cic_flow.basic_flow.flow_key
+    }
+
+    fn set_session_id(&mut self, session_id: String) {
+        self.session_id = Some(session_id);
+    }
+
+    fn basic_flow(&self) -> &BasicFlow {
+        &self.cic_flow.basic_flow
+    }
+
+    fn basic_flow_mut(&mut self) -> &mut BasicFlow {
+        &mut self.cic_flow.basic_flow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+    use common::parse::{IPV4_DONT_FRAGMENT, IPV4_MORE_FRAGMENTS};
+
+    use crate::{
+        args::FeatureGroup,
+        flows::{
+            flow::Flow, rusti_flow::RustiFlow, settings::FlowSettings, signatures::PayloadSnapshot,
+        },
+        packet_features::PacketFeatures,
+    };
+
+    fn setup_rusti_flow() -> RustiFlow {
+        RustiFlow::new(
+            Arc::default(),
+            "".to_string(),
+            IpAddr::V6(Ipv6Addr::from(1)),
+            80,
+            IpAddr::V6(Ipv6Addr::from(2)),
+            8080,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        )
+    }
+
+    fn setup_packet(timestamp: DateTime<Utc>) -> PacketFeatures {
+        PacketFeatures {
+            source_ip: IpAddr::V6(Ipv6Addr::from(1)),
+            destination_ip: IpAddr::V6(Ipv6Addr::from(2)),
+            source_port: 80,
+            destination_port: 8080,
+            protocol: 6,
+            timestamp,
+            fin_flag: 0,
+            syn_flag: 0,
+            rst_flag: 0,
+            psh_flag: 0,
+            ack_flag: 1,
+            urg_flag: 0,
+            cwe_flag: 0,
+            ece_flag: 0,
+            data_length: 100,
+            header_length: 20,
+            length: 160,
+            window_size: 1024,
+            sequence_number: 1,
+            sequence_number_ack: 1,
+            extension_header_count: 0,
+            ttl: 64,
+            ip_options_length: 0,
+            ip_flags: 0,
+            tcp_options_mask: 0,
+            flow_label: 0,
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
+        }
+    }
+
+    #[test]
+    fn test_extension_header_packets() {
+        let mut rusti_flow = setup_rusti_flow();
+        let timestamp = DateTime::from_timestamp(1, 0).unwrap();
+
+        let mut packet = setup_packet(timestamp);
+        rusti_flow.update_flow(&packet, true);
+        packet.extension_header_count = 1;
+        rusti_flow.update_flow(&packet, true);
+        packet.extension_header_count = 2;
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.fwd_extension_header_packets, 1);
+        assert_eq!(rusti_flow.bwd_extension_header_packets, 1);
+    }
+
+    #[test]
+    fn test_flag_combinations() {
+        let mut rusti_flow = setup_rusti_flow();
+        let timestamp = DateTime::from_timestamp(1, 0).unwrap();
+
+        assert_eq!(rusti_flow.distinct_flag_combinations(), 0);
+        assert_eq!(rusti_flow.cumulative_flags(), 0);
+
+        // SYN, SYN-ACK, ACK, ACK, FIN-ACK
+        let mut packet = setup_packet(timestamp);
+        packet.ack_flag = 0;
+        packet.syn_flag = 1;
+        rusti_flow.update_flow(&packet, true);
+        packet.ack_flag = 1;
+        rusti_flow.update_flow(&packet, false);
+        packet.syn_flag = 0;
+        rusti_flow.update_flow(&packet, true);
+        rusti_flow.update_flow(&packet, false);
+        packet.fin_flag = 1;
+        rusti_flow.update_flow(&packet, true);
+
+        assert_eq!(rusti_flow.distinct_flag_combinations(), 4);
+        assert_eq!(rusti_flow.cumulative_flags(), 0b00010011);
+    }
+
+    #[test]
+    fn test_time_to_first_byte() {
+        let mut rusti_flow = setup_rusti_flow();
+
+        assert_eq!(rusti_flow.fwd_time_to_first_byte(), None);
+        assert_eq!(rusti_flow.bwd_time_to_first_byte(), None);
+
+        // Handshake without payload, then data in the forward direction only
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+        packet.data_length = 0;
+        rusti_flow.update_flow(&packet, true);
+        packet.timestamp = DateTime::from_timestamp(0, 1_000).unwrap();
+        rusti_flow.update_flow(&packet, false);
+        packet.data_length = 100;
+        packet.timestamp = DateTime::from_timestamp(0, 250_000).unwrap();
+        rusti_flow.update_flow(&packet, true);
+        packet.timestamp = DateTime::from_timestamp(1, 0).unwrap();
+        rusti_flow.update_flow(&packet, true);
+
+        assert_eq!(rusti_flow.fwd_time_to_first_byte(), Some(250));
+        assert_eq!(rusti_flow.bwd_time_to_first_byte(), None);
+        assert!(rusti_flow.dump().contains(",250,,"));
+    }
+
+    #[test]
+    fn test_data_transfer_duration() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        // Handshake, a request, a response and a teardown after an idle second
+        packet.data_length = 0;
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.data_transfer_duration(), None);
+        packet.data_length = 100;
+        packet.timestamp = DateTime::from_timestamp(0, 10_000_000).unwrap();
+        rusti_flow.update_flow(&packet, true);
+        packet.timestamp = DateTime::from_timestamp(0, 60_000_000).unwrap();
+        rusti_flow.update_flow(&packet, false);
+        packet.data_length = 0;
+        packet.timestamp = DateTime::from_timestamp(1, 60_000_000).unwrap();
+        rusti_flow.update_flow(&packet, true);
+
+        assert_eq!(rusti_flow.data_transfer_duration(), Some(50_000));
+        assert!(rusti_flow.dump().contains(",50000,"));
+    }
+
+    #[test]
+    fn test_periodicity() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        // A forward beacon every 60 seconds with a few milliseconds of jitter
+        for (index, jitter) in [0, 2, -3, 1, 0, -1, 3, -2, 1, 0].into_iter().enumerate() {
+            packet.timestamp =
+                DateTime::from_timestamp_millis(index as i64 * 60_000 + jitter).unwrap();
+            rusti_flow.update_flow(&packet, true);
+        }
+
+        let coefficient = rusti_flow.iat_coefficient_of_variation().unwrap();
+        assert!(coefficient < 0.001);
+        assert!(rusti_flow.is_periodic_with(0.1, 10));
+        assert!(!rusti_flow.is_periodic_with(0.1, 11));
+
+        // A burst after the beacons makes the flow irregular
+        for millis in [540_010, 540_020, 600_000] {
+            packet.timestamp = DateTime::from_timestamp_millis(millis).unwrap();
+            rusti_flow.update_flow(&packet, true);
+        }
+        assert!(rusti_flow.iat_coefficient_of_variation().unwrap() > 0.4);
+        assert!(!rusti_flow.is_periodic_with(0.1, 10));
+
+        assert_eq!(setup_rusti_flow().iat_coefficient_of_variation(), None);
+    }
+
+    #[test]
+    fn test_out_of_order_packets() {
+        let mut rusti_flow = setup_rusti_flow();
+        let timestamp = DateTime::from_timestamp(1, 0).unwrap();
+
+        // Segments 1, 201 and 101 of 100 bytes each, then a retransmission of 201
+        let mut packet = setup_packet(timestamp);
+        for sequence_number in [1, 201, 101, 201, 301] {
+            packet.sequence_number = sequence_number;
+            rusti_flow.update_flow(&packet, true);
+        }
+
+        assert_eq!(rusti_flow.fwd_out_of_order_packets(), 2);
+        assert_eq!(rusti_flow.bwd_out_of_order_packets(), 0);
+
+        // The sequence numbers wrap around without counting as out of order
+        let mut rusti_flow = setup_rusti_flow();
+        for sequence_number in [u32::MAX - 149, u32::MAX - 49, 50] {
+            packet.sequence_number = sequence_number;
+            rusti_flow.update_flow(&packet, true);
+        }
+        assert_eq!(rusti_flow.fwd_out_of_order_packets(), 0);
+    }
+
+    #[test]
+    fn test_retransmitted_bytes() {
+        let mut rusti_flow = setup_rusti_flow();
+
+        // Segments of 100 bytes at 1 and 201, then the one at 101 and a retransmission of 201,
+        // and a segment at 251 overlapping the data sent before by 50 bytes
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+        for (sequence_number, seconds) in [(1, 0), (201, 0), (101, 0), (201, 1), (251, 1)] {
+            packet.sequence_number = sequence_number;
+            packet.timestamp = DateTime::from_timestamp(seconds, 0).unwrap();
+            rusti_flow.update_flow(&packet, true);
+        }
+
+        assert_eq!(rusti_flow.fwd_retransmitted_bytes(), 250);
+        assert_eq!(rusti_flow.bwd_retransmitted_bytes(), 0);
+        // 500 payload bytes over a second, 250 of them sent before
+        assert_eq!(rusti_flow.fwd_goodput(), Some(250.0));
+        assert_eq!(rusti_flow.bwd_goodput(), Some(0.0));
+
+        // Without a duration there is no goodput
+        assert_eq!(setup_rusti_flow().fwd_goodput(), None);
+    }
+
+    #[test]
+    fn test_size_ratios() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        // Ratios of 2 and 0.5
+        for length in [100, 200, 100] {
+            packet.length = length;
+            rusti_flow.update_flow(&packet, true);
+        }
+        assert_eq!(rusti_flow.fwd_size_ratio_mean(), Some(1.25));
+        assert_eq!(rusti_flow.fwd_size_ratio_std(), Some(0.75));
+
+        // A packet without length gives a ratio of 0, but none for the packet after it
+        for length in [0, 100] {
+            packet.length = length;
+            rusti_flow.update_flow(&packet, true);
+        }
+        assert_eq!(rusti_flow.fwd_size_ratio.count, 3);
+
+        // A single packet has nothing to compare to
+        rusti_flow.update_flow(&packet, false);
+        assert_eq!(rusti_flow.bwd_size_ratio_mean(), None);
+        assert_eq!(rusti_flow.bwd_size_ratio_std(), None);
+    }
+
+    #[test]
+    fn test_keepalive_packets() {
+        let mut rusti_flow = setup_rusti_flow();
+        let timestamp = DateTime::from_timestamp(1, 0).unwrap();
+
+        // 100 bytes at 1, the next expected sequence number is 101
+        let mut packet = setup_packet(timestamp);
+        rusti_flow.update_flow(&packet, true);
+        let basic_flow = &rusti_flow.cic_flow.basic_flow;
+        packet.sequence_number = 100;
+        packet.data_length = 0;
+        assert!(!basic_flow.is_keepalive(&packet, false));
+        assert!(basic_flow.is_keepalive(&packet, true));
+
+        // Keepalives without and with a garbage byte, they don't move the sequence number back
+        rusti_flow.update_flow(&packet, true);
+        packet.data_length = 1;
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.cic_flow.basic_flow.keepalive_packets(), 2);
+
+        // The next byte is only a zero window probe while the other side's window is closed
+        packet.sequence_number = 101;
+        assert!(!rusti_flow.cic_flow.basic_flow.is_keepalive(&packet, true));
+        let mut zero_window = setup_packet(timestamp);
+        zero_window.data_length = 0;
+        zero_window.window_size = 0;
+        rusti_flow.update_flow(&zero_window, false);
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.cic_flow.basic_flow.fwd_keepalive_packets, 3);
+
+        // Data and FINs are never keepalives
+        packet.data_length = 100;
+        rusti_flow.update_flow(&packet, true);
+        packet.sequence_number = 200;
+        packet.data_length = 0;
+        packet.fin_flag = 1;
+        assert!(!rusti_flow.cic_flow.basic_flow.is_keepalive(&packet, true));
+        assert_eq!(rusti_flow.cic_flow.basic_flow.keepalive_packets(), 3);
+    }
+
+    #[test]
+    fn test_zero_window() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+        packet.data_length = 0;
+
+        // The receiver stalls twice, repeating its zero window in between
+        for window_size in [1024, 0, 0, 0, 512, 0, 1024] {
+            packet.window_size = window_size;
+            rusti_flow.update_flow(&packet, false);
+        }
+        // A reset with a zero window isn't a stalled receiver
+        packet.window_size = 0;
+        packet.rst_flag = 1;
+        rusti_flow.update_flow(&packet, true);
+
+        assert_eq!(rusti_flow.bwd_zero_window_packets(), 4);
+        assert_eq!(rusti_flow.bwd_zero_window_events(), 2);
+        assert_eq!(rusti_flow.fwd_zero_window_packets(), 0);
+        assert_eq!(rusti_flow.fwd_zero_window_events(), 0);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(rusti_flow.fwd_ttl.first, None);
+        for ttl in [60, 58, 61] {
+            packet.ttl = ttl;
+            rusti_flow.update_flow(&packet, true);
+        }
+        packet.ttl = 118;
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.fwd_ttl.first, Some(60));
+        assert_eq!(rusti_flow.fwd_ttl.min, Some(58));
+        assert_eq!(rusti_flow.fwd_ttl.max, Some(61));
+        assert_eq!(rusti_flow.bwd_ttl.first, Some(118));
+        // The directions start from different TTLs, that alone isn't inconsistent
+        assert!(!rusti_flow.is_ttl_inconsistent());
+
+        // A forged packet from much closer to the capture
+        packet.ttl = 250;
+        rusti_flow.update_flow(&packet, true);
+        assert!(rusti_flow.is_ttl_inconsistent());
+    }
+
+    #[test]
+    fn test_duplicate_acks() {
+        let mut rusti_flow = setup_rusti_flow();
+        let timestamp = DateTime::from_timestamp(1, 0).unwrap();
+
+        // Three pure ACKs for the same data, followed by an ACK acknowledging new data
+        let mut packet = setup_packet(timestamp);
+        packet.data_length = 0;
+        packet.sequence_number_ack = 1001;
+        for _ in 0..3 {
+            rusti_flow.update_flow(&packet, false);
+        }
+        packet.sequence_number_ack = 2001;
+        rusti_flow.update_flow(&packet, false);
+
+        // An ACK with data repeating the acknowledgment number isn't a duplicate ACK
+        packet.data_length = 100;
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.bwd_duplicate_acks(), 2);
+        assert_eq!(rusti_flow.fwd_duplicate_acks(), 0);
+    }
+
+    #[test]
+    fn test_direction_runs() {
+        let mut rusti_flow = setup_rusti_flow();
+        let packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(rusti_flow.max_fwd_run(), 0);
+        // A request, a response of 3 packets, 2 ACKs and a final response
+        for fwd in [true, false, false, false, true, true, false] {
+            rusti_flow.update_flow(&packet, fwd);
+        }
+
+        assert_eq!(rusti_flow.max_fwd_run(), 2);
+        assert_eq!(rusti_flow.max_bwd_run(), 3);
+        assert_eq!(rusti_flow.direction_switches(), 3);
+    }
+
+    #[test]
+    fn test_turnaround_times() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut packet = setup_packet(start);
+
+        // A request at 0 ms, a response at 10 and 15 ms and the next request at 40 ms
+        for (fwd, milliseconds) in [(true, 0), (false, 10), (false, 15), (true, 40)] {
+            packet.timestamp = start + TimeDelta::milliseconds(milliseconds);
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        // Turnarounds of 10 and 30 ms
+        assert_eq!(rusti_flow.turnaround_time_mean(), Some(20_000.0));
+        assert_eq!(rusti_flow.turnaround_time_std(), Some(10_000.0));
+
+        // A flow in one direction never turns around
+        let mut one_way_flow = setup_rusti_flow();
+        one_way_flow.update_flow(&packet, true);
+        one_way_flow.update_flow(&packet, true);
+        assert_eq!(one_way_flow.turnaround_time_mean(), None);
+        assert_eq!(one_way_flow.turnaround_time_std(), None);
+    }
+
+    #[test]
+    fn test_max_idle_gap() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut packet = setup_packet(start);
+
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.max_idle_gap(), None);
+        assert_eq!(rusti_flow.max_idle_gap_position(), None);
+
+        // The flow stalls for 5 s after its third packet, across the directions
+        for (fwd, milliseconds) in [(false, 10), (true, 20), (false, 5020), (true, 5030)] {
+            packet.timestamp = start + TimeDelta::milliseconds(milliseconds);
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        assert_eq!(rusti_flow.max_idle_gap(), Some(5_000_000.0));
+        assert_eq!(rusti_flow.max_idle_gap_position(), Some(3));
+
+        // Packets at the same time make a gap of 0 after the first packet
+        let mut burst_flow = setup_rusti_flow();
+        burst_flow.update_flow(&packet, true);
+        burst_flow.update_flow(&packet, true);
+        assert_eq!(burst_flow.max_idle_gap(), Some(0.0));
+        assert_eq!(burst_flow.max_idle_gap_position(), Some(1));
+    }
+
+    #[test]
+    fn test_ip_options_bytes() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(rusti_flow.ip_options_bytes_mean(), None);
+        // Two packets without options and two with 4 and 8 bytes of options
+        for (fwd, options) in [(true, 0), (false, 0), (true, 4), (false, 8)] {
+            packet.ip_options_length = options;
+            rusti_flow.update_flow(&packet, fwd);
+        }
+
+        assert_eq!(rusti_flow.ip_options_bytes_mean(), Some(3.0));
+        assert_eq!(rusti_flow.ip_options_bytes_max(), 8);
+    }
+
+    #[test]
+    fn test_tcp_options_mask() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        // The SYN, the SYN-ACK without timestamps and an ACK, which carries no mask
+        for (fwd, syn, mask) in [(true, 1, 0b1_1111), (false, 1, 0b1_0111), (true, 0, 0)] {
+            packet.syn_flag = syn;
+            packet.tcp_options_mask = mask;
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        assert_eq!(rusti_flow.fwd_tcp_options_mask(), 0b1_1111);
+        assert_eq!(rusti_flow.bwd_tcp_options_mask(), 0b1_0111);
+        assert_eq!(setup_rusti_flow().fwd_tcp_options_mask(), 0);
+    }
+
+    #[test]
+    fn test_flow_label() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        // The responder doesn't set a label at first, a later label doesn't replace the first
+        for (fwd, flow_label) in [(true, 0), (false, 0), (true, 0x12345), (false, 0x54321)] {
+            packet.flow_label = flow_label;
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        packet.flow_label = 0x11111;
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.fwd_flow_label(), Some(0x12345));
+        assert_eq!(rusti_flow.bwd_flow_label(), Some(0x54321));
+        assert_eq!(setup_rusti_flow().fwd_flow_label(), None);
+    }
+
+    #[test]
+    fn test_first_window() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut packet = setup_packet(start);
+        let settings = &rusti_flow.cic_flow.basic_flow.settings;
+        let byte_count = u64::from(settings.packet_byte_count(&packet));
+
+        // The default window of 1 s ends with the packet at 1000 ms
+        let packets = [(true, 0), (false, 10), (true, 500), (false, 1000), (true, 1001)];
+        for (fwd, milliseconds) in packets {
+            packet.timestamp = start + TimeDelta::milliseconds(milliseconds);
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        assert_eq!(rusti_flow.fwd_packets_first_window(), 2);
+        assert_eq!(rusti_flow.bwd_packets_first_window(), 2);
+        assert_eq!(rusti_flow.fwd_bytes_first_window(), 2 * byte_count);
+        assert_eq!(rusti_flow.bwd_bytes_first_window(), 2 * byte_count);
+
+        let mut tracker = super::FirstWindowTracker::default();
+        tracker.update(TimeDelta::milliseconds(150), TimeDelta::milliseconds(100), 60, true);
+        tracker.update(TimeDelta::milliseconds(50), TimeDelta::milliseconds(100), 60, false);
+        assert_eq!((tracker.fwd_packets, tracker.bwd_bytes), (0, 60));
+    }
+
+    #[test]
+    fn test_df_packets() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let mut rusti_flow =
+            RustiFlow::new(Arc::default(), String::new(), client, 40000, server, 80, 6, start);
+        let mut packet = setup_packet(start);
+
+        assert_eq!(rusti_flow.fwd_df_packets(), Some(0));
+        assert_eq!(rusti_flow.is_fragmented_despite_df(), Some(false));
+        for (fwd, flags) in [(true, IPV4_DONT_FRAGMENT), (false, 0), (true, IPV4_DONT_FRAGMENT)] {
+            packet.ip_flags = flags;
+            rusti_flow.update_flow(&packet, fwd);
+        }
+        assert_eq!(rusti_flow.fwd_df_packets(), Some(2));
+        assert_eq!(rusti_flow.bwd_df_packets(), Some(0));
+        // A fragment of the backward direction, which doesn't set Don't Fragment
+        packet.ip_flags = IPV4_MORE_FRAGMENTS;
+        rusti_flow.update_flow(&packet, false);
+        assert_eq!(rusti_flow.is_fragmented_despite_df(), Some(false));
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.is_fragmented_despite_df(), Some(true));
+
+        // IPv6 has no Don't Fragment bit
+        let ipv6_flow = setup_rusti_flow();
+        assert_eq!(ipv6_flow.fwd_df_packets(), None);
+        assert_eq!(ipv6_flow.is_fragmented_despite_df(), None);
+    }
+
+    #[test]
+    fn test_counters_beyond_u32() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+        rusti_flow.update_flow(&packet, true);
+
+        // A long bulk flow that already carried 4 GiB in 4 billion packets
+        let boundary = u64::from(u32::MAX);
+        let cic_flow = &mut rusti_flow.cic_flow;
+        cic_flow.basic_flow.fwd_packet_count = boundary;
+        cic_flow.basic_flow.fwd_total_bytes = boundary;
+        cic_flow.fwd_pkt_len_tot = boundary;
+        cic_flow.fwd_seg_len_tot = boundary;
+        cic_flow.fwd_header_length = boundary;
+        packet.timestamp = DateTime::from_timestamp(3600, 0).unwrap();
+        rusti_flow.update_flow(&packet, true);
+        rusti_flow.update_flow(&packet, false);
+
+        let cic_flow = &rusti_flow.cic_flow;
+        assert_eq!(cic_flow.basic_flow.fwd_packet_count, boundary + 1);
+        assert_eq!(cic_flow.basic_flow.get_packet_count(), boundary + 2);
+        assert_eq!(cic_flow.basic_flow.fwd_total_bytes, boundary + 160);
+        let byte_count = u64::from(cic_flow.basic_flow.settings.packet_byte_count(&packet));
+        assert_eq!(cic_flow.fwd_pkt_len_tot, boundary + byte_count);
+        assert_eq!(cic_flow.fwd_seg_len_tot, boundary + byte_count);
+        assert_eq!(cic_flow.fwd_header_length, boundary + 20);
+        // The rates divide the totals as floats, without a product that could overflow
+        assert_eq!(rusti_flow.packet_rate_ratio(), Some((boundary + 1) as f64));
+        assert!(rusti_flow.dump().contains(&(boundary + 1).to_string()));
+    }
+
+    #[test]
+    fn test_payload_entropy() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(rusti_flow.fwd_payload_entropy(), None);
+        // Only the first payload of a direction is kept
+        packet.payload_snapshot = Some(PayloadSnapshot::new(&(0..64).collect::<Vec<u8>>()));
+        rusti_flow.update_flow(&packet, true);
+        packet.payload_snapshot = Some(PayloadSnapshot::new(&[0; 64]));
+        rusti_flow.update_flow(&packet, true);
+        rusti_flow.update_flow(&packet, false);
+
+        assert!((rusti_flow.fwd_payload_entropy().unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(rusti_flow.bwd_payload_entropy(), Some(0.0));
+    }
+
+    #[test]
+    fn test_handshake_incomplete() {
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+        packet.data_length = 0;
+
+        // A SYN scan: retransmitted SYNs without an answer
+        let mut syn_only_flow = setup_rusti_flow();
+        packet.syn_flag = 1;
+        packet.ack_flag = 0;
+        syn_only_flow.update_flow(&packet, true);
+        syn_only_flow.update_flow(&packet, true);
+        assert_eq!(syn_only_flow.is_handshake_incomplete(), Some(true));
+
+        // The SYN-ACK without the final ACK, e.g. a port scan answered by the server
+        let mut rusti_flow = setup_rusti_flow();
+        rusti_flow.update_flow(&packet, true);
+        packet.ack_flag = 1;
+        rusti_flow.update_flow(&packet, false);
+        assert_eq!(rusti_flow.is_handshake_incomplete(), Some(true));
+        packet.syn_flag = 0;
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.is_handshake_incomplete(), Some(false));
+
+        // A connection seen after its handshake
+        let mut midstream_flow = setup_rusti_flow();
+        midstream_flow.update_flow(&packet, true);
+        assert_eq!(midstream_flow.is_handshake_incomplete(), Some(false));
+
+        let udp_flow = RustiFlow::new(
+            Arc::default(),
+            "".to_string(),
+            IpAddr::V6(Ipv6Addr::from(1)),
+            53,
+            IpAddr::V6(Ipv6Addr::from(2)),
+            5353,
+            17,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        );
+        assert_eq!(udp_flow.is_handshake_incomplete(), None);
+    }
+
+    #[test]
+    fn test_connection_status() {
+        let packet = |syn_flag, ack_flag, rst_flag| {
+            let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+            packet.data_length = 0;
+            packet.syn_flag = syn_flag;
+            packet.ack_flag = ack_flag;
+            packet.rst_flag = rst_flag;
+            packet
+        };
+        let (syn, syn_ack) = (packet(1, 0, 0), packet(1, 1, 0));
+        let (ack, rst) = (packet(0, 1, 0), packet(0, 1, 1));
+
+        // A closed port answers the SYN with RST-ACK
+        let mut refused_flow = setup_rusti_flow();
+        refused_flow.update_flow(&syn, true);
+        refused_flow.update_flow(&rst, false);
+        assert_eq!(refused_flow.connection_status(), Some("refused"));
+        assert!(refused_flow.cic_flow.basic_flow.is_connection_attempt());
+
+        let mut rusti_flow = setup_rusti_flow();
+        rusti_flow.update_flow(&syn, true);
+        assert_eq!(rusti_flow.connection_status(), Some("unanswered"));
+        rusti_flow.update_flow(&syn_ack, false);
+        assert_eq!(rusti_flow.connection_status(), Some("incomplete"));
+        rusti_flow.update_flow(&ack, true);
+        assert_eq!(rusti_flow.connection_status(), Some("established"));
+        rusti_flow.update_flow(&rst, true);
+        assert_eq!(rusti_flow.connection_status(), Some("reset"));
+
+        let mut midstream_flow = setup_rusti_flow();
+        midstream_flow.update_flow(&ack, true);
+        assert_eq!(midstream_flow.connection_status(), Some("midstream"));
+        assert_eq!(midstream_flow.syn_to_first_data_time(), None);
+        assert!(!midstream_flow.cic_flow.basic_flow.is_connection_attempt());
+    }
+
+    #[test]
+    fn test_syn_to_first_data_time() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut packet = setup_packet(start);
+
+        // A SYN at 0 ms, the SYN-ACK at 5 ms, the ACK at 6 ms and the request at 50 ms
+        packet.data_length = 0;
+        packet.syn_flag = 1;
+        packet.ack_flag = 0;
+        rusti_flow.update_flow(&packet, true);
+        packet.ack_flag = 1;
+        packet.timestamp = start + TimeDelta::milliseconds(5);
+        rusti_flow.update_flow(&packet, false);
+        packet.syn_flag = 0;
+        packet.timestamp = start + TimeDelta::milliseconds(6);
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.syn_to_first_data_time(), None);
+
+        packet.data_length = 100;
+        packet.timestamp = start + TimeDelta::milliseconds(50);
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.syn_to_first_data_time(), Some(50_000));
+    }
+
+    #[test]
+    fn test_idle_ratio() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(rusti_flow.cic_flow.idle_ratio(), 0.0);
+
+        // Idle for 6 seconds after the first packet, then active for 2 seconds
+        for seconds in [0, 6, 8] {
+            let packet = setup_packet(start + TimeDelta::seconds(seconds));
+            rusti_flow.update_flow(&packet, true);
+        }
+        assert_eq!(rusti_flow.cic_flow.idle_ratio(), 0.75);
+    }
+
+    #[test]
+    fn test_direction_asymmetry() {
+        let mut rusti_flow = setup_rusti_flow();
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut packet = setup_packet(start);
+
+        assert_eq!(rusti_flow.byte_rate_ratio(), None);
+        assert_eq!(rusti_flow.dominant_direction(), "balanced");
+
+        // A request and a download of 3 times the bytes, over two seconds
+        packet.data_length = 100;
+        rusti_flow.update_flow(&packet, true);
+        assert_eq!(rusti_flow.byte_rate_difference(), None);
+        packet.data_length = 300;
+        packet.timestamp = start + TimeDelta::seconds(1);
+        rusti_flow.update_flow(&packet, false);
+        packet.timestamp = start + TimeDelta::seconds(2);
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.byte_rate_ratio(), Some(100.0 / 600.0));
+        assert_eq!(rusti_flow.byte_rate_difference(), Some(-250.0));
+        assert_eq!(rusti_flow.packet_rate_ratio(), Some(0.5));
+        assert_eq!(rusti_flow.packet_rate_difference(), Some(-0.5));
+        assert_eq!(rusti_flow.dominant_direction(), "bwd");
+
+        assert_eq!(super::dominant_direction(199, 100), "balanced");
+        assert_eq!(super::dominant_direction(200, 100), "fwd");
+        assert_eq!(super::dominant_direction(0, 0), "balanced");
+    }
+
+    #[test]
+    fn test_payload_packet_ratio() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        assert_eq!(rusti_flow.fwd_payload_packet_ratio(), None);
+
+        // Three forward data segments acknowledged by a single pure ACK
+        for _ in 0..3 {
+            rusti_flow.update_flow(&packet, true);
+        }
+        packet.data_length = 0;
+        rusti_flow.update_flow(&packet, true);
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.fwd_payload_packet_ratio(), Some(0.75));
+        assert_eq!(rusti_flow.bwd_payload_packet_ratio(), Some(0.0));
+        let tail = ",0,0,4,1,1,,,4,,fwd,0,0,0,0,64,64,64,64,64,64,0,200,0,,,1,0,,,\
+            well-known,registered,80,0,0,0,0,0,midstream,,0,,,,,,,0,1,0,0,4,1,300,0,,";
+        assert!(rusti_flow.dump().ends_with(tail));
+    }
+
+    #[test]
+    fn test_packet_size_histogram() {
+        let mut rusti_flow = setup_rusti_flow();
+        let mut packet = setup_packet(DateTime::from_timestamp(0, 0).unwrap());
+
+        for length in [40, 64, 65, 1500, 1501] {
+            packet.length = length;
+            rusti_flow.update_flow(&packet, true);
+        }
+        packet.length = 300;
+        rusti_flow.update_flow(&packet, false);
+        packet.length = 9000;
+        rusti_flow.update_flow(&packet, false);
+        packet.length = 9001;
+        rusti_flow.update_flow(&packet, false);
+
+        assert_eq!(rusti_flow.fwd_packet_size_histogram, [2, 1, 0, 0, 0, 1, 1, 0]);
+        assert_eq!(rusti_flow.bwd_packet_size_histogram, [0, 0, 0, 1, 0, 0, 1, 1]);
+        assert_eq!(rusti_flow.jumbo_packets, 3);
+        let header = RustiFlow::get_features();
+        assert!(header.contains(",FWD_PACKET_SIZE_0_64,FWD_PACKET_SIZE_65_128,"));
+        assert!(header.contains(",BWD_PACKET_SIZE_1025_1500,BWD_PACKET_SIZE_1501_9000,"));
+        assert!(header.contains(",BWD_PACKET_SIZE_ABOVE_9000,"));
+        assert_eq!(
+            header.split(',').count(),
+            rusti_flow.dump().split(',').count()
+        );
+
+        // The fwd and bwd buckets are dumped below their header columns
+        let dump = rusti_flow.dump();
+        let first_bucket = header
+            .split(',')
+            .position(|column| column == "FWD_PACKET_SIZE_0_64")
+            .unwrap();
+        let buckets: Vec<&str> = dump.split(',').skip(first_bucket).take(16).collect();
+        assert_eq!(
+            buckets,
+            ["2", "1", "0", "0", "0", "1", "1", "0", "0", "0", "0", "1", "0", "0", "1", "1"]
+        );
+    }
+
+    #[test]
+    fn test_disabled_feature_groups() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let client = IpAddr::V6(Ipv6Addr::from(1));
+        let server = IpAddr::V6(Ipv6Addr::from(2));
+        let settings = Arc::new(FlowSettings {
+            disabled_features: [FeatureGroup::Iat, FeatureGroup::Histograms].into_iter().collect(),
+            ..FlowSettings::default()
+        });
+        let mut flows = [
+            RustiFlow::new(Arc::default(), String::new(), client, 80, server, 8080, 6, start),
+            RustiFlow::new(settings, String::new(), client, 80, server, 8080, 6, start),
+        ];
+        let mut packet = setup_packet(start);
+        for flow in &mut flows {
+            flow.update_flow(&packet, true);
+        }
+        packet.timestamp = start + TimeDelta::milliseconds(10);
+        for flow in &mut flows {
+            flow.update_flow(&packet, false);
+        }
+
+        let header = RustiFlow::get_features();
+        let value = |flow: &RustiFlow, column: &str| {
+            let index = header.split(',').position(|name| name == column).unwrap();
+            flow.dump().split(',').nth(index).unwrap().to_string()
+        };
+        assert_eq!(value(&flows[0], "FLOW_IAT_MAX"), "10000");
+        assert_eq!(value(&flows[0], "FWD_PACKET_SIZE_129_256"), "1");
+        // The columns of the disabled groups stay in the header, without values
+        assert_eq!(value(&flows[1], "FLOW_IAT_MAX"), "");
+        assert_eq!(value(&flows[1], "FWD_PACKET_SIZE_129_256"), "");
+        assert_eq!(flows[1].fwd_packet_size_histogram, [0; 8]);
+        assert_eq!(value(&flows[1], "DURATION"), value(&flows[0], "DURATION"));
+    }
+
+    #[test]
+    fn test_port_categories() {
+        let flow = |port_source, port_destination, protocol| {
+            RustiFlow::new(
+                Arc::default(),
+                "".to_string(),
+                IpAddr::V6(Ipv6Addr::from(1)),
+                port_source,
+                IpAddr::V6(Ipv6Addr::from(2)),
+                port_destination,
+                protocol,
+                DateTime::from_timestamp(0, 0).unwrap(),
+            )
+        };
+
+        let https = flow(51000, 443, 6);
+        assert_eq!(https.source_port_category(), Some("ephemeral"));
+        assert_eq!(https.destination_port_category(), Some("well-known"));
+        assert_eq!(https.cic_flow.basic_flow.server_port(), Some(443));
+        // The server answering first, e.g. a flow whose first packets were missed
+        assert_eq!(flow(443, 51000, 6).cic_flow.basic_flow.server_port(), Some(443));
+        // The responder within the same range
+        assert_eq!(flow(50000, 60000, 17).cic_flow.basic_flow.server_port(), Some(60000));
+
+        let icmp = flow(0, 0, 1);
+        assert_eq!(icmp.source_port_category(), None);
+        assert_eq!(icmp.cic_flow.basic_flow.server_port(), None);
+    }
+}
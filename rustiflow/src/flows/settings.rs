@@ -0,0 +1,453 @@
+use std::{fmt, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+
+use crate::{
+    args::{
+        ByteCount, FeatureGroup, FlowKeyStrategy, IpVersion, NullValue, PacketFilterRule,
+        PartitionKey, PersistentFlowRule, TimestampFormat, Timezone, DEFAULT_FIRST_WINDOW,
+        DEFAULT_MAX_STRING_LENGTH, DEFAULT_PERIODICITY_MIN_PACKETS, DEFAULT_PERIODICITY_THRESHOLD,
+    },
+    packet_features::PacketFeatures,
+};
+
+use super::{
+    anonymize::IpAnonymizer, basic_flow::BasicFlow, signatures::SignatureSet,
+    util::sanitize_string_feature,
+};
+
+/// A set of feature groups, a bit per group as it is checked for every packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FeatureGroups(u8);
+
+impl FeatureGroups {
+    /// Checks whether the group is in the set.
+    pub fn contains(self, group: FeatureGroup) -> bool {
+        self.0 & Self::bit(group) != 0
+    }
+
+    fn bit(group: FeatureGroup) -> u8 {
+        1 << group as u8
+    }
+}
+
+impl FromIterator<FeatureGroup> for FeatureGroups {
+    fn from_iter<I: IntoIterator<Item = FeatureGroup>>(groups: I) -> Self {
+        FeatureGroups(groups.into_iter().fold(0, |bits, group| bits | Self::bit(group)))
+    }
+}
+
+/// Settings that change how flows are tracked, compute and dump their features.
+///
+/// The settings are set once at startup and shared by all flows of a run, every flow holds
+/// them (see `BasicFlow::settings`) as do the flow tables and the output writer.
+#[derive(Debug, Clone)]
+pub struct FlowSettings {
+    /// Which packet length feeds the byte features.
+    pub byte_count: ByteCount,
+    /// The IP versions whose packets are processed.
+    pub ip_version: IpVersion,
+    /// Which packet fields make up the flow keys.
+    pub flow_key: FlowKeyStrategy,
+    /// The maximum length of dumped string features.
+    pub max_string_length: usize,
+    /// How features without a value are dumped.
+    pub null_value: NullValue,
+    /// How the flow timestamps are dumped.
+    pub timestamp_format: TimestampFormat,
+    /// The time zone of the text timestamps, UTC if `None`.
+    pub timezone: Option<Timezone>,
+    /// Whether related flows announced on control channels are linked to them.
+    pub track_expectations: bool,
+    /// Only 1 in this many flows is tracked, if flow sampling is enabled.
+    pub flow_sampling: Option<u32>,
+    /// The interval of the interim records of all active flows in seconds, if enabled.
+    pub export_window: Option<u64>,
+    /// The number of packets whose lengths are exported as a sequence, if enabled.
+    pub packet_sequence_length: Option<usize>,
+    /// The memory the optional variable-length features of all flows may hold in MiB.
+    pub feature_memory_limit: Option<u64>,
+    /// The number of flows each flow table reserves room for at the start, if configured.
+    pub flow_table_capacity: Option<usize>,
+    /// Whether the raw TCP flag bytes of both directions are exported.
+    pub raw_flags: bool,
+    /// Flows with a lower coefficient of variation of their IATs are periodic.
+    pub periodicity_threshold: f64,
+    /// The minimum number of packets of a periodic flow.
+    pub periodicity_min_packets: u32,
+    /// The milliseconds after the first packet of a flow its first window features cover.
+    pub first_window: u64,
+    /// Whether TCP keepalives and zero window probes are left out of the statistical features.
+    pub exclude_keepalives: bool,
+    /// The feature groups whose accumulators are skipped.
+    pub disabled_features: FeatureGroups,
+    /// The local subnets, the flows within one of them are east-west traffic.
+    pub local_subnets: Vec<IpNetwork>,
+    /// The rules for flows that are kept across the active timeout.
+    pub persistent_flows: Vec<PersistentFlowRule>,
+    /// The rules selecting the packets that are processed.
+    pub packet_filters: Vec<PacketFilterRule>,
+    /// Pseudonymizes the dumped IP addresses, if anonymization is enabled.
+    pub anonymizer: Option<IpAnonymizer>,
+    /// Whether the lower endpoint of a flow is dumped as its source, instead of the initiator.
+    pub canonical_endpoints: bool,
+    /// Whether the wall clock time a flow was exported at is dumped with it.
+    pub export_time: bool,
+    /// Whether the reason a flow was exported is dumped with it.
+    pub end_reason: bool,
+    /// Whether TCP connection attempts are exported regardless of the packet threshold.
+    pub keep_connection_attempts: bool,
+    /// The signatures the first payloads of the flows are matched against, if enabled.
+    pub payload_signatures: Option<SignatureSet>,
+    /// Whether the first payload bytes of each direction are kept, for the signatures or the
+    /// payload entropy.
+    pub payload_snapshots: bool,
+    /// The id of the sensor exported with every flow, if configured.
+    pub sensor_id: Option<String>,
+    /// What the flows are written to separate outputs by, if the output is partitioned.
+    pub partition_by: Option<PartitionKey>,
+    /// The interface or the pcap file the packets are captured from.
+    pub capture_source: String,
+}
+
+impl Default for FlowSettings {
+    fn default() -> Self {
+        FlowSettings {
+            byte_count: ByteCount::default(),
+            ip_version: IpVersion::default(),
+            flow_key: FlowKeyStrategy::default(),
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            null_value: NullValue::default(),
+            timestamp_format: TimestampFormat::default(),
+            timezone: None,
+            track_expectations: false,
+            flow_sampling: None,
+            export_window: None,
+            packet_sequence_length: None,
+            feature_memory_limit: None,
+            flow_table_capacity: None,
+            raw_flags: false,
+            periodicity_threshold: DEFAULT_PERIODICITY_THRESHOLD,
+            periodicity_min_packets: DEFAULT_PERIODICITY_MIN_PACKETS,
+            first_window: DEFAULT_FIRST_WINDOW,
+            exclude_keepalives: false,
+            disabled_features: FeatureGroups::default(),
+            local_subnets: Vec::new(),
+            persistent_flows: Vec::new(),
+            packet_filters: Vec::new(),
+            anonymizer: None,
+            canonical_endpoints: false,
+            export_time: false,
+            end_reason: false,
+            keep_connection_attempts: false,
+            payload_signatures: None,
+            payload_snapshots: false,
+            sensor_id: None,
+            partition_by: None,
+            capture_source: String::new(),
+        }
+    }
+}
+
+impl FlowSettings {
+    /// Returns the number of bytes a packet contributes to the byte features.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The packet to be counted.
+    ///
+    /// ### Returns
+    ///
+    /// The payload length or the total IP length of the packet, depending on the settings.
+    pub fn packet_byte_count(&self, packet: &PacketFeatures) -> u32 {
+        match self.byte_count {
+            ByteCount::Payload => u32::from(packet.data_length),
+            ByteCount::Total => u32::from(packet.length),
+        }
+    }
+
+    /// Prepares a string feature for the export, see `sanitize_string_feature`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `value` - The string feature to be dumped.
+    ///
+    /// ### Returns
+    ///
+    /// The value capped at the configured maximum length, safe to put in a CSV field.
+    pub fn string_feature(&self, value: &str) -> String {
+        sanitize_string_feature(value, self.max_string_length)
+    }
+
+    /// Checks whether the features of a group are computed in this run.
+    ///
+    /// ### Arguments
+    ///
+    /// * `group` - The feature group to be checked.
+    pub fn is_feature_group_enabled(&self, group: FeatureGroup) -> bool {
+        !self.disabled_features.contains(group)
+    }
+
+    /// Prepares a timestamp for the export, all exported timestamps are written by this.
+    ///
+    /// ### Arguments
+    ///
+    /// * `timestamp` - The timestamp to be dumped.
+    /// * `default` - The format of the feature set, used unless another one is configured.
+    pub fn timestamp_feature(&self, timestamp: DateTime<Utc>, default: TimestampFormat) -> String {
+        self.timestamp_format.or(default).format(timestamp, self.timezone)
+    }
+
+    /// Appends a timestamp to a record, like `timestamp_feature`.
+    pub fn write_timestamp_feature(
+        &self,
+        timestamp: DateTime<Utc>,
+        default: TimestampFormat,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        self.timestamp_format.or(default).write(timestamp, self.timezone, out)
+    }
+
+    /// Prepares an IP address for the export, pseudonymized if anonymization is enabled.
+    ///
+    /// ### Arguments
+    ///
+    /// * `ip` - The IP address to be dumped.
+    pub fn ip_feature(&self, ip: IpAddr) -> IpAddr {
+        match &self.anonymizer {
+            Some(anonymizer) => anonymizer.anonymize(ip),
+            None => ip,
+        }
+    }
+
+    /// Prepares a flow key for the export, see `ip_feature` and `string_feature`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `flow_key` - The flow key (or the session id) to be dumped.
+    pub fn flow_key_feature(&self, flow_key: &str) -> String {
+        match &self.anonymizer {
+            Some(anonymizer) => self.string_feature(
+                &anonymizer.anonymize_flow_key(flow_key, self.flow_key.has_ports()),
+            ),
+            None => self.string_feature(flow_key),
+        }
+    }
+
+    /// Checks whether the initiator of a flow is dumped as its source.
+    ///
+    /// With canonical endpoints, the lower IP and port (compared before the anonymization) is
+    /// the source, so both directions of a connection give the same endpoints in every run.
+    pub fn is_source_initiator(&self, flow: &BasicFlow) -> bool {
+        !self.canonical_endpoints
+            || (flow.ip_source, flow.port_source) <= (flow.ip_destination, flow.port_destination)
+    }
+
+    /// Prepares the endpoints of a flow for the export, e.g. for the table output.
+    ///
+    /// The forward and backward features stay those of the initiator, only the endpoints (and
+    /// the flow key, see `registry::flow_key_feature`) follow `is_source_initiator`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `flow` - The flow whose endpoints are dumped.
+    pub fn endpoint_features(&self, flow: &BasicFlow) -> EndpointFeatures {
+        if self.is_source_initiator(flow) {
+            return EndpointFeatures {
+                ip_source: self.ip_feature(flow.ip_source),
+                port_source: flow.port_source,
+                ip_destination: self.ip_feature(flow.ip_destination),
+                port_destination: flow.port_destination,
+            };
+        }
+
+        EndpointFeatures {
+            ip_source: self.ip_feature(flow.ip_destination),
+            port_source: flow.port_destination,
+            ip_destination: self.ip_feature(flow.ip_source),
+            port_destination: flow.port_source,
+        }
+    }
+
+    /// Returns the flow sampling rate N of this run, every N-th flow is tracked (1 without
+    /// sampling).
+    pub fn sampling_rate(&self) -> u32 {
+        self.flow_sampling.unwrap_or(1)
+    }
+
+    /// Decides whether the flow a packet starts is tracked, according to the flow sampling.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The first packet of the flow.
+    ///
+    /// ### Returns
+    ///
+    /// `true` if the flow is sampled (always without flow sampling).
+    pub fn is_flow_sampled(&self, packet: &PacketFeatures) -> bool {
+        match self.flow_sampling {
+            Some(rate) if rate > 1 => keeps_flow(&packet.biflow_key(self.flow_key), rate),
+            _ => true,
+        }
+    }
+
+    /// Returns the first persistent flow rule that matches the given flow, if any.
+    ///
+    /// ### Arguments
+    ///
+    /// * `flow` - The flow to be matched.
+    pub fn persistent_flow_rule(&self, flow: &BasicFlow) -> Option<&PersistentFlowRule> {
+        self.persistent_flows.iter().find(|rule| {
+            rule.matches(
+                flow.ip_source,
+                flow.port_source,
+                flow.ip_destination,
+                flow.port_destination,
+                flow.protocol,
+            )
+        })
+    }
+
+    /// Checks whether a packet passes the packet filter rules of this run.
+    ///
+    /// ### Arguments
+    ///
+    /// * `packet` - The packet to be checked.
+    pub fn is_packet_accepted(&self, packet: &PacketFeatures) -> bool {
+        PacketFilterRule::accepts(
+            &self.packet_filters,
+            packet.source_ip,
+            packet.source_port,
+            packet.destination_ip,
+            packet.destination_port,
+            packet.protocol,
+        )
+    }
+}
+
+/// The endpoints of a flow as they are dumped.
+pub struct EndpointFeatures {
+    pub ip_source: IpAddr,
+    pub port_source: u16,
+    pub ip_destination: IpAddr,
+    pub port_destination: u16,
+}
+
+/// Picks 1 in `rate` flows by a hash of their direction independent key.
+///
+/// The hash (FNV-1a with a final mix) doesn't depend on the process or the Rust version,
+/// so the same flows are sampled in every run.
+pub fn keeps_flow(biflow_key: &str, rate: u32) -> bool {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in biflow_key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    // Spread the low bits, similar keys differ in only a few bytes
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash.is_multiple_of(u64::from(rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::DateTime;
+
+    use super::{keeps_flow, FlowSettings};
+    use crate::{
+        args::Anonymization,
+        flows::{anonymize::IpAnonymizer, basic_flow::BasicFlow, flow::Flow},
+    };
+
+    /// A flow from 10.0.0.2:40000 to 10.0.0.1:443, the responder is the lower endpoint.
+    fn setup_flow(settings: FlowSettings) -> BasicFlow {
+        BasicFlow::new(
+            Arc::new(settings),
+            "10.0.0.2:40000-10.0.0.1:443-6".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            443,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        )
+    }
+
+    /// Returns the first five columns of a dumped flow, the flow key and the endpoints.
+    fn dumped_endpoints(flow: &BasicFlow) -> Vec<String> {
+        flow.dump().split(',').take(5).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_canonical_endpoints() {
+        let flow = setup_flow(FlowSettings::default());
+        assert!(flow.settings.is_source_initiator(&flow));
+        assert_eq!(
+            dumped_endpoints(&flow),
+            ["10.0.0.2:40000-10.0.0.1:443-6", "10.0.0.2", "40000", "10.0.0.1", "443"]
+        );
+
+        // The lower endpoint is the source, the flow key is reversed with it
+        let flow = setup_flow(FlowSettings {
+            canonical_endpoints: true,
+            ..FlowSettings::default()
+        });
+        assert!(!flow.settings.is_source_initiator(&flow));
+        assert_eq!(
+            dumped_endpoints(&flow),
+            ["10.0.0.1:443-10.0.0.2:40000-6", "10.0.0.1", "443", "10.0.0.2", "40000"]
+        );
+        let endpoints = flow.settings.endpoint_features(&flow);
+        assert_eq!(endpoints.ip_source, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(endpoints.port_source, 443);
+    }
+
+    #[test]
+    fn test_anonymized_endpoints() {
+        let anonymizer = IpAnonymizer::new(Anonymization::Hash, "secret");
+        let client = anonymizer.anonymize(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let server = anonymizer.anonymize(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let flow = setup_flow(FlowSettings {
+            anonymizer: Some(anonymizer),
+            ..FlowSettings::default()
+        });
+
+        // The addresses of the flow key are pseudonymized like the endpoints, the ports kept
+        assert_eq!(
+            dumped_endpoints(&flow),
+            [
+                format!("{}:40000-{}:443-6", client, server),
+                client.to_string(),
+                "40000".to_string(),
+                server.to_string(),
+                "443".to_string(),
+            ]
+        );
+        // Flows of other runs are unaffected
+        let flow = setup_flow(FlowSettings::default());
+        assert_eq!(dumped_endpoints(&flow)[1], "10.0.0.2");
+    }
+
+    #[test]
+    fn test_keeps_flow() {
+        let keys: Vec<String> = (0..10_000)
+            .map(|port| format!("10.0.0.1:{}-10.0.0.2:443-6", port))
+            .collect();
+
+        assert!(keys.iter().all(|key| keeps_flow(key, 1)));
+        let sampled = keys.iter().filter(|key| keeps_flow(key, 10)).count();
+        assert!((800..1200).contains(&sampled), "sampled {} of 10000 flows", sampled);
+
+        // The decision only depends on the key
+        let first: Vec<bool> = keys.iter().map(|key| keeps_flow(key, 10)).collect();
+        let second: Vec<bool> = keys.iter().map(|key| keeps_flow(key, 10)).collect();
+        assert_eq!(first, second);
+    }
+}
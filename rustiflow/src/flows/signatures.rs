@@ -0,0 +1,249 @@
+//! Matching of the first payload bytes of the flows against a set of byte pattern signatures,
+//! a lightweight application identification without parsing the protocols.
+//!
+//! A signature is a label and a hex pattern at an offset into the first payload of a
+//! direction, e.g. `474554` at offset 0 of the forward payload for an HTTP GET. The flows keep
+//! only the first `PAYLOAD_SNAPSHOT_LENGTH` payload bytes of each direction for it.
+
+use std::fs;
+
+use serde::Deserialize;
+
+use super::basic_flow::BasicFlow;
+
+/// The payload bytes kept of the first payload packet of each direction.
+pub const PAYLOAD_SNAPSHOT_LENGTH: usize = 64;
+
+/// The first `PAYLOAD_SNAPSHOT_LENGTH` bytes of a payload, in a fixed array so that the
+/// packets carry them without an allocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadSnapshot {
+    bytes: [u8; PAYLOAD_SNAPSHOT_LENGTH],
+    length: u8,
+}
+
+impl PayloadSnapshot {
+    /// Copies the first bytes of a payload, at most `PAYLOAD_SNAPSHOT_LENGTH`.
+    pub fn new(payload: &[u8]) -> Self {
+        let length = payload.len().min(PAYLOAD_SNAPSHOT_LENGTH);
+        let mut bytes = [0; PAYLOAD_SNAPSHOT_LENGTH];
+        bytes[..length].copy_from_slice(&payload[..length]);
+        PayloadSnapshot {
+            bytes,
+            length: length as u8,
+        }
+    }
+
+    /// Returns the kept bytes of the payload.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.length)]
+    }
+}
+
+/// The direction whose first payload a signature is matched against.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum SignatureDirection {
+    /// The first payload of the initiator.
+    Forward,
+    /// The first payload of the responder.
+    Backward,
+    /// The first payload of either direction.
+    #[default]
+    Any,
+}
+
+/// A signature as it is written in the signature file, a `[[signature]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignatureEntry {
+    label: String,
+    /// The bytes in hex, whitespace is ignored and `??` matches any byte.
+    pattern: String,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    direction: SignatureDirection,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignatureFile {
+    #[serde(default)]
+    signature: Vec<SignatureEntry>,
+}
+
+/// A byte pattern at an offset of the first payload of a direction.
+#[derive(Debug, Clone, PartialEq)]
+struct PayloadSignature {
+    label: String,
+    offset: usize,
+    /// The bytes to match, `None` matches any byte.
+    pattern: Vec<Option<u8>>,
+    direction: SignatureDirection,
+}
+
+impl PayloadSignature {
+    fn matches(&self, payload: Option<&[u8]>) -> bool {
+        let Some(bytes) = payload.and_then(|payload| {
+            payload.get(self.offset..self.offset + self.pattern.len())
+        }) else {
+            return false;
+        };
+        bytes
+            .iter()
+            .zip(&self.pattern)
+            .all(|(byte, pattern)| pattern.is_none_or(|pattern| pattern == *byte))
+    }
+}
+
+/// The signatures the flows are matched against at their export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureSet {
+    signatures: Vec<PayloadSignature>,
+}
+
+impl SignatureSet {
+    /// Loads the signatures of a TOML file with a `[[signature]]` table per signature.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the signature file {}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| format!("Invalid signature file {}: {}", path, e))
+    }
+
+    /// Parses the signatures of the content of a signature file.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: SignatureFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let signatures = file
+            .signature
+            .into_iter()
+            .map(|entry| {
+                let label = entry.label;
+                let valid_label = |c: char| c.is_ascii_alphanumeric() || "_-.".contains(c);
+                if label.is_empty() || !label.chars().all(valid_label) {
+                    return Err(format!(
+                        "the label `{}` must be letters, digits, `_`, `-` and `.`",
+                        label
+                    ));
+                }
+                let pattern = parse_pattern(&entry.pattern)
+                    .map_err(|e| format!("the pattern of `{}` {}", label, e))?;
+                if entry.offset + pattern.len() > PAYLOAD_SNAPSHOT_LENGTH {
+                    return Err(format!(
+                        "the pattern of `{}` ends beyond the first {} payload bytes",
+                        label, PAYLOAD_SNAPSHOT_LENGTH
+                    ));
+                }
+                Ok(PayloadSignature {
+                    label,
+                    offset: entry.offset,
+                    pattern,
+                    direction: entry.direction,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SignatureSet { signatures })
+    }
+
+    /// Returns the labels of the signatures matching the first payloads of a flow, each once
+    /// and in the order of the file.
+    pub fn matches<'a>(&'a self, flow: &BasicFlow) -> Vec<&'a str> {
+        let fwd = flow.fwd_payload_snapshot.as_deref().map(PayloadSnapshot::as_bytes);
+        let bwd = flow.bwd_payload_snapshot.as_deref().map(PayloadSnapshot::as_bytes);
+        let mut labels: Vec<&str> = Vec::new();
+        for signature in &self.signatures {
+            let matched = match signature.direction {
+                SignatureDirection::Forward => signature.matches(fwd),
+                SignatureDirection::Backward => signature.matches(bwd),
+                SignatureDirection::Any => signature.matches(fwd) || signature.matches(bwd),
+            };
+            if matched && !labels.contains(&signature.label.as_str()) {
+                labels.push(&signature.label);
+            }
+        }
+        labels
+    }
+}
+
+/// Parses a hex pattern, `??` for any byte.
+fn parse_pattern(pattern: &str) -> Result<Vec<Option<u8>>, String> {
+    let digits: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err("needs an even number of hex digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            if byte == "??" {
+                return Ok(None);
+            }
+            u8::from_str_radix(&byte, 16)
+                .map(Some)
+                .map_err(|_| format!("has the invalid byte `{}`", byte))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::DateTime;
+
+    use super::{PayloadSnapshot, SignatureSet, PAYLOAD_SNAPSHOT_LENGTH};
+    use crate::flows::{basic_flow::BasicFlow, flow::Flow};
+
+    #[test]
+    fn test_signature_matches() {
+        let signatures = SignatureSet::parse(
+            r#"
+            [[signature]]
+            label = "http"
+            pattern = "47 45 54 20"
+            direction = "Forward"
+
+            [[signature]]
+            label = "http"
+            pattern = "485454502f"
+            direction = "Backward"
+
+            [[signature]]
+            label = "tls"
+            pattern = "16 03 ??"
+
+            [[signature]]
+            label = "ssh"
+            pattern = "5353482d"
+            offset = 2
+            "#,
+        )
+        .unwrap();
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let mut flow = BasicFlow::new(Arc::default(), String::new(), ip, 40000, ip, 80, 6, start);
+        assert!(signatures.matches(&flow).is_empty());
+
+        let snapshot = |payload: &[u8]| Some(Box::new(PayloadSnapshot::new(payload)));
+        flow.fwd_payload_snapshot = snapshot(b"GET / HTTP/1.1");
+        flow.bwd_payload_snapshot = snapshot(b"HTTP/1.1 200 OK");
+        assert_eq!(signatures.matches(&flow), ["http"]);
+        // The wildcard takes any version byte, a pattern beyond the payload doesn't match
+        flow.fwd_payload_snapshot = snapshot(&[0x16, 0x03, 0x01, 0x02]);
+        flow.bwd_payload_snapshot = snapshot(b"xxSSH");
+        assert_eq!(signatures.matches(&flow), ["tls"]);
+        // Only the first bytes of a payload are kept
+        let snapshot = PayloadSnapshot::new(&[0x47; 100]);
+        assert_eq!(snapshot.as_bytes(), [0x47; PAYLOAD_SNAPSHOT_LENGTH]);
+
+        let error = SignatureSet::parse("[[signature]]\nlabel = \"a,b\"\npattern = \"00\"\n");
+        assert!(error.unwrap_err().contains("label"));
+        let error = SignatureSet::parse("[[signature]]\nlabel = \"x\"\npattern = \"0g\"\n");
+        assert!(error.unwrap_err().contains("0g"));
+        let error =
+            SignatureSet::parse("[[signature]]\nlabel = \"x\"\npattern = \"00\"\noffset = 64\n");
+        assert!(error.unwrap_err().contains("beyond"));
+    }
+}
@@ -1,3 +1,7 @@
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+
 /// Calculates the new mean using the old_mean, the number of packets, and the new value.
 ///
 /// ### Arguments
@@ -38,3 +42,272 @@ pub fn calculate_std(
         / packet_count as f64)
         .sqrt()
 }
+
+/// Combines the population variances of two groups into the variance of all their values.
+///
+/// ### Arguments
+///
+/// * `count_a` - The number of values in the first group.
+/// * `mean_a` - The mean of the first group.
+/// * `variance_a` - The population variance of the first group.
+/// * `count_b` - The number of values in the second group.
+/// * `mean_b` - The mean of the second group.
+/// * `variance_b` - The population variance of the second group.
+///
+/// ### Returns
+///
+/// The population variance of both groups together, 0 if both are empty.
+pub fn combine_variances(
+    count_a: u64,
+    mean_a: f64,
+    variance_a: f64,
+    count_b: u64,
+    mean_b: f64,
+    variance_b: f64,
+) -> f64 {
+    let count = count_a + count_b;
+    if count == 0 {
+        return 0.0;
+    }
+
+    let mean = (count_a as f64 * mean_a + count_b as f64 * mean_b) / count as f64;
+    (count_a as f64 * (variance_a + (mean_a - mean).powi(2))
+        + count_b as f64 * (variance_b + (mean_b - mean).powi(2)))
+        / count as f64
+}
+
+/// Marker appended to string features that were cut off.
+const TRUNCATION_MARKER: &str = "...";
+
+/// Makes a string feature safe to export.
+///
+/// Delimiters, quotes and control characters (e.g. newlines) are replaced with `_`, so a
+/// crafted value can't break the CSV structure. Values longer than `max_length` characters
+/// are cut off and end with `...`, the result never exceeds `max_length` characters.
+///
+/// ### Arguments
+///
+/// * `value` - The string feature to be sanitized.
+/// * `max_length` - The maximum number of characters of the result.
+///
+/// ### Returns
+///
+/// The sanitized value.
+pub fn sanitize_string_feature(value: &str, max_length: usize) -> String {
+    let sanitize = |c: char| {
+        if c == ',' || c == '"' || c.is_control() {
+            '_'
+        } else {
+            c
+        }
+    };
+
+    if value.chars().count() <= max_length {
+        return value.chars().map(sanitize).collect();
+    }
+
+    let marker_length = TRUNCATION_MARKER.len().min(max_length);
+    let mut truncated: String = value
+        .chars()
+        .take(max_length - marker_length)
+        .map(sanitize)
+        .collect();
+    truncated.push_str(&TRUNCATION_MARKER[..marker_length]);
+    truncated
+}
+
+/// Checks whether an IP address is private: RFC 1918, unique local (fc00::/7) or loopback.
+///
+/// IPv4-mapped IPv6 addresses are classified by their IPv4 address.
+pub fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ip(IpAddr::V4(ip)),
+            None => ip.is_unique_local() || ip.is_loopback(),
+        },
+    }
+}
+
+/// Classifies which side of the private/public boundary the endpoints of a flow are on.
+///
+/// ### Arguments
+///
+/// * `source` - The source IP address of the flow.
+/// * `destination` - The destination IP address of the flow.
+///
+/// ### Returns
+///
+/// One of `private-to-private`, `private-to-public`, `public-to-private` and `public-to-public`.
+pub fn endpoint_locality(source: IpAddr, destination: IpAddr) -> &'static str {
+    match (is_private_ip(source), is_private_ip(destination)) {
+        (true, true) => "private-to-private",
+        (true, false) => "private-to-public",
+        (false, true) => "public-to-private",
+        (false, false) => "public-to-public",
+    }
+}
+
+/// Checks whether both endpoints of a flow are in one of the given subnets.
+///
+/// ### Arguments
+///
+/// * `subnets` - The subnets, e.g. the local subnets of a datacenter.
+/// * `source` - The source IP address of the flow.
+/// * `destination` - The destination IP address of the flow.
+///
+/// ### Returns
+///
+/// `true` if a subnet contains both addresses, e.g. for east-west traffic.
+pub fn same_subnet(subnets: &[IpNetwork], source: IpAddr, destination: IpAddr) -> bool {
+    subnets
+        .iter()
+        .any(|subnet| subnet.contains(source) && subnet.contains(destination))
+}
+
+/// The highest well-known (system) port of the IANA port ranges.
+pub const MAX_WELL_KNOWN_PORT: u16 = 1023;
+
+/// The highest registered (user) port, the ports above are dynamic (ephemeral) ones.
+pub const MAX_REGISTERED_PORT: u16 = 49151;
+
+/// Classifies a port by the IANA port ranges.
+///
+/// ### Arguments
+///
+/// * `port` - The transport port.
+///
+/// ### Returns
+///
+/// One of `well-known` (below 1024), `registered` (up to 49151) and `ephemeral`.
+pub fn port_category(port: u16) -> &'static str {
+    if port <= MAX_WELL_KNOWN_PORT {
+        "well-known"
+    } else if port <= MAX_REGISTERED_PORT {
+        "registered"
+    } else {
+        "ephemeral"
+    }
+}
+
+/// Calculates the Shannon entropy of bytes, normalized to [0, 1].
+///
+/// The entropy in bits per byte is divided by the most a sample of this size can have,
+/// `log2` of its length up to 256, so uniformly distributed bytes are close to 1 for short
+/// samples too. Encrypted and compressed data are there, text and protocol headers lower.
+///
+/// ### Arguments
+///
+/// * `bytes` - The sample, e.g. the first bytes of a payload.
+///
+/// ### Returns
+///
+/// The normalized entropy, 0 for fewer than two bytes.
+pub fn normalized_entropy(bytes: &[u8]) -> f64 {
+    if bytes.len() < 2 {
+        return 0.0;
+    }
+    let mut counts = [0u32; 256];
+    for &byte in bytes {
+        counts[usize::from(byte)] += 1;
+    }
+    let length = bytes.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let probability = f64::from(count) / length;
+            // Without a negation, which would dump a single repeated byte as -0
+            probability * (1.0 / probability).log2()
+        })
+        .sum();
+    entropy / length.min(256.0).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{
+        combine_variances, endpoint_locality, is_private_ip, normalized_entropy, port_category,
+        same_subnet, sanitize_string_feature,
+    };
+
+    #[test]
+    fn test_endpoint_locality() {
+        let ip = |address: &str| address.parse::<IpAddr>().unwrap();
+
+        for private in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "fd00::1", "::1", "::ffff:10.0.0.1"] {
+            assert!(is_private_ip(ip(private)), "{} is private", private);
+        }
+        for public in ["8.8.8.8", "172.32.0.1", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(!is_private_ip(ip(public)), "{} is public", public);
+        }
+
+        assert_eq!(endpoint_locality(ip("10.0.0.1"), ip("192.168.0.1")), "private-to-private");
+        assert_eq!(endpoint_locality(ip("10.0.0.1"), ip("8.8.8.8")), "private-to-public");
+        assert_eq!(endpoint_locality(ip("2001:db8::1"), ip("fd00::1")), "public-to-private");
+        assert_eq!(endpoint_locality(ip("8.8.8.8"), ip("1.1.1.1")), "public-to-public");
+    }
+
+    #[test]
+    fn test_same_subnet() {
+        let ip = |address: &str| address.parse::<IpAddr>().unwrap();
+        let subnets = ["10.1.0.0/16".parse().unwrap(), "fd00:1::/64".parse().unwrap()];
+
+        assert!(same_subnet(&subnets, ip("10.1.2.3"), ip("10.1.200.1")));
+        assert!(same_subnet(&subnets, ip("fd00:1::1"), ip("fd00:1::2")));
+        // Both local, but in different subnets
+        assert!(!same_subnet(&subnets, ip("10.1.2.3"), ip("fd00:1::2")));
+        assert!(!same_subnet(&subnets, ip("10.1.2.3"), ip("8.8.8.8")));
+        assert!(!same_subnet(&[], ip("10.1.2.3"), ip("10.1.2.4")));
+    }
+
+    #[test]
+    fn test_port_category() {
+        assert_eq!(port_category(443), "well-known");
+        assert_eq!(port_category(1023), "well-known");
+        assert_eq!(port_category(1024), "registered");
+        assert_eq!(port_category(49151), "registered");
+        assert_eq!(port_category(49152), "ephemeral");
+    }
+
+    #[test]
+    fn test_normalized_entropy() {
+        assert_eq!(normalized_entropy(&[]), 0.0);
+        assert_eq!(normalized_entropy(&[0x41; 64]).to_string(), "0");
+        // 64 distinct bytes have the most entropy 64 bytes can have
+        let distinct: Vec<u8> = (0..64).collect();
+        assert!((normalized_entropy(&distinct) - 1.0).abs() < 1e-9);
+        // Two values in equal parts are 1 bit of the 6 bits of 64 bytes
+        let halves: Vec<u8> = (0..64).map(|i| (i % 2) as u8).collect();
+        assert!((normalized_entropy(&halves) - 1.0 / 6.0).abs() < 1e-9);
+        // Text repeats its letters
+        assert!(normalized_entropy(b"GET / HTTP/1.1\r\nHost: example.com\r\n") < 0.9);
+    }
+
+    #[test]
+    fn test_combine_variances() {
+        // [10, 20, 30, 40, 50] and [15, 25, 35]
+        let variance = combine_variances(5, 30.0, 200.0, 3, 25.0, 200.0 / 3.0);
+        assert!((variance - 155.859375).abs() < 1e-9);
+
+        assert_eq!(combine_variances(0, 0.0, 0.0, 0, 0.0, 0.0), 0.0);
+        assert_eq!(combine_variances(4, 5.0, 2.0, 0, 0.0, 0.0), 2.0);
+    }
+
+    #[test]
+    fn test_sanitize_string_feature() {
+        assert_eq!(sanitize_string_feature("example.com", 20), "example.com");
+        assert_eq!(
+            sanitize_string_feature("evil.com,1\n\"x\"", 20),
+            "evil.com_1__x_"
+        );
+        assert_eq!(
+            sanitize_string_feature("a-very-long-hostname.example.com", 10),
+            "a-very-..."
+        );
+        assert_eq!(sanitize_string_feature("abcdef", 2), "..");
+        assert_eq!(sanitize_string_feature("héllo", 5), "héllo");
+    }
+}
@@ -0,0 +1,378 @@
+//! Per-host rollups of the exported flows, a coarser view than the flow records, e.g. for
+//! capacity planning dashboards. Over tumbling windows, the flows, packets and bytes of each
+//! host (or subnet) are summed up and written as one record per host when the window ends.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    sync::Arc,
+    time::Instant,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use ipnetwork::IpNetwork;
+use log::info;
+
+use crate::{
+    args::{HostRollupConfig, RollupKey, TimestampFormat},
+    flows::{basic_flow::BasicFlow, settings::FlowSettings},
+};
+
+/// The columns of the rollup records.
+const ROLLUP_HEADER: &str = "WINDOW_START,WINDOW,HOST,FLOWS,PACKETS,BYTES";
+
+/// The traffic of a host within a window.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct HostCounters {
+    flows: u64,
+    packets: u64,
+    bytes: u64,
+}
+
+/// Sums up the exported flows per host and writes a record for every host of a window once a
+/// flow of a later window arrives, or `tick` finds the capture past the window.
+///
+/// A flow counts into the window its last packet falls into, so pcap files are rolled up like
+/// a live capture. A flow exported after a later window started counts into that window, e.g.
+/// an idle flow whose last packet came less than `idle_timeout` before the end of its window.
+/// The window is validated to be longer than `idle_timeout`, so the others make it. Only the
+/// final records count, the interim ones of early exports and export windows would count the
+/// traffic of a flow again.
+pub struct HostRollup {
+    /// The settings of the run, the hosts are exported like the flows export them.
+    settings: Arc<FlowSettings>,
+    key: RollupKey,
+    window: TimeDelta,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    replace_flows: bool,
+    /// The start of the current window and the hosts seen in it.
+    window_start: Option<DateTime<Utc>>,
+    hosts: HashMap<IpNetwork, HostCounters>,
+    /// The end of the latest flow and when it was added, `tick` estimates the time of the
+    /// capture from them.
+    latest_flow: Option<(DateTime<Utc>, Instant)>,
+    /// The rollups file, the records are logged if there is none.
+    writer: Option<BufWriter<File>>,
+    records: u64,
+}
+
+impl HostRollup {
+    /// Creates the rollup and its file, if the configuration names one.
+    pub fn new(settings: Arc<FlowSettings>, config: HostRollupConfig) -> io::Result<Self> {
+        let writer = match &config.path {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("Failed to create file {}: {}", path, e))
+                })?;
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "{}", ROLLUP_HEADER)?;
+                info!("Writing the host rollups to {}", path);
+                Some(writer)
+            }
+            None => None,
+        };
+
+        Ok(HostRollup {
+            settings,
+            key: config.key,
+            window: TimeDelta::seconds(config.window as i64),
+            ipv4_prefix: config.ipv4_prefix,
+            ipv6_prefix: config.ipv6_prefix,
+            replace_flows: config.replace_flows,
+            window_start: None,
+            hosts: HashMap::new(),
+            latest_flow: None,
+            writer,
+            records: 0,
+        })
+    }
+
+    /// Whether only the rollups are written, not the flows.
+    pub fn replaces_flows(&self) -> bool {
+        self.replace_flows
+    }
+
+    /// Counts an exported flow for its host, writing out the previous window if it ended.
+    ///
+    /// Interim records are skipped, their flow is counted with its final record.
+    pub fn add_flow(&mut self, flow: &BasicFlow) -> io::Result<()> {
+        if flow.snapshot_time.is_some() {
+            return Ok(());
+        }
+        let end = flow.last_timestamp;
+        if self.latest_flow.is_none_or(|(latest, _)| end > latest) {
+            self.latest_flow = Some((end, Instant::now()));
+        }
+        self.move_to_window(end)?;
+
+        let counters = self.hosts.entry(self.host(flow)).or_default();
+        counters.flows += 1;
+        counters.packets += flow.get_packet_count();
+        counters.bytes += flow.fwd_total_bytes + flow.bwd_total_bytes;
+        Ok(())
+    }
+
+    /// Writes out the current window if the capture has passed its end, e.g. on a quiet link
+    /// where no later flow arrives to do so. Called periodically by the output.
+    ///
+    /// The time of the capture is the end of the latest flow plus the time since it was added,
+    /// so the windows of a capture file read faster than the packets are timed stay intact.
+    ///
+    /// ### Arguments
+    ///
+    /// * `now` - The current time.
+    pub fn tick(&mut self, now: Instant) -> io::Result<()> {
+        let Some((latest, added)) = self.latest_flow else {
+            return Ok(());
+        };
+        let capture_time = TimeDelta::from_std(now.saturating_duration_since(added))
+            .ok()
+            .and_then(|elapsed| latest.checked_add_signed(elapsed));
+        match capture_time {
+            Some(capture_time) => self.move_to_window(capture_time),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes the window of the given time the current one, writing out the previous one if it
+    /// ended. An earlier time stays in the current window.
+    fn move_to_window(&mut self, time: DateTime<Utc>) -> io::Result<()> {
+        // The windows are aligned to multiples of their length since the Unix epoch
+        let window = self.window.num_microseconds().unwrap_or(i64::MAX);
+        let start = time - TimeDelta::microseconds(time.timestamp_micros().rem_euclid(window));
+        match self.window_start {
+            Some(current) if start > current => {
+                self.write_window()?;
+                self.window_start = Some(start);
+            }
+            Some(_) => {}
+            None => self.window_start = Some(start),
+        }
+        Ok(())
+    }
+
+    /// Returns the host a flow is rolled up under, the address or the subnet of the key.
+    fn host(&self, flow: &BasicFlow) -> IpNetwork {
+        let (ip, subnet) = match self.key {
+            RollupKey::SourceIp => (flow.ip_source, false),
+            RollupKey::DestinationIp => (flow.ip_destination, false),
+            RollupKey::SourceSubnet => (flow.ip_source, true),
+            RollupKey::DestinationSubnet => (flow.ip_destination, true),
+        };
+        let prefix = match (ip, subnet) {
+            (IpAddr::V4(_), true) => self.ipv4_prefix,
+            (IpAddr::V6(_), true) => self.ipv6_prefix,
+            (IpAddr::V4(_), false) => 32,
+            (IpAddr::V6(_), false) => 128,
+        };
+        // The prefixes are validated with the configuration
+        let network = IpNetwork::new(ip, prefix).expect("Invalid rollup prefix");
+        IpNetwork::new(network.network(), prefix).expect("Invalid rollup prefix")
+    }
+
+    /// Writes a record for every host of the current window, ordered by the host.
+    fn write_window(&mut self) -> io::Result<()> {
+        let Some(start) = self.window_start else {
+            return Ok(());
+        };
+        let mut hosts: Vec<(IpNetwork, HostCounters)> = self.hosts.drain().collect();
+        hosts.sort_by_key(|(host, _)| *host);
+        let subnets = matches!(self.key, RollupKey::SourceSubnet | RollupKey::DestinationSubnet);
+        for (host, counters) in hosts {
+            // The host as it is exported, pseudonymized if anonymization is enabled
+            let ip = self.settings.ip_feature(host.network());
+            let host = if subnets {
+                format!("{}/{}", ip, host.prefix())
+            } else {
+                ip.to_string()
+            };
+            let record = format!(
+                "{},{},{},{},{},{}",
+                self.settings.timestamp_feature(start, TimestampFormat::Text),
+                self.window.num_seconds(),
+                host,
+                counters.flows,
+                counters.packets,
+                counters.bytes
+            );
+            self.records += 1;
+            match self.writer.as_mut() {
+                Some(writer) => writeln!(writer, "{}", record)?,
+                None => info!("Host rollup: {}", record),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the buffered records, e.g. at the periodic flush of the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes out the last window at the end of the run.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.write_window()?;
+        info!("Wrote {} host rollup records", self.records);
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use chrono::{DateTime, TimeDelta};
+    use ipnetwork::IpNetwork;
+
+    use super::{HostCounters, HostRollup};
+    use crate::{
+        args::{HostRollupConfig, RollupKey},
+        flow_table::interim_record,
+        flows::{basic_flow::BasicFlow, flow::Flow},
+    };
+
+    fn flow(source: u8, seconds: i64, packets: u64, bytes: u64) -> BasicFlow {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut flow = BasicFlow::new(
+            Arc::default(),
+            String::new(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, source)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1)),
+            443,
+            6,
+            start,
+        );
+        flow.last_timestamp = start + TimeDelta::seconds(seconds);
+        flow.fwd_packet_count = packets;
+        flow.fwd_total_bytes = bytes;
+        flow
+    }
+
+    #[test]
+    fn test_host_rollup() {
+        let mut rollup = HostRollup::new(Arc::default(), HostRollupConfig {
+            window: 60,
+            ..HostRollupConfig::default()
+        })
+        .unwrap();
+
+        rollup.add_flow(&flow(1, 1, 3, 300)).unwrap();
+        rollup.add_flow(&flow(1, 10, 2, 200)).unwrap();
+        rollup.add_flow(&flow(2, 20, 1, 40)).unwrap();
+        let host = |source: u8| IpNetwork::from(IpAddr::V4(Ipv4Addr::new(10, 0, 0, source)));
+        let expected = HostCounters {
+            flows: 2,
+            packets: 5,
+            bytes: 500,
+        };
+        assert_eq!(rollup.hosts[&host(1)], expected);
+        assert_eq!(rollup.hosts.len(), 2);
+
+        // A flow ending in the next window writes out the first one
+        rollup.add_flow(&flow(1, 70, 1, 60)).unwrap();
+        assert_eq!(rollup.records, 2);
+        assert_eq!(rollup.hosts[&host(1)].bytes, 60);
+        // A late flow counts into the current window
+        rollup.add_flow(&flow(2, 30, 1, 40)).unwrap();
+        assert_eq!(rollup.hosts.len(), 2);
+        rollup.close().unwrap();
+        assert_eq!(rollup.records, 4);
+
+        // The subnets of the key with the prefix of their IP version
+        let mut rollup = HostRollup::new(Arc::default(), HostRollupConfig {
+            key: RollupKey::SourceSubnet,
+            ipv4_prefix: 16,
+            ..HostRollupConfig::default()
+        })
+        .unwrap();
+        rollup.add_flow(&flow(1, 0, 1, 40)).unwrap();
+        rollup.add_flow(&flow(2, 0, 1, 40)).unwrap();
+        assert_eq!(rollup.hosts.len(), 1);
+        assert_eq!(rollup.hosts.keys().next().unwrap().to_string(), "10.0.0.0/16");
+    }
+
+    #[test]
+    fn test_host_rollup_idle_flow() {
+        let mut rollup = HostRollup::new(Arc::default(), HostRollupConfig {
+            window: 60,
+            ..HostRollupConfig::default()
+        })
+        .unwrap();
+
+        // The window ends 40 seconds after the start, the capture is 34 seconds in when the
+        // idle timeout of 30 seconds exports a flow that ended at 4 seconds
+        rollup.add_flow(&flow(1, 1, 1, 40)).unwrap();
+        let (_, added) = rollup.latest_flow.unwrap();
+        rollup.tick(added + Duration::from_secs(33)).unwrap();
+        rollup.add_flow(&flow(2, 4, 1, 40)).unwrap();
+        assert_eq!(rollup.records, 0);
+        assert_eq!(rollup.hosts.len(), 2);
+
+        // Another one, exported once the capture passed the window, counts into the next one
+        let (_, added) = rollup.latest_flow.unwrap();
+        rollup.tick(added + Duration::from_secs(40)).unwrap();
+        assert_eq!(rollup.records, 2);
+        rollup.add_flow(&flow(3, 20, 1, 40)).unwrap();
+        rollup.close().unwrap();
+        assert_eq!(rollup.records, 3);
+    }
+
+    #[test]
+    fn test_host_rollup_interim_records() {
+        let mut rollup = HostRollup::new(Arc::default(), HostRollupConfig {
+            window: 60,
+            ..HostRollupConfig::default()
+        })
+        .unwrap();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        // An export window of 10 seconds takes two interim records before the final one
+        let mut long_flow = flow(1, 0, 0, 0);
+        for (seconds, packets, bytes) in [(10, 2, 200), (20, 5, 500)] {
+            long_flow.last_timestamp = start + TimeDelta::seconds(seconds);
+            long_flow.fwd_packet_count = packets;
+            long_flow.fwd_total_bytes = bytes;
+            let snapshot_time = start + TimeDelta::seconds(seconds);
+            rollup
+                .add_flow(&interim_record(&long_flow, snapshot_time))
+                .unwrap();
+            long_flow.start_window(snapshot_time);
+        }
+        long_flow.last_timestamp = start + TimeDelta::seconds(25);
+        long_flow.fwd_packet_count = 6;
+        long_flow.fwd_total_bytes = 600;
+        rollup.add_flow(&long_flow).unwrap();
+        // Only the final record counts, with all the traffic of the flow
+        let host = IpNetwork::from(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let expected = HostCounters {
+            flows: 1,
+            packets: 6,
+            bytes: 600,
+        };
+        assert_eq!(rollup.hosts[&host], expected);
+
+        // The window ends 40 seconds after the start, a tick writes it out once the capture
+        // passed it without a later flow
+        let (_, added) = rollup.latest_flow.unwrap();
+        rollup.tick(added + Duration::from_secs(10)).unwrap();
+        assert_eq!(rollup.records, 0);
+        rollup.tick(added + Duration::from_secs(20)).unwrap();
+        assert_eq!(rollup.records, 1);
+        assert!(rollup.hosts.is_empty());
+        // A late flow of the written window counts into the next one
+        rollup.add_flow(&flow(2, 30, 1, 40)).unwrap();
+        rollup.close().unwrap();
+        assert_eq!(rollup.records, 2);
+    }
+}
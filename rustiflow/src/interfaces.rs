@@ -0,0 +1,298 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::anyhow;
+use log::{info, warn};
+
+use crate::metrics;
+use crate::realtime::{
+    get_missing_capabilities, is_ip_link_type, privileges_hint, ARPHRD_IPGRE, ARPHRD_NONE,
+    ARPHRD_PPP, ARPHRD_RAWIP, ARPHRD_SIT, ARPHRD_TUNNEL, ARPHRD_TUNNEL6,
+};
+
+/// Where Linux lists the network interfaces, one directory per interface.
+const SYSFS_NET: &str = "/sys/class/net";
+
+// Link layer types with an Ethernet header, see if_arp.h
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+
+/// A network interface and what RustiFlow knows about capturing from it.
+#[derive(Debug, PartialEq)]
+pub(crate) struct InterfaceInfo {
+    pub name: String,
+    pub ifindex: Option<u32>,
+    /// The ARPHRD link layer type of the interface.
+    pub link_type: Option<u16>,
+    /// The operational state, e.g. `up`, `down` or `unknown` for tunnels.
+    pub state: String,
+    pub mtu: Option<u32>,
+}
+
+impl InterfaceInfo {
+    /// Checks whether the eBPF programs can parse the packets of this interface.
+    ///
+    /// They expect an Ethernet header or none at all, other link layers (e.g. Wi-Fi in
+    /// monitor mode or InfiniBand) are not supported.
+    pub fn supports_ebpf(&self) -> bool {
+        self.link_type.is_some_and(|link_type| {
+            link_type == ARPHRD_ETHER || link_type == ARPHRD_LOOPBACK || is_ip_link_type(link_type)
+        })
+    }
+}
+
+/// Returns a readable name of an ARPHRD link layer type.
+fn link_type_name(link_type: Option<u16>) -> String {
+    match link_type {
+        Some(ARPHRD_ETHER) => "ethernet".to_string(),
+        Some(ARPHRD_LOOPBACK) => "loopback".to_string(),
+        Some(ARPHRD_PPP) => "ppp".to_string(),
+        Some(ARPHRD_RAWIP) => "raw ip".to_string(),
+        Some(ARPHRD_TUNNEL) => "ipip tunnel".to_string(),
+        Some(ARPHRD_TUNNEL6) => "ip6 tunnel".to_string(),
+        Some(ARPHRD_SIT) => "sit tunnel".to_string(),
+        Some(ARPHRD_IPGRE) => "gre tunnel".to_string(),
+        Some(ARPHRD_NONE) => "none (tun)".to_string(),
+        Some(link_type) => format!("other ({})", link_type),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Reads the network interfaces from a sysfs directory like `/sys/class/net`.
+///
+/// ### Returns
+///
+/// The interfaces ordered by their ifindex, empty if the directory can't be read.
+pub(crate) fn read_interfaces(sysfs_net: &Path) -> Vec<InterfaceInfo> {
+    let Ok(entries) = std::fs::read_dir(sysfs_net) else {
+        return Vec::new();
+    };
+    let read = |name: &str, attribute: &str| {
+        std::fs::read_to_string(sysfs_net.join(name).join(attribute))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+
+    let mut interfaces: Vec<InterfaceInfo> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .map(|name| InterfaceInfo {
+            ifindex: read(&name, "ifindex").and_then(|value| value.parse().ok()),
+            link_type: read(&name, "type").and_then(|value| value.parse().ok()),
+            state: read(&name, "operstate").unwrap_or_else(|| "unknown".to_string()),
+            mtu: read(&name, "mtu").and_then(|value| value.parse().ok()),
+            name,
+        })
+        .collect();
+    interfaces.sort_by_key(|interface| (interface.ifindex, interface.name.clone()));
+    interfaces
+}
+
+/// Prints the network interfaces and whether realtime capture is likely to work on them.
+pub fn list_interfaces() {
+    let interfaces = read_interfaces(Path::new(SYSFS_NET));
+    if interfaces.is_empty() {
+        println!("No network interfaces found in {}", SYSFS_NET);
+        return;
+    }
+
+    println!(
+        "{:<16} {:>7}  {:<14} {:<8} {:>6}  EBPF",
+        "NAME", "IFINDEX", "LINK TYPE", "STATE", "MTU"
+    );
+    for interface in &interfaces {
+        println!(
+            "{:<16} {:>7}  {:<14} {:<8} {:>6}  {}",
+            interface.name,
+            interface
+                .ifindex
+                .map_or_else(|| "-".to_string(), |ifindex| ifindex.to_string()),
+            link_type_name(interface.link_type),
+            interface.state,
+            interface.mtu.map_or_else(|| "-".to_string(), |mtu| mtu.to_string()),
+            if interface.supports_ebpf() {
+                "yes"
+            } else {
+                "no, unsupported link type (use --backend afpacket)"
+            }
+        );
+    }
+
+    let missing_capabilities = get_missing_capabilities();
+    if !missing_capabilities.is_empty() {
+        println!("\n{}", privileges_hint(&missing_capabilities));
+    }
+}
+
+/// Checks that the interface exists before anything is loaded or attached to it.
+///
+/// Skipped if the interfaces can't be listed, the capture then reports its own error.
+pub(crate) fn check_interface(interface: &str) -> Result<(), anyhow::Error> {
+    let interfaces = read_interfaces(Path::new(SYSFS_NET));
+    if interfaces.is_empty() || interfaces.iter().any(|info| info.name == interface) {
+        return Ok(());
+    }
+
+    let names: Vec<&str> = interfaces.iter().map(|info| info.name.as_str()).collect();
+    Err(anyhow!(
+        "Interface {} doesn't exist, available interfaces: {}. Run `rustiflow list-interfaces` \
+        for details.",
+        interface,
+        names.join(", ")
+    ))
+}
+
+/// The packet counters the kernel keeps for an interface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct InterfaceCounters {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+}
+
+/// Reads the packet counters of an interface from a sysfs directory like `/sys/class/net`.
+pub(crate) fn read_interface_counters(sysfs_net: &Path, interface: &str) -> Option<InterfaceCounters> {
+    let read = |counter: &str| {
+        std::fs::read_to_string(sysfs_net.join(interface).join("statistics").join(counter))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some(InterfaceCounters {
+        rx_packets: read("rx_packets")?,
+        tx_packets: read("tx_packets")?,
+    })
+}
+
+/// Returns the share of the interface packets that were captured between two checks.
+///
+/// ### Arguments
+///
+/// * `captured` - The packets captured since the previous check.
+/// * `previous` - The interface counters at the previous check.
+/// * `current` - The interface counters now.
+/// * `ingress_only` - Whether only received packets are captured.
+///
+/// ### Returns
+///
+/// The ratio, `None` if the interface had no packets or its counters were reset.
+pub(crate) fn capture_ratio(
+    captured: u64,
+    previous: InterfaceCounters,
+    current: InterfaceCounters,
+    ingress_only: bool,
+) -> Option<f64> {
+    let received = current.rx_packets.checked_sub(previous.rx_packets)?;
+    let sent = current.tx_packets.checked_sub(previous.tx_packets)?;
+    let packets = if ingress_only { received } else { received + sent };
+    (packets > 0).then(|| captured as f64 / packets as f64)
+}
+
+/// Compares the captured packets with the interface counters every `interval` seconds.
+///
+/// Logs the share of the interface packets that reached RustiFlow and publishes it to the
+/// metrics, runs until the task is aborted. Packets the eBPF programs don't pass on (e.g. ARP
+/// or other non-IP traffic) count as missing, so judge the ratio against the traffic mix.
+pub async fn check_capture_ratio(interface: String, interval: u64, ingress_only: bool) {
+    let sysfs_net = Path::new(SYSFS_NET);
+    let mut ticks = tokio::time::interval(Duration::from_secs(interval));
+    ticks.tick().await;
+    let Some(mut previous) = read_interface_counters(sysfs_net, &interface) else {
+        warn!("Can't read the packet counters of {}, the capture isn't checked", interface);
+        return;
+    };
+    let mut previous_captured = metrics::captured_packets();
+
+    loop {
+        ticks.tick().await;
+        let Some(current) = read_interface_counters(sysfs_net, &interface) else {
+            continue;
+        };
+        let captured = metrics::captured_packets();
+        let captured_delta = captured.saturating_sub(previous_captured);
+        if let Some(ratio) = capture_ratio(captured_delta, previous, current, ingress_only) {
+            metrics::set_capture_ratio(ratio);
+            info!(
+                "Captured {:.1}% of the packets on {} in the last {} seconds ({} packets)",
+                ratio * 100.0,
+                interface,
+                interval,
+                captured_delta
+            );
+        }
+        previous = current;
+        previous_captured = captured;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        capture_ratio, read_interface_counters, read_interfaces, InterfaceCounters, InterfaceInfo,
+    };
+
+    #[test]
+    fn test_read_interfaces() {
+        let sysfs_net = std::env::temp_dir().join("rustiflow_test_sysfs_net");
+        let _ = std::fs::remove_dir_all(&sysfs_net);
+        for (name, ifindex, link_type, state, mtu) in [
+            ("wg0", "3", "65534", "unknown", "1420"),
+            ("eth0", "2", "1", "up", "9000"),
+            ("ib0", "4", "32", "down", "2044"),
+        ] {
+            let interface = sysfs_net.join(name);
+            std::fs::create_dir_all(&interface).unwrap();
+            std::fs::write(interface.join("ifindex"), format!("{}\n", ifindex)).unwrap();
+            std::fs::write(interface.join("type"), format!("{}\n", link_type)).unwrap();
+            std::fs::write(interface.join("operstate"), format!("{}\n", state)).unwrap();
+            std::fs::write(interface.join("mtu"), format!("{}\n", mtu)).unwrap();
+        }
+
+        let interfaces = read_interfaces(&sysfs_net);
+        std::fs::remove_dir_all(&sysfs_net).unwrap();
+
+        assert_eq!(
+            interfaces[0],
+            InterfaceInfo {
+                name: "eth0".to_string(),
+                ifindex: Some(2),
+                link_type: Some(1),
+                state: "up".to_string(),
+                mtu: Some(9000),
+            }
+        );
+        let names: Vec<&str> = interfaces.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, ["eth0", "wg0", "ib0"]);
+        // Tunnels without a link layer header are supported, InfiniBand isn't
+        let supported: Vec<bool> = interfaces.iter().map(InterfaceInfo::supports_ebpf).collect();
+        assert_eq!(supported, [true, true, false]);
+    }
+
+    #[test]
+    fn test_capture_ratio() {
+        let sysfs_net = std::env::temp_dir().join("rustiflow_test_sysfs_counters");
+        let statistics = sysfs_net.join("eth0").join("statistics");
+        std::fs::create_dir_all(&statistics).unwrap();
+        std::fs::write(statistics.join("rx_packets"), "1500\n").unwrap();
+        std::fs::write(statistics.join("tx_packets"), "500\n").unwrap();
+
+        let previous = read_interface_counters(&sysfs_net, "eth0").unwrap();
+        assert!(read_interface_counters(&sysfs_net, "eth1").is_none());
+        std::fs::remove_dir_all(&sysfs_net).unwrap();
+        assert_eq!(
+            previous,
+            InterfaceCounters {
+                rx_packets: 1500,
+                tx_packets: 500,
+            }
+        );
+
+        let current = InterfaceCounters {
+            rx_packets: 2500,
+            tx_packets: 1500,
+        };
+        assert_eq!(capture_ratio(1800, previous, current, false), Some(0.9));
+        assert_eq!(capture_ratio(500, previous, current, true), Some(0.5));
+        // Nothing happened on the interface, or its counters were reset
+        assert_eq!(capture_ratio(0, previous, previous, false), None);
+        assert_eq!(capture_ratio(10, current, previous, false), None);
+    }
+}
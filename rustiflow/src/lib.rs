@@ -1,8 +1,30 @@
+#[cfg(target_os = "linux")]
+pub mod afpacket;
 pub mod args;
-pub mod flow_table;
+mod clickhouse;
+mod dead_letter;
+mod expectations;
+pub mod export_error;
+mod flow_table;
 pub mod flows;
+mod host_rollup;
+#[cfg(target_os = "linux")]
+pub mod interfaces;
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod output;
 pub mod packet_features;
+mod parquet_file;
+mod partition;
 pub mod pcap;
+pub mod pipeline;
+mod protobuf;
+#[cfg(target_os = "linux")]
 pub mod realtime;
-pub mod tui;
\ No newline at end of file
+pub mod reload;
+pub mod replay;
+mod scan_detector;
+pub mod schema;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
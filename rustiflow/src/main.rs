@@ -1,142 +1,288 @@
-mod args;
-mod flow_table;
-mod flows;
-mod output;
-mod packet_features;
-mod pcap;
-mod realtime;
 mod tui;
 
-use crate::flows::{cic_flow::CicFlow, ntl_flow::NTLFlow};
-use crate::pcap::read_pcap_file;
-use crate::realtime::handle_realtime;
-use args::{Cli, Commands, ConfigFile, ExportConfig, FlowType, OutputConfig};
-use clap::Parser;
+#[cfg(target_os = "linux")]
+use rustiflow::interfaces;
+#[cfg(feature = "otel")]
+use rustiflow::otel;
+use rustiflow::{args, export_error, flows, metrics, output, reload, replay, schema};
+#[cfg(target_os = "linux")]
+use rustiflow::afpacket::handle_afpacket;
+use rustiflow::flows::{cic_flow::CicFlow, ntl_flow::NTLFlow, rusti_flow::RustiFlow};
+use rustiflow::pcap::read_pcap_file;
+use rustiflow::pipeline::Pipeline;
+#[cfg(target_os = "linux")]
+use rustiflow::realtime::handle_realtime;
+#[cfg(target_os = "linux")]
+use args::{CaptureBackend, ConfigFile, FeatureGroup};
+use args::{Cli, Commands, FlowType, SchemaFormat};
+#[cfg(target_os = "linux")]
+use reload::reload_on_sighup;
+use reload::ReloadableConfig;
+use clap::{CommandFactory, FromArgMatches};
 use flows::{
-    basic_flow::BasicFlow, cidds_flow::CiddsFlow, custom_flow::CustomFlow, flow::Flow,
-    nf_flow::NfFlow,
+    anonymize::IpAnonymizer, basic_flow::BasicFlow, cidds_flow::CiddsFlow,
+    custom_flow::CustomFlow, nf_flow::NfFlow, settings::FlowSettings, signatures::SignatureSet,
 };
 use log::{debug, error, info};
 use output::OutputWriter;
-use std::time::Instant;
-use tokio::sync::mpsc;
+use std::{sync::Arc, time::Instant};
+#[cfg(target_os = "linux")]
+use tokio::sync::watch;
 use tui::{launch_tui, Config};
 
-#[tokio::main]
-async fn main() {
+fn main() {
     env_logger::init();
 
-    if std::env::args().len() == 1 {
+    let (config, config_path) = if std::env::args().len() == 1 {
         // No arguments provided, launch TUI
-        let config = launch_tui().await.unwrap_or_else(|e| {
-            error!("Error: {:?}", e);
-            std::process::exit(1);
-        });
+        let config = build_runtime(false)
+            .block_on(launch_tui())
+            .unwrap_or_else(|e| {
+                error!("Error: {:?}", e);
+                std::process::exit(1);
+            });
 
         if let Some(config) = config {
-            run_with_config(config).await;
+            (config, None)
         } else {
             error!("No configuration provided.");
             std::process::exit(1);
         }
     } else {
-        let cli = Cli::parse();
-
-        // If a config file is provided, load it
-        let config: Config = if let Some(config_path) = cli.config_file {
-            // Try to load config from file
-            match confy::load_path::<ConfigFile>(config_path) {
-                Ok(cfg_file) => Config {
-                    config: cfg_file.config,
-                    output: cfg_file.output,
-                    command: cli.command,
-                },
-                Err(e) => {
-                    error!("Error loading configuration file: {:?}", e);
-                    return;
-                }
-            }
-        } else {
-            // No config file: build config from CLI arguments
-            Config {
-                config: ExportConfig {
-                    features: cli.features.unwrap(),
-                    active_timeout: cli.active_timeout,
-                    idle_timeout: cli.idle_timeout,
-                    early_export: cli.early_export,
-                    threads: cli.threads,
-                    expiration_check_interval: cli.expiration_check_interval,
-                },
-                output: OutputConfig {
-                    output: cli.output.unwrap(),
-                    export_path: cli.export_path,
-                    header: cli.header,
-                    drop_contaminant_features: cli.drop_contaminant_features,
-                },
+        let matches = Cli::command().get_matches();
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        // Listing the interfaces needs no feature set or output
+        #[cfg(target_os = "linux")]
+        if let Commands::ListInterfaces = cli.command {
+            interfaces::list_interfaces();
+            return;
+        }
+
+        // Load the config file if one is provided, flags given on the command line override it
+        let config = match cli.to_config_file(&matches) {
+            Ok(config_file) => Config {
+                config: config_file.config,
+                output: config_file.output,
                 command: cli.command,
+            },
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
             }
         };
 
-        run_with_config(config).await;
+        (config, cli.config_file)
+    };
+
+    // The runtime is picked by the configuration, so it's built once that is known
+    build_runtime(config.config.single_threaded).block_on(run_with_config(config, config_path));
+}
+
+/// Builds the tokio runtime, on the current thread only if `single_threaded`.
+fn build_runtime(single_threaded: bool) -> tokio::runtime::Runtime {
+    let mut builder = if single_threaded {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    builder.enable_all().build().unwrap_or_else(|e| {
+        error!("Error starting the runtime: {:?}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Returns the name of the interface or the pcap file (without its extension) of a capture.
+fn capture_source(command: &Commands) -> String {
+    match command {
+        Commands::Realtime { interface, .. } => interface.clone(),
+        Commands::Pcap { path } if path == "-" => "stdin".to_string(),
+        Commands::Pcap { path } => std::path::Path::new(path)
+            .file_stem()
+            .map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned()),
+        Commands::ListInterfaces | Commands::Schema { .. } | Commands::Replay { .. } => {
+            String::new()
+        }
     }
 }
 
-async fn run_with_config(config: Config) {
+/// Runs the selected command, `config_path` is the file to reload from on SIGHUP (realtime only).
+async fn run_with_config(
+    config: Config,
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] config_path: Option<String>,
+) {
+    let capture_source = capture_source(&config.command);
+    let settings = Arc::new(FlowSettings {
+        byte_count: config.config.byte_count,
+        ip_version: config.config.ip_version,
+        flow_key: config.config.flow_key,
+        max_string_length: config.output.max_string_length,
+        null_value: config.output.null_value,
+        timestamp_format: config.output.timestamp_format,
+        timezone: config.output.timezone,
+        track_expectations: config.config.track_expectations,
+        flow_sampling: config.config.flow_sampling,
+        export_window: config.config.export_window,
+        packet_sequence_length: config.config.packet_sequence_length,
+        feature_memory_limit: config.config.feature_memory_limit,
+        flow_table_capacity: config.config.flow_table_capacity,
+        raw_flags: config.config.raw_flags,
+        periodicity_threshold: config.config.periodicity_threshold,
+        periodicity_min_packets: config.config.periodicity_min_packets,
+        first_window: config.config.first_window,
+        exclude_keepalives: config.config.exclude_keepalives,
+        disabled_features: config.config.disabled_features.iter().copied().collect(),
+        local_subnets: config.config.local_subnets.clone(),
+        persistent_flows: config.config.persistent_flows.clone(),
+        packet_filters: config.config.packet_filters.clone(),
+        anonymizer: config.output.anonymize.map(|method| {
+            // The key is required by the validation of the configuration
+            IpAnonymizer::new(method, config.output.anonymization_key.as_deref().unwrap_or_default())
+        }),
+        canonical_endpoints: config.output.canonical_endpoints,
+        export_time: config.output.export_time,
+        end_reason: config.output.end_reason,
+        payload_signatures: config.output.payload_signatures.as_deref().map(|path| {
+            SignatureSet::load(path).unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            })
+        }),
+        payload_snapshots: config.output.payload_signatures.is_some()
+            || (config.config.features == FlowType::Rustiflow
+                && !config.config.disabled_features.contains(&FeatureGroup::Payload)),
+        sensor_id: config.output.sensor_id.clone(),
+        keep_connection_attempts: config.output.keep_connection_attempts,
+        partition_by: config.output.partition_by,
+        capture_source: capture_source.clone(),
+    });
+    if let Some(rate) = config.config.flow_sampling {
+        info!("Sampling 1 in {} flows, scale the flow counts and totals by {}", rate, rate);
+    }
+
+    // Only a live capture is worth tracing, the metrics of a pcap file are exported too
+    #[cfg(feature = "otel")]
+    if let Commands::Realtime { .. } | Commands::Pcap { .. } = config.command {
+        let trace_flows = matches!(config.command, Commands::Realtime { .. });
+        otel::init_from_env(trace_flows, &capture_source);
+    }
+
     // Start the selected command
     match config.command {
+        #[cfg(target_os = "linux")]
         Commands::Realtime {
             interface,
             ingress_only,
+            backend,
         } => {
             macro_rules! execute_realtime {
                 ($flow_ty:ty) => {{
+                    // Publish reloaded settings to the flow tables and the output writer
+                    let (reload_sender, reload_receiver) =
+                        watch::channel(ReloadableConfig::new(&config.config, &config.output));
+                    let reload_task = config_path.map(|config_path| {
+                        tokio::spawn(reload_on_sighup(
+                            config_path,
+                            ConfigFile {
+                                config: config.config.clone(),
+                                output: config.output.clone(),
+                            },
+                            reload_sender,
+                        ))
+                    });
+
                     // Create output writer and initialize it
                     let mut output_writer = OutputWriter::<$flow_ty>::new(
+                        Arc::clone(&settings),
+                        config.config.features.clone(),
                         config.output.output,
                         config.output.header,
                         config.output.drop_contaminant_features,
+                        config.output.min_packets,
                         config.output.export_path,
+                        config.output.clickhouse,
+                        config.output.protobuf_address,
                     );
+                    output_writer.watch_config(reload_receiver.clone());
+                    if let Some(window) = config.output.reorder_window {
+                        output_writer.reorder_flows(window);
+                    }
+                    if let Some(scan_detection) = config.output.scan_detection.clone() {
+                        output_writer.detect_scans(scan_detection).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
+                    if let Some(host_rollups) = config.output.host_rollups.clone() {
+                        output_writer.roll_up_hosts(host_rollups).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
+                    output_writer.retry_writes(
+                        config.output.write_retries,
+                        config.output.write_retry_backoff,
+                    );
+                    if let Some(path) = config.output.dead_letter_path.clone() {
+                        output_writer.dead_letter(path).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
 
-                    // Synchronous initialization to ensure headers are written
-                    output_writer.init();
-
-                    // Create channel for exporting flows
-                    let (sender, mut receiver) = mpsc::channel::<$flow_ty>(1000);
-
-                    // Start the output writer in a separate task
-                    let output_task = tokio::spawn(async move {
-                        while let Some(flow) = receiver.recv().await {
-                            if let Err(e) = output_writer.write_flow(flow) {
-                                error!("Error writing flow: {:?}", e);
-                            }
-                        }
+                    // Initialization ahead of the flows to ensure headers are written
+                    output_writer.init().await;
 
-                        // Ensure that all remaining flows are flushed properly before ending
-                        output_writer.flush_and_close().unwrap_or_else(|e| {
-                            error!("Error flushing and closing the writer: {:?}", e);
-                        });
-                        debug!("OutputWriter task finished");
-                    });
+                    // The flow tables and the output writer, each in their own task
+                    let pipeline = Pipeline::with_config_updates(
+                        config.config.num_threads(),
+                        reload_receiver,
+                        output_writer,
+                        export_error::default_handler(),
+                    );
 
                     debug!("Starting realtime processing...");
                     let start = Instant::now();
-                    let result = handle_realtime::<$flow_ty>(
-                        &interface,
-                        sender,
-                        config.config.threads.unwrap_or(num_cpus::get() as u8),
-                        config.config.active_timeout,
-                        config.config.idle_timeout,
-                        config.config.early_export,
-                        config.config.expiration_check_interval,
-                        ingress_only,
-                    )
-                    .await;
+                    let check_task = config.config.interface_check_interval.map(|interval| {
+                        tokio::spawn(interfaces::check_capture_ratio(
+                            interface.clone(),
+                            interval,
+                            ingress_only,
+                        ))
+                    });
+                    let report_task = config
+                        .config
+                        .shard_report_interval
+                        .map(|interval| tokio::spawn(metrics::report_shard_throughput(interval)));
+                    let result = match backend {
+                        CaptureBackend::Ebpf => {
+                            handle_realtime(
+                                &pipeline,
+                                &interface,
+                                ingress_only,
+                                config.config.ring_buffer_size,
+                            )
+                            .await
+                        }
+                        CaptureBackend::Afpacket => {
+                            handle_afpacket(&pipeline, &interface, ingress_only).await
+                        }
+                    };
 
-                    // Wait for the output task to finish (flush and close the writer)
-                    if let Err(e) = output_task.await {
-                        error!("Error waiting for output task: {:?}", e);
+                    if let Some(reload_task) = reload_task {
+                        reload_task.abort();
+                    }
+                    if let Some(check_task) = check_task {
+                        check_task.abort();
                     }
+                    if let Some(report_task) = report_task {
+                        report_task.abort();
+                    }
+
+                    // Export the remaining flows, then flush and close the writer
+                    pipeline.shutdown_within(config.output.shutdown_timeout).await;
+                    #[cfg(feature = "otel")]
+                    otel::shutdown();
 
                     let end = Instant::now();
                     info!(
@@ -149,6 +295,20 @@ async fn run_with_config(config: Config) {
                         Ok(dropped_packets) => {
                             // If successful, log dropped packets count after writer is flushed
                             info!("Total dropped packets: {}", dropped_packets);
+                            info!("Total malformed packets: {}", metrics::malformed_packets());
+                            if metrics::filtered_packets() > 0 {
+                                info!("Filtered out {} packets", metrics::filtered_packets());
+                            }
+                            if metrics::truncated_feature_flows() > 0 {
+                                info!(
+                                    "{} flows went without a packet sequence or a payload \
+                                    snapshot at the feature memory limit",
+                                    metrics::truncated_feature_flows()
+                                );
+                            }
+                            if let Some(ratio) = metrics::capture_ratio() {
+                                info!("Last capture ratio: {:.1}%", ratio * 100.0);
+                            }
                         }
                         Err(err) => {
                             // Handle errors and log them
@@ -164,61 +324,91 @@ async fn run_with_config(config: Config) {
                 FlowType::CIDDS => execute_realtime!(CiddsFlow),
                 FlowType::Nfstream => execute_realtime!(NfFlow),
                 FlowType::NTL => execute_realtime!(NTLFlow),
+                FlowType::Rustiflow => execute_realtime!(RustiFlow),
                 FlowType::Custom => execute_realtime!(CustomFlow),
             }
         }
         Commands::Pcap { path } => {
             macro_rules! execute_offline {
                 ($flow_ty:ty) => {{
+                    let flow_table_config = ReloadableConfig::new(&config.config, &config.output);
+
                     // Create output writer and initialize it
                     let mut output_writer = OutputWriter::<$flow_ty>::new(
+                        Arc::clone(&settings),
+                        config.config.features.clone(),
                         config.output.output,
                         config.output.header,
                         config.output.drop_contaminant_features,
+                        config.output.min_packets,
                         config.output.export_path,
+                        config.output.clickhouse,
+                        config.output.protobuf_address,
                     );
+                    if let Some(window) = config.output.reorder_window {
+                        output_writer.reorder_flows(window);
+                    }
+                    if let Some(scan_detection) = config.output.scan_detection.clone() {
+                        output_writer.detect_scans(scan_detection).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
+                    if let Some(host_rollups) = config.output.host_rollups.clone() {
+                        output_writer.roll_up_hosts(host_rollups).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
+                    output_writer.retry_writes(
+                        config.output.write_retries,
+                        config.output.write_retry_backoff,
+                    );
+                    if let Some(path) = config.output.dead_letter_path.clone() {
+                        output_writer.dead_letter(path).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
 
-                    // Synchronous initialization to ensure headers are written
-                    output_writer.init();
-
-                    // Create channel for exporting flows
-                    let (sender, mut receiver) = mpsc::channel::<$flow_ty>(1000);
-
-                    // Start the output writer in a separate task
-                    let output_task = tokio::spawn(async move {
-                        while let Some(flow) = receiver.recv().await {
-                            if let Err(e) = output_writer.write_flow(flow) {
-                                error!("Error writing flow: {:?}", e);
-                            }
-                        }
+                    // Initialization ahead of the flows to ensure headers are written
+                    output_writer.init().await;
 
-                        // Ensure that all remaining flows are flushed properly before ending
-                        output_writer.flush_and_close().unwrap_or_else(|e| {
-                            error!("Error flushing and closing the writer: {:?}", e);
-                        });
-                        debug!("OutputWriter task finished");
-                    });
+                    // The flow tables and the output writer, each in their own task
+                    let pipeline = Pipeline::new(
+                        config.config.num_threads(),
+                        flow_table_config,
+                        output_writer,
+                    );
 
                     let start = Instant::now();
+                    let report_task = config
+                        .config
+                        .shard_report_interval
+                        .map(|interval| tokio::spawn(metrics::report_shard_throughput(interval)));
 
-                    if let Err(err) = read_pcap_file::<$flow_ty>(
-                        &path,
-                        sender,
-                        config.config.threads.unwrap_or(num_cpus::get() as u8),
-                        config.config.active_timeout,
-                        config.config.idle_timeout,
-                        config.config.early_export,
-                        config.config.expiration_check_interval,
-                    )
-                    .await
-                    {
+                    if let Err(err) = read_pcap_file(&pipeline, &path).await {
                         error!("Error: {:?}", err);
                     }
+                    if let Some(report_task) = report_task {
+                        report_task.abort();
+                    }
 
-                    // Wait for the output task to finish
-                    output_task.await.unwrap_or_else(|e| {
-                        error!("Error waiting for output task: {:?}", e);
-                    });
+                    // Export the remaining flows, then flush and close the writer
+                    pipeline.shutdown_within(config.output.shutdown_timeout).await;
+                    #[cfg(feature = "otel")]
+                    otel::shutdown();
+                    info!("Exported {} flows", metrics::exported_flows());
+                    if metrics::filtered_packets() > 0 {
+                        info!("Filtered out {} packets", metrics::filtered_packets());
+                    }
+                    if metrics::truncated_feature_flows() > 0 {
+                        info!(
+                            "{} flows went without a packet sequence or a payload snapshot at the \
+                            feature memory limit",
+                            metrics::truncated_feature_flows()
+                        );
+                    }
 
                     let end = Instant::now();
                     debug!(
@@ -234,8 +424,103 @@ async fn run_with_config(config: Config) {
                 FlowType::CIDDS => execute_offline!(CiddsFlow),
                 FlowType::Nfstream => execute_offline!(NfFlow),
                 FlowType::NTL => execute_offline!(NTLFlow),
+                FlowType::Rustiflow => execute_offline!(RustiFlow),
                 FlowType::Custom => execute_offline!(CustomFlow),
             }
         }
+        Commands::Schema { format } => {
+            macro_rules! print_schema {
+                ($flow_ty:ty) => {{
+                    let features = &config.config.features;
+                    let skip = config.output.drop_contaminant_features;
+                    match format {
+                        SchemaFormat::Json => println!(
+                            "{:#}",
+                            schema::json_schema::<$flow_ty>(&settings, features, skip)
+                        ),
+                        SchemaFormat::Proto => print!(
+                            "{}",
+                            schema::proto_schema::<$flow_ty>(&settings, features, skip)
+                        ),
+                    }
+                }};
+            }
+
+            match config.config.features {
+                FlowType::Basic => print_schema!(BasicFlow),
+                FlowType::CIC => print_schema!(CicFlow),
+                FlowType::CIDDS => print_schema!(CiddsFlow),
+                FlowType::Nfstream => print_schema!(NfFlow),
+                FlowType::NTL => print_schema!(NTLFlow),
+                FlowType::Rustiflow => print_schema!(RustiFlow),
+                FlowType::Custom => print_schema!(CustomFlow),
+            }
+        }
+        Commands::Replay { path, format } => {
+            if let Err(e) = replay::check_output(&config.output, &path) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            macro_rules! execute_replay {
+                ($flow_ty:ty) => {{
+                    let mut output_writer = OutputWriter::<$flow_ty>::new(
+                        Arc::clone(&settings),
+                        config.config.features.clone(),
+                        config.output.output,
+                        config.output.header,
+                        config.output.drop_contaminant_features,
+                        config.output.min_packets,
+                        config.output.export_path,
+                        config.output.clickhouse,
+                        config.output.protobuf_address,
+                    );
+                    output_writer.retry_writes(
+                        config.output.write_retries,
+                        config.output.write_retry_backoff,
+                    );
+                    if let Some(path) = config.output.dead_letter_path.clone() {
+                        output_writer.dead_letter(path).unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        });
+                    }
+                    output_writer.init().await;
+
+                    let error_handler = export_error::default_handler();
+                    if let Err(e) = replay::replay(
+                        &mut output_writer,
+                        &path,
+                        format,
+                        config.output.drop_contaminant_features,
+                        &error_handler,
+                    )
+                    .await
+                    {
+                        error!("Error replaying {}: {}", path, e);
+                    }
+                    if let Err(e) = output_writer.flush_and_close().await {
+                        error!("Error closing the output: {}", e);
+                    }
+                }};
+            }
+
+            match config.config.features {
+                FlowType::Basic => execute_replay!(BasicFlow),
+                FlowType::CIC => execute_replay!(CicFlow),
+                FlowType::CIDDS => execute_replay!(CiddsFlow),
+                FlowType::Nfstream => execute_replay!(NfFlow),
+                FlowType::NTL => execute_replay!(NTLFlow),
+                FlowType::Rustiflow => execute_replay!(RustiFlow),
+                FlowType::Custom => execute_replay!(CustomFlow),
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Commands::ListInterfaces => interfaces::list_interfaces(),
+        // The capture backends and the interfaces are Linux only, pcap files can be read anywhere
+        #[cfg(not(target_os = "linux"))]
+        Commands::Realtime { .. } | Commands::ListInterfaces => {
+            error!("Capturing from an interface needs Linux, only pcap files can be read here");
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,348 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::flows::feature_memory;
+
+/// A single blocked export longer than this is reported as a slow sink.
+const SLOW_SINK_THRESHOLD: Duration = Duration::from_millis(100);
+/// Minimum time between two slow sink warnings.
+const SLOW_SINK_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+/// A shard processing this many times the mean packets of the shards is reported as hot.
+const SHARD_IMBALANCE_THRESHOLD: f64 = 2.0;
+/// Intervals with fewer packets in total are too short to judge the balance of the shards.
+const SHARD_IMBALANCE_MIN_PACKETS: u64 = 1000;
+
+static SINK_NAME: OnceLock<String> = OnceLock::new();
+static BLOCKED_EXPORTS: AtomicU64 = AtomicU64::new(0);
+static BLOCKED_EXPORT_TIME_US: AtomicU64 = AtomicU64::new(0);
+static LAST_SLOW_SINK_WARNING: Mutex<Option<Instant>> = Mutex::new(None);
+static ACTIVE_FLOWS: AtomicI64 = AtomicI64::new(0);
+static EXPORTED_FLOWS: AtomicU64 = AtomicU64::new(0);
+static OUTPUT_FLOWS: AtomicU64 = AtomicU64::new(0);
+static EXPORT_ERRORS: AtomicU64 = AtomicU64::new(0);
+static MALFORMED_PACKETS: AtomicU64 = AtomicU64::new(0);
+static FILTERED_PACKETS: AtomicU64 = AtomicU64::new(0);
+static CAPTURED_PACKETS: AtomicU64 = AtomicU64::new(0);
+static TRUNCATED_FEATURE_FLOWS: AtomicU64 = AtomicU64::new(0);
+// The bits of the f64, `u64::MAX` (a NaN) until the first interface check
+static CAPTURE_RATIO: AtomicU64 = AtomicU64::new(u64::MAX);
+static SHARDS: Mutex<Vec<Arc<ShardMetrics>>> = Mutex::new(Vec::new());
+
+/// The counters of one flow table shard.
+#[derive(Debug, Default)]
+pub(crate) struct ShardMetrics {
+    packets: AtomicU64,
+    active_flows: AtomicU64,
+}
+
+impl ShardMetrics {
+    /// Records packets handed to the flow table of the shard, including filtered ones.
+    pub fn add_packets(&self, count: u64) {
+        self.packets.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Sets the number of flows the flow table of the shard currently holds.
+    pub fn set_active_flows(&self, flows: u64) {
+        self.active_flows.store(flows, Ordering::Relaxed);
+    }
+
+    /// Returns the number of packets handed to the shard so far.
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of flows the shard currently holds.
+    pub fn active_flows(&self) -> u64 {
+        self.active_flows.load(Ordering::Relaxed)
+    }
+}
+
+/// Names the sink the flows are exported to, used in the slow sink warnings.
+///
+/// ### Arguments
+///
+/// * `name` - A human-readable name of the sink, e.g. the output file.
+pub(crate) fn set_sink_name(name: String) {
+    let _ = SINK_NAME.set(name);
+}
+
+/// Records an export that had to wait for room on the full export channel.
+///
+/// Warns, at most every few seconds, when the wait exceeds the slow sink threshold.
+///
+/// ### Arguments
+///
+/// * `waited` - How long the export was blocked.
+pub(crate) fn record_blocked_export(waited: Duration) {
+    BLOCKED_EXPORTS.fetch_add(1, Ordering::Relaxed);
+    BLOCKED_EXPORT_TIME_US.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+
+    if waited < SLOW_SINK_THRESHOLD {
+        return;
+    }
+
+    let mut last_warning = LAST_SLOW_SINK_WARNING.lock().unwrap();
+    if last_warning.is_none_or(|last| last.elapsed() >= SLOW_SINK_WARNING_INTERVAL) {
+        *last_warning = Some(Instant::now());
+        warn!(
+            "Packet processing was blocked for {} ms exporting a flow, the sink ({}) can't keep up; \
+            packets may be dropped",
+            waited.as_millis(),
+            SINK_NAME.get().map_or("unknown", String::as_str)
+        );
+    }
+}
+
+/// Returns the number of exports that had to wait for the sink.
+pub fn blocked_exports() -> u64 {
+    BLOCKED_EXPORTS.load(Ordering::Relaxed)
+}
+
+/// Returns the total time packet processing spent waiting for the sink.
+pub fn blocked_export_time() -> Duration {
+    Duration::from_micros(BLOCKED_EXPORT_TIME_US.load(Ordering::Relaxed))
+}
+
+/// Adjusts the number of flows currently held by the flow tables.
+///
+/// ### Arguments
+///
+/// * `delta` - The change of the number of flows of one flow table.
+pub(crate) fn add_active_flows(delta: i64) {
+    ACTIVE_FLOWS.fetch_add(delta, Ordering::Relaxed);
+}
+
+/// Returns the number of flows currently held by the flow tables.
+pub fn active_flows() -> u64 {
+    ACTIVE_FLOWS.load(Ordering::Relaxed).max(0) as u64
+}
+
+/// Records a flow handed to the output, including early export snapshots.
+pub(crate) fn record_exported_flow() {
+    EXPORTED_FLOWS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of flows handed to the output so far.
+pub fn exported_flows() -> u64 {
+    EXPORTED_FLOWS.load(Ordering::Relaxed)
+}
+
+/// Records a flow the output took from the export channel, including flows it leaves out.
+pub(crate) fn record_output_flow() {
+    OUTPUT_FLOWS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of flows the output took from the export channel so far.
+pub fn output_flows() -> u64 {
+    OUTPUT_FLOWS.load(Ordering::Relaxed)
+}
+
+/// Records a flow that was lost on the export path, see `export_error`.
+pub(crate) fn record_export_error() {
+    EXPORT_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of flows lost on the export path so far.
+pub fn export_errors() -> u64 {
+    EXPORT_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Records packets skipped because their headers are truncated or inconsistent.
+///
+/// ### Arguments
+///
+/// * `count` - The number of skipped packets, e.g. 1 or the count of an eBPF program.
+pub(crate) fn add_malformed_packets(count: u64) {
+    MALFORMED_PACKETS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Returns the number of malformed packets skipped so far.
+pub fn malformed_packets() -> u64 {
+    MALFORMED_PACKETS.load(Ordering::Relaxed)
+}
+
+/// Records packets dropped by the packet filter rules.
+///
+/// ### Arguments
+///
+/// * `count` - The number of dropped packets.
+pub(crate) fn add_filtered_packets(count: u64) {
+    FILTERED_PACKETS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Returns the number of packets dropped by the packet filter rules so far.
+pub fn filtered_packets() -> u64 {
+    FILTERED_PACKETS.load(Ordering::Relaxed)
+}
+
+/// Records flows that went without an optional feature at the feature memory limit.
+///
+/// ### Arguments
+///
+/// * `count` - The number of flows.
+pub(crate) fn add_truncated_feature_flows(count: u64) {
+    TRUNCATED_FEATURE_FLOWS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Returns the number of flows that went without an optional feature so far.
+pub fn truncated_feature_flows() -> u64 {
+    TRUNCATED_FEATURE_FLOWS.load(Ordering::Relaxed)
+}
+
+/// Records packets received from the capture, before they are parsed.
+///
+/// ### Arguments
+///
+/// * `count` - The number of received packets or eBPF events.
+pub(crate) fn add_captured_packets(count: u64) {
+    CAPTURED_PACKETS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Returns the number of packets received from the capture so far.
+pub fn captured_packets() -> u64 {
+    CAPTURED_PACKETS.load(Ordering::Relaxed)
+}
+
+/// Sets the share of the interface packets captured in the last interface check.
+pub(crate) fn set_capture_ratio(ratio: f64) {
+    CAPTURE_RATIO.store(ratio.to_bits(), Ordering::Relaxed);
+}
+
+/// Returns the share of the interface packets captured in the last interface check, if any.
+pub fn capture_ratio() -> Option<f64> {
+    Some(f64::from_bits(CAPTURE_RATIO.load(Ordering::Relaxed))).filter(|ratio| !ratio.is_nan())
+}
+
+/// Adds the counters of a new flow table shard, numbered in the order of registration.
+pub(crate) fn register_shard() -> Arc<ShardMetrics> {
+    let shard = Arc::new(ShardMetrics::default());
+    SHARDS.lock().unwrap().push(Arc::clone(&shard));
+    shard
+}
+
+/// Returns the counters of all flow table shards, in the order of their registration.
+pub(crate) fn shards() -> Vec<Arc<ShardMetrics>> {
+    SHARDS.lock().unwrap().clone()
+}
+
+/// Returns how many times the mean packets of the shards the busiest shard processed.
+///
+/// ### Arguments
+///
+/// * `packets` - The packets each shard processed in an interval.
+///
+/// ### Returns
+///
+/// The ratio and the index of the busiest shard, `None` with a single shard or too few
+/// packets to judge the balance.
+pub(crate) fn shard_imbalance(packets: &[u64]) -> Option<(f64, usize)> {
+    let total: u64 = packets.iter().sum();
+    if packets.len() < 2 || total < SHARD_IMBALANCE_MIN_PACKETS {
+        return None;
+    }
+    // The first of equally busy shards
+    let (busiest, max) = packets.iter().enumerate().rev().max_by_key(|(_, packets)| **packets)?;
+    let mean = total as f64 / packets.len() as f64;
+    Some((*max as f64 / mean, busiest))
+}
+
+/// Logs the packets per second and active flows of every shard and in total every `interval`
+/// seconds, runs until the task is aborted.
+///
+/// Warns when the busiest shard processes far more than its share of the packets, e.g.
+/// because a single huge flow or a skewed 5-tuple distribution lands on it.
+pub async fn report_shard_throughput(interval: u64) {
+    let mut ticks = tokio::time::interval(Duration::from_secs(interval));
+    ticks.tick().await;
+    let mut previous: Vec<u64> = Vec::new();
+    let mut previous_time = Instant::now();
+
+    loop {
+        ticks.tick().await;
+        let shards = shards();
+        let packets: Vec<u64> = shards.iter().map(|shard| shard.packets()).collect();
+        let deltas: Vec<u64> = packets
+            .iter()
+            .enumerate()
+            .map(|(index, packets)| packets - previous.get(index).copied().unwrap_or_default())
+            .collect();
+        let seconds = previous_time.elapsed().as_secs_f64().max(f64::EPSILON);
+        previous = packets;
+        previous_time = Instant::now();
+
+        let per_shard: Vec<String> = shards
+            .iter()
+            .zip(&deltas)
+            .enumerate()
+            .map(|(index, (shard, delta))| {
+                format!(
+                    "shard {}: {:.0} packets/s, {} flows",
+                    index,
+                    *delta as f64 / seconds,
+                    shard.active_flows()
+                )
+            })
+            .collect();
+        // The optional features reserve memory only while they are enabled
+        let feature_memory = match feature_memory::used_bytes() {
+            0 => String::new(),
+            bytes => format!(", {:.1} MiB of feature memory", bytes as f64 / (1024.0 * 1024.0)),
+        };
+        info!(
+            "Processed {:.0} packets/s with {} active flows{} ({})",
+            deltas.iter().sum::<u64>() as f64 / seconds,
+            active_flows(),
+            feature_memory,
+            per_shard.join("; ")
+        );
+
+        if let Some((ratio, busiest)) = shard_imbalance(&deltas) {
+            if ratio >= SHARD_IMBALANCE_THRESHOLD {
+                warn!(
+                    "Shard {} processed {:.1} times the mean packets of the {} shards in the \
+                    last {} seconds, the traffic is skewed towards a few flows or 5-tuples",
+                    busiest,
+                    ratio,
+                    deltas.len(),
+                    interval
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{blocked_export_time, blocked_exports, record_blocked_export, shard_imbalance};
+
+    #[test]
+    fn test_record_blocked_export() {
+        let exports = blocked_exports();
+        let time = blocked_export_time();
+
+        record_blocked_export(Duration::from_millis(5));
+        record_blocked_export(Duration::from_millis(200));
+
+        assert!(blocked_exports() >= exports + 2);
+        assert!(blocked_export_time() >= time + Duration::from_millis(205));
+    }
+
+    #[test]
+    fn test_shard_imbalance() {
+        assert_eq!(shard_imbalance(&[500, 500, 500, 500]), Some((1.0, 0)));
+        // One shard holds a huge flow
+        assert_eq!(shard_imbalance(&[100, 100, 3700, 100]), Some((3.7, 2)));
+        // A single shard, or too few packets to tell
+        assert_eq!(shard_imbalance(&[5000]), None);
+        assert_eq!(shard_imbalance(&[10, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,670 @@
+//! Traces and metrics of the pipeline over OTLP, for setups whose observability runs on
+//! OpenTelemetry. Built with the `otel` feature.
+//!
+//! The export runs on the OpenTelemetry SDK and its OTLP exporters, over gRPC, HTTP with
+//! protobuf or HTTP with JSON bodies. It is configured by the standard `OTEL_*` environment
+//! variables, see `OtlpConfig::from_env`. Every sampled flow of a live capture gives a trace
+//! through the stages of the pipeline, from the kernel capturing its first packet to the output
+//! taking it, the capture latency of every packet goes into a histogram, and the counters of
+//! `metrics` are exported periodically. The SDK exports from its own threads, so a slow or
+//! unreachable collector only costs the spans that don't fit into its queue.
+
+use std::{sync::OnceLock, thread, time::Duration};
+
+use anyhow::Context as _;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info, warn};
+use opentelemetry::{
+    metrics::{Histogram, Meter, MeterProvider},
+    trace::{Span, TraceContextExt, Tracer, TracerProvider},
+    Context, InstrumentationScope, KeyValue,
+};
+use opentelemetry_otlp::{
+    MetricExporter, Protocol, SpanExporter, WithExportConfig, OTEL_EXPORTER_OTLP_PROTOCOL,
+};
+use opentelemetry_sdk::{
+    metrics::SdkMeterProvider,
+    resource::{EnvResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector},
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use tokio::runtime::Runtime;
+
+use crate::{
+    flows::basic_flow::BasicFlow,
+    metrics,
+};
+
+const DEFAULT_SERVICE_NAME: &str = "rustiflow";
+/// The buckets of the capture latency in seconds, from 10 µs to a second.
+const LATENCY_BOUNDARIES: [f64; 11] =
+    [0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.01, 0.1, 1.0];
+
+static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+
+/// A counter of `metrics`: its name, unit and description, and how it is read.
+type Counter = (&'static str, &'static str, &'static str, fn() -> u64);
+
+/// Which signals are exported, and over which protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtlpConfig {
+    /// The protocol of the traces, `None` if they aren't exported.
+    traces: Option<Protocol>,
+    /// The protocol of the metrics, `None` if they aren't exported.
+    metrics: Option<Protocol>,
+    /// Attributes added to the resource of the SDK.
+    attributes: Vec<KeyValue>,
+}
+
+impl OtlpConfig {
+    /// Reads the configuration from the standard environment variables of the OpenTelemetry
+    /// SDKs.
+    ///
+    /// * `OTEL_SDK_DISABLED=true` turns the export off.
+    /// * `OTEL_TRACES_EXPORTER=none` and `OTEL_METRICS_EXPORTER=none` skip a signal.
+    /// * `OTEL_EXPORTER_OTLP_PROTOCOL` is the protocol of both signals, `grpc`,
+    ///   `http/protobuf` (the default) or `http/json`, `OTEL_EXPORTER_OTLP_TRACES_PROTOCOL` and
+    ///   `OTEL_EXPORTER_OTLP_METRICS_PROTOCOL` the one of a signal.
+    ///
+    /// The SDK reads the others itself: the endpoints, headers, timeouts and compression of
+    /// `OTEL_EXPORTER_OTLP_*`, `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES`, the sampler of
+    /// `OTEL_TRACES_SAMPLER`, the batches of `OTEL_BSP_*` and `OTEL_METRIC_EXPORT_INTERVAL`.
+    ///
+    /// ### Returns
+    ///
+    /// The configuration, or `None` if the export is disabled or no signal is left.
+    pub fn from_env() -> Option<OtlpConfig> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<OtlpConfig> {
+        if var("OTEL_SDK_DISABLED").is_some_and(|disabled| disabled.eq_ignore_ascii_case("true")) {
+            return None;
+        }
+        let traces = signal_protocol(&var, "TRACES");
+        let metrics = signal_protocol(&var, "METRICS");
+        if traces.is_none() && metrics.is_none() {
+            return None;
+        }
+        Some(OtlpConfig {
+            traces,
+            metrics,
+            attributes: Vec::new(),
+        })
+    }
+}
+
+/// Returns the protocol of a signal (`TRACES` or `METRICS`), `None` if it isn't exported.
+fn signal_protocol(var: &impl Fn(&str) -> Option<String>, signal: &str) -> Option<Protocol> {
+    let name = signal.to_lowercase();
+    match var(&format!("OTEL_{}_EXPORTER", signal)).as_deref() {
+        Some("none") => return None,
+        None | Some("otlp") => {}
+        Some(exporter) => warn!("Unsupported {} exporter {}, exporting over OTLP", name, exporter),
+    }
+    let protocol = var(&format!("OTEL_EXPORTER_OTLP_{}_PROTOCOL", signal))
+        .or_else(|| var(OTEL_EXPORTER_OTLP_PROTOCOL));
+    match protocol.as_deref().map(str::trim) {
+        None | Some("http/protobuf") => Some(Protocol::HttpBinary),
+        Some("http/json") => Some(Protocol::HttpJson),
+        Some("grpc") => Some(Protocol::Grpc),
+        Some(protocol) => {
+            warn!("Unsupported OTLP protocol {}, sending the {} as http/protobuf", protocol, name);
+            Some(Protocol::HttpBinary)
+        }
+    }
+}
+
+fn span_exporter(protocol: Protocol) -> anyhow::Result<SpanExporter> {
+    let exporter = match protocol {
+        Protocol::Grpc => SpanExporter::builder().with_tonic().build(),
+        protocol => SpanExporter::builder().with_http().with_protocol(protocol).build(),
+    };
+    exporter.context("failed to build the OTLP span exporter")
+}
+
+fn metric_exporter(protocol: Protocol) -> anyhow::Result<MetricExporter> {
+    let exporter = match protocol {
+        Protocol::Grpc => MetricExporter::builder().with_tonic().build(),
+        protocol => MetricExporter::builder().with_http().with_protocol(protocol).build(),
+    };
+    exporter.context("failed to build the OTLP metric exporter")
+}
+
+/// Builds the resource of both signals, named `rustiflow` unless the environment names it.
+fn resource(attributes: &[KeyValue]) -> Resource {
+    let builder = Resource::builder_empty()
+        .with_service_name(DEFAULT_SERVICE_NAME)
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+        .with_attributes(attributes.iter().cloned())
+        .with_detectors(&[
+            Box::new(TelemetryResourceDetector),
+            Box::new(EnvResourceDetector::new()),
+        ]);
+    // The detector falls back to `unknown_service` without the variable
+    if std::env::var_os("OTEL_SERVICE_NAME").is_some() {
+        builder.with_detectors(&[Box::new(SdkProvidedResourceDetector)]).build()
+    } else {
+        builder.build()
+    }
+}
+
+fn scope() -> InstrumentationScope {
+    InstrumentationScope::builder(DEFAULT_SERVICE_NAME)
+        .with_version(env!("CARGO_PKG_VERSION"))
+        .build()
+}
+
+/// Converts a count to the signed integers of the attributes.
+fn int(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Registers the counters of `metrics` as instruments read at every export.
+///
+/// ### Returns
+///
+/// The histogram of the capture latency, recorded by the capture.
+fn register_metrics(meter: &Meter) -> Histogram<f64> {
+    let counters: [Counter; 8] = [
+        (
+            "rustiflow.packets.captured",
+            "{packet}",
+            "Packets received from the capture, before they are parsed",
+            metrics::captured_packets,
+        ),
+        (
+            "rustiflow.packets.malformed",
+            "{packet}",
+            "Packets skipped because their headers are truncated or inconsistent",
+            metrics::malformed_packets,
+        ),
+        (
+            "rustiflow.packets.filtered",
+            "{packet}",
+            "Packets dropped by the packet filter rules",
+            metrics::filtered_packets,
+        ),
+        (
+            "rustiflow.flows.exported",
+            "{flow}",
+            "Flows handed to the output, including interim records",
+            metrics::exported_flows,
+        ),
+        (
+            "rustiflow.flows.output",
+            "{flow}",
+            "Flows the output took from the export channel",
+            metrics::output_flows,
+        ),
+        (
+            "rustiflow.flows.lost",
+            "{flow}",
+            "Flows lost on the export path",
+            metrics::export_errors,
+        ),
+        (
+            "rustiflow.flows.truncated_features",
+            "{flow}",
+            "Flows that went without an optional feature at the feature memory limit",
+            metrics::truncated_feature_flows,
+        ),
+        (
+            "rustiflow.export.blocked",
+            "{export}",
+            "Exports that had to wait for room on the full export channel",
+            metrics::blocked_exports,
+        ),
+    ];
+    for (name, unit, description, read) in counters {
+        meter
+            .u64_observable_counter(name)
+            .with_unit(unit)
+            .with_description(description)
+            .with_callback(move |observer| observer.observe(read(), &[]))
+            .build();
+    }
+    meter
+        .f64_observable_counter("rustiflow.export.blocked_time")
+        .with_unit("s")
+        .with_description("The time packet processing spent waiting for the output")
+        .with_callback(|observer| {
+            observer.observe(metrics::blocked_export_time().as_secs_f64(), &[])
+        })
+        .build();
+    meter
+        .u64_observable_gauge("rustiflow.flows.active")
+        .with_unit("{flow}")
+        .with_description("Flows currently held by the flow tables")
+        .with_callback(|observer| observer.observe(metrics::active_flows(), &[]))
+        .build();
+    meter
+        .u64_observable_counter("rustiflow.shard.packets")
+        .with_unit("{packet}")
+        .with_description("Packets processed by each flow table shard")
+        .with_callback(|observer| {
+            for (index, shard) in metrics::shards().iter().enumerate() {
+                observer.observe(shard.packets(), &[KeyValue::new("shard", index as i64)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_gauge("rustiflow.shard.flows.active")
+        .with_unit("{flow}")
+        .with_description("Flows currently held by each flow table shard")
+        .with_callback(|observer| {
+            for (index, shard) in metrics::shards().iter().enumerate() {
+                observer.observe(shard.active_flows(), &[KeyValue::new("shard", index as i64)]);
+            }
+        })
+        .build();
+    meter
+        .f64_observable_gauge("rustiflow.capture.ratio")
+        .with_unit("1")
+        .with_description("The share of the interface packets captured in the last interface check")
+        .with_callback(|observer| {
+            if let Some(ratio) = metrics::capture_ratio() {
+                observer.observe(ratio, &[]);
+            }
+        })
+        .build();
+    meter
+        .f64_histogram("rustiflow.capture.latency")
+        .with_unit("s")
+        .with_description("The time from the kernel capturing a packet to userspace reading it")
+        .with_boundaries(LATENCY_BOUNDARIES.to_vec())
+        .build()
+}
+
+/// Records the trace of a flow: the flow, with the way of its first packet from the kernel to
+/// userspace, the packets being aggregated into it, the wait for its timeout or end, and the way
+/// through the export channel to the output.
+fn trace_flow<T>(tracer: &T, flow: &BasicFlow, output_time: DateTime<Utc>)
+where
+    T: Tracer,
+    T::Span: Send + Sync + 'static,
+{
+    let mut attributes = vec![
+        KeyValue::new("flow.id", flow.settings.flow_key_feature(&flow.flow_key)),
+        KeyValue::new("flow.protocol", i64::from(flow.protocol)),
+        KeyValue::new("flow.packets", int(flow.get_packet_count())),
+        KeyValue::new("flow.bytes", int(flow.fwd_total_bytes + flow.bwd_total_bytes)),
+    ];
+    if let Some(end_reason) = flow.end_reason {
+        attributes.push(KeyValue::new("flow.end_reason", end_reason.to_string()));
+    }
+    let root = tracer
+        .span_builder("flow")
+        .with_start_time(flow.first_timestamp)
+        .with_attributes(attributes)
+        .start(tracer);
+    let cx = Context::current_with_span(root);
+
+    // The packets are dated when the kernel saw them
+    let export_time = flow.export_time.unwrap_or(output_time);
+    let mut stages = Vec::with_capacity(4);
+    let capture_latency = flow.capture_latency.and_then(|latency| TimeDelta::from_std(latency).ok());
+    if let Some(latency) = capture_latency {
+        stages.push(("capture", flow.first_timestamp, flow.first_timestamp + latency));
+    }
+    stages.extend([
+        ("aggregate", flow.first_timestamp, flow.last_timestamp),
+        ("expire", flow.last_timestamp, export_time),
+        ("export", export_time, output_time),
+    ]);
+    for (name, start, end) in stages {
+        let mut span = tracer
+            .span_builder(name)
+            .with_start_time(start)
+            .start_with_context(tracer, &cx);
+        // The capture clock of the packets may be behind the wall clock
+        span.end_with_timestamp(end.max(start).into());
+    }
+    cx.span().end_with_timestamp(output_time.max(flow.first_timestamp).into());
+}
+
+/// The providers of the export, shared by the capture and the outputs.
+struct Telemetry {
+    /// The tracer of the flows, `None` if they aren't traced.
+    tracer: Option<SdkTracer>,
+    tracer_provider: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+    capture_latency: Option<Histogram<f64>>,
+    /// Drives the connections of the gRPC exporters, `None` over HTTP.
+    _runtime: Option<Runtime>,
+}
+
+impl Telemetry {
+    fn new(config: &OtlpConfig, traces: Option<Protocol>) -> anyhow::Result<Telemetry> {
+        // The tonic client spawns its connections onto a Tokio runtime, the one of the caller
+        // may be single-threaded and ends before the last export
+        let runtime = [traces, config.metrics]
+            .contains(&Some(Protocol::Grpc))
+            .then(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .thread_name("otlp-grpc")
+                    .enable_all()
+                    .build()
+            })
+            .transpose()
+            .context("failed to start the runtime of the gRPC exporters")?;
+        let guard = runtime.as_ref().map(Runtime::enter);
+
+        let resource = resource(&config.attributes);
+        let tracer_provider = match traces {
+            Some(protocol) => Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(span_exporter(protocol)?)
+                    .with_resource(resource.clone())
+                    .build(),
+            ),
+            None => None,
+        };
+        let meter_provider = match config.metrics {
+            Some(protocol) => Some(
+                SdkMeterProvider::builder()
+                    .with_periodic_exporter(metric_exporter(protocol)?)
+                    .with_resource(resource)
+                    .build(),
+            ),
+            None => None,
+        };
+        drop(guard);
+
+        Ok(Telemetry {
+            tracer: tracer_provider.as_ref().map(|provider| provider.tracer_with_scope(scope())),
+            capture_latency: meter_provider
+                .as_ref()
+                .map(|provider| register_metrics(&provider.meter_with_scope(scope()))),
+            tracer_provider,
+            meter_provider,
+            _runtime: runtime,
+        })
+    }
+}
+
+/// Starts the export configured by the environment, if it isn't disabled.
+///
+/// ### Arguments
+///
+/// * `trace_flows` - Whether the flows are traced, only the timing of a live capture is worth
+///   tracing; the metrics are exported either way.
+/// * `capture_source` - The interface or file of the capture, added to the resource.
+pub fn init_from_env(trace_flows: bool, capture_source: &str) {
+    if let Some(mut config) = OtlpConfig::from_env() {
+        if !capture_source.is_empty() {
+            config
+                .attributes
+                .push(KeyValue::new("rustiflow.capture_source", capture_source.to_string()));
+        }
+        init(config, trace_flows);
+    }
+}
+
+/// Starts the exporters, later calls are ignored.
+///
+/// ### Arguments
+///
+/// * `config` - Which signals are exported, and over which protocol.
+/// * `trace_flows` - Whether the flows are traced, see `init_from_env`.
+pub fn init(config: OtlpConfig, trace_flows: bool) {
+    let traces = config.traces.filter(|_| trace_flows);
+    let signals: Vec<String> = [("traces", traces), ("metrics", config.metrics)]
+        .into_iter()
+        .filter_map(|(name, protocol)| Some(format!("{} over {:?}", name, protocol?)))
+        .collect();
+    if signals.is_empty() {
+        return;
+    }
+    if TELEMETRY.get().is_some() {
+        warn!("The OTLP export was already started, ignoring the new configuration");
+        return;
+    }
+    // The blocking HTTP client can't be built inside the runtime of the caller
+    let telemetry = thread::scope(|scope| {
+        scope
+            .spawn(|| Telemetry::new(&config, traces))
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("building the exporters panicked")))
+    });
+    match telemetry {
+        Ok(telemetry) => {
+            if TELEMETRY.set(telemetry).is_ok() {
+                info!("Exporting the {} with OTLP", signals.join(" and "));
+            }
+        }
+        Err(e) => error!("Failed to start the OTLP export: {:#}", e),
+    }
+}
+
+/// Records the trace of a flow the output took from the export channel, if it is sampled.
+///
+/// Only final records are traced, the interim ones are part of the flow's final trace.
+///
+/// ### Arguments
+///
+/// * `flow` - The flow that reached the output.
+pub(crate) fn record_flow(flow: &BasicFlow) {
+    let Some(tracer) = TELEMETRY.get().and_then(|telemetry| telemetry.tracer.as_ref()) else {
+        return;
+    };
+    if flow.end_reason.is_some() {
+        trace_flow(tracer, flow, Utc::now());
+    }
+}
+
+/// Records how long a packet took from the kernel to userspace.
+///
+/// ### Arguments
+///
+/// * `latency` - The capture latency of the packet, see `PacketFeatures::capture_latency`.
+pub(crate) fn record_capture_latency(latency: Duration) {
+    let telemetry = TELEMETRY.get();
+    if let Some(histogram) = telemetry.and_then(|telemetry| telemetry.capture_latency.as_ref()) {
+        histogram.record(latency.as_secs_f64(), &[]);
+    }
+}
+
+/// Exports the spans left in the queue and the last metrics, then stops the exporters.
+///
+/// Called at the end of a run, each signal waits at most for the export timeout.
+pub fn shutdown() {
+    let Some(telemetry) = TELEMETRY.get() else {
+        return;
+    };
+    if let Some(Err(e)) = telemetry.tracer_provider.as_ref().map(SdkTracerProvider::shutdown) {
+        warn!("Failed to export the last traces over OTLP: {}", e);
+    }
+    if let Some(Err(e)) = telemetry.meter_provider.as_ref().map(SdkMeterProvider::shutdown) {
+        warn!("Failed to export the last metrics over OTLP: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use chrono::{DateTime, TimeDelta};
+    use opentelemetry::{
+        metrics::MeterProvider,
+        trace::{Status, TracerProvider},
+        KeyValue, Value,
+    };
+    use opentelemetry_otlp::Protocol;
+    use opentelemetry_sdk::{
+        error::OTelSdkResult,
+        metrics::{
+            data::ResourceMetrics, exporter::PushMetricExporter, PeriodicReader,
+            SdkMeterProvider, Temporality,
+        },
+        trace::{SdkTracerProvider, Span, SpanData, SpanProcessor},
+    };
+
+    use super::{register_metrics, trace_flow, OtlpConfig};
+    use crate::flows::{
+        basic_flow::{BasicFlow, EndReason},
+        flow::Flow,
+    };
+
+    fn config(vars: &[(&str, &str)]) -> Option<OtlpConfig> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        OtlpConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    /// Keeps the ended spans.
+    #[derive(Debug, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanProcessor for SpanRecorder {
+        fn on_start(&self, _span: &mut Span, _cx: &opentelemetry::Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+    }
+
+    /// Keeps the names of the exported metrics.
+    #[derive(Debug, Default)]
+    struct MetricRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl PushMetricExporter for MetricRecorder {
+        async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+            let names = metrics
+                .scope_metrics()
+                .flat_map(|scope| scope.metrics())
+                .map(|metric| metric.name().to_string());
+            self.0.lock().unwrap().extend(names);
+            Ok(())
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn temporality(&self) -> Temporality {
+            Temporality::Cumulative
+        }
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let defaults = config(&[]).unwrap();
+        assert_eq!(defaults.traces, Some(Protocol::HttpBinary));
+        assert_eq!(defaults.metrics, Some(Protocol::HttpBinary));
+
+        let grpc = config(&[("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc")]).unwrap();
+        assert_eq!((grpc.traces, grpc.metrics), (Some(Protocol::Grpc), Some(Protocol::Grpc)));
+
+        // The protocol of a signal wins over the one of both
+        let configured = config(&[
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc"),
+            ("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL", "http/json"),
+            ("OTEL_TRACES_EXPORTER", "none"),
+        ])
+        .unwrap();
+        assert_eq!(configured.traces, None);
+        assert_eq!(configured.metrics, Some(Protocol::HttpJson));
+
+        let unsupported = config(&[("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", "thrift")]).unwrap();
+        assert_eq!(unsupported.traces, Some(Protocol::HttpBinary));
+
+        assert!(config(&[("OTEL_SDK_DISABLED", "true")]).is_none());
+        assert!(config(&[("OTEL_TRACES_EXPORTER", "none"), ("OTEL_METRICS_EXPORTER", "none")])
+            .is_none());
+    }
+
+    #[test]
+    fn test_trace_flow() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(SpanRecorder(Arc::clone(&spans)))
+            .build();
+        let tracer = provider.tracer("test");
+
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut flow = BasicFlow::new(
+            Arc::default(),
+            "10.0.0.1:1234-10.0.0.2:80-6".to_string(),
+            "10.0.0.1".parse().unwrap(),
+            1234,
+            "10.0.0.2".parse().unwrap(),
+            80,
+            6,
+            start,
+        );
+        flow.last_timestamp = start + TimeDelta::seconds(2);
+        flow.export_time = Some(start + TimeDelta::seconds(5));
+        flow.end_reason = Some(EndReason::IdleTimeout);
+        flow.capture_latency = Some(Duration::from_micros(250));
+        trace_flow(&tracer, &flow, start + TimeDelta::seconds(6));
+
+        let spans = spans.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, vec!["capture", "aggregate", "expire", "export", "flow"]);
+        let root = &spans[4];
+        let trace_id = root.span_context.trace_id();
+        assert!(spans.iter().all(|span| span.span_context.trace_id() == trace_id));
+        assert!(spans[..4].iter().all(|span| span.parent_span_id == root.span_context.span_id()));
+        assert_eq!(root.status, Status::Unset);
+
+        let seconds = |time| DateTime::<chrono::Utc>::from(time) - start;
+        assert_eq!(seconds(spans[0].end_time), TimeDelta::microseconds(250));
+        assert_eq!(seconds(spans[2].start_time), TimeDelta::seconds(2));
+        assert_eq!(seconds(spans[3].start_time), TimeDelta::seconds(5));
+        assert_eq!(seconds(root.start_time), TimeDelta::zero());
+        assert_eq!(seconds(root.end_time), TimeDelta::seconds(6));
+        assert!(root
+            .attributes
+            .contains(&KeyValue::new("flow.end_reason", Value::from("idle-timeout"))));
+
+        // Without the latency of its first packet, e.g. read from a file, there is no capture
+        flow.capture_latency = None;
+        drop(spans);
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(SpanRecorder(Arc::clone(&spans)))
+            .build();
+        trace_flow(&provider.tracer("test"), &flow, start + TimeDelta::seconds(6));
+        assert_eq!(spans.lock().unwrap()[0].name, "aggregate");
+    }
+
+    #[test]
+    fn test_register_metrics() {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let reader = PeriodicReader::builder(MetricRecorder(Arc::clone(&names))).build();
+        let provider = SdkMeterProvider::builder().with_reader(reader).build();
+        let latency = register_metrics(&provider.meter("test"));
+        latency.record(0.0002, &[]);
+        provider.force_flush().unwrap();
+
+        let names = names.lock().unwrap();
+        for name in [
+            "rustiflow.packets.captured",
+            "rustiflow.flows.active",
+            "rustiflow.export.blocked_time",
+            "rustiflow.capture.latency",
+        ] {
+            assert!(names.iter().any(|exported| exported == name), "{} is missing", name);
+        }
+    }
+}
@@ -1,14 +1,134 @@
-use crate::{args::ExportMethodType, flows::flow::Flow};
-use log::{debug, error};
+use crate::{
+    args::{ClickHouseConfig, ExportMethodType, FlowType, HostRollupConfig, ScanDetectionConfig},
+    clickhouse::ClickHouseSink,
+    dead_letter::DeadLetterFile,
+    export_error::ExportError,
+    flows::{
+        basic_flow::BasicFlow,
+        flow::Flow,
+        registry::{self, Feature},
+        settings::FlowSettings,
+    },
+    host_rollup::HostRollup,
+    metrics,
+    parquet_file::ParquetFile,
+    partition::{PartitionTarget, PartitionedOutput},
+    protobuf::ProtobufEncoder,
+    reload::ReloadableConfig,
+    scan_detector::ScanDetector,
+};
+use crossterm::style::{Color, Stylize};
+use log::{debug, error, info, warn};
+use chrono::{DateTime, TimeDelta, Utc};
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::{BufWriter, Write},
+    io::{BufWriter, IsTerminal, Write},
+    net::{IpAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::{sync::watch, task::JoinHandle};
+
+/// The longest wait between two retries of a failed write.
+const MAX_WRITE_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often the output checks for ClickHouse batches that waited for their `flush_interval`
+/// and host rollup windows that ended while no flows arrive.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Waits for the output task to write out the flows left in the export channel and close the
+/// output at shutdown.
+///
+/// ### Arguments
+///
+/// * `output_task` - The task writing the flows of the export channel, it finishes once the
+///   flow tables have exported their last flows and closed the channel.
+/// * `shutdown_timeout` - The seconds to wait at most, e.g. for a slow or unreachable sink;
+///   the flows still in the channel are dropped then. `None` waits until all are written.
+pub(crate) async fn wait_for_output(
+    mut output_task: JoinHandle<std::io::Result<()>>,
+    shutdown_timeout: Option<u64>,
+) {
+    let output_flows = metrics::output_flows();
+    let result = match shutdown_timeout {
+        Some(timeout) => {
+            match tokio::time::timeout(Duration::from_secs(timeout), &mut output_task).await {
+                Ok(result) => result,
+                Err(_) => {
+                    output_task.abort();
+                    let written = metrics::output_flows() - output_flows;
+                    let dropped = metrics::exported_flows().saturating_sub(metrics::output_flows());
+                    error!(
+                        "The output didn't finish within the shutdown timeout of {} seconds, \
+                        {} flows were written at shutdown and {} flows are dropped",
+                        timeout, written, dropped
+                    );
+                    return;
+                }
+            }
+        }
+        None => (&mut output_task).await,
+    };
+
+    match result {
+        Ok(Ok(())) => debug!(
+            "Wrote {} flows at shutdown",
+            metrics::output_flows() - output_flows
+        ),
+        Ok(Err(e)) => error!("Error flushing and closing the writer: {:?}", e),
+        Err(e) => error!("Error waiting for output task: {:?}", e),
+    }
+}
+
+/// What a write with retries writes out.
+enum Record<'a, T> {
+    /// A flow, in the format of the output method.
+    Flow(&'a T),
+    /// A dumped record, see `write_dumped`.
+    Dumped(&'a str),
+}
 
 pub struct OutputWriter<T> {
+    /// The settings of the run, the same the flows are built with.
+    settings: Arc<FlowSettings>,
+    /// The optional features of the settings, built once for all records.
+    optional_features: Vec<Feature<BasicFlow>>,
     write_header: bool,
     skip_contaminant_features: bool,
+    min_packets: u32,
+    dropped_flows: u64,
+    table: Option<TableStyle>,
+    /// Batches the flows for ClickHouse instead of writing them out, if that's the method.
+    clickhouse: Option<ClickHouseSink>,
+    /// Encodes the flows as Protocol Buffers messages, if that's the method.
+    protobuf: Option<ProtobufEncoder>,
+    /// Writes the flows to a Parquet file instead, if that's the method.
+    parquet: Option<ParquetFile>,
+    config_updates: Option<watch::Receiver<ReloadableConfig>>,
+    /// Holds the flows back to write them ordered by their end, if enabled.
+    reorder_buffer: Option<ReorderBuffer<T>>,
+    /// Writes the flows to an output per partition instead, if the output is partitioned.
+    partitions: Option<PartitionedOutput>,
+    /// Checks the flows for port and host scans, if enabled.
+    scan_detector: Option<ScanDetector>,
+    /// Rolls the flows up per host, if enabled.
+    host_rollup: Option<HostRollup>,
+    /// How often a failed write is retried, and the wait before the first retry.
+    write_retries: u32,
+    write_retry_backoff: Duration,
+    /// Set once a write failed after all of its retries, until the next backoff of the
+    /// schedule passed. The flows go straight to the dead-letter file until then, or get a
+    /// single try without one.
+    down_until: Option<Instant>,
+    /// Whether the output is ClickHouse, whose sinks retry and keep the failed flows on their
+    /// own, so its writes aren't retried here.
+    clickhouse_output: bool,
+    /// Keeps the flows that failed to write, if enabled.
+    dead_letter: Option<DeadLetterFile>,
     writer: BufWriter<Box<dyn Write + Send>>,
+    /// The CSV file behind the writer, to sync it to disk on `flush`.
+    file: Option<File>,
     _phantom_data: std::marker::PhantomData<T>,
 }
 
@@ -17,33 +137,167 @@ where
     T: Flow,
 {
     pub fn new(
+        settings: Arc<FlowSettings>,
+        feature_set: FlowType,
         export_type: ExportMethodType,
         write_header: bool,
         skip_contaminant_features: bool,
+        min_packets: u32,
         file_path: Option<String>,
+        clickhouse_config: ClickHouseConfig,
+        protobuf_address: Option<String>,
     ) -> Self {
+        let table = match export_type {
+            ExportMethodType::Table => Some(TableStyle {
+                colored: std::io::stdout().is_terminal(),
+            }),
+            _ => None,
+        };
+        let header = registry::header::<T>(&settings, skip_contaminant_features);
+        let partitions = settings.partition_by.and_then(|partition_by| {
+            let target = match export_type {
+                ExportMethodType::Csv => PartitionTarget::Csv {
+                    path: file_path.clone().expect("File path required for CSV output"),
+                },
+                ExportMethodType::ClickHouse => {
+                    PartitionTarget::ClickHouse(clickhouse_config.clone())
+                }
+                // Rejected by the validation of the configuration
+                ExportMethodType::Print
+                | ExportMethodType::Table
+                | ExportMethodType::Protobuf
+                | ExportMethodType::Parquet => return None,
+            };
+            metrics::set_sink_name(format!("{:?} partitions", partition_by));
+            Some(PartitionedOutput::new(
+                Arc::clone(&settings),
+                partition_by,
+                target,
+                header.clone(),
+                write_header,
+            ))
+        });
+        let clickhouse = match export_type {
+            ExportMethodType::ClickHouse if partitions.is_none() => {
+                metrics::set_sink_name(format!(
+                    "ClickHouse table {} at {}",
+                    clickhouse_config.table, clickhouse_config.address
+                ));
+                Some(ClickHouseSink::new(
+                    clickhouse_config,
+                    &header,
+                    Arc::clone(&settings),
+                ))
+            }
+            _ => None,
+        };
+
+        let mut synced_file = None;
+        let mut parquet = None;
         let writer: BufWriter<Box<dyn Write + Send>> = match export_type {
-            ExportMethodType::Csv => {
+            ExportMethodType::Csv if partitions.is_none() => {
                 let path = file_path
                     .clone()
                     .expect("File path required for CSV output");
                 let file = File::create(&path).expect("Failed to create file");
+                metrics::set_sink_name(format!("CSV file {}", path));
+                synced_file = file.try_clone().ok();
                 BufWriter::new(Box::new(file))
             }
-            ExportMethodType::Print => BufWriter::new(Box::new(std::io::stdout())),
+            ExportMethodType::Print | ExportMethodType::Table => {
+                metrics::set_sink_name("console".to_string());
+                BufWriter::new(Box::new(std::io::stdout()))
+            }
+            ExportMethodType::Protobuf => match &protobuf_address {
+                Some(address) => {
+                    let stream = TcpStream::connect(address)
+                        .unwrap_or_else(|e| panic!("Failed to connect to {}: {}", address, e));
+                    metrics::set_sink_name(format!("Protobuf stream to {}", address));
+                    BufWriter::new(Box::new(stream))
+                }
+                None => {
+                    let path = file_path
+                        .clone()
+                        .expect("File path or address required for Protobuf output");
+                    let file = File::create(&path).expect("Failed to create file");
+                    metrics::set_sink_name(format!("Protobuf file {}", path));
+                    synced_file = file.try_clone().ok();
+                    BufWriter::new(Box::new(file))
+                }
+            },
+            ExportMethodType::Parquet => {
+                let path = file_path
+                    .clone()
+                    .expect("File path required for Parquet output");
+                let file = File::create(&path).expect("Failed to create file");
+                metrics::set_sink_name(format!("Parquet file {}", path));
+                synced_file = file.try_clone().ok();
+                parquet = Some(
+                    ParquetFile::new::<T>(
+                        Arc::clone(&settings),
+                        &feature_set,
+                        skip_contaminant_features,
+                        file,
+                    )
+                    .unwrap_or_else(|e| panic!("Failed to create Parquet file {}: {}", path, e)),
+                );
+                BufWriter::new(Box::new(std::io::sink()))
+            }
+            // The flows go to the ClickHouse sink or the partitions, nothing is written
+            ExportMethodType::Csv | ExportMethodType::ClickHouse => {
+                BufWriter::new(Box::new(std::io::sink()))
+            }
         };
 
+        let protobuf = (export_type == ExportMethodType::Protobuf)
+            .then(|| ProtobufEncoder::new::<T>(&settings, skip_contaminant_features));
+
         OutputWriter {
+            optional_features: BasicFlow::optional_features(&settings),
+            settings,
             write_header,
             skip_contaminant_features,
+            min_packets,
+            dropped_flows: 0,
+            table,
+            clickhouse,
+            protobuf,
+            parquet,
+            config_updates: None,
+            reorder_buffer: None,
+            partitions,
+            scan_detector: None,
+            host_rollup: None,
+            write_retries: 0,
+            write_retry_backoff: Duration::ZERO,
+            down_until: None,
+            clickhouse_output: export_type == ExportMethodType::ClickHouse,
+            dead_letter: None,
             writer,
+            file: synced_file,
             _phantom_data: std::marker::PhantomData,
         }
     }
 
-    pub fn init(&mut self) {
+    pub async fn init(&mut self) {
         debug!("Initializing output writer");
-        if self.write_header {
+        if let Some(clickhouse) = self.clickhouse.as_mut() {
+            // The inserts keep retrying, the server may just not be up yet
+            if let Err(e) = clickhouse.connect().await {
+                error!("{}", e);
+            }
+        } else if let Some(table) = &self.table {
+            // The table is meant for humans, so it always gets its column titles
+            let header = table.header();
+            if let Err(e) = writeln!(self.writer, "{}", header) {
+                error!("Error writing header: {}", e);
+            }
+        } else if self.write_header
+            && self.partitions.is_none()
+            && self.protobuf.is_none()
+            && self.parquet.is_none()
+            && !self.host_rollup.as_ref().is_some_and(HostRollup::replaces_flows)
+        {
             if let Err(e) = self.write_header() {
                 error!("Error writing header: {}", e);
             }
@@ -51,30 +305,764 @@ where
         debug!("Output writer initialized");
     }
 
-    pub fn write_flow(&mut self, flow: T) -> std::io::Result<()> {
-        let flow_str = if self.skip_contaminant_features {
-            flow.dump_without_contamination()
-        } else {
-            flow.dump()
+    /// Applies reloaded filter settings from the given channel to the following flows.
+    pub fn watch_config(&mut self, config_updates: watch::Receiver<ReloadableConfig>) {
+        self.config_updates = Some(config_updates);
+    }
+
+    /// Writes the flows ordered by the timestamp of their last packet within `window` seconds.
+    ///
+    /// A flow is held back until a flow ending `window` seconds after it arrives, so the
+    /// window bounds both the delay and the number of held flows.
+    pub fn reorder_flows(&mut self, window: u64) {
+        self.reorder_buffer = Some(ReorderBuffer::new(window));
+    }
+
+    /// Checks the flows for port and host scans and writes alerts for the scanning sources.
+    ///
+    /// Every flow is checked, also the ones below the minimum packet count, as scans are
+    /// mostly single packet probes.
+    pub fn detect_scans(&mut self, config: ScanDetectionConfig) -> std::io::Result<()> {
+        self.scan_detector = Some(ScanDetector::new(config)?);
+        Ok(())
+    }
+
+    /// Sums the flows up per host over tumbling windows and writes a record per host and window.
+    ///
+    /// Every flow is counted, also the ones below the minimum packet count, so the rollups
+    /// hold all the traffic.
+    pub fn roll_up_hosts(&mut self, config: HostRollupConfig) -> std::io::Result<()> {
+        self.host_rollup = Some(HostRollup::new(Arc::clone(&self.settings), config)?);
+        Ok(())
+    }
+
+    /// Retries a failed write of a flow up to `retries` times, waiting `backoff` milliseconds
+    /// before the first retry and twice as long before every further one.
+    ///
+    /// Once a flow failed after all retries, the following flows aren't retried until the
+    /// next wait of the schedule passed, they go straight to the dead-letter file meanwhile.
+    ///
+    /// The ClickHouse inserts aren't retried here, they have their own `max_retries`.
+    pub fn retry_writes(&mut self, retries: u32, backoff: u64) {
+        self.write_retries = retries;
+        self.write_retry_backoff = Duration::from_millis(backoff);
+    }
+
+    /// Writes the flows that still fail to export after the retries to a dead-letter file, as
+    /// CSV records under the feature header.
+    ///
+    /// With ClickHouse, these are the flows the sinks give up on, e.g. the ones the table
+    /// rejects or the ones pending when the server is still unreachable at shutdown.
+    pub fn dead_letter(&mut self, path: String) -> std::io::Result<()> {
+        let header = self.header();
+        self.dead_letter = Some(DeadLetterFile::new(path, &header)?);
+        if let Some(clickhouse) = self.clickhouse.as_mut() {
+            clickhouse.keep_dropped_rows();
+        }
+        if let Some(partitions) = self.partitions.as_mut() {
+            partitions.keep_dropped_rows();
+        }
+        Ok(())
+    }
+
+    /// Inserts the ClickHouse batches that waited for their `flush_interval` and writes out the
+    /// host rollup window if it ended, so the last flows before a quiet period don't wait for
+    /// more. Called every `TICK_INTERVAL` by the task of the output.
+    pub async fn tick(&mut self) -> std::io::Result<()> {
+        if let Some(host_rollup) = self.host_rollup.as_mut() {
+            host_rollup.tick(Instant::now())?;
+        }
+        if let Some(clickhouse) = self.clickhouse.as_mut() {
+            let flushed = clickhouse.flush_if_due().await;
+            self.dead_letter_dropped_rows()?;
+            flushed?;
+        }
+        if let Some(partitions) = self.partitions.as_mut() {
+            let flushed = partitions.flush_if_due().await;
+            self.dead_letter_dropped_rows()?;
+            flushed?;
+        }
+        Ok(())
+    }
+
+    /// Writes a flow out, or holds it back if the flows are reordered.
+    pub async fn write_flow(&mut self, flow: T) -> Result<(), ExportError> {
+        metrics::record_output_flow();
+        if let Some(config_updates) = self.config_updates.as_mut() {
+            if config_updates.has_changed().unwrap_or(false) {
+                self.min_packets = config_updates.borrow_and_update().min_packets;
+            }
+        }
+        // A failing alerts file doesn't hold back the flows
+        if let Some(scan_detector) = self.scan_detector.as_mut() {
+            if let Err(e) = scan_detector.check_flow(flow.basic_flow()) {
+                error!("Error writing scan alert: {}", e);
+            }
+        }
+        if let Some(host_rollup) = self.host_rollup.as_mut() {
+            if let Err(e) = host_rollup.add_flow(flow.basic_flow()) {
+                error!("Error writing host rollup: {}", e);
+            }
+            if host_rollup.replaces_flows() {
+                return Ok(());
+            }
+        }
+
+        // Flows below the packet threshold are noise (e.g. single SYN probes), drop them silently
+        if flow.basic_flow().get_packet_count() < u64::from(self.min_packets)
+            && !(self.settings.keep_connection_attempts
+                && flow.basic_flow().is_connection_attempt())
+        {
+            self.dropped_flows += 1;
+            return Ok(());
+        }
+        #[cfg(feature = "otel")]
+        crate::otel::record_flow(flow.basic_flow());
+
+        let Some(reorder_buffer) = self.reorder_buffer.as_mut() else {
+            return Ok(self.export_record(&flow).await?);
+        };
+        reorder_buffer.push(flow.basic_flow().last_timestamp, flow);
+        while let Some(flow) = self.reorder_buffer.as_mut().and_then(ReorderBuffer::pop_ready) {
+            self.export_record(&flow).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes a dumped record of a flow out as it is, e.g. one of an earlier export.
+    ///
+    /// The record isn't filtered or reordered, and can't be shown as a table row or be
+    /// partitioned, as those need the flow. Failed writes are retried and dead-lettered like
+    /// the ones of the flows.
+    pub async fn replay_record(&mut self, record: &str) -> Result<(), ExportError> {
+        metrics::record_output_flow();
+        let result = self.write_with_retries(Record::Dumped(record)).await;
+        Ok(self.dead_letter_failed(result, |_| record.to_string())?)
+    }
+
+    /// Writes a flow out with the retries, and to the dead-letter file if they all fail.
+    async fn export_record(&mut self, flow: &T) -> std::io::Result<()> {
+        let result = self.write_with_retries(Record::Flow(flow)).await;
+        self.dead_letter_failed(result, |writer| writer.record(flow))
+    }
+
+    async fn write_with_retries(&mut self, record: Record<'_, T>) -> std::io::Result<()> {
+        let mut retries = if self.clickhouse_output { 0 } else { self.write_retries };
+        if let Some(down_until) = self.down_until {
+            if self.dead_letter.is_some() && Instant::now() < down_until {
+                return Err(std::io::Error::other("the output is down after a failed write"));
+            }
+            // Only a single try checks whether the output is back
+            retries = 0;
+        }
+
+        let mut backoff = self.write_retry_backoff;
+        for _ in 0..retries {
+            match self.write(&record).await {
+                Err(e) => {
+                    warn!("Writing a flow failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_WRITE_RETRY_BACKOFF);
+                }
+                result => {
+                    self.down_until = None;
+                    return result;
+                }
+            }
+        }
+        let result = self.write(&record).await;
+        self.down_until = match result {
+            Err(_) if !self.clickhouse_output => Some(Instant::now() + backoff),
+            _ => None,
+        };
+        result
+    }
+
+    async fn write(&mut self, record: &Record<'_, T>) -> std::io::Result<()> {
+        match record {
+            Record::Flow(flow) => self.write_record(flow).await,
+            Record::Dumped(record) => self.write_dumped(record).await,
+        }
+    }
+
+    /// Writes the record of a flow whose write failed to the dead-letter file, if enabled.
+    fn dead_letter_failed(
+        &mut self,
+        result: std::io::Result<()>,
+        record: impl FnOnce(&Self) -> String,
+    ) -> std::io::Result<()> {
+        self.dead_letter_dropped_rows()?;
+        match result {
+            // A failed ClickHouse insert keeps the flow in its batch
+            Err(e) if self.dead_letter.is_some() && !self.clickhouse_output => {
+                warn!("Writing a flow failed, it's kept in the dead-letter file: {}", e);
+                let record = record(self);
+                self.dead_letter.as_mut().map_or(Ok(()), |dead_letter| dead_letter.write(&record))
+            }
+            result => result,
+        }
+    }
+
+    /// Writes the flows the ClickHouse sinks gave up on to the dead-letter file.
+    fn dead_letter_dropped_rows(&mut self) -> std::io::Result<()> {
+        let Some(dead_letter) = self.dead_letter.as_mut() else {
+            return Ok(());
         };
+        let mut rows = Vec::new();
+        if let Some(clickhouse) = self.clickhouse.as_mut() {
+            rows.extend(clickhouse.take_dropped_rows());
+        }
+        if let Some(partitions) = self.partitions.as_mut() {
+            rows.extend(partitions.take_dropped_rows());
+        }
+        for row in rows {
+            dead_letter.write(&row)?;
+        }
+        Ok(())
+    }
 
-        writeln!(self.writer, "{}", flow_str)
+    /// Writes a flow out in the format of the output method.
+    async fn write_record(&mut self, flow: &T) -> std::io::Result<()> {
+        if let Some(table) = &self.table {
+            let row = format!("{}\n", table.row(flow.basic_flow()));
+            // Show each flow as soon as it completes, a failed row isn't left in the buffer to
+            // be written again with its retry
+            self.writer.flush()?;
+            let stdout = self.writer.get_mut();
+            stdout.write_all(row.as_bytes())?;
+            return stdout.flush();
+        }
+
+        // The typed outputs take the values of the features instead of the dumped record
+        if self.parquet.is_some() || self.partitions.is_some() || self.clickhouse.is_some() {
+            let values = registry::record_values(
+                flow,
+                &self.optional_features,
+                self.skip_contaminant_features,
+            );
+            if let Some(parquet) = self.parquet.as_mut() {
+                return parquet.push(&values);
+            }
+            if let Some(partitions) = self.partitions.as_mut() {
+                return partitions.write(flow.basic_flow(), values).await;
+            }
+            if let Some(clickhouse) = self.clickhouse.as_mut() {
+                return clickhouse.push(values).await;
+            }
+        }
+
+        let flow_str = self.record(flow);
+        self.write_dumped(&flow_str).await
+    }
+
+    /// Writes a dumped record out, to the sinks that don't need the flow itself.
+    async fn write_dumped(&mut self, record: &str) -> std::io::Result<()> {
+        if let Some(parquet) = self.parquet.as_mut() {
+            return parquet.push_dumped(record);
+        }
+        if let Some(protobuf) = self.protobuf.as_mut() {
+            let mut message = Vec::new();
+            protobuf.encode(record, &mut message);
+            return self.writer.write_all(&message);
+        }
+        match self.clickhouse.as_mut() {
+            Some(clickhouse) => clickhouse.push_dumped(record).await,
+            // A single write, so a failed one buffers nothing that its retry would repeat
+            None => self.writer.write_all(format!("{}\n", record).as_bytes()),
+        }
+    }
+
+    /// Writes out the buffered flows without closing the output.
+    ///
+    /// The pending ClickHouse batch is inserted and a CSV file is synced to disk, so the flows
+    /// written so far are durable once this returns.
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(clickhouse) = self.clickhouse.as_mut() {
+            let flushed = clickhouse.flush().await;
+            self.dead_letter_dropped_rows()?;
+            flushed?;
+        }
+        if let Some(partitions) = self.partitions.as_mut() {
+            let flushed = partitions.flush().await;
+            self.dead_letter_dropped_rows()?;
+            flushed?;
+        }
+        if let Some(scan_detector) = self.scan_detector.as_mut() {
+            scan_detector.flush()?;
+        }
+        if let Some(host_rollup) = self.host_rollup.as_mut() {
+            host_rollup.flush()?;
+        }
+        if let Some(parquet) = self.parquet.as_mut() {
+            parquet.flush()?;
+        }
+        self.writer.flush()?;
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+        }
+        Ok(())
     }
 
     /// Flushes the writer and closes the output file
     /// Explicitly called in the main function to ensure all data is written
-    pub fn flush_and_close(&mut self) -> std::io::Result<()> {
-        self.writer.flush() // Ensure all data is written
+    pub async fn flush_and_close(&mut self) -> std::io::Result<()> {
+        // The held back flows are the latest ones, they are written in order
+        while let Some(flow) = self.reorder_buffer.as_mut().and_then(ReorderBuffer::pop) {
+            self.export_record(&flow).await?;
+        }
+        if self.min_packets > 0 || self.dropped_flows > 0 {
+            info!(
+                "Dropped {} flows below the minimum packet count",
+                self.dropped_flows
+            );
+        }
+        if let Some(scan_detector) = self.scan_detector.as_mut() {
+            scan_detector.close()?;
+        }
+        if let Some(host_rollup) = self.host_rollup.as_mut() {
+            host_rollup.close()?;
+        }
+        if metrics::blocked_exports() > 0 {
+            info!(
+                "Exporting was blocked by the output {} times, for {:.4} seconds in total",
+                metrics::blocked_exports(),
+                metrics::blocked_export_time().as_secs_f64()
+            );
+        }
+        if metrics::export_errors() > 0 {
+            error!("Lost {} flows on the way to the output", metrics::export_errors());
+        }
+        let result = if let Some(partitions) = self.partitions.as_mut() {
+            partitions.close().await
+        } else if let Some(parquet) = self.parquet.as_mut() {
+            parquet.close()
+        } else if let Some(clickhouse) = self.clickhouse.as_mut() {
+            let result = clickhouse.flush().await;
+            if result.is_err() {
+                clickhouse.drop_batch();
+            }
+            info!("Inserted {} flows into ClickHouse", clickhouse.inserted_flows());
+            if clickhouse.lost_flows() > 0 {
+                error!("Lost {} flows that couldn't be inserted", clickhouse.lost_flows());
+            }
+            result
+        } else {
+            self.writer.flush() // Ensure all data is written
+        };
+        self.dead_letter_dropped_rows()?;
+        if let Some(dead_letter) = self.dead_letter.as_mut() {
+            dead_letter.close()?;
+        }
+        result
     }
 
     // Private method for writing the header
     fn write_header(&mut self) -> std::io::Result<()> {
         debug!("Writing header to output");
-        let header = if self.skip_contaminant_features {
-            T::get_features_without_contamination()
+        let header = self.header();
+        writeln!(self.writer, "{}", header)
+    }
+
+    /// Returns the settings of the run the writer dumps the flows with.
+    pub fn settings(&self) -> &Arc<FlowSettings> {
+        &self.settings
+    }
+
+    /// Returns the exported features of a flow, in the order of `header`.
+    pub fn record(&self, flow: &T) -> String {
+        registry::record(flow, &self.optional_features, self.skip_contaminant_features)
+    }
+
+    /// Returns the names of the exported features, in the order of the dumped flows.
+    pub fn header(&self) -> String {
+        registry::header::<T>(&self.settings, self.skip_contaminant_features)
+    }
+}
+
+/// Formatting of flows as aligned table rows for interactive inspection.
+/// Orders the exported flows by their end time within a window, without buffering all of them.
+struct ReorderBuffer<T> {
+    window: TimeDelta,
+    /// The held flows by their end time and arrival, so equal end times keep their order.
+    flows: BTreeMap<(DateTime<Utc>, u64), T>,
+    arrivals: u64,
+    /// The latest end time of the flows so far.
+    latest: Option<DateTime<Utc>>,
+}
+
+impl<T> ReorderBuffer<T> {
+    fn new(window: u64) -> Self {
+        ReorderBuffer {
+            window: TimeDelta::seconds(window as i64),
+            flows: BTreeMap::new(),
+            arrivals: 0,
+            latest: None,
+        }
+    }
+
+    fn push(&mut self, end: DateTime<Utc>, flow: T) {
+        self.flows.insert((end, self.arrivals), flow);
+        self.arrivals += 1;
+        self.latest = self.latest.max(Some(end));
+    }
+
+    /// Takes the earliest flow if it ended at least the window before the latest one.
+    fn pop_ready(&mut self) -> Option<T> {
+        let latest = self.latest?;
+        let (end, _) = self.flows.keys().next()?;
+        if latest - *end < self.window {
+            return None;
+        }
+        self.pop()
+    }
+
+    /// Takes the earliest flow regardless of the window.
+    fn pop(&mut self) -> Option<T> {
+        self.flows.pop_first().map(|(_, flow)| flow)
+    }
+}
+
+struct TableStyle {
+    /// Whether to color-code rows by protocol, only done when writing to a terminal.
+    colored: bool,
+}
+
+impl TableStyle {
+    fn header(&self) -> String {
+        let header = format!(
+            "{:<6} {:>45} {:>45} {:>12} {:>9} {:>10}",
+            "PROTO", "SOURCE", "DESTINATION", "DURATION", "PACKETS", "BYTES"
+        );
+        if self.colored {
+            header.bold().to_string()
         } else {
-            T::get_features()
+            header
+        }
+    }
+
+    fn row(&self, flow: &BasicFlow) -> String {
+        let endpoints = flow.settings.endpoint_features(flow);
+        let row = format!(
+            "{:<6} {:>45} {:>45} {:>12} {:>9} {:>10}",
+            protocol_name(flow.protocol),
+            format_endpoint(&endpoints.ip_source, endpoints.port_source),
+            format_endpoint(&endpoints.ip_destination, endpoints.port_destination),
+            format_duration(flow.get_flow_duration_usec()),
+            flow.get_packet_count(),
+            format_bytes(flow.get_bytes()),
+        );
+        if self.colored {
+            row.with(protocol_color(flow.protocol)).to_string()
+        } else {
+            row
+        }
+    }
+}
+
+fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "ICMP".to_string(),
+        6 => "TCP".to_string(),
+        17 => "UDP".to_string(),
+        33 => "DCCP".to_string(),
+        58 => "ICMPv6".to_string(),
+        132 => "SCTP".to_string(),
+        _ => protocol.to_string(),
+    }
+}
+
+fn protocol_color(protocol: u8) -> Color {
+    match protocol {
+        6 => Color::Cyan,
+        17 => Color::Green,
+        1 | 58 => Color::Yellow,
+        _ => Color::Magenta,
+    }
+}
+
+fn format_endpoint(ip: &IpAddr, port: u16) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("{}:{}", ip, port),
+        IpAddr::V6(ip) => format!("[{}]:{}", ip, port),
+    }
+}
+
+fn format_duration(duration_usec: f64) -> String {
+    if duration_usec < 1_000.0 {
+        format!("{:.0}us", duration_usec)
+    } else if duration_usec < 1_000_000.0 {
+        format!("{:.2}ms", duration_usec / 1_000.0)
+    } else {
+        format!("{:.2}s", duration_usec / 1_000_000.0)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, BufWriter, Write},
+        net::{IpAddr, Ipv4Addr},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{OutputWriter, ReorderBuffer};
+    use crate::{
+        args::{ClickHouseConfig, ExportMethodType, FlowType},
+        flows::{basic_flow::BasicFlow, flow::Flow, settings::FlowSettings},
+    };
+
+    /// A sink whose writes fail until the given number of failures is used up.
+    struct FailingWriter {
+        failures: Arc<AtomicU32>,
+        /// The bytes of the writes that didn't fail.
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                return Err(io::Error::other("disk full"));
+            }
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_retries_and_dead_letter() {
+        let path = std::env::temp_dir().join("rustiflow_test_output_dead_letter.csv");
+        let _ = std::fs::remove_file(&path);
+        let failures = Arc::new(AtomicU32::new(0));
+        let mut output_writer = OutputWriter::<BasicFlow>::new(
+            Arc::default(),
+            FlowType::Basic,
+            ExportMethodType::Print,
+            false,
+            false,
+            0,
+            None,
+            ClickHouseConfig::default(),
+            None,
+        );
+        // Unbuffered, so every record reaches the failing sink right away
+        output_writer.writer = BufWriter::with_capacity(
+            0,
+            Box::new(FailingWriter {
+                failures: failures.clone(),
+                written: Arc::default(),
+            }),
+        );
+        output_writer.retry_writes(2, 1);
+        output_writer
+            .dead_letter(path.to_str().unwrap().to_string())
+            .unwrap();
+        let flow = |source_port| {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+            let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+            BasicFlow::new(Arc::default(), String::new(), ip, source_port, ip, 80, 6, start)
         };
-        writeln!(self.writer, "{}", header)
+
+        // Two failures are covered by the retries, the third one isn't
+        failures.store(2, Ordering::Relaxed);
+        output_writer.write_flow(flow(1000)).await.unwrap();
+        assert_eq!(failures.load(Ordering::Relaxed), 0);
+        failures.store(3, Ordering::Relaxed);
+        output_writer.write_flow(flow(2000)).await.unwrap();
+        output_writer.flush_and_close().await.unwrap();
+
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = dead_letters.lines().collect();
+        assert_eq!(lines[0], output_writer.header());
+        assert_eq!(lines[1..], [output_writer.record(&flow(2000))]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_while_output_down() {
+        let path = std::env::temp_dir().join("rustiflow_test_output_down.csv");
+        let _ = std::fs::remove_file(&path);
+        let failures = Arc::new(AtomicU32::new(0));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut output_writer = OutputWriter::<BasicFlow>::new(
+            Arc::default(),
+            FlowType::Basic,
+            ExportMethodType::Print,
+            false,
+            false,
+            0,
+            None,
+            ClickHouseConfig::default(),
+            None,
+        );
+        output_writer.writer = BufWriter::with_capacity(
+            0,
+            Box::new(FailingWriter {
+                failures: failures.clone(),
+                written: written.clone(),
+            }),
+        );
+        output_writer.retry_writes(1, 200);
+        output_writer
+            .dead_letter(path.to_str().unwrap().to_string())
+            .unwrap();
+        let flow = |source_port| {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+            let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+            BasicFlow::new(Arc::default(), String::new(), ip, source_port, ip, 80, 6, start)
+        };
+
+        // The first flow fails after its retry, the next one isn't even tried for 400 ms
+        failures.store(2, Ordering::Relaxed);
+        output_writer.write_flow(flow(1000)).await.unwrap();
+        output_writer.write_flow(flow(2000)).await.unwrap();
+        assert!(written.lock().unwrap().is_empty());
+
+        // Afterwards, a single try finds the output back
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        output_writer.write_flow(flow(3000)).await.unwrap();
+        output_writer.flush_and_close().await.unwrap();
+
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert_eq!(written, format!("{}\n", output_writer.record(&flow(3000))));
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = dead_letters.lines().collect();
+        let expected = [output_writer.record(&flow(1000)), output_writer.record(&flow(2000))];
+        assert_eq!(lines[1..], expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_min_packets() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let settings = FlowSettings {
+            keep_connection_attempts: true,
+            ..Default::default()
+        };
+        let mut output_writer = OutputWriter::<BasicFlow>::new(
+            Arc::new(settings),
+            FlowType::Basic,
+            ExportMethodType::Print,
+            false,
+            false,
+            3,
+            None,
+            ClickHouseConfig::default(),
+            None,
+        );
+        output_writer.writer = BufWriter::with_capacity(
+            0,
+            Box::new(FailingWriter {
+                failures: Arc::default(),
+                written: written.clone(),
+            }),
+        );
+        let flow = |source_port, fwd_packets, fwd_syn_flags| {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+            let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+            let mut flow =
+                BasicFlow::new(Arc::default(), String::new(), ip, source_port, ip, 80, 6, start);
+            flow.fwd_packet_count = fwd_packets;
+            flow.fwd_syn_flag_count = fwd_syn_flags;
+            flow
+        };
+
+        // Flows below the threshold are dropped and counted, unless they are connection attempts
+        output_writer.write_flow(flow(1000, 2, 0)).await.unwrap();
+        output_writer.write_flow(flow(2000, 3, 0)).await.unwrap();
+        output_writer.write_flow(flow(3000, 1, 1)).await.unwrap();
+        output_writer.write_flow(flow(4000, 1, 0)).await.unwrap();
+        assert_eq!(output_writer.dropped_flows, 2);
+
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        let expected = [
+            output_writer.record(&flow(2000, 3, 0)),
+            output_writer.record(&flow(3000, 1, 1)),
+        ];
+        assert_eq!(lines, expected);
+    }
+
+    #[tokio::test]
+    async fn test_retried_buffered_writes() {
+        let failures = Arc::new(AtomicU32::new(0));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut output_writer = OutputWriter::<BasicFlow>::new(
+            Arc::default(),
+            FlowType::Basic,
+            ExportMethodType::Print,
+            false,
+            false,
+            0,
+            None,
+            ClickHouseConfig::default(),
+            None,
+        );
+        output_writer.writer = BufWriter::new(Box::new(FailingWriter {
+            failures: failures.clone(),
+            written: written.clone(),
+        }));
+        output_writer.retry_writes(1, 1);
+
+        // The second record fills the default buffer of 8 KiB, so writing it out for the
+        // newline fails, after the record itself was buffered
+        let first = "a".repeat(4095);
+        let second = "b".repeat(4096);
+        output_writer.replay_record(&first).await.unwrap();
+        failures.store(1, Ordering::Relaxed);
+        output_writer.replay_record(&second).await.unwrap();
+        assert_eq!(failures.load(Ordering::Relaxed), 0);
+        output_writer.flush_and_close().await.unwrap();
+
+        // The retry doesn't write the record twice, glued to itself
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert_eq!(written, format!("{}\n{}\n", first, second));
+    }
+
+    #[test]
+    fn test_reorder_buffer() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut reorder_buffer = ReorderBuffer::new(10);
+        let mut written = Vec::new();
+
+        // Flows ending out of order by a few seconds, e.g. exported by different shards
+        for (name, end) in [("a", 5), ("b", 2), ("c", 5), ("d", 12), ("e", 9), ("f", 16)] {
+            reorder_buffer.push(start + TimeDelta::seconds(end), name);
+            while let Some(flow) = reorder_buffer.pop_ready() {
+                written.push(flow);
+            }
+        }
+        // Only the flows ending 10 seconds before the latest one are written yet
+        assert_eq!(written, ["b", "a", "c"]);
+
+        while let Some(flow) = reorder_buffer.pop() {
+            written.push(flow);
+        }
+        assert_eq!(written, ["b", "a", "c", "e", "d", "f"]);
     }
 }
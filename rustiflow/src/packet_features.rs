@@ -1,15 +1,28 @@
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
-use common::{EbpfEventIpv4, EbpfEventIpv6};
+use common::{
+    parse::{
+        parse_ipv4_header, parse_ipv6_header, parse_transport_header, tcp_options_mask,
+        ParseError, IPV4_MIN_HEADER_LENGTH, TCP, TCP_MIN_HEADER_LENGTH,
+    },
+    EbpfEventIpv4, EbpfEventIpv6,
+};
 use log::debug;
+
+use crate::{
+    args::FlowKeyStrategy,
+    expectations::{parse_ftp_expectation, ExpectedFlow},
+    flows::{settings::FlowSettings, signatures::PayloadSnapshot},
+    metrics,
+};
 use pnet::packet::{
-    icmp::IcmpPacket,
     ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
     ipv4::Ipv4Packet,
     ipv6::Ipv6Packet,
-    tcp::TcpPacket,
-    udp::UdpPacket,
     Packet,
 };
 
@@ -23,6 +36,9 @@ const URG_FLAG: u8 = 0b00100000;
 const ECE_FLAG: u8 = 0b01000000;
 const CWE_FLAG: u8 = 0b10000000;
 
+// Upper bound of IPv6 extension headers to walk before giving up on a packet
+const MAX_IPV6_EXTENSION_HEADERS: u8 = 8;
+
 pub struct PacketFeatures {
     pub source_ip: IpAddr,
     pub destination_ip: IpAddr,
@@ -44,16 +60,36 @@ pub struct PacketFeatures {
     pub window_size: u16,
     pub sequence_number: u32,
     pub sequence_number_ack: u32,
+    pub extension_header_count: u8,
+    /// The IPv4 TTL or the IPv6 hop limit.
+    pub ttl: u8,
+    /// The bytes of the IPv4 options or of the IPv6 extension headers.
+    pub ip_options_length: u16,
+    /// The flags of the IPv4 header, e.g. `IPV4_DONT_FRAGMENT`, 0 for IPv6.
+    pub ip_flags: u8,
+    /// The TCP option kinds of a SYN, see `parse::tcp_option_bit`, 0 for all other packets.
+    pub tcp_options_mask: u8,
+    /// The IPv6 flow label, 0 if the sender didn't set one and for IPv4.
+    pub flow_label: u32,
+    /// The connection this packet announces on a control channel, if expectations are tracked.
+    pub expected_flow: Option<ExpectedFlow>,
+    /// The outer VLAN id of a tagged frame, the eBPF events don't carry it.
+    pub vlan_id: Option<u16>,
+    /// The first bytes of the payload, if the flows keep them, see `settings.payload_snapshots`.
+    pub payload_snapshot: Option<PayloadSnapshot>,
+    /// The time from the kernel capturing the packet to userspace reading it, set by the live
+    /// captures. Their `timestamp` is when the kernel saw the packet.
+    pub capture_latency: Option<Duration>,
 }
 
 impl PacketFeatures {
-    // Constructor to create PacketFeatures from EbpfEventIpv4
+    // Constructor to create PacketFeatures from EbpfEventIpv4, the event fields are in network byte order
     pub fn from_ebpf_event_ipv4(event: &EbpfEventIpv4) -> Self {
         PacketFeatures {
-            source_ip: IpAddr::V4(Ipv4Addr::from(event.ipv4_source.to_be())),
-            destination_ip: IpAddr::V4(Ipv4Addr::from(event.ipv4_destination.to_be())),
-            source_port: event.port_source,
-            destination_port: event.port_destination,
+            source_ip: IpAddr::V4(Ipv4Addr::from(u32::from_be(event.ipv4_source))),
+            destination_ip: IpAddr::V4(Ipv4Addr::from(u32::from_be(event.ipv4_destination))),
+            source_port: u16::from_be(event.port_source),
+            destination_port: u16::from_be(event.port_destination),
             protocol: event.protocol,
             timestamp: chrono::Utc::now(),
             fin_flag: get_tcp_flag(event.combined_flags, FIN_FLAG),
@@ -64,22 +100,32 @@ impl PacketFeatures {
             urg_flag: get_tcp_flag(event.combined_flags, URG_FLAG),
             cwe_flag: get_tcp_flag(event.combined_flags, CWE_FLAG),
             ece_flag: get_tcp_flag(event.combined_flags, ECE_FLAG),
-            data_length: event.data_length,
+            data_length: u16::from_be(event.data_length),
             header_length: event.header_length,
-            length: event.length,
-            window_size: event.window_size,
-            sequence_number: event.sequence_number,
-            sequence_number_ack: event.sequence_number_ack,
+            length: u16::from_be(event.length),
+            window_size: u16::from_be(event.window_size),
+            sequence_number: u32::from_be(event.sequence_number),
+            sequence_number_ack: u32::from_be(event.sequence_number_ack),
+            extension_header_count: 0,
+            ttl: event.ttl,
+            ip_options_length: u16::from_be(event.ip_options_length),
+            ip_flags: event.ip_flags,
+            tcp_options_mask: event.tcp_options_mask,
+            flow_label: 0,
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
         }
     }
 
-    // Constructor to create PacketFeatures from EbpfEventIpv6
+    // Constructor to create PacketFeatures from EbpfEventIpv6, the event fields are in network byte order
     pub fn from_ebpf_event_ipv6(event: &EbpfEventIpv6) -> Self {
         PacketFeatures {
-            source_ip: IpAddr::V6(Ipv6Addr::from(event.ipv6_source.to_be())),
-            destination_ip: IpAddr::V6(Ipv6Addr::from(event.ipv6_destination.to_be())),
-            source_port: event.port_source,
-            destination_port: event.port_destination,
+            source_ip: IpAddr::V6(Ipv6Addr::from(u128::from_be(event.ipv6_source))),
+            destination_ip: IpAddr::V6(Ipv6Addr::from(u128::from_be(event.ipv6_destination))),
+            source_port: u16::from_be(event.port_source),
+            destination_port: u16::from_be(event.port_destination),
             protocol: event.protocol,
             timestamp: chrono::Utc::now(),
             fin_flag: get_tcp_flag(event.combined_flags, FIN_FLAG),
@@ -90,184 +136,570 @@ impl PacketFeatures {
             urg_flag: get_tcp_flag(event.combined_flags, URG_FLAG),
             cwe_flag: get_tcp_flag(event.combined_flags, CWE_FLAG),
             ece_flag: get_tcp_flag(event.combined_flags, ECE_FLAG),
-            data_length: event.data_length,
+            data_length: u16::from_be(event.data_length),
             header_length: event.header_length,
-            length: event.length,
-            window_size: event.window_size,
-            sequence_number: event.sequence_number,
-            sequence_number_ack: event.sequence_number_ack,
+            length: u16::from_be(event.length),
+            window_size: u16::from_be(event.window_size),
+            sequence_number: u32::from_be(event.sequence_number),
+            sequence_number_ack: u32::from_be(event.sequence_number_ack),
+            extension_header_count: event.extension_header_count,
+            ttl: event.hop_limit,
+            ip_options_length: u16::from_be(event.ip_options_length),
+            ip_flags: 0,
+            tcp_options_mask: event.tcp_options_mask,
+            flow_label: u32::from_be(event.flow_label),
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
         }
     }
 
     // Constructor to create PacketFeatures from an IPv4 packet
-    pub fn from_ipv4_packet(packet: &Ipv4Packet, timestamp: DateTime<Utc>) -> Option<Self> {
-        extract_packet_features_transport(
-            packet.get_source().into(),
-            packet.get_destination().into(),
-            packet.get_next_level_protocol(),
+    pub fn from_ipv4_packet(
+        packet: &Ipv4Packet,
+        timestamp: DateTime<Utc>,
+        settings: &FlowSettings,
+    ) -> Option<Self> {
+        let header = match parse_ipv4_header(packet.packet()) {
+            Ok(header) if usize::from(header.header_length) <= packet.packet().len() => header,
+            _ => return malformed("IPv4 header length beyond the captured bytes"),
+        };
+        // Segmentation offload leaves the total length at 0
+        if header.total_length != 0 && header.total_length < u16::from(header.header_length) {
+            return malformed("IPv4 total length shorter than the header");
+        }
+        // Non-first fragments don't carry a transport header
+        if header.fragment_offset != 0 {
+            return None;
+        }
+
+        let mut packet_features = extract_packet_features_transport(
+            Ipv4Addr::from(header.source).into(),
+            Ipv4Addr::from(header.destination).into(),
+            header.protocol,
             timestamp,
-            packet.get_total_length(),
+            header.total_length,
             packet.payload(),
-        )
+            settings,
+        )?;
+        packet_features.ttl = header.ttl;
+        packet_features.ip_options_length =
+            u16::from(header.header_length) - IPV4_MIN_HEADER_LENGTH as u16;
+        packet_features.ip_flags = header.flags;
+        Some(packet_features)
     }
 
     // Constructor to create PacketFeatures from an IPv6 packet
-    pub fn from_ipv6_packet(packet: &Ipv6Packet, timestamp: DateTime<Utc>) -> Option<Self> {
-        extract_packet_features_transport(
-            packet.get_source().into(),
-            packet.get_destination().into(),
-            packet.get_next_header(),
+    pub fn from_ipv6_packet(
+        packet: &Ipv6Packet,
+        timestamp: DateTime<Utc>,
+        settings: &FlowSettings,
+    ) -> Option<Self> {
+        let Ok(header) = parse_ipv6_header(packet.packet()) else {
+            return malformed("IPv6 packet with another IP version");
+        };
+        let next_header = IpNextHeaderProtocol::new(header.next_header);
+        let (protocol, transport_packet, extension_header_count) =
+            skip_ipv6_extension_headers(next_header, packet.payload())?;
+        let mut packet_features = extract_packet_features_transport(
+            Ipv6Addr::from(header.source).into(),
+            Ipv6Addr::from(header.destination).into(),
+            protocol.0,
             timestamp,
-            packet.packet().len() as u16,
-            packet.payload(),
-        )
+            saturating_length(packet.packet().len()),
+            transport_packet,
+            settings,
+        )?;
+        packet_features.extension_header_count = extension_header_count;
+        packet_features.ttl = header.hop_limit;
+        packet_features.flow_label = header.flow_label;
+        packet_features.ip_options_length =
+            saturating_length(packet.payload().len() - transport_packet.len());
+        Some(packet_features)
     }
 
-    /// Generates a flow key based on IPs, ports, and protocol
-    pub fn flow_key(&self) -> String {
-        format!(
-            "{}:{}-{}:{}-{}",
-            self.source_ip,
-            self.source_port,
-            self.destination_ip,
-            self.destination_port,
-            self.protocol
+    /// Combines the TCP flags into a single byte, using the bit positions of the TCP header
+    pub fn combined_flags(&self) -> u8 {
+        (self.fin_flag * FIN_FLAG)
+            | (self.syn_flag * SYN_FLAG)
+            | (self.rst_flag * RST_FLAG)
+            | (self.psh_flag * PSH_FLAG)
+            | (self.ack_flag * ACK_FLAG)
+            | (self.urg_flag * URG_FLAG)
+            | (self.ece_flag * ECE_FLAG)
+            | (self.cwe_flag * CWE_FLAG)
+    }
+
+    /// Generates the flow key of the packet's direction, with the fields of the flow key strategy
+    pub fn flow_key(&self, strategy: FlowKeyStrategy) -> String {
+        self.format_flow_key(
+            strategy,
+            (self.source_ip, self.source_port),
+            (self.destination_ip, self.destination_port),
         )
     }
 
-    /// Generates a flow key based on IPs, ports, and protocol in the reverse direction
-    pub fn flow_key_bwd(&self) -> String {
-        format!(
-            "{}:{}-{}:{}-{}",
-            self.destination_ip,
-            self.destination_port,
-            self.source_ip,
-            self.source_port,
-            self.protocol
+    /// Generates the flow key of the reverse direction, with the fields of the flow key strategy
+    pub fn flow_key_bwd(&self, strategy: FlowKeyStrategy) -> String {
+        self.format_flow_key(
+            strategy,
+            (self.destination_ip, self.destination_port),
+            (self.source_ip, self.source_port),
         )
     }
 
-    /// Generates a biflow key
-    pub fn biflow_key(&self) -> String {
+    /// Generates a biflow key, the same for both directions, with the fields of the flow key
+    /// strategy
+    pub fn biflow_key(&self, strategy: FlowKeyStrategy) -> String {
         // Create tuples of (IP, port) for comparison
-        let src = (&self.source_ip, self.source_port);
-        let dst = (&self.destination_ip, self.destination_port);
+        let src = (self.source_ip, self.source_port);
+        let dst = (self.destination_ip, self.destination_port);
 
-        // Determine the correct order (src < dst)
-        if src < dst {
-            format!(
-                "{}:{}-{}:{}-{}",
-                self.source_ip,
-                self.source_port,
-                self.destination_ip,
-                self.destination_port,
-                self.protocol
-            )
+        // Determine the correct order (src < dst), by the IPs only if the ports aren't keyed
+        let ordered = if strategy.has_ports() {
+            src < dst
+        } else {
+            src.0 < dst.0
+        };
+        if ordered {
+            self.format_flow_key(strategy, src, dst)
         } else {
             // If destination IP/port is "smaller", swap the order
-            format!(
+            self.format_flow_key(strategy, dst, src)
+        }
+    }
+
+    /// Formats a flow key from the given endpoints, see `FlowKeyStrategy`.
+    fn format_flow_key(
+        &self,
+        strategy: FlowKeyStrategy,
+        source: (IpAddr, u16),
+        destination: (IpAddr, u16),
+    ) -> String {
+        match strategy {
+            FlowKeyStrategy::FiveTuple => format!(
                 "{}:{}-{}:{}-{}",
-                self.destination_ip,
-                self.destination_port,
-                self.source_ip,
-                self.source_port,
-                self.protocol
-            )
+                source.0, source.1, destination.0, destination.1, self.protocol
+            ),
+            FlowKeyStrategy::FiveTupleVlan => format!(
+                "{}:{}-{}:{}-{}-{}",
+                source.0,
+                source.1,
+                destination.0,
+                destination.1,
+                self.protocol,
+                self.vlan_id.unwrap_or(0)
+            ),
+            FlowKeyStrategy::FiveTupleFlowLabel => format!(
+                "{}:{}-{}:{}-{}-{}",
+                source.0, source.1, destination.0, destination.1, self.protocol, self.flow_label
+            ),
+            FlowKeyStrategy::ThreeTuple => {
+                format!("{}-{}-{}", source.0, destination.0, self.protocol)
+            }
+            FlowKeyStrategy::HostPair => format!("{}-{}", source.0, destination.0),
         }
     }
 }
 
+/// Reverses a flow key, so it is the key of the other direction.
+///
+/// The endpoints are the first two parts of every flow key strategy.
+pub fn reverse_flow_key(flow_key: &str) -> String {
+    let mut parts = flow_key.splitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(source), Some(destination), Some(rest)) => {
+            format!("{}-{}-{}", destination, source, rest)
+        }
+        (Some(source), Some(destination), None) => format!("{}-{}", destination, source),
+        _ => flow_key.to_string(),
+    }
+}
+
+/// Converts a captured length to the `u16` of the length features.
+///
+/// Jumbo frames fit easily, only packets merged by segmentation offload beyond the 64 KiB
+/// of the IP length field are capped instead of wrapping around.
+fn saturating_length(length: usize) -> u16 {
+    u16::try_from(length).unwrap_or(u16::MAX)
+}
+
 fn get_tcp_flag(value: u8, flag: u8) -> u8 {
     ((value & flag) != 0) as u8
 }
 
+/// Walks the IPv6 extension header chain to find the transport header.
+///
+/// ### Arguments
+///
+/// * `next_header` - The next header field of the IPv6 header.
+/// * `payload` - The payload of the IPv6 packet.
+///
+/// ### Returns
+///
+/// The transport protocol, the transport packet and the number of skipped extension headers.
+/// Returns `None` for truncated or overly long chains and for non-first fragments,
+/// as those don't carry a transport header.
+fn skip_ipv6_extension_headers(
+    mut next_header: IpNextHeaderProtocol,
+    mut payload: &[u8],
+) -> Option<(IpNextHeaderProtocol, &[u8], u8)> {
+    for count in 0..MAX_IPV6_EXTENSION_HEADERS {
+        let header_length = match next_header {
+            IpNextHeaderProtocols::Hopopt
+            | IpNextHeaderProtocols::Ipv6Route
+            | IpNextHeaderProtocols::Ipv6Opts => {
+                payload.get(1).map(|length| (usize::from(*length) + 1) * 8)
+            }
+            IpNextHeaderProtocols::Ipv6Frag => match payload.get(2..4) {
+                Some(offset) if u16::from_be_bytes([offset[0], offset[1]]) >> 3 != 0 => {
+                    return None;
+                }
+                Some(_) => Some(8),
+                None => None,
+            },
+            IpNextHeaderProtocols::Ah => payload.get(1).map(|length| (usize::from(*length) + 2) * 4),
+            _ => return Some((next_header, payload, count)),
+        };
+        let Some((next, rest)) = header_length
+            .and_then(|header_length| Some((*payload.first()?, payload.get(header_length..)?)))
+        else {
+            return malformed("IPv6 extension header beyond the captured bytes");
+        };
+        next_header = IpNextHeaderProtocol::new(next);
+        payload = rest;
+    }
+    malformed("too many IPv6 extension headers")
+}
+
+/// Counts a packet whose headers are truncated or inconsistent and skips it.
+///
+/// Such packets are crafted or cut off by the capture, their features would be nonsense.
+pub(crate) fn malformed<T>(reason: &str) -> Option<T> {
+    debug!("Skipping a malformed packet: {}", reason);
+    metrics::add_malformed_packets(1);
+    None
+}
+
+/// Extracts the features of a packet from its transport header with the parser shared with
+/// the eBPF programs, so the features are the same for every capture method.
 fn extract_packet_features_transport(
     source_ip: IpAddr,
     destination_ip: IpAddr,
-    protocol: IpNextHeaderProtocol,
+    protocol: u8,
     timestamp: DateTime<Utc>,
     total_length: u16,
     packet: &[u8],
+    settings: &FlowSettings,
 ) -> Option<PacketFeatures> {
-    match protocol {
-        IpNextHeaderProtocols::Tcp => {
-            let tcp_packet = TcpPacket::new(packet)?;
-            Some(PacketFeatures {
-                source_ip,
-                destination_ip,
-                source_port: tcp_packet.get_source(),
-                destination_port: tcp_packet.get_destination(),
-                protocol: protocol.0,
-                timestamp,
-                fin_flag: get_tcp_flag(tcp_packet.get_flags(), FIN_FLAG),
-                syn_flag: get_tcp_flag(tcp_packet.get_flags(), SYN_FLAG),
-                rst_flag: get_tcp_flag(tcp_packet.get_flags(), RST_FLAG),
-                psh_flag: get_tcp_flag(tcp_packet.get_flags(), PSH_FLAG),
-                ack_flag: get_tcp_flag(tcp_packet.get_flags(), ACK_FLAG),
-                urg_flag: get_tcp_flag(tcp_packet.get_flags(), URG_FLAG),
-                cwe_flag: get_tcp_flag(tcp_packet.get_flags(), CWE_FLAG),
-                ece_flag: get_tcp_flag(tcp_packet.get_flags(), ECE_FLAG),
-                data_length: tcp_packet.payload().len() as u16,
-                header_length: (tcp_packet.get_data_offset() * 4) as u8,
-                length: total_length,
-                window_size: tcp_packet.get_window(),
-                sequence_number: tcp_packet.get_sequence(),
-                sequence_number_ack: tcp_packet.get_acknowledgement(),
-            })
-        }
-        IpNextHeaderProtocols::Udp => {
-            let udp_packet = UdpPacket::new(packet)?;
-            Some(PacketFeatures {
-                source_ip,
-                destination_ip,
-                source_port: udp_packet.get_source(),
-                destination_port: udp_packet.get_destination(),
-                protocol: protocol.0,
-                timestamp,
-                fin_flag: 0,
-                syn_flag: 0,
-                rst_flag: 0,
-                psh_flag: 0,
-                ack_flag: 0,
-                urg_flag: 0,
-                cwe_flag: 0,
-                ece_flag: 0,
-                data_length: udp_packet.payload().len() as u16,
-                header_length: 8, // Fixed header size for UDP
-                length: total_length,
-                window_size: 0,         // No window size for UDP
-                sequence_number: 0,     // No sequence number for UDP
-                sequence_number_ack: 0, // No sequence number ACK for UDP
-            })
+    let header = match parse_transport_header(protocol, packet) {
+        Ok(header) => header,
+        Err(ParseError::Unsupported) => {
+            debug!("Unsupported protocol in packet!");
+            return None;
         }
-        IpNextHeaderProtocols::Icmp | IpNextHeaderProtocols::Icmpv6 => {
-            let icmp_packet = IcmpPacket::new(packet)?;
-            Some(PacketFeatures {
+        Err(ParseError::Truncated) => return malformed("truncated transport header"),
+        Err(ParseError::Invalid) => return malformed("transport header with an impossible length"),
+    };
+    // The options must fit into the captured bytes, the SCTP chunks count as payload
+    let Some(payload) = packet.get(usize::from(header.header_length)..) else {
+        return malformed("transport header beyond the captured bytes");
+    };
+
+    let flags = header.combined_flags;
+    // Only the SYNs carry the options the stacks are told apart by
+    let options_mask = if protocol == TCP && get_tcp_flag(flags, SYN_FLAG) == 1 {
+        tcp_options_mask(&packet[TCP_MIN_HEADER_LENGTH..usize::from(header.header_length)])
+    } else {
+        0
+    };
+    Some(PacketFeatures {
+        source_ip,
+        destination_ip,
+        source_port: header.source_port,
+        destination_port: header.destination_port,
+        protocol,
+        timestamp,
+        fin_flag: get_tcp_flag(flags, FIN_FLAG),
+        syn_flag: get_tcp_flag(flags, SYN_FLAG),
+        rst_flag: get_tcp_flag(flags, RST_FLAG),
+        psh_flag: get_tcp_flag(flags, PSH_FLAG),
+        ack_flag: get_tcp_flag(flags, ACK_FLAG),
+        urg_flag: get_tcp_flag(flags, URG_FLAG),
+        cwe_flag: get_tcp_flag(flags, CWE_FLAG),
+        ece_flag: get_tcp_flag(flags, ECE_FLAG),
+        data_length: saturating_length(payload.len()),
+        header_length: header.header_length,
+        length: total_length,
+        window_size: header.window_size,
+        sequence_number: header.sequence_number,
+        sequence_number_ack: header.sequence_number_ack,
+        extension_header_count: 0,
+        ttl: 0,
+        ip_options_length: 0,
+        ip_flags: 0,
+        tcp_options_mask: options_mask,
+        flow_label: 0,
+        expected_flow: if protocol == TCP && settings.track_expectations {
+            parse_ftp_expectation(
                 source_ip,
+                header.source_port,
                 destination_ip,
-                source_port: 0,
-                destination_port: 0,
-                protocol: protocol.0,
-                timestamp,
-                fin_flag: 0,
-                syn_flag: 0,
-                rst_flag: 0,
-                psh_flag: 0,
-                ack_flag: 0,
-                urg_flag: 0,
-                cwe_flag: 0,
-                ece_flag: 0,
-                data_length: icmp_packet.payload().len() as u16,
-                header_length: 8, // Fixed header size for ICMP
-                length: total_length,
-                window_size: 0,         // No window size for ICMP
-                sequence_number: 0,     // No sequence number for ICMP
-                sequence_number_ack: 0, // No sequence number ACK for ICMP
-            })
-        }
-        _ => {
-            debug!("Unsupported protocol in packet!");
+                header.destination_port,
+                payload,
+            )
+        } else {
             None
+        },
+        vlan_id: None,
+        payload_snapshot: (settings.payload_snapshots && !payload.is_empty())
+            .then(|| PayloadSnapshot::new(payload)),
+        capture_latency: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use chrono::DateTime;
+    use common::{parse::IPV4_DONT_FRAGMENT, EbpfEventIpv4, EbpfEventIpv6};
+    use pnet::packet::ipv6::Ipv6Packet;
+
+    use super::{reverse_flow_key, PacketFeatures};
+    use crate::{args::FlowKeyStrategy, flows::settings::FlowSettings};
+
+    /// Builds an IPv6 packet from ::1 to ::2 with the given extension headers and a TCP SYN from 443 to 51000.
+    fn setup_ipv6_packet(first_next_header: u8, extension_headers: &[u8]) -> Vec<u8> {
+        let tcp_header: [u8; 20] = [
+            0x01, 0xbb, 0xc7, 0x38, // ports 443 -> 51000
+            0x00, 0x00, 0x00, 0x01, // sequence number
+            0x00, 0x00, 0x00, 0x00, // acknowledgement number
+            0x50, 0x02, 0xff, 0xff, // data offset 5, SYN, window
+            0x00, 0x00, 0x00, 0x00, // checksum, urgent pointer
+        ];
+        let payload_length = (extension_headers.len() + tcp_header.len()) as u16;
+
+        // Flow label 0xabcde
+        let mut packet = vec![0x60, 0x0a, 0xbc, 0xde];
+        packet.extend_from_slice(&payload_length.to_be_bytes());
+        packet.extend_from_slice(&[first_next_header, 64]);
+        packet.extend_from_slice(&1u128.to_be_bytes());
+        packet.extend_from_slice(&2u128.to_be_bytes());
+        packet.extend_from_slice(extension_headers);
+        packet.extend_from_slice(&tcp_header);
+        packet
+    }
+
+    /// Parses an IPv6 packet with the default settings.
+    fn parse_ipv6_packet(packet: &Ipv6Packet) -> Option<PacketFeatures> {
+        let timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        PacketFeatures::from_ipv6_packet(packet, timestamp, &FlowSettings::default())
+    }
+
+    #[test]
+    fn test_ipv6_without_extension_headers() {
+        let data = setup_ipv6_packet(6, &[]);
+        let packet = Ipv6Packet::new(&data).unwrap();
+
+        let features = parse_ipv6_packet(&packet).unwrap();
+
+        assert_eq!(features.protocol, 6);
+        assert_eq!(features.source_port, 443);
+        assert_eq!(features.destination_port, 51000);
+        assert_eq!(features.extension_header_count, 0);
+        assert_eq!(features.ip_options_length, 0);
+        assert_eq!(features.ttl, 64);
+    }
+
+    #[test]
+    fn test_ipv6_jumbo_frame() {
+        // A 9000 byte packet, the MTU of common jumbo frame setups
+        let mut data = setup_ipv6_packet(6, &[]);
+        data.resize(9000, 0);
+        data[4..6].copy_from_slice(&(9000u16 - 40).to_be_bytes());
+        let packet = Ipv6Packet::new(&data).unwrap();
+
+        let features = parse_ipv6_packet(&packet).unwrap();
+
+        assert_eq!(features.length, 9000);
+        assert_eq!(features.data_length, 9000 - 40 - 20);
+        assert_eq!(super::saturating_length(70_000), u16::MAX);
+    }
+
+    #[test]
+    fn test_ipv6_hop_by_hop_extension_header() {
+        // Hop-by-Hop header: next header TCP, length 0 (8 bytes), PadN option
+        let hop_by_hop = [6, 0, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        let data = setup_ipv6_packet(0, &hop_by_hop);
+        let packet = Ipv6Packet::new(&data).unwrap();
+
+        let features = parse_ipv6_packet(&packet).unwrap();
+
+        assert_eq!(features.protocol, 6);
+        assert_eq!(features.source_port, 443);
+        assert_eq!(features.destination_port, 51000);
+        assert_eq!(features.syn_flag, 1);
+        assert_eq!(features.data_length, 0);
+        assert_eq!(features.extension_header_count, 1);
+        assert_eq!(features.ip_options_length, 8);
+    }
+
+    #[test]
+    fn test_ipv6_chained_extension_headers() {
+        // Hop-by-Hop -> Destination Options (16 bytes) -> TCP
+        let mut extension_headers = vec![60, 0, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        extension_headers.extend_from_slice(&[6, 1, 0x01, 0x0c]);
+        extension_headers.extend_from_slice(&[0x00; 12]);
+        let data = setup_ipv6_packet(0, &extension_headers);
+        let packet = Ipv6Packet::new(&data).unwrap();
+
+        let features = parse_ipv6_packet(&packet).unwrap();
+
+        assert_eq!(features.protocol, 6);
+        assert_eq!(features.source_port, 443);
+        assert_eq!(features.extension_header_count, 2);
+        assert_eq!(features.ip_options_length, 24);
+    }
+
+    #[test]
+    fn test_ipv6_non_first_fragment() {
+        // Fragment header with offset 1 (8 bytes), has no transport header
+        let fragment = [6, 0, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01];
+        let data = setup_ipv6_packet(44, &fragment);
+        let packet = Ipv6Packet::new(&data).unwrap();
+
+        assert!(parse_ipv6_packet(&packet).is_none());
+    }
+
+    #[test]
+    fn test_flow_key_strategies() {
+        let data = setup_ipv6_packet(6, &[]);
+        let packet = Ipv6Packet::new(&data).unwrap();
+        let mut features = parse_ipv6_packet(&packet).unwrap();
+        features.vlan_id = Some(100);
+        assert_eq!(features.flow_label, 0xabcde);
+        let source = (features.source_ip, features.source_port);
+        let destination = (features.destination_ip, features.destination_port);
+
+        let keys = [
+            (FlowKeyStrategy::FiveTuple, "::1:443-::2:51000-6"),
+            (FlowKeyStrategy::FiveTupleVlan, "::1:443-::2:51000-6-100"),
+            (FlowKeyStrategy::FiveTupleFlowLabel, "::1:443-::2:51000-6-703710"),
+            (FlowKeyStrategy::ThreeTuple, "::1-::2-6"),
+            (FlowKeyStrategy::HostPair, "::1-::2"),
+        ];
+        for (strategy, key) in keys {
+            assert_eq!(features.format_flow_key(strategy, source, destination), key);
+            // The reversed key is the key of the other direction
+            assert_eq!(
+                reverse_flow_key(key),
+                features.format_flow_key(strategy, destination, source)
+            );
         }
     }
+
+    #[test]
+    fn test_from_ebpf_event_ipv4_byte_order() {
+        // The fields as the eBPF program reads them from the wire, in network byte order
+        let event = EbpfEventIpv4::new(
+            u32::from_ne_bytes([10, 0, 0, 2]),
+            u32::from_ne_bytes([192, 168, 1, 10]),
+            u16::from_ne_bytes([0xc7, 0x38]),
+            u16::from_ne_bytes([0x01, 0xbb]),
+            100u16.to_be(),
+            120u16.to_be(),
+            u16::from_ne_bytes([0x01, 0x00]),
+            0,
+            6,
+            20,
+            u32::from_ne_bytes([0x00, 0x00, 0x01, 0x00]),
+            u32::from_ne_bytes([0x00, 0x00, 0x00, 0x02]),
+            64,
+            8u16.to_be(),
+            IPV4_DONT_FRAGMENT,
+            0b1_0001,
+            0,
+        );
+
+        let packet = PacketFeatures::from_ebpf_event_ipv4(&event);
+        assert_eq!(packet.source_ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)));
+        assert_eq!(packet.destination_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(packet.source_port, 443);
+        assert_eq!(packet.destination_port, 51000);
+        assert_eq!(packet.data_length, 100);
+        assert_eq!(packet.length, 120);
+        assert_eq!(packet.window_size, 256);
+        assert_eq!(packet.sequence_number, 256);
+        assert_eq!(packet.sequence_number_ack, 2);
+        assert_eq!(packet.ttl, 64);
+        assert_eq!(packet.ip_options_length, 8);
+        assert_eq!(packet.ip_flags, IPV4_DONT_FRAGMENT);
+        assert_eq!(packet.tcp_options_mask, 0b1_0001);
+    }
+
+    #[test]
+    fn test_ebpf_event_bytes() {
+        let (data_length, length) = (100u16.to_be(), 120u16.to_be());
+        let event =
+            EbpfEventIpv4::new(1, 2, 3, 4, data_length, length, 0, 0, 6, 20, 5, 6, 64, 0, 0, 0, 0);
+        let bytes = event.as_bytes();
+        assert_eq!(bytes.len(), EbpfEventIpv4::LEN);
+
+        let packet =
+            PacketFeatures::from_ebpf_event_ipv4(&EbpfEventIpv4::from_bytes(bytes).unwrap());
+        assert_eq!(packet.data_length, 100);
+        assert_eq!(packet.length, 120);
+        assert!(EbpfEventIpv4::from_bytes(&bytes[..EbpfEventIpv4::LEN - 1]).is_none());
+
+        // Events are read from any offset, the packed struct has no alignment
+        let (data_length, length) = (32u16.to_be(), 40u16.to_be());
+        let event = EbpfEventIpv6::new(
+            1, 2, 3, 4, data_length, length, 0, 0, 17, 8, 0, 0, 1, 64, 0, 0, 0, 0,
+        );
+        let mut unaligned = vec![0];
+        unaligned.extend_from_slice(event.as_bytes());
+        let event = EbpfEventIpv6::from_bytes(&unaligned[1..]).unwrap();
+        let packet = PacketFeatures::from_ebpf_event_ipv6(&event);
+        assert_eq!(packet.length, 40);
+        assert_eq!(packet.extension_header_count, 1);
+    }
+
+    #[test]
+    fn test_from_ebpf_event_ipv6_byte_order() {
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let event = EbpfEventIpv6::new(
+            u128::from_ne_bytes(destination.octets()),
+            u128::from_ne_bytes(source.octets()),
+            u16::from_ne_bytes([0x00, 0x35]),
+            u16::from_ne_bytes([0xc7, 0x38]),
+            32u16.to_be(),
+            40u16.to_be(),
+            0,
+            0,
+            17,
+            8,
+            0,
+            0,
+            0,
+            255,
+            16u16.to_be(),
+            0,
+            0xabcdeu32.to_be(),
+            0,
+        );
+
+        let packet = PacketFeatures::from_ebpf_event_ipv6(&event);
+        assert_eq!(packet.source_ip, IpAddr::V6(source));
+        assert_eq!(packet.destination_ip, IpAddr::V6(destination));
+        assert_eq!(packet.source_port, 51000);
+        assert_eq!(packet.destination_port, 53);
+        assert_eq!(packet.data_length, 32);
+        assert_eq!(packet.length, 40);
+        assert_eq!(packet.ttl, 255);
+        assert_eq!(packet.ip_options_length, 16);
+        assert_eq!(packet.flow_label, 0xabcde);
+    }
 }
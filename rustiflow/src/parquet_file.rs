@@ -0,0 +1,378 @@
+//! The `Parquet` output, the exported flows as a column per feature of a Parquet file.
+//!
+//! The columns are those of the schemas (see `schema::columns`), typed `INT64`, `DOUBLE`,
+//! `BOOLEAN` or UTF-8 `BYTE_ARRAY`, and all optional, missing values are nulls. The file
+//! describes itself in its key-value metadata: the feature set, the schema version and the
+//! description and unit of every column, taken from the feature registry.
+
+use std::{fs::File, io, sync::Arc};
+
+use log::warn;
+use parquet::{
+    basic::{LogicalType, Repetition, Type as PhysicalType},
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    errors::ParquetError,
+    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+
+use crate::{
+    args::{FlowType, TimestampFormat},
+    flows::{flow::Flow, registry::Value, settings::FlowSettings},
+    schema::{self, ColumnType, SCHEMA_VERSION},
+};
+
+/// The flows of a row group, written out together once this many are buffered.
+const ROW_GROUP_SIZE: usize = 16_384;
+
+/// The values of a column of the buffered flows, the nulls left out.
+enum ColumnValues {
+    Boolean(Vec<bool>),
+    Integer(Vec<i64>),
+    Number(Vec<f64>),
+    String(Vec<ByteArray>),
+}
+
+/// A column of the file and its values of the buffered flows.
+struct ParquetColumn {
+    name: String,
+    values: ColumnValues,
+    /// The definition level of every buffered flow, 0 for a null.
+    levels: Vec<i16>,
+    /// Whether a value of another type was written as a null, warned about once.
+    warned: bool,
+}
+
+impl ParquetColumn {
+    /// Buffers the value of a flow, as a null if it doesn't fit the type of the column.
+    fn push(&mut self, value: &Value, settings: &FlowSettings) {
+        let pushed = match (&mut self.values, value) {
+            (_, Value::Null) => false,
+            (ColumnValues::Boolean(values), Value::Boolean(value)) => {
+                values.push(*value);
+                true
+            }
+            (ColumnValues::Integer(values), value) => integer(value, settings)
+                .map(|value| values.push(value))
+                .is_some(),
+            (ColumnValues::Number(values), value) => {
+                number(value).map(|value| values.push(value)).is_some()
+            }
+            (ColumnValues::String(values), value) => {
+                let mut text = String::new();
+                value.write(settings, &mut text);
+                values.push(ByteArray::from(text.into_bytes()));
+                true
+            }
+            (ColumnValues::Boolean(_), _) => false,
+        };
+        if !pushed && *value != Value::Null && !self.warned {
+            self.warned = true;
+            warn!(
+                "The value {:?} of column {} isn't of its type in the schema, it is left out",
+                value, self.name
+            );
+        }
+        self.levels.push(i16::from(pushed));
+    }
+
+    /// Parses the value of a dumped record, e.g. a replayed one, to buffer it like `push`.
+    fn push_dumped(&mut self, value: &str, settings: &FlowSettings) {
+        let value = if value == settings.null_value.as_str() {
+            Value::Null
+        } else {
+            match self.values {
+                ColumnValues::Boolean(_) => value.parse().map_or(Value::Null, Value::Boolean),
+                ColumnValues::Integer(_) => value.parse().map_or(Value::Null, Value::Signed),
+                ColumnValues::Number(_) => value.parse().map_or(Value::Null, Value::Double),
+                ColumnValues::String(_) => Value::Text(value.to_string().into()),
+            }
+        };
+        self.push(&value, settings);
+    }
+
+    /// Writes the buffered values to the column of a row group and clears them.
+    fn write(&mut self, writer: &mut ColumnWriter) -> Result<(), ParquetError> {
+        let levels = Some(self.levels.as_slice());
+        match (&mut self.values, writer) {
+            (ColumnValues::Boolean(values), ColumnWriter::BoolColumnWriter(writer)) => {
+                writer.write_batch(values, levels, None)?;
+                values.clear();
+            }
+            (ColumnValues::Integer(values), ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(values, levels, None)?;
+                values.clear();
+            }
+            (ColumnValues::Number(values), ColumnWriter::DoubleColumnWriter(writer)) => {
+                writer.write_batch(values, levels, None)?;
+                values.clear();
+            }
+            (ColumnValues::String(values), ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                writer.write_batch(values, levels, None)?;
+                values.clear();
+            }
+            _ => return Err(ParquetError::General(format!("Column {} mismatch", self.name))),
+        }
+        self.levels.clear();
+        Ok(())
+    }
+}
+
+/// Returns an integer value, or a timestamp in an epoch format, as the value of an `INT64`.
+fn integer(value: &Value, settings: &FlowSettings) -> Option<i64> {
+    match value {
+        Value::Unsigned(value) => i64::try_from(*value).ok(),
+        Value::Signed(value) => Some(*value),
+        Value::Timestamp(timestamp, default) => match settings.timestamp_format.or(*default) {
+            TimestampFormat::EpochSeconds => Some(timestamp.timestamp()),
+            TimestampFormat::EpochMillis => Some(timestamp.timestamp_millis()),
+            TimestampFormat::EpochMicros => Some(timestamp.timestamp_micros()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns a number, or an integer, as the value of a `DOUBLE`.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float(value) => Some(f64::from(*value)),
+        Value::Double(value) => Some(*value),
+        Value::Unsigned(value) => Some(*value as f64),
+        Value::Signed(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn parquet_error(error: ParquetError) -> io::Error {
+    io::Error::other(error)
+}
+
+/// Writes the exported flows to a Parquet file, in row groups of `ROW_GROUP_SIZE` flows.
+pub struct ParquetFile {
+    settings: Arc<FlowSettings>,
+    columns: Vec<ParquetColumn>,
+    rows: usize,
+    /// The writer of the file, `None` once it's closed.
+    writer: Option<SerializedFileWriter<File>>,
+}
+
+impl ParquetFile {
+    /// Creates the file with the columns of the feature set `T`, see `schema::columns`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `settings` - The settings of the run.
+    /// * `feature_set` - The name of the feature set, for the metadata.
+    /// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+    /// * `file` - The file the flows are written to.
+    pub fn new<T: Flow>(
+        settings: Arc<FlowSettings>,
+        feature_set: &FlowType,
+        skip_contaminant_features: bool,
+        file: File,
+    ) -> io::Result<Self> {
+        let mut metadata = vec![
+            KeyValue::new("rustiflow.feature_set".to_string(), format!("{:?}", feature_set)),
+            KeyValue::new("rustiflow.schema_version".to_string(), SCHEMA_VERSION.to_string()),
+        ];
+        let mut fields = Vec::new();
+        let mut columns = Vec::new();
+        for column in schema::columns::<T>(&settings, skip_contaminant_features) {
+            let description = match &column.repeats {
+                Some(feature) => format!("Repeats the {} column", feature),
+                None => column.description,
+            };
+            metadata.push(KeyValue::new(
+                format!("rustiflow.column.{}.description", column.name),
+                description,
+            ));
+            // Features without a unit, e.g. identifiers and ratios, have no value for it
+            metadata.push(KeyValue::new(
+                format!("rustiflow.column.{}.unit", column.name),
+                column.unit.name().map(str::to_string),
+            ));
+
+            let (physical_type, values) = match column.column_type {
+                ColumnType::Boolean => (PhysicalType::BOOLEAN, ColumnValues::Boolean(Vec::new())),
+                ColumnType::Integer => (PhysicalType::INT64, ColumnValues::Integer(Vec::new())),
+                ColumnType::Number => (PhysicalType::DOUBLE, ColumnValues::Number(Vec::new())),
+                ColumnType::String => {
+                    (PhysicalType::BYTE_ARRAY, ColumnValues::String(Vec::new()))
+                }
+            };
+            let logical_type =
+                (column.column_type == ColumnType::String).then_some(LogicalType::String);
+            let field = Type::primitive_type_builder(&column.name, physical_type)
+                .with_repetition(Repetition::OPTIONAL)
+                .with_logical_type(logical_type)
+                .build()
+                .map_err(parquet_error)?;
+            fields.push(Arc::new(field));
+            columns.push(ParquetColumn {
+                name: column.name,
+                values,
+                levels: Vec::new(),
+                warned: false,
+            });
+        }
+
+        let schema = Type::group_type_builder("flow")
+            .with_fields(fields)
+            .build()
+            .map_err(parquet_error)?;
+        let properties = WriterProperties::builder()
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        let writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))
+            .map_err(parquet_error)?;
+        Ok(ParquetFile {
+            settings,
+            columns,
+            rows: 0,
+            writer: Some(writer),
+        })
+    }
+
+    /// Buffers the values of a flow, see `registry::record_values`, and writes out the row
+    /// group once it's full.
+    ///
+    /// A full row group is written before the flow is buffered, so a flow whose write fails
+    /// isn't kept and can be retried.
+    pub fn push(&mut self, values: &[Value]) -> io::Result<()> {
+        self.write_full_row_group()?;
+        for (column, value) in self.columns.iter_mut().zip(values) {
+            column.push(value, &self.settings);
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Buffers a dumped record, e.g. a replayed one, like `push`.
+    pub fn push_dumped(&mut self, record: &str) -> io::Result<()> {
+        self.write_full_row_group()?;
+        for (column, value) in self.columns.iter_mut().zip(record.split(',')) {
+            column.push_dumped(value, &self.settings);
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn write_full_row_group(&mut self) -> io::Result<()> {
+        if self.rows >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered flows out as a row group.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("The Parquet file is closed"))?;
+        let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+        for column in &mut self.columns {
+            let Some(mut column_writer) = row_group.next_column().map_err(parquet_error)? else {
+                break;
+            };
+            column.write(column_writer.untyped()).map_err(parquet_error)?;
+            column_writer.close().map_err(parquet_error)?;
+        }
+        row_group.close().map_err(parquet_error)?;
+        writer.flush()?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Writes out the buffered flows and the footer with the metadata, which completes the
+    /// file.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(parquet_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::DateTime;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    use super::ParquetFile;
+    use crate::{
+        args::FlowType,
+        flows::{basic_flow::BasicFlow, flow::Flow, registry},
+        schema::SCHEMA_VERSION,
+    };
+
+    #[test]
+    fn test_parquet_file() {
+        let path = std::env::temp_dir().join("rustiflow_test_output.parquet");
+        let settings = Arc::default();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut parquet =
+            ParquetFile::new::<BasicFlow>(Arc::clone(&settings), &FlowType::Basic, false, file)
+                .unwrap();
+        let flow = BasicFlow::new(
+            settings,
+            "10.0.0.1:40000-10.0.0.2:443-6".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            443,
+            6,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        );
+        parquet.push(&registry::record_values(&flow, &[], false)).unwrap();
+        // A replayed record, the values that don't fit their column are nulls
+        let mut record: Vec<String> = flow.dump().split(',').map(str::to_string).collect();
+        record[2] = "http".to_string();
+        parquet.push_dumped(&record.join(",")).unwrap();
+        parquet.close().unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let file_metadata = reader.metadata().file_metadata();
+        let metadata: HashMap<&str, Option<&str>> = file_metadata
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_deref()))
+            .collect();
+        assert_eq!(metadata["rustiflow.feature_set"], Some("Basic"));
+        let version = SCHEMA_VERSION.to_string();
+        assert_eq!(metadata["rustiflow.schema_version"], Some(version.as_str()));
+        assert_eq!(
+            metadata["rustiflow.column.PORT_SOURCE.description"],
+            Some("The transport port of the source")
+        );
+        assert_eq!(metadata["rustiflow.column.PORT_SOURCE.unit"], None);
+        assert_eq!(metadata["rustiflow.column.DURATION.unit"], Some("microseconds"));
+        assert_eq!(file_metadata.num_rows(), 2);
+
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(rows[0].starts_with(
+            "{FLOW_ID: \"10.0.0.1:40000-10.0.0.2:443-6\", IP_SOURCE: \"10.0.0.1\", \
+            PORT_SOURCE: 40000,"
+        ));
+        assert!(rows[1].contains("PORT_SOURCE: null,"));
+        assert_eq!(rows[0].replace("PORT_SOURCE: 40000,", "PORT_SOURCE: null,"), rows[1]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+//! Routing of the exported flows to an output per partition, e.g. a CSV file or a ClickHouse
+//! table per protocol, so the output doesn't need to be split afterwards.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::Path,
+    sync::Arc,
+};
+
+use log::{debug, error, info};
+
+use crate::{
+    args::{ClickHouseConfig, PartitionKey},
+    clickhouse::ClickHouseSink,
+    flows::{
+        basic_flow::BasicFlow,
+        registry::{self, Value},
+        settings::FlowSettings,
+    },
+};
+
+/// The prefix lengths of the subnets the flows are partitioned by.
+const IPV4_SUBNET_PREFIX: u8 = 24;
+const IPV6_SUBNET_PREFIX: u8 = 64;
+
+/// The most partitions open at once, so e.g. the subnets of a scan can't use up the file
+/// descriptors or the ClickHouse connections. The least recently written one is closed for a new
+/// one.
+const MAX_OPEN_PARTITIONS: usize = 256;
+
+/// Where the partitions are written to, the partition name is added to the file or table.
+pub enum PartitionTarget {
+    Csv { path: String },
+    ClickHouse(ClickHouseConfig),
+}
+
+/// The output of one partition.
+enum PartitionSink {
+    Csv { writer: BufWriter<File> },
+    ClickHouse(Box<ClickHouseSink>),
+}
+
+/// An open partition and when it was last written to.
+struct OpenPartition {
+    sink: PartitionSink,
+    /// The number of the flow last written to it, the least recent one is closed first.
+    last_write: u64,
+}
+
+/// Writes the dumped flows to an output per partition, created on the first flow of it.
+pub struct PartitionedOutput {
+    /// The settings the flows are dumped with.
+    settings: Arc<FlowSettings>,
+    partition_by: PartitionKey,
+    target: PartitionTarget,
+    header: String,
+    write_header: bool,
+    /// Whether the ClickHouse partitions keep the flows they can't insert.
+    keep_dropped_rows: bool,
+    sinks: HashMap<String, OpenPartition>,
+    max_open: usize,
+    /// The number of flows written so far.
+    writes: u64,
+    /// The partitions opened in this run, their files are appended to when opened again.
+    opened: HashSet<String>,
+    /// The inserted and lost flows of the closed ClickHouse partitions, logged at the end.
+    closed_counts: HashMap<String, (u64, u64)>,
+    /// The flows the closed ClickHouse partitions dropped, until they are taken.
+    dropped_rows: Vec<String>,
+}
+
+impl PartitionedOutput {
+    /// Creates the output without any partition yet.
+    ///
+    /// ### Arguments
+    ///
+    /// * `settings` - The settings the flows are dumped with.
+    /// * `partition_by` - What the flows are partitioned by.
+    /// * `target` - The CSV path or the ClickHouse table the partitions are named after.
+    /// * `header` - The dumped feature names, the header of the files and the insert columns.
+    /// * `write_header` - Whether a new file starts with the header.
+    pub fn new(
+        settings: Arc<FlowSettings>,
+        partition_by: PartitionKey,
+        target: PartitionTarget,
+        header: String,
+        write_header: bool,
+    ) -> Self {
+        PartitionedOutput {
+            settings,
+            partition_by,
+            target,
+            header,
+            write_header,
+            keep_dropped_rows: false,
+            sinks: HashMap::new(),
+            max_open: MAX_OPEN_PARTITIONS,
+            writes: 0,
+            opened: HashSet::new(),
+            closed_counts: HashMap::new(),
+            dropped_rows: Vec::new(),
+        }
+    }
+
+    /// Keeps the flows the ClickHouse partitions can't insert, see
+    /// `ClickHouseSink::keep_dropped_rows`.
+    pub fn keep_dropped_rows(&mut self) {
+        self.keep_dropped_rows = true;
+    }
+
+    /// Takes the flows the ClickHouse partitions dropped since the last call.
+    pub fn take_dropped_rows(&mut self) -> Vec<String> {
+        let mut rows = std::mem::take(&mut self.dropped_rows);
+        for partition in self.sinks.values_mut() {
+            if let PartitionSink::ClickHouse(clickhouse) = &mut partition.sink {
+                rows.extend(clickhouse.take_dropped_rows());
+            }
+        }
+        rows
+    }
+
+    /// Writes the values of a flow to the output of its partition, see
+    /// `registry::record_values`.
+    pub async fn write(&mut self, flow: &BasicFlow, values: Vec<Value>) -> io::Result<()> {
+        let name = partition_name(self.partition_by, flow);
+        if !self.sinks.contains_key(&name) {
+            if self.sinks.len() >= self.max_open {
+                self.close_least_recent().await;
+            }
+            let sink = self.open(&name).await?;
+            self.opened.insert(name.clone());
+            let partition = OpenPartition {
+                sink,
+                last_write: 0,
+            };
+            self.sinks.insert(name.clone(), partition);
+        }
+
+        self.writes += 1;
+        let Some(partition) = self.sinks.get_mut(&name) else {
+            return Ok(());
+        };
+        partition.last_write = self.writes;
+        match &mut partition.sink {
+            // A single write, so a failed one buffers nothing that its retry would repeat
+            PartitionSink::Csv { writer } => {
+                let record = registry::dump_values(&values, &self.settings);
+                writer.write_all(format!("{}\n", record).as_bytes())
+            }
+            PartitionSink::ClickHouse(clickhouse) => clickhouse.push(values).await,
+        }
+    }
+
+    /// Closes the partition written to least recently, to make room for a new one.
+    async fn close_least_recent(&mut self) {
+        let least_recent = self
+            .sinks
+            .iter()
+            .min_by_key(|(_, partition)| partition.last_write)
+            .map(|(name, _)| name.clone());
+        let Some((name, partition)) = least_recent.and_then(|name| self.sinks.remove_entry(&name))
+        else {
+            return;
+        };
+        debug!(
+            "Closing the {} partition, {} partitions are open",
+            name, self.max_open
+        );
+        if let Err(e) = self.close_sink(&name, partition.sink).await {
+            error!("Failed to write out the {} partition: {}", name, e);
+        }
+    }
+
+    /// Writes out the buffered flows of a partition that is closed, the ClickHouse flows it
+    /// can't insert are dropped.
+    async fn close_sink(&mut self, name: &str, sink: PartitionSink) -> io::Result<()> {
+        match sink {
+            PartitionSink::Csv { mut writer } => writer.flush(),
+            PartitionSink::ClickHouse(mut clickhouse) => {
+                let flushed = clickhouse.flush().await;
+                if flushed.is_err() {
+                    clickhouse.drop_batch();
+                }
+                let counts = self.closed_counts.entry(name.to_string()).or_default();
+                counts.0 += clickhouse.inserted_flows();
+                counts.1 += clickhouse.lost_flows();
+                self.dropped_rows.extend(clickhouse.take_dropped_rows());
+                flushed
+            }
+        }
+    }
+
+    /// Creates the output of a new partition, or opens it again after it was closed.
+    async fn open(&self, name: &str) -> io::Result<PartitionSink> {
+        match &self.target {
+            PartitionTarget::Csv { path } => {
+                let path = partition_path(path, name);
+                if self.opened.contains(name) {
+                    let file = OpenOptions::new().append(true).open(&path).map_err(|e| {
+                        io::Error::new(e.kind(), format!("Failed to open file {}: {}", path, e))
+                    })?;
+                    return Ok(PartitionSink::Csv {
+                        writer: BufWriter::new(file),
+                    });
+                }
+                let file = File::create(&path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("Failed to create file {}: {}", path, e))
+                })?;
+                info!("Writing the {} partition to {}", name, path);
+                let mut writer = BufWriter::new(file);
+                if self.write_header {
+                    writeln!(writer, "{}", self.header)?;
+                }
+                Ok(PartitionSink::Csv { writer })
+            }
+            PartitionTarget::ClickHouse(config) => {
+                let config = ClickHouseConfig {
+                    table: format!("{}_{}", config.table, name),
+                    ..config.clone()
+                };
+                if !self.opened.contains(name) {
+                    info!(
+                        "Inserting the {} partition into the ClickHouse table {}",
+                        name, config.table
+                    );
+                }
+                let settings = Arc::clone(&self.settings);
+                let mut clickhouse = ClickHouseSink::new(config, &self.header, settings);
+                if self.keep_dropped_rows {
+                    clickhouse.keep_dropped_rows();
+                }
+                // The inserts keep retrying, the server may just not be up yet
+                if let Err(e) = clickhouse.connect().await {
+                    error!("{}", e);
+                }
+                Ok(PartitionSink::ClickHouse(Box::new(clickhouse)))
+            }
+        }
+    }
+
+    /// Writes out the buffered flows of all partitions, see `OutputWriter::flush`.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        for partition in self.sinks.values_mut() {
+            match &mut partition.sink {
+                PartitionSink::Csv { writer } => {
+                    writer.flush()?;
+                    writer.get_ref().sync_data()?;
+                }
+                PartitionSink::ClickHouse(clickhouse) => clickhouse.flush().await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts the batches of the ClickHouse partitions that waited for their `flush_interval`,
+    /// see `ClickHouseSink::flush_if_due`.
+    ///
+    /// Every partition is tried even if another one fails, the first error is returned.
+    pub async fn flush_if_due(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for partition in self.sinks.values_mut() {
+            if let PartitionSink::ClickHouse(clickhouse) = &mut partition.sink {
+                let flushed = clickhouse.flush_if_due().await;
+                if result.is_ok() {
+                    result = flushed;
+                }
+            }
+        }
+        result
+    }
+
+    /// Writes out the buffered flows of all partitions at the end of the run.
+    ///
+    /// Every partition is flushed even if another one fails, the first error is returned.
+    pub async fn close(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        let sinks: Vec<_> = self.sinks.drain().collect();
+        for (name, partition) in sinks {
+            let flushed = self.close_sink(&name, partition.sink).await;
+            if result.is_ok() {
+                result = flushed;
+            }
+        }
+        for (name, (inserted_flows, lost_flows)) in &self.closed_counts {
+            info!(
+                "Inserted {} flows of the {} partition into ClickHouse",
+                inserted_flows, name
+            );
+            if *lost_flows > 0 {
+                error!(
+                    "Lost {} flows of the {} partition that couldn't be inserted",
+                    lost_flows, name
+                );
+            }
+        }
+        info!("Wrote the flows to {} partitions", self.opened.len());
+        result
+    }
+}
+
+/// Returns the name of the partition of a flow, made of letters, digits and `_` only, so it
+/// can be part of a file or table name.
+///
+/// ### Arguments
+///
+/// * `partition_by` - What the flows are partitioned by.
+/// * `flow` - The flow to be written.
+pub fn partition_name(partition_by: PartitionKey, flow: &BasicFlow) -> String {
+    let name = match partition_by {
+        PartitionKey::Protocol => protocol_name(flow.protocol),
+        PartitionKey::Interface => flow.settings.capture_source.clone(),
+        // The exported source, so the names don't reveal anonymized addresses
+        PartitionKey::Subnet => {
+            let ip_source = flow.settings.endpoint_features(flow).ip_source;
+            match ip_source {
+                IpAddr::V4(ip) => {
+                    let mask = u32::MAX << (32 - IPV4_SUBNET_PREFIX);
+                    let subnet = std::net::Ipv4Addr::from(u32::from(ip) & mask);
+                    format!("{}_{}", subnet, IPV4_SUBNET_PREFIX)
+                }
+                IpAddr::V6(ip) => {
+                    let mask = u128::MAX << (128 - IPV6_SUBNET_PREFIX);
+                    let subnet = std::net::Ipv6Addr::from(u128::from(ip) & mask);
+                    format!("{}_{}", subnet, IPV6_SUBNET_PREFIX)
+                }
+            }
+        }
+    };
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Returns the name of a transport protocol, its number if it has no name.
+fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        33 => "dccp".to_string(),
+        58 => "icmpv6".to_string(),
+        132 => "sctp".to_string(),
+        _ => format!("protocol_{}", protocol),
+    }
+}
+
+/// Returns the path of the file of a partition, the name is added to the file stem.
+fn partition_path(path: &str, name: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("flows");
+    let file_name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}_{}.{}", stem, name, extension),
+        None => format!("{}_{}", stem, name),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        sync::Arc,
+    };
+
+    use chrono::DateTime;
+
+    use super::{partition_name, partition_path, PartitionTarget, PartitionedOutput};
+    use crate::{
+        args::PartitionKey,
+        flows::{basic_flow::BasicFlow, flow::Flow, registry::Value},
+    };
+
+    fn flow(ip_source: IpAddr, protocol: u8) -> BasicFlow {
+        BasicFlow::new(
+            Arc::default(),
+            String::new(),
+            ip_source,
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            443,
+            protocol,
+            DateTime::from_timestamp(0, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_partition_name() {
+        let ipv4_flow = flow(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 77)), 6);
+        assert_eq!(partition_name(PartitionKey::Protocol, &ipv4_flow), "tcp");
+        assert_eq!(
+            partition_name(PartitionKey::Subnet, &ipv4_flow),
+            "192_168_1_0_24"
+        );
+
+        let ipv6_flow = flow(
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 7, 1, 2, 3, 4)),
+            47,
+        );
+        assert_eq!(
+            partition_name(PartitionKey::Protocol, &ipv6_flow),
+            "protocol_47"
+        );
+        assert_eq!(
+            partition_name(PartitionKey::Subnet, &ipv6_flow),
+            "2001_db8_0_7___64"
+        );
+    }
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(partition_path("out/flows.csv", "tcp"), "out/flows_tcp.csv");
+        assert_eq!(partition_path("flows", "eth0"), "flows_eth0");
+    }
+
+    #[tokio::test]
+    async fn test_close_least_recent_partition() {
+        let path = std::env::temp_dir().join("rustiflow_test_partitions.csv");
+        let path = path.to_string_lossy().into_owned();
+        let target = PartitionTarget::Csv { path: path.clone() };
+        let header = "HEADER".to_string();
+        let mut output =
+            PartitionedOutput::new(Arc::default(), PartitionKey::Protocol, target, header, true);
+        output.max_open = 2;
+
+        let ip_source = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        for (protocol, record) in [(6, "tcp 1"), (17, "udp 1"), (6, "tcp 2"), (1, "icmp 1")] {
+            output
+                .write(&flow(ip_source, protocol), vec![Value::Text(record.into())])
+                .await
+                .unwrap();
+        }
+        // The UDP partition was written to least recently and made room for the ICMP one
+        assert_eq!(output.sinks.len(), 2);
+        assert!(!output.sinks.contains_key("udp"));
+
+        // The UDP file is appended to when opened again, without another header
+        output
+            .write(&flow(ip_source, 17), vec![Value::Text("udp 2".into())])
+            .await
+            .unwrap();
+        output.close().await.unwrap();
+        let udp_path = partition_path(&path, "udp");
+        let udp = std::fs::read_to_string(&udp_path).unwrap();
+        assert_eq!(udp, "HEADER\nudp 1\nudp 2\n");
+        let tcp_path = partition_path(&path, "tcp");
+        let tcp = std::fs::read_to_string(&tcp_path).unwrap();
+        assert_eq!(tcp, "HEADER\ntcp 1\ntcp 2\n");
+
+        for name in ["tcp", "udp", "icmp"] {
+            std::fs::remove_file(partition_path(&path, name)).unwrap();
+        }
+    }
+}
@@ -1,198 +1,667 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::{
+    fs::File,
+    io::{self, BufReader, PipeReader, PipeWriter, Read, Seek},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crate::flows::flow::Flow;
-use crate::{flow_table::FlowTable, packet_features::PacketFeatures};
+use crate::flows::{flow::Flow, settings::FlowSettings};
+use crate::{
+    metrics,
+    packet_features::{malformed, PacketFeatures},
+    pipeline::Pipeline,
+};
+use anyhow::{anyhow, bail, Context};
 use chrono::{DateTime, Utc};
-use log::{debug, error};
+use flate2::read::MultiGzDecoder;
+use log::{debug, error, info, warn};
+use pcap::{Capture, Offline};
 use pnet::packet::{
-    ethernet::{EtherTypes, EthernetPacket},
+    ethernet::{EtherType, EtherTypes, EthernetPacket},
     ipv4::Ipv4Packet,
     ipv6::Ipv6Packet,
-    Packet,
 };
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::Sender;
-
-// Define constants for Linux cooked capture EtherTypes
-const SLL_IPV4: u16 = 0x0800;
-const SLL_IPV6: u16 = 0x86DD;
-
-pub async fn read_pcap_file<T>(
-    path: &str,
-    output_channel: Sender<T>,
-    num_threads: u8,
-    active_timeout: u64,
-    idle_timeout: u64,
-    early_export: Option<u64>,
-    expiration_check_interval: u64,
-) -> Result<(), anyhow::Error>
+use tokio::signal;
+use tokio::sync::{mpsc, oneshot};
+
+// Header lengths in front of the IP header
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const SLL_HEADER_LENGTH: usize = 16;
+// The PPPoE session header and the PPP protocol field
+const PPPOE_HEADER_LENGTH: usize = 8;
+// The tag control information and the EtherType of the tagged frame
+const VLAN_TAG_LENGTH: usize = 4;
+
+// PPP protocol numbers of the IP versions
+const PPP_PROTOCOL_IPV4: u16 = 0x0021;
+const PPP_PROTOCOL_IPV6: u16 = 0x0057;
+
+// Minimum time between two progress reports while reading a file
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+// Sizes of the pcap file and record headers, used to estimate the read position
+const PCAP_FILE_HEADER_LENGTH: u64 = 24;
+const PCAP_RECORD_HEADER_LENGTH: u64 = 16;
+
+// The first bytes of a gzip member and of a zstd frame
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression of a capture file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression by the first bytes of a file, `None` if it isn't compressed.
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the compression the extension of a path names, e.g. `capture.pcap.gz`.
+    fn from_extension(path: &str) -> Option<Self> {
+        if path.ends_with(".gz") {
+            Some(Compression::Gzip)
+        } else if path.ends_with(".zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// The thread decompressing a capture file for libpcap, see `open_capture`.
+type Decompressor = JoinHandle<io::Result<()>>;
+
+/// Opens a pcap or pcapng file, or stdin for `-`, for libpcap.
+///
+/// A gzip or zstd compressed file, detected by its first bytes, is decompressed on a thread of
+/// its own into a pipe libpcap reads from, so it is never written to disk. The thread returns
+/// the error of a truncated or corrupt file once libpcap read to its end.
+fn open_capture(path: &str) -> Result<(Capture<Offline>, Option<Decompressor>), anyhow::Error> {
+    // libpcap reads stdin for `-` and never seeks, so pipes work without buffering the file.
+    // Only regular files are checked for compression, reading a pipe would take its first bytes.
+    let is_file = std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file());
+    if path == "-" || !is_file {
+        return Ok((Capture::from_file(path)?, None));
+    }
+    let mut file = File::open(path)?;
+    let mut header = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut file).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut header)?;
+    let Some(compression) = Compression::detect(&header) else {
+        if let Some(compression) = Compression::from_extension(path) {
+            bail!("{} is named like a {:?} compressed file, but isn't one", path, compression);
+        }
+        return Ok((Capture::from_file(path)?, None));
+    };
+    file.rewind()?;
+
+    let decoder: Box<dyn Read + Send> = match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    };
+    let (reader, writer) = io::pipe()?;
+    // libpcap reads the file header when opening, so the decompression has to run already
+    let decompressor = std::thread::spawn(move || decompress(decoder, writer));
+    match capture_from_pipe(reader) {
+        Ok(capture) => Ok((capture, Some(decompressor))),
+        Err(e) => match decompressor.join() {
+            // The decompressed file isn't a capture
+            Ok(Ok(())) => Err(anyhow!(e)),
+            Ok(Err(e)) => Err(anyhow!("Failed to decompress {}: {}", path, e)),
+            Err(_) => Err(anyhow!("Failed to decompress {}", path)),
+        },
+    }
+}
+
+/// Writes a decompressed file into the pipe libpcap reads from.
+fn decompress(mut decoder: impl Read, mut pipe: PipeWriter) -> io::Result<()> {
+    match io::copy(&mut decoder, &mut pipe) {
+        // libpcap stopped reading early, e.g. at an incomplete packet
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+#[cfg(unix)]
+fn capture_from_pipe(reader: PipeReader) -> Result<Capture<Offline>, pcap::Error> {
+    use std::os::fd::IntoRawFd;
+
+    // SAFETY: the descriptor is released by the pipe, the capture becomes its sole owner
+    unsafe { Capture::from_raw_fd(reader.into_raw_fd()) }
+}
+
+#[cfg(not(unix))]
+fn capture_from_pipe(_reader: PipeReader) -> Result<Capture<Offline>, pcap::Error> {
+    Err(pcap::Error::PcapError(
+        "compressed capture files can only be read on Unix".to_string(),
+    ))
+}
+
+/// Periodically logs how far a pcap file has been read.
+struct Progress {
+    file_size: Option<u64>,
+    bytes_read: u64,
+    packets_read: u64,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new(file_size: Option<u64>) -> Self {
+        Progress {
+            file_size,
+            bytes_read: PCAP_FILE_HEADER_LENGTH,
+            packets_read: 0,
+            start: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Counts a read packet and reports the progress if the interval passed.
+    fn record_packet(&mut self, captured_length: u32) {
+        self.packets_read += 1;
+        self.bytes_read += PCAP_RECORD_HEADER_LENGTH + u64::from(captured_length);
+
+        // Checking the clock for every packet would slow down reading
+        if self.packets_read.is_multiple_of(1024) && self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            info!("{}", self.report());
+        }
+    }
+
+    fn report(&self) -> String {
+        // The position is estimated from the pcap record sizes (pcapng records are larger)
+        let position = self.file_size.filter(|size| *size > 0).map(|size| {
+            let fraction = (self.bytes_read as f64 / size as f64).min(1.0);
+            let elapsed = self.start.elapsed().as_secs_f64();
+            let remaining = elapsed / fraction - elapsed;
+            format!("{:.1}% (ETA {:.0} s), ", fraction * 100.0, remaining)
+        });
+
+        format!(
+            "Progress: {}{} packets read, {} flows active, {} flows exported",
+            position.unwrap_or_default(),
+            self.packets_read,
+            metrics::active_flows(),
+            metrics::exported_flows()
+        )
+    }
+}
+
+/// Reads the packets of a pcap or pcapng file, which may be gzip or zstd compressed, into
+/// the flow tables of a pipeline.
+///
+/// The path `-` reads the capture from stdin, e.g. `tcpdump -U -w - | rustiflow ... pcap -`.
+/// Stdin and named pipes are read as a stream: the packets are processed as they arrive, and
+/// Ctrl-C stops reading and exports the active flows like the end of the stream.
+pub async fn read_pcap_file<T>(pipeline: &Pipeline<T>, path: &str) -> Result<(), anyhow::Error>
 where
     T: Flow,
 {
     debug!("Opening the pcap file: {:?} ...", path);
 
-    let mut pcap_capture = match pcap::Capture::from_file(path) {
-        Ok(c) => c,
+    let (mut pcap_capture, decompressor) = match open_capture(path) {
+        Ok(capture) => capture,
         Err(e) => {
             error!("Error opening file: {:?}", e);
-            return Err(anyhow::Error::new(e));
+            return Err(e);
         }
     };
+    let file_size = std::fs::metadata(path).ok().filter(|m| m.is_file()).map(|m| m.len());
+    let is_stream = file_size.is_none();
+    // The position in a decompressed file can't be told from the size of the compressed one
+    let progress_size = file_size.filter(|_| decompressor.is_none());
 
-    // Create sharded FlowTables each in their own task and returns channels to send packets to the shards
+    let settings = Arc::clone(pipeline.settings());
     let buffer_num_packets = 10_000;
-    let shard_senders = create_shard_senders::<T>(
-        num_threads,
-        buffer_num_packets,
-        output_channel,
-        active_timeout,
-        idle_timeout,
-        early_export,
-        expiration_check_interval,
-    );
-
-    debug!("Reading the pcap file: {:?} ...", path);
-    while let Ok(packet) = pcap_capture.next_packet() {
-        // Convert TimeVal from packet capture to DateTime<Utc>
-        let timestamp = DateTime::from_timestamp(
-            packet.header.ts.tv_sec,
-            (packet.header.ts.tv_usec * 1000) as u32,
-        )
-        .unwrap();
 
-        if let Some(ethernet) = EthernetPacket::new(packet.data) {
-            match ethernet.get_ethertype() {
-                EtherTypes::Ipv4 => {
-                    if let Some(packet) = Ipv4Packet::new(ethernet.payload()) {
-                        process_packet::<T, Ipv4Packet>(
-                            &packet,
-                            timestamp,
-                            &shard_senders,
-                            num_threads,
-                            PacketFeatures::from_ipv4_packet,
-                        )
-                        .await;
-                    }
+    // libpcap blocks until a packet arrived, so the packets are read on a thread of their own.
+    // It isn't joined: a stream stopped with Ctrl-C may still be waiting for its next packet.
+    let link_layer = LinkLayer::from_linktype(pcap_capture.get_datalink());
+    let (packet_sender, mut packet_receiver) = mpsc::channel(buffer_num_packets);
+    let (done_sender, done) = oneshot::channel();
+    let reader_path = path.to_string();
+    std::thread::spawn(move || {
+        let mut progress = Progress::new(progress_size);
+        debug!("Reading the pcap file: {:?} ...", reader_path);
+        loop {
+            let packet = match pcap_capture.next_packet() {
+                Ok(packet) => packet,
+                Err(pcap::Error::NoMorePackets) => break,
+                Err(e) => {
+                    // E.g. the writer of a stream was stopped in the middle of a packet
+                    warn!("Stopped reading {} at an incomplete packet: {}", reader_path, e);
+                    break;
                 }
-                EtherTypes::Ipv6 => {
-                    if let Some(packet) = Ipv6Packet::new(ethernet.payload()) {
-                        process_packet::<T, Ipv6Packet>(
-                            &packet,
-                            timestamp,
-                            &shard_senders,
-                            num_threads,
-                            PacketFeatures::from_ipv6_packet,
-                        )
-                        .await;
-                    }
-                }
-                _ => {
-                    // Check if it is a Linux cooked capture
-                    let ethertype = u16::from_be_bytes([packet.data[14], packet.data[15]]);
-                    match ethertype {
-                        SLL_IPV4 => {
-                            if let Some(packet) = Ipv4Packet::new(&packet.data[16..]) {
-                                process_packet::<T, Ipv4Packet>(
-                                    &packet,
-                                    timestamp,
-                                    &shard_senders,
-                                    num_threads,
-                                    PacketFeatures::from_ipv4_packet,
-                                )
-                                .await;
-                            }
-                        }
-                        SLL_IPV6 => {
-                            if let Some(packet) = Ipv6Packet::new(&packet.data[16..]) {
-                                process_packet::<T, Ipv6Packet>(
-                                    &packet,
-                                    timestamp,
-                                    &shard_senders,
-                                    num_threads,
-                                    PacketFeatures::from_ipv6_packet,
-                                )
-                                .await;
-                            }
-                        }
-                        _ => debug!("Failed to parse packet as IPv4 or IPv6..."),
-                    }
+            };
+            progress.record_packet(packet.header.caplen);
+
+            // Convert TimeVal from packet capture to DateTime<Utc>
+            let timestamp = DateTime::from_timestamp(
+                packet.header.ts.tv_sec,
+                (packet.header.ts.tv_usec * 1000) as u32,
+            )
+            .unwrap();
+
+            if let Some(packet_features) =
+                extract_packet_features(packet.data, link_layer, timestamp, &settings)
+            {
+                if packet_sender.blocking_send(packet_features).is_err() {
+                    break;
                 }
             }
+        }
+        // Closes the pipe of a compressed file, a decompression still writing into it ends
+        drop(pcap_capture);
+        let decompressed = decompressor.map(|decompressor| {
+            decompressor
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("the decompression panicked")))
+        });
+        let _ = done_sender.send((progress, decompressed));
+    });
+
+    let interrupt = async {
+        if is_stream {
+            let _ = signal::ctrl_c().await;
         } else {
-            error!("Error parsing packet...");
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::pin!(interrupt);
+    loop {
+        tokio::select! {
+            packet_features = packet_receiver.recv() => {
+                let Some(packet_features) = packet_features else {
+                    break;
+                };
+                if let Err(e) = pipeline.push(packet_features).await {
+                    error!("Failed to send packet_features: {}", e);
+                }
+            }
+            _ = &mut interrupt => {
+                info!("Stopped reading {} at Ctrl-C", path);
+                return Ok(());
+            }
         }
     }
+
     debug!("Finished reading the pcap file: {:?}", path);
+    if let Ok((progress, decompressed)) = done.await {
+        info!(
+            "Read {} packets in {:.1} seconds",
+            progress.packets_read,
+            progress.start.elapsed().as_secs_f64()
+        );
+        // The flows read up to a truncated or corrupt part are exported all the same
+        decompressed
+            .transpose()
+            .with_context(|| format!("Failed to decompress {}, it is truncated or corrupt", path))?;
+    }
+    if metrics::malformed_packets() > 0 {
+        info!("Skipped {} malformed packets", metrics::malformed_packets());
+    }
     Ok(())
 }
 
-/// Processes and sends packet features to the appropriate shard.
-async fn process_packet<T, P>(
-    packet: &P,
+/// The framing in front of the IP header of captured packets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LinkLayer {
+    /// Ethernet II frames
+    Ethernet,
+    /// Linux cooked capture (SLL), used when capturing on the "any" device
+    LinuxCooked,
+    /// Packets starting at the IP header, like on TUN and WireGuard interfaces
+    RawIp,
+}
+
+impl LinkLayer {
+    /// Maps the data link type of a capture to its framing.
+    ///
+    /// Unknown link types are treated as Ethernet, which most captures are.
+    pub fn from_linktype(linktype: pcap::Linktype) -> Self {
+        match linktype {
+            pcap::Linktype::LINUX_SLL => LinkLayer::LinuxCooked,
+            // DLT_RAW is 12 on most platforms and 14 on OpenBSD, LINKTYPE_RAW in files is 101
+            pcap::Linktype(12)
+            | pcap::Linktype(14)
+            | pcap::Linktype::RAW
+            | pcap::Linktype::IPV4
+            | pcap::Linktype::IPV6 => LinkLayer::RawIp,
+            pcap::Linktype::ETHERNET => LinkLayer::Ethernet,
+            _ => {
+                error!(
+                    "Unsupported link type {:?}, parsing packets as Ethernet",
+                    linktype
+                );
+                LinkLayer::Ethernet
+            }
+        }
+    }
+}
+
+/// Extracts the packet features from a captured frame.
+///
+/// ### Arguments
+///
+/// * `data` - The captured frame.
+/// * `link_layer` - The framing in front of the IP header.
+/// * `timestamp` - The capture time of the frame.
+/// * `settings` - The settings of the run, e.g. the IP versions that are processed.
+///
+/// ### Returns
+///
+/// The packet features, or `None` if the frame isn't a supported IPv4 or IPv6 packet.
+pub(crate) fn extract_packet_features(
+    data: &[u8],
+    link_layer: LinkLayer,
     timestamp: DateTime<Utc>,
-    shard_senders: &Vec<mpsc::Sender<PacketFeatures>>,
-    num_shards: u8,
-    extractor: fn(&P, DateTime<Utc>) -> Option<PacketFeatures>,
-) where
-    T: Flow,
-    P: Packet,
-{
-    if let Some(packet_features) = extractor(packet, timestamp) {
-        let flow_key = packet_features.biflow_key();
-        let shard_index = compute_shard_index(&flow_key, num_shards);
+    settings: &FlowSettings,
+) -> Option<PacketFeatures> {
+    let (ethertype, ip_packet) = match link_layer {
+        LinkLayer::Ethernet => {
+            let Some(ethernet) = EthernetPacket::new(data) else {
+                return malformed("truncated Ethernet header");
+            };
+            (ethernet.get_ethertype(), &data[ETHERNET_HEADER_LENGTH..])
+        }
+        LinkLayer::LinuxCooked => {
+            let Some(ip_packet) = data.get(SLL_HEADER_LENGTH..) else {
+                return malformed("truncated Linux cooked capture header");
+            };
+            (EtherType(u16::from_be_bytes([data[14], data[15]])), ip_packet)
+        }
+        // Without a link layer header the IP version tells the packets apart
+        LinkLayer::RawIp => match data.first()? >> 4 {
+            4 => (EtherTypes::Ipv4, data),
+            6 => (EtherTypes::Ipv6, data),
+            _ => (EtherType(0), data),
+        },
+    };
+    // 802.1Q and 802.1ad tags sit in front of the EtherType of the tagged frame
+    let Some((ethertype, ip_packet, vlan_id)) = unwrap_vlan_tags(ethertype, ip_packet) else {
+        return malformed("truncated VLAN tag");
+    };
+    // DSL links wrap the IP packets in PPPoE sessions
+    let (ethertype, ip_packet) = if ethertype == EtherTypes::PppoeSession {
+        match unwrap_pppoe(ip_packet) {
+            Some(unwrapped) => unwrapped,
+            None => return malformed("truncated or invalid PPPoE header"),
+        }
+    } else {
+        (ethertype, ip_packet)
+    };
 
-        if let Err(e) = shard_senders[shard_index].send(packet_features).await {
-            error!(
-                "Failed to send packet_features to shard {}: {}",
-                shard_index, e
-            );
+    let ip_version = settings.ip_version;
+    let mut packet_features = match ethertype {
+        EtherTypes::Ipv4 if ip_version.includes_ipv4() => match Ipv4Packet::new(ip_packet) {
+            Some(packet) => PacketFeatures::from_ipv4_packet(&packet, timestamp, settings),
+            None => malformed("truncated IPv4 header"),
+        },
+        EtherTypes::Ipv6 if ip_version.includes_ipv6() => match Ipv6Packet::new(ip_packet) {
+            Some(packet) => PacketFeatures::from_ipv6_packet(&packet, timestamp, settings),
+            None => malformed("truncated IPv6 header"),
+        },
+        // The IP version is disabled, its packets aren't parsed
+        EtherTypes::Ipv4 | EtherTypes::Ipv6 => None,
+        _ => {
+            debug!("Failed to parse packet as IPv4 or IPv6...");
+            None
         }
+    }?;
+    packet_features.vlan_id = vlan_id;
+    Some(packet_features)
+}
+
+/// Skips the VLAN tags in front of the payload of a frame.
+///
+/// ### Returns
+///
+/// The EtherType of the tagged frame, its payload and the VLAN id of the outer tag (`None` for
+/// untagged frames), or `None` if a tag is truncated.
+fn unwrap_vlan_tags(
+    mut ethertype: EtherType,
+    mut payload: &[u8],
+) -> Option<(EtherType, &[u8], Option<u16>)> {
+    let mut vlan_id = None;
+    while matches!(ethertype, EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ) {
+        let tag = payload.get(..VLAN_TAG_LENGTH)?;
+        // The VLAN id is the lowest 12 bits of the tag control information
+        vlan_id = vlan_id.or(Some(u16::from_be_bytes([tag[0], tag[1]]) & 0x0fff));
+        ethertype = EtherType(u16::from_be_bytes([tag[2], tag[3]]));
+        payload = &payload[VLAN_TAG_LENGTH..];
     }
+    Some((ethertype, payload, vlan_id))
 }
 
-fn compute_shard_index(flow_key: &str, num_shards: u8) -> usize {
-    assert!(num_shards > 0, "num_shards must be greater than 0");
-    let mut hasher = DefaultHasher::new();
-    flow_key.hash(&mut hasher);
-    let hash = hasher.finish();
-    (hash % num_shards as u64) as usize
+/// Unwraps the payload of a PPPoE session frame.
+///
+/// ### Returns
+///
+/// The EtherType of the IP version carried and the IP packet, `EtherType(0)` for the other PPP
+/// protocols, or `None` if the header is truncated or not a session header.
+fn unwrap_pppoe(payload: &[u8]) -> Option<(EtherType, &[u8])> {
+    let header = payload.get(..PPPOE_HEADER_LENGTH)?;
+    // Version 1 and type 1 in one byte, then the code, 0 for session data
+    if header[0] != 0x11 || header[1] != 0 {
+        return None;
+    }
+    let ethertype = match u16::from_be_bytes([header[6], header[7]]) {
+        PPP_PROTOCOL_IPV4 => EtherTypes::Ipv4,
+        PPP_PROTOCOL_IPV6 => EtherTypes::Ipv6,
+        // Link control and authentication carry no IP packets
+        _ => EtherType(0),
+    };
+    Some((ethertype, &payload[PPPOE_HEADER_LENGTH..]))
 }
 
-/// Creates shard channels to FlowTables and spawns processing tasks for each shard.
-fn create_shard_senders<T>(
-    num_shards: u8,
-    buffer_num_packets: usize,
-    output_channel: Sender<T>,
-    active_timeout: u64,
-    idle_timeout: u64,
-    early_export: Option<u64>,
-    expiration_check_interval: u64,
-) -> Vec<mpsc::Sender<PacketFeatures>>
-where
-    T: Flow,
-{
-    debug!("Creating {} sharded FlowTables...", num_shards);
-    let mut shard_senders = Vec::with_capacity(num_shards as usize);
-    for _ in 0..num_shards {
-        let (tx, mut rx) = mpsc::channel::<PacketFeatures>(buffer_num_packets);
-        let mut flow_table = FlowTable::new(
-            active_timeout,
-            idle_timeout,
-            early_export,
-            output_channel.clone(),
-            expiration_check_interval,
-        );
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{extract_packet_features, open_capture, Compression, LinkLayer};
+    use crate::{flows::settings::FlowSettings, metrics, packet_features::PacketFeatures};
+    use chrono::Utc;
+    use common::parse::{tcp_option_bit, IPV4_DONT_FRAGMENT, TCP_OPTION_MSS};
+
+    const IPV4_UDP_PACKET: [u8; 28] = [
+        0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0x04, 0xd2, 0x00,
+        0x35, 0, 8, 0, 0,
+    ];
+
+    /// Extracts the features of a frame captured now, with the default settings.
+    fn extract(data: &[u8], link_layer: LinkLayer) -> Option<PacketFeatures> {
+        extract_packet_features(data, link_layer, Utc::now(), &FlowSettings::default())
+    }
+
+    #[test]
+    fn test_extract_packet_features_linux_cooked_capture() {
+        // SLL header with protocol IPv4, followed by an IPv4 header carrying UDP
+        let mut frame = vec![0u8; 16];
+        frame[14..16].copy_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&IPV4_UDP_PACKET);
+
+        let packet_features = extract(&frame, LinkLayer::LinuxCooked).unwrap();
+        assert_eq!(packet_features.source_port, 1234);
+        assert_eq!(packet_features.destination_port, 53);
+        assert_eq!(packet_features.protocol, 17);
+    }
+
+    #[test]
+    fn test_extract_packet_features_raw_ip() {
+        let packet_features = extract(&IPV4_UDP_PACKET, LinkLayer::RawIp).unwrap();
+        assert_eq!(packet_features.source_port, 1234);
+        assert_eq!(packet_features.destination_port, 53);
+        assert_eq!(packet_features.ip_options_length, 0);
+        assert_eq!(packet_features.ip_flags, 0);
+
+        // A header length of 6 words, with 4 bytes of NOP options in front of the UDP header
+        // and the Don't Fragment bit set
+        let mut packet = IPV4_UDP_PACKET.to_vec();
+        packet[0] = 0x46;
+        packet[3] = 32;
+        packet[6] = 0x40;
+        packet.splice(20..20, [1, 1, 1, 0]);
+        let packet_features = extract(&packet, LinkLayer::RawIp).unwrap();
+        assert_eq!(packet_features.ip_options_length, 4);
+        assert_eq!(packet_features.ip_flags, IPV4_DONT_FRAGMENT);
+        assert_eq!(packet_features.destination_port, 53);
+
+        // A truncated IPv6 header is rejected
+        assert!(extract(&[0x60; 8], LinkLayer::RawIp).is_none());
+    }
+
+    #[test]
+    fn test_extract_packet_features_pppoe() {
+        // Ethernet with the PPPoE session EtherType, the session header and PPP protocol IPv4
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&0x8864u16.to_be_bytes());
+        frame.extend_from_slice(&[0x11, 0x00, 0x12, 0x34, 0x00, 30, 0x00, 0x21]);
+        frame.extend_from_slice(&IPV4_UDP_PACKET);
+
+        let packet_features = extract(&frame, LinkLayer::Ethernet).unwrap();
+        assert_eq!(packet_features.source_port, 1234);
+        assert_eq!(packet_features.destination_port, 53);
+        assert_eq!(packet_features.length, 28);
+
+        // LCP frames carry no IP packet, a discovery code isn't session data
+        frame[20..22].copy_from_slice(&0xc021u16.to_be_bytes());
+        assert!(extract(&frame, LinkLayer::Ethernet).is_none());
+        frame[15] = 0x09;
+        assert!(extract(&frame[..18], LinkLayer::Ethernet).is_none());
+    }
+
+    #[test]
+    fn test_extract_packet_features_vlan() {
+        // Ethernet with an 802.1ad tag of VLAN 100 and an 802.1Q tag of VLAN 200
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x88, 0xa8, 0x00, 0x64, 0x81, 0x00, 0x20, 0xc8, 0x08, 0x00]);
+        frame.extend_from_slice(&IPV4_UDP_PACKET);
+
+        let packet_features = extract(&frame, LinkLayer::Ethernet).unwrap();
+        assert_eq!(packet_features.vlan_id, Some(100));
+        assert_eq!(packet_features.source_port, 1234);
+        assert_eq!(packet_features.destination_port, 53);
+
+        // Without the outer tag the inner one gives the VLAN id, untagged frames have none
+        let packet_features = extract(&frame[4..], LinkLayer::Ethernet).unwrap();
+        assert_eq!(packet_features.vlan_id, Some(200));
+        let packet_features = extract(&frame[8..], LinkLayer::Ethernet).unwrap();
+        assert_eq!(packet_features.vlan_id, None);
+
+        // A truncated tag is rejected
+        assert!(extract(&frame[..20], LinkLayer::Ethernet).is_none());
+    }
+
+    #[test]
+    fn test_extract_packet_features_short_frame() {
+        assert!(extract(&[0u8; 15], LinkLayer::LinuxCooked).is_none());
+        assert!(extract(&[], LinkLayer::RawIp).is_none());
+    }
 
-        tokio::spawn(async move {
-            while let Some(packet_features) = rx.recv().await {
-                flow_table.process_packet(&packet_features).await;
+    #[test]
+    fn test_extract_packet_features_truncated_and_random() {
+        // A TCP SYN with a 24 byte header (MSS option) after an IPv4 header with options
+        let mut packet = vec![0x46, 0, 0, 48, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        packet.extend_from_slice(&[0x01, 0x04, 0x00, 0x00]);
+        packet.extend_from_slice(&[0x04, 0xd2, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[0x60, 0x02, 0xff, 0xff, 0, 0, 0, 0, 0x02, 0x04, 0x05, 0xb4]);
+
+        let features = extract(&packet, LinkLayer::RawIp).unwrap();
+        assert_eq!(features.source_port, 1234);
+        assert_eq!(features.header_length, 24);
+        assert_eq!(features.data_length, 0);
+        assert_eq!(features.tcp_options_mask, tcp_option_bit(TCP_OPTION_MSS));
+
+        // Every truncation is either rejected or parsed without panicking
+        let malformed_before = metrics::malformed_packets();
+        for length in 0..packet.len() {
+            assert!(extract(&packet[..length], LinkLayer::RawIp).is_none());
+        }
+        assert!(metrics::malformed_packets() > malformed_before);
+
+        // Random bytes behind all link layers, with the version nibbles set to reach the parsers
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for round in 0..2000 {
+            let mut frame: Vec<u8> = (0..(round % 120))
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            if let Some(first) = frame.first_mut() {
+                *first = (*first & 0x0f) | if round % 2 == 0 { 0x40 } else { 0x60 };
             }
-            // Handle flow exporting when the receiver is closed
-            flow_table.export_all_flows().await;
-        });
-        shard_senders.push(tx);
+            for link_layer in [LinkLayer::Ethernet, LinkLayer::LinuxCooked, LinkLayer::RawIp] {
+                if let Some(features) = extract(&frame, link_layer) {
+                    assert!(features.length as usize >= features.data_length as usize);
+                }
+            }
+        }
     }
-    debug!("Sharded FlowTables created");
 
-    shard_senders
+    #[test]
+    fn test_compressed_capture() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(&[0xd4, 0xc3, 0xb2, 0xa1]), None);
+        assert_eq!(Compression::from_extension("a.pcapng.zst"), Some(Compression::Zstd));
+
+        let capture = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/cic_parity.pcap"
+        ))
+        .unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&capture).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(capture.as_slice(), 0).unwrap();
+
+        let directory = std::env::temp_dir();
+        let read = |name: &str, content: &[u8]| {
+            let path = directory.join(name);
+            std::fs::write(&path, content).unwrap();
+            let result = open_capture(path.to_str().unwrap()).map(|(mut capture, decompressor)| {
+                let mut packets = 0;
+                while capture.next_packet().is_ok() {
+                    packets += 1;
+                }
+                drop(capture);
+                (packets, decompressor.map(|decompressor| decompressor.join().unwrap()))
+            });
+            std::fs::remove_file(&path).unwrap();
+            result
+        };
+
+        let (packets, decompressed) = read("rustiflow_test_capture.pcap", &capture).unwrap();
+        assert_eq!(packets, 13);
+        assert!(decompressed.is_none());
+        for (name, content) in [
+            ("rustiflow_test_capture.gz", &gzip),
+            ("rustiflow_test_capture.bin", &zstd),
+        ] {
+            let (packets, decompressed) = read(name, content).unwrap();
+            assert_eq!(packets, 13);
+            assert!(decompressed.unwrap().is_ok());
+        }
+
+        // A truncated file keeps the packets before the damage, the decompression fails
+        let (packets, decompressed) =
+            read("rustiflow_test_truncated.gz", &gzip[..gzip.len() / 2]).unwrap();
+        assert!(packets < 13);
+        assert!(decompressed.unwrap().is_err());
+        let error = read("rustiflow_test_plain.pcap.gz", &capture).unwrap_err();
+        assert!(error.to_string().contains("isn't one"));
+    }
 }
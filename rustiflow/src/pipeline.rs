@@ -0,0 +1,391 @@
+//! The flow pipeline of the captures, also for embedding RustiFlow as a library: packets go
+//! in, the flows come out at an output writer.
+//!
+//! The captures (see `pcap` and `realtime`) push the packets they read, a library takes them
+//! from its caller instead and can flush the pipeline at any point, e.g. at the end of a batch.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use log::{debug, error};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+};
+
+use crate::{
+    export_error::{self, ExportErrorHandler},
+    flow_table::FlowTable,
+    flows::{flow::Flow, settings::FlowSettings},
+    output::{self, OutputWriter},
+    packet_features::PacketFeatures,
+    reload::ReloadableConfig,
+};
+
+// Capacities of the channels to the flow tables and to the output writer
+const SHARD_CHANNEL_CAPACITY: usize = 10_000;
+const EXPORT_CHANNEL_CAPACITY: usize = 1000;
+
+/// What the flow table of a shard is asked to do.
+enum ShardMessage {
+    Packet(PacketFeatures),
+    /// Export all flows, then reply.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Passes packets to the flow tables of a pipeline, from any task or thread.
+///
+/// The flow tables only export their last flows once the pipeline and all of its senders are
+/// dropped, see `Pipeline::shutdown`.
+#[derive(Clone)]
+pub struct PacketSender {
+    settings: Arc<FlowSettings>,
+    shard_senders: Vec<mpsc::Sender<ShardMessage>>,
+}
+
+impl PacketSender {
+    /// Passes a packet to the flow table of its flow.
+    ///
+    /// Waits while the flow table is busy with the packets before.
+    pub async fn send(&self, packet: PacketFeatures) -> Result<(), anyhow::Error> {
+        let shard_index = self.shard_index(&packet);
+        self.shard_senders[shard_index]
+            .send(ShardMessage::Packet(packet))
+            .await
+            .map_err(|_| anyhow!("The flow table of shard {} stopped", shard_index))
+    }
+
+    /// Passes a packet like `send`, blocking the thread. Must not be called within a tokio
+    /// runtime, e.g. on the threads reading the captures.
+    pub fn blocking_send(&self, packet: PacketFeatures) -> Result<(), anyhow::Error> {
+        let shard_index = self.shard_index(&packet);
+        self.shard_senders[shard_index]
+            .blocking_send(ShardMessage::Packet(packet))
+            .map_err(|_| anyhow!("The flow table of shard {} stopped", shard_index))
+    }
+
+    fn shard_index(&self, packet: &PacketFeatures) -> usize {
+        compute_shard_index(
+            &packet.biflow_key(self.settings.flow_key),
+            self.shard_senders.len() as u8,
+        )
+    }
+}
+
+/// Sharded flow tables feeding an output writer, each running in its own task.
+pub struct Pipeline<T> {
+    sender: PacketSender,
+    shard_tasks: Vec<JoinHandle<()>>,
+    flush_sender: mpsc::Sender<oneshot::Sender<std::io::Result<()>>>,
+    output_task: JoinHandle<std::io::Result<()>>,
+    _phantom_data: std::marker::PhantomData<T>,
+}
+
+impl<T> Pipeline<T>
+where
+    T: Flow,
+{
+    /// Starts the flow tables and the output writer.
+    ///
+    /// Must be called within a tokio runtime. The flows are built with the settings of the
+    /// output writer (see `OutputWriter::new`), so they match the header it writes.
+    ///
+    /// ### Arguments
+    ///
+    /// * `num_shards` - The number of flow tables, the flows are spread by their 5-tuple.
+    /// * `config` - The timeouts and flow splitting settings of the flow tables.
+    /// * `output_writer` - Where the flows are written, after `init` wrote the header.
+    pub fn new(num_shards: u8, config: ReloadableConfig, output_writer: OutputWriter<T>) -> Self {
+        Self::with_error_handler(
+            num_shards,
+            config,
+            output_writer,
+            export_error::default_handler(),
+        )
+    }
+
+    /// Starts the pipeline like `new`, the flows that can't be exported go to `error_handler`
+    /// instead of the log.
+    ///
+    /// The handler is called from the tasks of the flow tables and of the output writer, e.g.
+    /// with `ExportError::Write` for every flow the output failed to write. The pipeline
+    /// keeps running, the handler may have it shut down.
+    pub fn with_error_handler(
+        num_shards: u8,
+        config: ReloadableConfig,
+        output_writer: OutputWriter<T>,
+        error_handler: ExportErrorHandler,
+    ) -> Self {
+        // The configuration can't change, so the sender is dropped right away
+        let (_, config_updates) = watch::channel(config);
+        Self::with_config_updates(num_shards, config_updates, output_writer, error_handler)
+    }
+
+    /// Starts the pipeline like `with_error_handler`, the flow tables start with the current
+    /// configuration of `config_updates` and follow its later reloads.
+    ///
+    /// The output writer follows the reloads it watches itself, see
+    /// `OutputWriter::watch_config`.
+    pub fn with_config_updates(
+        num_shards: u8,
+        config_updates: watch::Receiver<ReloadableConfig>,
+        mut output_writer: OutputWriter<T>,
+        error_handler: ExportErrorHandler,
+    ) -> Self {
+        let (export_sender, mut export_receiver) = mpsc::channel::<T>(EXPORT_CHANNEL_CAPACITY);
+        let (flush_sender, mut flush_receiver) =
+            mpsc::channel::<oneshot::Sender<std::io::Result<()>>>(1);
+
+        let settings = Arc::clone(output_writer.settings());
+        let output_error_handler = error_handler.clone();
+        let output_task = tokio::spawn(async move {
+            let mut tick = tokio::time::interval(output::TICK_INTERVAL);
+            loop {
+                tokio::select! {
+                    // Write the flows exported before a flush request first
+                    biased;
+                    flow = export_receiver.recv() => match flow {
+                        Some(flow) => {
+                            if let Err(e) = output_writer.write_flow(flow).await {
+                                export_error::report(&output_error_handler, e);
+                            }
+                        }
+                        None => break,
+                    },
+                    Some(reply) = flush_receiver.recv() => {
+                        while let Ok(flow) = export_receiver.try_recv() {
+                            if let Err(e) = output_writer.write_flow(flow).await {
+                                export_error::report(&output_error_handler, e);
+                            }
+                        }
+                        let _ = reply.send(output_writer.flush().await);
+                    }
+                    _ = tick.tick() => {
+                        if let Err(e) = output_writer.tick().await {
+                            error!("{}", e);
+                        }
+                    }
+                }
+            }
+            output_writer.flush_and_close().await
+        });
+
+        debug!("Creating {} sharded FlowTables...", num_shards);
+        let config = config_updates.borrow().clone();
+        let mut shard_senders = Vec::with_capacity(num_shards as usize);
+        let mut shard_tasks = Vec::with_capacity(num_shards as usize);
+        for _ in 0..num_shards {
+            let (sender, mut receiver) = mpsc::channel::<ShardMessage>(SHARD_CHANNEL_CAPACITY);
+            let mut flow_table = FlowTable::new(
+                Arc::clone(&settings),
+                config.active_timeout,
+                config.idle_timeout,
+                config.early_export,
+                export_sender.clone(),
+                config.expiration_check_interval,
+            );
+            flow_table.apply_config(&config);
+            flow_table.watch_config(config_updates.clone());
+            flow_table.on_export_error(error_handler.clone());
+
+            shard_tasks.push(tokio::spawn(async move {
+                while let Some(message) = receiver.recv().await {
+                    match message {
+                        ShardMessage::Packet(packet) => flow_table.process_packet(&packet).await,
+                        ShardMessage::Flush(reply) => {
+                            flow_table.export_all_flows().await;
+                            let _ = reply.send(());
+                        }
+                    }
+                }
+                flow_table.export_all_flows().await;
+            }));
+            shard_senders.push(sender);
+        }
+
+        Pipeline {
+            sender: PacketSender {
+                settings,
+                shard_senders,
+            },
+            shard_tasks,
+            flush_sender,
+            output_task,
+            _phantom_data: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns the settings the flows are built with, those of the output writer.
+    pub fn settings(&self) -> &Arc<FlowSettings> {
+        &self.sender.settings
+    }
+
+    /// Passes a packet to the flow table of its flow, see `PacketSender::send`.
+    pub async fn push(&self, packet: PacketFeatures) -> Result<(), anyhow::Error> {
+        self.sender.send(packet).await
+    }
+
+    /// Returns a sender passing packets into the pipeline from other tasks or threads.
+    pub fn sender(&self) -> PacketSender {
+        self.sender.clone()
+    }
+
+    /// Exports all active flows and waits until the output writer has durably written them.
+    ///
+    /// The flows end like at the end of a capture, later packets of their connections start
+    /// new flows. The pipeline keeps running afterwards.
+    pub async fn flush(&self) -> Result<(), anyhow::Error> {
+        // The flow tables only reply once their flows are in the export channel
+        for (shard_index, sender) in self.sender.shard_senders.iter().enumerate() {
+            let (reply_sender, reply) = oneshot::channel();
+            sender
+                .send(ShardMessage::Flush(reply_sender))
+                .await
+                .map_err(|_| anyhow!("The flow table of shard {} stopped", shard_index))?;
+            reply
+                .await
+                .map_err(|_| anyhow!("The flow table of shard {} stopped", shard_index))?;
+        }
+
+        let (reply_sender, reply) = oneshot::channel();
+        self.flush_sender
+            .send(reply_sender)
+            .await
+            .map_err(|_| anyhow!("The output writer stopped"))?;
+        reply.await.map_err(|_| anyhow!("The output writer stopped"))??;
+        debug!("Pipeline flushed");
+        Ok(())
+    }
+
+    /// Exports all active flows, then flushes and closes the output writer.
+    pub async fn shutdown(self) -> Result<(), anyhow::Error> {
+        // Closing the channels makes the flow tables export their flows and stop
+        drop(self.sender);
+        for shard_task in self.shard_tasks {
+            shard_task.await?;
+        }
+        // The writer stops once the last flow table dropped its end of the export channel
+        drop(self.flush_sender);
+        self.output_task.await??;
+        debug!("Pipeline shut down");
+        Ok(())
+    }
+
+    /// Shuts the pipeline down like `shutdown`, waiting at most `shutdown_timeout` seconds for
+    /// the output, see `output::wait_for_output`. Errors are logged.
+    pub async fn shutdown_within(self, shutdown_timeout: Option<u64>) {
+        drop(self.sender);
+        drop(self.flush_sender);
+        // The output finishes once the flow tables exported their last flows
+        output::wait_for_output(self.output_task, shutdown_timeout).await;
+        // Done unless the output was aborted, their flows are dropped then like those in the
+        // channel
+        for shard_task in self.shard_tasks {
+            shard_task.abort();
+        }
+    }
+}
+
+/// Returns the shard of a flow by its bidirectional flow key.
+pub fn compute_shard_index(flow_key: &str, num_shards: u8) -> usize {
+    assert!(num_shards > 0, "num_shards must be greater than 0");
+    let mut hasher = DefaultHasher::new();
+    flow_key.hash(&mut hasher);
+    let hash = hasher.finish();
+    (hash % num_shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::Pipeline;
+    use crate::{
+        args::{ClickHouseConfig, ExportConfig, ExportMethodType, FlowType, OutputConfig},
+        flows::basic_flow::BasicFlow,
+        output::OutputWriter,
+        packet_features::PacketFeatures,
+        reload::ReloadableConfig,
+    };
+
+    /// A UDP packet from 10.0.0.1 on the given port to 10.0.0.2:53.
+    fn setup_packet(source_port: u16, timestamp: DateTime<Utc>) -> PacketFeatures {
+        PacketFeatures {
+            source_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            destination_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            source_port,
+            destination_port: 53,
+            protocol: 17,
+            timestamp,
+            fin_flag: 0,
+            syn_flag: 0,
+            rst_flag: 0,
+            psh_flag: 0,
+            ack_flag: 0,
+            urg_flag: 0,
+            cwe_flag: 0,
+            ece_flag: 0,
+            data_length: 40,
+            header_length: 8,
+            length: 68,
+            window_size: 0,
+            sequence_number: 0,
+            sequence_number_ack: 0,
+            extension_header_count: 0,
+            ttl: 64,
+            ip_options_length: 0,
+            ip_flags: 0,
+            tcp_options_mask: 0,
+            flow_label: 0,
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_and_shutdown() {
+        let path = std::env::temp_dir().join("rustiflow_test_pipeline.csv");
+        let mut output_writer = OutputWriter::<BasicFlow>::new(
+            Arc::default(),
+            FlowType::Basic,
+            ExportMethodType::Csv,
+            true,
+            false,
+            0,
+            Some(path.to_str().unwrap().to_string()),
+            ClickHouseConfig::default(),
+            None,
+        );
+        output_writer.init().await;
+        let config = ReloadableConfig::new(&ExportConfig::default(), &OutputConfig::default());
+        let pipeline = Pipeline::new(4, config, output_writer);
+        let read_lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for source_port in 1000..1010 {
+            pipeline.push(setup_packet(source_port, start)).await.unwrap();
+        }
+        pipeline.flush().await.unwrap();
+        // The header and a record per flow, although none of them timed out
+        assert_eq!(read_lines(), 11);
+
+        // Later packets of the flushed flows start new flows
+        let later = start + TimeDelta::seconds(1);
+        for source_port in 1000..1005 {
+            pipeline.push(setup_packet(source_port, later)).await.unwrap();
+        }
+        pipeline.shutdown().await.unwrap();
+        assert_eq!(read_lines(), 16);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Length-delimited Protocol Buffers encoding of the exported flows, the `Protobuf` output.
+//!
+//! A flow is encoded as a message of the schema printed by `rustiflow schema --format proto`,
+//! a field per column numbered from 1. Every message is preceded by its length as a varint,
+//! like `writeDelimitedTo` of the Protocol Buffers libraries, so a file or a stream of them can
+//! be read message by message. The messages are built from the dumped records, so they hold the
+//! same values as the CSV output.
+
+use std::io::{self, Read};
+
+use log::warn;
+
+use crate::{
+    clickhouse::{read_varint, write_varint},
+    flows::{flow::Flow, settings::FlowSettings},
+    schema::{self, ColumnType},
+};
+
+// The wire types of the field keys
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+
+/// Encodes the dumped records of a feature set as Protocol Buffers messages.
+pub struct ProtobufEncoder {
+    /// The type of each column and whether a value of another type was left out of it.
+    columns: Vec<(ColumnType, bool)>,
+    null_value: &'static str,
+    message: Vec<u8>,
+}
+
+impl ProtobufEncoder {
+    /// Creates the encoder for the columns of the feature set `T`, see `schema::columns`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `settings` - The settings of the run.
+    /// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+    pub fn new<T: Flow>(settings: &FlowSettings, skip_contaminant_features: bool) -> Self {
+        ProtobufEncoder {
+            columns: schema::columns::<T>(settings, skip_contaminant_features)
+                .into_iter()
+                .map(|column| (column.column_type, false))
+                .collect(),
+            null_value: settings.null_value.as_str(),
+            message: Vec::new(),
+        }
+    }
+
+    /// Appends the length-delimited message of a dumped record to `out`.
+    ///
+    /// Missing values are left out of the message. A value that doesn't fit the type of its
+    /// column in the schema is left out as well, with a warning for the first one of a column.
+    pub fn encode(&mut self, record: &str, out: &mut Vec<u8>) {
+        self.message.clear();
+        let message = &mut self.message;
+        for (index, (value, (column_type, warned))) in
+            record.split(',').zip(self.columns.iter_mut()).enumerate()
+        {
+            if value == self.null_value {
+                continue;
+            }
+            let field = index as u64 + 1;
+            let encoded = match column_type {
+                ColumnType::Boolean => value.parse::<bool>().ok().map(|value| {
+                    write_varint(message, field << 3 | WIRE_VARINT);
+                    write_varint(message, value as u64);
+                }),
+                ColumnType::Integer => value.parse::<i64>().ok().map(|value| {
+                    // sint64, zigzag encoded
+                    write_varint(message, field << 3 | WIRE_VARINT);
+                    write_varint(message, ((value << 1) ^ (value >> 63)) as u64);
+                }),
+                ColumnType::Number => value.parse::<f64>().ok().map(|value| {
+                    write_varint(message, field << 3 | WIRE_FIXED64);
+                    message.extend(value.to_le_bytes());
+                }),
+                ColumnType::String => {
+                    write_varint(message, field << 3 | WIRE_LENGTH_DELIMITED);
+                    write_varint(message, value.len() as u64);
+                    message.extend(value.as_bytes());
+                    Some(())
+                }
+            };
+            if encoded.is_none() && !*warned {
+                *warned = true;
+                warn!(
+                    "The value {} of column {} isn't of its type in the schema, it is left out",
+                    value, field
+                );
+            }
+        }
+
+        write_varint(out, self.message.len() as u64);
+        out.extend(&self.message);
+    }
+}
+
+/// Decodes the messages of the `Protobuf` output back into dumped records, to replay them.
+pub struct ProtobufDecoder {
+    columns: Vec<ColumnType>,
+    null_value: &'static str,
+}
+
+impl ProtobufDecoder {
+    /// Creates the decoder for the columns of the feature set `T`, like `ProtobufEncoder::new`.
+    pub fn new<T: Flow>(settings: &FlowSettings, skip_contaminant_features: bool) -> Self {
+        ProtobufDecoder {
+            columns: schema::columns::<T>(settings, skip_contaminant_features)
+                .into_iter()
+                .map(|column| column.column_type)
+                .collect(),
+            null_value: settings.null_value.as_str(),
+        }
+    }
+
+    /// Reads the next length-delimited message, `None` at the end of the input.
+    pub fn read_message(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+        let mut first = [0; 1];
+        if reader.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let length = read_varint(&mut first.chain(&mut *reader))?;
+        let mut message = vec![0; length as usize];
+        reader.read_exact(&mut message)?;
+        Ok(Some(message))
+    }
+
+    /// Returns the dumped record of a message, the values left out are the null value.
+    pub fn decode(&self, message: &[u8]) -> io::Result<String> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let mut values = vec![None; self.columns.len()];
+        let mut reader = message;
+        while !reader.is_empty() {
+            let key = read_varint(&mut reader)?;
+            let field = (key >> 3) as usize;
+            let Some(column_type) = field.checked_sub(1).and_then(|index| self.columns.get(index))
+            else {
+                return Err(invalid(format!("field {} isn't a column of the features", field)));
+            };
+            let value = match (column_type, key & 7) {
+                (ColumnType::Boolean, WIRE_VARINT) => (read_varint(&mut reader)? != 0).to_string(),
+                (ColumnType::Integer, WIRE_VARINT) => {
+                    // sint64, zigzag encoded
+                    let value = read_varint(&mut reader)?;
+                    ((value >> 1) as i64 ^ -((value & 1) as i64)).to_string()
+                }
+                (ColumnType::Number, WIRE_FIXED64) => {
+                    let mut bytes = [0; 8];
+                    reader.read_exact(&mut bytes)?;
+                    f64::from_le_bytes(bytes).to_string()
+                }
+                (ColumnType::String, WIRE_LENGTH_DELIMITED) => {
+                    let length = read_varint(&mut reader)? as usize;
+                    if length > reader.len() {
+                        return Err(invalid(format!("field {} is cut off", field)));
+                    }
+                    let (value, rest) = reader.split_at(length);
+                    reader = rest;
+                    String::from_utf8(value.to_vec())
+                        .map_err(|_| invalid(format!("field {} isn't UTF-8", field)))?
+                }
+                (_, wire_type) => {
+                    return Err(invalid(format!(
+                        "field {} has the wire type {}, not the one of its column",
+                        field, wire_type
+                    )))
+                }
+            };
+            values[field - 1] = Some(value);
+        }
+
+        let values: Vec<&str> = values
+            .iter()
+            .map(|value| value.as_deref().unwrap_or(self.null_value))
+            .collect();
+        Ok(values.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProtobufDecoder, ProtobufEncoder};
+    use crate::schema::ColumnType;
+
+    #[test]
+    fn test_encode() {
+        let mut encoder = ProtobufEncoder {
+            columns: vec![
+                (ColumnType::String, false),
+                (ColumnType::Integer, false),
+                (ColumnType::Number, false),
+                (ColumnType::Boolean, false),
+                (ColumnType::Integer, false),
+            ],
+            null_value: "",
+            message: Vec::new(),
+        };
+
+        let mut out = Vec::new();
+        encoder.encode("ab,-2,1.5,true,", &mut out);
+        let mut expected = vec![17, 0x0a, 2, b'a', b'b', 0x10, 3, 0x19];
+        expected.extend(1.5f64.to_le_bytes());
+        expected.extend([0x20, 1]);
+        assert_eq!(out, expected);
+
+        // Missing values and values of another type are left out
+        out.clear();
+        encoder.encode(",0.5,,false,300", &mut out);
+        assert_eq!(out, vec![5, 0x20, 0, 0x28, 0xd8, 0x04]);
+        assert!(encoder.columns[1].1);
+    }
+
+    #[test]
+    fn test_decode() {
+        let columns = vec![
+            ColumnType::String,
+            ColumnType::Integer,
+            ColumnType::Number,
+            ColumnType::Boolean,
+            ColumnType::Integer,
+        ];
+        let mut encoder = ProtobufEncoder {
+            columns: columns.iter().map(|&column_type| (column_type, false)).collect(),
+            null_value: "",
+            message: Vec::new(),
+        };
+        let decoder = ProtobufDecoder {
+            columns,
+            null_value: "",
+        };
+
+        let mut out = Vec::new();
+        encoder.encode("ab,-2,1.5,true,", &mut out);
+        encoder.encode(",0,,false,300", &mut out);
+        let mut reader = out.as_slice();
+        let mut records = Vec::new();
+        while let Some(message) = ProtobufDecoder::read_message(&mut reader).unwrap() {
+            records.push(decoder.decode(&message).unwrap());
+        }
+        assert_eq!(records, ["ab,-2,1.5,true,", ",0,,false,300"]);
+
+        // A field beyond the columns or of another wire type isn't a message of these features
+        assert!(decoder.decode(&[0x30, 1]).is_err());
+        assert!(decoder.decode(&[0x08, 1]).is_err());
+    }
+}
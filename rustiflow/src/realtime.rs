@@ -1,111 +1,115 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
-
-use crate::{flow_table::FlowTable, flows::flow::Flow, packet_features::PacketFeatures};
+use std::time::Duration;
+
+use crate::{
+    flows::flow::Flow,
+    interfaces::check_interface,
+    metrics,
+    packet_features::PacketFeatures,
+    pipeline::Pipeline,
+};
 use aya::maps::PerCpuValues;
 use aya::{
     include_bytes_aligned,
     maps::{PerCpuArray, RingBuf},
     programs::{tc, SchedClassifier, TcAttachType},
-    Ebpf,
+    Ebpf, EbpfLoader,
 };
 use aya_log::EbpfLogger;
+use anyhow::anyhow;
+use chrono::TimeDelta;
 use common::{EbpfEventIpv4, EbpfEventIpv6};
 use log::{debug, error, info};
-use tokio::{
-    io::unix::AsyncFd,
-    signal,
-    sync::mpsc::{self, Sender},
-    task::JoinSet,
-};
-
-/// Starts the realtime processing of packets on the given interface.
+use tokio::{io::unix::AsyncFd, signal, task::JoinSet};
+
+// Linux capability numbers, see capabilities(7)
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+// Ethernet header length, where the IP header starts on most interfaces
+const ETHERNET_HEADER_LENGTH: u32 = 14;
+// Link layer types without a header in front of IP, see if_arp.h
+pub(crate) const ARPHRD_PPP: u16 = 512;
+pub(crate) const ARPHRD_RAWIP: u16 = 519;
+pub(crate) const ARPHRD_TUNNEL: u16 = 768;
+pub(crate) const ARPHRD_TUNNEL6: u16 = 769;
+pub(crate) const ARPHRD_SIT: u16 = 776;
+pub(crate) const ARPHRD_IPGRE: u16 = 778;
+pub(crate) const ARPHRD_NONE: u16 = 65534;
+
+/// Starts the realtime processing of packets on the given interface into the flow tables of a
+/// pipeline.
 /// The function will return the number of packets dropped by the eBPF program.
 pub async fn handle_realtime<T>(
+    pipeline: &Pipeline<T>,
     interface: &str,
-    output_channel: Sender<T>,
-    num_threads: u8,
-    active_timeout: u64,
-    idle_timeout: u64,
-    early_export: Option<u64>,
-    expiration_check_interval: u64,
     ingress_only: bool,
+    ring_buffer_size: u32,
 ) -> Result<u64, anyhow::Error>
 where
     T: Flow,
 {
+    // Fail early with an actionable message instead of an obscure load error
+    check_interface(interface)?;
+    let missing_capabilities = get_missing_capabilities();
+    if !missing_capabilities.is_empty() {
+        return Err(anyhow!(privileges_hint(&missing_capabilities)));
+    }
+
     // Needed for older kernels
     bump_memlock_rlimit();
 
-    // Load the eBPF programs and attach to the event arrays
-    let mut bpf_ingress_ipv4 = load_ebpf_ipv4(interface, TcAttachType::Ingress)?;
-    let mut bpf_ingress_ipv6 = load_ebpf_ipv6(interface, TcAttachType::Ingress)?;
-    let events_ingress_ipv4 = RingBuf::try_from(bpf_ingress_ipv4.take_map("EVENTS_IPV4").unwrap())?;
-    let dropped_packets_ingress_ipv4 =
-        PerCpuArray::try_from(bpf_ingress_ipv4.take_map("DROPPED_PACKETS").unwrap())?;
-    let events_ingress_ipv6 = RingBuf::try_from(bpf_ingress_ipv6.take_map("EVENTS_IPV6").unwrap())?;
-    let dropped_packets_ingress_ipv6 =
-        PerCpuArray::try_from(bpf_ingress_ipv6.take_map("DROPPED_PACKETS").unwrap())?;
-    let event_sources_v4;
-    let event_sources_v6;
-    let dropped_packet_counters;
-
-    if !ingress_only {
-        let mut bpf_egress_ipv4 = load_ebpf_ipv4(interface, TcAttachType::Egress)?;
-        let mut bpf_egress_ipv6 = load_ebpf_ipv6(interface, TcAttachType::Egress)?;
-        let events_egress_ipv4 =
-            RingBuf::try_from(bpf_egress_ipv4.take_map("EVENTS_IPV4").unwrap())?;
-        let dropped_packets_egress_ipv4 =
-            PerCpuArray::try_from(bpf_egress_ipv4.take_map("DROPPED_PACKETS").unwrap())?;
-        let events_egress_ipv6 =
-            RingBuf::try_from(bpf_egress_ipv6.take_map("EVENTS_IPV6").unwrap())?;
-        let dropped_packets_egress_ipv6 =
-            PerCpuArray::try_from(bpf_egress_ipv6.take_map("DROPPED_PACKETS").unwrap())?;
-        event_sources_v4 = vec![events_egress_ipv4, events_ingress_ipv4];
-        event_sources_v6 = vec![events_egress_ipv6, events_ingress_ipv6];
-        dropped_packet_counters = vec![
-            dropped_packets_egress_ipv4,
-            dropped_packets_ingress_ipv4,
-            dropped_packets_egress_ipv6,
-            dropped_packets_ingress_ipv6,
-        ];
-    } else {
-        event_sources_v4 = vec![events_ingress_ipv4];
-        event_sources_v6 = vec![events_ingress_ipv6];
-        dropped_packet_counters = vec![dropped_packets_ingress_ipv4, dropped_packets_ingress_ipv6];
-    }
+    let ip_header_offset = get_ip_header_offset(interface);
+    debug!("IP header offset on {}: {}", interface, ip_header_offset);
 
-    let buffer_num_packets = 10_000;
-    let mut shard_senders = Vec::with_capacity(num_threads as usize);
-
-    debug!("Creating {} sharded FlowTables...", num_threads);
-    for _ in 0..num_threads {
-        let (tx, mut rx) = mpsc::channel::<PacketFeatures>(buffer_num_packets);
-        let mut flow_table = FlowTable::new(
-            active_timeout,
-            idle_timeout,
-            early_export,
-            output_channel.clone(),
-            expiration_check_interval,
-        );
-
-        // Spawn a task per shard
-        tokio::spawn(async move {
-            while let Some(packet_features) = rx.recv().await {
-                flow_table.process_packet(&packet_features).await;
-            }
-            debug!("Shard finished processing packets");
-            // Handle flow exporting when the receiver is closed
-            flow_table.export_all_flows().await;
-        });
-        shard_senders.push(tx);
+    // Every loaded program gets its own ring buffer of this size, the size is validated
+    // to fit the u32 of the kernel. aya rounds it up to a power-of-two number of pages.
+    let ring_buffer_bytes = ring_buffer_size * 1024 * 1024;
+
+    // Load the eBPF programs and attach to the event arrays, the programs stay attached
+    // as long as they are loaded
+    let attach_types = if ingress_only {
+        vec![TcAttachType::Ingress]
+    } else {
+        vec![TcAttachType::Egress, TcAttachType::Ingress]
+    };
+    let ip_version = pipeline.settings().ip_version;
+    let mut loaded_programs = Vec::new();
+    let mut event_sources_v4 = Vec::new();
+    let mut event_sources_v6 = Vec::new();
+    let mut dropped_packet_counters = Vec::new();
+    let mut malformed_packet_counters = Vec::new();
+
+    for attach_type in attach_types {
+        // A disabled IP version gets no program, so the kernel doesn't process its packets
+        if ip_version.includes_ipv4() {
+            let mut bpf_ipv4 =
+                load_ebpf_ipv4(interface, attach_type, ip_header_offset, ring_buffer_bytes)?;
+            event_sources_v4.push(RingBuf::try_from(bpf_ipv4.take_map("EVENTS_IPV4").unwrap())?);
+            dropped_packet_counters
+                .push(PerCpuArray::try_from(bpf_ipv4.take_map("DROPPED_PACKETS").unwrap())?);
+            malformed_packet_counters
+                .push(PerCpuArray::try_from(bpf_ipv4.take_map("MALFORMED_PACKETS").unwrap())?);
+            loaded_programs.push(bpf_ipv4);
+        }
+        if ip_version.includes_ipv6() {
+            let mut bpf_ipv6 =
+                load_ebpf_ipv6(interface, attach_type, ip_header_offset, ring_buffer_bytes)?;
+            event_sources_v6.push(RingBuf::try_from(bpf_ipv6.take_map("EVENTS_IPV6").unwrap())?);
+            dropped_packet_counters
+                .push(PerCpuArray::try_from(bpf_ipv6.take_map("DROPPED_PACKETS").unwrap())?);
+            malformed_packet_counters
+                .push(PerCpuArray::try_from(bpf_ipv6.take_map("MALFORMED_PACKETS").unwrap())?);
+            loaded_programs.push(bpf_ipv6);
+        }
     }
-    debug!("Sharded FlowTables created");
+    debug!("Loaded {} eBPF programs", loaded_programs.len());
 
     // Spawn a task per event source
     let mut handle_set = JoinSet::new();
 
     for ebpf_event_source in event_sources_v4 {
-        let shard_senders_clone = shard_senders.clone();
+        let sender = pipeline.sender();
         handle_set.spawn(async move {
             // Wrap the RingBuf in AsyncFd to poll it with tokio
             let mut async_ring_buf = AsyncFd::new(ebpf_event_source).unwrap();
@@ -116,17 +120,16 @@ where
 
                 let ring_buf = guard.get_inner_mut();
                 while let Some(event) = ring_buf.next() {
-                    let ebpf_event_ipv4: EbpfEventIpv4 =
-                        unsafe { std::ptr::read(event.as_ptr() as *const _) };
-                    let packet_features = PacketFeatures::from_ebpf_event_ipv4(&ebpf_event_ipv4);
-                    let flow_key = packet_features.biflow_key();
-                    let shard_index = compute_shard_index(&flow_key, num_threads);
-
-                    if let Err(e) = shard_senders_clone[shard_index].send(packet_features).await {
-                        error!(
-                            "Failed to send packet_features to shard {}: {}",
-                            shard_index, e
-                        );
+                    metrics::add_captured_packets(1);
+                    let Some(ebpf_event_ipv4) = EbpfEventIpv4::from_bytes(&event) else {
+                        error!("Skipping a truncated IPv4 event of {} bytes", event.len());
+                        continue;
+                    };
+                    let mut packet_features =
+                        PacketFeatures::from_ebpf_event_ipv4(&ebpf_event_ipv4);
+                    stamp_capture(&mut packet_features, ebpf_event_ipv4.kernel_timestamp);
+                    if let Err(e) = sender.send(packet_features).await {
+                        error!("Failed to send packet_features: {}", e);
                     }
                 }
 
@@ -137,7 +140,7 @@ where
     }
 
     for ebpf_event_source in event_sources_v6 {
-        let shard_senders_clone = shard_senders.clone();
+        let sender = pipeline.sender();
 
         handle_set.spawn(async move {
             // Wrap the RingBuf in AsyncFd to poll it with tokio
@@ -149,17 +152,16 @@ where
 
                 let ring_buf = guard.get_inner_mut();
                 while let Some(event) = ring_buf.next() {
-                    let ebpf_event_ipv6: EbpfEventIpv6 =
-                        unsafe { std::ptr::read(event.as_ptr() as *const _) };
-                    let packet_features = PacketFeatures::from_ebpf_event_ipv6(&ebpf_event_ipv6);
-                    let flow_key = packet_features.biflow_key();
-                    let shard_index = compute_shard_index(&flow_key, num_threads);
-
-                    if let Err(e) = shard_senders_clone[shard_index].send(packet_features).await {
-                        error!(
-                            "Failed to send packet_features to shard {}: {}",
-                            shard_index, e
-                        );
+                    metrics::add_captured_packets(1);
+                    let Some(ebpf_event_ipv6) = EbpfEventIpv6::from_bytes(&event) else {
+                        error!("Skipping a truncated IPv6 event of {} bytes", event.len());
+                        continue;
+                    };
+                    let mut packet_features =
+                        PacketFeatures::from_ebpf_event_ipv6(&ebpf_event_ipv6);
+                    stamp_capture(&mut packet_features, ebpf_event_ipv6.kernel_timestamp);
+                    if let Err(e) = sender.send(packet_features).await {
+                        error!("Failed to send packet_features: {}", e);
                     }
                 }
 
@@ -182,6 +184,12 @@ where
         }
     }
 
+    // And the packets they skipped because of truncated or inconsistent headers
+    for malformed_packets_array in malformed_packet_counters {
+        let values: PerCpuValues<u64> = malformed_packets_array.get(&0, 0)?;
+        metrics::add_malformed_packets(values.iter().sum());
+    }
+
     // Cancel the tasks reading ebpf events
     handle_set.abort_all();
 
@@ -203,15 +211,103 @@ where
         }
     }
 
+    // Detach the programs
+    drop(loaded_programs);
+
     Ok(total_dropped)
 }
 
-fn compute_shard_index(flow_key: &str, num_shards: u8) -> usize {
-    assert!(num_shards > 0, "num_shards must be greater than 0");
-    let mut hasher = DefaultHasher::new();
-    flow_key.hash(&mut hasher);
-    let hash = hasher.finish();
-    (hash % num_shards as u64) as usize
+/// Dates a packet back to when the eBPF program saw it, like the kernel timestamps of the other
+/// captures, and records how long it took to userspace.
+fn stamp_capture(packet_features: &mut PacketFeatures, kernel_timestamp: u64) {
+    let Some(latency) = capture_latency(kernel_timestamp) else {
+        return;
+    };
+    if let Ok(delta) = TimeDelta::from_std(latency) {
+        packet_features.timestamp -= delta;
+    }
+    packet_features.capture_latency = Some(latency);
+    #[cfg(feature = "otel")]
+    crate::otel::record_capture_latency(latency);
+}
+
+/// Returns how long ago the eBPF program saw a packet, from the timestamp of its event.
+fn capture_latency(kernel_timestamp: u64) -> Option<Duration> {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // `bpf_ktime_get_ns` reads the monotonic clock
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    let now = Duration::new(u64::try_from(now.tv_sec).ok()?, u32::try_from(now.tv_nsec).ok()?);
+    now.checked_sub(Duration::from_nanos(u64::from_be(kernel_timestamp)))
+}
+
+/// Returns the capabilities needed for loading and attaching the eBPF programs that this process lacks.
+///
+/// Loading needs CAP_BPF (or CAP_SYS_ADMIN on kernels before 5.8), attaching to the
+/// interface needs CAP_NET_ADMIN. If the capabilities can't be determined, nothing is
+/// reported and loading is attempted anyway.
+pub(crate) fn get_missing_capabilities() -> Vec<&'static str> {
+    let Some(effective) = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_effective_capabilities(&status))
+    else {
+        return Vec::new();
+    };
+    let has = |capability: u32| effective & (1 << capability) != 0;
+
+    let mut missing = Vec::new();
+    if !has(CAP_BPF) && !has(CAP_SYS_ADMIN) {
+        missing.push("CAP_BPF");
+    }
+    if !has(CAP_NET_ADMIN) {
+        missing.push("CAP_NET_ADMIN");
+    }
+    missing
+}
+
+/// Parses the effective capability set from the contents of `/proc/self/status`.
+fn parse_effective_capabilities(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|value| u64::from_str_radix(value.trim(), 16).ok())
+}
+
+/// Explains which privileges are missing for realtime capture and what to do instead.
+pub(crate) fn privileges_hint(missing_capabilities: &[&str]) -> String {
+    format!(
+        "Realtime capture needs root or the CAP_BPF and CAP_NET_ADMIN capabilities to load \
+        the eBPF programs, missing: {}. Run RustiFlow with sudo, grant the capabilities with \
+        `sudo setcap cap_bpf,cap_net_admin+ep <path to rustiflow>`, use `--backend afpacket` \
+        which only needs CAP_NET_RAW, or record the traffic with a tool like tcpdump and use \
+        `rustiflow pcap <file>`, which needs no privileges.",
+        missing_capabilities.join(", ")
+    )
+}
+
+/// Returns where the IP header starts in the packets of the given interface.
+///
+/// TUN, WireGuard and other point-to-point interfaces carry no link layer header, so their
+/// packets start at the IP header. Everything else is assumed to be Ethernet.
+fn get_ip_header_offset(interface: &str) -> u32 {
+    let link_type = std::fs::read_to_string(format!("/sys/class/net/{}/type", interface))
+        .ok()
+        .and_then(|link_type| link_type.trim().parse::<u16>().ok());
+
+    match link_type {
+        Some(link_type) if is_ip_link_type(link_type) => 0,
+        _ => ETHERNET_HEADER_LENGTH,
+    }
+}
+
+/// Checks whether packets of the given ARPHRD link layer type start at the IP header.
+pub(crate) fn is_ip_link_type(link_type: u16) -> bool {
+    matches!(
+        link_type,
+        ARPHRD_PPP | ARPHRD_RAWIP | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT | ARPHRD_IPGRE
+            | ARPHRD_NONE
+    )
 }
 
 fn bump_memlock_rlimit() {
@@ -227,14 +323,23 @@ fn bump_memlock_rlimit() {
     }
 }
 
-fn load_ebpf_ipv4(interface: &str, tc_attach_type: TcAttachType) -> Result<Ebpf, anyhow::Error> {
+fn load_ebpf_ipv4(
+    interface: &str,
+    tc_attach_type: TcAttachType,
+    ip_header_offset: u32,
+    ring_buffer_bytes: u32,
+) -> Result<Ebpf, anyhow::Error> {
+    let mut loader = EbpfLoader::new();
+    loader.set_global("IP_HEADER_OFFSET", &ip_header_offset, true);
+    loader.set_max_entries("EVENTS_IPV4", ring_buffer_bytes);
+
     // Loading the eBPF program, the macros make sure the correct file is loaded
     #[cfg(debug_assertions)]
-    let mut bpf_ipv4 = Ebpf::load(include_bytes_aligned!(
+    let mut bpf_ipv4 = loader.load(include_bytes_aligned!(
         "../../target/bpfel-unknown-none/debug/rustiflow-ebpf-ipv4"
     ))?;
     #[cfg(not(debug_assertions))]
-    let mut bpf_ipv4 = Ebpf::load(include_bytes_aligned!(
+    let mut bpf_ipv4 = loader.load(include_bytes_aligned!(
         "../../target/bpfel-unknown-none/release/rustiflow-ebpf-ipv4"
     ))?;
 
@@ -258,14 +363,23 @@ fn load_ebpf_ipv4(interface: &str, tc_attach_type: TcAttachType) -> Result<Ebpf,
     Ok(bpf_ipv4)
 }
 
-fn load_ebpf_ipv6(interface: &str, tc_attach_type: TcAttachType) -> Result<Ebpf, anyhow::Error> {
+fn load_ebpf_ipv6(
+    interface: &str,
+    tc_attach_type: TcAttachType,
+    ip_header_offset: u32,
+    ring_buffer_bytes: u32,
+) -> Result<Ebpf, anyhow::Error> {
+    let mut loader = EbpfLoader::new();
+    loader.set_global("IP_HEADER_OFFSET", &ip_header_offset, true);
+    loader.set_max_entries("EVENTS_IPV6", ring_buffer_bytes);
+
     // Loading the eBPF program, the macros make sure the correct file is loaded
     #[cfg(debug_assertions)]
-    let mut bpf_ipv6 = Ebpf::load(include_bytes_aligned!(
+    let mut bpf_ipv6 = loader.load(include_bytes_aligned!(
         "../../target/bpfel-unknown-none/debug/rustiflow-ebpf-ipv6"
     ))?;
     #[cfg(not(debug_assertions))]
-    let mut bpf_ipv6 = Ebpf::load(include_bytes_aligned!(
+    let mut bpf_ipv6 = loader.load(include_bytes_aligned!(
         "../../target/bpfel-unknown-none/release/rustiflow-ebpf-ipv6"
     ))?;
 
@@ -280,3 +394,32 @@ fn load_ebpf_ipv6(interface: &str, tc_attach_type: TcAttachType) -> Result<Ebpf,
 
     Ok(bpf_ipv6)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{capture_latency, parse_effective_capabilities};
+
+    #[test]
+    fn test_capture_latency() {
+        let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        assert_eq!(unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) }, 0);
+        let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32).as_nanos() as u64;
+
+        // The timestamps of the events are in network byte order
+        let latency = capture_latency((now - 5_000_000).to_be()).unwrap();
+        assert!(latency >= Duration::from_millis(5) && latency < Duration::from_secs(5));
+        // A timestamp ahead of the clock gives no latency
+        assert_eq!(capture_latency((now + 60_000_000_000).to_be()), None);
+    }
+
+    #[test]
+    fn test_parse_effective_capabilities() {
+        let status = "Name:\trustiflow\nCapInh:\t0000000000000000\nCapPrm:\t000001ffffffffff\n\
+            CapEff:\t0000008000001000\nCapBnd:\t000001ffffffffff\n";
+
+        assert_eq!(parse_effective_capabilities(status), Some(0x0000008000001000));
+        assert_eq!(parse_effective_capabilities("Name:\trustiflow\n"), None);
+    }
+}
@@ -0,0 +1,300 @@
+#[cfg(target_os = "linux")]
+use log::{error, info};
+#[cfg(target_os = "linux")]
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+use crate::args::{
+    ConfigFile, ExportConfig, ExportOrder, OutputConfig, PacketFilterRule, PersistentFlowRule,
+};
+
+/// The part of the configuration that can be changed while flows are being captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableConfig {
+    /// The maximum time a flow is allowed to last in seconds.
+    pub active_timeout: u64,
+    /// The maximum time with no packets for a flow in seconds.
+    pub idle_timeout: u64,
+    /// The maximum lifetime of any flow in seconds, if it is capped.
+    pub max_flow_duration: Option<u64>,
+    /// The print interval for open flows in seconds.
+    pub early_export: Option<u64>,
+    /// Interval (in seconds) for checking and expiring flows in the flowtable.
+    pub expiration_check_interval: u64,
+    /// The minimum number of packets a flow needs to be exported.
+    pub min_packets: u32,
+    /// Whether a new TCP handshake on an existing connection starts a new flow.
+    pub split_on_syn: bool,
+    /// The backwards sequence number jump that starts a new TCP flow, if enabled.
+    pub sequence_reset_threshold: Option<u32>,
+    /// What the flows exported together are ordered by.
+    pub export_order: ExportOrder,
+    /// The rules of the flows exported as snapshots instead of on the active timeout.
+    pub persistent_flows: Vec<PersistentFlowRule>,
+    /// The rules deciding which packets are processed.
+    pub packet_filters: Vec<PacketFilterRule>,
+}
+
+impl ReloadableConfig {
+    /// Takes the reloadable settings out of a configuration.
+    pub fn new(export_config: &ExportConfig, output_config: &OutputConfig) -> Self {
+        ReloadableConfig {
+            active_timeout: export_config.active_timeout,
+            idle_timeout: export_config.idle_timeout,
+            max_flow_duration: export_config.max_flow_duration,
+            early_export: export_config.early_export,
+            expiration_check_interval: export_config.expiration_check_interval,
+            min_packets: output_config.min_packets,
+            split_on_syn: export_config.split_on_syn,
+            sequence_reset_threshold: export_config.sequence_reset_threshold,
+            export_order: output_config.export_order.unwrap_or_default(),
+            persistent_flows: export_config.persistent_flows.clone(),
+            packet_filters: export_config.packet_filters.clone(),
+        }
+    }
+}
+
+/// Reloads the configuration file every time a SIGHUP is received.
+///
+/// The reloadable settings are published on the watch channel, the flow tables and the
+/// output writer pick them up on their next evaluation. A reload that changes settings
+/// which can't be applied to a running capture is rejected as a whole.
+///
+/// ### Arguments
+///
+/// * `config_path` - The configuration file to reload.
+/// * `current` - The configuration the capture was started with.
+/// * `sender` - The channel to publish the reloaded settings on.
+#[cfg(target_os = "linux")]
+pub async fn reload_on_sighup(
+    config_path: String,
+    mut current: ConfigFile,
+    sender: watch::Sender<ReloadableConfig>,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, configuration reload disabled: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration from {}", config_path);
+
+        let new = match ConfigFile::load(&config_path) {
+            Ok(new) => new,
+            Err(e) => {
+                error!(
+                    "Error loading configuration file, keeping the current configuration: {:#}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let fixed = changed_fixed_settings(&current, &new);
+        if !fixed.is_empty() {
+            error!(
+                "Configuration not reloaded: {} can't be changed without a restart",
+                fixed.join(", ")
+            );
+            continue;
+        }
+
+        let reloadable = ReloadableConfig::new(&new.config, &new.output);
+        info!("Configuration reloaded: {:?}", reloadable);
+        sender.send_replace(reloadable);
+        current = new;
+    }
+}
+
+/// Returns the names of the settings that differ but can't be applied while running.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn changed_fixed_settings(current: &ConfigFile, new: &ConfigFile) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+
+    if current.config.features != new.config.features {
+        changed.push("features");
+    }
+    if current.config.threads != new.config.threads {
+        changed.push("threads (number of shards)");
+    }
+    if current.config.single_threaded != new.config.single_threaded {
+        changed.push("single_threaded");
+    }
+    if current.config.byte_count != new.config.byte_count {
+        changed.push("byte_count");
+    }
+    if current.config.ip_version != new.config.ip_version {
+        changed.push("ip_version");
+    }
+    if current.config.track_expectations != new.config.track_expectations {
+        changed.push("track_expectations");
+    }
+    if current.config.export_window != new.config.export_window {
+        changed.push("export_window");
+    }
+    if current.config.flow_key != new.config.flow_key {
+        changed.push("flow_key");
+    }
+    if current.config.packet_sequence_length != new.config.packet_sequence_length {
+        changed.push("packet_sequence_length");
+    }
+    if current.config.feature_memory_limit != new.config.feature_memory_limit {
+        changed.push("feature_memory_limit");
+    }
+    if current.config.flow_table_capacity != new.config.flow_table_capacity {
+        changed.push("flow_table_capacity");
+    }
+    if current.config.raw_flags != new.config.raw_flags {
+        changed.push("raw_flags");
+    }
+    if current.config.flow_sampling != new.config.flow_sampling {
+        changed.push("flow_sampling");
+    }
+    if current.config.periodicity_threshold != new.config.periodicity_threshold
+        || current.config.periodicity_min_packets != new.config.periodicity_min_packets
+    {
+        changed.push("periodicity_threshold and periodicity_min_packets");
+    }
+    if current.config.first_window != new.config.first_window {
+        changed.push("first_window");
+    }
+    if current.config.exclude_keepalives != new.config.exclude_keepalives {
+        changed.push("exclude_keepalives");
+    }
+    if current.config.disabled_features != new.config.disabled_features {
+        changed.push("disabled_features");
+    }
+    if current.config.local_subnets != new.config.local_subnets {
+        changed.push("local_subnets");
+    }
+    if current.config.ring_buffer_size != new.config.ring_buffer_size {
+        changed.push("ring_buffer_size");
+    }
+    if current.config.interface_check_interval != new.config.interface_check_interval {
+        changed.push("interface_check_interval");
+    }
+    if current.config.shard_report_interval != new.config.shard_report_interval {
+        changed.push("shard_report_interval");
+    }
+    if current.output.output != new.output.output {
+        changed.push("output");
+    }
+    if current.output.export_path != new.output.export_path {
+        changed.push("export_path");
+    }
+    if current.output.protobuf_address != new.output.protobuf_address {
+        changed.push("protobuf_address");
+    }
+    if current.output.clickhouse != new.output.clickhouse {
+        changed.push("clickhouse");
+    }
+    if current.output.header != new.output.header {
+        changed.push("header");
+    }
+    if current.output.drop_contaminant_features != new.output.drop_contaminant_features {
+        changed.push("drop_contaminant_features");
+    }
+    if current.output.max_string_length != new.output.max_string_length {
+        changed.push("max_string_length");
+    }
+    if current.output.null_value != new.output.null_value {
+        changed.push("null_value");
+    }
+    if current.output.timestamp_format != new.output.timestamp_format
+        || current.output.timezone != new.output.timezone
+    {
+        changed.push("timestamp_format");
+    }
+    if current.output.anonymize != new.output.anonymize
+        || current.output.anonymization_key != new.output.anonymization_key
+    {
+        changed.push("anonymize");
+    }
+    if current.output.canonical_endpoints != new.output.canonical_endpoints {
+        changed.push("canonical_endpoints");
+    }
+    if current.output.export_time != new.output.export_time {
+        changed.push("export_time");
+    }
+    if current.output.end_reason != new.output.end_reason {
+        changed.push("end_reason");
+    }
+    if current.output.payload_signatures != new.output.payload_signatures {
+        changed.push("payload_signatures");
+    }
+    if current.output.sensor_id != new.output.sensor_id {
+        changed.push("sensor_id");
+    }
+    if current.output.keep_connection_attempts != new.output.keep_connection_attempts {
+        changed.push("keep_connection_attempts");
+    }
+    if current.output.shutdown_timeout != new.output.shutdown_timeout {
+        changed.push("shutdown_timeout");
+    }
+    if current.output.reorder_window != new.output.reorder_window {
+        changed.push("reorder_window");
+    }
+    if current.output.partition_by != new.output.partition_by {
+        changed.push("partition_by");
+    }
+    if current.output.write_retries != new.output.write_retries
+        || current.output.write_retry_backoff != new.output.write_retry_backoff
+    {
+        changed.push("write_retries and write_retry_backoff");
+    }
+    if current.output.dead_letter_path != new.output.dead_letter_path {
+        changed.push("dead_letter_path");
+    }
+    if current.output.scan_detection != new.output.scan_detection {
+        changed.push("scan_detection");
+    }
+    if current.output.host_rollups != new.output.host_rollups {
+        changed.push("host_rollups");
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_fixed_settings, ReloadableConfig};
+    use crate::args::{ConfigFile, FilterAction, FlowType, PacketFilterRule, PersistentFlowRule};
+
+    #[test]
+    fn test_changed_fixed_settings() {
+        let current = ConfigFile::default();
+        let mut new = current.clone();
+        new.config.idle_timeout += 1;
+        new.output.min_packets = 5;
+        new.config.packet_filters.push(PacketFilterRule {
+            action: FilterAction::Exclude,
+            source_ip: None,
+            source_port: None,
+            destination_ip: None,
+            destination_port: None,
+            protocol: Some(17),
+        });
+        new.config.persistent_flows.push(PersistentFlowRule {
+            source_ip: None,
+            source_port: Some(443),
+            destination_ip: None,
+            destination_port: None,
+            protocol: None,
+            snapshot_interval: 60,
+        });
+        assert!(changed_fixed_settings(&current, &new).is_empty());
+        let reloadable = ReloadableConfig::new(&new.config, &new.output);
+        assert_eq!(reloadable.min_packets, 5);
+        assert_eq!(reloadable.packet_filters, new.config.packet_filters);
+        assert_eq!(reloadable.persistent_flows, new.config.persistent_flows);
+
+        new.config.features = FlowType::Nfstream;
+        new.output.sensor_id = Some("sensor".to_string());
+        assert_eq!(changed_fixed_settings(&current, &new), ["features", "sensor_id"]);
+    }
+}
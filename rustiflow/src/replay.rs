@@ -0,0 +1,169 @@
+//! Replaying exported flow records into the output, the `replay` command.
+//!
+//! The records of a CSV export, a dead-letter file or a `Protobuf` output are written to the
+//! configured output as they are, without any packets or flow tables, e.g. to backfill a new
+//! database or to write the dead-lettered flows out once the sink works again. The records
+//! need the same feature set and export settings as the configuration.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    sync::Arc,
+};
+
+use log::info;
+
+use crate::{
+    args::{ExportMethodType, OutputConfig, ReplayFormat},
+    export_error::{self, ExportErrorHandler},
+    flows::{flow::Flow, registry},
+    output::OutputWriter,
+    protobuf::ProtobufDecoder,
+};
+
+/// Checks that the output can take replayed records, which come without their flows.
+///
+/// ### Arguments
+///
+/// * `output` - The output configuration.
+/// * `path` - The file the records are replayed from.
+pub fn check_output(output: &OutputConfig, path: &str) -> Result<(), String> {
+    if output.output == ExportMethodType::Table {
+        return Err("The Table output needs the flows, records can't be replayed into it".into());
+    }
+    if output.partition_by.is_some() {
+        return Err("The partitions need the flows, records can't be replayed into them".into());
+    }
+    if output.export_path.as_deref() == Some(path)
+        || output.dead_letter_path.as_deref() == Some(path)
+    {
+        return Err(format!("The records of {} can't be replayed into the same file", path));
+    }
+    Ok(())
+}
+
+/// Writes the records of a file, or of stdin for `-`, to the output and returns their number.
+///
+/// A record that fails to write is passed to the error handler like a flow of a capture, the
+/// replay goes on. A malformed input stops it.
+///
+/// ### Arguments
+///
+/// * `output_writer` - The initialized output.
+/// * `path` - The file of the records.
+/// * `format` - The format of the records.
+/// * `skip_contaminant_features` - Whether the records were exported without the contaminant
+///   features.
+/// * `error_handler` - Gets the failed writes.
+pub async fn replay<T: Flow>(
+    output_writer: &mut OutputWriter<T>,
+    path: &str,
+    format: ReplayFormat,
+    skip_contaminant_features: bool,
+    error_handler: &ExportErrorHandler,
+) -> io::Result<u64> {
+    let input: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to open {}: {}", path, e))
+        })?)
+    };
+    let mut input = BufReader::new(input);
+    let settings = Arc::clone(output_writer.settings());
+    let mut write = async |record: &str| {
+        if let Err(e) = output_writer.replay_record(record).await {
+            export_error::report(error_handler, e);
+        }
+    };
+
+    let replayed = match format {
+        ReplayFormat::Csv => {
+            let header = registry::header::<T>(&settings, skip_contaminant_features);
+            replay_csv(&mut input, &header, &mut write).await?
+        }
+        ReplayFormat::Protobuf => {
+            let decoder = ProtobufDecoder::new::<T>(&settings, skip_contaminant_features);
+            let mut replayed = 0;
+            while let Some(message) = ProtobufDecoder::read_message(&mut input)? {
+                let record = decoder.decode(&message).map_err(|e| {
+                    io::Error::new(e.kind(), format!("Message {}: {}", replayed + 1, e))
+                })?;
+                write(&record).await;
+                replayed += 1;
+            }
+            replayed
+        }
+    };
+    info!("Replayed {} flow records from {}", replayed, path);
+    Ok(replayed)
+}
+
+/// Passes the records of a CSV export to `write`, skipping its header.
+///
+/// The export may come without a header, a first line naming other features than `header`
+/// is rejected, as are records with another number of values.
+async fn replay_csv(
+    input: &mut impl BufRead,
+    header: &str,
+    write: &mut impl AsyncFnMut(&str),
+) -> io::Result<u64> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let columns = header.split(',').count();
+    let first_column = header.split(',').next().unwrap_or_default();
+    let mut replayed = 0;
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if index == 0 && line.split(',').next() == Some(first_column) {
+            if line != header {
+                return Err(invalid(
+                    "The header names other features than the configuration exports".to_string(),
+                ));
+            }
+            continue;
+        }
+        let values = line.split(',').count();
+        if values != columns {
+            return Err(invalid(format!(
+                "Line {} has {} values instead of the {} exported features",
+                index + 1,
+                values,
+                columns
+            )));
+        }
+        write(&line).await;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::replay_csv;
+
+    #[tokio::test]
+    async fn test_replay_csv() {
+        let replay = async |input: &str| {
+            let mut records = Vec::new();
+            let result = replay_csv(&mut input.as_bytes(), "A,B,C", &mut async |record: &str| {
+                records.push(record.to_string())
+            })
+            .await;
+            result.map(|replayed| (replayed, records))
+        };
+
+        let (replayed, records) = replay("A,B,C\n1,2,3\n\n4,,6\n").await.unwrap();
+        assert_eq!(replayed, 2);
+        assert_eq!(records, ["1,2,3", "4,,6"]);
+        // An export without a header is replayed from its first line
+        assert_eq!(replay("1,2,3\n").await.unwrap().0, 1);
+
+        let error = replay("A,B,D\n1,2,3\n").await.unwrap_err();
+        assert!(error.to_string().contains("header"));
+        let error = replay("A,B,C\n1,2\n").await.unwrap_err();
+        assert!(error.to_string().contains("Line 2"));
+    }
+}
@@ -0,0 +1,268 @@
+//! Detection of port and host scans on top of the exported flows. Over a sliding window, the
+//! distinct destination ports and hosts each source contacts are counted, and a source reaching
+//! a threshold is reported in an alert record.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+
+use crate::{
+    args::{ScanDetectionConfig, TimestampFormat},
+    flows::basic_flow::BasicFlow,
+};
+
+/// The columns of the alert records.
+const ALERT_HEADER: &str =
+    "TIMESTAMP,SOURCE_IP,DISTINCT_DESTINATION_PORTS,DISTINCT_DESTINATION_IPS,WINDOW";
+
+/// A source that reached a threshold of the scan detection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanAlert {
+    /// The start of the flow that reached the threshold.
+    pub timestamp: DateTime<Utc>,
+    pub source_ip: IpAddr,
+    /// The distinct destination ports of the source within the window.
+    pub destination_ports: usize,
+    /// The distinct destination hosts of the source within the window.
+    pub destination_ips: usize,
+}
+
+/// The flows a source started within the window.
+#[derive(Default)]
+struct SourceContacts {
+    /// The start, destination host and port of each flow, in the order they were exported.
+    contacts: VecDeque<(DateTime<Utc>, IpAddr, u16)>,
+    /// The number of flows to each destination port and host within the window.
+    ports: HashMap<u16, u32>,
+    hosts: HashMap<IpAddr, u32>,
+    /// Whether the source was reported and hasn't dropped below the thresholds since.
+    alerted: bool,
+}
+
+impl SourceContacts {
+    /// Forgets the flows that started before `cutoff`.
+    fn evict(&mut self, cutoff: DateTime<Utc>) {
+        while let Some(&(start, host, port)) = self.contacts.front() {
+            if start >= cutoff {
+                break;
+            }
+            self.contacts.pop_front();
+            decrement(&mut self.ports, port);
+            decrement(&mut self.hosts, host);
+        }
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, u32>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// Counts the distinct destinations of the sources of the exported flows and writes an alert
+/// for a source that reaches a threshold.
+///
+/// The window is measured by the start of the flows, so pcap files are checked like a live
+/// capture. A source is reported once until it drops below both thresholds again.
+pub struct ScanDetector {
+    window: TimeDelta,
+    port_threshold: usize,
+    host_threshold: usize,
+    sources: HashMap<IpAddr, SourceContacts>,
+    /// The latest flow start seen and the last time idle sources were dropped.
+    latest: Option<DateTime<Utc>>,
+    last_sweep: Option<DateTime<Utc>>,
+    /// The alerts file, the alerts are logged if there is none.
+    writer: Option<BufWriter<File>>,
+    alerts: u64,
+}
+
+impl ScanDetector {
+    /// Creates the detector and the alerts file, if the configuration names one.
+    pub fn new(config: ScanDetectionConfig) -> io::Result<Self> {
+        let writer = match &config.alerts_path {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| {
+                    io::Error::new(e.kind(), format!("Failed to create file {}: {}", path, e))
+                })?;
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "{}", ALERT_HEADER)?;
+                info!("Writing the scan alerts to {}", path);
+                Some(writer)
+            }
+            None => None,
+        };
+
+        Ok(ScanDetector {
+            window: TimeDelta::seconds(config.window as i64),
+            port_threshold: config.port_threshold,
+            host_threshold: config.host_threshold,
+            sources: HashMap::new(),
+            latest: None,
+            last_sweep: None,
+            writer,
+            alerts: 0,
+        })
+    }
+
+    /// Counts an exported flow and writes an alert if its source became a scanner with it.
+    pub fn check_flow(&mut self, flow: &BasicFlow) -> io::Result<()> {
+        let Some(alert) = self.observe(flow) else {
+            return Ok(());
+        };
+        self.alerts += 1;
+
+        // The source as it is exported, pseudonymized if anonymization is enabled
+        let record = format!(
+            "{},{},{},{},{}",
+            flow.settings.timestamp_feature(alert.timestamp, TimestampFormat::Text),
+            flow.settings.ip_feature(alert.source_ip),
+            alert.destination_ports,
+            alert.destination_ips,
+            self.window.num_seconds()
+        );
+        match self.writer.as_mut() {
+            Some(writer) => writeln!(writer, "{}", record),
+            None => {
+                warn!("Possible scan: {}", record);
+                Ok(())
+            }
+        }
+    }
+
+    /// Counts a flow for its source, returns an alert if the source reached a threshold with it.
+    fn observe(&mut self, flow: &BasicFlow) -> Option<ScanAlert> {
+        let start = flow.first_timestamp;
+        let latest = self.latest.map_or(start, |latest| latest.max(start));
+        self.latest = Some(latest);
+        let cutoff = latest - self.window;
+
+        // Forget the sources that started nothing within the window, once per window
+        if self.last_sweep.is_none_or(|last_sweep| latest - last_sweep >= self.window) {
+            self.sources.retain(|_, source| {
+                source.evict(cutoff);
+                !source.contacts.is_empty()
+            });
+            self.last_sweep = Some(latest);
+        }
+        if start < cutoff {
+            // Too late for the window, e.g. a long flow exported at its end
+            return None;
+        }
+
+        let source = self.sources.entry(flow.ip_source).or_default();
+        source.evict(cutoff);
+        source
+            .contacts
+            .push_back((start, flow.ip_destination, flow.port_destination));
+        *source.ports.entry(flow.port_destination).or_insert(0) += 1;
+        *source.hosts.entry(flow.ip_destination).or_insert(0) += 1;
+
+        let scanning = source.ports.len() >= self.port_threshold
+            || source.hosts.len() >= self.host_threshold;
+        if !scanning {
+            source.alerted = false;
+            return None;
+        }
+        if source.alerted {
+            return None;
+        }
+        source.alerted = true;
+        Some(ScanAlert {
+            timestamp: start,
+            source_ip: flow.ip_source,
+            destination_ports: source.ports.len(),
+            destination_ips: source.hosts.len(),
+        })
+    }
+
+    /// Writes out the buffered alerts, e.g. at the periodic flush of the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes out the remaining alerts at the end of the run.
+    pub fn close(&mut self) -> io::Result<()> {
+        info!("Detected {} possible scans", self.alerts);
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, TimeDelta};
+
+    use super::ScanDetector;
+    use crate::{
+        args::ScanDetectionConfig,
+        flows::{basic_flow::BasicFlow, flow::Flow},
+    };
+
+    fn flow(source: u8, destination: u8, port: u16, seconds: i64) -> BasicFlow {
+        BasicFlow::new(
+            Arc::default(),
+            String::new(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, source)),
+            40000,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 1, destination)),
+            port,
+            6,
+            DateTime::from_timestamp(1_700_000_000, 0).unwrap() + TimeDelta::seconds(seconds),
+        )
+    }
+
+    #[test]
+    fn test_scan_detection() {
+        let mut detector = ScanDetector::new(ScanDetectionConfig {
+            alerts_path: None,
+            window: 10,
+            port_threshold: 5,
+            host_threshold: 3,
+        })
+        .unwrap();
+
+        // A port scan of one host, reported once when it reaches the threshold
+        for port in 1..5 {
+            assert_eq!(detector.observe(&flow(1, 1, port, 0)), None);
+        }
+        let alert = detector.observe(&flow(1, 1, 5, 1)).unwrap();
+        assert_eq!(alert.source_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!((alert.destination_ports, alert.destination_ips), (5, 1));
+        assert_eq!(detector.observe(&flow(1, 1, 6, 2)), None);
+
+        // Repeated connections to the same service are no scan
+        for _ in 0..10 {
+            assert_eq!(detector.observe(&flow(2, 1, 443, 3)), None);
+        }
+        // A sweep of the same port over several hosts
+        assert_eq!(detector.observe(&flow(3, 1, 22, 3)), None);
+        assert_eq!(detector.observe(&flow(3, 2, 22, 3)), None);
+        let alert = detector.observe(&flow(3, 3, 22, 4)).unwrap();
+        assert_eq!((alert.destination_ports, alert.destination_ips), (1, 3));
+
+        // The contacts older than the window are forgotten, the source can be reported again
+        for port in 7..11 {
+            assert_eq!(detector.observe(&flow(1, 1, port, 20)), None);
+        }
+        assert!(detector.observe(&flow(1, 1, 11, 21)).is_some());
+        // The sources without flows in the window are dropped
+        assert_eq!(detector.sources.len(), 1);
+    }
+}
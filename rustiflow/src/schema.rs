@@ -0,0 +1,480 @@
+//! The JSON Schema and the Protocol Buffers schema of the exported flow records, for consumers
+//! that generate parsers or validate the output.
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    args::{FlowType, TimestampFormat},
+    flows::{
+        flow::Flow,
+        registry::{self, FeatureType, Unit},
+        settings::FlowSettings,
+    },
+};
+
+/// The version of the flow record schemas, raised when columns of a feature set are renamed,
+/// removed or change their type. Added columns keep it.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The JSON type of a feature column, ordered from the most to the least specific.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum ColumnType {
+    Boolean,
+    Integer,
+    Number,
+    String,
+}
+
+impl ColumnType {
+    /// Returns the type a dumped value is written as.
+    #[cfg(test)]
+    fn of(value: &str) -> ColumnType {
+        if value == "true" || value == "false" {
+            ColumnType::Boolean
+        } else if value.parse::<i64>().is_ok() || value.parse::<u64>().is_ok() {
+            ColumnType::Integer
+        } else if value.parse::<f64>().is_ok() {
+            ColumnType::Number
+        } else {
+            ColumnType::String
+        }
+    }
+
+    /// Returns the type the values of a feature are written as with the given settings.
+    pub fn of_feature(feature_type: FeatureType, settings: &FlowSettings) -> ColumnType {
+        match feature_type {
+            FeatureType::Integer => ColumnType::Integer,
+            FeatureType::Number => ColumnType::Number,
+            FeatureType::Boolean => ColumnType::Boolean,
+            FeatureType::Text => ColumnType::String,
+            FeatureType::Timestamp(default) => match settings.timestamp_format.or(default) {
+                TimestampFormat::EpochSeconds
+                | TimestampFormat::EpochMillis
+                | TimestampFormat::EpochMicros => ColumnType::Integer,
+                _ => ColumnType::String,
+            },
+        }
+    }
+
+    /// Returns the type that holds the values of both types.
+    #[cfg(test)]
+    fn merge(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (ColumnType::Boolean, ColumnType::Boolean) => ColumnType::Boolean,
+            (ColumnType::Boolean, _) | (_, ColumnType::Boolean) => ColumnType::String,
+            _ if self > other => self,
+            _ => other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Number => "number",
+            ColumnType::String => "string",
+        }
+    }
+
+    /// Returns the Protocol Buffers type of the column, integers are zigzag encoded as some
+    /// features can be negative.
+    fn proto_name(self) -> &'static str {
+        match self {
+            ColumnType::Boolean => "bool",
+            ColumnType::Integer => "sint64",
+            ColumnType::Number => "double",
+            ColumnType::String => "string",
+        }
+    }
+}
+
+/// A column of the exported records, as described by the schemas.
+pub struct Column {
+    /// The feature name, with the occurrence appended to a repeated feature, e.g. `_2`.
+    pub name: String,
+    /// The feature a repeated column repeats.
+    pub repeats: Option<String>,
+    pub column_type: ColumnType,
+    pub unit: Unit,
+    /// What the feature of the column holds.
+    pub description: String,
+    /// Whether the column is empty (the null value) for some flows.
+    pub nullable: bool,
+}
+
+/// Returns the columns of the records exported for the feature set `T`, in their order.
+///
+/// The columns are the exported features of the feature registry, see
+/// `registry::exported_features`, so they follow the settings of the run (e.g. the
+/// contaminant features, windowed export and the disabled feature groups) like the output.
+///
+/// ### Arguments
+///
+/// * `settings` - The settings of the run.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn columns<T: Flow>(settings: &FlowSettings, skip_contaminant_features: bool) -> Vec<Column> {
+    let mut columns: Vec<Column> = Vec::new();
+    for feature in registry::exported_features::<T>(settings, skip_contaminant_features) {
+        // The CIC features repeat a column like CICFlowMeter does, the names must be unique
+        let mut name = feature.name.to_string();
+        let mut occurrence = 1;
+        while columns.iter().any(|column| column.name == name) {
+            occurrence += 1;
+            name = format!("{}_{}", feature.name, occurrence);
+        }
+        columns.push(Column {
+            name,
+            repeats: (occurrence > 1).then(|| feature.name.to_string()),
+            column_type: ColumnType::of_feature(feature.feature_type, settings),
+            unit: feature.unit,
+            description: feature.description.to_string(),
+            nullable: feature.is_nullable(settings),
+        });
+    }
+    columns
+}
+
+/// Returns the JSON Schema of the records exported for the feature set `T`.
+///
+/// The flows are exported as CSV, so the schema describes one record as an object of its
+/// columns, in their order, see `columns`.
+///
+/// ### Arguments
+///
+/// * `settings` - The settings of the run.
+/// * `feature_set` - The name of the feature set, for the title.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn json_schema<T: Flow>(
+    settings: &FlowSettings,
+    feature_set: &FlowType,
+    skip_contaminant_features: bool,
+) -> Value {
+    let null_value = settings.null_value.as_str();
+    let mut properties = Map::new();
+    let columns = columns::<T>(settings, skip_contaminant_features);
+    for (index, column) in columns.into_iter().enumerate() {
+        let column_type = column.column_type.name();
+        let types = if column.nullable && null_value == "null" {
+            json!([column_type, "null"])
+        } else {
+            json!(column_type)
+        };
+        let description = match &column.repeats {
+            Some(feature) => format!("Repeats the {} column", feature),
+            // Missing values are empty CSV fields
+            None if column.nullable && null_value.is_empty() => {
+                format!("{}, empty if the flow has no value for it", column.description)
+            }
+            None => column.description,
+        };
+        let mut property = json!({
+            "type": types,
+            "description": description,
+            "x-column": index,
+        });
+        if let Some(unit) = column.unit.name() {
+            property["x-unit"] = json!(unit);
+        }
+        properties.insert(column.name, property);
+    }
+
+    let required: Vec<String> = properties.keys().cloned().collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("RustiFlow {:?} flow record", feature_set),
+        "description": "An exported flow, the properties are the CSV columns in the order \
+            given by x-column",
+        "type": "object",
+        "x-schema-version": SCHEMA_VERSION,
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the Protocol Buffers (proto3) schema of the records exported for the feature set
+/// `T`, for the `Protobuf` output.
+///
+/// A record is a message with a field per column, numbered by the column order from 1, see
+/// `columns`. Columns that are empty for some flows are `optional`, their missing values are
+/// left out of the message instead of being written as the null value. The package carries
+/// the schema version.
+///
+/// ### Arguments
+///
+/// * `settings` - The settings of the run.
+/// * `feature_set` - The name of the feature set, for the message name.
+/// * `skip_contaminant_features` - Whether the contaminant features are dropped.
+pub fn proto_schema<T: Flow>(
+    settings: &FlowSettings,
+    feature_set: &FlowType,
+    skip_contaminant_features: bool,
+) -> String {
+    let mut schema = format!(
+        "// The RustiFlow {:?} flow record, schema version {}\n\
+        syntax = \"proto3\";\n\npackage rustiflow.v{};\n\n\
+        // An exported flow, the fields are the CSV columns in their order\n\
+        message {}FlowRecord {{\n",
+        feature_set,
+        SCHEMA_VERSION,
+        SCHEMA_VERSION,
+        proto_message_name(feature_set)
+    );
+    for (index, column) in columns::<T>(settings, skip_contaminant_features).iter().enumerate() {
+        schema.push_str(&format!(
+            "  {}{} {} = {};\n",
+            if column.nullable { "optional " } else { "" },
+            column.column_type.proto_name(),
+            proto_field_name(&column.name),
+            index + 1
+        ));
+    }
+    schema.push_str("}\n");
+    schema
+}
+
+/// Returns the feature set name in the upper camel case of message names, e.g. `Cic`.
+fn proto_message_name(feature_set: &FlowType) -> String {
+    let name = format!("{:?}", feature_set);
+    let mut chars = name.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect()
+    })
+}
+
+/// Returns a feature name in the lower snake case of field names, e.g. `flow_duration`.
+fn proto_field_name(feature: &str) -> String {
+    let name: String = feature
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::{columns, json_schema, proto_schema, ColumnType};
+    use crate::{
+        args::{FlowKeyStrategy, FlowType},
+        flows::{
+            basic_flow::{BasicFlow, EndReason},
+            cic_flow::CicFlow,
+            cidds_flow::CiddsFlow,
+            custom_flow::CustomFlow,
+            flow::Flow,
+            nf_flow::NfFlow,
+            ntl_flow::NTLFlow,
+            registry,
+            rusti_flow::RustiFlow,
+            settings::FlowSettings,
+        },
+        packet_features::PacketFeatures,
+    };
+
+    /// Returns flows whose records show the type of every feature: a single SYN, which
+    /// leaves most of the backward and statistical features without a value, and a complete
+    /// TCP connection with data in both directions.
+    fn sample_flows<T: Flow>() -> [T; 2] {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        // Direction, flags (FIN, SYN, PSH, ACK), payload and time since the start in ms. The
+        // payloads make the means of the lengths fractional, so they aren't taken for integers
+        let connection = [
+            (true, (0, 1, 0, 0), 0, 0),
+            (false, (0, 1, 0, 1), 0, 1),
+            (true, (0, 0, 0, 1), 0, 2),
+            (true, (0, 0, 1, 1), 101, 3),
+            (false, (0, 0, 0, 1), 1000, 10),
+            (false, (0, 0, 1, 1), 501, 11),
+            (true, (0, 0, 0, 1), 0, 12),
+            (true, (1, 0, 0, 1), 0, 1000),
+            (false, (1, 0, 0, 1), 0, 1001),
+            (true, (0, 0, 0, 1), 0, 1002),
+        ];
+
+        let mut flows = [sample_flow::<T>(start), sample_flow::<T>(start)];
+        for (index, (forward, flags, data_length, milliseconds)) in connection.iter().enumerate() {
+            let packet = sample_packet(
+                *forward,
+                *flags,
+                *data_length,
+                start + TimeDelta::milliseconds(*milliseconds),
+            );
+            if index == 0 {
+                flows[0].update_flow(&packet, true);
+            }
+            flows[1].update_flow(&packet, *forward);
+        }
+        for flow in &mut flows {
+            flow.basic_flow_mut().export_time = Some(start + TimeDelta::seconds(2));
+            flow.basic_flow_mut().end_reason = Some(EndReason::EndOfFlow);
+        }
+        flows
+    }
+
+    fn sample_flow<T: Flow>(timestamp: DateTime<Utc>) -> T {
+        let packet = sample_packet(true, (0, 1, 0, 0), 0, timestamp);
+        T::new(
+            Arc::default(),
+            packet.flow_key(FlowKeyStrategy::default()),
+            packet.source_ip,
+            packet.source_port,
+            packet.destination_ip,
+            packet.destination_port,
+            packet.protocol,
+            timestamp,
+        )
+    }
+
+    /// A TCP packet between 10.0.0.1:40000 and 10.0.0.2:80.
+    fn sample_packet(
+        forward: bool,
+        (fin_flag, syn_flag, psh_flag, ack_flag): (u8, u8, u8, u8),
+        data_length: u16,
+        timestamp: DateTime<Utc>,
+    ) -> PacketFeatures {
+        let client = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 40000);
+        let server = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 80);
+        let (source, destination) = if forward { (client, server) } else { (server, client) };
+        PacketFeatures {
+            source_ip: source.0,
+            destination_ip: destination.0,
+            source_port: source.1,
+            destination_port: destination.1,
+            protocol: 6,
+            timestamp,
+            fin_flag,
+            syn_flag,
+            rst_flag: 0,
+            psh_flag,
+            ack_flag,
+            urg_flag: 0,
+            cwe_flag: 0,
+            ece_flag: 0,
+            data_length,
+            header_length: 20,
+            length: 40 + data_length,
+            window_size: 65535,
+            sequence_number: 0,
+            sequence_number_ack: 0,
+            extension_header_count: 0,
+            ttl: 64,
+            ip_options_length: 0,
+            ip_flags: 0,
+            tcp_options_mask: 0,
+            flow_label: 0,
+            expected_flow: None,
+            vlan_id: None,
+            payload_snapshot: None,
+            capture_latency: None,
+        }
+    }
+
+    /// Checks that the values of the sample flows are of the declared types of their columns.
+    fn check_column_types<T: Flow>() {
+        let settings = FlowSettings::default();
+        let columns = columns::<T>(&settings, false);
+        let header = registry::header::<T>(&settings, false);
+        assert_eq!(columns.len(), header.split(',').count());
+        let optional_features = BasicFlow::optional_features(&settings);
+        for flow in sample_flows::<T>() {
+            let record = registry::record(&flow, &optional_features, false);
+            assert_eq!(record.split(',').count(), columns.len());
+            for (column, value) in columns.iter().zip(record.split(',')) {
+                if value == settings.null_value.as_str() {
+                    assert!(column.nullable, "{} is not nullable", column.name);
+                    continue;
+                }
+                assert_eq!(
+                    column.column_type.merge(ColumnType::of(value)),
+                    column.column_type,
+                    "{} holds {}",
+                    column.name,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_column_types() {
+        assert_eq!(ColumnType::of("42"), ColumnType::Integer);
+        assert_eq!(ColumnType::of("-1"), ColumnType::Integer);
+        assert_eq!(ColumnType::of("0.75"), ColumnType::Number);
+        assert_eq!(ColumnType::of("true"), ColumnType::Boolean);
+        assert_eq!(ColumnType::of("2023-11-14 22:13:21 UTC"), ColumnType::String);
+        assert_eq!(ColumnType::Integer.merge(ColumnType::Number), ColumnType::Number);
+        assert_eq!(ColumnType::Boolean.merge(ColumnType::Integer), ColumnType::String);
+    }
+
+    #[test]
+    fn test_declared_column_types() {
+        check_column_types::<BasicFlow>();
+        check_column_types::<CicFlow>();
+        check_column_types::<CiddsFlow>();
+        check_column_types::<CustomFlow>();
+        check_column_types::<NfFlow>();
+        check_column_types::<NTLFlow>();
+        check_column_types::<RustiFlow>();
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let settings = FlowSettings::default();
+        let schema = json_schema::<RustiFlow>(&settings, &FlowType::Rustiflow, false);
+        let properties = schema["properties"].as_object().unwrap();
+
+        // Every column of the header, in its order
+        let header = RustiFlow::get_features();
+        assert_eq!(properties.len(), header.split(',').count());
+        let columns: Vec<u64> = properties
+            .values()
+            .map(|property| property["x-column"].as_u64().unwrap())
+            .collect();
+        assert_eq!(columns, (0..properties.len() as u64).collect::<Vec<_>>());
+        assert_eq!(schema["required"].as_array().unwrap().len(), properties.len());
+        // The CIC features export the forward header length twice
+        assert!(properties.contains_key("FWD_HEADER_LENGTH_2"));
+
+        assert_eq!(properties["FWD_PAYLOAD_PACKET_RATIO"]["type"], "number");
+        assert_eq!(properties["MAX_FWD_RUN"]["type"], "integer");
+        // Missing values are empty fields by default
+        assert!(properties["FWD_TIME_TO_FIRST_BYTE"]["description"].is_string());
+
+        let header = CicFlow::get_features_without_contamination();
+        let schema = json_schema::<CicFlow>(&settings, &FlowType::CIC, true);
+        assert_eq!(
+            schema["properties"].as_object().unwrap().len(),
+            header.split(',').count()
+        );
+    }
+
+    #[test]
+    fn test_proto_schema() {
+        let schema = proto_schema::<CicFlow>(&FlowSettings::default(), &FlowType::CIC, false);
+        assert!(schema.contains("package rustiflow.v2;"));
+        assert!(schema.contains("message CicFlowRecord {"));
+
+        // A field per column, numbered in their order, with unique names
+        let fields: Vec<&str> = schema.lines().filter(|line| line.starts_with("  ")).collect();
+        assert_eq!(fields.len(), CicFlow::get_features().split(',').count());
+        assert_eq!(fields[0], "  string flow_id = 1;");
+        let names: HashSet<&str> = fields
+            .iter()
+            .map(|field| field.split(' ').rev().nth(2).unwrap())
+            .collect();
+        assert_eq!(names.len(), fields.len());
+        assert!(names.contains("fwd_header_length_2"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Drives the flow pipeline from packet events in tests, without a network interface or a
+//! capture file.
+//!
+//! Available to the unit tests and, with the `test-util` feature, to other crates:
+//!
+//! ```ignore
+//! let mut pipeline = TestPipeline::<BasicFlow>::new(config);
+//! pipeline.push_ipv4(&event, timestamp).await;
+//! pipeline.advance_to(timestamp + TimeDelta::seconds(120)).await;
+//! let flows = pipeline.exported_flows();
+//! ```
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use common::{EbpfEventIpv4, EbpfEventIpv6};
+use tokio::sync::mpsc;
+
+use crate::{
+    flow_table::FlowTable,
+    flows::{flow::Flow, settings::FlowSettings},
+    packet_features::PacketFeatures,
+    reload::ReloadableConfig,
+};
+
+/// Flows the export channel holds, the tests collect them only when asked to.
+const EXPORT_CHANNEL_CAPACITY: usize = 100_000;
+
+/// A flow table fed with packets by the test, with the exported flows collected from its
+/// export channel.
+///
+/// The packets are processed one at a time, in the order they are pushed, so the exports
+/// are deterministic. Time only passes with the timestamps of the packets or `advance_to`.
+pub struct TestPipeline<T> {
+    flow_table: FlowTable<T>,
+    exported: mpsc::Receiver<T>,
+}
+
+impl<T> TestPipeline<T>
+where
+    T: Flow,
+{
+    /// Creates a pipeline with the timeouts and flow splitting settings of `config`, and the
+    /// default flow settings.
+    pub fn new(config: ReloadableConfig) -> Self {
+        Self::with_settings(Arc::default(), config)
+    }
+
+    /// Creates a pipeline like `new` whose flows are built with the given settings.
+    pub fn with_settings(settings: Arc<FlowSettings>, config: ReloadableConfig) -> Self {
+        let (sender, exported) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+        let mut flow_table = FlowTable::new(
+            settings,
+            config.active_timeout,
+            config.idle_timeout,
+            config.early_export,
+            sender,
+            config.expiration_check_interval,
+        );
+        flow_table.apply_config(&config);
+
+        TestPipeline {
+            flow_table,
+            exported,
+        }
+    }
+
+    /// Processes a packet like one read from a capture.
+    pub async fn push(&mut self, packet: PacketFeatures) {
+        self.flow_table.process_packet(&packet).await;
+    }
+
+    /// Processes an event of the IPv4 eBPF program, seen at `timestamp`.
+    pub async fn push_ipv4(&mut self, event: &EbpfEventIpv4, timestamp: DateTime<Utc>) {
+        let mut packet = PacketFeatures::from_ebpf_event_ipv4(event);
+        packet.timestamp = timestamp;
+        self.push(packet).await;
+    }
+
+    /// Processes an event of the IPv6 eBPF program, seen at `timestamp`.
+    pub async fn push_ipv6(&mut self, event: &EbpfEventIpv6, timestamp: DateTime<Utc>) {
+        let mut packet = PacketFeatures::from_ebpf_event_ipv6(event);
+        packet.timestamp = timestamp;
+        self.push(packet).await;
+    }
+
+    /// Exports the flows that expired at `timestamp`, as the next expiration check would.
+    pub async fn advance_to(&mut self, timestamp: DateTime<Utc>) {
+        self.flow_table.export_expired_flows(timestamp).await;
+    }
+
+    /// Returns the flows exported since the last call, in the order of their export.
+    pub fn exported_flows(&mut self) -> Vec<T> {
+        let mut flows = Vec::new();
+        while let Ok(flow) = self.exported.try_recv() {
+            flows.push(flow);
+        }
+        flows
+    }
+
+    /// Exports the remaining flows like at the end of a capture and returns all flows not
+    /// collected yet.
+    pub async fn finish(mut self) -> Vec<T> {
+        self.flow_table.export_all_flows().await;
+        self.exported_flows()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeDelta};
+    use common::EbpfEventIpv4;
+
+    use super::TestPipeline;
+    use crate::{
+        args::{ExportConfig, OutputConfig},
+        flows::basic_flow::BasicFlow,
+        reload::ReloadableConfig,
+    };
+
+    // TCP flags of the eBPF events
+    const SYN: u8 = 0x02;
+    const ACK: u8 = 0x10;
+
+    /// An event between 10.0.0.1:40000 and 10.0.0.2:80, in network byte order like the
+    /// eBPF program writes it.
+    fn event(forward: bool, flags: u8, data_length: u16) -> EbpfEventIpv4 {
+        let (client, server) = (0x0a00_0001u32.to_be(), 0x0a00_0002u32.to_be());
+        let (client_port, server_port) = (40000u16.to_be(), 80u16.to_be());
+        let (source, destination, port_source, port_destination) = if forward {
+            (client, server, client_port, server_port)
+        } else {
+            (server, client, server_port, client_port)
+        };
+        EbpfEventIpv4::new(
+            destination,
+            source,
+            port_destination,
+            port_source,
+            data_length.to_be(),
+            (40 + data_length).to_be(),
+            0xffffu16.to_be(),
+            flags,
+            6,
+            20,
+            0,
+            0,
+            64,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_flow_created_updated_and_idle_expired() {
+        let config = ReloadableConfig {
+            idle_timeout: 60,
+            ..ReloadableConfig::new(&ExportConfig::default(), &OutputConfig::default())
+        };
+        let mut pipeline = TestPipeline::<BasicFlow>::new(config);
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        // The handshake creates the flow, the request and the response update it
+        pipeline.push_ipv4(&event(true, SYN, 0), start).await;
+        let mut timestamp = start + TimeDelta::milliseconds(1);
+        pipeline.push_ipv4(&event(false, SYN | ACK, 0), timestamp).await;
+        timestamp += TimeDelta::milliseconds(1);
+        pipeline.push_ipv4(&event(true, ACK, 100), timestamp).await;
+        timestamp += TimeDelta::milliseconds(5);
+        pipeline.push_ipv4(&event(false, ACK, 1000), timestamp).await;
+        assert!(pipeline.exported_flows().is_empty());
+
+        // Not idle for longer than the timeout yet
+        pipeline.advance_to(timestamp + TimeDelta::seconds(60)).await;
+        assert!(pipeline.exported_flows().is_empty());
+
+        pipeline.advance_to(timestamp + TimeDelta::seconds(61)).await;
+        let flows = pipeline.exported_flows();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].port_source, 40000);
+        assert_eq!(flows[0].port_destination, 80);
+        assert_eq!(flows[0].fwd_packet_count, 2);
+        assert_eq!(flows[0].bwd_packet_count, 2);
+        assert_eq!(flows[0].first_timestamp, start);
+        assert_eq!(flows[0].last_timestamp, timestamp);
+
+        assert!(pipeline.finish().await.is_empty());
+    }
+}
@@ -15,7 +15,9 @@ use tui::text::{Span, Spans, Text};
 use tui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph};
 use tui::{Frame, Terminal};
 
-use crate::args::{Commands, ConfigFile, ExportConfig, ExportMethodType, FlowType, OutputConfig};
+use rustiflow::args::{
+    CaptureBackend, Commands, ConfigFile, ExportConfig, ExportMethodType, FlowType, OutputConfig,
+};
 
 const CONFIG_FILE_NAME: &str = "rustiflow.toml";
 
@@ -29,23 +31,12 @@ pub struct Config {
 impl Config {
     fn reset() -> Self {
         Config {
-            config: ExportConfig {
-                features: FlowType::Basic,
-                active_timeout: 3600,
-                idle_timeout: 120,
-                early_export: None,
-                threads: None,
-                expiration_check_interval: 60,
-            },
-            output: OutputConfig {
-                output: ExportMethodType::Print,
-                export_path: None,
-                header: false,
-                drop_contaminant_features: false,
-            },
+            config: ExportConfig::default(),
+            output: OutputConfig::default(),
             command: Commands::Realtime {
                 interface: String::from("eth0"),
                 ingress_only: false,
+                backend: CaptureBackend::default(),
             },
         }
     }
@@ -55,28 +46,7 @@ impl Default for Config {
     fn default() -> Self {
         let config: ConfigFile = match confy::load_path(CONFIG_FILE_NAME) {
             Ok(config) => config,
-            Err(_) => {
-                return Config {
-                    config: ExportConfig {
-                        features: FlowType::Basic,
-                        active_timeout: 3600,
-                        idle_timeout: 120,
-                        early_export: None,
-                        threads: None,
-                        expiration_check_interval: 60,
-                    },
-                    output: OutputConfig {
-                        output: ExportMethodType::Print,
-                        export_path: None,
-                        header: false,
-                        drop_contaminant_features: false,
-                    },
-                    command: Commands::Realtime {
-                        interface: String::from("eth0"),
-                        ingress_only: false,
-                    },
-                };
-            }
+            Err(_) => return Config::reset(),
         };
         Config {
             config: config.config,
@@ -84,6 +54,7 @@ impl Default for Config {
             command: Commands::Realtime {
                 interface: String::from("eth0"),
                 ingress_only: false,
+                backend: CaptureBackend::default(),
             },
         }
     }
@@ -128,6 +99,7 @@ struct App {
     expiration_check_interval_input: String,
     threads_input: String,
     early_export_input: String,
+    min_packets_input: String,
     main_menu_items: Vec<&'static str>,
 }
 
@@ -159,6 +131,7 @@ impl App {
             "Early Export",
             "Header",
             "Drop Contaminant Features",
+            "Min Packets",
         ];
 
         App {
@@ -174,6 +147,7 @@ impl App {
             threads_input: String::new(),
             early_export_input: String::new(),
             expiration_check_interval_input: String::new(),
+            min_packets_input: String::new(),
             main_menu_items,
         }
     }
@@ -196,6 +170,7 @@ enum AppFocus {
     EarlyExportInput,
     HeaderInput,
     DropContaminantFeaturesInput,
+    MinPacketsInput,
 }
 
 async fn run_app<B: Backend>(
@@ -219,7 +194,8 @@ async fn run_app<B: Backend>(
                     | AppFocus::IdleTimeoutInput
                     | AppFocus::ExpirationCheckIntervalInput
                     | AppFocus::ThreadsInput
-                    | AppFocus::EarlyExportInput => {
+                    | AppFocus::EarlyExportInput
+                    | AppFocus::MinPacketsInput => {
                         handle_numeric_input(key, app, app.focus.clone())?
                     }
                     AppFocus::CommandSelection | AppFocus::OutputSelection => {
@@ -316,6 +292,7 @@ fn handle_menu_input(key: KeyEvent, app: &mut App) -> Result<(), Box<dyn Error>>
             Some(7) => app.focus = AppFocus::EarlyExportInput,
             Some(8) => app.focus = AppFocus::HeaderInput,
             Some(9) => app.focus = AppFocus::DropContaminantFeaturesInput,
+            Some(10) => app.focus = AppFocus::MinPacketsInput,
             _ => {}
         },
         KeyCode::Esc => {
@@ -370,6 +347,7 @@ fn handle_numeric_input(
                 AppFocus::ExpirationCheckIntervalInput => &mut app.expiration_check_interval_input,
                 AppFocus::ThreadsInput => &mut app.threads_input,
                 AppFocus::EarlyExportInput => &mut app.early_export_input,
+                AppFocus::MinPacketsInput => &mut app.min_packets_input,
                 _ => unreachable!(),
             };
             input.push(c);
@@ -381,6 +359,7 @@ fn handle_numeric_input(
                 AppFocus::ExpirationCheckIntervalInput => &mut app.expiration_check_interval_input,
                 AppFocus::ThreadsInput => &mut app.threads_input,
                 AppFocus::EarlyExportInput => &mut app.early_export_input,
+                AppFocus::MinPacketsInput => &mut app.min_packets_input,
                 _ => unreachable!(),
             };
             input.pop();
@@ -392,6 +371,7 @@ fn handle_numeric_input(
                 AppFocus::ExpirationCheckIntervalInput => &mut app.expiration_check_interval_input,
                 AppFocus::ThreadsInput => &mut app.threads_input,
                 AppFocus::EarlyExportInput => &mut app.early_export_input,
+                AppFocus::MinPacketsInput => &mut app.min_packets_input,
                 _ => unreachable!(),
             };
             if let Ok(value) = input.parse::<u64>() {
@@ -401,13 +381,19 @@ fn handle_numeric_input(
                     AppFocus::ExpirationCheckIntervalInput => {
                         app.config.config.expiration_check_interval = value
                     }
-                    AppFocus::ThreadsInput => {
-                        app.config.config.threads =
-                            if value == 0 { None } else { Some(value as u8) }
-                    }
+                    AppFocus::ThreadsInput => match u8::try_from(value) {
+                        Ok(0) => app.config.config.threads = None,
+                        Ok(value) => app.config.config.threads = Some(value),
+                        // Too large a value is rejected, the input stays to be corrected
+                        Err(_) => return Ok(()),
+                    },
                     AppFocus::EarlyExportInput => {
                         app.config.config.early_export = if value == 0 { None } else { Some(value) }
                     }
+                    AppFocus::MinPacketsInput => match u32::try_from(value) {
+                        Ok(value) => app.config.output.min_packets = value,
+                        Err(_) => return Ok(()),
+                    },
                     _ => {}
                 }
                 input.clear();
@@ -421,6 +407,7 @@ fn handle_numeric_input(
                 AppFocus::ExpirationCheckIntervalInput => &mut app.expiration_check_interval_input,
                 AppFocus::ThreadsInput => &mut app.threads_input,
                 AppFocus::EarlyExportInput => &mut app.early_export_input,
+                AppFocus::MinPacketsInput => &mut app.min_packets_input,
                 _ => unreachable!(),
             };
             input.clear();
@@ -430,6 +417,9 @@ fn handle_numeric_input(
             if let AppFocus::EarlyExportInput = focus {
                 app.config.config.early_export = None;
             }
+            if let AppFocus::MinPacketsInput = focus {
+                app.config.output.min_packets = 0;
+            }
             app.focus = AppFocus::Menu;
         }
         _ => {}
@@ -490,7 +480,7 @@ fn handle_selection_input(
 ) -> Result<(), Box<dyn Error>> {
     let (state, max_index) = match focus {
         AppFocus::CommandSelection => (&mut app.command_state, 1),
-        AppFocus::OutputSelection => (&mut app.output_state, 1),
+        AppFocus::OutputSelection => (&mut app.output_state, 2),
         _ => return Ok(()),
     };
     match key.code {
@@ -514,6 +504,7 @@ fn handle_selection_input(
                     app.config.command = Commands::Realtime {
                         interface: String::new(),
                         ingress_only: false,
+                        backend: CaptureBackend::default(),
                     };
                     app.focus = AppFocus::CommandArgumentInput;
                 }
@@ -535,6 +526,10 @@ fn handle_selection_input(
                     app.config.output.export_path = Some(String::new());
                     app.focus = AppFocus::OutputArgumentInput;
                 }
+                Some(2) => {
+                    app.config.output.output = ExportMethodType::Table;
+                    app.config.output.export_path = None;
+                }
                 _ => {}
             },
             _ => {}
@@ -556,6 +551,7 @@ fn handle_command_argument_input(key: KeyEvent, app: &mut App) -> Result<(), Box
             Commands::Pcap { path } => {
                 path.push(c);
             }
+            Commands::ListInterfaces | Commands::Schema { .. } | Commands::Replay { .. } => {}
         },
         KeyCode::Backspace => match &mut app.config.command {
             Commands::Realtime { interface, .. } => {
@@ -564,12 +560,16 @@ fn handle_command_argument_input(key: KeyEvent, app: &mut App) -> Result<(), Box
             Commands::Pcap { path } => {
                 path.pop();
             }
+            Commands::ListInterfaces | Commands::Schema { .. } | Commands::Replay { .. } => {}
         },
         KeyCode::Enter => match &app.config.command {
             Commands::Realtime { .. } => {
                 app.focus = AppFocus::IngressOnlyInput;
             }
-            Commands::Pcap { .. } => {
+            Commands::Pcap { .. }
+            | Commands::ListInterfaces
+            | Commands::Schema { .. }
+            | Commands::Replay { .. } => {
                 app.focus = AppFocus::Menu;
             }
         },
@@ -578,6 +578,7 @@ fn handle_command_argument_input(key: KeyEvent, app: &mut App) -> Result<(), Box
                 Commands::Realtime {
                     interface,
                     ingress_only,
+                    ..
                 } => {
                     interface.clear();
                     *ingress_only = false;
@@ -585,6 +586,7 @@ fn handle_command_argument_input(key: KeyEvent, app: &mut App) -> Result<(), Box
                 Commands::Pcap { path } => {
                     path.clear();
                 }
+                Commands::ListInterfaces | Commands::Schema { .. } | Commands::Replay { .. } => {}
             }
             app.focus = AppFocus::Menu;
         }
@@ -806,7 +808,11 @@ fn render_content<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
             );
         }
         Some(2) => {
-            let items = vec![ListItem::new("Print"), ListItem::new("Csv")];
+            let items = vec![
+                ListItem::new("Print"),
+                ListItem::new("Csv"),
+                ListItem::new("Table"),
+            ];
             render_selectable_list(
                 f,
                 area,
@@ -867,6 +873,15 @@ fn render_content<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
         Some(9) => {
             render_boolean_choice(f, area, app.config.output.drop_contaminant_features);
         }
+        Some(10) => {
+            render_input_paragraph(
+                f,
+                area,
+                app.min_packets_input.as_ref(),
+                "Enter Min Packets (0 for all flows)",
+                matches!(app.focus, AppFocus::MinPacketsInput),
+            );
+        }
         _ => {}
     }
 }
@@ -1014,6 +1029,13 @@ fn render_current_selections<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect
                 ),
             ]),
         ])),
+        ListItem::new(Spans::from(vec![
+            Span::raw("Min Packets: "),
+            Span::styled(
+                format!("{}", app.config.output.min_packets),
+                Style::default().fg(Color::Yellow),
+            ),
+        ])),
     ];
 
     // Build the Mode entry dynamically
@@ -1021,6 +1043,7 @@ fn render_current_selections<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect
         Commands::Realtime {
             interface,
             ingress_only,
+            ..
         } => {
             let mut text = Text::from(Spans::from(vec![
                 Span::raw("Mode: "),
@@ -1053,6 +1076,27 @@ fn render_current_selections<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect
                 Span::styled(path, Style::default().fg(Color::Yellow)),
             ])]);
 
+            ListItem::new(text)
+        }
+        Commands::ListInterfaces => ListItem::new(Text::from(Spans::from(vec![
+            Span::raw("Mode: "),
+            Span::styled("List interfaces", Style::default().fg(Color::Yellow)),
+        ]))),
+        Commands::Schema { .. } => ListItem::new(Text::from(Spans::from(vec![
+            Span::raw("Mode: "),
+            Span::styled("Schema", Style::default().fg(Color::Yellow)),
+        ]))),
+        Commands::Replay { path, .. } => {
+            let mut text = Text::from(Spans::from(vec![
+                Span::raw("Mode: "),
+                Span::styled("Replay", Style::default().fg(Color::Yellow)),
+            ]));
+
+            text.extend(vec![Spans::from(vec![
+                Span::raw("Path: "),
+                Span::styled(path, Style::default().fg(Color::Yellow)),
+            ])]);
+
             ListItem::new(text)
         }
     };
@@ -1076,6 +1120,9 @@ fn render_popups<B: Backend>(f: &mut Frame<B>, app: &App, size: Rect) {
         let (input_text, title) = match &app.config.command {
             Commands::Realtime { interface, .. } => (interface.as_str(), "Enter Interface"),
             Commands::Pcap { path } => (path.as_str(), "Enter Pcap File Path"),
+            Commands::ListInterfaces => ("", "List Interfaces"),
+            Commands::Schema { .. } => ("", "Schema"),
+            Commands::Replay { path, .. } => (path.as_str(), "Enter Records File Path"),
         };
         render_popup_input(f, size, input_text, title);
     }
@@ -0,0 +1,94 @@
+//! Checks the byte features with `byte_count = Total` end to end, from the capture to the
+//! dumped flows.
+//!
+//! The reference capture of `cicflowmeter_parity` is read once per feature set: the CIC byte
+//! features count the total IP lengths then, while the CIDDS bytes always do.
+
+use std::{collections::HashMap, sync::Arc};
+
+use rustiflow::{
+    args::{ByteCount, ClickHouseConfig, ExportConfig, ExportMethodType, FlowType, OutputConfig},
+    flows::{cic_flow::CicFlow, cidds_flow::CiddsFlow, flow::Flow, settings::FlowSettings},
+    output::OutputWriter,
+    pcap::read_pcap_file,
+    pipeline::Pipeline,
+    reload::ReloadableConfig,
+};
+
+const CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/cic_parity.pcap");
+
+/// Runs the capture through a pipeline into a CSV file and returns the written flows by their
+/// first column, the flow id or the first timestamp.
+async fn export_flows<T: Flow>(
+    settings: &Arc<FlowSettings>,
+    feature_set: FlowType,
+) -> HashMap<String, HashMap<String, String>> {
+    let file_name = format!("rustiflow_test_byte_count_{:?}.csv", feature_set);
+    let path = std::env::temp_dir().join(file_name);
+    let mut output_writer = OutputWriter::<T>::new(
+        Arc::clone(settings),
+        feature_set,
+        ExportMethodType::Csv,
+        true,
+        false,
+        0,
+        Some(path.to_str().unwrap().to_string()),
+        ClickHouseConfig::default(),
+        None,
+    );
+    output_writer.init().await;
+    let config = ReloadableConfig::new(&ExportConfig::default(), &OutputConfig::default());
+    let pipeline = Pipeline::new(1, config, output_writer);
+    read_pcap_file(&pipeline, CAPTURE)
+        .await
+        .expect("the reference capture can be read");
+    pipeline.shutdown().await.unwrap();
+
+    let output = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut lines = output.lines();
+    let header = lines.next().unwrap();
+    let mut flows = HashMap::new();
+    for record in lines {
+        let mut features = HashMap::new();
+        for (name, value) in header.split(',').zip(record.split(',')) {
+            features
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        let key = record.split(',').next().unwrap().to_string();
+        flows.insert(key, features);
+    }
+    flows
+}
+
+#[tokio::test]
+async fn test_total_byte_count() {
+    let settings = Arc::new(FlowSettings {
+        byte_count: ByteCount::Total,
+        ..FlowSettings::default()
+    });
+
+    let cic = export_flows::<CicFlow>(&settings, FlowType::CIC).await;
+    let tcp = &cic["192.168.1.10:40000-192.168.1.20:80-6"];
+    // The 6 forward packets without payload are 40 bytes, the request 100 bytes more
+    assert_eq!(tcp["FWD_PKT_LEN_TOT"], "340");
+    assert_eq!(tcp["FWD_PKT_LEN_MIN"], "40");
+    // The segment size means count the bytes like the packet lengths
+    assert_eq!(tcp["FWD_SEGMENT_LENGTH_MEAN"], tcp["FWD_PKT_LEN_MEAN"]);
+    assert_eq!(tcp["BWD_SEGMENT_LENGTH_MEAN"], tcp["BWD_PKT_LEN_MEAN"]);
+
+    // The CIDDS bytes are the same total IP lengths
+    let cidds = export_flows::<CiddsFlow>(&settings, FlowType::CIDDS).await;
+    let mut bytes: Vec<u64> = cidds.values().map(|flow| flow["BYTES"].parse().unwrap()).collect();
+    bytes.sort();
+    let mut totals: Vec<u64> = cic
+        .values()
+        .map(|flow| {
+            flow["FWD_PKT_LEN_TOT"].parse::<u64>().unwrap()
+                + flow["BWD_PKT_LEN_TOT"].parse::<u64>().unwrap()
+        })
+        .collect();
+    totals.sort();
+    assert_eq!(bytes, totals);
+}
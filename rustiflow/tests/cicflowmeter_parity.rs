@@ -0,0 +1,163 @@
+//! Compares the CIC features of a reference capture against the values of CICFlowMeter.
+//!
+//! `data/cic_parity.pcap` holds two flows between 192.168.1.10 and 192.168.1.20:
+//!
+//! - A TCP connection to port 80: handshake, a 100 byte request, a 1000 and a 500 byte
+//!   response and a FIN teardown, 11 packets within 220 ms.
+//! - A UDP exchange on port 53: a 40 byte query answered with 120 bytes after 3 ms.
+//!
+//! `data/cic_parity.csv` has the CICFlowMeter values of these flows, one row per flow id.
+//! Only the listed columns are compared and empty cells are skipped. Regenerate it from a
+//! CICFlowMeter 4.0 run over the capture with
+//!
+//! ```text
+//! cfm tests/data/cic_parity.pcap /tmp/cfm
+//! python3 tests/data/cicflowmeter_golden.py /tmp/cfm/cic_parity.pcap_Flow.csv \
+//!     > tests/data/cic_parity.csv
+//! ```
+//!
+//! from the `rustiflow` directory. The committed values were derived from CICFlowMeter's
+//! feature definitions and await such a run.
+//!
+//! A few features are defined differently on purpose, see `KNOWN_DIFFERENCES`, those are
+//! checked against the value RustiFlow exports instead. The initial window bytes of UDP flows
+//! are skipped, CICFlowMeter releases disagree on them (0 or -1).
+
+use std::{collections::HashMap, sync::Arc};
+
+use rustiflow::{
+    args::{ClickHouseConfig, ExportConfig, ExportMethodType, FlowType, OutputConfig},
+    flows::cic_flow::CicFlow,
+    output::OutputWriter,
+    pcap::read_pcap_file,
+    pipeline::Pipeline,
+    reload::ReloadableConfig,
+};
+
+const CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/cic_parity.pcap");
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/cic_parity.csv");
+
+const TCP_FLOW: &str = "192.168.1.10:40000-192.168.1.20:80-6";
+const UDP_FLOW: &str = "192.168.1.10:53000-192.168.1.20:53-17";
+
+/// The features RustiFlow defines differently from CICFlowMeter, with the flow and the value
+/// RustiFlow exports for it:
+///
+/// - The standard deviations and variances are population instead of sample ones, they
+///   only agree for a single value.
+/// - The flow packet length variance pools the variances of the two directions, without
+///   the spread of their means, and is 0 with fewer than three packets.
+/// - The down/up ratio divides the forward by the backward packet count.
+const KNOWN_DIFFERENCES: &[(&str, &str, f64)] = &[
+    (TCP_FLOW, "FWD_PKT_LEN_STD", 37.267799),
+    (TCP_FLOW, "BWD_PKT_LEN_STD", 400.0),
+    (TCP_FLOW, "FLOW_IAT_STD", 16613.247726),
+    (TCP_FLOW, "FWD_IAT_STD", 44090.815370),
+    (TCP_FLOW, "BWD_IAT_STD", 18708.286934),
+    (TCP_FLOW, "FLOW_PACKET_LENGTH_STD", 268.109523),
+    (TCP_FLOW, "FLOW_PACKET_LENGTH_VARIANCE", 71882.716064),
+    (TCP_FLOW, "DOWN_UP_RATIO", 1.2),
+    (UDP_FLOW, "FLOW_PACKET_LENGTH_STD", 0.0),
+    (UDP_FLOW, "FLOW_PACKET_LENGTH_VARIANCE", 0.0),
+];
+
+/// Relative tolerance, the golden values are rounded to six decimals.
+const TOLERANCE: f64 = 1e-6;
+
+/// Runs the capture through a pipeline into a CSV file and returns the written flows by flow
+/// id.
+async fn export_flows() -> HashMap<String, HashMap<String, String>> {
+    let path = std::env::temp_dir().join("rustiflow_test_cicflowmeter_parity.csv");
+    let mut output_writer = OutputWriter::<CicFlow>::new(
+        Arc::default(),
+        FlowType::CIC,
+        ExportMethodType::Csv,
+        true,
+        false,
+        0,
+        Some(path.to_str().unwrap().to_string()),
+        ClickHouseConfig::default(),
+        None,
+    );
+    output_writer.init().await;
+    let config = ReloadableConfig::new(&ExportConfig::default(), &OutputConfig::default());
+    let pipeline = Pipeline::new(1, config, output_writer);
+    read_pcap_file(&pipeline, CAPTURE)
+        .await
+        .expect("the reference capture can be read");
+    pipeline.shutdown().await.unwrap();
+
+    let output = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut lines = output.lines();
+    let header = lines.next().unwrap();
+    let mut flows = HashMap::new();
+    for record in lines {
+        let mut features = HashMap::new();
+        for (name, value) in header.split(',').zip(record.split(',')) {
+            // Duplicate columns (FWD_HEADER_LENGTH) keep their first value
+            features
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        flows.insert(features["FLOW_ID"].clone(), features);
+    }
+    flows
+}
+
+fn approx_eq(actual: f64, expected: f64) -> bool {
+    (actual - expected).abs() <= TOLERANCE * expected.abs().max(1.0)
+}
+
+#[tokio::test]
+async fn test_cic_features_match_cicflowmeter() {
+    let flows = export_flows().await;
+
+    let mut golden = csv::Reader::from_path(GOLDEN).expect("the golden file can be read");
+    let columns = golden.headers().unwrap().clone();
+    let mut rows = 0;
+    for row in golden.records() {
+        let row = row.unwrap();
+        rows += 1;
+
+        let flow_id = &row[0];
+        let flow = flows
+            .get(flow_id)
+            .unwrap_or_else(|| panic!("flow {} was not exported", flow_id));
+
+        let mut mismatches = Vec::new();
+        for (column, expected) in columns.iter().zip(row.iter()).skip(1) {
+            if expected.is_empty() {
+                continue;
+            }
+            let actual = flow
+                .get(column)
+                .unwrap_or_else(|| panic!("{} is not a CIC feature", column));
+            let known_difference = KNOWN_DIFFERENCES
+                .iter()
+                .find(|(flow, name, _)| *flow == flow_id && *name == column);
+            if let Some((_, _, value)) = known_difference {
+                let actual: f64 = actual.parse().unwrap();
+                if !approx_eq(actual, *value) {
+                    mismatches.push(format!("{}: {} (known as {})", column, actual, value));
+                }
+                continue;
+            }
+            let matches = match (actual.parse::<f64>(), expected.parse::<f64>()) {
+                (Ok(actual), Ok(expected)) => approx_eq(actual, expected),
+                _ => actual == expected,
+            };
+            if !matches {
+                mismatches.push(format!("{}: {} (expected {})", column, actual, expected));
+            }
+        }
+        assert!(
+            mismatches.is_empty(),
+            "flow {} differs from CICFlowMeter:\n{}",
+            flow_id,
+            mismatches.join("\n")
+        );
+    }
+
+    assert_eq!(flows.len(), rows, "unexpected number of exported flows");
+}
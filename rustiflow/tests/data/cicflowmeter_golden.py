@@ -0,0 +1,99 @@
+"""Converts the flows CSV of a CICFlowMeter 4.0 run into `cic_parity.csv`.
+
+    cfm tests/data/cic_parity.pcap /tmp/cfm
+    python3 tests/data/cicflowmeter_golden.py /tmp/cfm/cic_parity.pcap_Flow.csv \
+        > tests/data/cic_parity.csv
+
+The columns are renamed to the RustiFlow features and the values rounded to six decimals.
+The initial window bytes of UDP flows are left empty, CICFlowMeter releases disagree on them.
+"""
+
+import csv
+import sys
+
+# The RustiFlow feature of each compared CICFlowMeter column, in the order of the golden file
+COLUMNS = {
+    "DURATION": "Flow Duration",
+    "FWD_PACKET_COUNT": "Total Fwd Packet",
+    "BWD_PACKET_COUNT": "Total Bwd packets",
+    "FWD_PKT_LEN_TOT": "Total Length of Fwd Packet",
+    "BWD_PKT_LEN_TOT": "Total Length of Bwd Packet",
+    "FWD_PKT_LEN_MAX": "Fwd Packet Length Max",
+    "FWD_PKT_LEN_MIN": "Fwd Packet Length Min",
+    "FWD_PKT_LEN_MEAN": "Fwd Packet Length Mean",
+    "BWD_PKT_LEN_MAX": "Bwd Packet Length Max",
+    "BWD_PKT_LEN_MIN": "Bwd Packet Length Min",
+    "BWD_PKT_LEN_MEAN": "Bwd Packet Length Mean",
+    "FLOW_BYTES_S": "Flow Bytes/s",
+    "FLOW_PACKETS_S": "Flow Packets/s",
+    "FWD_PACKETS_S": "Fwd Packets/s",
+    "BWD_PACKETS_S": "Bwd Packets/s",
+    "FWD_IAT_TOTAL": "Fwd IAT Total",
+    "FWD_IAT_MEAN": "Fwd IAT Mean",
+    "FWD_IAT_MAX": "Fwd IAT Max",
+    "FWD_IAT_MIN": "Fwd IAT Min",
+    "BWD_IAT_TOTAL": "Bwd IAT Total",
+    "BWD_IAT_MEAN": "Bwd IAT Mean",
+    "BWD_IAT_MAX": "Bwd IAT Max",
+    "BWD_IAT_MIN": "Bwd IAT Min",
+    "FWD_PSH_FLAG_COUNT": "Fwd PSH Flags",
+    "BWD_PSH_FLAG_COUNT": "Bwd PSH Flags",
+    "FWD_URG_FLAG_COUNT": "Fwd URG Flags",
+    "BWD_URG_FLAG_COUNT": "Bwd URG Flags",
+    "FWD_HEADER_LENGTH": "Fwd Header Length",
+    "BWD_HEADER_LENGTH": "Bwd Header Length",
+    "FLOW_PACKET_LENGTH_MIN": "Packet Length Min",
+    "FLOW_PACKET_LENGTH_MAX": "Packet Length Max",
+    "FLOW_PACKET_LENGTH_MEAN": "Packet Length Mean",
+    "FLOW_FIN_COUNT": "FIN Flag Count",
+    "FLOW_SYN_COUNT": "SYN Flag Count",
+    "FLOW_RST_COUNT": "RST Flag Count",
+    "FLOW_PSH_COUNT": "PSH Flag Count",
+    "FLOW_ACK_COUNT": "ACK Flag Count",
+    "FLOW_URG_COUNT": "URG Flag Count",
+    "FWD_INIT_WIN_BYTES": "FWD Init Win Bytes",
+    "BWD_INIT_WIN_BYTES": "Bwd Init Win Bytes",
+    "FWD_ACT_DATA_PKT": "Fwd Act Data Pkts",
+    "FWD_HEADER_LEN_MIN": "Fwd Seg Size Min",
+    "FWD_PKT_LEN_STD": "Fwd Packet Length Std",
+    "BWD_PKT_LEN_STD": "Bwd Packet Length Std",
+    "FLOW_IAT_MEAN": "Flow IAT Mean",
+    "FLOW_IAT_STD": "Flow IAT Std",
+    "FLOW_IAT_MAX": "Flow IAT Max",
+    "FLOW_IAT_MIN": "Flow IAT Min",
+    "FWD_IAT_STD": "Fwd IAT Std",
+    "BWD_IAT_STD": "Bwd IAT Std",
+    "FLOW_PACKET_LENGTH_STD": "Packet Length Std",
+    "FLOW_PACKET_LENGTH_VARIANCE": "Packet Length Variance",
+    "DOWN_UP_RATIO": "Down/Up Ratio",
+    "FWD_SEGMENT_LENGTH_MEAN": "Fwd Segment Size Avg",
+    "BWD_SEGMENT_LENGTH_MEAN": "Bwd Segment Size Avg",
+}
+
+
+def value(text):
+    number = float(text)
+    return str(int(number)) if number.is_integer() else f"{number:.6f}".rstrip("0")
+
+
+def main(path):
+    with open(path, newline="") as file:
+        reader = csv.DictReader(file)
+        # The releases differ in the case and spacing of the names
+        names = {name.strip().lower(): name for name in reader.fieldnames}
+        writer = csv.writer(sys.stdout, lineterminator="\n")
+        writer.writerow(["FLOW_ID"] + list(COLUMNS))
+        for row in reader:
+            get = lambda column: row[names[column.lower()]]
+            flow_id = "{}:{}-{}:{}-{}".format(
+                get("Src IP"), get("Src Port"), get("Dst IP"), get("Dst Port"), get("Protocol")
+            )
+            values = [value(get(COLUMNS[column])) for column in COLUMNS]
+            if get("Protocol") == "17":
+                for column in ("FWD_INIT_WIN_BYTES", "BWD_INIT_WIN_BYTES"):
+                    values[list(COLUMNS).index(column)] = ""
+            writer.writerow([flow_id] + values)
+
+
+if __name__ == "__main__":
+    main(sys.argv[1])